@@ -82,6 +82,25 @@
 #ifndef SECCOMP_FILTER_FLAG_TSYNC
 #define SECCOMP_FILTER_FLAG_TSYNC 1
 #endif
+// New in the 4.14 kernel
+#ifndef SECCOMP_RET_ACTION_FULL
+#define SECCOMP_RET_ACTION_FULL 0xffff0000U
+#endif
+// New in the 5.0 kernel
+#ifndef SECCOMP_FILTER_FLAG_NEW_LISTENER
+#define SECCOMP_FILTER_FLAG_NEW_LISTENER (1UL << 3)
+#endif
+#ifndef SECCOMP_RET_USER_NOTIF
+#define SECCOMP_RET_USER_NOTIF 0x7fc00000U
+#endif
+// New in the 5.5 kernel
+#ifndef SECCOMP_USER_NOTIF_FLAG_CONTINUE
+#define SECCOMP_USER_NOTIF_FLAG_CONTINUE (1UL << 0)
+#endif
+// New in the 5.9 kernel
+#ifndef SECCOMP_ADDFD_FLAG_SETFD
+#define SECCOMP_ADDFD_FLAG_SETFD (1UL << 0)
+#endif
 
 #ifndef SYS_SECCOMP
 #define SYS_SECCOMP 1
@@ -307,6 +326,32 @@ struct rr_input_mask {
 #ifndef KEYCTL_DH_COMPUTE
 #define KEYCTL_DH_COMPUTE 23
 #endif
+// New in the 4.20 kernel
+#ifndef KEYCTL_PKEY_QUERY
+#define KEYCTL_PKEY_QUERY 24
+#endif
+#ifndef KEYCTL_PKEY_ENCRYPT
+#define KEYCTL_PKEY_ENCRYPT 25
+#endif
+#ifndef KEYCTL_PKEY_DECRYPT
+#define KEYCTL_PKEY_DECRYPT 26
+#endif
+#ifndef KEYCTL_PKEY_SIGN
+#define KEYCTL_PKEY_SIGN 27
+#endif
+#ifndef KEYCTL_PKEY_VERIFY
+#define KEYCTL_PKEY_VERIFY 28
+#endif
+#ifndef KEYCTL_RESTRICT_KEYRING
+#define KEYCTL_RESTRICT_KEYRING 29
+#endif
+// New in the 5.7 kernel
+#ifndef KEYCTL_MOVE
+#define KEYCTL_MOVE 30
+#endif
+#ifndef KEYCTL_CAPABILITIES
+#define KEYCTL_CAPABILITIES 31
+#endif
 
 #ifndef PR_CAP_AMBIENT
 #define PR_CAP_AMBIENT 47
@@ -332,6 +377,52 @@ struct rr_input_mask {
 #define MAP_SYNC  0x80000
 #endif
 
+// New in the 4.18 kernel
+#ifndef RSEQ_FLAG_UNREGISTER
+#define RSEQ_FLAG_UNREGISTER 1
+#endif
+#ifndef RSEQ_CPU_ID_UNINITIALIZED
+#define RSEQ_CPU_ID_UNINITIALIZED -1
+#endif
+
+// New in the 5.2 kernel
+#ifndef CLONE_PIDFD
+#define CLONE_PIDFD 0x1000
+#endif
+
+// New in the 5.3 kernel
+#ifndef CLONE_ARGS_SIZE_VER0
+#define CLONE_ARGS_SIZE_VER0 64
+#endif
+
+// New in the 5.5 kernel
+#ifndef CLONE_ARGS_SIZE_VER1
+#define CLONE_ARGS_SIZE_VER1 80
+#endif
+
+// New in the 5.7 kernel
+#ifndef CLONE_ARGS_SIZE_VER2
+#define CLONE_ARGS_SIZE_VER2 88
+#endif
+#ifndef CLONE_INTO_CGROUP
+#define CLONE_INTO_CGROUP 0x200000000ULL
+#endif
+
+// New in the 5.6 kernel
+#ifndef OPEN_HOW_SIZE_VER0
+#define OPEN_HOW_SIZE_VER0 24
+#endif
+
+// New in the 5.9 kernel
+#ifndef CLOSE_RANGE_UNSHARE
+#define CLOSE_RANGE_UNSHARE (1U << 1)
+#endif
+
+// New in the 5.11 kernel
+#ifndef CLOSE_RANGE_CLOEXEC
+#define CLOSE_RANGE_CLOEXEC (1U << 2)
+#endif
+
 enum {
   BPF_MAP_CREATE,
   BPF_MAP_LOOKUP_ELEM,
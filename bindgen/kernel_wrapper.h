@@ -15,6 +15,7 @@
 #include <linux/msg.h>
 #include <linux/net.h>
 #include <linux/netfilter/x_tables.h>
+#include <linux/rseq.h>
 #include <linux/sem.h>
 #include <linux/shm.h>
 #include <linux/sockios.h>
@@ -0,0 +1,37 @@
+#!/usr/bin/env python3
+
+# Record the traces in tests/traces that tests/record_replay.rs replays.
+# Run from the top of the tree after `cargo build`, on a machine rd can
+# record on. Only rerun this when a trace format change makes the old traces
+# unreadable: the point of these traces is that the rd replaying them isn't
+# the one that recorded them.
+
+import os
+import shutil
+import subprocess
+import sys
+import tempfile
+
+# Program in tests/programs, arguments
+TRACES = [
+    ('hello', []),
+]
+
+def main():
+    rd = os.path.abspath(sys.argv[1] if len(sys.argv) > 1 else 'target/debug/rd')
+    cc = os.environ.get('CC', 'cc')
+    with tempfile.TemporaryDirectory() as tmp:
+        for name, args in TRACES:
+            exe = os.path.join(tmp, name)
+            subprocess.check_call([cc, '-static', '-g', '-O0', '-o', exe,
+                                   os.path.join('tests/programs', name + '.c')])
+            trace_root = os.path.join(tmp, 'traces-' + name)
+            env = dict(os.environ, _RD_TRACE_DIR=trace_root)
+            subprocess.check_call([rd, 'record', exe] + args, env=env)
+            dest = os.path.join('tests/traces', name)
+            shutil.rmtree(dest, ignore_errors=True)
+            # latest-trace is a symlink; copy what it points at.
+            shutil.copytree(os.path.realpath(os.path.join(trace_root, 'latest-trace')), dest)
+
+if __name__ == '__main__':
+    main()
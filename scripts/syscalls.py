@@ -1358,8 +1358,17 @@ kexec_load = UnsupportedSyscall(x86=283, x64=246)
 # nonzero value in this field after the call returns.
 waitid = IrregularEmulatedSyscall(x86=284, x64=247)
 
+#  key_serial_t add_key(const char *type, const char *description,
+#                       const void *payload, size_t plen,
+#                       key_serial_t keyring);
+#  key_serial_t request_key(const char *type, const char *description,
+#                           const char *callout_info,
+#                           key_serial_t dest_keyring);
+#
+# The key serial numbers these return are picked by the kernel and come
+# from the trace during replay, like every other result.
 add_key = EmulatedSyscall(x86=286, x64=248)
-request_key = UnsupportedSyscall(x86=287, x64=249)
+request_key = EmulatedSyscall(x86=287, x64=249)
 keyctl = IrregularEmulatedSyscall(x86=288, x64=250)
 ioprio_set = UnsupportedSyscall(x86=289, x64=251)
 ioprio_get = UnsupportedSyscall(x86=290, x64=252)
@@ -1642,7 +1651,7 @@ memfd_create = IrregularEmulatedSyscall(x86=356, x64=319)
 arch_prctl = IrregularEmulatedSyscall(x86=384, x64=158)
 
 bpf = IrregularEmulatedSyscall(x86=357, x64=321)
-execveat = UnsupportedSyscall(x86=358, x64=322)
+execveat = IrregularEmulatedSyscall(x86=358, x64=322)
 userfaultfd = UnsupportedSyscall(x86=374, x64=323)
 membarrier = EmulatedSyscall(x86=375, x64=324)
 mlock2 = UnsupportedSyscall(x86=376, x64=325)
@@ -1678,10 +1687,26 @@ futex_time64 = UnsupportedSyscall(x86=422)
 sched_rr_get_interval_time64 = UnsupportedSyscall(x86=423)
 
 # x86-64 decided to skip ahead here to catchup
-pidfd_send_signal = UnsupportedSyscall(x86=424, x64=424)
-io_uring_setup = UnsupportedSyscall(x86=425, x64=425)
-io_uring_enter = UnsupportedSyscall(x86=426, x64=426)
-io_uring_register = UnsupportedSyscall(x86=427, x64=427)
+
+#  int pidfd_send_signal(int pidfd, int sig, siginfo_t *info,
+#                        unsigned int flags)
+#
+# kill() through a pidfd. Handled like kill() when the pidfd is one of a
+# tracee.
+pidfd_send_signal = IrregularEmulatedSyscall(x86=424, x64=424)
+
+#  int io_uring_setup(u32 entries, struct io_uring_params *p)
+#  int io_uring_enter(unsigned int fd, u32 to_submit, u32 min_complete,
+#                     u32 flags, const void *arg, size_t argsz)
+#  int io_uring_register(unsigned int fd, unsigned int opcode, void *arg,
+#                        unsigned int nr_args)
+#
+# Denied with ENOSYS by default. With --io-uring=record, what the kernel
+# writes to the rings is recorded at each io_uring_enter(), see io_uring.rs.
+io_uring_setup = IrregularEmulatedSyscall(x86=425, x64=425)
+io_uring_enter = IrregularEmulatedSyscall(x86=426, x64=426)
+io_uring_register = IrregularEmulatedSyscall(x86=427, x64=427)
+
 open_tree = UnsupportedSyscall(x86=428, x64=428)
 move_mount = UnsupportedSyscall(x86=429, x64=429)
 fsopen = UnsupportedSyscall(x86=430, x64=430)
@@ -1689,18 +1714,74 @@ fsconfig = UnsupportedSyscall(x86=431, x64=431)
 fsmount = UnsupportedSyscall(x86=432, x64=432)
 fspick = UnsupportedSyscall(x86=433, x64=433)
 
+#  int pidfd_open(pid_t pid, unsigned int flags)
+#
+# Returns an fd referring to process pid. The fd table remembers which
+# process, for pidfd_send_signal() and pidfd_getfd().
+pidfd_open = IrregularEmulatedSyscall(x86=434, x64=434)
+
+#  long clone3(struct clone_args *cl_args, size_t size)
+#
+# Like clone(), but the arguments are in a struct that can grow. glibc
+# 2.34 and later create threads with it.
+clone3 = IrregularEmulatedSyscall(x86=435, x64=435)
+
+#  int close_range(unsigned int first, unsigned int last, unsigned int flags)
+#
+# Closes (or with CLOSE_RANGE_CLOEXEC, marks close-on-exec) every fd in
+# [first, last]. Programs spawning children call it with last = ~0U, which
+# would take rd's own fds with it.
+close_range = IrregularEmulatedSyscall(x86=436, x64=436)
+
+#  long openat2(int dirfd, const char *pathname, struct open_how *how,
+#               size_t size)
+#
+# openat() with the flags and mode in an extensible struct, plus RESOLVE_*
+# flags.
+openat2 = IrregularEmulatedSyscall(x86=437, x64=437)
+
+#  int pidfd_getfd(int pidfd, int targetfd, unsigned int flags)
+#
+# Duplicates fd targetfd of the process pidfd refers to into the caller.
+pidfd_getfd = IrregularEmulatedSyscall(x86=438, x64=438)
+
+#  int faccessat2(int dirfd, const char *pathname, int mode, int flags)
+#
+# faccessat() with the flags argument glibc used to emulate.
+faccessat2 = EmulatedSyscall(x86=439, x64=439)
+
+#  int landlock_create_ruleset(const struct landlock_ruleset_attr *attr,
+#                              size_t size, uint32_t flags)
+#
+# Creates a Landlock ruleset fd, or with LANDLOCK_CREATE_RULESET_VERSION
+# returns the ABI version. Replay gives the fd a monitor, see landlock.rs.
+landlock_create_ruleset = IrregularEmulatedSyscall(x86=444, x64=444)
+
+#  int landlock_add_rule(int ruleset_fd, enum landlock_rule_type rule_type,
+#                        const void *rule_attr, uint32_t flags)
+landlock_add_rule = EmulatedSyscall(x86=445, x64=445)
+
+#  int landlock_restrict_self(int ruleset_fd, uint32_t flags)
+landlock_restrict_self = EmulatedSyscall(x86=446, x64=446)
+
+#  int memfd_secret(unsigned int flags)
+#
+# Always fails with ENOSYS while recording, see memfd.rs.
+memfd_secret = IrregularEmulatedSyscall(x86=447, x64=447)
+
 # restart_syscall is a little special.
 restart_syscall = RestartSyscall(x86=0, x64=219)
 
-rdcall_init_preload = IrregularEmulatedSyscall(x86=442, x64=442)
-rdcall_init_buffers = IrregularEmulatedSyscall(x86=443, x64=443)
-rdcall_notify_syscall_hook_exit = IrregularEmulatedSyscall(x86=444, x64=444)
-rdcall_notify_control_msg = IrregularEmulatedSyscall(x86=445, x64=445)
-rdcall_reload_auxv = IrregularEmulatedSyscall(x86=446, x64=446)
-rdcall_mprotect_record = IrregularEmulatedSyscall(x86=447, x64=447)
+# rdcalls are numbered from RD_CALL_BASE in preload_interface.rs.
+rdcall_init_preload = IrregularEmulatedSyscall(x86=1000, x64=1000)
+rdcall_init_buffers = IrregularEmulatedSyscall(x86=1001, x64=1001)
+rdcall_notify_syscall_hook_exit = IrregularEmulatedSyscall(x86=1002, x64=1002)
+rdcall_notify_control_msg = IrregularEmulatedSyscall(x86=1003, x64=1003)
+rdcall_reload_auxv = IrregularEmulatedSyscall(x86=1004, x64=1004)
+rdcall_mprotect_record = IrregularEmulatedSyscall(x86=1005, x64=1005)
 # Disable in rd for now. @TODO this is enabled in rr
-#rdcall_notify_stap_semaphore_added = IrregularEmulatedSyscall(x86=448, x64=448)
-#rdcall_notify_stap_semaphore_removed = IrregularEmulatedSyscall(x86=449, x64=449)
+#rdcall_notify_stap_semaphore_added = IrregularEmulatedSyscall(x86=1006, x64=1006)
+#rdcall_notify_stap_semaphore_removed = IrregularEmulatedSyscall(x86=1007, x64=1007)
 # End disabled
 
 # These syscalls are also subsumed under socketcall on x86.
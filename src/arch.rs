@@ -1,6 +1,6 @@
 use crate::{
     kernel_abi::{
-        common::preload_interface::preload_globals,
+        common::preload_interface::{preload_globals, syscall_patch_hook},
         x64,
         x86,
         CloneParameterOrdering,
@@ -527,6 +527,16 @@ pub trait Architecture {
     const FSCONFIG: i32;
     const FSMOUNT: i32;
     const FSPICK: i32;
+    const PIDFD_OPEN: i32;
+    const CLONE3: i32;
+    const CLOSE_RANGE: i32;
+    const OPENAT2: i32;
+    const PIDFD_GETFD: i32;
+    const FACCESSAT2: i32;
+    const LANDLOCK_CREATE_RULESET: i32;
+    const LANDLOCK_ADD_RULE: i32;
+    const LANDLOCK_RESTRICT_SELF: i32;
+    const MEMFD_SECRET: i32;
     const RDCALL_INIT_PRELOAD: i32;
     const RDCALL_INIT_BUFFERS: i32;
     const RDCALL_NOTIFY_SYSCALL_HOOK_EXIT: i32;
@@ -605,6 +615,12 @@ pub trait Architecture {
 
     fn set_csmsghdr(msg: &mut Self::cmsghdr, cmsg_len: usize, cmsg_level: i32, cmsg_type: i32);
 
+    /// msg_control and msg_controllen.
+    fn get_msghdr_control(msg: &Self::msghdr) -> (RemotePtr<u8>, usize);
+
+    /// cmsg_len, cmsg_level and cmsg_type.
+    fn get_cmsghdr(cmsghdr: &Self::cmsghdr) -> (usize, i32, i32);
+
     fn set_siginfo_for_waited_task(r: &RecordTask, si: &mut Self::siginfo_t);
 
     fn rdcall_init_preload_params_syscallbuf_enabled(d: &Self::rdcall_init_preload_params) -> bool;
@@ -612,6 +628,12 @@ pub trait Architecture {
     fn rdcall_init_preload_params_globals(
         params: &Self::rdcall_init_preload_params,
     ) -> (RemotePtr<preload_globals>, RemoteCodePtr, usize);
+
+    /// The syscall patch hooks array, its length and the entry point the
+    /// vdso's __kernel_vsyscall should be redirected to.
+    fn rdcall_init_preload_params_patch_hooks(
+        params: &Self::rdcall_init_preload_params,
+    ) -> (RemotePtr<syscall_patch_hook>, usize, RemoteCodePtr);
 }
 impl Architecture for X86Arch {
     const MMAP_SEMANTICS: MmapCallingSemantics = x86::MMAP_SEMANTICS;
@@ -1053,13 +1075,23 @@ impl Architecture for X86Arch {
     const FSCONFIG: i32 = 431;
     const FSMOUNT: i32 = 432;
     const FSPICK: i32 = 433;
-    const RDCALL_INIT_PRELOAD: i32 = 442;
-    const RDCALL_INIT_BUFFERS: i32 = 443;
-    const RDCALL_NOTIFY_SYSCALL_HOOK_EXIT: i32 = 444;
-    const RDCALL_NOTIFY_CONTROL_MSG: i32 = 445;
-    const RDCALL_RELOAD_AUXV: i32 = 446;
-    const RDCALL_MPROTECT_RECORD: i32 = 447;
-    const VALID_SYSCALL_COUNT: i32 = 419;
+    const PIDFD_OPEN: i32 = 434;
+    const CLONE3: i32 = 435;
+    const CLOSE_RANGE: i32 = 436;
+    const OPENAT2: i32 = 437;
+    const PIDFD_GETFD: i32 = 438;
+    const FACCESSAT2: i32 = 439;
+    const LANDLOCK_CREATE_RULESET: i32 = 444;
+    const LANDLOCK_ADD_RULE: i32 = 445;
+    const LANDLOCK_RESTRICT_SELF: i32 = 446;
+    const MEMFD_SECRET: i32 = 447;
+    const RDCALL_INIT_PRELOAD: i32 = 1000;
+    const RDCALL_INIT_BUFFERS: i32 = 1001;
+    const RDCALL_NOTIFY_SYSCALL_HOOK_EXIT: i32 = 1002;
+    const RDCALL_NOTIFY_CONTROL_MSG: i32 = 1003;
+    const RDCALL_RELOAD_AUXV: i32 = 1004;
+    const RDCALL_MPROTECT_RECORD: i32 = 1005;
+    const VALID_SYSCALL_COUNT: i32 = 429;
     const INVALID_SYSCALL_COUNT: i32 = 17;
     // End list from generate_syscalls.py. See above.
 
@@ -1125,6 +1157,18 @@ impl Architecture for X86Arch {
         cmsghdr.cmsg_type = cmsg_type;
     }
 
+    fn get_msghdr_control(msg: &Self::msghdr) -> (RemotePtr<u8>, usize) {
+        (msg.msg_control.rptr(), msg.msg_controllen as usize)
+    }
+
+    fn get_cmsghdr(cmsghdr: &Self::cmsghdr) -> (usize, i32, i32) {
+        (
+            cmsghdr.cmsg_len as usize,
+            cmsghdr.cmsg_level,
+            cmsghdr.cmsg_type,
+        )
+    }
+
     fn set_siginfo_for_waited_task(r: &RecordTask, si: &mut x86::siginfo_t) {
         // XXX handle CLD_EXITED here
         if r.emulated_stop_type == EmulatedStopType::GroupStop {
@@ -1153,6 +1197,16 @@ impl Architecture for X86Arch {
             params.breakpoint_table_entry_size.try_into().unwrap(),
         )
     }
+
+    fn rdcall_init_preload_params_patch_hooks(
+        params: &Self::rdcall_init_preload_params,
+    ) -> (RemotePtr<syscall_patch_hook>, usize, RemoteCodePtr) {
+        (
+            params.syscall_patch_hooks.rptr(),
+            params.syscall_patch_hook_count.try_into().unwrap(),
+            params.syscallhook_vsyscall_entry.rptr().to_code_ptr(),
+        )
+    }
 }
 
 impl Architecture for X64Arch {
@@ -1595,13 +1649,23 @@ impl Architecture for X64Arch {
     const FSCONFIG: i32 = 431;
     const FSMOUNT: i32 = 432;
     const FSPICK: i32 = 433;
-    const RDCALL_INIT_PRELOAD: i32 = 442;
-    const RDCALL_INIT_BUFFERS: i32 = 443;
-    const RDCALL_NOTIFY_SYSCALL_HOOK_EXIT: i32 = 444;
-    const RDCALL_NOTIFY_CONTROL_MSG: i32 = 445;
-    const RDCALL_RELOAD_AUXV: i32 = 446;
-    const RDCALL_MPROTECT_RECORD: i32 = 447;
-    const VALID_SYSCALL_COUNT: i32 = 350;
+    const PIDFD_OPEN: i32 = 434;
+    const CLONE3: i32 = 435;
+    const CLOSE_RANGE: i32 = 436;
+    const OPENAT2: i32 = 437;
+    const PIDFD_GETFD: i32 = 438;
+    const FACCESSAT2: i32 = 439;
+    const LANDLOCK_CREATE_RULESET: i32 = 444;
+    const LANDLOCK_ADD_RULE: i32 = 445;
+    const LANDLOCK_RESTRICT_SELF: i32 = 446;
+    const MEMFD_SECRET: i32 = 447;
+    const RDCALL_INIT_PRELOAD: i32 = 1000;
+    const RDCALL_INIT_BUFFERS: i32 = 1001;
+    const RDCALL_NOTIFY_SYSCALL_HOOK_EXIT: i32 = 1002;
+    const RDCALL_NOTIFY_CONTROL_MSG: i32 = 1003;
+    const RDCALL_RELOAD_AUXV: i32 = 1004;
+    const RDCALL_MPROTECT_RECORD: i32 = 1005;
+    const VALID_SYSCALL_COUNT: i32 = 360;
     const INVALID_SYSCALL_COUNT: i32 = 86;
     // End list from generate_syscalls.py. See above.
 
@@ -1665,6 +1729,18 @@ impl Architecture for X64Arch {
         cmsghdr.cmsg_type = cmsg_type;
     }
 
+    fn get_msghdr_control(msg: &Self::msghdr) -> (RemotePtr<u8>, usize) {
+        (msg.msg_control.rptr(), msg.msg_controllen as usize)
+    }
+
+    fn get_cmsghdr(cmsghdr: &Self::cmsghdr) -> (usize, i32, i32) {
+        (
+            cmsghdr.cmsg_len as usize,
+            cmsghdr.cmsg_level,
+            cmsghdr.cmsg_type,
+        )
+    }
+
     fn set_siginfo_for_waited_task(r: &RecordTask, si: &mut x64::siginfo_t) {
         // XXX handle CLD_EXITED here
         if r.emulated_stop_type == EmulatedStopType::GroupStop {
@@ -1692,4 +1768,14 @@ impl Architecture for X64Arch {
             params.breakpoint_table_entry_size.try_into().unwrap(),
         )
     }
+
+    fn rdcall_init_preload_params_patch_hooks(
+        params: &Self::rdcall_init_preload_params,
+    ) -> (RemotePtr<syscall_patch_hook>, usize, RemoteCodePtr) {
+        (
+            params.syscall_patch_hooks.rptr(),
+            params.syscall_patch_hook_count.try_into().unwrap(),
+            params.syscallhook_vsyscall_entry.rptr().to_code_ptr(),
+        )
+    }
 }
@@ -14,6 +14,7 @@ use crate::{
         syscall_instruction,
         syscall_number_for__llseek,
         syscall_number_for_close,
+        syscall_number_for_dup3,
         syscall_number_for_lseek,
         syscall_number_for_mmap,
         syscall_number_for_mmap2,
@@ -27,7 +28,7 @@ use crate::{
     kernel_metadata::{errno_name, signal_name, syscall_name},
     log::LogLevel::LogDebug,
     monitored_shared_memory::MonitoredSharedMemorySharedPtr,
-    rd::RD_RESERVED_ROOT_DIR_FD,
+    rd::RD_RESERVED_FD_FLOOR,
     registers::Registers,
     remote_code_ptr::RemoteCodePtr,
     remote_ptr::{RemotePtr, Void},
@@ -55,7 +56,7 @@ use crate::{
             Task,
         },
     },
-    util::{find, is_kernel_trap, page_size, resize_shmem_segment, running_under_rd, tmp_dir},
+    util::{find, is_kernel_trap, page_size, resize_shmem_segment, running_under_rd},
     wait_status::{MaybeStopSignal, WaitStatus},
 };
 use core::ffi::c_void;
@@ -74,6 +75,7 @@ use libc::{
     SOL_SOCKET,
 };
 use nix::{
+    fcntl::OFlag,
     sys::{
         mman::{munmap, MapFlags, ProtFlags},
         stat::fstat,
@@ -685,6 +687,37 @@ impl<'a> AutoRemoteSyscalls<'a> {
         }
     }
 
+    /// Give the tracee's `fd` the number `target_fd` instead, closing whatever
+    /// `target_fd` referred to before. Replay uses this to put fds it has to
+    /// open for real at the numbers they had during recording.
+    /// Returns `target_fd`.
+    pub fn infallible_move_fd(&mut self, fd: i32, target_fd: i32, flags: OFlag) -> i32 {
+        if fd == target_fd {
+            return fd;
+        }
+        let arch = self.arch();
+        rd_infallible_syscall!(
+            self,
+            syscall_number_for_dup3(arch),
+            fd,
+            target_fd,
+            flags.bits()
+        );
+        rd_infallible_syscall!(self, syscall_number_for_close(arch), fd);
+        target_fd
+    }
+
+    /// Move `fd`, which rd opened in the tracee for its own use, to the lowest
+    /// free number at or above `RD_RESERVED_FD_FLOOR` so it can't occupy a
+    /// number the recorded program used. Returns the new fd.
+    pub fn infallible_move_fd_to_reserved_range(&mut self, fd: i32) -> i32 {
+        if fd >= RD_RESERVED_FD_FLOOR {
+            return fd;
+        }
+        let target_fd = self.task().find_free_file_descriptor(RD_RESERVED_FD_FLOOR);
+        self.infallible_move_fd(fd, target_fd, OFlag::O_CLOEXEC)
+    }
+
     /// The Task in the context of which we're making syscalls.
     pub fn task(&self) -> &dyn Task {
         self.t
@@ -732,7 +765,7 @@ impl<'a> AutoRemoteSyscalls<'a> {
             ));
         }
 
-        let child_sock = remote_buf.task().session().tracee_fd_number();
+        let child_sock = remote_buf.task().reserved_tracee_socket_fd();
         let child_syscall_result: isize =
             child_sendmsg(&mut remote_buf, maybe_sc_args, sc_args_end, child_sock, fd);
         if child_syscall_result == -ESRCH as isize {
@@ -979,8 +1012,9 @@ impl<'a> AutoRemoteSyscalls<'a> {
         let tracee_flags = maybe_tracee_flags.unwrap_or(MapFlags::empty());
 
         // Create the segment we'll share with the tracee.
+        let dir = self.task().session().temp_resources().dir();
         let mut path: Vec<u8> = Vec::new();
-        path.extend_from_slice(tmp_dir().as_bytes());
+        path.extend_from_slice(dir.as_bytes());
         path.extend_from_slice(SessionInner::rd_mapping_prefix().as_bytes());
         path.extend_from_slice(name.as_bytes());
         write!(
@@ -999,13 +1033,14 @@ impl<'a> AutoRemoteSyscalls<'a> {
         let child_shmem_fd: i32;
         {
             let arch = self.arch();
+            let root_dir_fd = self.task().reserved_root_dir_fd();
             let mut child_path = AutoRestoreMem::push_cstr(self, path.as_slice());
             // skip leading '/' since we want the path to be relative to the root fd
             let path_addr_val = (child_path.get().unwrap() + 1usize).as_usize();
             child_shmem_fd = rd_infallible_syscall!(
                 child_path,
                 syscall_number_for_openat(arch),
-                RD_RESERVED_ROOT_DIR_FD,
+                root_dir_fd,
                 path_addr_val,
                 (O_CREAT | O_EXCL | O_RDWR | O_CLOEXEC),
                 0o600
@@ -1013,7 +1048,8 @@ impl<'a> AutoRemoteSyscalls<'a> {
         }
 
         // Remove the fs name so that we don't have to worry about cleaning
-        // up this segment in error conditions.
+        // up this segment in error conditions. If we die before getting
+        // here, it goes with the session's directory.
         //
         // DIFF NOTE: rr swallows any potential error but we don't for now.
         unlink(path.as_slice()).unwrap();
@@ -1250,7 +1286,7 @@ fn is_usable_area(km: &KernelMapping) -> bool {
         && (km.flags().contains(MapFlags::MAP_PRIVATE))
 }
 
-fn ignore_signal(t: &dyn Task) -> bool {
+fn ignore_signal(t: &mut dyn Task) -> bool {
     let maybe_sig: MaybeStopSignal = t.maybe_stop_sig();
     if !maybe_sig.is_sig() {
         return false;
@@ -1261,7 +1297,7 @@ fn ignore_signal(t: &dyn Task) -> bool {
             return true;
         }
     } else if t.session().is_recording() {
-        let rt = t.as_record_task().unwrap();
+        let rt = t.as_record_task_mut().unwrap();
         // Better to use unwrap_sig() here as we've already made sure that maybe_sig.is_sig() above.
         if maybe_sig.unwrap_sig()
             != rt.session().as_record().unwrap().syscallbuf_desched_sig() as i32
@@ -1438,7 +1474,7 @@ fn child_sendmsg<Arch: Architecture>(
     )
 }
 
-fn recvmsg_socket(sock: &ScopedFd) -> i32 {
+pub fn recvmsg_socket(sock: &ScopedFd) -> i32 {
     let mut received_data: u8 = 0;
     let mut msgdata: libc::iovec = unsafe { zeroed() };
     msgdata.iov_base = &raw mut received_data as *mut c_void;
@@ -0,0 +1,51 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// Lets a UI thread (or a signal handler, or a remote client) ask a long
+/// replay operation like a seek to stop early. Operations check the token
+/// between replay steps, so a cancelled operation leaves the session at a
+/// consistent point somewhere short of its target, and returns as if it
+/// had failed to get there.
+///
+/// Clones share the same flag: keep one and hand the other to the operation.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Make the token usable for the next operation.
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn clones_share_state() {
+        let token = CancellationToken::new();
+        let other = token.clone();
+        assert!(!other.is_cancelled());
+        token.cancel();
+        assert!(other.is_cancelled());
+        other.reset();
+        assert!(!token.is_cancelled());
+    }
+}
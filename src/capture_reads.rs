@@ -0,0 +1,67 @@
+//! `rd record --capture-reads=<glob>`. Reads of most files go through the
+//! syscall buffer, which may clone file blocks into the trace instead of
+//! copying the data read, and counts on files rd assumes don't change. For
+//! files matching one of the patterns, say a program's config files, rd
+//! records every read's data in full instead: the fd gets a
+//! `CaptureReadsMonitor` when the file is opened, which disables syscall
+//! buffering for it.
+//!
+//! The patterns go in the trace header, so replay knows which fds got the
+//! monitor and disables buffering for them too.
+//!
+//! Patterns are matched against the whole path of the opened file, as the
+//! tracee sees it. `*` and `?` match any run of characters and any one
+//! character other than `/`, and `**` matches any run of characters,
+//! including `/`.
+
+use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+/// Whether `path` matches any of `patterns`.
+pub fn matches_any<P: AsRef<OsStr>>(patterns: &[P], path: &OsStr) -> bool {
+    patterns
+        .iter()
+        .any(|p| glob_matches(p.as_ref().as_bytes(), path.as_bytes()))
+}
+
+pub fn glob_matches(pattern: &[u8], path: &[u8]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        [b'*', b'*', rest @ ..] => (0..=path.len()).any(|i| glob_matches(rest, &path[i..])),
+        [b'*', rest @ ..] => {
+            let component = path.iter().position(|&c| c == b'/').unwrap_or(path.len());
+            (0..=component).any(|i| glob_matches(rest, &path[i..]))
+        }
+        [b'?', rest @ ..] => match path {
+            [c, path_rest @ ..] if *c != b'/' => glob_matches(rest, path_rest),
+            _ => false,
+        },
+        [p, rest @ ..] => match path {
+            [c, path_rest @ ..] if c == p => glob_matches(rest, path_rest),
+            _ => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn globs() {
+        assert!(glob_matches(b"/etc/*.conf", b"/etc/ld.so.conf"));
+        assert!(!glob_matches(b"/etc/*.conf", b"/etc/ld.so.conf.d/x.conf"));
+        assert!(glob_matches(b"/etc/**.conf", b"/etc/ld.so.conf.d/x.conf"));
+        assert!(glob_matches(
+            b"/home/*/.config/**",
+            b"/home/me/.config/app/rc"
+        ));
+        assert!(glob_matches(b"/tmp/?", b"/tmp/a"));
+        assert!(!glob_matches(b"/tmp/?", b"/tmp/ab"));
+        assert!(!glob_matches(b"/tmp/?x", b"/tmp//x"));
+        assert!(matches_any(
+            &["/nope", "/etc/hosts"],
+            OsStr::new("/etc/hosts")
+        ));
+        assert!(!matches_any::<&str>(&[], OsStr::new("/etc/hosts")));
+    }
+}
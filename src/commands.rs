@@ -1,12 +1,24 @@
 use std::io;
 
+pub mod backtrace_command;
 pub mod build_id_command;
+pub mod check_command;
+pub mod doctor_command;
 pub mod dump_command;
+pub mod grep_command;
+pub mod lint_command;
+pub mod maps_command;
+pub mod pack_command;
 pub mod ps_command;
 pub mod rd_options;
+pub mod record_command;
 pub mod replay_command;
 pub mod rerun_command;
+pub mod stats_command;
+pub mod tasks_command;
 pub mod trace_info_command;
+pub mod tui_command;
+pub mod which_wrote_command;
 
 pub trait RdCommand {
     fn run(&mut self) -> io::Result<()>;
@@ -0,0 +1,102 @@
+use crate::{
+    cancellation_token::CancellationToken,
+    commands::{
+        rd_options::{RdOptions, RdSubCommand},
+        RdCommand,
+    },
+    replay_timeline::ReplayTimeline,
+    session::replay_session::{Flags, ReplaySession},
+    trace::trace_frame::FrameTime,
+    unwinder::backtrace,
+};
+use libc::pid_t;
+use std::{
+    io,
+    io::{stdout, Write},
+    path::PathBuf,
+};
+
+pub struct BacktraceCommand {
+    event: FrameTime,
+    tid: Option<pid_t>,
+    trace_dir: Option<PathBuf>,
+}
+
+impl BacktraceCommand {
+    pub fn new(options: &RdOptions) -> BacktraceCommand {
+        match options.cmd.clone() {
+            RdSubCommand::Backtrace {
+                event,
+                tid,
+                trace_dir,
+            } => BacktraceCommand {
+                event,
+                tid,
+                trace_dir,
+            },
+            _ => panic!("Unexpected RdSubCommand variant. Not a `Backtrace` variant!"),
+        }
+    }
+
+    fn backtrace(&self, out: &mut dyn Write) -> io::Result<()> {
+        let flags = Flags {
+            redirect_stdio: false,
+            share_private_mappings: false,
+            cpu_unbound: true,
+            dead_tasks: Default::default(),
+        };
+        let mut timeline =
+            ReplayTimeline::new(ReplaySession::create(self.trace_dir.as_ref(), flags));
+        if !timeline.seek_to_event(self.event, &CancellationToken::new()) {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("Replay stopped before reaching event {}", self.event),
+            ));
+        }
+        let session = timeline.current_session();
+        let maybe_task = match self.tid {
+            Some(tid) => session.find_task_from_rec_tid(tid),
+            None => session.as_replay().unwrap().current_task(),
+        };
+        let t = match maybe_task {
+            Some(t) => t,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "No task {} at event {}",
+                        self.tid.map_or("to replay".into(), |tid| tid.to_string()),
+                        self.event
+                    ),
+                ))
+            }
+        };
+
+        let rec_tid = t.borrow().rec_tid;
+        let frames = backtrace(t.borrow_mut().as_mut());
+        write!(out, "Task {} at event {}:\n", rec_tid, self.event)?;
+        let mut n = 0;
+        for frame in &frames {
+            for call in &frame.inlined {
+                write!(out, "#{:<3} {} in {} (inlined", n, frame.ip, call.name)?;
+                if let Some(line) = call.call_line {
+                    write!(out, ", called from line {}", line)?;
+                }
+                write!(out, ")\n")?;
+                n += 1;
+            }
+            match &frame.symbol {
+                Some(symbol) => write!(out, "#{:<3} {} in {}\n", n, frame.ip, symbol)?,
+                None => write!(out, "#{:<3} {}\n", n, frame.ip)?,
+            }
+            n += 1;
+        }
+        Ok(())
+    }
+}
+
+impl RdCommand for BacktraceCommand {
+    fn run(&mut self) -> io::Result<()> {
+        self.backtrace(&mut stdout())
+    }
+}
@@ -0,0 +1,44 @@
+use crate::{
+    commands::RdCommand,
+    host_check::{check_host, CheckStatus},
+};
+use std::{
+    io,
+    io::{stdout, Write},
+};
+
+pub struct CheckCommand;
+
+impl CheckCommand {
+    pub fn new() -> CheckCommand {
+        CheckCommand
+    }
+}
+
+impl RdCommand for CheckCommand {
+    fn run(&mut self) -> io::Result<()> {
+        let results = check_host();
+        let mut out = stdout();
+        for r in &results {
+            write!(out, "[{:^7}] {}: {}\n", r.status, r.name, r.detail)?;
+            if let Some(advice) = &r.advice {
+                write!(out, "          {}\n", advice)?;
+            }
+        }
+        let errors = results
+            .iter()
+            .filter(|r| r.status == CheckStatus::Error)
+            .count();
+        if errors > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!(
+                    "{} problem(s) prevent rd from recording on this host",
+                    errors
+                ),
+            ));
+        }
+        write!(out, "\nrd can record on this host.\n")?;
+        Ok(())
+    }
+}
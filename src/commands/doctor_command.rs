@@ -0,0 +1,475 @@
+use crate::{
+    commands::{
+        rd_options::{RdOptions, RdSubCommand},
+        RdCommand,
+    },
+    event::EventType,
+    monkey_patcher::vdso_syscall_patches,
+    remote_code_ptr::RemoteCodePtr,
+    session::address_space::kernel_mapping::{KernelMapping, SharedMemoryKind},
+    trace::{
+        trace_frame::FrameTime,
+        trace_reader::{TraceReader, ValidateSourceFile},
+        trace_stream::{MappedData, MappedDataSource},
+        trace_task_event::{TraceTaskEvent, TraceTaskEventVariant},
+    },
+    util::{emulated_instruction_ending_with, TrappedInstruction},
+};
+use libc::{pid_t, CLONE_VM};
+use nix::sys::mman::{MapFlags, ProtFlags};
+use std::{
+    cmp::max,
+    collections::HashMap,
+    ffi::OsString,
+    fmt::{self, Display, Formatter},
+    fs::File,
+    io,
+    io::{stdout, Write},
+    os::unix::fs::FileExt,
+    path::PathBuf,
+};
+
+pub struct DoctorCommand {
+    trace_dir: Option<PathBuf>,
+}
+
+impl DoctorCommand {
+    pub fn new(options: &RdOptions) -> DoctorCommand {
+        match options.cmd.clone() {
+            RdSubCommand::Doctor { trace_dir } => DoctorCommand { trace_dir },
+            _ => panic!("Unexpected RdSubCommand variant. Not a `Doctor` variant!"),
+        }
+    }
+}
+
+impl RdCommand for DoctorCommand {
+    fn run(&mut self) -> io::Result<()> {
+        let trace = TraceReader::new(self.trace_dir.as_ref());
+        let environment = trace.record_environment().warnings();
+        let suspects = find_suspects(trace);
+        let mut out = stdout();
+        if suspects.is_empty() {
+            write!(
+                out,
+                "No known sources of replay divergence found in the trace.\n"
+            )?;
+        } else {
+            write!(
+                out,
+                "Possible sources of replay divergence, most likely first:\n\n"
+            )?;
+            for (i, s) in suspects.iter().enumerate() {
+                write!(out, "{}. {}\n   {}\n\n", i + 1, s, s.explanation())?;
+            }
+        }
+        // These don't make replay diverge, but programs that depend on timing
+        // may have done something during recording they usually don't.
+        if !environment.is_empty() {
+            write!(
+                out,
+                "\nThe trace was recorded under conditions that change tracee timing:\n"
+            )?;
+            for warning in environment {
+                write!(out, "  - {}\n", warning)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Something in the trace that lets a tracee observe state rd didn't record.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Suspect {
+    /// A process whose vdso time functions weren't patched to make real
+    /// syscalls after exec, so they read the clock straight from memory.
+    UnpatchedVdso {
+        tid: pid_t,
+        time: FrameTime,
+        exe: OsString,
+    },
+    /// A file (or POSIX shm object) mapped MAP_SHARED. Writes to it by
+    /// processes outside the recording aren't captured.
+    SharedFileMapping {
+        path: OsString,
+        writable: bool,
+        first_time: FrameTime,
+        count: u64,
+    },
+    /// rdtsc instructions executed by a task. Each one traps and is recorded,
+    /// but code that reads the TSC is usually timing something, and often
+    /// also reads timing information from somewhere we can't see.
+    Rdtsc {
+        tid: pid_t,
+        first_time: FrameTime,
+        count: u64,
+    },
+}
+
+impl Suspect {
+    /// Higher is more likely to cause a divergence. Ties are broken by how
+    /// often the suspect shows up in the trace, then by which shows up first.
+    fn rank(&self) -> (u32, u64) {
+        match self {
+            Suspect::UnpatchedVdso { .. } => (3, 1),
+            Suspect::SharedFileMapping {
+                writable, count, ..
+            } => (if *writable { 3 } else { 2 }, *count),
+            Suspect::Rdtsc { count, .. } => (1, *count),
+        }
+    }
+
+    /// The first event at which the suspect shows up.
+    fn time(&self) -> FrameTime {
+        match self {
+            Suspect::UnpatchedVdso { time, .. } => *time,
+            Suspect::SharedFileMapping { first_time, .. } => *first_time,
+            Suspect::Rdtsc { first_time, .. } => *first_time,
+        }
+    }
+
+    fn explanation(&self) -> &'static str {
+        match self {
+            Suspect::UnpatchedVdso { .. } => {
+                "clock_gettime(), gettimeofday() and time() run in user space without \
+                 entering the kernel, so their results are not recorded."
+            }
+            Suspect::SharedFileMapping { writable: true, .. } => {
+                "Writes to this file by processes outside the recording are not captured."
+            }
+            Suspect::SharedFileMapping {
+                writable: false, ..
+            } => {
+                "Changes to this file made by processes outside the recording while it \
+                 is mapped are not captured."
+            }
+            Suspect::Rdtsc { .. } => {
+                "rdtsc results are recorded, but code that reads the TSC directly may also \
+                 read timing state rd cannot see."
+            }
+        }
+    }
+}
+
+impl Display for Suspect {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Suspect::UnpatchedVdso { tid, time, exe } => write!(
+                f,
+                "vdso not patched after exec of {:?} (tid {}, event {})",
+                exe, tid, time
+            ),
+            Suspect::SharedFileMapping {
+                path,
+                writable,
+                first_time,
+                count,
+            } => write!(
+                f,
+                "{} shared mapping of {:?} ({} time(s), first at event {})",
+                if *writable { "writable" } else { "read-only" },
+                path,
+                count,
+                first_time
+            ),
+            Suspect::Rdtsc {
+                tid,
+                first_time,
+                count,
+            } => write!(
+                f,
+                "{} rdtsc instruction(s) in tid {} (first at event {})",
+                count, tid, first_time
+            ),
+        }
+    }
+}
+
+/// An exec whose vdso patches we haven't seen yet.
+struct PendingExec {
+    time: FrameTime,
+    exe: OsString,
+    patched: bool,
+}
+
+/// Scan `trace` once and return everything suspicious, most likely culprit
+/// first.
+fn find_suspects(mut trace: TraceReader) -> Vec<Suspect> {
+    let mut execs: Vec<(FrameTime, pid_t, OsString)> = Vec::new();
+    let mut time: FrameTime = 0;
+    let mut task_events: Vec<(FrameTime, TraceTaskEvent)> = Vec::new();
+    while let Some(e) = trace.read_task_event(Some(&mut time)) {
+        if let TraceTaskEventVariant::Exec(ex) = e.event_variant() {
+            execs.push((time, e.tid(), ex.file_name().to_owned()));
+        }
+        task_events.push((time, e));
+    }
+    execs.reverse();
+    task_events.reverse();
+
+    let patches = vdso_syscall_patches();
+    let mut suspects = Vec::new();
+    let mut pending: HashMap<pid_t, PendingExec> = HashMap::new();
+    let mut shared: HashMap<OsString, Suspect> = HashMap::new();
+    let mut rdtsc: HashMap<pid_t, Suspect> = HashMap::new();
+    let mut code = CodeMappings::default();
+    while !trace.at_end() {
+        let frame = trace.read_frame();
+        while task_events.last().map_or(false, |e| e.0 <= frame.time()) {
+            code.task_event(&task_events.pop().unwrap().1);
+        }
+        while execs.last().map_or(false, |e| e.0 <= frame.time()) {
+            let (time, tid, exe) = execs.pop().unwrap();
+            let exec = PendingExec {
+                time,
+                exe,
+                patched: false,
+            };
+            if let Some(prev) = pending.insert(tid, exec) {
+                finish_exec(tid, prev, &mut suspects);
+            }
+        }
+
+        // CPUID traps are recorded as the same event. Only the instruction
+        // in front of the recorded ip (which is past the emulated
+        // instruction) tells them apart. If we can't find it, e.g. because
+        // it's JIT code, assume the worse.
+        let is_rdtsc = || match code.instruction_before(frame.tid(), frame.regs_ref().ip()) {
+            TrappedInstruction::CpuId => false,
+            _ => true,
+        };
+        if frame.event().event_type() == EventType::EvInstructionTrap && is_rdtsc() {
+            match rdtsc.get_mut(&frame.tid()) {
+                Some(Suspect::Rdtsc { count, .. }) => *count += 1,
+                _ => {
+                    rdtsc.insert(
+                        frame.tid(),
+                        Suspect::Rdtsc {
+                            tid: frame.tid(),
+                            first_time: frame.time(),
+                            count: 1,
+                        },
+                    );
+                }
+            }
+        }
+
+        loop {
+            let mut data = MappedData::default();
+            let maybe_km = trace.read_mapped_region(
+                Some(&mut data),
+                Some(ValidateSourceFile::DontValidate),
+                None,
+                None,
+                None,
+            );
+            match maybe_km {
+                Some(km) => {
+                    note_mapping(&km, frame.time(), &mut shared);
+                    code.mapped(frame.tid(), km, data);
+                }
+                None => break,
+            }
+        }
+
+        // The vdso is patched right after exec, and the patches are recorded
+        // with the exec event itself (or, at the latest, the task's next one).
+        let maybe_exec = pending.get_mut(&frame.tid());
+        match maybe_exec {
+            Some(exec) => {
+                while let Some(raw) = trace.read_raw_data_for_frame() {
+                    if patches.contains(&raw.data) {
+                        exec.patched = true;
+                    }
+                }
+                if exec.patched || frame.time() > exec.time {
+                    let exec = pending.remove(&frame.tid()).unwrap();
+                    finish_exec(frame.tid(), exec, &mut suspects);
+                }
+            }
+            None => while trace.read_raw_data_metadata_for_frame().is_some() {},
+        }
+    }
+    for (tid, exec) in pending {
+        finish_exec(tid, exec, &mut suspects);
+    }
+
+    suspects.extend(shared.into_iter().map(|(_, s)| s));
+    suspects.extend(rdtsc.into_iter().map(|(_, s)| s));
+    rank_suspects(&mut suspects);
+    suspects
+}
+
+fn finish_exec(tid: pid_t, exec: PendingExec, suspects: &mut Vec<Suspect>) {
+    if !exec.patched {
+        suspects.push(Suspect::UnpatchedVdso {
+            tid,
+            time: exec.time,
+            exe: exec.exe,
+        });
+    }
+}
+
+fn note_mapping(km: &KernelMapping, time: FrameTime, shared: &mut HashMap<OsString, Suspect>) {
+    if !km.flags().contains(MapFlags::MAP_SHARED)
+        || km.shared_memory_kind() == Some(SharedMemoryKind::Anonymous)
+        || !km.fsname().to_string_lossy().starts_with('/')
+    {
+        return;
+    }
+    let is_writable = km.prot().contains(ProtFlags::PROT_WRITE);
+    match shared.get_mut(km.fsname()) {
+        Some(Suspect::SharedFileMapping {
+            writable, count, ..
+        }) => {
+            *writable |= is_writable;
+            *count += 1;
+        }
+        _ => {
+            shared.insert(
+                km.fsname().to_owned(),
+                Suspect::SharedFileMapping {
+                    path: km.fsname().to_owned(),
+                    writable: is_writable,
+                    first_time: time,
+                    count: 1,
+                },
+            );
+        }
+    }
+}
+
+/// A mapping whose contents can be read back from a file.
+#[derive(Clone)]
+struct FileMapping {
+    start: usize,
+    end: usize,
+    file_name: OsString,
+    offset: usize,
+}
+
+/// Enough of each address space's layout to read its code from the files
+/// it was mapped from. Mappings are never removed, since unmaps aren't in
+/// the trace; newer mappings shadow older ones instead.
+#[derive(Default)]
+struct CodeMappings {
+    /// The address space each tid is in, named after the tid that created it.
+    space_of: HashMap<pid_t, pid_t>,
+    spaces: HashMap<pid_t, Vec<FileMapping>>,
+}
+
+impl CodeMappings {
+    fn space(&self, tid: pid_t) -> pid_t {
+        self.space_of.get(&tid).copied().unwrap_or(tid)
+    }
+
+    fn task_event(&mut self, e: &TraceTaskEvent) {
+        match e.event_variant() {
+            TraceTaskEventVariant::Clone(c) => {
+                let parent_space = self.space(c.parent_tid());
+                if c.clone_flags() & CLONE_VM != 0 {
+                    self.space_of.insert(e.tid(), parent_space);
+                } else {
+                    let mappings = self.spaces.get(&parent_space).cloned();
+                    self.space_of.insert(e.tid(), e.tid());
+                    self.spaces.insert(e.tid(), mappings.unwrap_or_default());
+                }
+            }
+            TraceTaskEventVariant::Exec(_) => {
+                self.space_of.insert(e.tid(), e.tid());
+                self.spaces.insert(e.tid(), Vec::new());
+            }
+            TraceTaskEventVariant::Exit(_) => (),
+        }
+    }
+
+    fn mapped(&mut self, tid: pid_t, km: KernelMapping, data: MappedData) {
+        if data.source != MappedDataSource::SourceFile {
+            return;
+        }
+        let space = self.space(tid);
+        self.spaces.entry(space).or_default().push(FileMapping {
+            start: km.start().as_usize(),
+            end: km.end().as_usize(),
+            file_name: data.filename,
+            offset: data.data_offset_bytes,
+        });
+    }
+
+    /// The emulated instruction that ends at `ip` in the address space of
+    /// `tid`, if its code can be read.
+    fn instruction_before(&self, tid: pid_t, ip: RemoteCodePtr) -> TrappedInstruction {
+        let ip = ip.register_value();
+        let mappings = match self.spaces.get(&self.space(tid)) {
+            Some(mappings) => mappings,
+            None => return TrappedInstruction::None,
+        };
+        let m = match mappings.iter().rev().find(|m| m.start < ip && ip <= m.end) {
+            Some(m) => m,
+            None => return TrappedInstruction::None,
+        };
+        let start = max(m.start, ip.saturating_sub(MAX_EMULATED_INSN_LEN));
+        let mut code = vec![0u8; ip - start];
+        match File::open(&m.file_name)
+            .and_then(|f| f.read_exact_at(&mut code, (m.offset + start - m.start) as u64))
+        {
+            Ok(()) => emulated_instruction_ending_with(&code),
+            Err(_) => TrappedInstruction::None,
+        }
+    }
+}
+
+/// RDTSCP is the longest instruction rd emulates.
+const MAX_EMULATED_INSN_LEN: usize = 3;
+
+fn rank_suspects(suspects: &mut Vec<Suspect>) {
+    suspects.sort_by(|a, b| b.rank().cmp(&a.rank()).then(a.time().cmp(&b.time())));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn suspects_rank() {
+        let rdtsc = Suspect::Rdtsc {
+            tid: 1,
+            first_time: 10,
+            count: 1000,
+        };
+        let read_only = Suspect::SharedFileMapping {
+            path: "/tmp/a".into(),
+            writable: false,
+            first_time: 5,
+            count: 1,
+        };
+        let writable = Suspect::SharedFileMapping {
+            path: "/tmp/b".into(),
+            writable: true,
+            first_time: 7,
+            count: 2,
+        };
+        let vdso = Suspect::UnpatchedVdso {
+            tid: 1,
+            time: 1,
+            exe: "/bin/true".into(),
+        };
+        let mut suspects = vec![
+            rdtsc.clone(),
+            read_only.clone(),
+            vdso.clone(),
+            writable.clone(),
+        ];
+        rank_suspects(&mut suspects);
+        assert_eq!(suspects, vec![writable, vdso, read_only, rdtsc]);
+    }
+
+    #[test]
+    fn emulated_instructions() {
+        let insn = emulated_instruction_ending_with;
+        assert!(insn(&[0x90, 0x0f, 0x31]) == TrappedInstruction::Rdtsc);
+        assert!(insn(&[0x0f, 0x01, 0xf9]) == TrappedInstruction::Rdtscp);
+        assert!(insn(&[0x48, 0x0f, 0xa2]) == TrappedInstruction::CpuId);
+        assert!(insn(&[0x0f, 0xa2, 0x90]) == TrappedInstruction::None);
+        assert!(insn(&[0x31]) == TrappedInstruction::None);
+    }
+}
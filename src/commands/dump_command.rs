@@ -38,6 +38,10 @@ pub struct DumpCommand {
     dump_mmaps: bool,
     raw_dump: bool,
     statistics: bool,
+    dump_raw_data: bool,
+    only_syscalls: bool,
+    syscall_names: Vec<String>,
+    only_signals: bool,
     only_tid: Option<libc::pid_t>,
     trace_dir: Option<PathBuf>,
     event_spec: Option<(FrameTime, Option<FrameTime>)>,
@@ -53,16 +57,24 @@ impl DumpCommand {
                 mmaps,
                 raw_dump,
                 statistics,
+                raw_data,
+                syscalls,
+                syscall_names,
+                signals,
                 only_tid,
                 trace_dir,
                 event_spec,
             } => DumpCommand {
                 dump_syscallbuf: syscallbuf,
                 dump_task_events: task_events,
-                dump_recorded_data_metadata: recorded_metadata,
+                dump_recorded_data_metadata: recorded_metadata || raw_data,
                 dump_mmaps: mmaps,
                 raw_dump,
                 statistics,
+                dump_raw_data: raw_data,
+                only_syscalls: syscalls || !syscall_names.is_empty(),
+                syscall_names,
+                only_signals: signals,
                 only_tid,
                 trace_dir,
                 event_spec,
@@ -92,6 +104,26 @@ impl DumpCommand {
         Ok(())
     }
 
+    /// Does `frame` pass the event type filters (--syscalls, --syscall, --signals)?
+    /// With no filters, every frame does.
+    fn event_matches(&self, frame: &TraceFrame) -> bool {
+        if !self.only_syscalls && !self.only_signals {
+            return true;
+        }
+        let ev = frame.event();
+        if self.only_signals && ev.is_signal_event() {
+            return true;
+        }
+        if self.only_syscalls && ev.is_syscall_event() {
+            if self.syscall_names.is_empty() {
+                return true;
+            }
+            let name = ev.syscall().syscall_name();
+            return self.syscall_names.iter().any(|n| *n == name);
+        }
+        false
+    }
+
     fn dump_statistics(&self, trace: &mut TraceReader, f: &mut dyn Write) -> io::Result<()> {
         let ub = trace.uncompressed_bytes();
         let cb = trace.compressed_bytes();
@@ -150,6 +182,7 @@ impl DumpCommand {
             if start <= frame.time()
                 && frame.time() <= end
                 && (self.only_tid.is_none() || self.only_tid.unwrap() == frame.tid())
+                && self.event_matches(&frame)
             {
                 if self.raw_dump {
                     frame.dump_raw(Some(f))?;
@@ -223,6 +256,20 @@ impl DumpCommand {
                     }
                 }
 
+                while self.dump_raw_data {
+                    let data = match trace.read_raw_data_for_frame() {
+                        Some(data) => data,
+                        None => break,
+                    };
+                    write!(
+                        f,
+                        "  {{ tid:{}, addr:{:#x}, length:{:#x} }}\n",
+                        data.rec_tid,
+                        data.addr.as_usize(),
+                        data.data.len()
+                    )?;
+                    dump_hex(f, data.addr.as_usize(), &data.data)?;
+                }
                 while let Some(data) = trace.read_raw_data_metadata_for_frame() {
                     if self.dump_recorded_data_metadata {
                         // DIFF NOTE rr prints `(nil)` if addr is 0 or length is 0.
@@ -287,6 +334,16 @@ fn dump_task_event(out: &mut dyn Write, event: &TraceTaskEvent) -> io::Result<()
                 event.tid(),
                 filename_trimmed
             )?;
+            if let Some(fds) = ev.attached_fds() {
+                for f in fds {
+                    write!(
+                        out,
+                        "    (attached) fd {} -> {}\n",
+                        f.fd,
+                        f.path.to_string_lossy()
+                    )?;
+                }
+            }
         }
         TraceTaskEventVariant::Exit(ev) => {
             write!(
@@ -301,6 +358,18 @@ fn dump_task_event(out: &mut dyn Write, event: &TraceTaskEvent) -> io::Result<()
     Ok(())
 }
 
+/// Dump `data`, which was recorded at tracee address `addr`, 16 bytes to a line.
+pub(crate) fn dump_hex(out: &mut dyn Write, addr: usize, data: &[u8]) -> io::Result<()> {
+    for (i, line) in data.chunks(16).enumerate() {
+        write!(out, "    {:#x}:", addr + i * 16)?;
+        for b in line {
+            write!(out, " {:02x}", b)?;
+        }
+        write!(out, "\n")?;
+    }
+    Ok(())
+}
+
 unsafe fn dump_syscallbuf_data(
     trace: &mut TraceReader,
     out: &mut dyn Write,
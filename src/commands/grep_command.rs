@@ -0,0 +1,299 @@
+use crate::{
+    capture_reads::glob_matches,
+    commands::{
+        rd_options::{RdOptions, RdSubCommand},
+        RdCommand,
+    },
+    event::SyscallState,
+    kernel_abi::{self, SupportedArch},
+    kernel_metadata::syscall_name,
+    record_syscall::syscall_path_args,
+    trace::{
+        trace_frame::{FrameTime, TraceFrame},
+        trace_reader::{TraceReader, ValidateSourceFile},
+        trace_stream::MappedData,
+        trace_task_event::TraceTaskEventVariant,
+    },
+};
+use libc::pid_t;
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    io,
+    io::{stdout, Write},
+    os::unix::ffi::OsStrExt,
+    path::PathBuf,
+};
+
+/// Strings shorter than this in recorded data are too likely to be noise.
+const MIN_DATA_STRING_LEN: usize = 2;
+
+pub struct GrepCommand {
+    path_pattern: Option<OsString>,
+    fd: Option<i32>,
+    only_tid: Option<pid_t>,
+    trace_dir: Option<PathBuf>,
+}
+
+impl GrepCommand {
+    pub fn new(options: &RdOptions) -> GrepCommand {
+        match options.cmd.clone() {
+            RdSubCommand::Grep {
+                path,
+                fd,
+                only_tid,
+                trace_dir,
+            } => GrepCommand {
+                path_pattern: path,
+                fd,
+                only_tid,
+                trace_dir,
+            },
+            _ => panic!("Unexpected RdSubCommand variant. Not a Grep variant!"),
+        }
+    }
+
+    /// Scan the whole trace once, reporting every place where a path matching
+    /// `path_pattern` appears or where `fd` is operated on:
+    /// - files opened by syscalls (recorded with the syscall event)
+    /// - files exec'd
+    /// - files mmapped
+    /// - paths passed to syscalls that don't open anything (stat(), unlink()
+    ///   etc.), see `syscall_path_args()`
+    /// - strings in recorded syscall output data (e.g. readlink(),
+    ///   getcwd(), getdents() results)
+    /// - syscalls whose first argument is `fd`
+    fn grep(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut trace = TraceReader::new(self.trace_dir.as_ref());
+        write!(out, "EVENT\tTID\tWHAT\n")?;
+
+        let mut execs: HashMap<FrameTime, (pid_t, OsString)> = HashMap::new();
+        let mut time: FrameTime = 0;
+        while let Some(e) = trace.read_task_event(Some(&mut time)) {
+            if let TraceTaskEventVariant::Exec(ex) = e.event_variant() {
+                execs.insert(time, (e.tid(), ex.file_name().to_owned()));
+            }
+        }
+
+        while !trace.at_end() {
+            let frame = trace.read_frame();
+            let wanted = self.only_tid.map_or(true, |tid| tid == frame.tid());
+
+            if wanted {
+                if let Some((tid, file_name)) = execs.get(&frame.time()) {
+                    if self.path_matches(file_name) {
+                        write!(out, "{}\t{}\texec {:?}\n", frame.time(), tid, file_name)?;
+                    }
+                }
+                self.grep_syscall(&frame, out)?;
+            }
+
+            loop {
+                let mut data = MappedData::default();
+                let maybe_km = trace.read_mapped_region(
+                    Some(&mut data),
+                    Some(ValidateSourceFile::DontValidate),
+                    None,
+                    None,
+                    None,
+                );
+                match maybe_km {
+                    Some(km) if wanted && self.path_matches(km.fsname()) => write!(
+                        out,
+                        "{}\t{}\tmmap {:?} at {:#x}-{:#x}\n",
+                        frame.time(),
+                        frame.tid(),
+                        km.fsname(),
+                        km.start().as_usize(),
+                        km.end().as_usize()
+                    )?,
+                    Some(_) => (),
+                    None => break,
+                }
+            }
+
+            if wanted && self.path_pattern.is_some() {
+                let path_args = path_args(&frame);
+                while let Some(raw) = trace.read_raw_data_for_frame() {
+                    if path_args.contains(&raw.addr.as_usize()) {
+                        let path = raw.data.split(|&c| c == 0).next().unwrap_or(&[]);
+                        let path = OsStr::from_bytes(path);
+                        if self.path_matches(path) {
+                            let sys = frame.event().syscall();
+                            write!(
+                                out,
+                                "{}\t{}\t{}({:?}) = {}\n",
+                                frame.time(),
+                                raw.rec_tid,
+                                syscall_name(sys.number, sys.arch()),
+                                path,
+                                frame.regs_ref().syscall_result_signed()
+                            )?;
+                        }
+                        continue;
+                    }
+                    for (offset, s) in data_strings(&raw.data) {
+                        if self.path_matches(s) {
+                            write!(
+                                out,
+                                "{}\t{}\tdata {:?} at {:#x}\n",
+                                frame.time(),
+                                raw.rec_tid,
+                                s,
+                                raw.addr.as_usize() + offset
+                            )?;
+                        }
+                    }
+                }
+            } else {
+                while trace.read_raw_data_metadata_for_frame().is_some() {}
+            }
+        }
+        Ok(())
+    }
+
+    fn grep_syscall(&self, frame: &TraceFrame, out: &mut dyn Write) -> io::Result<()> {
+        let ev = frame.event();
+        if !ev.is_syscall_event() {
+            return Ok(());
+        }
+        let sys = ev.syscall();
+        for opened in &sys.opened {
+            if self.path_matches(&opened.path) || self.fd == Some(opened.fd) {
+                write!(
+                    out,
+                    "{}\t{}\t{} opened {:?} as fd {}\n",
+                    frame.time(),
+                    frame.tid(),
+                    syscall_name(sys.number, sys.arch()),
+                    opened.path,
+                    opened.fd
+                )?;
+            }
+        }
+        if let Some(fd) = self.fd {
+            // Both the entry and the exit of a syscall are in the trace. Only
+            // report the exit so each syscall is one hit. x86 and x86-64
+            // syscalls don't clobber their argument registers so arg1 is still
+            // valid there.
+            if sys.state == SyscallState::ExitingSyscall
+                && takes_fd_arg1(sys.number, sys.arch())
+                && frame.regs_ref().arg1_signed() as i32 == fd
+            {
+                write!(
+                    out,
+                    "{}\t{}\t{}({}, ...) {}\n",
+                    frame.time(),
+                    frame.tid(),
+                    syscall_name(sys.number, sys.arch()),
+                    fd,
+                    ev.str()
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn path_matches(&self, path: &OsStr) -> bool {
+        match &self.path_pattern {
+            Some(pattern) => glob_matches(pattern.as_bytes(), path.as_bytes()),
+            None => false,
+        }
+    }
+}
+
+impl RdCommand for GrepCommand {
+    fn run(&mut self) -> io::Result<()> {
+        self.grep(&mut stdout())
+    }
+}
+
+/// Where the paths passed to the syscall that `frame` is the exit of are in
+/// its recorded data, see `syscall_path_args()`. x86 and x86-64 syscalls
+/// don't clobber their argument registers.
+fn path_args(frame: &TraceFrame) -> Vec<usize> {
+    let ev = frame.event();
+    if !ev.is_syscall_event() || ev.syscall().state != SyscallState::ExitingSyscall {
+        return Vec::new();
+    }
+    syscall_path_args(ev.syscall().number, ev.syscall().arch(), frame.regs_ref())
+}
+
+/// Runs of printable bytes in `data` that could be paths, with their offsets.
+/// Bytes >= 0x80 are considered printable so that UTF-8 paths are found.
+fn data_strings(data: &[u8]) -> Vec<(usize, &OsStr)> {
+    let mut result = Vec::new();
+    let mut start = 0;
+    for (i, &c) in data.iter().chain(&[0u8]).enumerate() {
+        let printable = c.is_ascii_graphic() || c == b' ' || c >= 0x80;
+        if !printable {
+            if i - start >= MIN_DATA_STRING_LEN {
+                result.push((start, OsStr::from_bytes(&data[start..i])));
+            }
+            start = i + 1;
+        }
+    }
+    result
+}
+
+/// Syscalls whose first parameter is an fd (or a dirfd).
+fn takes_fd_arg1(sys: i32, arch: SupportedArch) -> bool {
+    uses_fd_arg1(sys, arch) || takes_dirfd_arg1(sys, arch)
+}
+
+/// Syscalls that fail with EBADF unless their first parameter is an open fd.
+pub fn uses_fd_arg1(sys: i32, arch: SupportedArch) -> bool {
+    kernel_abi::is_read_syscall(sys, arch)
+        || kernel_abi::is_write_syscall(sys, arch)
+        || kernel_abi::is_pread64_syscall(sys, arch)
+        || kernel_abi::is_pwrite64_syscall(sys, arch)
+        || kernel_abi::is_readv_syscall(sys, arch)
+        || kernel_abi::is_writev_syscall(sys, arch)
+        || kernel_abi::is_preadv_syscall(sys, arch)
+        || kernel_abi::is_pwritev_syscall(sys, arch)
+        || kernel_abi::is_close_syscall(sys, arch)
+        || kernel_abi::is_fstat_syscall(sys, arch)
+        || kernel_abi::is_fstatfs_syscall(sys, arch)
+        || kernel_abi::is_lseek_syscall(sys, arch)
+        || kernel_abi::is__llseek_syscall(sys, arch)
+        || kernel_abi::is_ioctl_syscall(sys, arch)
+        || kernel_abi::is_fcntl_syscall(sys, arch)
+        || kernel_abi::is_fcntl64_syscall(sys, arch)
+        || kernel_abi::is_fsync_syscall(sys, arch)
+        || kernel_abi::is_fdatasync_syscall(sys, arch)
+        || kernel_abi::is_ftruncate_syscall(sys, arch)
+        || kernel_abi::is_getdents_syscall(sys, arch)
+        || kernel_abi::is_getdents64_syscall(sys, arch)
+        || kernel_abi::is_dup_syscall(sys, arch)
+        || kernel_abi::is_dup2_syscall(sys, arch)
+        || kernel_abi::is_dup3_syscall(sys, arch)
+        || kernel_abi::is_fchdir_syscall(sys, arch)
+        || kernel_abi::is_fchmod_syscall(sys, arch)
+        || kernel_abi::is_fchown_syscall(sys, arch)
+        || kernel_abi::is_flock_syscall(sys, arch)
+        || kernel_abi::is_sendfile_syscall(sys, arch)
+        || kernel_abi::is_recvfrom_syscall(sys, arch)
+        || kernel_abi::is_sendto_syscall(sys, arch)
+        || kernel_abi::is_recvmsg_syscall(sys, arch)
+        || kernel_abi::is_sendmsg_syscall(sys, arch)
+        || kernel_abi::is_connect_syscall(sys, arch)
+        || kernel_abi::is_bind_syscall(sys, arch)
+        || kernel_abi::is_listen_syscall(sys, arch)
+        || kernel_abi::is_accept_syscall(sys, arch)
+        || kernel_abi::is_accept4_syscall(sys, arch)
+        || kernel_abi::is_shutdown_syscall(sys, arch)
+        || kernel_abi::is_getsockopt_syscall(sys, arch)
+        || kernel_abi::is_setsockopt_syscall(sys, arch)
+}
+
+/// Syscalls whose first parameter is a dirfd, which they ignore for absolute
+/// paths.
+fn takes_dirfd_arg1(sys: i32, arch: SupportedArch) -> bool {
+    kernel_abi::is_openat_syscall(sys, arch)
+        || kernel_abi::is_fstatat64_syscall(sys, arch)
+        || kernel_abi::is_unlinkat_syscall(sys, arch)
+        || kernel_abi::is_mkdirat_syscall(sys, arch)
+        || kernel_abi::is_renameat_syscall(sys, arch)
+        || kernel_abi::is_readlinkat_syscall(sys, arch)
+        || kernel_abi::is_faccessat_syscall(sys, arch)
+}
@@ -0,0 +1,675 @@
+use crate::{
+    commands::{
+        grep_command::uses_fd_arg1,
+        rd_options::{RdOptions, RdSubCommand},
+        RdCommand,
+    },
+    event::{EventType, SyscallEventData, SyscallState},
+    kernel_abi::{self, SupportedArch},
+    kernel_metadata::{is_exec, syscall_name},
+    registers::Registers,
+    session::address_space::kernel_mapping::KernelMapping,
+    ticks::Ticks,
+    trace::{
+        trace_frame::{FrameTime, TraceFrame},
+        trace_reader::{TraceReader, ValidateSourceFile},
+        trace_stream::{MappedData, MappedDataSource, RawDataMetadata},
+        trace_task_event::{TraceTaskEvent, TraceTaskEventVariant},
+    },
+};
+use libc::{pid_t, CLONE_FILES};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    fmt::{self, Display, Formatter},
+    fs,
+    io,
+    io::{stdout, Write},
+    path::PathBuf,
+    rc::Rc,
+};
+
+pub struct LintCommand {
+    trace_dir: Option<PathBuf>,
+}
+
+impl LintCommand {
+    pub fn new(options: &RdOptions) -> LintCommand {
+        match options.cmd.clone() {
+            RdSubCommand::Lint { trace_dir } => LintCommand { trace_dir },
+            _ => panic!("Unexpected RdSubCommand variant. Not a `Lint` variant!"),
+        }
+    }
+}
+
+impl RdCommand for LintCommand {
+    fn run(&mut self) -> io::Result<()> {
+        let trace = TraceReader::new(self.trace_dir.as_ref());
+        let problems = lint(trace);
+        let mut out = stdout();
+        if problems.is_empty() {
+            write!(out, "No problems found in the trace.\n")?;
+            return Ok(());
+        }
+        for p in &problems {
+            write!(out, "event {}: {}\n", p.time(), p)?;
+        }
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!(
+                "{} problem(s) found; replaying this trace will probably fail",
+                problems.len()
+            ),
+        ))
+    }
+}
+
+/// Something in the trace that can't be right, whatever the tracees did.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Problem {
+    /// Task events are supposed to be in the order they happened.
+    TaskEventOutOfOrder {
+        time: FrameTime,
+        tid: pid_t,
+        prev_time: FrameTime,
+    },
+    /// A clone(), fork() or vfork() returned `child`, but there is no task
+    /// creation for it.
+    CloneWithoutTask {
+        time: FrameTime,
+        tid: pid_t,
+        syscall: String,
+        child: pid_t,
+    },
+    /// An event of a task that was never created, or had exited.
+    EventOfDeadTask {
+        time: FrameTime,
+        tid: pid_t,
+        exited: bool,
+    },
+    /// A task's tick count went down.
+    TicksWentBack {
+        time: FrameTime,
+        tid: pid_t,
+        ticks: Ticks,
+        prev_ticks: Ticks,
+    },
+    /// The mapping of `fsname` should be replayed from `file`, which isn't
+    /// there.
+    MissingMappedFile {
+        time: FrameTime,
+        tid: pid_t,
+        fsname: OsString,
+        file: OsString,
+    },
+    /// The contents of the mapping of `fsname` at `start` should have been
+    /// saved with the event, but weren't.
+    MissingMappedData {
+        time: FrameTime,
+        tid: pid_t,
+        fsname: OsString,
+        start: usize,
+    },
+    /// A syscall succeeded on an fd the trace shows being closed.
+    ClosedFd {
+        time: FrameTime,
+        tid: pid_t,
+        syscall: String,
+        fd: i32,
+    },
+}
+
+impl Problem {
+    fn time(&self) -> FrameTime {
+        match self {
+            Problem::TaskEventOutOfOrder { time, .. }
+            | Problem::CloneWithoutTask { time, .. }
+            | Problem::EventOfDeadTask { time, .. }
+            | Problem::TicksWentBack { time, .. }
+            | Problem::MissingMappedFile { time, .. }
+            | Problem::MissingMappedData { time, .. }
+            | Problem::ClosedFd { time, .. } => *time,
+        }
+    }
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::TaskEventOutOfOrder { tid, prev_time, .. } => write!(
+                f,
+                "task event of tid {} comes after one at event {}",
+                tid, prev_time
+            ),
+            Problem::CloneWithoutTask {
+                tid,
+                syscall,
+                child,
+                ..
+            } => write!(
+                f,
+                "{} by tid {} returned {}, but no such task was created",
+                syscall, tid, child
+            ),
+            Problem::EventOfDeadTask { tid, exited, .. } => write!(
+                f,
+                "event of tid {}, which {}",
+                tid,
+                if *exited {
+                    "had exited"
+                } else {
+                    "was never created"
+                }
+            ),
+            Problem::TicksWentBack {
+                tid,
+                ticks,
+                prev_ticks,
+                ..
+            } => write!(
+                f,
+                "tid {} is at {} ticks, down from {}",
+                tid, ticks, prev_ticks
+            ),
+            Problem::MissingMappedFile {
+                tid, fsname, file, ..
+            } => write!(
+                f,
+                "mapping of {:?} by tid {} needs {:?}, which can't be found",
+                fsname, tid, file
+            ),
+            Problem::MissingMappedData {
+                tid, fsname, start, ..
+            } => write!(
+                f,
+                "contents of the mapping of {:?} at {:#x} by tid {} weren't saved",
+                fsname, start, tid
+            ),
+            Problem::ClosedFd {
+                tid, syscall, fd, ..
+            } => write!(
+                f,
+                "{}({}, ...) by tid {} succeeded, but fd {} was closed",
+                syscall, fd, tid, fd
+            ),
+        }
+    }
+}
+
+/// The fds each fd table is known to have closed. Tasks inherit fds from
+/// outside the recording and get new ones in ways that don't show in the
+/// trace (from pipe() or recvmsg(), in buffered syscalls), so only fds the
+/// trace shows being closed are tracked, until something may have reused
+/// them.
+#[derive(Default)]
+struct FdTables {
+    closed: HashMap<pid_t, Rc<RefCell<HashSet<i32>>>>,
+}
+
+impl FdTables {
+    fn table(&mut self, tid: pid_t) -> Rc<RefCell<HashSet<i32>>> {
+        self.closed.entry(tid).or_default().clone()
+    }
+
+    fn did_clone(&mut self, parent: pid_t, child: pid_t, clone_flags: i32) {
+        let table = self.table(parent);
+        let table = if clone_flags & CLONE_FILES != 0 {
+            table
+        } else {
+            Rc::new(RefCell::new(table.borrow().clone()))
+        };
+        self.closed.insert(child, table);
+    }
+
+    fn is_closed(&self, tid: pid_t, fd: i32) -> bool {
+        self.closed
+            .get(&tid)
+            .map_or(false, |table| table.borrow().contains(&fd))
+    }
+
+    fn did_close(&mut self, tid: pid_t, fd: i32) {
+        self.table(tid).borrow_mut().insert(fd);
+    }
+
+    fn did_open(&mut self, tid: pid_t, fd: i32) {
+        self.table(tid).borrow_mut().remove(&fd);
+    }
+
+    /// `tid` may have opened any fd.
+    fn forget(&mut self, tid: pid_t) {
+        self.table(tid).borrow_mut().clear();
+    }
+}
+
+#[derive(Default)]
+struct TaskState {
+    ticks: Ticks,
+    exited: bool,
+}
+
+/// A clone task event: (time, tid, parent tid, clone flags).
+type Creation = (FrameTime, pid_t, pid_t, i32);
+
+/// Scan `trace` once and return every problem, in event order.
+fn lint(mut trace: TraceReader) -> Vec<Problem> {
+    let mut task_events: Vec<(FrameTime, TraceTaskEvent)> = Vec::new();
+    let mut time: FrameTime = 0;
+    while let Some(e) = trace.read_task_event(Some(&mut time)) {
+        task_events.push((time, e));
+    }
+    let mut linter = Linter::new(&task_events);
+    while !trace.at_end() {
+        let frame = trace.read_frame();
+        let mut mappings: Vec<(KernelMapping, MappedData)> = Vec::new();
+        loop {
+            let mut data = MappedData::default();
+            let maybe_km = trace.read_mapped_region(
+                Some(&mut data),
+                Some(ValidateSourceFile::DontValidate),
+                None,
+                None,
+                None,
+            );
+            match maybe_km {
+                Some(km) => mappings.push((km, data)),
+                None => break,
+            }
+        }
+        let mut raw: Vec<RawDataMetadata> = Vec::new();
+        while let Some(r) = trace.read_raw_data_metadata_for_frame() {
+            raw.push(r);
+        }
+        linter.check_frame(&frame, &mappings, &raw);
+    }
+    linter.problems()
+}
+
+/// What `lint()` knows about the trace so far. Task events are all read
+/// up front, frames are checked one at a time in event order.
+struct Linter {
+    problems: Vec<Problem>,
+    /// Latest last.
+    creations: Vec<Creation>,
+    /// (parent, child) by the tid the child has in the parent's namespace
+    /// as well as its recorded tid.
+    clones: HashSet<(pid_t, pid_t)>,
+    tasks: HashMap<pid_t, TaskState>,
+    fds: FdTables,
+    first_frame: bool,
+}
+
+impl Linter {
+    /// `task_events` are the trace's task events with the times they were
+    /// recorded at, in the order they're in the trace.
+    fn new(task_events: &[(FrameTime, TraceTaskEvent)]) -> Linter {
+        let mut problems = Vec::new();
+        let mut creations: Vec<Creation> = Vec::new();
+        let mut clones: HashSet<(pid_t, pid_t)> = HashSet::new();
+        let mut prev_time: FrameTime = 0;
+        for &(time, ref e) in task_events {
+            if time < prev_time {
+                problems.push(Problem::TaskEventOutOfOrder {
+                    time,
+                    tid: e.tid(),
+                    prev_time,
+                });
+            }
+            prev_time = time;
+            if let TraceTaskEventVariant::Clone(c) = e.event_variant() {
+                clones.insert((c.parent_tid(), e.tid()));
+                clones.insert((c.parent_tid(), c.own_ns_tid()));
+                creations.push((time, e.tid(), c.parent_tid(), c.clone_flags()));
+            }
+        }
+        creations.sort_by_key(|c| c.0);
+        creations.reverse();
+        Linter {
+            problems,
+            creations,
+            clones,
+            tasks: HashMap::new(),
+            fds: FdTables::default(),
+            first_frame: true,
+        }
+    }
+
+    /// Check `frame` and the `mappings` and `raw` data recorded with it.
+    fn check_frame(
+        &mut self,
+        frame: &TraceFrame,
+        mappings: &[(KernelMapping, MappedData)],
+        raw: &[RawDataMetadata],
+    ) {
+        let tid = frame.tid();
+        while self.creations.last().map_or(false, |c| c.0 <= frame.time()) {
+            let (_, child, parent, clone_flags) = self.creations.pop().unwrap();
+            self.tasks.insert(child, TaskState::default());
+            self.fds.did_clone(parent, child, clone_flags);
+        }
+        // The initial task isn't created by anyone in the trace.
+        if self.first_frame {
+            self.tasks.entry(tid).or_default();
+            self.first_frame = false;
+        }
+
+        let ev = frame.event();
+        match self.tasks.get_mut(&tid) {
+            Some(task) if !task.exited => {
+                if frame.ticks() < task.ticks {
+                    self.problems.push(Problem::TicksWentBack {
+                        time: frame.time(),
+                        tid,
+                        ticks: frame.ticks(),
+                        prev_ticks: task.ticks,
+                    });
+                }
+                task.ticks = frame.ticks();
+                task.exited = ev.event_type() == EventType::EvExit;
+            }
+            // A thread other than the leader exec'd and took over the tid of
+            // the leader, which exited first. It keeps its own tick count.
+            Some(task)
+                if ev.is_syscall_event() && is_exec(ev.syscall().number, ev.syscall().arch()) =>
+            {
+                task.ticks = frame.ticks();
+                task.exited = false;
+            }
+            maybe_task => {
+                self.problems.push(Problem::EventOfDeadTask {
+                    time: frame.time(),
+                    tid,
+                    exited: maybe_task.is_some(),
+                });
+                // Once is enough.
+                self.tasks.insert(
+                    tid,
+                    TaskState {
+                        ticks: frame.ticks(),
+                        exited: false,
+                    },
+                );
+            }
+        }
+
+        match ev.event_type() {
+            EventType::EvSyscall if ev.syscall().state == SyscallState::ExitingSyscall => {
+                let sys = ev.syscall();
+                let regs = frame.regs_ref();
+                let syscall = || syscall_name(sys.number, sys.arch());
+                let result = regs.syscall_result_signed();
+                if result > 0
+                    && is_clone_family(sys.number, sys.arch())
+                    && !self.clones.contains(&(tid, result as pid_t))
+                {
+                    self.problems.push(Problem::CloneWithoutTask {
+                        time: frame.time(),
+                        tid,
+                        syscall: syscall(),
+                        child: result as pid_t,
+                    });
+                }
+                let fd = regs.arg1_signed() as i32;
+                if result >= 0
+                    && uses_fd_arg1(sys.number, sys.arch())
+                    && self.fds.is_closed(tid, fd)
+                {
+                    self.problems.push(Problem::ClosedFd {
+                        time: frame.time(),
+                        tid,
+                        syscall: syscall(),
+                        fd,
+                    });
+                }
+                note_syscall_fds(&mut self.fds, tid, sys, regs);
+            }
+            // Whatever the buffered syscalls did isn't in the trace.
+            EventType::EvSyscallbufFlush => self.fds.forget(tid),
+            _ => (),
+        }
+
+        for (km, data) in mappings {
+            if let Some(p) = check_mapping(km, data, raw, frame.time(), tid) {
+                self.problems.push(p);
+            }
+        }
+    }
+
+    /// Every problem found, in event order.
+    fn problems(mut self) -> Vec<Problem> {
+        self.problems.sort_by_key(|p| p.time());
+        self.problems
+    }
+}
+
+fn is_clone_family(sys: i32, arch: SupportedArch) -> bool {
+    kernel_abi::is_clone_syscall(sys, arch)
+        || kernel_abi::is_clone3_syscall(sys, arch)
+        || kernel_abi::is_fork_syscall(sys, arch)
+        || kernel_abi::is_vfork_syscall(sys, arch)
+}
+
+/// Syscalls that can give the caller new fds without returning them:
+/// they're written to memory (pipe(), socketpair(), SCM_RIGHTS messages,
+/// CLONE_PIDFD) instead.
+fn writes_new_fds(sys: i32, arch: SupportedArch) -> bool {
+    kernel_abi::is_pipe_syscall(sys, arch)
+        || kernel_abi::is_pipe2_syscall(sys, arch)
+        || kernel_abi::is_socketpair_syscall(sys, arch)
+        || kernel_abi::is_recvmsg_syscall(sys, arch)
+        || kernel_abi::is_recvmmsg_syscall(sys, arch)
+        || kernel_abi::is_socketcall_syscall(sys, arch)
+        || is_clone_family(sys, arch)
+}
+
+/// Update `fds` for the syscall `tid` completed with `regs`.
+fn note_syscall_fds(fds: &mut FdTables, tid: pid_t, sys: &SyscallEventData, regs: &Registers) {
+    let result = regs.syscall_result_signed();
+    if result < 0 {
+        return;
+    }
+    if kernel_abi::is_close_syscall(sys.number, sys.arch()) {
+        fds.did_close(tid, regs.arg1_signed() as i32);
+    }
+    for &fd in &sys.exec_fds_to_close {
+        fds.did_close(tid, fd);
+    }
+    for opened in &sys.opened {
+        fds.did_open(tid, opened.fd);
+    }
+    if writes_new_fds(sys.number, sys.arch()) {
+        fds.forget(tid);
+    } else if result <= i32::MAX as isize {
+        // Lots of syscalls return new fds (dup(), socket(), accept(),
+        // fcntl(F_DUPFD) ...). A syscall that returned some other number that
+        // happens to be a closed fd just makes us miss a problem.
+        fds.did_open(tid, result as i32);
+    }
+}
+
+/// The problem with `km`, mapped by `tid` at event `time`, if the data to
+/// replay it from is missing.
+fn check_mapping(
+    km: &KernelMapping,
+    data: &MappedData,
+    raw: &[RawDataMetadata],
+    time: FrameTime,
+    tid: pid_t,
+) -> Option<Problem> {
+    match data.source {
+        MappedDataSource::SourceFile if fs::metadata(&data.filename).is_err() => {
+            Some(Problem::MissingMappedFile {
+                time,
+                tid,
+                fsname: km.fsname().to_owned(),
+                file: data.filename.clone(),
+            })
+        }
+        MappedDataSource::SourceTrace => {
+            // Nothing is saved for the part of a file mapping that's past the
+            // end of the file.
+            let past_eof =
+                data.file_size_bytes > 0 && km.file_offset_bytes() >= data.file_size_bytes as u64;
+            let saved = raw
+                .iter()
+                .any(|r| r.addr >= km.start() && r.addr < km.end());
+            if past_eof || saved {
+                None
+            } else {
+                Some(Problem::MissingMappedData {
+                    time,
+                    tid,
+                    fsname: km.fsname().to_owned(),
+                    start: km.start().as_usize(),
+                })
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        arch::{Architecture, X64Arch},
+        event::Event,
+        remote_ptr::RemotePtr,
+    };
+    use libc::SIGCHLD;
+    use nix::sys::mman::{MapFlags, ProtFlags};
+    use std::ffi::OsStr;
+
+    /// A frame of `tid` leaving syscall `sys` with `arg1` and `result`.
+    fn syscall_exit(
+        time: FrameTime,
+        tid: pid_t,
+        ticks: Ticks,
+        sys: i32,
+        arg1: usize,
+        result: isize,
+    ) -> TraceFrame {
+        let mut data = SyscallEventData::new(sys, SupportedArch::X64);
+        data.state = SyscallState::ExitingSyscall;
+        let mut frame = TraceFrame::new_with(time, tid, Event::new_syscall_event(data), ticks, 0.0);
+        let mut regs = Registers::new(SupportedArch::X64);
+        regs.set_arg1(arg1);
+        regs.set_syscall_result_signed(result);
+        *frame.regs_mut() = regs;
+        frame
+    }
+
+    #[test]
+    fn lint_synthetic_trace() {
+        let task_events = vec![(1, TraceTaskEvent::for_clone(2, 1, 2, CLONE_FILES))];
+        let mut linter = Linter::new(&task_events);
+        linter.check_frame(&syscall_exit(1, 1, 10, X64Arch::CLONE, 0, 2), &[], &[]);
+        linter.check_frame(&syscall_exit(2, 1, 20, X64Arch::CLONE, 0, 5), &[], &[]);
+        linter.check_frame(&syscall_exit(3, 1, 30, X64Arch::CLOSE, 3, 0), &[], &[]);
+        // The thread shares the fd table its parent closed fd 3 in.
+        linter.check_frame(&syscall_exit(4, 2, 5, X64Arch::READ, 3, 10), &[], &[]);
+        linter.check_frame(&syscall_exit(5, 1, 25, X64Arch::GETPID, 0, 1), &[], &[]);
+        linter.check_frame(&syscall_exit(6, 9, 0, X64Arch::GETPID, 0, 9), &[], &[]);
+        let km = KernelMapping::new_with_opts(
+            RemotePtr::new_from_val(0x10000),
+            RemotePtr::new_from_val(0x11000),
+            OsStr::new("/lib/libgone.so"),
+            1,
+            2,
+            ProtFlags::PROT_READ,
+            MapFlags::MAP_PRIVATE,
+            0,
+        );
+        let data = MappedData {
+            source: MappedDataSource::SourceFile,
+            filename: "/nonexistent/libgone.so".into(),
+            ..Default::default()
+        };
+        let mmap = syscall_exit(7, 1, 40, X64Arch::MMAP, 0x10000, 0x10000);
+        linter.check_frame(&mmap, &[(km, data)], &[]);
+
+        let problems = linter.problems();
+        assert_eq!(
+            problems.iter().map(|p| p.time()).collect::<Vec<_>>(),
+            vec![2, 4, 5, 6, 7]
+        );
+        assert_eq!(
+            problems[0],
+            Problem::CloneWithoutTask {
+                time: 2,
+                tid: 1,
+                syscall: "clone".into(),
+                child: 5,
+            }
+        );
+        assert_eq!(
+            problems[1],
+            Problem::ClosedFd {
+                time: 4,
+                tid: 2,
+                syscall: "read".into(),
+                fd: 3,
+            }
+        );
+        assert_eq!(
+            problems[2],
+            Problem::TicksWentBack {
+                time: 5,
+                tid: 1,
+                ticks: 25,
+                prev_ticks: 30,
+            }
+        );
+        assert_eq!(
+            problems[3],
+            Problem::EventOfDeadTask {
+                time: 6,
+                tid: 9,
+                exited: false,
+            }
+        );
+        assert_eq!(
+            problems[4],
+            Problem::MissingMappedFile {
+                time: 7,
+                tid: 1,
+                fsname: "/lib/libgone.so".into(),
+                file: "/nonexistent/libgone.so".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn lint_task_events_out_of_order() {
+        let task_events = vec![
+            (5, TraceTaskEvent::for_clone(2, 1, 2, SIGCHLD)),
+            (3, TraceTaskEvent::for_clone(3, 1, 3, SIGCHLD)),
+        ];
+        assert_eq!(
+            Linter::new(&task_events).problems(),
+            vec![Problem::TaskEventOutOfOrder {
+                time: 3,
+                tid: 3,
+                prev_time: 5,
+            }]
+        );
+    }
+
+    #[test]
+    fn fd_tables_follow_clone_flags() {
+        let mut fds = FdTables::default();
+        fds.did_close(1, 3);
+        fds.did_clone(1, 2, CLONE_FILES);
+        fds.did_clone(1, 3, SIGCHLD);
+        assert!(fds.is_closed(2, 3));
+        assert!(fds.is_closed(3, 3));
+        // Threads share the table, forked processes have their own copy.
+        fds.did_open(2, 3);
+        assert!(!fds.is_closed(1, 3));
+        assert!(fds.is_closed(3, 3));
+        fds.did_close(3, 4);
+        assert!(!fds.is_closed(1, 4));
+        fds.forget(3);
+        assert!(!fds.is_closed(3, 3));
+    }
+}
@@ -0,0 +1,298 @@
+use crate::{
+    cancellation_token::CancellationToken,
+    commands::{
+        rd_options::{RdOptions, RdSubCommand},
+        RdCommand,
+    },
+    replay_timeline::ReplayTimeline,
+    session::{
+        address_space::{address_space::Mapping, MappingFlags},
+        replay_session::{Flags, ReplaySession},
+    },
+    trace::{
+        trace_frame::FrameTime,
+        trace_reader::{TraceReader, ValidateSourceFile},
+        trace_stream::{MappedData, MappedDataSource},
+        trace_task_event::{TraceTaskEvent, TraceTaskEventVariant},
+    },
+};
+use libc::{pid_t, CLONE_THREAD};
+use nix::sys::{
+    mman::{MapFlags, ProtFlags},
+    stat::{major, minor},
+};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    io,
+    io::{stdout, Write},
+    path::PathBuf,
+};
+
+pub struct MapsCommand {
+    event: FrameTime,
+    tid: Option<pid_t>,
+    json: bool,
+    trace_dir: Option<PathBuf>,
+}
+
+impl MapsCommand {
+    pub fn new(options: &RdOptions) -> MapsCommand {
+        match options.cmd.clone() {
+            RdSubCommand::Maps {
+                event,
+                tid,
+                json,
+                trace_dir,
+            } => MapsCommand {
+                event,
+                tid,
+                json,
+                trace_dir,
+            },
+            _ => panic!("Unexpected RdSubCommand variant. Not a `Maps` variant!"),
+        }
+    }
+
+    fn maps(&self, out: &mut dyn Write) -> io::Result<()> {
+        let flags = Flags {
+            redirect_stdio: false,
+            share_private_mappings: false,
+            cpu_unbound: true,
+            dead_tasks: Default::default(),
+        };
+        let mut timeline =
+            ReplayTimeline::new(ReplaySession::create(self.trace_dir.as_ref(), flags));
+        if !timeline.seek_to_event(self.event, &CancellationToken::new()) {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("Replay stopped before reaching event {}", self.event),
+            ));
+        }
+        let session = timeline.current_session();
+        let maybe_task = match self.tid {
+            Some(tid) => session.find_task_from_rec_tid(tid),
+            None => session.as_replay().unwrap().current_task(),
+        };
+        let t = match maybe_task {
+            Some(t) => t,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "No task {} at event {}",
+                        self.tid.map_or("to replay".into(), |tid| tid.to_string()),
+                        self.event
+                    ),
+                ))
+            }
+        };
+
+        let rec_tid = t.borrow().rec_tid;
+        let sources = recorded_sources(self.trace_dir.as_ref(), self.event, rec_tid);
+        let mut entries: Vec<MapEntry> = Vec::new();
+        for (_, m) in &t.borrow().vm().maps() {
+            entries.push(MapEntry::new(m, &sources));
+        }
+        if self.json {
+            let serialized = serde_json::to_string(&entries).unwrap();
+            write!(out, "{}\n", serialized)?;
+        } else {
+            write!(
+                out,
+                "Task {} at event {}:\n",
+                t.borrow().rec_tid,
+                self.event
+            )?;
+            for e in &entries {
+                e.write_line(out)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RdCommand for MapsCommand {
+    fn run(&mut self) -> io::Result<()> {
+        self.maps(&mut stdout())
+    }
+}
+
+/// A mapping record from the trace.
+#[derive(Clone)]
+struct RecordedMapping {
+    start: usize,
+    end: usize,
+    source: MappedDataSource,
+    /// The file replay maps the data from, for `SourceFile`.
+    backing_file: String,
+}
+
+/// The mapping records of the events before `event` in the process of
+/// `rec_tid` since its last exec, in trace order.
+fn recorded_sources(
+    trace_dir: Option<&PathBuf>,
+    event: FrameTime,
+    rec_tid: pid_t,
+) -> Vec<RecordedMapping> {
+    let mut trace = TraceReader::new(trace_dir);
+    let mut task_events: Vec<(FrameTime, TraceTaskEvent)> = Vec::new();
+    let mut time: FrameTime = 0;
+    while let Some(e) = trace.read_task_event(Some(&mut time)) {
+        task_events.push((time, e));
+    }
+    task_events.reverse();
+
+    // Threads have the tgid of the thread that cloned them; everything else
+    // is its own process.
+    let mut tgids: HashMap<pid_t, pid_t> = HashMap::new();
+    let tgid_of = |tgids: &HashMap<pid_t, pid_t>, tid| tgids.get(&tid).copied().unwrap_or(tid);
+    let mut per_process: HashMap<pid_t, Vec<RecordedMapping>> = HashMap::new();
+    while !trace.at_end() {
+        let frame = trace.read_frame();
+        if frame.time() >= event {
+            break;
+        }
+        while task_events.last().map_or(false, |e| e.0 <= frame.time()) {
+            let (_, e) = task_events.pop().unwrap();
+            match e.event_variant() {
+                TraceTaskEventVariant::Clone(c) if c.clone_flags() & CLONE_THREAD != 0 => {
+                    let tgid = tgid_of(&tgids, c.parent_tid());
+                    tgids.insert(e.tid(), tgid);
+                }
+                TraceTaskEventVariant::Clone(c) => {
+                    // A new process starts with a copy of its parent's
+                    // address space.
+                    let inherited = per_process.get(&tgid_of(&tgids, c.parent_tid())).cloned();
+                    tgids.insert(e.tid(), e.tid());
+                    per_process.insert(e.tid(), inherited.unwrap_or_default());
+                }
+                TraceTaskEventVariant::Exec(_) => {
+                    per_process.remove(&tgid_of(&tgids, e.tid()));
+                }
+                TraceTaskEventVariant::Exit(_) => (),
+            }
+        }
+        let tgid = tgid_of(&tgids, frame.tid());
+        loop {
+            let mut data = MappedData::default();
+            let maybe_km = trace.read_mapped_region(
+                Some(&mut data),
+                Some(ValidateSourceFile::DontValidate),
+                None,
+                None,
+                None,
+            );
+            match maybe_km {
+                Some(km) => per_process.entry(tgid).or_default().push(RecordedMapping {
+                    start: km.start().as_usize(),
+                    end: km.end().as_usize(),
+                    source: data.source,
+                    backing_file: data.filename.to_string_lossy().into_owned(),
+                }),
+                None => break,
+            }
+        }
+        while trace.read_raw_data_metadata_for_frame().is_some() {}
+    }
+    per_process
+        .remove(&tgid_of(&tgids, rec_tid))
+        .unwrap_or_default()
+}
+
+/// One line of the output: the recorded mapping (the file names, devices and
+/// inodes are what the tracee saw during recording) and where replay got the
+/// contents from.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MapEntry {
+    start: usize,
+    end: usize,
+    prot: String,
+    shared: bool,
+    offset: u64,
+    device: String,
+    inode: u64,
+    fsname: String,
+    /// `trace`, `file`, `zero`, `rd` for rd's own mappings, or `unknown` if
+    /// no mapping record covers the mapping (e.g. the stack, or the heap
+    /// grown by brk).
+    source: String,
+    backing_file: Option<String>,
+}
+
+impl MapEntry {
+    fn new(m: &Mapping, sources: &[RecordedMapping]) -> MapEntry {
+        let km = &m.recorded_map;
+        let start = m.map.start().as_usize();
+        let mut backing_file = None;
+        let source = if m.flags.intersects(
+            MappingFlags::IS_SYSCALLBUF
+                | MappingFlags::IS_THREAD_LOCALS
+                | MappingFlags::IS_PATCH_STUBS
+                | MappingFlags::IS_RD_PAGE,
+        ) {
+            "rd"
+        } else {
+            // The most recent record covering the mapping; mprotect() and the
+            // like split mappings, so an exact match is too much to ask for.
+            match sources
+                .iter()
+                .rev()
+                .find(|r| r.start <= start && start < r.end)
+            {
+                Some(r) => match r.source {
+                    MappedDataSource::SourceTrace => "trace",
+                    MappedDataSource::SourceFile => {
+                        backing_file = Some(r.backing_file.clone());
+                        "file"
+                    }
+                    MappedDataSource::SourceZero => "zero",
+                },
+                None => "unknown",
+            }
+        };
+        let prot = km.prot();
+        MapEntry {
+            start,
+            end: m.map.end().as_usize(),
+            prot: [
+                (ProtFlags::PROT_READ, 'r'),
+                (ProtFlags::PROT_WRITE, 'w'),
+                (ProtFlags::PROT_EXEC, 'x'),
+            ]
+            .iter()
+            .map(|&(bit, c)| if prot.contains(bit) { c } else { '-' })
+            .collect(),
+            shared: km.flags().contains(MapFlags::MAP_SHARED),
+            offset: km.file_offset_bytes(),
+            device: format!("{:02x}:{:02x}", major(km.device()), minor(km.device())),
+            inode: km.inode(),
+            fsname: km.fsname().to_string_lossy().into_owned(),
+            source: source.into(),
+            backing_file,
+        }
+    }
+
+    fn write_line(&self, out: &mut dyn Write) -> io::Result<()> {
+        write!(
+            out,
+            "{:x}-{:x} {}{} {:08x} {} {:<10} {:<7} {}",
+            self.start,
+            self.end,
+            self.prot,
+            if self.shared { 's' } else { 'p' },
+            self.offset,
+            self.device,
+            self.inode,
+            self.source,
+            self.fsname
+        )?;
+        if let Some(f) = &self.backing_file {
+            if *f != self.fsname {
+                write!(out, " (from {})", f)?;
+            }
+        }
+        write!(out, "\n")
+    }
+}
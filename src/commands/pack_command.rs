@@ -0,0 +1,140 @@
+use crate::{
+    commands::{
+        rd_options::{RdOptions, RdSubCommand},
+        RdCommand,
+    },
+    kernel_supplement::BTRFS_IOC_CLONE_,
+    log::LogLevel::{LogDebug, LogWarn},
+    trace::trace_reader::TraceReader,
+    trace_capnp::m_map,
+};
+use libc::ioctl;
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    fs,
+    fs::{File, OpenOptions},
+    io,
+    io::{stdout, Write},
+    os::unix::{ffi::OsStrExt, io::AsRawFd},
+    path::{Path, PathBuf},
+};
+
+/// Make a trace self-contained: copy every file that mmap records refer to by
+/// absolute path into the trace directory and point the records at the copies.
+/// Files the trace already holds (clones, copies, hardlinks) are left alone, so
+/// packing a trace twice is harmless.
+pub struct PackCommand {
+    trace_dir: Option<PathBuf>,
+}
+
+impl PackCommand {
+    pub fn new(options: &RdOptions) -> PackCommand {
+        match options.cmd.clone() {
+            RdSubCommand::Pack { trace_dir } => PackCommand { trace_dir },
+            _ => panic!("Unexpected RdSubCommand variant. Not a Pack variant!"),
+        }
+    }
+
+    fn pack(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut trace = TraceReader::new(self.trace_dir.as_ref());
+        let dir = trace.dir().to_owned();
+        // Original file name -> name of the copy, relative to the trace dir.
+        let mut packed: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+        let mut failed = 0;
+
+        trace.rewrite_mapped_regions(|mut map| {
+            let file_name = match map.reborrow().get_source().which() {
+                Ok(m_map::source::File(f)) => f.get_backing_file_name().unwrap().to_vec(),
+                _ => return,
+            };
+            // A relative name is already in the trace directory.
+            if !file_name.starts_with(b"/") {
+                return;
+            }
+            if !packed.contains_key(&file_name) {
+                match pack_file(&dir, &file_name, packed.len()) {
+                    Ok(new_name) => {
+                        packed.insert(file_name.clone(), new_name);
+                    }
+                    Err(e) => {
+                        log!(
+                            LogWarn,
+                            "Can't copy {:?} into the trace: {}",
+                            OsStr::from_bytes(&file_name),
+                            e
+                        );
+                        failed += 1;
+                        return;
+                    }
+                }
+            }
+            map.get_source()
+                .init_file()
+                .set_backing_file_name(&packed[&file_name]);
+        })?;
+        let renamed: HashMap<OsString, OsString> = packed
+            .iter()
+            .map(|(old_name, new_name)| {
+                (
+                    OsStr::from_bytes(old_name).to_os_string(),
+                    OsStr::from_bytes(new_name).to_os_string(),
+                )
+            })
+            .collect();
+        trace.rename_backing_files(&renamed)?;
+
+        write!(out, "Packed {} files into {:?}\n", packed.len(), dir)?;
+        if failed > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!(
+                    "{} files could not be packed; the trace still depends on them",
+                    failed
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl RdCommand for PackCommand {
+    fn run(&mut self) -> io::Result<()> {
+        self.pack(&mut stdout())
+    }
+}
+
+/// Put a copy of `file_name` into `dir` and return its name relative to `dir`.
+/// Clone it if the filesystem can, since that's free, and copy it otherwise.
+/// A hardlink would be free too, but it changes along with the original when
+/// that's rewritten in place. The `mmap_pack_` prefix tells replay not to
+/// compare the file's metadata with the recorded one.
+fn pack_file(dir: &OsStr, file_name: &[u8], index: usize) -> io::Result<Vec<u8>> {
+    let src = Path::new(OsStr::from_bytes(file_name));
+    if !fs::metadata(src)?.is_file() {
+        return Err(io::Error::new(io::ErrorKind::Other, "not a regular file"));
+    }
+    let mut new_name = format!("mmap_pack_{}_", index).into_bytes();
+    new_name.extend_from_slice(src.file_name().unwrap_or_default().as_bytes());
+    let dest = Path::new(dir).join(OsStr::from_bytes(&new_name));
+    let mut src_file = File::open(src)?;
+    let mut dest_file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&dest)?;
+    let ret = unsafe {
+        ioctl(
+            dest_file.as_raw_fd(),
+            BTRFS_IOC_CLONE_,
+            src_file.as_raw_fd(),
+        )
+    };
+    if ret < 0 {
+        if let Err(e) = io::copy(&mut src_file, &mut dest_file) {
+            fs::remove_file(&dest).ok();
+            return Err(e);
+        }
+    }
+    log!(LogDebug, "Packed {:?} as {:?}", src, dest);
+    Ok(new_name)
+}
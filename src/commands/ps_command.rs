@@ -4,6 +4,8 @@ use crate::{
         RdCommand,
     },
     trace::{
+        trace_exec_history::ExecHistory,
+        trace_frame::FrameTime,
         trace_reader::TraceReader,
         trace_task_event::{TraceTaskEvent, TraceTaskEventVariant},
     },
@@ -12,6 +14,7 @@ use crate::{
 use libc::pid_t;
 use std::{
     collections::HashMap,
+    ffi::OsString,
     fmt::Write as fmtWrite,
     io,
     io::{stdout, Write},
@@ -20,12 +23,13 @@ use std::{
 
 pub struct PsCommand {
     trace_dir: Option<PathBuf>,
+    full: bool,
 }
 
 impl PsCommand {
     pub fn new(options: &RdOptions) -> PsCommand {
         match options.cmd.clone() {
-            RdSubCommand::Ps { trace_dir } => PsCommand { trace_dir },
+            RdSubCommand::Ps { trace_dir, full } => PsCommand { trace_dir, full },
             _ => panic!("Unexpected RdSubCommand variant. Not a `Ps` variant!"),
         }
     }
@@ -45,8 +49,11 @@ impl PsCommand {
         write!(out, "PID\tPPID\tEXIT\tCMD\n")?;
 
         let mut events: Vec<TraceTaskEvent> = Vec::new();
-        while let Some(r) = trace.read_task_event(None) {
+        let mut times: Vec<FrameTime> = Vec::new();
+        let mut time: FrameTime = 0;
+        while let Some(r) = trace.read_task_event(Some(&mut time)) {
             events.push(r);
+            times.push(time);
         }
 
         let not_exec = match events[0].event_variant() {
@@ -72,6 +79,15 @@ impl PsCommand {
         )?;
         write_exec_cmd_line(&events[0], out)?;
 
+        let maybe_history = if self.full {
+            Some(ExecHistory::new(&events, &times))
+        } else {
+            None
+        };
+        if let Some(history) = &maybe_history {
+            write_exec_generations(history, initial_tid, times[0], out)?;
+        }
+
         for (i, e) in events.iter().enumerate() {
             update_tid_to_pid_map(&mut tid_to_pid, e);
 
@@ -103,6 +119,9 @@ impl PsCommand {
                             write_exec_cmd_line(&events[cmd_line_index], out)?;
                         }
                     }
+                    if let Some(history) = &maybe_history {
+                        write_exec_generations(history, e.tid(), times[i], out)?;
+                    }
                 }
                 _ => (),
             }
@@ -187,9 +206,35 @@ fn find_cmd_line(
     None
 }
 
+/// For `rd ps --full`: one line per exec done by the process `pid` that was
+/// alive at `time`.
+fn write_exec_generations(
+    history: &ExecHistory,
+    pid: pid_t,
+    time: FrameTime,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    for g in history.generations(pid, time) {
+        write!(
+            out,
+            "\t\t\t[exec {} at event {}] {:?} ({:?}) ",
+            g.exec_count,
+            g.time,
+            g.file_name,
+            g.prname()
+        )?;
+        write_cmd_line(&g.cmd_line, out)?;
+    }
+    Ok(())
+}
+
 fn write_exec_cmd_line(event: &TraceTaskEvent, out: &mut dyn Write) -> io::Result<()> {
+    write_cmd_line(event.exec_variant().cmd_line(), out)
+}
+
+fn write_cmd_line(cmd_line: &[OsString], out: &mut dyn Write) -> io::Result<()> {
     let mut first = true;
-    for word in event.exec_variant().cmd_line() {
+    for word in cmd_line {
         if !first {
             write!(out, " ")?;
         } else {
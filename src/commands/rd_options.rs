@@ -1,6 +1,11 @@
 use crate::{
     commands::rerun_command::TraceFields,
+    dead_tasks::{parse_dead_task_policy, DeadTaskPolicy},
     flags::{Checksum, DumpOn},
+    io_uring::IoUringPolicy,
+    perf_counters::{CoreType, TicksSemantics},
+    session::task::task_inner::MAX_TICKS_REQUEST,
+    ticks::Ticks,
     trace::trace_frame::FrameTime,
 };
 use libc::pid_t;
@@ -43,6 +48,21 @@ pub struct RdOptions {
     #[structopt(short = "A", long)]
     pub microarch: Option<String>,
 
+    /// Where <core-type> := `p-core` | `e-core`
+    ///
+    /// On CPUs with two kinds of core (e.g. Intel Alder Lake), run tracees on cores of this
+    /// kind only and program the performance counters for it. The default is P-cores.
+    /// Replay must use the kind of core the trace was recorded on.
+    #[structopt(long, parse(try_from_str = parse_core_type))]
+    pub core_type: Option<CoreType>,
+
+    /// How many ticks past the requested point a performance counter interrupt may arrive.
+    /// Replay programs interrupts this far short of its target and single-steps the rest.
+    /// Defaults to a value determined for the CPU's microarchitecture; increase it if replay
+    /// reports overshooting a ticks target although nothing else diverged.
+    #[structopt(long, parse(try_from_str = parse_skid_size))]
+    pub skid_size: Option<Ticks>,
+
     /// Force rd to do some things that don't seem like good ideas, for example launching
     /// an interactive emergency debugger if stderr isn't a tty.
     #[structopt(short = "F", long)]
@@ -55,6 +75,13 @@ pub struct RdOptions {
     )]
     pub check_cached_mmaps: bool,
 
+    #[structopt(
+        long,
+        help = "After each syscall during recording, check that the kernel left registers \
+        in the expected state and warn if it didn't."
+    )]
+    pub check_syscall_regs: bool,
+
     #[structopt(
         short = "E",
         long,
@@ -92,11 +119,12 @@ pub struct RdOptions {
     short = "C",
     long,
     parse(try_from_str = parse_checksum),
-    help = "Where <checksum> := `on-syscalls` | `on-all-events` | <from-time>\n\n\
-                Compute and store (during recording) or read and verify (during replay) checksums \
-                of each of a tracee's memory mappings either at the end of all syscalls (`on-syscalls`), \
-                at all events (`on-all-events`), or starting from a global timepoint <from-time> \
-                (which is a positive integer).",
+    help = "Where <checksum> := `on-syscalls` | `on-all-events` | `every-<n>` | <from-time>\n\n\
+                Compute and store (during recording) checksums of each of a tracee's memory \
+                mappings either at the end of all syscalls (`on-syscalls`), at all events \
+                (`on-all-events`), every <n> events (`every-<n>`), or starting from a global \
+                timepoint <from-time> (which is a positive integer). Replay verifies any \
+                checksums in the trace and reports the first event at which memory diverged.",
     )]
     pub checksum: Option<Checksum>,
 
@@ -120,14 +148,33 @@ fn parse_resource_path(res_path: &OsStr) -> Result<PathBuf, OsString> {
     }
 }
 
+fn parse_core_type(core_type: &str) -> Result<CoreType, Box<dyn Error>> {
+    match core_type {
+        "p-core" => Ok(CoreType::Performance),
+        "e-core" => Ok(CoreType::Efficiency),
+        _ => Err(Box::new(clap::Error::with_description(
+            "Please provide one of 'p-core' or 'e-core'",
+            clap::ErrorKind::InvalidValue,
+        ))),
+    }
+}
+
 fn parse_checksum(checksum_s: &str) -> Result<Checksum, Box<dyn Error>> {
     if checksum_s == "on-syscalls" {
         Ok(Checksum::ChecksumSyscall)
     } else if checksum_s == "on-all-events" {
         Ok(Checksum::ChecksumAll)
+    } else if checksum_s.starts_with("every-") {
+        match checksum_s["every-".len()..].parse::<FrameTime>()? {
+            0 => Err(Box::new(clap::Error::with_description(
+                "The checksum interval must be positive",
+                clap::ErrorKind::InvalidValue,
+            ))),
+            n => Ok(Checksum::ChecksumEvery(n)),
+        }
     } else if checksum_s.chars().all(|c| !c.is_ascii_digit()) {
         Err(Box::new(clap::Error::with_description(
-            "Only `on-syscalls` or `on-all-events` or an unsigned integer is valid here",
+            "Only `on-syscalls`, `on-all-events`, `every-<n>` or an unsigned integer is valid here",
             clap::ErrorKind::InvalidValue,
         )))
     } else {
@@ -197,6 +244,24 @@ pub enum RdSubCommand {
         #[structopt(short = "s")]
         statistics: bool,
 
+        /// Dump the contents of recorded data blocks, in hex. Implies -m
+        #[structopt(short = "d", long)]
+        raw_data: bool,
+
+        /// Only dump syscall events
+        #[structopt(long)]
+        syscalls: bool,
+
+        /// Only dump syscall events for the named syscall. May be given more than once.
+        /// Implies --syscalls
+        #[structopt(long = "syscall", number_of_values = 1)]
+        syscall_names: Vec<String>,
+
+        /// Only dump signal events (SIGNAL, SIGNAL_DELIVERY and SIGNAL_HANDLER). When given
+        /// along with --syscalls, both kinds of events are dumped
+        #[structopt(long)]
+        signals: bool,
+
         /// Dump events only for the specified tid
         #[structopt(short = "t", long = "tid")]
         only_tid: Option<libc::pid_t>,
@@ -210,6 +275,118 @@ pub enum RdSubCommand {
         event_spec: Option<(FrameTime, Option<FrameTime>)>,
     },
 
+    /// Record a program and its children.
+    #[structopt(name = "record")]
+    Record {
+        /// Randomize scheduling decisions to try to reproduce bugs
+        #[structopt(short = "h", long = "chaos")]
+        chaos: bool,
+
+        /// Seed the chaos mode scheduler with <chaos-seed> instead of a random value, to repeat
+        /// the scheduling decisions of an earlier chaos mode recording. Implies --chaos
+        #[structopt(long = "chaos-seed")]
+        chaos_seed: Option<u64>,
+
+        /// Where <num-cpu-ticks> := <ticks>. Maximum number of 'CPU ticks' (currently retired
+        /// conditional branches) to allow a task to run before interrupting it
+        #[structopt(short = "c", long = "num-cpu-ticks", parse(try_from_str = parse_ticks))]
+        num_cpu_ticks: Option<Ticks>,
+
+        /// Where <chaos-ticks> := <low>-<high>. In chaos mode, make each timeslice between
+        /// <low> and <high> 'CPU ticks' long
+        #[structopt(long = "chaos-ticks", parse(try_from_str = parse_ticks_range))]
+        chaos_ticks: Option<(Ticks, Ticks)>,
+
+        /// Run all runnable tasks in turn in strict round-robin order, ignoring priorities,
+        /// each for exactly <num-cpu-ticks> 'CPU ticks'. Helps reproduce multi-threaded hangs
+        #[structopt(long = "round-robin", conflicts_with_all = &["chaos", "chaos-seed"])]
+        round_robin: bool,
+
+        /// Bind rd and all tracees to CPU <bind-to-cpu> instead of a randomly chosen one.
+        /// The CPU is recorded in the trace and replay binds to it too
+        #[structopt(long = "bind-to-cpu", conflicts_with = "cpu-unbound")]
+        bind_to_cpu: Option<u32>,
+
+        /// Don't bind to any CPU. Tick counts may be less reliable and replay may diverge if
+        /// tracees look at which CPU they're running on
+        #[structopt(short = "u", long = "cpu-unbound")]
+        cpu_unbound: bool,
+
+        /// In chaos mode, move tasks between CPUs at random as they get scheduled. The
+        /// recording isn't bound to a CPU
+        #[structopt(
+            long = "chaos-migrate",
+            requires = "chaos",
+            conflicts_with = "bind-to-cpu"
+        )]
+        chaos_migrate: bool,
+
+        /// What 'CPU ticks' count: 'rcb' (retired conditional branches) or 'taken-branches'.
+        /// Defaults to 'rcb' on CPUs that support it
+        #[structopt(long = "ticks-semantics", parse(try_from_str = parse_ticks_semantics))]
+        ticks_semantics: Option<TicksSemantics>,
+
+        /// Tell tracees there are <num-cores> CPUs (in sched_getaffinity(), /proc/cpuinfo and
+        /// /sys/devices/system/cpu) instead of 1
+        #[structopt(long = "num-cores", parse(try_from_str = parse_num_cores))]
+        num_cores: Option<u32>,
+
+        /// Attach to the running process <attach>, and its threads and descendants, and
+        /// record them from here on instead of starting a program
+        #[structopt(short = "p", long = "attach", parse(try_from_str = parse_pid))]
+        attach: Option<pid_t>,
+
+        /// Answer some syscalls (getpid() and friends, sched_yield()) through a seccomp user
+        /// notification listener instead of ptrace stops. Needs Linux 5.5 or later
+        #[structopt(long = "experimental-unotify")]
+        experimental_unotify: bool,
+
+        /// What to do when tracees set up an io_uring: 'deny' makes io_uring_setup() fail
+        /// with ENOSYS, so they fall back to plain syscalls. 'record' records the rings;
+        /// it needs Linux 6.1 or later and only supports reads, writes and a few other
+        /// operations. Defaults to 'deny'
+        #[structopt(long = "io-uring", parse(try_from_str = parse_io_uring_policy))]
+        io_uring: Option<IoUringPolicy>,
+
+        /// Write the trace to <output-trace-dir>, which must not exist yet, instead of a new
+        /// directory in the trace save directory. The latest-trace link is left alone, so
+        /// recordings with distinct output directories can run in parallel
+        #[structopt(short = "o", long = "output-trace-dir", parse(from_os_str))]
+        output_trace_dir: Option<PathBuf>,
+
+        /// Make clock_gettime(), gettimeofday() and time() return a virtual clock that
+        /// advances with each trace event instead of the real time, so repeated recordings
+        /// of the same deterministic program see the same times
+        #[structopt(long = "virtual-clock")]
+        virtual_clock: bool,
+
+        /// Answer getrandom() and reads of /dev/urandom and /dev/random from a generator
+        /// seeded with <random-seed> instead of the kernel. Recording again with the same
+        /// seed gives the program the same random bytes
+        #[structopt(long = "random-seed")]
+        random_seed: Option<u64>,
+
+        /// Copy files tracees open or map on FUSE filesystems into the trace, because the
+        /// filesystem may be gone or serve something else at replay. Without this rd only
+        /// warns about them
+        #[structopt(long = "copy-fuse-files")]
+        copy_fuse_files: bool,
+
+        /// Record everything read from files whose paths match <capture-reads>, without
+        /// syscall buffering, so nothing depends on the files staying the same. `*` and `?`
+        /// don't match `/`, `**` does. May be given more than once
+        #[structopt(long = "capture-reads", parse(from_os_str), number_of_values = 1)]
+        capture_reads: Vec<OsString>,
+
+        /// The program to record followed by its arguments
+        #[structopt(
+            parse(from_os_str),
+            required_unless = "attach",
+            conflicts_with = "attach"
+        )]
+        exe_args: Vec<OsString>,
+    },
+
     /// Replay a previously recorded trace.
     #[structopt(name = "replay")]
     Replay {
@@ -290,6 +467,22 @@ pub enum RdSubCommand {
         #[structopt(long = "stats", parse(try_from_str = parse_stats))]
         stats: Option<u32>,
 
+        /// Show a progress bar with an estimate of the remaining replay time. This is the
+        /// default with -a when stderr is a terminal
+        #[structopt(long = "progress")]
+        progress: bool,
+
+        /// Don't show a progress bar
+        #[structopt(long = "quiet", conflicts_with = "progress")]
+        quiet: bool,
+
+        /// What the debugger can still see of a thread after its exit: 'none', 'registers',
+        /// or 'memory' for registers plus the memory of a process's last thread. Append
+        /// ':<n>' to keep at most <n> threads, oldest forgotten first. Defaults to
+        /// 'registers:64'
+        #[structopt(long = "dead-tasks", parse(try_from_str = parse_dead_tasks))]
+        dead_tasks: Option<DeadTaskPolicy>,
+
         /// Which directory is the trace data in? If omitted the latest trace dir is used
         trace_dir: Option<PathBuf>,
         // @TODO There are extra debugger options also passed after a `--`
@@ -333,6 +526,13 @@ pub enum RdSubCommand {
         #[structopt(long = "singlestep", parse(try_from_str = crate::commands::rerun_command::parse_regs))]
         singlestep_regs: Option<TraceFields>,
 
+        /// Output the registers in <event-regs> (same syntax as for --singlestep) once for
+        /// every event in the tracing range, right after the event completes. Unlike
+        /// --singlestep this doesn't singlestep the tracee, so it is much faster. Comparing
+        /// the output for two traces shows the first event at which they diverge
+        #[structopt(long = "event-regs", parse(try_from_str = crate::commands::rerun_command::parse_regs))]
+        event_regs: Option<TraceFields>,
+
         /// Which directory is the trace data in? If omitted the latest trace dir is used
         trace_dir: Option<PathBuf>,
     },
@@ -344,9 +544,181 @@ pub enum RdSubCommand {
         trace_dir: Option<PathBuf>,
     },
 
+    /// Look for known sources of nondeterminism that rd doesn't capture (unpatched vdso
+    /// time functions, files shared with processes outside the recording, direct TSC
+    /// reads) and list them, most likely culprit first. Useful when a replay diverges.
+    #[structopt(name = "doctor")]
+    Doctor {
+        /// Which directory is the trace data in? If omitted the latest trace dir is used
+        trace_dir: Option<PathBuf>,
+    },
+
+    /// Check that a trace is consistent: every clone has a matching task creation, mapped
+    /// files and saved mapping contents are where replay will look for them, syscalls
+    /// don't use fds the trace shows being closed, and tick counts never go down. Run it
+    /// before a long replay of a trace you don't trust.
+    #[structopt(name = "lint")]
+    Lint {
+        /// Which directory is the trace data in? If omitted the latest trace dir is used
+        trace_dir: Option<PathBuf>,
+    },
+
+    /// Count the events and syscalls in a trace, and how many syscalls took the slow
+    /// path through rd rather than being handled by the syscall buffer.
+    #[structopt(name = "stats")]
+    Stats {
+        /// Also list each syscall with how often it took each path, most slow-path
+        /// calls first: the syscalls worth adding to the syscall buffer next
+        #[structopt(long)]
+        syscalls: bool,
+
+        /// Which directory is the trace data in? If omitted the latest trace dir is used
+        trace_dir: Option<PathBuf>,
+    },
+
     /// Dump information on the processes encountered during recording.
     #[structopt(name = "ps")]
     Ps {
+        /// Also list every exec of each process, with its image and command line
+        #[structopt(short = "f", long)]
+        full: bool,
+
+        /// Which directory is the trace data in? If omitted the latest trace dir is used
+        trace_dir: Option<PathBuf>,
+    },
+
+    /// Find every event in the trace that opens, execs, maps or reads back a path,
+    /// or operates on a file descriptor.
+    #[structopt(name = "grep")]
+    Grep {
+        /// Glob pattern that paths must match, e.g. `/etc/*`. `*` and `?` don't match `/`,
+        /// `**` does
+        #[structopt(long, parse(from_os_str), required_unless = "fd")]
+        path: Option<OsString>,
+
+        /// Report syscalls that open, or take as their first argument, this fd
+        #[structopt(long)]
+        fd: Option<i32>,
+
+        /// Only report events for the specified tid
+        #[structopt(short = "t", long = "tid")]
+        only_tid: Option<libc::pid_t>,
+
+        /// Which directory is the trace data in? If omitted the latest trace dir is used
+        trace_dir: Option<PathBuf>,
+    },
+
+    /// Browse the events of a trace interactively. Shows decoded event details and
+    /// register changes, and can start a replay debugging session at any event.
+    #[structopt(name = "tui")]
+    Tui {
+        /// Which directory is the trace data in? If omitted the latest trace dir is used
+        trace_dir: Option<PathBuf>,
+    },
+
+    /// Copy all files the trace maps (executables, libraries etc.) into the trace
+    /// directory, so the trace can be moved to another machine and replayed there.
+    #[structopt(name = "pack")]
+    Pack {
+        /// Which directory is the trace data in? If omitted the latest trace dir is used
+        trace_dir: Option<PathBuf>,
+    },
+
+    /// Replay to the start of an event and print the memory mappings of a task: the
+    /// recorded file names, protection, device and inode of each, and whether replay got
+    /// the contents from the trace, a file, or zeroes.
+    #[structopt(name = "maps")]
+    Maps {
+        /// The event to stop at; mappings are shown as they are just before it's replayed
+        #[structopt(short = "e", long, parse(try_from_str = parse_goto_event))]
+        event: FrameTime,
+
+        /// The (recorded) tid of the task whose address space to show. By default the task
+        /// that the event belongs to
+        #[structopt(short = "t", long)]
+        tid: Option<pid_t>,
+
+        /// Print JSON instead of a /proc/<pid>/maps-like table
+        #[structopt(long)]
+        json: bool,
+
+        /// Which directory is the trace data in? If omitted the latest trace dir is used
+        trace_dir: Option<PathBuf>,
+    },
+
+    /// Replay to the start of an event and print every live task: its tid, recorded tid,
+    /// thread group, name and ip, whether it's the task the event belongs to, blocked in a
+    /// syscall or runnable, the last syscall it made and the signals pending for it.
+    #[structopt(name = "tasks")]
+    Tasks {
+        /// The event to stop at; tasks are shown as they are just before it's replayed
+        #[structopt(short = "e", long, parse(try_from_str = parse_goto_event))]
+        event: FrameTime,
+
+        /// Which directory is the trace data in? If omitted the latest trace dir is used
+        trace_dir: Option<PathBuf>,
+    },
+
+    /// Replay to the start of an event and print the stack of a task, with the nearest
+    /// symbol for each frame and functions inlined there if the files have DWARF debug info.
+    #[structopt(name = "backtrace")]
+    Backtrace {
+        /// The event to stop at; the stack is shown as it is just before it's replayed
+        #[structopt(short = "e", long, parse(try_from_str = parse_goto_event))]
+        event: FrameTime,
+
+        /// The (recorded) tid of the task whose stack to show. By default the task that
+        /// the event belongs to
+        #[structopt(short = "t", long)]
+        tid: Option<pid_t>,
+
+        /// Which directory is the trace data in? If omitted the latest trace dir is used
+        trace_dir: Option<PathBuf>,
+    },
+
+    /// Probe this machine for what rd needs to record (perf counters, the
+    /// perf_event_paranoid and ptrace_scope sysctls, CPUID faulting, seccomp, the
+    /// kernel version) and say how to fix whatever is missing.
+    #[structopt(name = "check")]
+    Check,
+
+    /// Find the event that last wrote a memory address, as far as the trace knows.
+    /// Only writes rd records data for (syscall outparams, signal frames etc.) are found.
+    #[structopt(name = "which-wrote")]
+    WhichWrote {
+        /// Address to look up, in hex (with or without a leading `0x`)
+        #[structopt(parse(try_from_str = parse_addr))]
+        addr: usize,
+
+        /// Only consider writes strictly before this event. By default, the whole trace
+        /// is considered
+        #[structopt(short = "b", long, parse(try_from_str = parse_goto_event))]
+        before: Option<FrameTime>,
+
+        /// Interpret the address in the address space of this tid (at the time given by
+        /// --before). By default writes to all address spaces are considered
+        #[structopt(short = "t", long = "tid")]
+        only_tid: Option<libc::pid_t>,
+
+        /// List every recorded write to the address instead of just the last one
+        #[structopt(short = "a", long)]
+        all: bool,
+
+        /// Also print the bytes each write stored, in hex
+        #[structopt(short = "d", long)]
+        data: bool,
+
+        /// Instead of searching the trace, replay from this event until the address is
+        /// next written to. This also finds stores by the tracee's own code. The address
+        /// is watched in the address space of the task the event belongs to
+        #[structopt(
+            short = "n",
+            long = "next-after",
+            parse(try_from_str = parse_goto_event),
+            conflicts_with_all = &["before", "only-tid", "all", "data"]
+        )]
+        next_after: Option<FrameTime>,
+
         /// Which directory is the trace data in? If omitted the latest trace dir is used
         trace_dir: Option<PathBuf>,
     },
@@ -362,6 +734,18 @@ fn parse_range(range_or_single: &str) -> Result<(FrameTime, Option<FrameTime>),
     Ok((low, high))
 }
 
+fn parse_num_cores(maybe_num_cores: &str) -> Result<u32, Box<dyn Error>> {
+    let num_cores = maybe_num_cores.trim().parse::<u32>()?;
+    if num_cores == 0 {
+        Err(Box::new(clap::Error::with_description(
+            "number of cores cannot be 0",
+            clap::ErrorKind::InvalidValue,
+        )))
+    } else {
+        Ok(num_cores)
+    }
+}
+
 fn parse_pid(maybe_pid: &str) -> Result<pid_t, Box<dyn Error>> {
     let pid = maybe_pid.trim().parse::<pid_t>()?;
     if pid < 1 {
@@ -386,6 +770,90 @@ fn parse_stats(maybe_stats: &str) -> Result<u32, Box<dyn Error>> {
     }
 }
 
+fn parse_ticks(maybe_ticks: &str) -> Result<Ticks, Box<dyn Error>> {
+    let ticks = maybe_ticks.trim().parse::<Ticks>()?;
+    if ticks == 0 || ticks > MAX_TICKS_REQUEST {
+        Err(Box::new(clap::Error::with_description(
+            &format!(
+                "Please provide a number between 1 and {}",
+                MAX_TICKS_REQUEST
+            ),
+            clap::ErrorKind::InvalidValue,
+        )))
+    } else {
+        Ok(ticks)
+    }
+}
+
+fn parse_skid_size(maybe_skid_size: &str) -> Result<Ticks, Box<dyn Error>> {
+    let skid_size = maybe_skid_size.trim().parse::<Ticks>()?;
+    // Replay programs interrupts `skid_size` short of targets up to
+    // MAX_TICKS_REQUEST away, which must leave a positive request.
+    if skid_size == 0 || skid_size > MAX_TICKS_REQUEST / 2 {
+        Err(Box::new(clap::Error::with_description(
+            &format!(
+                "Please provide a number between 1 and {}",
+                MAX_TICKS_REQUEST / 2
+            ),
+            clap::ErrorKind::InvalidValue,
+        )))
+    } else {
+        Ok(skid_size)
+    }
+}
+
+fn parse_ticks_semantics(semantics: &str) -> Result<TicksSemantics, Box<dyn Error>> {
+    match semantics {
+        "rcb" => Ok(TicksSemantics::TicksRetiredConditionalBranches),
+        "taken-branches" => Ok(TicksSemantics::TicksTakenBranches),
+        _ => Err(Box::new(clap::Error::with_description(
+            "Please provide one of 'rcb' or 'taken-branches'",
+            clap::ErrorKind::InvalidValue,
+        ))),
+    }
+}
+
+fn parse_io_uring_policy(policy: &str) -> Result<IoUringPolicy, Box<dyn Error>> {
+    match policy {
+        "deny" => Ok(IoUringPolicy::Deny),
+        "record" => Ok(IoUringPolicy::Record),
+        _ => Err(Box::new(clap::Error::with_description(
+            "Please provide one of 'deny' or 'record'",
+            clap::ErrorKind::InvalidValue,
+        ))),
+    }
+}
+
+fn parse_dead_tasks(policy: &str) -> Result<DeadTaskPolicy, Box<dyn Error>> {
+    match parse_dead_task_policy(policy) {
+        Some(policy) => Ok(policy),
+        None => Err(Box::new(clap::Error::with_description(
+            "Please provide one of 'none', 'registers' or 'memory', optionally followed by ':<n>'",
+            clap::ErrorKind::InvalidValue,
+        ))),
+    }
+}
+
+fn parse_ticks_range(range: &str) -> Result<(Ticks, Ticks), Box<dyn Error>> {
+    let args: Vec<&str> = range.splitn(2, '-').collect();
+    if args.len() != 2 {
+        return Err(Box::new(clap::Error::with_description(
+            "Please provide a range of the form <low>-<high>",
+            clap::ErrorKind::InvalidValue,
+        )));
+    }
+    let low = args[0].trim().parse::<Ticks>()?;
+    let high = parse_ticks(args[1])?;
+    if low >= high {
+        Err(Box::new(clap::Error::with_description(
+            "<low> must be less than <high>",
+            clap::ErrorKind::InvalidValue,
+        )))
+    } else {
+        Ok((low, high))
+    }
+}
+
 fn parse_goto_event(maybe_goto_event: &str) -> Result<FrameTime, Box<dyn Error>> {
     let goto_event = maybe_goto_event.trim().parse::<FrameTime>()?;
     if goto_event == 0 {
@@ -398,6 +866,16 @@ fn parse_goto_event(maybe_goto_event: &str) -> Result<FrameTime, Box<dyn Error>>
     }
 }
 
+fn parse_addr(maybe_addr: &str) -> Result<usize, ParseIntError> {
+    let maybe_addr = maybe_addr.trim();
+    let hex = if maybe_addr.starts_with("0x") || maybe_addr.starts_with("0X") {
+        &maybe_addr[2..]
+    } else {
+        maybe_addr
+    };
+    usize::from_str_radix(hex, 16)
+}
+
 #[derive(Clone, Debug)]
 pub enum PidOrCommand {
     Pid(pid_t),
@@ -0,0 +1,180 @@
+use crate::{
+    commands::{
+        rd_options::{RdOptions, RdSubCommand},
+        RdCommand,
+    },
+    fuse_files::FuseFilePolicy,
+    io_uring::IoUringPolicy,
+    perf_counters::TicksSemantics,
+    session::record_session::{RecordResult, RecordSession},
+    syscall_interception::UnotifyInterception,
+    ticks::Ticks,
+    util::BindCPU,
+    virtual_clock::VirtualClock,
+};
+use libc::pid_t;
+use std::{env, ffi::OsString, fs, io, path::PathBuf, process};
+
+pub struct RecordCommand {
+    chaos: bool,
+    chaos_seed: Option<u64>,
+    num_cpu_ticks: Option<Ticks>,
+    chaos_ticks: Option<(Ticks, Ticks)>,
+    round_robin: bool,
+    bind_to_cpu: Option<u32>,
+    cpu_unbound: bool,
+    chaos_migrate: bool,
+    num_cores: Option<u32>,
+    ticks_semantics: Option<TicksSemantics>,
+    attach: Option<pid_t>,
+    io_uring: Option<IoUringPolicy>,
+    experimental_unotify: bool,
+    virtual_clock: bool,
+    random_seed: Option<u64>,
+    output_trace_dir: Option<PathBuf>,
+    copy_fuse_files: bool,
+    capture_reads: Vec<OsString>,
+    exe_args: Vec<OsString>,
+}
+
+impl RecordCommand {
+    pub fn new(options: &RdOptions) -> RecordCommand {
+        match options.cmd.clone() {
+            RdSubCommand::Record {
+                chaos,
+                chaos_seed,
+                num_cpu_ticks,
+                chaos_ticks,
+                round_robin,
+                bind_to_cpu,
+                cpu_unbound,
+                chaos_migrate,
+                num_cores,
+                ticks_semantics,
+                attach,
+                io_uring,
+                experimental_unotify,
+                virtual_clock,
+                random_seed,
+                output_trace_dir,
+                copy_fuse_files,
+                capture_reads,
+                exe_args,
+                ..
+            } => RecordCommand {
+                chaos,
+                chaos_seed,
+                num_cpu_ticks,
+                chaos_ticks,
+                round_robin,
+                bind_to_cpu,
+                cpu_unbound,
+                chaos_migrate,
+                num_cores,
+                ticks_semantics,
+                attach,
+                io_uring,
+                experimental_unotify,
+                virtual_clock,
+                random_seed,
+                output_trace_dir,
+                copy_fuse_files,
+                capture_reads,
+                exe_args,
+            },
+            _ => panic!("Unexpected RdSubCommand variant. Not a `Record` variant!"),
+        }
+    }
+}
+
+impl RdCommand for RecordCommand {
+    fn run(&mut self) -> io::Result<()> {
+        RecordSession::check_host_for_recording();
+
+        let output_trace_dir = self.output_trace_dir.as_ref().map(|dir| dir.as_os_str());
+        // Tasks migrated at random mustn't be bound to a CPU.
+        let bind_cpu = match self.bind_to_cpu {
+            Some(cpu) => BindCPU::BindToCPU(cpu),
+            None if self.cpu_unbound || self.chaos_migrate => BindCPU::UnboundCPU,
+            None => BindCPU::RandomCPU,
+        };
+        // With -p the trace is named after the program we attach to.
+        let exe_args = match self.attach {
+            Some(pid) => match fs::read_link(format!("/proc/{}/exe", pid)) {
+                Ok(exe) => vec![exe.into_os_string()],
+                Err(e) => {
+                    clean_fatal!("Can't attach to process {}: {}", pid, e);
+                }
+            },
+            None => self.exe_args.clone(),
+        };
+        let mut session = RecordSession::new(&exe_args, output_trace_dir, bind_cpu);
+        if let Some(ticks_semantics) = self.ticks_semantics {
+            session.set_ticks_semantics(ticks_semantics);
+        }
+        session.set_enable_chaos(self.chaos || self.chaos_seed.is_some(), self.chaos_seed);
+        if let Some(max_ticks) = self.num_cpu_ticks {
+            session.set_max_ticks(max_ticks);
+        }
+        if let Some((low, high)) = self.chaos_ticks {
+            session.set_chaos_ticks_range(low, high);
+        }
+        if self.chaos_migrate {
+            session.set_chaos_cpu_migration(true);
+        }
+        if let Some(num_cores) = self.num_cores {
+            session.scheduler_mut().set_pretend_num_cores(num_cores);
+        }
+        if self.round_robin {
+            let quantum = session.scheduler().max_ticks();
+            session.set_round_robin_quantum(Some(quantum));
+        }
+        if let Some(policy) = self.io_uring {
+            session.set_io_uring_policy(policy);
+        }
+        if self.experimental_unotify {
+            session.set_interception(Box::new(UnotifyInterception::default()));
+        }
+        if self.virtual_clock {
+            session.set_virtual_clock(Some(VirtualClock::default()));
+        }
+        session.set_random_seed(self.random_seed);
+        if self.copy_fuse_files {
+            session
+                .trace_writer_mut()
+                .set_fuse_file_policy(FuseFilePolicy::Copy);
+        }
+        if !self.capture_reads.is_empty() {
+            session.set_capture_reads(self.capture_reads.clone());
+        }
+        let session = match self.attach {
+            Some(pid) => session.attach(pid),
+            None => session.start(&self.exe_args, &environment()),
+        };
+        let exit_status = loop {
+            match session.as_record().unwrap().record_step() {
+                RecordResult::StepContinue => (),
+                RecordResult::StepExited(exit_status) => break exit_status,
+            }
+        };
+        session.as_record().unwrap().terminate_recording();
+
+        // Exit the way the initial tracee did.
+        match exit_status.fatal_sig() {
+            Some(sig) => process::exit(128 + sig),
+            None => process::exit(exit_status.exit_code().unwrap_or(0) as i32),
+        }
+    }
+}
+
+/// Our environment, as "NAME=value" strings for the initial tracee.
+fn environment() -> Vec<OsString> {
+    env::vars_os()
+        .map(|(name, value)| {
+            let mut var = name;
+            var.push("=");
+            var.push(value);
+            var
+        })
+        .collect()
+}
@@ -2,23 +2,34 @@ use super::rd_options::{PidOrCommand, RdOptions, RdSubCommand};
 use crate::{
     assert_prerequisites,
     bindings::kernel::{gettimeofday, timeval},
+    cancellation_token::CancellationToken,
     commands::RdCommand,
+    dead_tasks::DeadTaskPolicy,
     flags::Flags,
-    gdb_server::gdb_server,
+    gdb_server::gdb_server::{self, GdbServer},
     log::LogLevel::LogInfo,
+    replay_timeline::ReplayTimeline,
     session::{
         replay_session,
         session_inner::{session_inner::Statistics, RunCommand},
         SessionSharedPtr,
     },
-    trace::trace_frame::FrameTime,
-    util::running_under_rd,
+    trace::{trace_exec_history::ExecHistory, trace_frame::FrameTime, trace_reader::TraceReader},
+    util::{monotonic_now_sec, probably_not_interactive, running_under_rd},
 };
 use io::stderr;
 use libc::pid_t;
 use nix::unistd::{getpid, getppid};
 use replay_session::{ReplaySession, ReplayStatus};
-use std::{ffi::OsString, io, io::Write, path::PathBuf, ptr};
+use std::{
+    cmp::{max, min},
+    ffi::OsString,
+    io,
+    io::Write,
+    net::TcpListener,
+    path::PathBuf,
+    ptr,
+};
 
 #[derive(Copy, Clone, Eq, PartialEq)]
 enum CreatedHow {
@@ -74,9 +85,16 @@ pub struct ReplayCommand {
     /// to test the corresponding code.
     share_private_mappings: bool,
 
+    /// How much of exited tasks to keep for the debugger to look at.
+    dead_tasks: DeadTaskPolicy,
+
     /// When Some(_), display statistics every N steps.
     dump_interval: Option<u32>,
 
+    /// Whether to show a progress bar when replaying without a debugger.
+    /// None means show it if stderr is a terminal.
+    progress: Option<bool>,
+
     trace_dir: Option<PathBuf>,
 }
 
@@ -96,7 +114,9 @@ impl Default for ReplayCommand {
             redirect: true,
             cpu_unbound: false,
             share_private_mappings: false,
+            dead_tasks: DeadTaskPolicy::default(),
             dump_interval: None,
+            progress: None,
             gdb_options: vec![],
             trace_dir: None,
         }
@@ -123,8 +143,11 @@ impl ReplayCommand {
                 cpu_unbound,
                 gdb_x_file,
                 stats,
+                progress,
+                quiet,
                 trace_dir,
                 share_private_mappings,
+                dead_tasks,
             } => {
                 let mut flags = ReplayCommand::default();
 
@@ -197,8 +220,18 @@ impl ReplayCommand {
                     flags.dump_interval = stats;
                 }
 
+                if progress {
+                    flags.progress = Some(true);
+                } else if quiet {
+                    flags.progress = Some(false);
+                }
+
                 flags.cpu_unbound = cpu_unbound;
 
+                if let Some(policy) = dead_tasks {
+                    flags.dead_tasks = policy;
+                }
+
                 if interpreter.is_some() {
                     flags.gdb_options.push("-i".into());
                     flags.gdb_options.push(OsString::from(interpreter.unwrap()));
@@ -217,6 +250,7 @@ impl ReplayCommand {
             redirect_stdio: self.redirect,
             share_private_mappings: self.share_private_mappings,
             cpu_unbound: self.cpu_unbound,
+            dead_tasks: self.dead_tasks,
         }
     }
 
@@ -229,6 +263,15 @@ impl ReplayCommand {
         let mut last_dump_rectime: f64 = 0.0;
         let mut last_stats = Statistics::default();
         unsafe { gettimeofday(&raw mut last_dump_time, ptr::null_mut()) };
+        let mut progress = if self
+            .progress
+            .unwrap_or_else(|| !probably_not_interactive(None))
+        {
+            let trace = replay_session.trace_reader();
+            Some(ReplayProgress::new(trace.time(), trace.event_count()))
+        } else {
+            None
+        };
 
         loop {
             let mut cmd = RunCommand::RunContinue;
@@ -272,7 +315,16 @@ impl ReplayCommand {
                 last_stats = stats;
                 last_dump_rectime = rectime;
             }
+            if let Some(p) = progress.as_mut() {
+                p.update(out, after_time)?;
+            }
 
+            if result.status == ReplayStatus::ReplayDiverged {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    result.divergence.unwrap().to_string(),
+                ));
+            }
             if result.status == ReplayStatus::ReplayExited {
                 break;
             }
@@ -284,10 +336,54 @@ impl ReplayCommand {
             );
         }
 
+        if let Some(p) = progress.as_mut() {
+            p.finish(out, replay_session.trace_reader().time())?;
+        }
+
         log!(LogInfo, "Replayer successfully finished");
         Ok(())
     }
 
+    /// Replay to `target.event` and serve the replay to gdb clients on
+    /// `dbg_host`:`dbg_port`, see `GdbServer`.
+    fn serve_replay_with_debugger(&self, target: &gdb_server::Target) -> io::Result<()> {
+        let port = match self.dbg_port {
+            Some(port) => port,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Launching a debugger isn't supported yet. Serve the replay with \
+                        --dbgport (-s) and connect to it with gdb's `target remote`.",
+                ))
+            }
+        };
+        let session = ReplaySession::create(self.trace_dir.as_ref(), self.session_flags());
+        let mut timeline = ReplayTimeline::new(session);
+        if !timeline.seek_to_event(target.event, &CancellationToken::new()) {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("Replay ended before event {}", target.event),
+            ));
+        }
+        let exec_history = ExecHistory::from_trace(&mut TraceReader::new(self.trace_dir.as_ref()));
+        let mut server = GdbServer::new(timeline, exec_history);
+        let listener = TcpListener::bind((self.dbg_host.as_str(), port))?;
+        loop {
+            write!(
+                stderr(),
+                "Waiting for gdb: target remote {}:{}\n",
+                self.dbg_host,
+                port
+            )?;
+            let (stream, _) = listener.accept()?;
+            gdb_server::serve_connection(&mut server, stream)?;
+            if !self.keep_listening || server.is_killed() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
     // DIFF NOTE: In rr a result code e.g. 0 is return. We simply return Ok(()) if there is no error.
     fn replay(&self) -> io::Result<()> {
         let mut target = gdb_server::Target::default();
@@ -304,20 +400,11 @@ impl ReplayCommand {
         }
         target.event = self.goto_event;
 
-        // If we're not going to autolaunch the debugger, don't go
-        // through the rigamarole to set that up.  All it does is
-        // complicate the process tree and confuse users.
-        if self.dont_launch_debugger {
-            if target.event == FrameTime::MAX {
-                self.serve_replay_no_debugger(&mut stderr())?;
-            } else {
-                unimplemented!();
-            }
-
-            // @TODO
+        if target.event == FrameTime::MAX {
+            self.serve_replay_no_debugger(&mut stderr())
+        } else {
+            self.serve_replay_with_debugger(&target)
         }
-
-        Ok(())
     }
 }
 
@@ -376,3 +463,101 @@ impl RdCommand for ReplayCommand {
 fn to_microseconds(tv: &timeval) -> u64 {
     (tv.tv_sec as u64) * 1000000 + (tv.tv_usec as u64)
 }
+
+/// A one-line progress bar for `rd replay -a`, redrawn in place. The
+/// remaining time is estimated from the average replay speed so far. Traces
+/// without an event index don't say how many events they have, so for them
+/// only the events replayed so far and the speed are shown.
+struct ReplayProgress {
+    start_event: FrameTime,
+    total_events: Option<FrameTime>,
+    start_time: f64,
+    last_draw_time: f64,
+}
+
+impl ReplayProgress {
+    const REDRAW_INTERVAL_SEC: f64 = 0.25;
+    const BAR_WIDTH: u64 = 30;
+
+    fn new(start_event: FrameTime, total_events: Option<FrameTime>) -> ReplayProgress {
+        let now = monotonic_now_sec();
+        ReplayProgress {
+            start_event,
+            total_events: total_events.map(|total| max(total, 1)),
+            start_time: now,
+            last_draw_time: now,
+        }
+    }
+
+    fn update(&mut self, out: &mut dyn Write, event: FrameTime) -> io::Result<()> {
+        let now = monotonic_now_sec();
+        if now - self.last_draw_time < Self::REDRAW_INTERVAL_SEC {
+            return Ok(());
+        }
+        self.last_draw_time = now;
+        self.draw(out, event, now)
+    }
+
+    fn finish(&mut self, out: &mut dyn Write, event: FrameTime) -> io::Result<()> {
+        self.draw(out, event, monotonic_now_sec())?;
+        writeln!(out)
+    }
+
+    fn draw(&self, out: &mut dyn Write, event: FrameTime, now: f64) -> io::Result<()> {
+        let event = self.total_events.map_or(event, |total| min(event, total));
+        let elapsed = now - self.start_time;
+        let events_per_sec = if elapsed > 0.0 {
+            (event - min(event, self.start_event)) as f64 / elapsed
+        } else {
+            0.0
+        };
+        let total_events = match self.total_events {
+            Some(total) => total,
+            None => {
+                write!(out, "\revent {} ({:.0} events/s)  ", event, events_per_sec)?;
+                return out.flush();
+            }
+        };
+        let filled = event * Self::BAR_WIDTH / total_events;
+        let eta = if events_per_sec > 0.0 {
+            format_duration((total_events - event) as f64 / events_per_sec)
+        } else {
+            "?".into()
+        };
+        write!(
+            out,
+            "\r[{}{}] {:3}% event {}/{} ({:.0} events/s, ETA {})  ",
+            "#".repeat(filled as usize),
+            " ".repeat((Self::BAR_WIDTH - filled) as usize),
+            event * 100 / total_events,
+            event,
+            total_events,
+            events_per_sec,
+            eta
+        )?;
+        out.flush()
+    }
+}
+
+/// Format `secs` as `[h:]mm:ss`.
+fn format_duration(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{:02}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn format_duration_test() {
+        assert_eq!(format_duration(0.0), "00:00");
+        assert_eq!(format_duration(59.6), "01:00");
+        assert_eq!(format_duration(3599.0), "59:59");
+        assert_eq!(format_duration(3723.0), "1:02:03");
+    }
+}
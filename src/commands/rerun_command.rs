@@ -183,7 +183,10 @@ fn find_seg_reg(reg: &str) -> Option<u8> {
 
 fn treat_event_completion_as_singlestep_complete(ev: &Event) -> bool {
     match ev.event_type() {
-        EventType::EvPatchSyscall | EventType::EvInstructionTrap | EventType::EvSyscall => true,
+        EventType::EvPatchSyscall
+        | EventType::EvInstructionTrap
+        | EventType::EvSyscall
+        | EventType::EvInterceptedSyscall => true,
         _ => false,
     }
 }
@@ -241,6 +244,7 @@ pub struct ReRunCommand {
     trace_end: FrameTime,
     function: Option<RemoteCodePtr>,
     singlestep_trace: Vec<TraceField>,
+    event_trace: Vec<TraceField>,
     raw_dump: bool,
     cpu_unbound: bool,
     trace_dir: Option<PathBuf>,
@@ -342,12 +346,14 @@ impl ReRunCommand {
                 cpu_unbound,
                 function_addr,
                 singlestep_regs,
+                event_regs,
                 trace_dir,
             } => ReRunCommand {
                 trace_start: trace_start.unwrap_or(FrameTime::MIN),
                 trace_end: trace_end.unwrap_or(FrameTime::MAX),
                 function: function_addr.map(|a| a.into()),
                 singlestep_trace: singlestep_regs.map_or(Vec::new(), |r| r.0),
+                event_trace: event_regs.map_or(Vec::new(), |r| r.0),
                 raw_dump: raw,
                 cpu_unbound,
                 trace_dir,
@@ -361,6 +367,7 @@ impl ReRunCommand {
             redirect_stdio: false,
             share_private_mappings: false,
             cpu_unbound: self.cpu_unbound,
+            dead_tasks: Default::default(),
         }
     }
 
@@ -399,6 +406,7 @@ impl ReRunCommand {
                         if !self.singlestep_trace.is_empty() {
                             done_first_step = true;
                             self.write_regs(
+                                &self.singlestep_trace,
                                 old_task.unwrap().borrow_mut().as_mut(),
                                 before_time - 1,
                                 instruction_count_within_event,
@@ -407,13 +415,22 @@ impl ReRunCommand {
                         }
                     }
 
-                    cmd = RunCommand::RunSinglestepFastForward;
+                    // Per-event traces don't need us to stop at every instruction.
+                    if !self.singlestep_trace.is_empty() || self.event_trace.is_empty() {
+                        cmd = RunCommand::RunSinglestepFastForward;
+                    }
                 }
             }
 
             let replayed_event = replay_session.current_trace_frame().event().clone();
 
             let result = replay_session.replay_step(cmd);
+            if result.status == ReplayStatus::ReplayDiverged {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    result.divergence.unwrap().to_string(),
+                ));
+            }
             if result.status == ReplayStatus::ReplayExited {
                 break;
             }
@@ -452,6 +469,7 @@ impl ReRunCommand {
                                 && treat_event_completion_as_singlestep_complete(&replayed_event)))
                     {
                         self.write_regs(
+                            &self.singlestep_trace,
                             old_task.unwrap().borrow_mut().as_mut(),
                             before_time,
                             instruction_count_within_event,
@@ -464,6 +482,25 @@ impl ReRunCommand {
                     instruction_count_within_event += 1;
                 }
             }
+            if before_time < after_time
+                && done_initial_exec
+                && before_time >= self.trace_start
+                && !self.event_trace.is_empty()
+            {
+                // The task may have exited as part of the event, in which
+                // case there are no registers to show.
+                let old_task =
+                    old_task_tuid.and_then(|id| replay_session.find_task_from_task_uid(id));
+                if let Some(t) = old_task {
+                    self.write_regs(
+                        &self.event_trace,
+                        t.borrow_mut().as_mut(),
+                        before_time,
+                        instruction_count_within_event,
+                        &mut stdout(),
+                    )?;
+                }
+            }
             if before_time < after_time {
                 log!(
                     LogDebug,
@@ -508,7 +545,13 @@ impl ReRunCommand {
                 diversion_session
                     .borrow()
                     .diversion_step(t.borrow_mut().as_mut(), Some(cmd), None);
-            self.write_regs(t.borrow_mut().as_mut(), 0, 0, &mut stdout())?;
+            self.write_regs(
+                &self.singlestep_trace,
+                t.borrow_mut().as_mut(),
+                0,
+                0,
+                &mut stdout(),
+            )?;
             match result.break_status.signal {
                 Some(siginfo) => {
                     if siginfo.si_signo == libc::SIGSEGV
@@ -534,8 +577,10 @@ impl ReRunCommand {
         Ok(())
     }
 
-    pub fn write_regs(
+    /// Write the values of `fields` for `t` to `out` as one record.
+    fn write_regs(
         &self,
+        fields: &[TraceField],
         t: &mut dyn Task,
         event: FrameTime,
         instruction_count: u64,
@@ -545,7 +590,7 @@ impl ReRunCommand {
         let mut gp_regs: RegsData = unsafe { mem::zeroed() };
         let mut first = true;
 
-        for field in &self.singlestep_trace {
+        for field in fields {
             if first {
                 first = false;
             } else if !self.raw_dump {
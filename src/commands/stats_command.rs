@@ -0,0 +1,282 @@
+use crate::{
+    commands::{
+        rd_options::{RdOptions, RdSubCommand},
+        RdCommand,
+    },
+    event::{EventType, SyscallState},
+    kernel_abi::common::preload_interface::{
+        stored_record_size,
+        syscallbuf_hdr,
+        syscallbuf_record,
+    },
+    kernel_metadata::syscall_name,
+    remote_code_ptr::RemoteCodePtr,
+    session::address_space::{address_space::AddressSpace, Traced},
+    trace::{
+        trace_reader::{TraceReader, ValidateSourceFile},
+        trace_stream::MappedData,
+    },
+};
+use libc::pid_t;
+use std::{
+    cmp::min,
+    collections::{HashMap, HashSet},
+    io,
+    io::{stdout, Write},
+    mem::size_of,
+    path::PathBuf,
+    ptr,
+};
+
+pub struct StatsCommand {
+    syscalls: bool,
+    trace_dir: Option<PathBuf>,
+}
+
+impl StatsCommand {
+    pub fn new(options: &RdOptions) -> StatsCommand {
+        match options.cmd.clone() {
+            RdSubCommand::Stats {
+                syscalls,
+                trace_dir,
+            } => StatsCommand {
+                syscalls,
+                trace_dir,
+            },
+            _ => panic!("Unexpected RdSubCommand variant. Not a `Stats` variant!"),
+        }
+    }
+}
+
+impl RdCommand for StatsCommand {
+    fn run(&mut self) -> io::Result<()> {
+        let trace = TraceReader::new(self.trace_dir.as_ref());
+        let (events, counts) = count_syscalls(trace);
+        let mut out = stdout();
+        let slow: u64 = counts.values().map(|c| c.slow).sum();
+        let buffered: u64 = counts.values().map(|c| c.buffered).sum();
+        write!(out, "{} events\n", events)?;
+        write!(
+            out,
+            "{} syscalls: {} slow path, {} buffered ({})\n",
+            slow + buffered,
+            slow,
+            buffered,
+            buffered_percent(slow, buffered)
+        )?;
+        if self.syscalls {
+            write!(
+                out,
+                "\n{:<24} {:>10} {:>10} {:>9}\n",
+                "SYSCALL", "SLOW", "BUFFERED", "BUFFERED%"
+            )?;
+            for (name, c) in sorted_by_slow_path(counts) {
+                write!(
+                    out,
+                    "{:<24} {:>10} {:>10} {:>9}\n",
+                    name,
+                    c.slow,
+                    c.buffered,
+                    buffered_percent(c.slow, c.buffered)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+struct SyscallCounts {
+    /// Syscalls recorded with ptrace stops, as events of their own.
+    slow: u64,
+    /// Syscalls the syscallbuf handled, found in flush events.
+    buffered: u64,
+}
+
+/// Count the events of `trace`, and how each syscall made during the
+/// recording was recorded, by name.
+fn count_syscalls(mut trace: TraceReader) -> (u64, HashMap<String, SyscallCounts>) {
+    let mut counts: HashMap<String, SyscallCounts> = HashMap::new();
+    let mut events = 0;
+    // Tasks whose last syscall event was a syscall entry. Most slow-path
+    // syscalls have an entry and an exit event, but some never exit and some
+    // that rd refused are only recorded exiting.
+    let mut entered: HashSet<pid_t> = HashSet::new();
+    while !trace.at_end() {
+        let frame = trace.read_frame();
+        events += 1;
+        let ev = frame.event();
+        if ev.event_type() == EventType::EvSyscall {
+            let syscall = ev.syscall();
+            let counted = match syscall.state {
+                SyscallState::EnteringSyscall => {
+                    entered.insert(frame.tid());
+                    true
+                }
+                SyscallState::ExitingSyscall => !entered.remove(&frame.tid()),
+                _ => false,
+            };
+            if counted && !is_descheduled_buffered_syscall(frame.regs_ref().ip()) {
+                counts.entry(syscall.syscall_name()).or_default().slow += 1;
+            }
+        }
+
+        loop {
+            let mut data = MappedData::default();
+            let maybe_km = trace.read_mapped_region(
+                Some(&mut data),
+                Some(ValidateSourceFile::DontValidate),
+                None,
+                None,
+                None,
+            );
+            if maybe_km.is_none() {
+                break;
+            }
+        }
+        if ev.event_type() == EventType::EvSyscallbufFlush {
+            let buf = trace.read_raw_data();
+            // Buffered syscalls always use the task arch.
+            let arch = frame.regs_ref().arch();
+            for syscallno in buffered_syscalls(&buf.data) {
+                let name = syscall_name(syscallno, arch);
+                counts.entry(name).or_default().buffered += 1;
+            }
+        }
+        while trace.read_raw_data_metadata_for_frame().is_some() {}
+    }
+    (events, counts)
+}
+
+/// The syscall numbers of the records in `data`, the syscallbuf contents
+/// recorded with a flush event.
+fn buffered_syscalls(data: &[u8]) -> Vec<i32> {
+    let mut syscalls = Vec::new();
+    let header_size = size_of::<syscallbuf_hdr>();
+    if data.len() < header_size {
+        return syscalls;
+    }
+    let hdr = unsafe { ptr::read_unaligned(data.as_ptr() as *const syscallbuf_hdr) };
+    let end = min(data.len(), header_size + hdr.num_rec_bytes as usize);
+    let mut offset = header_size;
+    while offset + size_of::<syscallbuf_record>() <= end {
+        let record =
+            unsafe { ptr::read_unaligned(data[offset..].as_ptr() as *const syscallbuf_record) };
+        if (record.size as usize) < size_of::<syscallbuf_record>() {
+            break;
+        }
+        syscalls.push(record.syscallno as i32);
+        offset += stored_record_size(record.size) as usize;
+    }
+    syscalls
+}
+
+/// Whether a syscall event at `ip` is of a buffered syscall that blocked
+/// and was descheduled. Those stop at the syscallbuf's untraced syscall
+/// instruction and are recorded as events, but their records stay in the
+/// buffer and are counted from the flush.
+fn is_descheduled_buffered_syscall(ip: RemoteCodePtr) -> bool {
+    match AddressSpace::rd_page_syscall_from_exit_point(ip) {
+        Some(syscall_type) => syscall_type.traced == Traced::Untraced,
+        None => false,
+    }
+}
+
+/// Most slow-path syscalls first: the ones buffering would help most.
+fn sorted_by_slow_path(counts: HashMap<String, SyscallCounts>) -> Vec<(String, SyscallCounts)> {
+    let mut rows: Vec<_> = counts.into_iter().collect();
+    rows.sort_by(|(a_name, a), (b_name, b)| {
+        b.slow
+            .cmp(&a.slow)
+            .then(b.buffered.cmp(&a.buffered))
+            .then(a_name.cmp(b_name))
+    });
+    rows
+}
+
+fn buffered_percent(slow: u64, buffered: u64) -> String {
+    if slow + buffered == 0 {
+        return "-".into();
+    }
+    format!("{:.1}%", 100.0 * buffered as f64 / (slow + buffered) as f64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::session::address_space::{Enabled, Privileged};
+    use std::mem::zeroed;
+
+    /// A flush's syscallbuf contents: a header, then records of the syscalls
+    /// `records` with that many bytes of data each.
+    fn syscallbuf(records: &[(u16, usize)]) -> Vec<u8> {
+        let mut data = vec![0u8; size_of::<syscallbuf_hdr>()];
+        for &(syscallno, extra) in records {
+            let size = size_of::<syscallbuf_record>() + extra;
+            let mut record: syscallbuf_record = unsafe { zeroed() };
+            record.syscallno = syscallno;
+            record.size = size as u32;
+            let offset = data.len();
+            data.resize(offset + stored_record_size(size as u32) as usize, 0);
+            unsafe {
+                ptr::write_unaligned(
+                    data[offset..].as_mut_ptr() as *mut syscallbuf_record,
+                    record,
+                )
+            };
+        }
+        let mut hdr: syscallbuf_hdr = unsafe { zeroed() };
+        hdr.num_rec_bytes = (data.len() - size_of::<syscallbuf_hdr>()) as u32;
+        unsafe { ptr::write_unaligned(data.as_mut_ptr() as *mut syscallbuf_hdr, hdr) };
+        data
+    }
+
+    #[test]
+    fn buffered_syscalls_of_flush() {
+        let mut data = syscallbuf(&[(0, 5), (1, 0), (3, 16)]);
+        assert_eq!(buffered_syscalls(&data), vec![0, 1, 3]);
+        // Whatever follows the header's records isn't one of them.
+        data.extend_from_slice(&syscallbuf(&[(9, 0)])[size_of::<syscallbuf_hdr>()..]);
+        assert_eq!(buffered_syscalls(&data), vec![0, 1, 3]);
+        // A flush cut short keeps its whole records.
+        data.truncate(size_of::<syscallbuf_hdr>() + 40);
+        assert_eq!(buffered_syscalls(&data), vec![0, 1]);
+        assert!(buffered_syscalls(&data[..4]).is_empty());
+    }
+
+    #[test]
+    fn descheduled_buffered_syscalls() {
+        let untraced = AddressSpace::rd_page_syscall_exit_point(
+            Traced::Untraced,
+            Privileged::Unpriviledged,
+            Enabled::RecordingOnly,
+        );
+        let traced = AddressSpace::rd_page_syscall_exit_point(
+            Traced::Traced,
+            Privileged::Unpriviledged,
+            Enabled::RecordingAndReplay,
+        );
+        assert!(is_descheduled_buffered_syscall(untraced));
+        assert!(!is_descheduled_buffered_syscall(traced));
+        let program = RemoteCodePtr::from_val(0x401000);
+        assert!(!is_descheduled_buffered_syscall(program));
+    }
+
+    #[test]
+    fn sort_by_slow_path() {
+        let mut counts = HashMap::new();
+        let mut add = |name: &str, slow, buffered| {
+            counts.insert(name.to_owned(), SyscallCounts { slow, buffered });
+        };
+        add("read", 3, 90);
+        add("openat", 40, 0);
+        add("write", 3, 7);
+        let names: Vec<String> = sorted_by_slow_path(counts)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, vec!["openat", "read", "write"]);
+        assert_eq!(buffered_percent(1, 3), "75.0%");
+        assert_eq!(buffered_percent(0, 0), "-");
+    }
+}
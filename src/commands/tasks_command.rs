@@ -0,0 +1,167 @@
+use crate::{
+    cancellation_token::CancellationToken,
+    commands::{
+        rd_options::{RdOptions, RdSubCommand},
+        RdCommand,
+    },
+    event::{EventType, SyscallState},
+    kernel_metadata::{signal_name, syscall_name},
+    replay_timeline::ReplayTimeline,
+    session::replay_session::{Flags, ReplaySession},
+    trace::{trace_frame::FrameTime, trace_reader::TraceReader},
+};
+use libc::pid_t;
+use std::{
+    collections::HashMap,
+    io,
+    io::{stdout, Write},
+    path::PathBuf,
+};
+
+pub struct TasksCommand {
+    event: FrameTime,
+    trace_dir: Option<PathBuf>,
+}
+
+impl TasksCommand {
+    pub fn new(options: &RdOptions) -> TasksCommand {
+        match options.cmd.clone() {
+            RdSubCommand::Tasks { event, trace_dir } => TasksCommand { event, trace_dir },
+            _ => panic!("Unexpected RdSubCommand variant. Not a `Tasks` variant!"),
+        }
+    }
+
+    fn tasks(&self, out: &mut dyn Write) -> io::Result<()> {
+        let (histories, next_tid) = task_histories(self.trace_dir.as_ref(), self.event);
+
+        let flags = Flags {
+            redirect_stdio: false,
+            share_private_mappings: false,
+            cpu_unbound: true,
+            dead_tasks: Default::default(),
+        };
+        let mut timeline =
+            ReplayTimeline::new(ReplaySession::create(self.trace_dir.as_ref(), flags));
+        if !timeline.seek_to_event(self.event, &CancellationToken::new()) {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("Replay stopped before reaching event {}", self.event),
+            ));
+        }
+        let session = timeline.current_session();
+        let mut tasks: Vec<_> = session.tasks().values().cloned().collect();
+        tasks.sort_by_key(|t| t.borrow().rec_tid);
+
+        write!(out, "Tasks at event {}:\n", self.event)?;
+        write!(
+            out,
+            "TID\tREC_TID\tTGID\tNAME\tIP\tSCHED\tSYSCALL\tPENDING\n"
+        )?;
+        for t in tasks {
+            let t = t.borrow();
+            let history = histories.get(&t.rec_tid);
+            let sched = if next_tid == Some(t.rec_tid) {
+                "next"
+            } else if history.map_or(false, |h| h.in_syscall) {
+                "blocked"
+            } else {
+                "runnable"
+            };
+            let syscall = match history.and_then(|h| h.last_syscall.as_ref()) {
+                Some(name) => name.as_str(),
+                None => "--",
+            };
+            let pending = match history {
+                Some(h) if !h.pending_signals.is_empty() => h
+                    .pending_signals
+                    .iter()
+                    .map(|&sig| signal_name(sig))
+                    .collect::<Vec<_>>()
+                    .join(","),
+                _ => "--".into(),
+            };
+            write!(
+                out,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                t.tid,
+                t.rec_tid,
+                t.tgid(),
+                t.name().to_string_lossy(),
+                t.ip(),
+                sched,
+                syscall,
+                pending
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl RdCommand for TasksCommand {
+    fn run(&mut self) -> io::Result<()> {
+        self.tasks(&mut stdout())
+    }
+}
+
+/// What the trace says about a task up to some event. Replay doesn't
+/// keep track of any of this.
+#[derive(Default)]
+struct TaskHistory {
+    /// The name of the last syscall the task entered.
+    last_syscall: Option<String>,
+    /// Whether the task is still inside `last_syscall`.
+    in_syscall: bool,
+    /// Signals the task has stopped for but that haven't been delivered yet.
+    pending_signals: Vec<i32>,
+}
+
+/// Scan the events before `event` and return the history of every task
+/// that hasn't exited, and the tid of the task `event` belongs to.
+fn task_histories(
+    trace_dir: Option<&PathBuf>,
+    event: FrameTime,
+) -> (HashMap<pid_t, TaskHistory>, Option<pid_t>) {
+    let mut trace = TraceReader::new(trace_dir);
+    let mut histories: HashMap<pid_t, TaskHistory> = HashMap::new();
+    while !trace.at_end() {
+        let frame = trace.read_frame();
+        if frame.time() >= event {
+            return (histories, Some(frame.tid()));
+        }
+        let ev = frame.event();
+        match ev.event_type() {
+            EventType::EvSyscall => {
+                let syscall = ev.syscall_event();
+                let history = histories.entry(frame.tid()).or_default();
+                history.last_syscall = Some(syscall.syscall_name());
+                history.in_syscall = syscall.state != SyscallState::ExitingSyscall;
+            }
+            EventType::EvSyscallInterruption => {
+                histories.entry(frame.tid()).or_default().in_syscall = false;
+            }
+            EventType::EvInterceptedSyscall => {
+                let syscall = ev.intercepted_syscall_event();
+                let history = histories.entry(frame.tid()).or_default();
+                history.last_syscall = Some(syscall_name(syscall.number, syscall.arch));
+                history.in_syscall = false;
+            }
+            EventType::EvSignal => {
+                let sig = ev.signal_event().siginfo.si_signo;
+                let history = histories.entry(frame.tid()).or_default();
+                if !history.pending_signals.contains(&sig) {
+                    history.pending_signals.push(sig);
+                }
+            }
+            EventType::EvSignalDelivery | EventType::EvSignalHandler => {
+                let sig = ev.signal_event().siginfo.si_signo;
+                let history = histories.entry(frame.tid()).or_default();
+                history.pending_signals.retain(|&s| s != sig);
+            }
+            EventType::EvExit => {
+                histories.remove(&frame.tid());
+            }
+            _ => (),
+        }
+    }
+    (histories, None)
+}
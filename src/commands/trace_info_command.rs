@@ -8,6 +8,7 @@ use crate::{
         replay_session::{Flags, ReplaySession, ReplayStatus},
         session_inner::RunCommand,
     },
+    ticks::Ticks,
     trace::trace_reader::TraceReader,
     util::read_env,
 };
@@ -35,6 +36,11 @@ struct TraceHeader {
     bind_to_cpu: i32,
     cpuid_faulting: bool,
     ticks_semantics: String,
+    page_size: usize,
+    round_robin_quantum: Option<Ticks>,
+    core_type: Option<String>,
+    cpu_quota: Option<f64>,
+    nice_level: i32,
     cpuid_records: Vec<[u32; 6]>,
     environ: Vec<String>,
 }
@@ -51,6 +57,10 @@ impl RdCommand for TraceInfoCommand {
             TicksSemantics::TicksRetiredConditionalBranches => "rcb".into(),
             TicksSemantics::TicksTakenBranches => "branches".into(),
         };
+        let page_size = trace.page_size();
+        let round_robin_quantum = trace.round_robin_quantum();
+        let core_type = trace.core_type().map(|c| c.to_string().to_lowercase());
+        let environment = trace.record_environment();
 
         let mut cpuid_records: Vec<[u32; 6]> = Vec::new();
         for r in trace.cpuid_records() {
@@ -63,6 +73,7 @@ impl RdCommand for TraceInfoCommand {
             redirect_stdio: false,
             share_private_mappings: false,
             cpu_unbound: true,
+            dead_tasks: Default::default(),
         };
         let session = ReplaySession::create(self.trace_dir.as_ref(), flags);
         let replay_session = session.as_replay().unwrap();
@@ -75,6 +86,12 @@ impl RdCommand for TraceInfoCommand {
                 break;
             }
 
+            if result.status == ReplayStatus::ReplayDiverged {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    result.divergence.unwrap().to_string(),
+                ));
+            }
             if result.status == ReplayStatus::ReplayExited {
                 return Err(io::Error::new(
                     io::ErrorKind::Other,
@@ -92,6 +109,11 @@ impl RdCommand for TraceInfoCommand {
             bind_to_cpu: bind_to_cpu.map_or(-1, |c| c.try_into().unwrap()),
             cpuid_faulting,
             ticks_semantics,
+            page_size,
+            round_robin_quantum,
+            core_type,
+            cpu_quota: environment.cpu_quota.map(|q| q.cpus()),
+            nice_level: environment.nice,
             cpuid_records,
             environ: environ_strings,
         };
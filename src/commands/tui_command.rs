@@ -0,0 +1,678 @@
+//! `rd tui`: a full screen, keyboard driven trace browser.
+//!
+//! The whole trace is read up front (frames are small; recorded data is
+//! not decompressed) and then browsed in a list view. Selecting an event
+//! shows its decoded details along with the registers that changed since the
+//! previous event of the same task. From either view the user can start
+//! `rd replay -g <event> -s <port>` to debug that point with gdb (rd can't
+//! launch gdb itself yet), and come back to the browser when the debug
+//! session ends.
+//!
+//! This only needs a terminal that understands the usual ANSI escapes, so
+//! it's implemented directly on top of termios rather than pulling in a TUI
+//! library.
+
+use crate::{
+    commands::{
+        rd_options::{RdOptions, RdSubCommand},
+        RdCommand,
+    },
+    trace::{
+        trace_frame::{FrameTime, TraceFrame},
+        trace_reader::{TraceReader, ValidateSourceFile},
+        trace_stream::{MappedData, RawDataMetadata},
+        trace_task_event::{TraceTaskEvent, TraceTaskEventVariant},
+    },
+};
+use libc::{pid_t, winsize, STDIN_FILENO, STDOUT_FILENO, TIOCGWINSZ};
+use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg, Termios};
+use std::{
+    collections::HashMap,
+    env,
+    io,
+    io::{stdin, stdout, Read, Write},
+    net::TcpListener,
+    path::PathBuf,
+    process::Command,
+};
+
+/// Lines used by the title bar and the status line.
+const CHROME_LINES: usize = 2;
+
+/// How long to wait for the rest of an escape sequence after an ESC byte.
+const ESCAPE_SEQUENCE_TIMEOUT_MS: i32 = 50;
+
+pub struct TuiCommand {
+    trace_dir: Option<PathBuf>,
+}
+
+impl TuiCommand {
+    pub fn new(options: &RdOptions) -> TuiCommand {
+        match options.cmd.clone() {
+            RdSubCommand::Tui { trace_dir } => TuiCommand { trace_dir },
+            _ => panic!("Unexpected RdSubCommand variant. Not a Tui variant!"),
+        }
+    }
+}
+
+impl RdCommand for TuiCommand {
+    fn run(&mut self) -> io::Result<()> {
+        let rows = load_trace(self.trace_dir.as_ref());
+        let mut browser = Browser::new(rows, self.trace_dir.clone());
+        let term = RawTerminal::enter()?;
+        browser.run(&term)
+    }
+}
+
+/// Everything we show about one trace frame.
+struct EventRow {
+    frame: TraceFrame,
+    task_event: Option<TraceTaskEvent>,
+    mmaps: Vec<String>,
+    raw_data: Vec<RawDataMetadata>,
+    summary: String,
+}
+
+fn load_trace(trace_dir: Option<&PathBuf>) -> Vec<EventRow> {
+    let mut trace = TraceReader::new(trace_dir);
+    let mut task_events: HashMap<FrameTime, TraceTaskEvent> = HashMap::new();
+    let mut time: FrameTime = 0;
+    while let Some(e) = trace.read_task_event(Some(&mut time)) {
+        task_events.insert(time, e);
+    }
+
+    let mut rows = Vec::new();
+    while !trace.at_end() {
+        let frame = trace.read_frame();
+        let mut mmaps = Vec::new();
+        loop {
+            let mut data = MappedData::default();
+            match trace.read_mapped_region(
+                Some(&mut data),
+                Some(ValidateSourceFile::DontValidate),
+                None,
+                None,
+                None,
+            ) {
+                Some(km) => mmaps.push(format!("{}", km)),
+                None => break,
+            }
+        }
+        let mut raw_data = Vec::new();
+        while let Some(raw) = trace.read_raw_data_metadata_for_frame() {
+            raw_data.push(raw);
+        }
+        let summary = format!("{:>10} {:>7}  {}", frame.time(), frame.tid(), frame.event());
+        rows.push(EventRow {
+            task_event: task_events.remove(&frame.time()),
+            frame,
+            mmaps,
+            raw_data,
+            summary,
+        });
+    }
+    rows
+}
+
+/// Puts the terminal in raw mode on the alternate screen for as long as it
+/// lives.
+struct RawTerminal {
+    saved: Termios,
+    raw: Termios,
+}
+
+impl RawTerminal {
+    fn enter() -> io::Result<RawTerminal> {
+        let saved = tcgetattr(STDIN_FILENO).map_err(nix_to_io)?;
+        let mut raw = saved.clone();
+        cfmakeraw(&mut raw);
+        tcsetattr(STDIN_FILENO, SetArg::TCSAFLUSH, &raw).map_err(nix_to_io)?;
+        // Alternate screen, hide cursor
+        write!(stdout(), "\x1b[?1049h\x1b[?25l")?;
+        stdout().flush()?;
+        Ok(RawTerminal { saved, raw })
+    }
+
+    /// Run `f` with the terminal back in the state we found it in, e.g. to
+    /// let another program use it.
+    fn suspended<T>(&self, f: impl FnOnce() -> T) -> io::Result<T> {
+        write!(stdout(), "\x1b[?25h\x1b[?1049l")?;
+        stdout().flush()?;
+        tcsetattr(STDIN_FILENO, SetArg::TCSAFLUSH, &self.saved).map_err(nix_to_io)?;
+        let result = f();
+        tcsetattr(STDIN_FILENO, SetArg::TCSAFLUSH, &self.raw).map_err(nix_to_io)?;
+        write!(stdout(), "\x1b[?1049h\x1b[?25l")?;
+        Ok(result)
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        write!(stdout(), "\x1b[?25h\x1b[?1049l").unwrap_or(());
+        stdout().flush().unwrap_or(());
+        tcsetattr(STDIN_FILENO, SetArg::TCSAFLUSH, &self.saved).unwrap_or(());
+    }
+}
+
+fn nix_to_io(e: nix::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+fn terminal_size() -> (usize, usize) {
+    let mut ws: winsize = unsafe { std::mem::zeroed() };
+    let ret = unsafe { libc::ioctl(STDOUT_FILENO, TIOCGWINSZ, &mut ws) };
+    if ret < 0 || ws.ws_row == 0 || ws.ws_col == 0 {
+        (24, 80)
+    } else {
+        (ws.ws_row as usize, ws.ws_col as usize)
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Key {
+    Char(u8),
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Enter,
+    Backspace,
+    Escape,
+}
+
+fn input_pending(timeout_ms: i32) -> bool {
+    let mut pfd = libc::pollfd {
+        fd: STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut pfd, 1, timeout_ms) > 0 }
+}
+
+fn read_key() -> io::Result<Key> {
+    let mut buf = [0u8; 1];
+    stdin().read_exact(&mut buf)?;
+    let key = match buf[0] {
+        b'\r' | b'\n' => Key::Enter,
+        0x7f | 0x08 => Key::Backspace,
+        0x1b => {
+            // Escape sequences arrive all at once, so if nothing follows
+            // promptly this was the ESC key by itself.
+            if !input_pending(ESCAPE_SEQUENCE_TIMEOUT_MS) {
+                return Ok(Key::Escape);
+            }
+            let mut seq = [0u8; 2];
+            stdin().read_exact(&mut seq[0..1])?;
+            if seq[0] != b'[' {
+                return Ok(Key::Escape);
+            }
+            stdin().read_exact(&mut seq[1..2])?;
+            match seq[1] {
+                b'A' => Key::Up,
+                b'B' => Key::Down,
+                b'H' => Key::Home,
+                b'F' => Key::End,
+                b'5' | b'6' => {
+                    let mut tilde = [0u8; 1];
+                    stdin().read_exact(&mut tilde)?;
+                    if seq[1] == b'5' {
+                        Key::PageUp
+                    } else {
+                        Key::PageDown
+                    }
+                }
+                _ => Key::Escape,
+            }
+        }
+        c => Key::Char(c),
+    };
+    Ok(key)
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum PromptKind {
+    Filter,
+    Tid,
+    Goto,
+}
+
+enum Mode {
+    List,
+    Detail,
+    Prompt(PromptKind, String),
+}
+
+struct Browser {
+    rows: Vec<EventRow>,
+    trace_dir: Option<PathBuf>,
+    /// Indices into `rows` that pass the current filters.
+    visible: Vec<usize>,
+    /// Index into `visible`.
+    cursor: usize,
+    /// Index into `visible` of the first line on screen.
+    top: usize,
+    /// Scroll offset of the detail view.
+    detail_top: usize,
+    filter: String,
+    only_tid: Option<pid_t>,
+    mode: Mode,
+    status: String,
+}
+
+impl Browser {
+    fn new(rows: Vec<EventRow>, trace_dir: Option<PathBuf>) -> Browser {
+        let mut b = Browser {
+            rows,
+            trace_dir,
+            visible: Vec::new(),
+            cursor: 0,
+            top: 0,
+            detail_top: 0,
+            filter: String::new(),
+            only_tid: None,
+            mode: Mode::List,
+            status: String::new(),
+        };
+        b.apply_filters();
+        b
+    }
+
+    fn run(&mut self, term: &RawTerminal) -> io::Result<()> {
+        loop {
+            self.draw()?;
+            let key = read_key()?;
+            if !self.handle_key(key, term)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Returns false when the user asked to quit.
+    fn handle_key(&mut self, key: Key, term: &RawTerminal) -> io::Result<bool> {
+        let page = terminal_size().0.saturating_sub(CHROME_LINES).max(1);
+        self.status.clear();
+        match &mut self.mode {
+            Mode::Prompt(kind, text) => match key {
+                Key::Enter => {
+                    let (kind, text) = (*kind, text.clone());
+                    self.mode = Mode::List;
+                    self.finish_prompt(kind, &text);
+                }
+                Key::Escape => self.mode = Mode::List,
+                Key::Backspace => {
+                    text.pop();
+                }
+                Key::Char(c) if c.is_ascii_graphic() || c == b' ' => text.push(c as char),
+                _ => (),
+            },
+            Mode::Detail => match key {
+                Key::Char(b'q') | Key::Escape | Key::Backspace => self.mode = Mode::List,
+                Key::Up | Key::Char(b'k') => self.detail_top = self.detail_top.saturating_sub(1),
+                Key::Down | Key::Char(b'j') => self.detail_top += 1,
+                Key::PageUp => self.detail_top = self.detail_top.saturating_sub(page),
+                Key::PageDown => self.detail_top += page,
+                Key::Char(b'n') => self.move_cursor(1, true),
+                Key::Char(b'p') => self.move_cursor(-1, true),
+                Key::Char(b'r') => self.replay_to_selected(term)?,
+                _ => (),
+            },
+            Mode::List => match key {
+                Key::Char(b'q') => return Ok(false),
+                Key::Up | Key::Char(b'k') => self.move_cursor(-1, false),
+                Key::Down | Key::Char(b'j') => self.move_cursor(1, false),
+                Key::PageUp => self.move_cursor(-(page as isize), false),
+                Key::PageDown => self.move_cursor(page as isize, false),
+                Key::Home | Key::Char(b'g') => self.move_cursor(isize::MIN, false),
+                Key::End | Key::Char(b'G') => self.move_cursor(isize::MAX, false),
+                Key::Enter => {
+                    if !self.visible.is_empty() {
+                        self.detail_top = 0;
+                        self.mode = Mode::Detail;
+                    }
+                }
+                Key::Char(b'/') => self.mode = Mode::Prompt(PromptKind::Filter, String::new()),
+                Key::Char(b't') => self.mode = Mode::Prompt(PromptKind::Tid, String::new()),
+                Key::Char(b':') => self.mode = Mode::Prompt(PromptKind::Goto, String::new()),
+                Key::Char(b'c') => {
+                    self.filter.clear();
+                    self.only_tid = None;
+                    self.apply_filters();
+                }
+                Key::Char(b'r') => self.replay_to_selected(term)?,
+                _ => (),
+            },
+        }
+        Ok(true)
+    }
+
+    fn finish_prompt(&mut self, kind: PromptKind, text: &str) {
+        match kind {
+            PromptKind::Filter => {
+                self.filter = text.to_owned();
+                self.apply_filters();
+            }
+            PromptKind::Tid => {
+                if text.trim().is_empty() {
+                    self.only_tid = None;
+                } else {
+                    match text.trim().parse::<pid_t>() {
+                        Ok(tid) => self.only_tid = Some(tid),
+                        Err(_) => {
+                            self.status = format!("Not a tid: {}", text);
+                            return;
+                        }
+                    }
+                }
+                self.apply_filters();
+            }
+            PromptKind::Goto => match text.trim().parse::<FrameTime>() {
+                Ok(time) => {
+                    let rows = &self.rows;
+                    self.cursor = self
+                        .visible
+                        .iter()
+                        .position(|&i| rows[i].frame.time() >= time)
+                        .unwrap_or(self.visible.len().saturating_sub(1));
+                }
+                Err(_) => self.status = format!("Not an event number: {}", text),
+            },
+        }
+    }
+
+    fn apply_filters(&mut self) {
+        let selected = self.visible.get(self.cursor).copied();
+        let filter = self.filter.to_lowercase();
+        let only_tid = self.only_tid;
+        self.visible = self
+            .rows
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| only_tid.map_or(true, |tid| tid == r.frame.tid()))
+            .filter(|(_, r)| filter.is_empty() || r.summary.to_lowercase().contains(&filter))
+            .map(|(i, _)| i)
+            .collect();
+        // Keep the selection on the same event, or the next one after it, if
+        // it's still visible.
+        self.cursor = match selected {
+            Some(sel) => self
+                .visible
+                .iter()
+                .position(|&i| i >= sel)
+                .unwrap_or(self.visible.len().saturating_sub(1)),
+            None => 0,
+        };
+        if self.visible.is_empty() {
+            self.status = "No matching events".to_owned();
+        }
+    }
+
+    fn move_cursor(&mut self, delta: isize, reset_detail: bool) {
+        if self.visible.is_empty() {
+            return;
+        }
+        let last = self.visible.len() - 1;
+        self.cursor = if delta < 0 {
+            let up = delta.checked_neg().map_or(usize::MAX, |d| d as usize);
+            self.cursor.saturating_sub(up)
+        } else {
+            self.cursor.saturating_add(delta as usize).min(last)
+        };
+        if reset_detail {
+            self.detail_top = 0;
+        }
+    }
+
+    fn selected(&self) -> Option<usize> {
+        self.visible.get(self.cursor).copied()
+    }
+
+    fn replay_to_selected(&mut self, term: &RawTerminal) -> io::Result<()> {
+        let index = match self.selected() {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+        let time = self.rows[index].frame.time();
+        // Let the kernel pick a free port.
+        let port = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+        let exe = env::current_exe()?;
+        let mut cmd = Command::new(exe);
+        cmd.arg("replay")
+            .arg("-g")
+            .arg(time.to_string())
+            .arg("-s")
+            .arg(port.to_string());
+        if let Some(dir) = &self.trace_dir {
+            cmd.arg(dir);
+        }
+
+        // Hand the terminal over while the replay waits for gdb and serves
+        // it; it says where to connect.
+        let result = term.suspended(|| cmd.status())?;
+        self.status = match result {
+            Ok(status) if status.success() => format!("Debug session at event {} ended", time),
+            Ok(status) => format!("Replay to event {} failed: {}", time, status),
+            Err(e) => format!("Couldn't start replay: {}", e),
+        };
+        Ok(())
+    }
+
+    fn draw(&mut self) -> io::Result<()> {
+        let (height, width) = terminal_size();
+        let body_lines = height.saturating_sub(CHROME_LINES).max(1);
+        let mut screen = String::new();
+        screen.push_str("\x1b[H\x1b[2J");
+
+        let title = match self.mode {
+            Mode::Detail => " rd tui -- event details  (j/k scroll, n/p next/prev event, \
+                             r debug here, q back)"
+                .to_owned(),
+            _ => format!(
+                " rd tui -- {} of {} events  (/ filter, t tid, : goto, c clear, \
+                 Enter details, r debug here, q quit)",
+                self.visible.len(),
+                self.rows.len()
+            ),
+        };
+        push_line(&mut screen, &title, width, Some("\x1b[7m"));
+
+        let lines = match self.mode {
+            Mode::Detail => {
+                let lines = match self.selected() {
+                    Some(index) => self.detail_lines(index),
+                    None => Vec::new(),
+                };
+                self.detail_top = self
+                    .detail_top
+                    .min(lines.len().saturating_sub(body_lines));
+                lines
+                    .into_iter()
+                    .skip(self.detail_top)
+                    .take(body_lines)
+                    .map(|l| (l, None))
+                    .collect::<Vec<_>>()
+            }
+            _ => {
+                if self.cursor < self.top {
+                    self.top = self.cursor;
+                } else if self.cursor >= self.top + body_lines {
+                    self.top = self.cursor + 1 - body_lines;
+                }
+                self.visible
+                    .iter()
+                    .enumerate()
+                    .skip(self.top)
+                    .take(body_lines)
+                    .map(|(pos, &i)| {
+                        let style = if pos == self.cursor {
+                            Some("\x1b[1;7m")
+                        } else {
+                            None
+                        };
+                        (self.rows[i].summary.clone(), style)
+                    })
+                    .collect::<Vec<_>>()
+            }
+        };
+        let shown = lines.len();
+        for (line, style) in lines {
+            push_line(&mut screen, &line, width, style);
+        }
+        for _ in shown..body_lines {
+            screen.push_str("\r\n");
+        }
+
+        let status = match &self.mode {
+            Mode::Prompt(PromptKind::Filter, text) => format!("filter: {}", text),
+            Mode::Prompt(PromptKind::Tid, text) => format!("tid (empty for all): {}", text),
+            Mode::Prompt(PromptKind::Goto, text) => format!("go to event: {}", text),
+            _ => {
+                let mut filters = Vec::new();
+                if !self.filter.is_empty() {
+                    filters.push(format!("filter '{}'", self.filter));
+                }
+                if let Some(tid) = self.only_tid {
+                    filters.push(format!("tid {}", tid));
+                }
+                if self.status.is_empty() {
+                    filters.join(", ")
+                } else {
+                    self.status.clone()
+                }
+            }
+        };
+        screen.push_str(&truncate(&status, width));
+
+        let mut out = stdout();
+        out.write_all(screen.as_bytes())?;
+        out.flush()
+    }
+
+    fn detail_lines(&self, index: usize) -> Vec<String> {
+        let row = &self.rows[index];
+        let mut lines = Vec::new();
+        let mut dump = Vec::new();
+        row.frame.dump(Some(&mut dump)).unwrap();
+        let dump = String::from_utf8_lossy(&dump);
+        lines.extend(dump.lines().map(|l| l.to_owned()));
+
+        if let Some(e) = &row.task_event {
+            lines.push(String::new());
+            lines.push(match e.event_variant() {
+                TraceTaskEventVariant::Clone(c) => format!(
+                    "task event: CLONE tid={} parent={} clone_flags={:#x}",
+                    e.tid(),
+                    c.parent_tid(),
+                    c.clone_flags()
+                ),
+                TraceTaskEventVariant::Exec(ex) => {
+                    format!("task event: EXEC tid={} file={:?}", e.tid(), ex.file_name())
+                }
+                TraceTaskEventVariant::Exit(ex) => format!(
+                    "task event: EXIT tid={} status={}",
+                    e.tid(),
+                    ex.exit_status().get()
+                ),
+            });
+        }
+
+        if row.frame.event().record_regs() {
+            lines.push(String::new());
+            match self.previous_regs_frame(index) {
+                Some(prev) => {
+                    lines.push(format!(
+                        "registers changed since event {}:",
+                        prev.frame.time()
+                    ));
+                    let changed = register_diff(&prev.frame, &row.frame);
+                    if changed.is_empty() {
+                        lines.push("  (none)".to_owned());
+                    }
+                    lines.extend(changed);
+                }
+                None => lines.push("first event with registers for this task".to_owned()),
+            }
+        }
+
+        if !row.mmaps.is_empty() {
+            lines.push(String::new());
+            lines.push("mappings:".to_owned());
+            lines.extend(row.mmaps.iter().map(|m| format!("  {}", m)));
+        }
+        if !row.raw_data.is_empty() {
+            lines.push(String::new());
+            lines.push("recorded data:".to_owned());
+            lines.extend(row.raw_data.iter().map(|d| {
+                format!(
+                    "  tid:{} addr:{:#x} length:{:#x}",
+                    d.rec_tid,
+                    d.addr.as_usize(),
+                    d.size
+                )
+            }));
+        }
+        lines
+    }
+
+    /// The closest earlier event of the same task that has registers.
+    fn previous_regs_frame(&self, index: usize) -> Option<&EventRow> {
+        let tid = self.rows[index].frame.tid();
+        self.rows[0..index]
+            .iter()
+            .rev()
+            .find(|r| r.frame.tid() == tid && r.frame.event().record_regs())
+    }
+}
+
+/// "name: old -> new" for every register that differs between the two
+/// frames, based on the compact register dump format.
+fn register_diff(before: &TraceFrame, after: &TraceFrame) -> Vec<String> {
+    let compact = |frame: &TraceFrame| {
+        let mut buf = Vec::new();
+        frame.regs_ref().write_register_file_compact(&mut buf).unwrap();
+        String::from_utf8_lossy(&buf).into_owned()
+    };
+    let before_s = compact(before);
+    let after_s = compact(after);
+    let before_regs: HashMap<&str, &str> = before_s
+        .split_whitespace()
+        .filter_map(split_register)
+        .collect();
+    after_s
+        .split_whitespace()
+        .filter_map(split_register)
+        .filter(|(name, value)| before_regs.get(name).map_or(true, |v| v != value))
+        .map(|(name, value)| {
+            format!(
+                "  {:>8}: {} -> {}",
+                name,
+                before_regs.get(name).unwrap_or(&"?"),
+                value
+            )
+        })
+        .collect()
+}
+
+fn split_register(token: &str) -> Option<(&str, &str)> {
+    let colon = token.find(':')?;
+    Some((&token[0..colon], &token[colon + 1..]))
+}
+
+/// Append `line` clipped to `width` characters, plus a line break. `style`
+/// is an SGR escape sequence to display the line with.
+fn push_line(screen: &mut String, line: &str, width: usize, style: Option<&str>) {
+    match style {
+        Some(style) => {
+            screen.push_str(style);
+            screen.push_str(&truncate(line, width));
+            screen.push_str("\x1b[0m");
+        }
+        None => screen.push_str(&truncate(line, width)),
+    }
+    screen.push_str("\r\n");
+}
+
+fn truncate(s: &str, width: usize) -> String {
+    s.chars().take(width).collect()
+}
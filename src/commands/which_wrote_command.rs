@@ -0,0 +1,220 @@
+use crate::{
+    cancellation_token::CancellationToken,
+    commands::{
+        dump_command::dump_hex,
+        rd_options::{RdOptions, RdSubCommand},
+        RdCommand,
+    },
+    remote_ptr::{RemotePtr, Void},
+    replay_timeline::ReplayTimeline,
+    session::replay_session::{Flags, ReplaySession, ReplayStatus},
+    trace::{
+        trace_frame::FrameTime,
+        trace_reader::TraceReader,
+        trace_write_index::{MemoryWrite, WriteIndex},
+    },
+};
+use libc::pid_t;
+use std::{
+    io,
+    io::{stdout, Write},
+    path::PathBuf,
+};
+
+pub struct WhichWroteCommand {
+    addr: RemotePtr<Void>,
+    before: Option<FrameTime>,
+    only_tid: Option<pid_t>,
+    all: bool,
+    data: bool,
+    next_after: Option<FrameTime>,
+    trace_dir: Option<PathBuf>,
+}
+
+impl WhichWroteCommand {
+    pub fn new(options: &RdOptions) -> WhichWroteCommand {
+        match options.cmd.clone() {
+            RdSubCommand::WhichWrote {
+                addr,
+                before,
+                only_tid,
+                all,
+                data,
+                next_after,
+                trace_dir,
+            } => WhichWroteCommand {
+                addr: RemotePtr::new_from_val(addr),
+                before,
+                only_tid,
+                all,
+                data,
+                next_after,
+                trace_dir,
+            },
+            _ => panic!("Unexpected RdSubCommand variant. Not a WhichWrote variant!"),
+        }
+    }
+
+    fn which_wrote(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut trace = TraceReader::new(self.trace_dir.as_ref());
+        let index = WriteIndex::from_trace(&mut trace);
+        let before = self.before.unwrap_or(FrameTime::MAX);
+        let vm = self
+            .only_tid
+            .map(|tid| index.vm_at(tid, before.saturating_sub(1)));
+
+        if self.all {
+            let mut found = false;
+            for w in index.writes_to(self.addr, vm).filter(|w| w.time < before) {
+                self.write_memory_write(out, &trace, w)?;
+                found = true;
+            }
+            if !found {
+                write!(out, "No recorded writes to {}\n", self.addr)?;
+            }
+            return Ok(());
+        }
+
+        match index.last_write_before(self.addr, before, vm) {
+            Some(w) => self.write_memory_write(out, &trace, w),
+            None => write!(
+                out,
+                "No recorded writes to {}; the value was written by the tracee itself \
+                 or mapped in from a file\n",
+                self.addr
+            ),
+        }
+    }
+}
+
+impl WhichWroteCommand {
+    /// Replay from event `after` with a watchpoint on the address until
+    /// something writes to it.
+    fn next_write(&self, after: FrameTime, out: &mut dyn Write) -> io::Result<()> {
+        let flags = Flags {
+            redirect_stdio: false,
+            share_private_mappings: false,
+            cpu_unbound: true,
+            dead_tasks: Default::default(),
+        };
+        let mut timeline =
+            ReplayTimeline::new(ReplaySession::create(self.trace_dir.as_ref(), flags));
+        if !timeline.seek_to_event(after, &CancellationToken::new()) {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("Replay stopped before reaching event {}", after),
+            ));
+        }
+        let session = timeline.current_session();
+        let replay = session.as_replay().unwrap();
+        loop {
+            let result = match replay.continue_until_write(self.addr, 1) {
+                Some(result) => result,
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("Can't watch {} at event {}", self.addr, after),
+                    ))
+                }
+            };
+            let maybe_t = if result.break_status.watchpoints_hit.is_empty() {
+                None
+            } else {
+                result.break_status.task.as_ref().and_then(|t| t.upgrade())
+            };
+            if let Some(t) = maybe_t {
+                let t = t.borrow();
+                write!(
+                    out,
+                    "event {} tid {} wrote {} at ip {}\n",
+                    replay.current_frame_time(),
+                    t.rec_tid,
+                    self.addr,
+                    t.ip()
+                )?;
+                for v in &result.break_status.watchpoint_values {
+                    write!(out, "  before:\n")?;
+                    dump_hex(out, v.addr.as_usize(), &v.old_value)?;
+                    write!(out, "  after:\n")?;
+                    dump_hex(out, v.addr.as_usize(), &v.new_value)?;
+                }
+                return Ok(());
+            }
+            if result.status != ReplayStatus::ReplayContinue {
+                return write!(out, "No writes to {} after event {}\n", self.addr, after);
+            }
+        }
+    }
+
+    fn write_memory_write(
+        &self,
+        out: &mut dyn Write,
+        trace: &TraceReader,
+        w: &MemoryWrite,
+    ) -> io::Result<()> {
+        write!(
+            out,
+            "event {} tid {} wrote {}-{} ({} bytes)\n",
+            w.time,
+            w.rec_tid,
+            w.addr,
+            w.end(),
+            w.size
+        )?;
+        if self.data {
+            let raw = trace.raw_data_for_event(w.time);
+            match written_data(&raw, w) {
+                Some(data) => dump_hex(out, w.addr.as_usize(), data)?,
+                None => write!(out, "    (data not in the trace)\n")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RdCommand for WhichWroteCommand {
+    fn run(&mut self) -> io::Result<()> {
+        match self.next_after {
+            Some(after) => self.next_write(after, &mut stdout()),
+            None => self.which_wrote(&mut stdout()),
+        }
+    }
+}
+
+/// The bytes `w` stored, out of `raw`, the raw data of the frame for its
+/// event. If the frame wrote the same range more than once, the last write is
+/// the one replay leaves in memory.
+fn written_data<'a>(raw: &'a [(RemotePtr<Void>, Vec<u8>)], w: &MemoryWrite) -> Option<&'a [u8]> {
+    raw.iter()
+        .rev()
+        .find(|(addr, data)| *addr == w.addr && data.len() == w.size)
+        .map(|(_, data)| data.as_slice())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::trace::trace_write_index::VmKey;
+
+    #[test]
+    fn written_data_lookup() {
+        let addr = RemotePtr::new_from_val(0x1000);
+        let w = MemoryWrite {
+            time: 5,
+            rec_tid: 7,
+            vm: VmKey { tid: 7, since: 1 },
+            addr,
+            size: 4,
+        };
+        let raw = vec![
+            (addr, vec![1, 2, 3, 4]),
+            (addr, vec![9; 8]),
+            (RemotePtr::new_from_val(0x2000), vec![5; 4]),
+            (addr, vec![4, 3, 2, 1]),
+        ];
+        assert_eq!(written_data(&raw, &w), Some(&[4u8, 3, 2, 1][..]));
+        assert_eq!(written_data(&raw[..3], &w), Some(&[1u8, 2, 3, 4][..]));
+        assert_eq!(written_data(&raw[1..3], &w), None);
+        assert_eq!(written_data(&[], &w), None);
+    }
+}
@@ -0,0 +1,260 @@
+//! Tasks that replay has run past the exit of. Users want to look at a thread
+//! after it's gone, a crashed one in particular, but once its exit has been
+//! replayed there's no tracee left to ask. So the replay session keeps what
+//! each task looked like just before it exited: its registers and, for the
+//! last task of an address space, the contents of its mappings. Memory of a
+//! dead thread whose process lives on is read through one of the remaining
+//! threads instead.
+//!
+//! How much is kept is `DeadTaskPolicy`, `rd replay --dead-tasks`.
+
+use crate::{
+    extra_registers::ExtraRegisters,
+    registers::Registers,
+    remote_ptr::{RemotePtr, Void},
+    session::{
+        address_space::MappingFlags,
+        task::{replay_task::ReplayTask, Task},
+    },
+    trace::trace_frame::FrameTime,
+    util::for_each_readable_chunk,
+};
+use libc::pid_t;
+use nix::sys::mman::ProtFlags;
+use std::{cmp::min, collections::VecDeque};
+
+/// How many dead tasks are kept by default. Older ones are forgotten first.
+const DEFAULT_MAX_DEAD_TASKS: usize = 64;
+
+/// The most memory kept for all dead tasks together. The memory of the
+/// oldest ones is dropped first (their registers are still kept), and an
+/// address space bigger than this is only kept up to it.
+const MAX_DEAD_TASK_MEMORY: usize = 1 << 30;
+
+/// How much memory `copy_memory()` reads at a time.
+const COPY_CHUNK_SIZE: usize = 1 << 20;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DeadTaskRetention {
+    Nothing,
+    Registers,
+    /// Registers, plus the memory of the last task of each address space.
+    RegistersAndMemory,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct DeadTaskPolicy {
+    pub retention: DeadTaskRetention,
+    pub max_tasks: usize,
+}
+
+impl Default for DeadTaskPolicy {
+    fn default() -> Self {
+        DeadTaskPolicy {
+            retention: DeadTaskRetention::Registers,
+            max_tasks: DEFAULT_MAX_DEAD_TASKS,
+        }
+    }
+}
+
+/// Parse `none`, `registers` or `memory`, optionally followed by
+/// `:<max tasks>`.
+pub fn parse_dead_task_policy(s: &str) -> Option<DeadTaskPolicy> {
+    let mut parts = s.trim().splitn(2, ':');
+    let retention = match parts.next()? {
+        "none" => DeadTaskRetention::Nothing,
+        "registers" => DeadTaskRetention::Registers,
+        "memory" => DeadTaskRetention::RegistersAndMemory,
+        _ => return None,
+    };
+    let max_tasks = match parts.next() {
+        Some(n) => n.parse().ok().filter(|&n| n > 0)?,
+        None => DEFAULT_MAX_DEAD_TASKS,
+    };
+    Some(DeadTaskPolicy {
+        retention,
+        max_tasks,
+    })
+}
+
+/// A task as it was just before it exited.
+#[derive(Clone)]
+pub struct DeadTask {
+    pub rec_tid: pid_t,
+    pub tgid: pid_t,
+    /// The time of its exit event.
+    pub exit_time: FrameTime,
+    pub regs: Registers,
+    pub extra_regs: ExtraRegisters,
+    /// The start and contents of each readable mapping, if this was the last
+    /// task of its address space and memory is being kept.
+    memory: Vec<(RemotePtr<Void>, Vec<u8>)>,
+}
+
+impl DeadTask {
+    pub fn has_memory(&self) -> bool {
+        !self.memory.is_empty()
+    }
+
+    fn memory_size(&self) -> usize {
+        self.memory.iter().map(|(_, data)| data.len()).sum()
+    }
+
+    /// Copy the kept memory at `addr` into `buf`. Returns how many bytes
+    /// could be copied before hitting memory that wasn't kept.
+    pub fn read_memory(&self, addr: RemotePtr<Void>, buf: &mut [u8]) -> usize {
+        let mut done = 0;
+        while done < buf.len() {
+            let at = addr + done;
+            let found = self
+                .memory
+                .iter()
+                .find(|(start, data)| *start <= at && at < *start + data.len());
+            let (start, data) = match found {
+                Some(m) => m,
+                None => break,
+            };
+            let offset = at - *start;
+            let len = min(buf.len() - done, data.len() - offset);
+            buf[done..done + len].copy_from_slice(&data[offset..offset + len]);
+            done += len;
+        }
+        done
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct DeadTasks {
+    policy: DeadTaskPolicy,
+    tasks: VecDeque<DeadTask>,
+}
+
+impl DeadTasks {
+    pub fn new(policy: DeadTaskPolicy) -> DeadTasks {
+        DeadTasks {
+            policy,
+            tasks: VecDeque::new(),
+        }
+    }
+
+    /// `t` is about to exit, at the event at `time`.
+    pub fn task_exiting(&mut self, t: &mut ReplayTask, time: FrameTime) {
+        if self.policy.retention == DeadTaskRetention::Nothing {
+            return;
+        }
+        let memory = if self.policy.retention == DeadTaskRetention::RegistersAndMemory
+            && t.vm().task_set().len() == 1
+        {
+            copy_memory(t, MAX_DEAD_TASK_MEMORY)
+        } else {
+            Vec::new()
+        };
+        if self.tasks.len() >= self.policy.max_tasks {
+            self.tasks.pop_front();
+        }
+        let size = memory.iter().map(|(_, data)| data.len()).sum();
+        self.make_room_for_memory(size, MAX_DEAD_TASK_MEMORY);
+        let extra_regs = t.extra_regs_ref().clone();
+        self.tasks.push_back(DeadTask {
+            rec_tid: t.rec_tid,
+            tgid: t.tgid(),
+            exit_time: time,
+            regs: t.regs_ref().clone(),
+            extra_regs,
+            memory,
+        });
+    }
+
+    /// The most recent task with recorded tid `rec_tid` to exit, if it's
+    /// been kept. Tids get reused.
+    pub fn find(&self, rec_tid: pid_t) -> Option<&DeadTask> {
+        self.tasks.iter().rev().find(|t| t.rec_tid == rec_tid)
+    }
+
+    /// Drop the memory of the oldest tasks until `size` more bytes fit in
+    /// `max_memory`.
+    fn make_room_for_memory(&mut self, size: usize, max_memory: usize) {
+        let mut kept: usize = self.tasks.iter().map(|t| t.memory_size()).sum();
+        for t in self.tasks.iter_mut() {
+            if kept + size <= max_memory {
+                break;
+            }
+            kept -= t.memory_size();
+            t.memory = Vec::new();
+        }
+    }
+}
+
+/// Copy the readable memory of `t`'s address space, up to `max_size` bytes.
+fn copy_memory(t: &mut ReplayTask, max_size: usize) -> Vec<(RemotePtr<Void>, Vec<u8>)> {
+    let mut ranges = Vec::new();
+    for (_, m) in &t.vm().maps() {
+        if m.flags.intersects(
+            MappingFlags::IS_SYSCALLBUF | MappingFlags::IS_THREAD_LOCALS | MappingFlags::IS_RD_PAGE,
+        ) || !m.map.prot().contains(ProtFlags::PROT_READ)
+        {
+            continue;
+        }
+        ranges.push((m.map.start(), m.map.end() - m.map.start()));
+    }
+
+    let mut memory = Vec::new();
+    let mut left = max_size;
+    for (start, size) in ranges {
+        if left == 0 {
+            break;
+        }
+        let size = min(size, left);
+        for_each_readable_chunk(t, start, size, COPY_CHUNK_SIZE, |addr, data| {
+            memory.push((addr, data.to_vec()));
+            left -= data.len();
+        });
+    }
+    memory
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::kernel_abi::SupportedArch;
+
+    #[test]
+    fn parse_policy() {
+        assert_eq!(
+            parse_dead_task_policy("memory:8"),
+            Some(DeadTaskPolicy {
+                retention: DeadTaskRetention::RegistersAndMemory,
+                max_tasks: 8
+            })
+        );
+        assert_eq!(
+            parse_dead_task_policy("none"),
+            Some(DeadTaskPolicy {
+                retention: DeadTaskRetention::Nothing,
+                max_tasks: DEFAULT_MAX_DEAD_TASKS
+            })
+        );
+        assert_eq!(parse_dead_task_policy("registers:0"), None);
+        assert_eq!(parse_dead_task_policy("all"), None);
+    }
+
+    #[test]
+    fn oldest_memory_dropped_first() {
+        let dead = |rec_tid: pid_t, size: usize| DeadTask {
+            rec_tid,
+            tgid: rec_tid,
+            exit_time: 0,
+            regs: Registers::new(SupportedArch::X64),
+            extra_regs: ExtraRegisters::new(SupportedArch::X64),
+            memory: vec![(RemotePtr::from(0x1000usize), vec![0u8; size])],
+        };
+        let mut dead_tasks = DeadTasks::new(DeadTaskPolicy::default());
+        dead_tasks
+            .tasks
+            .extend(vec![dead(1, 30), dead(2, 30), dead(3, 30)]);
+        dead_tasks.make_room_for_memory(40, 100);
+        let kept: Vec<bool> = dead_tasks.tasks.iter().map(|t| t.has_memory()).collect();
+        assert_eq!(kept, vec![false, true, true]);
+        assert!(dead_tasks.find(1).is_some());
+    }
+}
@@ -0,0 +1,64 @@
+use std::{cell::RefCell, collections::VecDeque};
+
+/// Work queued by code that can't do it yet, to run later with a `&S`. See
+/// `SessionInner::defer()`, which uses this with the session as `S`.
+pub struct DeferredWorkQueue<S: ?Sized> {
+    work: RefCell<VecDeque<Box<dyn FnOnce(&S)>>>,
+}
+
+impl<S: ?Sized> Default for DeferredWorkQueue<S> {
+    fn default() -> Self {
+        DeferredWorkQueue {
+            work: Default::default(),
+        }
+    }
+}
+
+impl<S: ?Sized> DeferredWorkQueue<S> {
+    pub fn push<F: FnOnce(&S) + 'static>(&self, work: F) {
+        self.work.borrow_mut().push_back(Box::new(work));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.work.borrow().is_empty()
+    }
+
+    /// Run all queued work in the order it was queued, including any work
+    /// queued while doing so. The queue isn't borrowed while work runs.
+    pub fn run(&self, s: &S) {
+        loop {
+            let maybe_work = self.work.borrow_mut().pop_front();
+            match maybe_work {
+                Some(work) => work(s),
+                None => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn runs_in_order_including_work_queued_by_work() {
+        let queue: Rc<DeferredWorkQueue<RefCell<Vec<i32>>>> = Rc::new(Default::default());
+        let log = RefCell::new(Vec::new());
+        queue.push(|log| log.borrow_mut().push(1));
+        let q = queue.clone();
+        queue.push(move |log| {
+            log.borrow_mut().push(2);
+            q.push(|log| log.borrow_mut().push(4));
+        });
+        queue.push(|log| log.borrow_mut().push(3));
+        assert!(!queue.is_empty());
+        assert!(log.borrow().is_empty());
+
+        queue.run(&log);
+        assert_eq!(*log.borrow(), vec![1, 2, 3, 4]);
+        assert!(queue.is_empty());
+        queue.run(&log);
+        assert_eq!(log.borrow().len(), 4);
+    }
+}
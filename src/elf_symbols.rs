@@ -0,0 +1,305 @@
+use crate::{remote_code_ptr::RemoteCodePtr, session::task::Task};
+use gimli::{
+    AttributeValue,
+    DW_AT_abstract_origin,
+    DW_AT_call_line,
+    DW_AT_linkage_name,
+    DW_AT_name,
+    DW_AT_specification,
+    DW_TAG_inlined_subroutine,
+    DW_TAG_lexical_block,
+    DW_TAG_namespace,
+    DW_TAG_subprogram,
+    DebuggingInformationEntry,
+    Dwarf,
+    EndianSlice,
+    EntriesTreeNode,
+    LittleEndian,
+    RangeIter,
+    Reader,
+    SectionId,
+    Unit,
+};
+use goblin::elf::{
+    program_header::{ProgramHeader, PT_LOAD},
+    section_header::SHT_NOBITS,
+    sym::STT_FUNC,
+    Elf,
+};
+use std::{collections::HashMap, fs, ops::Range};
+
+/// A function inlined into the code at some address.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InlinedCall {
+    pub name: String,
+    /// The line of the caller the function was inlined at.
+    pub call_line: Option<u64>,
+}
+
+/// The file mapped at `ip` in `t`'s address space and the offset of `ip` in
+/// it, or `None` if `ip` isn't in a file mapping.
+pub fn file_offset(t: &dyn Task, ip: RemoteCodePtr) -> Option<(String, u64)> {
+    let vm = t.vm();
+    let m = vm.mapping_of(ip.to_data_ptr())?;
+    let file_name = m.map.fsname().to_string_lossy().into_owned();
+    if !file_name.starts_with('/') {
+        return None;
+    }
+    let offset = (ip.as_usize() - m.map.start().as_usize()) as u64 + m.map.file_offset_bytes();
+    Some((file_name, offset))
+}
+
+/// The parts of an ELF file that symbol lookup and unwinding need, parsed
+/// once. See `ElfFiles`.
+pub struct ElfFile {
+    data: Vec<u8>,
+    program_headers: Vec<ProgramHeader>,
+    /// Function symbols as (address, name), by address.
+    functions: Vec<(u64, String)>,
+    /// The file range and address of each section with contents, by name.
+    sections: HashMap<String, (Range<usize>, u64)>,
+}
+
+impl ElfFile {
+    pub fn parse(data: Vec<u8>) -> Option<ElfFile> {
+        let (program_headers, functions, sections) = {
+            let elf = Elf::parse(&data).ok()?;
+            // A symbol wins over a dynamic symbol at the same address, so it
+            // has to come after it.
+            let tables = [(&elf.dynsyms, &elf.dynstrtab), (&elf.syms, &elf.strtab)];
+            let mut functions = Vec::new();
+            for &(syms, strtab) in tables.iter() {
+                for sym in syms.iter().filter(|sym| sym.st_type() == STT_FUNC) {
+                    if let Some(Ok(name)) = strtab.get(sym.st_name) {
+                        functions.push((sym.st_value, name.to_owned()));
+                    }
+                }
+            }
+            functions.sort_by_key(|&(addr, _)| addr);
+
+            let mut sections = HashMap::new();
+            for sh in &elf.section_headers {
+                let start = sh.sh_offset as usize;
+                let end = match start.checked_add(sh.sh_size as usize) {
+                    Some(end) if sh.sh_type != SHT_NOBITS && end <= data.len() => end,
+                    _ => continue,
+                };
+                if let Some(Ok(name)) = elf.shdr_strtab.get(sh.sh_name) {
+                    sections
+                        .entry(name.to_owned())
+                        .or_insert((start..end, sh.sh_addr));
+                }
+            }
+            (elf.program_headers.clone(), functions, sections)
+        };
+        Some(ElfFile {
+            data,
+            program_headers,
+            functions,
+            sections,
+        })
+    }
+
+    /// The virtual address file offset `offset` is loaded at.
+    pub fn offset_to_vaddr(&self, offset: u64) -> Option<u64> {
+        self.program_headers
+            .iter()
+            .find(|ph| {
+                ph.p_type == PT_LOAD && ph.p_offset <= offset && offset < ph.p_offset + ph.p_filesz
+            })
+            .map(|ph| offset - ph.p_offset + ph.p_vaddr)
+    }
+
+    /// The contents and address of the section `name`.
+    pub fn section_data(&self, name: &str) -> Option<(&[u8], u64)> {
+        let (range, addr) = self.sections.get(name)?;
+        Some((&self.data[range.clone()], *addr))
+    }
+
+    /// The function symbol at or before `vaddr`, as (address, name).
+    fn nearest_function(&self, vaddr: u64) -> Option<&(u64, String)> {
+        let end = self.functions.partition_point(|&(addr, _)| addr <= vaddr);
+        self.functions[..end].last()
+    }
+}
+
+/// ELF files by path, each read and parsed the first time it's needed, so a
+/// backtrace through one library doesn't parse it again for every frame.
+#[derive(Default)]
+pub struct ElfFiles {
+    files: HashMap<String, Option<ElfFile>>,
+}
+
+impl ElfFiles {
+    /// The file `file_name`, or `None` if it can't be read or isn't ELF.
+    pub fn get(&mut self, file_name: &str) -> Option<&ElfFile> {
+        self.files
+            .entry(file_name.to_owned())
+            .or_insert_with(|| ElfFile::parse(fs::read(file_name).ok()?))
+            .as_ref()
+    }
+}
+
+/// The function symbol at or before `ip` in the ELF file mapped there, as
+/// `name+offset (file)`, or `file+offset` if there's no symbol.
+pub fn nearest_symbol(files: &mut ElfFiles, t: &dyn Task, ip: RemoteCodePtr) -> Option<String> {
+    let (file_name, offset) = file_offset(t, ip)?;
+    let symbol = files.get(&file_name).and_then(|elf| {
+        let vaddr = elf.offset_to_vaddr(offset)?;
+        let (addr, name) = elf.nearest_function(vaddr)?;
+        Some(format!("{}+{:#x} ({})", name, vaddr - addr, file_name))
+    });
+    Some(symbol.unwrap_or_else(|| format!("{}+{:#x}", file_name, offset)))
+}
+
+/// The functions inlined at `ip` in `t`, innermost first, according to the
+/// DWARF debug info of the file mapped there. Empty if there's no debug
+/// info (or nothing is inlined there).
+pub fn inlined_calls(files: &mut ElfFiles, t: &dyn Task, ip: RemoteCodePtr) -> Vec<InlinedCall> {
+    let (file_name, offset) = match file_offset(t, ip) {
+        Some(location) => location,
+        None => return Vec::new(),
+    };
+    let elf = match files.get(&file_name) {
+        Some(elf) => elf,
+        None => return Vec::new(),
+    };
+    let vaddr = match elf.offset_to_vaddr(offset) {
+        Some(vaddr) => vaddr,
+        None => return Vec::new(),
+    };
+    let load = |id: SectionId| {
+        let section = elf.section_data(id.name()).map_or(&[][..], |(d, _)| d);
+        Ok::<_, gimli::Error>(EndianSlice::new(section, LittleEndian))
+    };
+    let no_sup = |_| Ok(EndianSlice::new(&[][..], LittleEndian));
+    let dwarf = match Dwarf::load(load, no_sup) {
+        Ok(dwarf) => dwarf,
+        Err(_) => return Vec::new(),
+    };
+    find_inlined_calls(&dwarf, vaddr).unwrap_or_default()
+}
+
+fn find_inlined_calls<R: Reader>(dwarf: &Dwarf<R>, vaddr: u64) -> gimli::Result<Vec<InlinedCall>> {
+    let mut headers = dwarf.units();
+    while let Some(header) = headers.next()? {
+        let unit = dwarf.unit(header)?;
+        if !ranges_contain(dwarf.unit_ranges(&unit)?, vaddr)? {
+            continue;
+        }
+        let mut tree = unit.entries_tree(None)?;
+        let mut calls = Vec::new();
+        collect_inlined_calls(dwarf, &unit, tree.root()?, vaddr, &mut calls)?;
+        calls.reverse();
+        return Ok(calls);
+    }
+    Ok(Vec::new())
+}
+
+fn ranges_contain<R: Reader>(mut ranges: RangeIter<R>, vaddr: u64) -> gimli::Result<bool> {
+    while let Some(range) = ranges.next()? {
+        if range.begin <= vaddr && vaddr < range.end {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Walk down from `node` through the function, inlined call and block
+/// entries containing `vaddr`, appending the inlined calls to `calls`
+/// outermost first. Returns whether the function containing `vaddr` was
+/// found.
+fn collect_inlined_calls<R: Reader>(
+    dwarf: &Dwarf<R>,
+    unit: &Unit<R>,
+    node: EntriesTreeNode<'_, '_, '_, R>,
+    vaddr: u64,
+    calls: &mut Vec<InlinedCall>,
+) -> gimli::Result<bool> {
+    let mut children = node.children();
+    while let Some(child) = children.next()? {
+        let tag = child.entry().tag();
+        if tag == DW_TAG_namespace {
+            if collect_inlined_calls(dwarf, unit, child, vaddr, calls)? {
+                return Ok(true);
+            }
+        } else if tag == DW_TAG_subprogram
+            || tag == DW_TAG_inlined_subroutine
+            || tag == DW_TAG_lexical_block
+        {
+            if !ranges_contain(dwarf.die_ranges(unit, child.entry())?, vaddr)? {
+                continue;
+            }
+            if tag == DW_TAG_inlined_subroutine {
+                let entry = child.entry();
+                calls.push(InlinedCall {
+                    name: entry_name(dwarf, unit, entry)?.unwrap_or_else(|| "??".into()),
+                    call_line: match entry.attr_value(DW_AT_call_line)? {
+                        Some(AttributeValue::Udata(line)) => Some(line),
+                        _ => None,
+                    },
+                });
+            }
+            collect_inlined_calls(dwarf, unit, child, vaddr, calls)?;
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// The name of a function entry, following references from inlined calls
+/// and out-of-line definitions to the declaration.
+fn entry_name<R: Reader>(
+    dwarf: &Dwarf<R>,
+    unit: &Unit<R>,
+    entry: &DebuggingInformationEntry<'_, '_, R>,
+) -> gimli::Result<Option<String>> {
+    for &name_attr in &[DW_AT_name, DW_AT_linkage_name] {
+        if let Some(name) = entry.attr_value(name_attr)? {
+            let name = dwarf.attr_string(unit, name)?;
+            return Ok(Some(name.to_string_lossy()?.into_owned()));
+        }
+    }
+    for &ref_attr in &[DW_AT_abstract_origin, DW_AT_specification] {
+        if let Some(AttributeValue::UnitRef(offset)) = entry.attr_value(ref_attr)? {
+            return entry_name(dwarf, unit, &unit.entry(offset)?);
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env::current_exe;
+
+    #[test]
+    fn nearest_function() {
+        let elf = ElfFile {
+            data: Vec::new(),
+            program_headers: Vec::new(),
+            functions: vec![
+                (0x1000, "dynamic".into()),
+                (0x1000, "static".into()),
+                (0x2000, "next".into()),
+            ],
+            sections: HashMap::new(),
+        };
+        assert_eq!(elf.nearest_function(0xfff), None);
+        assert_eq!(elf.nearest_function(0x1000).unwrap().1, "static");
+        assert_eq!(elf.nearest_function(0x1fff).unwrap().1, "static");
+        assert_eq!(elf.nearest_function(0x2000).unwrap().1, "next");
+    }
+
+    #[test]
+    fn elf_files() {
+        let mut files = ElfFiles::default();
+        assert!(files.get("/nonexistent/libgone.so").is_none());
+        let exe = current_exe().unwrap();
+        let elf = files.get(exe.to_str().unwrap()).unwrap();
+        let (text, text_addr) = elf.section_data(".text").unwrap();
+        assert!(!text.is_empty());
+        let text_end = text_addr + text.len() as u64;
+        assert!(elf.nearest_function(text_end - 1).is_some());
+    }
+}
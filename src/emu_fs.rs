@@ -33,8 +33,13 @@
 
 use crate::{
     log::{LogDebug, LogError},
+    memfd::MFD_EXEC,
     scoped_fd::ScopedFd,
-    session::address_space::kernel_mapping::KernelMapping,
+    session::{
+        address_space::kernel_mapping::KernelMapping,
+        session_inner::session_inner::SessionInner,
+    },
+    temp_resources::{create_unlinked_file, process_dir},
     util::resize_shmem_segment,
 };
 use libc::{c_void, dev_t, ino_t, pread64, pwrite64};
@@ -410,7 +415,11 @@ fn create_memfd_file(
     name.truncate(255);
 
     let cname = CString::new(name.clone()).unwrap();
-    let result = memfd_create(&cname, MemFdCreateFlag::empty());
+    // The tracee may have mapped the original executable. Kernels before 6.3
+    // don't know MFD_EXEC, and their memfds are executable anyway.
+    let exec = unsafe { MemFdCreateFlag::from_bits_unchecked(MFD_EXEC) };
+    let result =
+        memfd_create(&cname, exec).or_else(|_| memfd_create(&cname, MemFdCreateFlag::empty()));
     if result.is_ok() {
         Some((
             ScopedFd::from_raw(result.unwrap()),
@@ -422,10 +431,42 @@ fn create_memfd_file(
 }
 
 /// Used only when memfd_create is not available, i.e. Linux < 3.17
+///
+/// The file never has a name on disk, or is unlinked as soon as it's open;
+/// tracees map it through `proc_path()`, so it goes away with the last
+/// mapping or with us.
 fn create_tmpfs_file(
-    _orig_path: &OsStr,
-    _orig_device: dev_t,
-    _orig_inode: ino_t,
+    orig_path: &OsStr,
+    orig_device: dev_t,
+    orig_inode: ino_t,
 ) -> Option<(ScopedFd, OsString)> {
-    unimplemented!()
+    let dir = process_dir();
+    let mut name: Vec<u8> = Vec::new();
+    // Skip the leading '/'.
+    name.extend_from_slice(&SessionInner::rd_mapping_prefix().as_bytes()[1..]);
+    write!(
+        name,
+        "emufs-{}-dev-{}-inode-{}-",
+        getpid(),
+        orig_device,
+        orig_inode
+    )
+    .unwrap();
+    // Path separators in the original name would point somewhere else.
+    name.extend(
+        orig_path
+            .as_bytes()
+            .iter()
+            .map(|&c| if c == b'/' { b'_' } else { c }),
+    );
+    name.truncate(255);
+
+    let fd = create_unlinked_file(&dir, OsStr::from_bytes(&name));
+    if !fd.is_open() {
+        return None;
+    }
+    let mut path = dir;
+    path.push("/");
+    path.push(OsStr::from_bytes(&name));
+    Some((fd, path))
 }
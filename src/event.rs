@@ -5,6 +5,7 @@ use crate::{
         EvExit,
         EvGrowMap,
         EvInstructionTrap,
+        EvInterceptedSyscall,
         EvNoop,
         EvPatchSyscall,
         EvSched,
@@ -19,10 +20,9 @@ use crate::{
     },
     kernel_abi::{
         common::preload_interface::{mprotect_record, syscallbuf_record},
-        is_execve_syscall,
         SupportedArch,
     },
-    kernel_metadata::{is_sigreturn, signal_name, syscall_name},
+    kernel_metadata::{is_exec, is_sigreturn, signal_name, syscall_name},
     log::LogLevel::LogInfo,
     registers::Registers,
     remote_ptr::RemotePtr,
@@ -101,6 +101,9 @@ pub enum EventType {
     EvSignalHandler,
     /// Use .syscall_event.
     EvSyscall,
+    /// A syscall rd answered through a seccomp user notification, without a
+    /// ptrace stop. Use .intercepted_syscall_event.
+    EvInterceptedSyscall,
 }
 
 /// Desched events track the fact that a tracee's desched-event
@@ -137,6 +140,17 @@ impl Default for SyscallbufFlushEventData {
     }
 }
 
+/// The task was blocked at the entry of the syscall while rd answered it, so
+/// there are no registers to record; replay runs to the syscall, skips it and
+/// sets `result`.
+#[derive(Copy, Clone)]
+pub struct InterceptedSyscallEventData {
+    pub arch: SupportedArch,
+    pub number: i32,
+    /// What the syscall returned, or -errno.
+    pub result: i64,
+}
+
 #[derive(Copy, Clone, Eq, PartialEq)]
 pub enum SignalDeterministic {
     NondeterministicSig = 0,
@@ -183,9 +197,10 @@ impl SignalEventData {
 /// interruptions from the original, normal syscalls.
 ///
 /// Normal system calls (interrupted or not) record two events: EnteringSyscall
-/// and ExitingSyscall. If the process exits before the syscall exit (because
-/// this is an exit/exit_group syscall or the process gets SIGKILL), there's no
-/// syscall exit event.
+/// and ExitingSyscall. If the process exits before the syscall exit because
+/// this is an exit/exit_group syscall, there's no syscall exit event. If it
+/// dies in the middle of some other syscall, e.g. from a SIGKILL, the exit
+/// event is synthesized, see `RecordSession::record_unexpected_task_exit()`.
 ///
 /// When PTRACE_SYSCALL is used, there will be three events:
 /// EnteringSyscallPtrace to run the process until it gets into the kernel,
@@ -258,6 +273,9 @@ pub struct SyscallEventData {
     pub write_offset: Option<u64>,
     pub exec_fds_to_close: Vec<i32>,
     pub opened: Vec<OpenedFd>,
+    /// For a recvmsg() that got fds with SCM_RIGHTS, which of the batches
+    /// in flight they came from, see `scm_rights`.
+    pub scm_rights_batch: Option<usize>,
 
     pub state: SyscallState,
     /// Syscall number.
@@ -290,6 +308,7 @@ impl SyscallEventData {
             failed_during_preparation: false,
             in_sysemu: false,
             opened: vec![],
+            scm_rights_batch: None,
         }
     }
 
@@ -313,6 +332,7 @@ pub enum EventExtraData {
     SignalEvent(SignalEventData),
     SyscallEvent(SyscallEventData),
     SyscallbufFlushEvent(SyscallbufFlushEventData),
+    InterceptedSyscallEvent(InterceptedSyscallEventData),
 }
 
 #[derive(Clone)]
@@ -370,6 +390,7 @@ impl Display for EventType {
             EventType::EvSignalDelivery => "SIGNAL_DELIVERY",
             EventType::EvSignalHandler => "SIGNAL_HANDLER",
             EventType::EvSyscall => "SYSCALL",
+            EventType::EvInterceptedSyscall => "INTERCEPTED_SYSCALL",
         };
 
         write!(f, "{}", disp)
@@ -405,6 +426,13 @@ impl Event {
         }
     }
 
+    pub fn new_intercepted_syscall_event(ev: InterceptedSyscallEventData) -> Event {
+        Event {
+            event_type: EvInterceptedSyscall,
+            event_extra_data: EventExtraData::InterceptedSyscallEvent(ev),
+        }
+    }
+
     pub fn new_syscall_interruption_event(ev: SyscallEventData) -> Event {
         Event {
             event_type: EvSyscallInterruption,
@@ -446,7 +474,7 @@ impl Event {
                 // sigreturn/rt_sigreturn restores register state
                 sys_ev.state == SyscallState::ExitingSyscall
                     && (is_sigreturn(sys_ev.number, sys_ev.arch())
-                        || is_execve_syscall(sys_ev.number, sys_ev.arch()))
+                        || is_exec(sys_ev.number, sys_ev.arch()))
             }
             EventType::EvSignalHandler => {
                 // entering a signal handler seems to clear FP/SSE regs,
@@ -505,6 +533,10 @@ impl Event {
                 )
                 .unwrap_or(());
             }
+            EventType::EvInterceptedSyscall => {
+                let e = self.intercepted_syscall_event();
+                write!(ss, ": {} = {}", syscall_name(e.number, e.arch), e.result).unwrap_or(());
+            }
             _ => {
                 // No auxiliary information.
             }
@@ -621,6 +653,13 @@ impl Event {
             _ => panic!("Not a syscallbuf flush event"),
         }
     }
+    pub fn intercepted_syscall_event(&self) -> &InterceptedSyscallEventData {
+        match &self.event_extra_data {
+            EventExtraData::InterceptedSyscallEvent(ev) => ev,
+            _ => panic!("Not an intercepted syscall event"),
+        }
+    }
+
     pub fn signal_event(&self) -> &SignalEventData {
         match &self.event_extra_data {
             EventExtraData::SignalEvent(ev) => ev,
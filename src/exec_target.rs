@@ -0,0 +1,233 @@
+//! Working out what an execve()/execveat() is actually going to run.
+//!
+//! The path a tracee passes to exec is not always the image that ends up
+//! mapped into the new address space:
+//! - execveat() resolves a relative path against a directory fd, and with
+//!   `AT_EMPTY_PATH` executes the fd itself (this is how glibc implements
+//!   fexecve()).
+//! - If the file starts with `#!` the kernel runs the interpreter named on
+//!   that line instead, rewriting argv as it goes. Interpreters can
+//!   themselves be scripts, up to a small nesting limit.
+//!
+//! We compute all of this at syscall entry (while the tracee's fd table and
+//! cwd still reflect what the kernel will see) so that, when the exec
+//! completes, we can record the right file name, command line and
+//! executable image.
+
+use crate::log::LogLevel::LogDebug;
+use libc::{pid_t, AT_EMPTY_PATH, AT_FDCWD};
+use std::{
+    ffi::{OsStr, OsString},
+    fs::{read_link, File},
+    io::Read,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+};
+
+/// The kernel only looks at this many bytes of the first line of a script.
+/// See BINPRM_BUF_SIZE in the kernel.
+pub const BINPRM_BUF_SIZE: usize = 256;
+
+/// The kernel gives up with ELOOP after this many nested interpreters.
+/// See the `depth` check in fs/exec.c.
+pub const BINPRM_MAX_RECURSION: usize = 4;
+
+/// The interpreter and optional argument from a `#!` line.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Shebang {
+    pub interpreter: OsString,
+    pub arg: Option<OsString>,
+}
+
+/// What an exec is expected to run, computed at syscall entry.
+#[derive(Clone, Debug)]
+pub struct ExecTarget {
+    /// The file the tracee asked to execute, made absolute where possible.
+    pub file_name: OsString,
+    /// The `#!` lines followed to get to the binary image, outermost first.
+    /// Empty if `file_name` is itself a binary.
+    pub interpreters: Vec<Shebang>,
+    /// The binary the kernel will end up loading.
+    pub exe_image: OsString,
+}
+
+impl ExecTarget {
+    pub fn is_script(&self) -> bool {
+        !self.interpreters.is_empty()
+    }
+}
+
+/// Parse the `#!` line in `buf` (the first bytes of a file) the way
+/// fs/binfmt_script.c does. Returns None if the file is not a script or
+/// the line names no interpreter.
+pub fn parse_shebang(buf: &[u8]) -> Option<Shebang> {
+    if buf.len() < 2 || &buf[0..2] != b"#!" {
+        return None;
+    }
+    let limit = buf.len().min(BINPRM_BUF_SIZE);
+    let line = &buf[2..limit];
+    let line = match line.iter().position(|&c| c == b'\n') {
+        Some(pos) => &line[0..pos],
+        None => line,
+    };
+
+    let is_blank = |c: &u8| *c == b' ' || *c == b'\t';
+    let start = line.iter().position(|c| !is_blank(c))?;
+    let end = line.iter().rposition(|c| !is_blank(c)).unwrap() + 1;
+    let line = &line[start..end];
+
+    let interp_end = line.iter().position(is_blank).unwrap_or(line.len());
+    let interpreter = OsStr::from_bytes(&line[0..interp_end]).to_owned();
+    // Everything after the interpreter (minus leading whitespace) is passed
+    // as a *single* argument, including any embedded whitespace.
+    let arg = line[interp_end..]
+        .iter()
+        .position(|c| !is_blank(c))
+        .map(|pos| OsStr::from_bytes(&line[interp_end + pos..]).to_owned());
+
+    Some(Shebang { interpreter, arg })
+}
+
+/// The argv the kernel constructs when running a script via `shebang`.
+/// argv[0] of the original command line is dropped and replaced by the
+/// interpreter, its optional argument and the path of the script.
+pub fn script_argv(shebang: &Shebang, script: &OsStr, argv: &[OsString]) -> Vec<OsString> {
+    let mut result = vec![shebang.interpreter.clone()];
+    if let Some(arg) = &shebang.arg {
+        result.push(arg.clone());
+    }
+    result.push(script.to_owned());
+    result.extend(argv.iter().skip(1).cloned());
+    result
+}
+
+/// Resolve the file an exec in task `tid` will open. `dirfd`, `path` and
+/// `flags` are the execveat() parameters; for a plain execve() pass
+/// `AT_FDCWD` and 0.
+pub fn resolve_exec_path(tid: pid_t, dirfd: i32, path: &OsStr, flags: i32) -> OsString {
+    if path.is_empty() && flags & AT_EMPTY_PATH != 0 {
+        // fexecve()-style: execute whatever `dirfd` refers to.
+        return proc_link_or(tid, &format!("fd/{}", dirfd), path);
+    }
+    if path.as_bytes().first() == Some(&b'/') {
+        return path.to_owned();
+    }
+    let base = if dirfd == AT_FDCWD {
+        proc_link_or(tid, "cwd", OsStr::new("."))
+    } else {
+        proc_link_or(tid, &format!("fd/{}", dirfd), OsStr::new("."))
+    };
+    let mut resolved = base.into_vec();
+    if resolved.last() != Some(&b'/') {
+        resolved.push(b'/');
+    }
+    resolved.extend_from_slice(path.as_bytes());
+    OsString::from_vec(resolved)
+}
+
+/// Compute what an exec in task `tid` is going to run. See
+/// `resolve_exec_path()` for the meaning of the parameters.
+///
+/// The file is opened through /proc/<tid>/fd when executing an fd so that
+/// deleted and memfd backed files work too.
+pub fn exec_target(tid: pid_t, dirfd: i32, path: &OsStr, flags: i32) -> ExecTarget {
+    let file_name = resolve_exec_path(tid, dirfd, path, flags);
+    let first_open = if path.is_empty() && flags & AT_EMPTY_PATH != 0 {
+        OsString::from(format!("/proc/{}/fd/{}", tid, dirfd))
+    } else {
+        file_name.clone()
+    };
+
+    let mut interpreters = Vec::new();
+    let mut exe_image = file_name.clone();
+    let mut to_open = first_open;
+    while interpreters.len() < BINPRM_MAX_RECURSION {
+        let mut buf = [0u8; BINPRM_BUF_SIZE];
+        let nread = match File::open(&to_open).and_then(|mut f| f.read(&mut buf)) {
+            Ok(nread) => nread,
+            // The exec is going to fail (or we can't see the file from here).
+            // Either way there is nothing more to learn.
+            Err(_) => break,
+        };
+        match parse_shebang(&buf[0..nread]) {
+            Some(shebang) => {
+                log!(
+                    LogDebug,
+                    "{:?} is a script run by {:?}",
+                    to_open,
+                    shebang.interpreter
+                );
+                // Interpreters are looked up relative to the cwd of the
+                // exec'ing task, just like a plain execve().
+                let interpreter =
+                    resolve_exec_path(tid, AT_FDCWD, &shebang.interpreter, 0);
+                exe_image = interpreter.clone();
+                to_open = interpreter;
+                interpreters.push(shebang);
+            }
+            None => break,
+        }
+    }
+
+    ExecTarget {
+        file_name,
+        interpreters,
+        exe_image,
+    }
+}
+
+fn proc_link_or(tid: pid_t, link: &str, default: &OsStr) -> OsString {
+    match read_link(format!("/proc/{}/{}", tid, link)) {
+        Ok(target) => target.into_os_string(),
+        Err(_) => default.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn not_a_script() {
+        assert_eq!(parse_shebang(b"\x7fELF\x02\x01\x01"), None);
+        assert_eq!(parse_shebang(b"#"), None);
+        assert_eq!(parse_shebang(b"#!   \n/bin/sh"), None);
+    }
+
+    #[test]
+    fn simple_shebang() {
+        assert_eq!(
+            parse_shebang(b"#!/bin/sh\necho hi\n"),
+            Some(Shebang {
+                interpreter: "/bin/sh".into(),
+                arg: None
+            })
+        );
+        assert_eq!(
+            parse_shebang(b"#! /usr/bin/env  python3 -u  \nimport os"),
+            Some(Shebang {
+                interpreter: "/usr/bin/env".into(),
+                arg: Some("python3 -u".into())
+            })
+        );
+    }
+
+    #[test]
+    fn script_argv_rewrite() {
+        let shebang = Shebang {
+            interpreter: "/bin/bash".into(),
+            arg: Some("-e".into()),
+        };
+        let argv: Vec<OsString> = vec!["./run.sh".into(), "a".into(), "b".into()];
+        let expected: Vec<OsString> = vec![
+            "/bin/bash".into(),
+            "-e".into(),
+            "/tmp/run.sh".into(),
+            "a".into(),
+            "b".into(),
+        ];
+        assert_eq!(
+            script_argv(&shebang, OsStr::new("/tmp/run.sh"), &argv),
+            expected
+        );
+    }
+}
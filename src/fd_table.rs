@@ -1,6 +1,14 @@
 use crate::{
+    capture_reads::matches_any,
     event::Switchable,
-    file_monitor::{FileMonitor, FileMonitorSharedPtr, LazyOffset, Range},
+    file_monitor::{
+        capture_reads_monitor::CaptureReadsMonitor,
+        preserve_file_monitor::PreserveFileMonitor,
+        FileMonitor,
+        FileMonitorSharedPtr,
+        LazyOffset,
+        Range,
+    },
     kernel_abi::common::preload_interface::{preload_globals, SYSCALLBUF_FDS_DISABLED_SIZE},
     log::LogLevel::LogDebug,
     remote_ptr::RemotePtr,
@@ -16,10 +24,12 @@ use crate::{
     taskish_uid::AddressSpaceUid,
     weak_ptr_set::WeakPtrSet,
 };
+use libc::pid_t;
 use nix::sys::stat::lstat;
 use std::{
     cell::{Ref, RefCell, RefMut},
     collections::{HashMap, HashSet},
+    ffi::{OsStr, OsString},
     rc::{Rc, Weak},
 };
 
@@ -28,12 +38,30 @@ pub type FdTableRefMut<'a> = RefMut<'a, FdTable>;
 pub type FdTableSharedPtr = Rc<RefCell<FdTable>>;
 pub type FdTableSharedWeakPtr = Weak<RefCell<FdTable>>;
 
+/// The fds rd keeps open in tracees for its own use.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ReservedFd {
+    /// Initially RD_RESERVED_ROOT_DIR_FD.
+    RootDir,
+    /// The socket tracees pass fds to rd through. Initially the session's
+    /// tracee fd number.
+    TraceeSocket,
+    /// The desched counter of the task with this rec_tid.
+    DeschedEvent(pid_t),
+    /// The cloned file data file of the task with this rec_tid.
+    ClonedFileData(pid_t),
+}
+
 #[derive(Clone)]
 pub struct FdTable {
     tasks: WeakPtrSet<Box<dyn Task>>,
     fds: HashMap<i32, FileMonitorSharedPtr>,
     /// Number of elements of `fds` that are >= SYSCALLBUF_FDS_DISABLED_SIZE
     fd_count_beyond_limit: u32,
+    /// Where rd's own fds currently are. All of these are also in `fds`.
+    reserved_fds: HashMap<i32, ReservedFd>,
+    /// How often a tracee tried to close or overwrite one of `reserved_fds`.
+    reserved_fd_conflicts: u32,
 }
 
 /// We DO NOT want Copy or Clone traits
@@ -63,6 +91,76 @@ impl FdTable {
         self.fds.insert(fd, Rc::new(RefCell::new(monitor)));
         self.update_syscallbuf_fds_disabled(fd, t);
     }
+
+    /// `fd` was just opened on `path`. If that matches one of the
+    /// `--capture-reads` `patterns`, monitor it so its reads are recorded in
+    /// full. Returns whether it did.
+    pub fn capture_reads_if_matching(
+        &mut self,
+        t: &mut dyn Task,
+        fd: i32,
+        path: &OsStr,
+        patterns: &[OsString],
+    ) -> bool {
+        if self.is_monitoring(fd) || !matches_any(patterns, path) {
+            return false;
+        }
+        log!(LogDebug, "Capturing reads of {:?} at fd {}", path, fd);
+        self.add_monitor(t, fd, Box::new(CaptureReadsMonitor::new()));
+        true
+    }
+
+    /// Note that `fd` is rd's fd `kind`, which the tracee doesn't know about.
+    pub fn reserve_fd(&mut self, t: &mut dyn Task, fd: i32, kind: ReservedFd) {
+        if !self.is_monitoring(fd) {
+            self.add_monitor(t, fd, Box::new(PreserveFileMonitor::new()));
+        }
+        self.reserved_fds.insert(fd, kind);
+    }
+
+    /// Where rd's fd `kind` currently is, if it's open.
+    pub fn reserved_fd(&self, kind: ReservedFd) -> Option<i32> {
+        self.reserved_fds
+            .iter()
+            .find(|&(_, &k)| k == kind)
+            .map(|(&fd, _)| fd)
+    }
+
+    /// rd's fds in [first, last], lowest first.
+    pub fn reserved_fds_in_range(&self, first: u32, last: u32) -> Vec<i32> {
+        let mut fds: Vec<i32> = self
+            .reserved_fds
+            .keys()
+            .copied()
+            .filter(|&fd| fd as u32 >= first && fd as u32 <= last)
+            .collect();
+        fds.sort_unstable();
+        fds
+    }
+
+    /// Which of rd's fds `fd` is, if any.
+    pub fn reserved_fd_kind(&self, fd: i32) -> Option<ReservedFd> {
+        self.reserved_fds.get(&fd).copied()
+    }
+
+    /// Rd moved its reserved fd `from` to `to` because a tracee wanted to use
+    /// the number.
+    /// DIFF NOTE: Additional param `active_task` to solve borrow issues.
+    pub fn did_move_reserved_fd(&mut self, from: i32, to: i32, active_task: &mut dyn Task) {
+        let maybe_kind = self.reserved_fd_kind(from);
+        self.did_dup(from, to, active_task);
+        self.did_close(from, active_task);
+        if let Some(kind) = maybe_kind {
+            self.reserved_fds.insert(to, kind);
+        }
+        self.reserved_fd_conflicts += 1;
+    }
+
+    /// How many times rd had to move one of its fds out of a tracee's way.
+    pub fn reserved_fd_conflicts(&self) -> u32 {
+        self.reserved_fd_conflicts
+    }
+
     pub fn emulate_ioctl(&self, fd: i32, t: &RecordTask, result: &mut u64) -> bool {
         match self.fds.get(&fd) {
             Some(f) => f.borrow_mut().emulate_ioctl(t, result),
@@ -127,9 +225,37 @@ impl FdTable {
             }
             self.fds.remove(&to);
         }
+        self.reserved_fds.remove(&to);
         self.update_syscallbuf_fds_disabled(to, active_task);
     }
 
+    /// `fd` is a new fd for a file another fd table has open, e.g. from
+    /// pidfd_getfd(). It shares `monitor`, the other table's monitor for the
+    /// file, if there is one.
+    pub fn did_import(
+        &mut self,
+        fd: i32,
+        monitor: Option<FileMonitorSharedPtr>,
+        active_task: &mut dyn Task,
+    ) {
+        match monitor {
+            Some(monitor) => {
+                if fd >= SYSCALLBUF_FDS_DISABLED_SIZE && !self.fds.contains_key(&fd) {
+                    self.fd_count_beyond_limit += 1;
+                }
+                self.fds.insert(fd, monitor);
+            }
+            None => {
+                if fd >= SYSCALLBUF_FDS_DISABLED_SIZE && self.fds.contains_key(&fd) {
+                    self.fd_count_beyond_limit -= 1;
+                }
+                self.fds.remove(&fd);
+            }
+        }
+        self.reserved_fds.remove(&fd);
+        self.update_syscallbuf_fds_disabled(fd, active_task);
+    }
+
     /// DIFF NOTE: Additional param `active_task` to solve borrow issues.
     pub fn did_close(&mut self, fd: i32, active_task: &mut dyn Task) {
         log!(LogDebug, "Close fd {}", fd);
@@ -137,15 +263,36 @@ impl FdTable {
             self.fd_count_beyond_limit -= 1;
         }
         self.fds.remove(&fd);
+        self.reserved_fds.remove(&fd);
         self.update_syscallbuf_fds_disabled(fd, active_task);
     }
 
+    /// The fds in [first, last] were closed by close_range(). rd's own fds
+    /// survive it: recording splits the syscall around them (see
+    /// `prepare_close_range()`).
+    /// DIFF NOTE: Additional param `active_task` to solve borrow issues.
+    pub fn did_close_range(&mut self, first: u32, last: u32, active_task: &mut dyn Task) {
+        let fds: Vec<i32> = self
+            .fds
+            .keys()
+            .copied()
+            .filter(|&fd| {
+                fd as u32 >= first && fd as u32 <= last && !self.reserved_fds.contains_key(&fd)
+            })
+            .collect();
+        for fd in fds {
+            self.did_close(fd, active_task);
+        }
+    }
+
     /// Method is called clone() in rr
     pub fn clone_into_task(&self, t: &mut dyn Task) -> FdTableSharedPtr {
         let mut file_mon = FdTable {
             tasks: WeakPtrSet::new(),
             fds: self.fds.clone(),
             fd_count_beyond_limit: self.fd_count_beyond_limit,
+            reserved_fds: self.reserved_fds.clone(),
+            reserved_fd_conflicts: 0,
         };
 
         file_mon.tasks.insert(t.weak_self_ptr());
@@ -157,6 +304,8 @@ impl FdTable {
             tasks: WeakPtrSet::new(),
             fds: Default::default(),
             fd_count_beyond_limit: 0,
+            reserved_fds: Default::default(),
+            reserved_fd_conflicts: 0,
         };
 
         file_mon.tasks.insert(wt);
@@ -248,6 +397,8 @@ impl FdTable {
             tasks: Default::default(),
             fds: Default::default(),
             fd_count_beyond_limit: 0,
+            reserved_fds: Default::default(),
+            reserved_fd_conflicts: 0,
         }
     }
 
@@ -317,3 +468,26 @@ fn is_fd_monitored_in_any_task(vm: &AddressSpace, fd: i32) -> bool {
     }
     false
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reserved_fds() {
+        let mut fds = FdTable::new();
+        fds.reserved_fds.insert(1000, ReservedFd::RootDir);
+        fds.reserved_fds.insert(1001, ReservedFd::TraceeSocket);
+        fds.reserved_fds.insert(100, ReservedFd::DeschedEvent(7));
+
+        assert_eq!(fds.reserved_fd(ReservedFd::RootDir), Some(1000));
+        assert_eq!(fds.reserved_fd(ReservedFd::DeschedEvent(7)), Some(100));
+        assert_eq!(fds.reserved_fd(ReservedFd::DeschedEvent(8)), None);
+        assert_eq!(fds.reserved_fd_kind(1001), Some(ReservedFd::TraceeSocket));
+        assert_eq!(fds.reserved_fd_kind(3), None);
+
+        assert_eq!(fds.reserved_fds_in_range(3, !0), vec![100, 1000, 1001]);
+        assert_eq!(fds.reserved_fds_in_range(101, 1000), vec![1000]);
+        assert_eq!(fds.reserved_fds_in_range(3, 99), vec![]);
+    }
+}
@@ -6,7 +6,12 @@ use crate::{
     remote_ptr::{RemotePtr, Void},
     session::task::{record_task::record_task::RecordTask, Task},
 };
+use io_uring_monitor::IoUringMonitor;
 use mmapped_file_monitor::MmappedFileMonitor;
+use pidfd_monitor::PidFdMonitor;
+use seccomp_notify_monitor::SeccompNotifyMonitor;
+use signalfd_monitor::SignalFdMonitor;
+use timerfd_monitor::TimerfdMonitor;
 use std::{
     cell::RefCell,
     fs::File,
@@ -14,14 +19,24 @@ use std::{
     mem::size_of,
     rc::{Rc, Weak},
 };
+use virtual_perf_counter_monitor::VirtualPerfCounterMonitor;
 
 pub mod base_file_monitor;
+pub mod capture_reads_monitor;
+pub mod dir_read_monitor;
+pub mod io_uring_monitor;
+pub mod landlock_monitor;
 pub mod magic_save_data_monitor;
 pub mod mmapped_file_monitor;
+pub mod pidfd_monitor;
 pub mod preserve_file_monitor;
 pub mod proc_fd_dir_monitor;
 pub mod proc_mem_monitor;
+pub mod random_device_monitor;
+pub mod seccomp_notify_monitor;
+pub mod signalfd_monitor;
 pub mod stdio_monitor;
+pub mod timerfd_monitor;
 pub mod virtual_perf_counter_monitor;
 
 pub type FileMonitorSharedPtr = Rc<RefCell<Box<dyn FileMonitor>>>;
@@ -33,6 +48,7 @@ pub struct FileMonitorInner;
 #[derive(Copy, Clone, Eq, PartialEq)]
 pub enum FileMonitorType {
     Base,
+    DirRead,
     MagicSaveData,
     Mmapped,
     Preserve,
@@ -40,6 +56,14 @@ pub enum FileMonitorType {
     ProcMem,
     Stdio,
     VirtualPerfCounter,
+    IoUring,
+    PidFd,
+    SeccompNotify,
+    Landlock,
+    SignalFd,
+    Timerfd,
+    RandomDevice,
+    CaptureReads,
 }
 
 /// Notification that task `t` wrote to the file descriptor.
@@ -141,7 +165,7 @@ fn retrieve_offset_arch<Arch: Architecture>(
         } else {
             Some(offset as u64)
         }
-    } else if syscallno == Arch::WRITEV || syscallno == Arch::WRITE {
+    } else if syscallno == Arch::WRITEV || syscallno == Arch::WRITE || syscallno == Arch::READ {
         ed_assert!(
             t,
             t.session().is_recording(),
@@ -190,8 +214,8 @@ fn retrieve_offset_arch<Arch: Architecture>(
         }
 
         let offset = maybe_offset.unwrap();
-        // The pos we just read, was after the write completed. Luckily, we do
-        // know how many bytes were written.
+        // The pos we just read, was after the write (or read) completed.
+        // Luckily, we do know how many bytes were transferred.
         // DIFF NOTE: This is slightly different from the rr approach.
         if offset < regs.syscall_result() as u64 {
             None
@@ -223,6 +247,34 @@ pub trait FileMonitor {
         None
     }
 
+    fn as_virtual_perf_counter_monitor(&self) -> Option<&VirtualPerfCounterMonitor> {
+        None
+    }
+
+    fn as_io_uring_monitor_mut(&mut self) -> Option<&mut IoUringMonitor> {
+        None
+    }
+
+    fn as_pidfd_monitor(&self) -> Option<&PidFdMonitor> {
+        None
+    }
+
+    fn as_seccomp_notify_monitor_mut(&mut self) -> Option<&mut SeccompNotifyMonitor> {
+        None
+    }
+
+    fn as_signalfd_monitor(&self) -> Option<&SignalFdMonitor> {
+        None
+    }
+
+    fn as_signalfd_monitor_mut(&mut self) -> Option<&mut SignalFdMonitor> {
+        None
+    }
+
+    fn as_timerfd_monitor_mut(&mut self) -> Option<&mut TimerfdMonitor> {
+        None
+    }
+
     /// Overriding this to return true will cause close() (and related fd-smashing
     /// operations such as dup2) to return EBADF, and hide it from the tracee's
     /// /proc/pid/fd/
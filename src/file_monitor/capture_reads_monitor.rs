@@ -0,0 +1,21 @@
+use crate::file_monitor::{FileMonitor, FileMonitorType};
+
+/// A file matching one of the `--capture-reads` patterns, see
+/// `capture_reads`. It does nothing itself.
+///
+/// The mere existence of this monitor disables syscall buffering for the
+/// fd, so every read() of it is recorded with all its data.
+#[derive(Default)]
+pub struct CaptureReadsMonitor;
+
+impl CaptureReadsMonitor {
+    pub fn new() -> CaptureReadsMonitor {
+        CaptureReadsMonitor
+    }
+}
+
+impl FileMonitor for CaptureReadsMonitor {
+    fn file_monitor_type(&self) -> FileMonitorType {
+        FileMonitorType::CaptureReads
+    }
+}
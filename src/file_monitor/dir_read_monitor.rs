@@ -0,0 +1,32 @@
+use crate::file_monitor::{FileMonitor, FileMonitorType};
+use std::ffi::{OsStr, OsString};
+
+/// A FileMonitor for a directory fd the tracee has read entries from with
+/// getdents/getdents64.
+///
+/// The entries themselves are always recorded in full (see
+/// `record_getdents()`), so replay doesn't depend on this. It's there so rd
+/// knows which directories were listed and can note them in the trace for
+/// tools like `rd pack`. Like any monitored fd, the fd is no longer handled by
+/// the syscallbuf.
+pub struct DirReadMonitor {
+    path: OsString,
+}
+
+impl FileMonitor for DirReadMonitor {
+    fn file_monitor_type(&self) -> FileMonitorType {
+        FileMonitorType::DirRead
+    }
+}
+
+impl DirReadMonitor {
+    pub fn new(path: &OsStr) -> DirReadMonitor {
+        DirReadMonitor {
+            path: path.to_owned(),
+        }
+    }
+
+    pub fn path(&self) -> &OsStr {
+        &self.path
+    }
+}
@@ -0,0 +1,215 @@
+use crate::{
+    arch::Architecture,
+    file_monitor::{FileMonitor, FileMonitorType},
+    io_uring::{
+        io_uring_op_output,
+        ring_runs,
+        IoUringOutput,
+        IORING_CQE_F_MORE,
+        IORING_FEAT_SINGLE_MMAP,
+        IORING_OFF_CQ_RING,
+        IORING_OFF_SQES,
+        IORING_OFF_SQ_RING,
+        IOSQE_BUFFER_SELECT,
+    },
+    kernel_abi::common::{io_uring_cqe, io_uring_params, io_uring_sqe},
+    remote_ptr::{RemotePtr, Void},
+    session::task::{
+        record_task::record_task::RecordTask,
+        task_common::{read_mem, read_val_mem},
+        Task,
+    },
+};
+use std::{cmp::min, collections::HashMap, mem::size_of};
+
+/// An operation submitted to the ring that hasn't completed yet.
+#[derive(Copy, Clone)]
+struct PendingOp {
+    output: IoUringOutput,
+    addr: u64,
+    len: u32,
+}
+
+/// An io_uring fd recorded with `--io-uring=record`, see `io_uring`. Tracks
+/// where the tracee mapped the rings and which operations are in flight, so
+/// each io_uring_enter() can be recorded.
+pub struct IoUringMonitor {
+    params: io_uring_params,
+    sq_ring: RemotePtr<u8>,
+    cq_ring: RemotePtr<u8>,
+    sqes: RemotePtr<io_uring_sqe>,
+    /// The cq tail after the last io_uring_enter().
+    cq_tail: u32,
+    /// By user_data. Nothing stops programs from reusing user_data while an
+    /// operation is in flight.
+    pending: HashMap<u64, Vec<PendingOp>>,
+}
+
+impl IoUringMonitor {
+    /// `params` are what io_uring_setup() returned.
+    pub fn new(params: io_uring_params) -> IoUringMonitor {
+        IoUringMonitor {
+            params,
+            sq_ring: RemotePtr::null(),
+            cq_ring: RemotePtr::null(),
+            sqes: RemotePtr::null(),
+            cq_tail: 0,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// The tracee mapped the part of the ring at mmap() offset `offset` at
+    /// `addr`.
+    pub fn did_mmap(&mut self, offset: u64, addr: RemotePtr<Void>) {
+        match offset {
+            IORING_OFF_SQ_RING => {
+                self.sq_ring = RemotePtr::cast(addr);
+                if self.params.features & IORING_FEAT_SINGLE_MMAP != 0 {
+                    self.cq_ring = RemotePtr::cast(addr);
+                }
+            }
+            IORING_OFF_CQ_RING => self.cq_ring = RemotePtr::cast(addr),
+            IORING_OFF_SQES => self.sqes = RemotePtr::cast(addr),
+            _ => (),
+        }
+    }
+
+    /// `t` is entering an io_uring_enter() that submits up to `to_submit`
+    /// SQEs. Remember where their output will go. Returns the opcode of the
+    /// first one rd can't record, if there is one; then nothing may be
+    /// submitted.
+    pub fn will_submit(&mut self, t: &mut RecordTask, to_submit: u32) -> Result<(), u8> {
+        // Without the rings mapped there's nothing the tracee could have
+        // submitted.
+        if to_submit == 0 || self.sq_ring.is_null() || self.sqes.is_null() {
+            return Ok(());
+        }
+        let sq_off = self.params.sq_off;
+        let head = ring_u32(t, self.sq_ring, sq_off.head);
+        let tail = ring_u32(t, self.sq_ring, sq_off.tail);
+        let array = RemotePtr::<u32>::cast(self.sq_ring + sq_off.array as usize);
+        let mut submissions = Vec::new();
+        for (slot, count) in ring_runs(head, tail, self.params.sq_entries) {
+            for index in read_mem(t, array + slot, count as usize, None) {
+                // The kernel drops out of range indices.
+                if index >= self.params.sq_entries {
+                    continue;
+                }
+                let sqe: io_uring_sqe = read_val_mem(t, self.sqes + index, None);
+                match io_uring_op_output(sqe.opcode) {
+                    Some(output) if sqe.flags & IOSQE_BUFFER_SELECT == 0 => submissions.push((
+                        sqe.user_data,
+                        PendingOp {
+                            output,
+                            addr: sqe.addr,
+                            len: sqe.len,
+                        },
+                    )),
+                    _ => return Err(sqe.opcode),
+                }
+            }
+        }
+        // SQEs the kernel doesn't get to this time are seen again by the
+        // next io_uring_enter().
+        for (user_data, op) in submissions.into_iter().take(to_submit as usize) {
+            self.pending.entry(user_data).or_default().push(op);
+        }
+        Ok(())
+    }
+
+    /// `t` has completed an io_uring_enter(). Record everything the kernel
+    /// changed in the rings, and the output of the operations that
+    /// completed.
+    pub fn did_enter(&mut self, t: &mut RecordTask) {
+        if self.sq_ring.is_null() || self.cq_ring.is_null() {
+            return;
+        }
+        let sq_off = self.params.sq_off;
+        let cq_off = self.params.cq_off;
+        for &offset in &[sq_off.head, sq_off.flags, sq_off.dropped] {
+            t.record_remote(RemotePtr::cast(self.sq_ring + offset as usize), 4);
+        }
+        for &offset in &[cq_off.tail, cq_off.overflow] {
+            t.record_remote(RemotePtr::cast(self.cq_ring + offset as usize), 4);
+        }
+        let tail = ring_u32(t, self.cq_ring, cq_off.tail);
+        let cqes = RemotePtr::<io_uring_cqe>::cast(self.cq_ring + cq_off.cqes as usize);
+        for (index, count) in ring_runs(self.cq_tail, tail, self.params.cq_entries) {
+            t.record_remote(
+                RemotePtr::cast(cqes + index),
+                count as usize * size_of::<io_uring_cqe>(),
+            );
+            for cqe in read_mem(t, cqes + index, count as usize, None) {
+                self.did_complete(t, &cqe);
+            }
+        }
+        self.cq_tail = tail;
+    }
+
+    fn did_complete(&mut self, t: &mut RecordTask, cqe: &io_uring_cqe) {
+        let ops = match self.pending.get_mut(&cqe.user_data) {
+            Some(ops) => ops,
+            None => return,
+        };
+        // If the user_data is ambiguous we can't tell which buffer the
+        // kernel wrote, so record them all.
+        if cqe.res > 0 {
+            for op in ops.iter() {
+                record_output(t, op, cqe.res as usize);
+            }
+        }
+        if cqe.flags & IORING_CQE_F_MORE == 0 {
+            ops.remove(0);
+            if ops.is_empty() {
+                self.pending.remove(&cqe.user_data);
+            }
+        }
+    }
+}
+
+impl FileMonitor for IoUringMonitor {
+    fn file_monitor_type(&self) -> FileMonitorType {
+        FileMonitorType::IoUring
+    }
+
+    fn as_io_uring_monitor_mut(&mut self) -> Option<&mut IoUringMonitor> {
+        Some(self)
+    }
+}
+
+fn ring_u32(t: &mut RecordTask, ring: RemotePtr<u8>, offset: u32) -> u32 {
+    read_val_mem(t, RemotePtr::<u32>::cast(ring + offset as usize), None)
+}
+
+/// Record the `res` bytes `op` wrote.
+fn record_output(t: &mut RecordTask, op: &PendingOp, res: usize) {
+    match op.output {
+        IoUringOutput::Nothing => (),
+        IoUringOutput::Buffer => {
+            t.record_remote(RemotePtr::from(op.addr), min(res, op.len as usize));
+        }
+        IoUringOutput::Iovecs => {
+            let arch = t.arch();
+            rd_arch_function_selfless!(record_iovecs_arch, arch, t, op, res)
+        }
+    }
+}
+
+fn record_iovecs_arch<Arch: Architecture>(t: &mut RecordTask, op: &PendingOp, res: usize) {
+    let iovecs = read_mem(
+        t,
+        RemotePtr::<Arch::iovec>::from(op.addr),
+        op.len as usize,
+        None,
+    );
+    let mut left = res;
+    for v in iovecs {
+        if left == 0 {
+            break;
+        }
+        let (addr, len) = Arch::get_iovec(&v);
+        let amount = min(left, len);
+        t.record_remote(addr, amount);
+        left -= amount;
+    }
+}
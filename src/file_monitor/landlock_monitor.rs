@@ -0,0 +1,19 @@
+use crate::file_monitor::{FileMonitor, FileMonitorType};
+
+/// A Landlock ruleset, from landlock_create_ruleset(), see `landlock`.
+///
+/// The mere existence of this monitor also disables syscall buffering for
+/// the fd, so we see every syscall on it.
+pub struct LandlockMonitor;
+
+impl LandlockMonitor {
+    pub fn new() -> LandlockMonitor {
+        LandlockMonitor
+    }
+}
+
+impl FileMonitor for LandlockMonitor {
+    fn file_monitor_type(&self) -> FileMonitorType {
+        FileMonitorType::Landlock
+    }
+}
@@ -0,0 +1,33 @@
+use crate::file_monitor::{FileMonitor, FileMonitorType};
+use libc::pid_t;
+
+/// A pidfd, from pidfd_open(). Remembers the process it refers to, so that
+/// pidfd_send_signal() and pidfd_getfd() on it can be handled like their
+/// pid-based counterparts.
+///
+/// The mere existence of this monitor also disables syscall buffering for
+/// the fd, so we see every syscall on it.
+pub struct PidFdMonitor {
+    /// As the tracee knows it, i.e. the recorded pid if it's a tracee.
+    pid: pid_t,
+}
+
+impl PidFdMonitor {
+    pub fn new(pid: pid_t) -> PidFdMonitor {
+        PidFdMonitor { pid }
+    }
+
+    pub fn pid(&self) -> pid_t {
+        self.pid
+    }
+}
+
+impl FileMonitor for PidFdMonitor {
+    fn file_monitor_type(&self) -> FileMonitorType {
+        FileMonitorType::PidFd
+    }
+
+    fn as_pidfd_monitor(&self) -> Option<&PidFdMonitor> {
+        Some(self)
+    }
+}
@@ -0,0 +1,21 @@
+use crate::file_monitor::{FileMonitor, FileMonitorType};
+
+/// /dev/urandom or /dev/random, opened while recording with a random seed.
+/// Reads of it are answered from the seeded generator, see `seeded_random`.
+///
+/// The mere existence of this monitor also disables syscall buffering for
+/// the fd, so we see every read() of it.
+#[derive(Default)]
+pub struct RandomDeviceMonitor;
+
+impl RandomDeviceMonitor {
+    pub fn new() -> RandomDeviceMonitor {
+        RandomDeviceMonitor
+    }
+}
+
+impl FileMonitor for RandomDeviceMonitor {
+    fn file_monitor_type(&self) -> FileMonitorType {
+        FileMonitorType::RandomDevice
+    }
+}
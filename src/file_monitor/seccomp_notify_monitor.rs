@@ -0,0 +1,47 @@
+use crate::file_monitor::{FileMonitor, FileMonitorType};
+use libc::pid_t;
+use std::collections::HashMap;
+
+/// A seccomp user notification listener, the fd a tracee gets from
+/// installing a filter with SECCOMP_FILTER_FLAG_NEW_LISTENER. Remembers which
+/// task each notification received through it is for, so an fd the
+/// supervisor installs with SECCOMP_IOCTL_NOTIF_ADDFD can be accounted for
+/// in the right fd table.
+///
+/// The mere existence of this monitor also disables syscall buffering for
+/// the fd, so we see every ioctl on it.
+#[derive(Default)]
+pub struct SeccompNotifyMonitor {
+    /// Notification ids that haven't been answered yet, and the tid (as the
+    /// supervisor knows it) of the task whose syscall each is for.
+    pending: HashMap<u64, pid_t>,
+}
+
+impl SeccompNotifyMonitor {
+    pub fn new() -> SeccompNotifyMonitor {
+        Default::default()
+    }
+
+    pub fn did_recv(&mut self, id: u64, pid: pid_t) {
+        self.pending.insert(id, pid);
+    }
+
+    pub fn did_answer(&mut self, id: u64) {
+        self.pending.remove(&id);
+    }
+
+    /// The task whose syscall notification `id` is for.
+    pub fn target(&self, id: u64) -> Option<pid_t> {
+        self.pending.get(&id).copied()
+    }
+}
+
+impl FileMonitor for SeccompNotifyMonitor {
+    fn file_monitor_type(&self) -> FileMonitorType {
+        FileMonitorType::SeccompNotify
+    }
+
+    fn as_seccomp_notify_monitor_mut(&mut self) -> Option<&mut SeccompNotifyMonitor> {
+        Some(self)
+    }
+}
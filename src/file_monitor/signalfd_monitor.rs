@@ -0,0 +1,132 @@
+use crate::{
+    file_monitor::{FileMonitor, FileMonitorType},
+    kernel_supplement::sig_set_t,
+};
+use libc::{siginfo_t, signalfd_siginfo, SIGBUS, SIGCHLD, SIGFPE, SIGILL, SIGSEGV, SIGTRAP};
+use std::mem::zeroed;
+
+/// A signalfd, from signalfd() or signalfd4(). Remembers which signals a
+/// read() of it dequeues, so that signals rd has already taken from the
+/// kernel and stashed for the task can be read from it too.
+///
+/// The mere existence of this monitor also disables syscall buffering for
+/// the fd, so we see every read() of it.
+pub struct SignalFdMonitor {
+    mask: sig_set_t,
+}
+
+impl SignalFdMonitor {
+    pub fn new(mask: sig_set_t) -> SignalFdMonitor {
+        SignalFdMonitor { mask }
+    }
+
+    pub fn mask(&self) -> sig_set_t {
+        self.mask
+    }
+
+    /// signalfd() on an existing signalfd replaces its mask.
+    pub fn set_mask(&mut self, mask: sig_set_t) {
+        self.mask = mask;
+    }
+}
+
+impl FileMonitor for SignalFdMonitor {
+    fn file_monitor_type(&self) -> FileMonitorType {
+        FileMonitorType::SignalFd
+    }
+
+    fn as_signalfd_monitor(&self) -> Option<&SignalFdMonitor> {
+        Some(self)
+    }
+
+    fn as_signalfd_monitor_mut(&mut self) -> Option<&mut SignalFdMonitor> {
+        Some(self)
+    }
+}
+
+/// What a read() of a signalfd returns for the signal `si`. Which fields are
+/// filled in depends on the signal and how it was sent, like the kernel's
+/// signalfd_copyinfo().
+pub fn to_signalfd_siginfo(si: &siginfo_t) -> signalfd_siginfo {
+    let mut ssi: signalfd_siginfo = unsafe { zeroed() };
+    ssi.ssi_signo = si.si_signo as u32;
+    ssi.ssi_errno = si.si_errno;
+    ssi.ssi_code = si.si_code;
+    unsafe {
+        match si.si_signo {
+            SIGSEGV | SIGBUS | SIGILL | SIGFPE | SIGTRAP if si.si_code > 0 => {
+                ssi.ssi_addr = si.si_addr() as u64;
+            }
+            SIGCHLD if si.si_code > 0 => {
+                ssi.ssi_pid = si.si_pid() as u32;
+                ssi.ssi_uid = si.si_uid();
+                ssi.ssi_status = si.si_status();
+                ssi.ssi_utime = si.si_utime() as u64;
+                ssi.ssi_stime = si.si_stime() as u64;
+            }
+            _ => {
+                ssi.ssi_pid = si.si_pid() as u32;
+                ssi.ssi_uid = si.si_uid();
+                // SI_QUEUE, SI_TIMER, SI_MESGQ etc. carry a value.
+                if si.si_code < 0 {
+                    let ptr = si.si_value().sival_ptr as u64;
+                    ssi.ssi_ptr = ptr;
+                    ssi.ssi_int = ptr as i32;
+                }
+            }
+        }
+    }
+    ssi
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bindings::signal::{SI_QUEUE, SI_USER};
+    use libc::SIGUSR1;
+
+    /// The fields of `siginfo_t` for signals from kill() and sigqueue(),
+    /// which the libc crate has no setters for.
+    #[repr(C)]
+    struct SigInfoKill {
+        si_signo: i32,
+        si_errno: i32,
+        si_code: i32,
+        _pad: i32,
+        si_pid: i32,
+        si_uid: u32,
+        si_value: usize,
+    }
+
+    fn siginfo(signo: i32, code: i32, pid: i32, uid: u32, value: usize) -> siginfo_t {
+        let mut si: siginfo_t = unsafe { zeroed() };
+        let fields = SigInfoKill {
+            si_signo: signo,
+            si_errno: 0,
+            si_code: code,
+            _pad: 0,
+            si_pid: pid,
+            si_uid: uid,
+            si_value: value,
+        };
+        unsafe { (&mut si as *mut siginfo_t as *mut SigInfoKill).write(fields) };
+        si
+    }
+
+    #[test]
+    fn signalfd_siginfo_fields() {
+        let ssi = to_signalfd_siginfo(&siginfo(SIGUSR1, SI_USER, 42, 1000, 0));
+        assert_eq!(
+            (ssi.ssi_signo, ssi.ssi_code, ssi.ssi_pid, ssi.ssi_uid),
+            (SIGUSR1 as u32, SI_USER, 42, 1000)
+        );
+        assert_eq!(ssi.ssi_ptr, 0);
+
+        let ssi = to_signalfd_siginfo(&siginfo(SIGUSR1, SI_QUEUE, 42, 1000, 7));
+        assert_eq!((ssi.ssi_ptr, ssi.ssi_int), (7, 7));
+
+        // SEGV_MAPERR: the address, which shares its place with si_pid.
+        let ssi = to_signalfd_siginfo(&siginfo(SIGSEGV, 1, 0x1000, 0, 0));
+        assert_eq!((ssi.ssi_addr, ssi.ssi_pid), (0x1000, 0));
+    }
+}
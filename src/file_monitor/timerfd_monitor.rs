@@ -0,0 +1,37 @@
+use crate::file_monitor::{FileMonitor, FileMonitorType};
+
+/// A timerfd, from timerfd_create(). What a read() of it returns, the number
+/// of expirations since the last read, depends on how long things took
+/// during recording, so every read() has to be recorded.
+///
+/// The mere existence of this monitor also disables syscall buffering for
+/// the fd, so we see every syscall on it.
+#[derive(Default)]
+pub struct TimerfdMonitor {
+    /// Expirations read so far, for logging.
+    expirations: u64,
+}
+
+impl TimerfdMonitor {
+    pub fn new() -> TimerfdMonitor {
+        Default::default()
+    }
+
+    pub fn expirations(&self) -> u64 {
+        self.expirations
+    }
+
+    pub fn did_read(&mut self, count: u64) {
+        self.expirations += count;
+    }
+}
+
+impl FileMonitor for TimerfdMonitor {
+    fn file_monitor_type(&self) -> FileMonitorType {
+        FileMonitorType::Timerfd
+    }
+
+    fn as_timerfd_monitor_mut(&mut self) -> Option<&mut TimerfdMonitor> {
+        Some(self)
+    }
+}
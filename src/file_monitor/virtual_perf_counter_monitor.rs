@@ -1 +1,106 @@
-pub struct VirtualPerfCounterMonitor;
+use crate::{
+    bindings::perf_event::{
+        perf_event_attr,
+        PERF_EVENT_IOC_DISABLE,
+        PERF_EVENT_IOC_ENABLE,
+        PERF_EVENT_IOC_RESET,
+    },
+    file_monitor::{FileMonitor, FileMonitorType},
+    perf_counters::PerfCounters,
+    session::task::record_task::record_task::RecordTask,
+    taskish_uid::TaskUid,
+    ticks::Ticks,
+};
+use libc::c_ulong;
+
+/// A perf counter a tracee opened, counting the ticks of a task in the
+/// session. The tracee gets an inotify fd (just so it has an fd) and
+/// ioctl()s and read()s on it are emulated.
+///
+/// Only rd's own ticks counter in counting mode is virtualized: that's what
+/// rd running under rd opens, and ticks are the one thing we know replay
+/// reproduces exactly. Everything else is denied, see
+/// `record_syscall::prepare_perf_event_open()`.
+pub struct VirtualPerfCounterMonitor {
+    target_tuid: TaskUid,
+    /// Ticks counted up to when the counter was last disabled.
+    counted: Ticks,
+    /// The target's tick count when the counter was enabled, or `None` if
+    /// it's disabled.
+    enabled_at: Option<Ticks>,
+}
+
+impl VirtualPerfCounterMonitor {
+    pub fn should_virtualize(attr: &perf_event_attr) -> bool {
+        PerfCounters::is_rd_ticks_attr(attr) && unsafe { attr.__bindgen_anon_1.sample_period } == 0
+    }
+
+    /// `target_ticks` is the tick count of the task with `target_tuid` now.
+    pub fn new(
+        target_tuid: TaskUid,
+        target_ticks: Ticks,
+        attr: &perf_event_attr,
+    ) -> VirtualPerfCounterMonitor {
+        VirtualPerfCounterMonitor {
+            target_tuid,
+            counted: 0,
+            enabled_at: if attr.disabled() != 0 {
+                None
+            } else {
+                Some(target_ticks)
+            },
+        }
+    }
+
+    /// The counter's value as a read() by `t` would return it. If the target
+    /// has exited the count stops where it was.
+    pub fn value(&self, t: &RecordTask) -> u64 {
+        match self.enabled_at {
+            Some(start) => self.counted + self.target_ticks(t).map_or(0, |now| now - start),
+            None => self.counted,
+        }
+    }
+
+    fn target_ticks(&self, t: &RecordTask) -> Option<Ticks> {
+        if t.tuid() == self.target_tuid {
+            return Some(t.tick_count());
+        }
+        t.session()
+            .find_task_from_task_uid(self.target_tuid)
+            .map(|target| target.borrow().tick_count())
+    }
+}
+
+impl FileMonitor for VirtualPerfCounterMonitor {
+    fn file_monitor_type(&self) -> FileMonitorType {
+        FileMonitorType::VirtualPerfCounter
+    }
+
+    fn as_virtual_perf_counter_monitor(&self) -> Option<&VirtualPerfCounterMonitor> {
+        Some(self)
+    }
+
+    /// Other ioctls aren't emulated, so they fail on the inotify fd.
+    fn emulate_ioctl(&mut self, t: &RecordTask, r: &mut u64) -> bool {
+        match t.regs_ref().arg2() as c_ulong {
+            PERF_EVENT_IOC_ENABLE => {
+                if self.enabled_at.is_none() {
+                    self.enabled_at = self.target_ticks(t);
+                }
+            }
+            PERF_EVENT_IOC_DISABLE => {
+                self.counted = self.value(t);
+                self.enabled_at = None;
+            }
+            PERF_EVENT_IOC_RESET => {
+                self.counted = 0;
+                if self.enabled_at.is_some() {
+                    self.enabled_at = self.target_ticks(t);
+                }
+            }
+            _ => return false,
+        }
+        *r = 0;
+        true
+    }
+}
@@ -1,4 +1,9 @@
-use crate::{commands::rd_options::RdOptions, trace::trace_frame::FrameTime};
+use crate::{
+    commands::rd_options::RdOptions,
+    perf_counters::CoreType,
+    ticks::Ticks,
+    trace::trace_frame::FrameTime,
+};
 use std::path::PathBuf;
 use structopt::StructOpt;
 
@@ -8,12 +13,13 @@ lazy_static! {
 
 /// When to generate or check memory checksums. One of CHECKSUM_NONE,
 /// CHECKSUM_SYSCALL or CHECKSUM_ALL, or a positive integer representing the
-/// event time at which to start checksumming.
+/// event time at which to start checksumming, or every N events.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum Checksum {
     ChecksumSyscall,
     ChecksumAll,
     ChecksumAt(FrameTime),
+    ChecksumEvery(FrameTime),
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
@@ -37,6 +43,8 @@ pub struct Flags {
     pub mark_stdio: bool,
     /// Check that cached mmaps match /proc/maps after each event.
     pub check_cached_mmaps: bool,
+    /// Sanity check register state at syscall exits during recording.
+    pub check_syscall_regs: bool,
     /// Suppress warnings related to environmental features outside rd's
     /// control.
     pub suppress_environment_warnings: bool,
@@ -49,6 +57,11 @@ pub struct Flags {
     pub disable_ptrace_exit_events: bool,
     /// User override for architecture detection, e.g. when running under valgrind.
     pub forced_uarch: Option<String>,
+    /// On hybrid CPUs, the kind of core to run tracees on, instead of
+    /// P-cores.
+    pub forced_core_type: Option<CoreType>,
+    /// User override for the ticks interrupt skid of the CPU.
+    pub skid_size: Option<Ticks>,
     /// User override for the path to page files and other resources.
     pub resource_path: Option<PathBuf>,
 }
@@ -69,11 +82,14 @@ pub fn init_flags() -> Flags {
         force_things: options.force_things,
         mark_stdio: options.mark_stdio,
         check_cached_mmaps: options.check_cached_mmaps,
+        check_syscall_regs: options.check_syscall_regs,
         suppress_environment_warnings: options.suppress_environment_warnings,
         fatal_errors_and_warnings: options.fatal_errors,
         disable_cpuid_faulting: options.disable_cpuid_faulting,
         disable_ptrace_exit_events: options.disable_ptrace_exit_events,
         forced_uarch: options.microarch,
+        forced_core_type: options.core_type,
+        skid_size: options.skid_size,
         resource_path: options.resource_path,
     }
 }
@@ -0,0 +1,40 @@
+//! Files on FUSE filesystems. Their contents come from a userspace daemon rd
+//! doesn't record, and the mount may well be gone at replay (an sshfs mount,
+//! an AppImage, a mounted archive), or serve something else by then.
+//!
+//! Reads of them are recorded like any others, so only files tracees map
+//! need to be there at replay. By default rd warns the first time a tracee
+//! opens or maps each one. With `--copy-fuse-files` it copies each file into
+//! the trace instead, when it's first opened or mapped, while the daemon is
+//! still there to read it from. Mappings of the file are then backed by the
+//! copy like those of the executables rd copies.
+
+use libc::statfs;
+use std::{ffi::OsStr, mem::zeroed, os::unix::ffi::OsStrExt};
+
+/// `f_type` of FUSE filesystems, from linux/magic.h.
+const FUSE_SUPER_MAGIC: i64 = 0x6573_5546;
+
+/// What to do with files tracees access on FUSE filesystems.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FuseFilePolicy {
+    /// Warn that they may not be available at replay.
+    Warn,
+    /// Copy them into the trace, `--copy-fuse-files`.
+    Copy,
+}
+
+impl Default for FuseFilePolicy {
+    fn default() -> Self {
+        FuseFilePolicy::Warn
+    }
+}
+
+/// Whether `path` is on a FUSE filesystem. False if it can't be statfs()ed.
+pub fn is_fuse_file(path: &OsStr) -> bool {
+    let mut c_path = path.as_bytes().to_vec();
+    c_path.push(0);
+    let mut buf: statfs = unsafe { zeroed() };
+    let ret = unsafe { libc::statfs(c_path.as_ptr() as *const _, &mut buf) };
+    ret == 0 && buf.f_type as i64 == FUSE_SUPER_MAGIC
+}
@@ -1,6 +1,46 @@
 pub mod gdb_server {
-    use crate::trace::trace_frame::FrameTime;
-    use libc::pid_t;
+    use crate::{
+        cancellation_token::CancellationToken,
+        extra_registers::ExtraRegisters,
+        gdb_register::GdbRegister,
+        kernel_abi::SupportedArch,
+        log::LogLevel::LogDebug,
+        registers::Registers,
+        remote_code_ptr::RemoteCodePtr,
+        remote_ptr::{RemotePtr, Void},
+        replay_timeline::ReplayTimeline,
+        session::{
+            address_space::{BreakpointCondition, WatchType, WatchValues},
+            replay_session::{ReplaySession, ReplayStatus},
+            session_inner::{BreakStatus, RunCommand},
+            task::{task_common::read_c_str_fallible, Task},
+            Session,
+            SessionSharedPtr,
+        },
+        ticks::Ticks,
+        trace::{trace_exec_history::ExecHistory, trace_frame::FrameTime},
+    };
+    use goblin::elf::{program_header::PT_TLS, Elf};
+    use libc::{pid_t, siginfo_t, PATH_MAX, SIGINT, SIGTRAP};
+    use std::{
+        convert::{TryFrom, TryInto},
+        ffi::{OsStr, OsString},
+        fmt::Write,
+        fs,
+        io,
+        io::{Read, Write as _},
+        mem::zeroed,
+        net::{Shutdown, TcpStream},
+        os::unix::ffi::{OsStrExt, OsStringExt},
+        sync::mpsc,
+        thread,
+    };
+
+    /// The most memory one `m` packet reply returns.
+    const MAX_MEMORY_READ: usize = 0x4000;
+
+    /// What gdb sends to interrupt the tracee.
+    const INTERRUPT: u8 = 0x03;
 
     #[derive(Clone)]
     pub struct Target {
@@ -27,4 +67,1112 @@ pub mod gdb_server {
             Target::new()
         }
     }
+
+    /// Payload of the reply to `qXfer:exec-file:read:<pid>:<offset>,<length>`
+    /// when the replay is at global time `time`. This is the file that `pid`
+    /// was exec'd with at that point in the trace, which need not be the file
+    /// the process was started or ended with.
+    ///
+    /// Returns `None` if we know of no exec for `pid`, in which case the stub
+    /// should reply with an error.
+    pub fn qxfer_exec_file_reply(
+        history: &ExecHistory,
+        pid: pid_t,
+        time: FrameTime,
+        offset: usize,
+        length: usize,
+    ) -> Option<Vec<u8>> {
+        let file_name = history.generation_at(pid, time)?.file_name.as_bytes();
+        let start = offset.min(file_name.len());
+        let end = file_name.len().min(start.saturating_add(length));
+        let mut reply = vec![if end == file_name.len() { b'l' } else { b'm' }];
+        for &c in &file_name[start..end] {
+            // gdb binary data escaping
+            if c == b'#' || c == b'$' || c == b'}' || c == b'*' {
+                reply.push(b'}');
+                reply.push(c ^ 0x20);
+            } else {
+                reply.push(c);
+            }
+        }
+        Some(reply)
+    }
+
+    /// Extra `name:value;` fields for the `T` stop reply packet of a stop
+    /// caused by watchpoints, giving the contents of each watched range before
+    /// and after the access as
+    /// `rd-watch-old:<addr>,<hex bytes>;rd-watch-new:<addr>,<hex bytes>;`.
+    /// gdb ignores fields it doesn't know, but rd-aware clients can use these
+    /// instead of reading the memory after every stop.
+    pub fn watch_values_stop_reply_fields(values: &[WatchValues]) -> String {
+        let mut fields = String::new();
+        for v in values {
+            for (name, bytes) in &[("old", &v.old_value), ("new", &v.new_value)] {
+                write!(fields, "rd-watch-{}:{:x},", name, v.addr.as_usize()).unwrap();
+                for b in bytes.iter() {
+                    write!(fields, "{:02x}", b).unwrap();
+                }
+                fields.push(';');
+            }
+        }
+        fields
+    }
+
+    /// The `monitor` commands (gdb's `qRcmd` packet, hex-decoded) rd knows.
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub enum MonitorCommand {
+        /// `when-ticks`: the tick count of the current thread.
+        WhenTicks,
+        /// `seek-ticks <ticks>`: replay the current thread to that tick count.
+        SeekTicks(Ticks),
+        /// `break-condition <addr> [thread <tid>] [hit <n>]`: only stop at the
+        /// breakpoint at `addr` (in hex) for hits by `tid`, and only on the
+        /// nth of those. Without either, the breakpoint's condition is removed.
+        BreakCondition(RemoteCodePtr, Option<BreakpointCondition>),
+    }
+
+    pub fn parse_monitor_command(cmd: &str) -> Option<MonitorCommand> {
+        let mut words = cmd.split_whitespace();
+        let command = match (words.next()?, words.next()) {
+            ("when-ticks", None) => MonitorCommand::WhenTicks,
+            ("seek-ticks", Some(ticks)) => MonitorCommand::SeekTicks(ticks.parse().ok()?),
+            ("break-condition", Some(addr)) => {
+                let addr = usize::from_str_radix(addr.trim_start_matches("0x"), 16).ok()?;
+                let mut condition = BreakpointCondition::default();
+                while let Some(word) = words.next() {
+                    match word {
+                        "thread" if condition.tid.is_none() => {
+                            condition.tid = Some(words.next()?.parse::<pid_t>().ok()?);
+                        }
+                        "hit" if condition.nth_hit.is_none() => {
+                            let n = words.next()?.parse::<u64>().ok().filter(|&n| n > 0)?;
+                            condition.nth_hit = Some(n);
+                        }
+                        _ => return None,
+                    }
+                }
+                let maybe_condition = Some(condition).filter(|&c| c != Default::default());
+                MonitorCommand::BreakCondition(RemoteCodePtr::from_val(addr), maybe_condition)
+            }
+            _ => return None,
+        };
+        match words.next() {
+            None => Some(command),
+            Some(_) => None,
+        }
+    }
+
+    /// The monitor command in the argument of a `qRcmd,<hex>` packet, or
+    /// `None` if it isn't one rd knows.
+    pub fn parse_qrcmd(hex_cmd: &str) -> Option<MonitorCommand> {
+        if hex_cmd.len() % 2 != 0 {
+            return None;
+        }
+        let bytes = (0..hex_cmd.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex_cmd.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        parse_monitor_command(std::str::from_utf8(&bytes).ok()?)
+    }
+
+    /// The reply to the `qRcmd,<hex>` packet gdb sends for `monitor`
+    /// commands, run for the thread with recorded tid `tid`: the command's
+    /// output, hex-encoded, or an empty reply, which tells gdb the command
+    /// isn't supported.
+    pub fn qrcmd_reply(
+        timeline: &mut ReplayTimeline,
+        tid: pid_t,
+        hex_cmd: &str,
+        cancel: &CancellationToken,
+    ) -> String {
+        let cmd = match parse_qrcmd(hex_cmd) {
+            Some(cmd) => cmd,
+            None => return String::new(),
+        };
+        hex_encode(monitor_command_reply(timeline, tid, cmd, cancel).as_bytes())
+    }
+
+    /// Run `cmd` for the thread with recorded tid `tid` and return the text
+    /// to show the user. `cancel` is cancelled when gdb interrupts us.
+    pub fn monitor_command_reply(
+        timeline: &mut ReplayTimeline,
+        tid: pid_t,
+        cmd: MonitorCommand,
+        cancel: &CancellationToken,
+    ) -> String {
+        match cmd {
+            MonitorCommand::WhenTicks => match timeline.task_ticks(tid) {
+                Some(ticks) => format!("Current tick: {}\n", ticks),
+                None => "No current thread.\n".to_owned(),
+            },
+            MonitorCommand::SeekTicks(ticks) => {
+                if timeline.seek_to_ticks(tid, ticks, cancel) {
+                    format!("Now at tick {}\n", ticks)
+                } else if cancel.is_cancelled() {
+                    format!("Interrupted seeking to tick {}\n", ticks)
+                } else {
+                    format!("Failed to seek to tick {}\n", ticks)
+                }
+            }
+            MonitorCommand::BreakCondition(addr, condition) => {
+                let found = timeline.set_breakpoint_condition(tid, addr, condition);
+                let addr = addr.as_usize();
+                if found {
+                    format!("Set the condition of the breakpoint at {:#x}\n", addr)
+                } else {
+                    format!("No breakpoint at {:#x}\n", addr)
+                }
+            }
+        }
+    }
+
+    /// The registers of the thread with recorded tid `tid` as they were when it
+    /// exited, for `g` and `p` packets naming a thread that's gone. Returns
+    /// `None` if the thread is alive or wasn't kept (see `--dead-tasks`).
+    pub fn dead_task_registers(
+        session: &ReplaySession,
+        tid: pid_t,
+    ) -> Option<(Registers, ExtraRegisters)> {
+        if session.find_task_from_rec_tid(tid).is_some() {
+            return None;
+        }
+        let dead_tasks = session.dead_tasks();
+        let dead = dead_tasks.find(tid)?;
+        Some((dead.regs.clone(), dead.extra_regs.clone()))
+    }
+
+    /// Up to `len` bytes at `addr` in the memory of the exited thread with
+    /// recorded tid `tid`, for `m` packets. If other threads of its process
+    /// are still around the memory is read through one of them, otherwise
+    /// from what was kept when the last one exited. Returns `None` if nothing
+    /// at `addr` can be read.
+    pub fn read_dead_task_memory(
+        session: &ReplaySession,
+        tid: pid_t,
+        addr: RemotePtr<Void>,
+        len: usize,
+    ) -> Option<Vec<u8>> {
+        if session.find_task_from_rec_tid(tid).is_some() {
+            return None;
+        }
+        let dead_tasks = session.dead_tasks();
+        let dead = dead_tasks.find(tid)?;
+        let mut buf = vec![0u8; len];
+        let live = match session.find_thread_group_from_pid(dead.tgid) {
+            Some(tg) => {
+                let maybe_t = tg.borrow().task_set().iter().next();
+                maybe_t
+            }
+            None => None,
+        };
+        let valid = match live {
+            Some(t) => t
+                .borrow_mut()
+                .read_bytes_fallible(RemotePtr::cast(addr), &mut buf)
+                .unwrap_or(0),
+            None => dead.read_memory(addr, &mut buf),
+        };
+        if valid == 0 {
+            return None;
+        }
+        buf.truncate(valid);
+        Some(buf)
+    }
+
+    /// The reply to a `p<regno>` packet for the exited thread with recorded
+    /// tid `tid`: the register's value when the thread exited, or an error
+    /// reply if the thread wasn't kept or has no such register. Only for
+    /// threads that aren't live, see `dead_task_registers()`.
+    pub fn dead_task_register_reply(
+        session: &ReplaySession,
+        tid: pid_t,
+        hex_regno: &str,
+    ) -> String {
+        match dead_task_registers(session, tid) {
+            Some((regs, extra_regs)) => register_reply(&regs, &extra_regs, hex_regno),
+            None => "E01".to_owned(),
+        }
+    }
+
+    /// The reply to `p<hex_regno>` given the thread's registers: the value in
+    /// target byte order, hex-encoded, or an error reply.
+    pub fn register_reply(
+        regs: &Registers,
+        extra_regs: &ExtraRegisters,
+        hex_regno: &str,
+    ) -> String {
+        let maybe_regno = u32::from_str_radix(hex_regno, 16)
+            .ok()
+            .and_then(|regno| GdbRegister::try_from(regno).ok());
+        let regno = match maybe_regno {
+            Some(regno) => regno,
+            None => return "E01".to_owned(),
+        };
+        let mut buf = [0u8; 64];
+        let maybe_size = regs
+            .read_register(&mut buf, regno)
+            .or_else(|| extra_regs.read_register(&mut buf, regno));
+        match maybe_size {
+            Some(size) => hex_encode(&buf[..size]),
+            None => "E01".to_owned(),
+        }
+    }
+
+    /// The address and length in the argument of an `m<addr>,<length>`
+    /// packet, both in hex.
+    pub fn parse_memory_read(args: &str) -> Option<(RemotePtr<Void>, usize)> {
+        let mut parts = args.splitn(2, ',');
+        let addr = usize::from_str_radix(parts.next()?, 16).ok()?;
+        let len = usize::from_str_radix(parts.next()?, 16).ok()?;
+        Some((RemotePtr::new_from_val(addr), len))
+    }
+
+    /// The reply to an `m<args>` packet for the exited thread with recorded
+    /// tid `tid`: the bytes `read_dead_task_memory()` finds, hex-encoded, or an
+    /// error reply if there are none.
+    pub fn dead_task_memory_reply(session: &ReplaySession, tid: pid_t, args: &str) -> String {
+        let (addr, len) = match parse_memory_read(args) {
+            Some(parsed) => parsed,
+            None => return "E01".to_owned(),
+        };
+        // A short reply is fine; gdb asks again for the rest.
+        let len = len.min(MAX_MEMORY_READ);
+        match read_dead_task_memory(session, tid, addr, len) {
+            Some(data) => hex_encode(&data),
+            None => "E01".to_owned(),
+        }
+    }
+
+    fn hex_encode(data: &[u8]) -> String {
+        let mut hex = String::new();
+        for b in data {
+            write!(hex, "{:02x}", b).unwrap();
+        }
+        hex
+    }
+
+    /// The arguments of a `qGetTLSAddr:<thread-id>,<offset>,<lm>` packet: the
+    /// thread's (recorded) tid, the offset and the link map address. The
+    /// thread id is `p<pid>.<tid>` or just `<tid>`, all in hex.
+    pub fn parse_qgettlsaddr(args: &str) -> Option<(pid_t, usize, RemotePtr<Void>)> {
+        let mut parts = args.split(',');
+        let tid = parse_thread_id(parts.next()?)?;
+        let offset = usize::from_str_radix(parts.next()?, 16).ok()?;
+        let lm = usize::from_str_radix(parts.next()?, 16).ok()?;
+        match parts.next() {
+            None => Some((tid, offset, RemotePtr::new_from_val(lm))),
+            Some(_) => None,
+        }
+    }
+
+    /// The reply to `qGetTLSAddr:<args>`: the address of the variable in hex,
+    /// or an error reply if the thread doesn't exist or the variable can't be
+    /// found, see `tls_address()`.
+    pub fn qgettlsaddr_reply(session: &ReplaySession, args: &str) -> String {
+        let (tid, offset, lm) = match parse_qgettlsaddr(args) {
+            Some(parsed) => parsed,
+            None => return "E01".to_owned(),
+        };
+        let t = match session.find_task_from_rec_tid(tid) {
+            Some(t) => t,
+            None => return "E01".to_owned(),
+        };
+        let maybe_addr = tls_address(t.borrow_mut().as_mut(), offset, lm);
+        match maybe_addr {
+            Some(addr) => format!("{:x}", addr.as_usize()),
+            None => "E01".to_owned(),
+        }
+    }
+
+    /// The address of the thread-local variable at `offset` in the TLS block of
+    /// the module whose `struct link_map` is at `lm`, for thread `t`. This is
+    /// the answer to `qGetTLSAddr:<thread>,<offset>,<lm>`, which gdb needs to
+    /// print `errno` and other `__thread` variables.
+    ///
+    /// We find the module's TLS block the way `__tls_get_addr` does: through
+    /// the dtv, whose address is the second word of the TCB. The module id is
+    /// recovered by counting the modules in the link map that have a PT_TLS
+    /// segment, which is how ld.so assigns them as long as nothing with TLS
+    /// was dlclose()d and its id reused.
+    ///
+    /// Returns `None` if the thread has no TLS set up yet, or hasn't allocated
+    /// the module's block yet (glibc does that lazily for dlopen()ed modules);
+    /// the stub should reply with an error then.
+    pub fn tls_address(
+        t: &mut dyn Task,
+        offset: usize,
+        lm: RemotePtr<Void>,
+    ) -> Option<RemotePtr<Void>> {
+        let word_size = match t.arch() {
+            SupportedArch::X86 => 4,
+            SupportedArch::X64 => 8,
+        };
+        let tcb = t.tls_base();
+        if tcb.is_null() {
+            return None;
+        }
+        let modid = tls_module_id(t, lm, word_size)?;
+        let dtv = read_word(t, tcb + word_size, word_size)?;
+        // Each dtv entry is a (block address, to_free) pair; entry 0 is the
+        // generation counter.
+        let block = read_word(
+            t,
+            RemotePtr::new_from_val(dtv + modid * 2 * word_size),
+            word_size,
+        )?;
+        if block == 0 || block == usize::MAX >> (64 - 8 * word_size) {
+            // TLS_DTV_UNALLOCATED
+            return None;
+        }
+        Some(RemotePtr::new_from_val(block + offset))
+    }
+
+    /// The TLS module id of the module whose `struct link_map` is at `lm`.
+    fn tls_module_id(t: &mut dyn Task, lm: RemotePtr<Void>, word_size: usize) -> Option<usize> {
+        // struct link_map starts with l_addr, l_name, l_ld, l_next, l_prev.
+        let l_name = |lm: RemotePtr<Void>| lm + word_size;
+        let l_next = |lm: RemotePtr<Void>| lm + 3 * word_size;
+        let l_prev = |lm: RemotePtr<Void>| lm + 4 * word_size;
+
+        let mut head = lm;
+        loop {
+            let prev = read_word(t, l_prev(head), word_size)?;
+            if prev == 0 {
+                break;
+            }
+            head = RemotePtr::new_from_val(prev);
+        }
+
+        let mut modid = 0;
+        let mut cur = head;
+        while !cur.is_null() {
+            let name_addr = read_word(t, l_name(cur), word_size)?;
+            let name = if name_addr == 0 {
+                Vec::new()
+            } else {
+                read_c_str_fallible(
+                    t,
+                    RemotePtr::new_from_val(name_addr),
+                    Some(PATH_MAX as usize),
+                )
+                .ok()?
+                .into_bytes()
+            };
+            // The main executable's entry has an empty name.
+            let file_name = if name.is_empty() {
+                t.vm().exe_image().to_owned()
+            } else {
+                OsString::from_vec(name)
+            };
+            if has_tls_segment(&file_name) {
+                modid += 1;
+            }
+            if cur == lm {
+                return if modid > 0 { Some(modid) } else { None };
+            }
+            cur = RemotePtr::new_from_val(read_word(t, l_next(cur), word_size)?);
+        }
+        None
+    }
+
+    fn has_tls_segment(file_name: &OsStr) -> bool {
+        match fs::read(file_name) {
+            Ok(data) => match Elf::parse(&data) {
+                Ok(elf) => elf.program_headers.iter().any(|ph| ph.p_type == PT_TLS),
+                Err(_) => false,
+            },
+            Err(_) => false,
+        }
+    }
+
+    fn read_word(t: &mut dyn Task, addr: RemotePtr<Void>, word_size: usize) -> Option<usize> {
+        let mut buf = [0u8; 8];
+        match t.read_bytes_fallible(RemotePtr::cast(addr), &mut buf[0..word_size]) {
+            Ok(n) if n == word_size => Some(u64::from_le_bytes(buf.try_into().unwrap()) as usize),
+            _ => None,
+        }
+    }
+
+    /// A gdb remote protocol stub for a replay. It only goes forwards: `c`
+    /// and `s` replay on from where we are, and memory and registers can't be
+    /// written. Thread ids are recorded tids.
+    ///
+    /// The replay stays with the server across connections, so with
+    /// `rd replay -k` a new client picks up where the last one detached.
+    pub struct GdbServer {
+        timeline: ReplayTimeline,
+        exec_history: ExecHistory,
+        /// The thread `Hg` selected, which `g`, `p`, `m` and `Z` packets apply
+        /// to. `None` means whichever thread the replay is at. This can be a
+        /// thread that has exited, whose registers and memory are then what
+        /// `--dead-tasks` kept.
+        general_thread: Option<pid_t>,
+        /// Cancelled when the client interrupts (^C) a `c`.
+        cancel: CancellationToken,
+        /// The client detached (`D`) or killed (`k`) the replay.
+        connection_done: bool,
+        killed: bool,
+    }
+
+    impl GdbServer {
+        pub fn new(timeline: ReplayTimeline, exec_history: ExecHistory) -> GdbServer {
+            GdbServer {
+                timeline,
+                exec_history,
+                general_thread: None,
+                cancel: CancellationToken::new(),
+                connection_done: false,
+                killed: false,
+            }
+        }
+
+        /// Whether a client killed the replay, so there's nothing left to serve.
+        pub fn is_killed(&self) -> bool {
+            self.killed
+        }
+
+        /// The reply to `packet` (without the `$...#<checksum>` framing), or
+        /// `None` if it doesn't get one.
+        pub fn process_packet(&mut self, packet: &[u8]) -> Option<Vec<u8>> {
+            let packet = match std::str::from_utf8(packet) {
+                Ok(packet) => packet,
+                Err(_) => return Some(b"E01".to_vec()),
+            };
+            log!(LogDebug, "gdb packet: {}", packet);
+            if let Some(args) = packet.strip_prefix("qXfer:exec-file:read:") {
+                return Some(self.exec_file_packet_reply(args));
+            }
+            let reply = if packet == "?" {
+                self.stop_reply(&BreakStatus::new())
+            } else if packet.starts_with("qSupported") {
+                format!("PacketSize={:x};qXfer:exec-file:read+", 2 * MAX_MEMORY_READ)
+            } else if packet == "qAttached" {
+                "1".to_owned()
+            } else if packet == "qC" {
+                match self.current_tid() {
+                    Some(tid) => format!("QC{:x}", tid),
+                    None => String::new(),
+                }
+            } else if let Some(args) = packet.strip_prefix("qGetTLSAddr:") {
+                let session = self.replay_session();
+                qgettlsaddr_reply(session.as_replay().unwrap(), args)
+            } else if let Some(hex_cmd) = packet.strip_prefix("qRcmd,") {
+                match self.general_tid() {
+                    Some(tid) => qrcmd_reply(&mut self.timeline, tid, hex_cmd, &self.cancel),
+                    None => "E01".to_owned(),
+                }
+            } else if packet == "qfThreadInfo" {
+                self.thread_list()
+            } else if packet == "qsThreadInfo" {
+                "l".to_owned()
+            } else if let Some(thread_id) = packet.strip_prefix("Hg") {
+                self.select_thread(thread_id)
+            } else if packet.starts_with("Hc") {
+                // Replay runs whatever thread ran next in the recording.
+                "OK".to_owned()
+            } else if let Some(thread_id) = packet.strip_prefix('T') {
+                match parse_thread_id(thread_id) {
+                    Some(tid) if self.is_live(tid) => "OK".to_owned(),
+                    _ => "E01".to_owned(),
+                }
+            } else if packet == "g" {
+                self.registers_packet_reply()
+            } else if let Some(hex_regno) = packet.strip_prefix('p') {
+                self.register_packet_reply(hex_regno)
+            } else if let Some(args) = packet.strip_prefix('m') {
+                self.memory_packet_reply(args)
+            } else if packet.starts_with(&['G', 'P', 'M', 'X'][..]) {
+                // Replay can't diverge from the recording.
+                "E01".to_owned()
+            } else if let Some(args) = packet.strip_prefix('Z') {
+                self.breakpoint_packet_reply(args, true)
+            } else if let Some(args) = packet.strip_prefix('z') {
+                self.breakpoint_packet_reply(args, false)
+            } else if packet == "c" {
+                self.resume(RunCommand::RunContinue)
+            } else if packet == "s" {
+                self.resume(RunCommand::RunSinglestep)
+            } else if packet == "D" {
+                self.connection_done = true;
+                "OK".to_owned()
+            } else if packet == "k" {
+                self.connection_done = true;
+                self.killed = true;
+                return None;
+            } else {
+                // Not supported.
+                String::new()
+            };
+            self.cancel.reset();
+            Some(reply.into_bytes())
+        }
+
+        fn replay_session(&self) -> SessionSharedPtr {
+            self.timeline.current_session()
+        }
+
+        /// The recorded tid of the thread the replay is at.
+        fn current_tid(&self) -> Option<pid_t> {
+            let session = self.replay_session();
+            let maybe_t = session.as_replay().unwrap().current_task();
+            maybe_t.map(|t| t.borrow().rec_tid)
+        }
+
+        fn general_tid(&self) -> Option<pid_t> {
+            self.general_thread.or_else(|| self.current_tid())
+        }
+
+        fn is_live(&self, tid: pid_t) -> bool {
+            self.replay_session().find_task_from_rec_tid(tid).is_some()
+        }
+
+        fn is_kept_dead(&self, tid: pid_t) -> bool {
+            let session = self.replay_session();
+            let dead_tasks = session.as_replay().unwrap().dead_tasks();
+            dead_tasks.find(tid).is_some()
+        }
+
+        fn thread_list(&self) -> String {
+            let session = self.replay_session();
+            let mut tids: Vec<pid_t> = session
+                .tasks()
+                .values()
+                .map(|t| t.borrow().rec_tid)
+                .collect();
+            if tids.is_empty() {
+                return "l".to_owned();
+            }
+            tids.sort_unstable();
+            let tids: Vec<String> = tids.iter().map(|tid| format!("{:x}", tid)).collect();
+            format!("m{}", tids.join(","))
+        }
+
+        fn select_thread(&mut self, thread_id: &str) -> String {
+            if thread_id == "0" || thread_id == "-1" {
+                self.general_thread = None;
+                return "OK".to_owned();
+            }
+            match parse_thread_id(thread_id) {
+                Some(tid) if self.is_live(tid) || self.is_kept_dead(tid) => {
+                    self.general_thread = Some(tid);
+                    "OK".to_owned()
+                }
+                _ => "E01".to_owned(),
+            }
+        }
+
+        fn registers_packet_reply(&self) -> String {
+            let tid = match self.general_tid() {
+                Some(tid) => tid,
+                None => return "E01".to_owned(),
+            };
+            let session = self.replay_session();
+            let replay = session.as_replay().unwrap();
+            match replay.find_task_from_rec_tid(tid) {
+                Some(t) => {
+                    let mut t = t.borrow_mut();
+                    let regs = t.regs_ref().clone();
+                    registers_reply(&regs, t.extra_regs_ref())
+                }
+                None => match dead_task_registers(replay, tid) {
+                    Some((regs, extra_regs)) => registers_reply(&regs, &extra_regs),
+                    None => "E01".to_owned(),
+                },
+            }
+        }
+
+        fn register_packet_reply(&self, hex_regno: &str) -> String {
+            let tid = match self.general_tid() {
+                Some(tid) => tid,
+                None => return "E01".to_owned(),
+            };
+            let session = self.replay_session();
+            let replay = session.as_replay().unwrap();
+            match replay.find_task_from_rec_tid(tid) {
+                Some(t) => {
+                    let mut t = t.borrow_mut();
+                    let regs = t.regs_ref().clone();
+                    register_reply(&regs, t.extra_regs_ref(), hex_regno)
+                }
+                None => dead_task_register_reply(replay, tid, hex_regno),
+            }
+        }
+
+        fn memory_packet_reply(&self, args: &str) -> String {
+            let tid = match self.general_tid() {
+                Some(tid) => tid,
+                None => return "E01".to_owned(),
+            };
+            let session = self.replay_session();
+            let replay = session.as_replay().unwrap();
+            let maybe_t = replay.find_task_from_rec_tid(tid);
+            let (t, (addr, len)) = match (maybe_t, parse_memory_read(args)) {
+                (Some(t), Some(parsed)) => (t, parsed),
+                (None, _) => return dead_task_memory_reply(replay, tid, args),
+                _ => return "E01".to_owned(),
+            };
+            // A short reply is fine; gdb asks again for the rest.
+            let mut buf = vec![0u8; len.min(MAX_MEMORY_READ)];
+            let mut t = t.borrow_mut();
+            match t.read_bytes_fallible(addr, &mut buf) {
+                Ok(n) if n > 0 => {
+                    // Show what the tracee sees, not our breakpoints.
+                    t.vm().replace_breakpoints_with_original_values(
+                        &mut buf[..n],
+                        RemotePtr::cast(addr),
+                    );
+                    hex_encode(&buf[..n])
+                }
+                _ => "E01".to_owned(),
+            }
+        }
+
+        /// `Z<type>,<addr>,<kind>` and `z<type>,<addr>,<kind>`: software
+        /// breakpoints (type 0), hardware breakpoints (1) and write (2), read
+        /// (3) and access (4) watchpoints. x86 can't watch for reads only, so
+        /// read watchpoints also trigger on writes.
+        fn breakpoint_packet_reply(&mut self, args: &str, insert: bool) -> String {
+            // Ignore conditions and commands for gdb to evaluate (after ';').
+            let mut parts = args.split(';').next().unwrap().split(',');
+            let type_ = parts.next().unwrap();
+            let maybe_addr = parts
+                .next()
+                .and_then(|addr| usize::from_str_radix(addr, 16).ok());
+            let maybe_kind = parts
+                .next()
+                .and_then(|kind| usize::from_str_radix(kind, 16).ok());
+            let (tid, addr, kind) = match (self.general_tid(), maybe_addr, maybe_kind) {
+                (Some(tid), Some(addr), Some(kind)) => (tid, addr, kind),
+                _ => return "E01".to_owned(),
+            };
+            let watch_type = match type_ {
+                "0" => {
+                    let addr = RemoteCodePtr::from_val(addr);
+                    if !insert {
+                        self.timeline.remove_breakpoint(tid, addr);
+                        return "OK".to_owned();
+                    }
+                    return if self.timeline.add_breakpoint(tid, addr) {
+                        "OK".to_owned()
+                    } else {
+                        "E01".to_owned()
+                    };
+                }
+                "1" => WatchType::WatchExec,
+                "2" => WatchType::WatchWrite,
+                "3" | "4" => WatchType::WatchReadWrite,
+                _ => return String::new(),
+            };
+            // A hardware breakpoint's kind is the instruction size, but the
+            // debug registers want a length of 1 for it.
+            let num_bytes = if watch_type == WatchType::WatchExec {
+                1
+            } else {
+                kind
+            };
+            let addr = RemotePtr::new_from_val(addr);
+            if !insert {
+                self.timeline
+                    .remove_watchpoint(tid, addr, num_bytes, watch_type);
+                "OK".to_owned()
+            } else if self
+                .timeline
+                .add_watchpoint(tid, addr, num_bytes, watch_type)
+            {
+                "OK".to_owned()
+            } else {
+                "E01".to_owned()
+            }
+        }
+
+        /// Replay until something the client wants to hear about happens (or
+        /// for one step, for `s`), and return the stop reply.
+        fn resume(&mut self, command: RunCommand) -> String {
+            loop {
+                if self.cancel.is_cancelled() {
+                    let mut break_status = BreakStatus::new();
+                    let mut siginfo: siginfo_t = unsafe { zeroed() };
+                    siginfo.si_signo = SIGINT;
+                    break_status.signal = Some(Box::new(siginfo));
+                    return self.stop_reply(&break_status);
+                }
+                let result = self.timeline.replay_step(command);
+                match result.status {
+                    ReplayStatus::ReplayExited => return "W00".to_owned(),
+                    ReplayStatus::ReplayDiverged => {
+                        fatal!("Replay diverged: {}", result.divergence.unwrap())
+                    }
+                    ReplayStatus::ReplayContinue => (),
+                }
+                if command == RunCommand::RunSinglestep || result.break_status.any_break() {
+                    return self.stop_reply(&result.break_status);
+                }
+            }
+        }
+
+        /// The `T` stop reply for a stop with `break_status`, which also makes
+        /// the stopped thread the general thread. Watchpoint stops come with
+        /// the watched values, see `watch_values_stop_reply_fields()`.
+        fn stop_reply(&mut self, break_status: &BreakStatus) -> String {
+            let stopped_tid = break_status
+                .task
+                .as_ref()
+                .and_then(|t| t.upgrade())
+                .map(|t| t.borrow().rec_tid)
+                .or_else(|| self.current_tid());
+            let tid = match stopped_tid {
+                Some(tid) => tid,
+                None => return "W00".to_owned(),
+            };
+            self.general_thread = None;
+            let signo = break_status
+                .signal
+                .as_ref()
+                .map_or(SIGTRAP, |siginfo| siginfo.si_signo);
+            let mut reply = format!("T{:02x}thread:{:x};", signo, tid);
+            if let Some(watch) = break_status.watchpoints_hit.first() {
+                let maybe_name = match watch.type_ {
+                    WatchType::WatchExec => None,
+                    WatchType::WatchWrite => Some("watch"),
+                    WatchType::WatchReadWrite => Some("awatch"),
+                };
+                if let Some(name) = maybe_name {
+                    write!(reply, "{}:{:x};", name, watch.addr.as_usize()).unwrap();
+                }
+            }
+            reply.push_str(&watch_values_stop_reply_fields(
+                &break_status.watchpoint_values,
+            ));
+            reply
+        }
+
+        /// `qXfer:exec-file:read:<pid>:<offset>,<length>`, where an empty pid
+        /// means the current process.
+        fn exec_file_packet_reply(&self, args: &str) -> Vec<u8> {
+            let mut parts = args.splitn(2, ':');
+            let annex = parts.next().unwrap();
+            let maybe_pid = if annex.is_empty() {
+                let session = self.replay_session();
+                let maybe_t = session.as_replay().unwrap().current_task();
+                maybe_t.map(|t| t.borrow().tgid())
+            } else {
+                pid_t::from_str_radix(annex, 16).ok()
+            };
+            let maybe_range = parts.next().and_then(|range| {
+                let mut range = range.splitn(2, ',');
+                let offset = usize::from_str_radix(range.next()?, 16).ok()?;
+                let length = usize::from_str_radix(range.next()?, 16).ok()?;
+                Some((offset, length))
+            });
+            let time = self
+                .replay_session()
+                .as_replay()
+                .unwrap()
+                .current_frame_time();
+            match (maybe_pid, maybe_range) {
+                (Some(pid), Some((offset, length))) => {
+                    qxfer_exec_file_reply(&self.exec_history, pid, time, offset, length)
+                        .unwrap_or_else(|| b"E01".to_vec())
+                }
+                _ => b"E01".to_vec(),
+            }
+        }
+    }
+
+    /// Serve the gdb client connected through `stream` until it detaches,
+    /// kills the replay or hangs up.
+    pub fn serve_connection(server: &mut GdbServer, stream: TcpStream) -> io::Result<()> {
+        // Read on another thread so that an interrupt (^C) can cancel a `c`
+        // or a seek while we're busy replaying.
+        let (sender, receiver) = mpsc::channel::<Vec<u8>>();
+        let mut reader = stream.try_clone()?;
+        let cancel = server.cancel.clone();
+        thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            while let Ok(n) = reader.read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+                if buf[..n].contains(&INTERRUPT) {
+                    cancel.cancel();
+                }
+                if sender.send(buf[..n].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut writer = stream;
+        let mut input = Vec::new();
+        server.connection_done = false;
+        while !server.connection_done {
+            match take_packet(&mut input) {
+                Some(Ok(packet)) => {
+                    writer.write_all(b"+")?;
+                    if let Some(reply) = server.process_packet(&packet) {
+                        writer.write_all(&frame_packet(&reply))?;
+                    }
+                }
+                Some(Err(())) => writer.write_all(b"-")?,
+                None => match receiver.recv() {
+                    Ok(bytes) => input.extend(bytes),
+                    // The client hung up.
+                    Err(_) => break,
+                },
+            }
+        }
+        let _ = writer.shutdown(Shutdown::Both);
+        Ok(())
+    }
+
+    /// Take the first `$<packet>#<checksum>` out of `input`, dropping the
+    /// acks and interrupts before it. Returns `None` if there's no complete
+    /// packet yet, and `Some(Err(()))` for a packet with a bad checksum.
+    pub fn take_packet(input: &mut Vec<u8>) -> Option<Result<Vec<u8>, ()>> {
+        let start = match input.iter().position(|&c| c == b'$') {
+            Some(start) => start,
+            None => {
+                input.clear();
+                return None;
+            }
+        };
+        input.drain(..start);
+        let end = input.iter().position(|&c| c == b'#')?;
+        if input.len() < end + 3 {
+            return None;
+        }
+        let packet: Vec<u8> = input[1..end].to_vec();
+        let checksum = std::str::from_utf8(&input[end + 1..end + 3])
+            .ok()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        input.drain(..end + 3);
+        if checksum == Some(checksum_of(&packet)) {
+            Some(Ok(packet))
+        } else {
+            Some(Err(()))
+        }
+    }
+
+    /// `payload` as `$<payload>#<checksum>`.
+    pub fn frame_packet(payload: &[u8]) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(payload.len() + 4);
+        packet.push(b'$');
+        packet.extend_from_slice(payload);
+        packet.extend_from_slice(format!("#{:02x}", checksum_of(payload)).as_bytes());
+        packet
+    }
+
+    fn checksum_of(payload: &[u8]) -> u8 {
+        payload.iter().fold(0u8, |sum, &c| sum.wrapping_add(c))
+    }
+
+    /// The recorded tid in a thread id, `<tid>` or `p<pid>.<tid>` in hex.
+    fn parse_thread_id(thread_id: &str) -> Option<pid_t> {
+        let tid = match thread_id.strip_prefix('p') {
+            Some(pid_tid) => pid_tid.splitn(2, '.').nth(1)?,
+            None => thread_id,
+        };
+        pid_t::from_str_radix(tid, 16).ok()
+    }
+
+    /// The reply to `g`: the registers that `p` can read, in order, up to
+    /// the first one it can't. gdb takes the ones missing from a short reply
+    /// as unavailable.
+    pub fn registers_reply(regs: &Registers, extra_regs: &ExtraRegisters) -> String {
+        let mut reply = String::new();
+        let mut buf = [0u8; 64];
+        for regno in 0u32.. {
+            let maybe_size = GdbRegister::try_from(regno).ok().and_then(|regno| {
+                regs.read_register(&mut buf, regno)
+                    .or_else(|| extra_regs.read_register(&mut buf, regno))
+            });
+            match maybe_size {
+                Some(size) => reply.push_str(&hex_encode(&buf[..size])),
+                None => break,
+            }
+        }
+        reply
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+        use crate::{
+            gdb_register::{DREG_64_YMM0H, DREG_RIP},
+            remote_ptr::RemotePtr,
+            trace::trace_task_event::TraceTaskEvent,
+        };
+
+        #[test]
+        fn monitor_commands() {
+            assert_eq!(
+                parse_monitor_command("when-ticks"),
+                Some(MonitorCommand::WhenTicks)
+            );
+            assert_eq!(
+                parse_monitor_command(" seek-ticks  1234 "),
+                Some(MonitorCommand::SeekTicks(1234))
+            );
+            assert_eq!(parse_monitor_command("seek-ticks"), None);
+            assert_eq!(parse_monitor_command("seek-ticks x"), None);
+            assert_eq!(parse_monitor_command("when-ticks 1"), None);
+        }
+
+        #[test]
+        fn break_condition_commands() {
+            let addr = RemoteCodePtr::from_val(0x401000);
+            let condition = |tid, nth_hit| BreakpointCondition { tid, nth_hit };
+            assert_eq!(
+                parse_monitor_command("break-condition 0x401000 thread 1001 hit 3"),
+                Some(MonitorCommand::BreakCondition(
+                    addr,
+                    Some(condition(Some(1001), Some(3)))
+                ))
+            );
+            assert_eq!(
+                parse_monitor_command("break-condition 401000 hit 2"),
+                Some(MonitorCommand::BreakCondition(
+                    addr,
+                    Some(condition(None, Some(2)))
+                ))
+            );
+            assert_eq!(
+                parse_monitor_command("break-condition 401000"),
+                Some(MonitorCommand::BreakCondition(addr, None))
+            );
+            assert_eq!(parse_monitor_command("break-condition"), None);
+            assert_eq!(parse_monitor_command("break-condition 401000 hit 0"), None);
+            assert_eq!(parse_monitor_command("break-condition 401000 hit"), None);
+            assert_eq!(
+                parse_monitor_command("break-condition 401000 hit 1 hit 2"),
+                None
+            );
+            assert_eq!(parse_monitor_command("break-condition 401000 tid 5"), None);
+        }
+
+        #[test]
+        fn qrcmd_packets() {
+            // "when-ticks"
+            assert_eq!(
+                parse_qrcmd("7768656e2d7469636b73"),
+                Some(MonitorCommand::WhenTicks)
+            );
+            // "seek-ticks 42"
+            assert_eq!(
+                parse_qrcmd("7365656b2d7469636b73203432"),
+                Some(MonitorCommand::SeekTicks(42))
+            );
+            // "help"
+            assert_eq!(parse_qrcmd("68656c70"), None);
+            assert_eq!(parse_qrcmd("7768656"), None);
+            assert_eq!(parse_qrcmd("zz"), None);
+        }
+
+        #[test]
+        fn register_replies() {
+            let mut regs = Registers::new(SupportedArch::X64);
+            regs.set_ip(RemoteCodePtr::from_val(0x401000));
+            let extra_regs = ExtraRegisters::new(SupportedArch::X64);
+            let rip = format!("{:x}", DREG_RIP.as_usize());
+            assert_eq!(register_reply(&regs, &extra_regs, &rip), "0010400000000000");
+            // Not saved without XSAVE data.
+            let ymm0h = format!("{:x}", DREG_64_YMM0H.as_usize());
+            assert_eq!(register_reply(&regs, &extra_regs, &ymm0h), "E01");
+            assert_eq!(register_reply(&regs, &extra_regs, "fff"), "E01");
+            assert_eq!(register_reply(&regs, &extra_regs, "rip"), "E01");
+        }
+
+        #[test]
+        fn memory_read_packets() {
+            assert_eq!(
+                parse_memory_read("7f0010,40"),
+                Some((RemotePtr::new_from_val(0x7f0010), 64))
+            );
+            assert_eq!(parse_memory_read("7f0010"), None);
+            assert_eq!(parse_memory_read("7f0010,"), None);
+            assert_eq!(parse_memory_read("xx,40"), None);
+            assert_eq!(hex_encode(&[0, 0x2a, 0xff]), "002aff");
+        }
+
+        #[test]
+        fn watch_values_fields() {
+            let values = [WatchValues {
+                addr: RemotePtr::new_from_val(0x1000),
+                old_value: vec![0, 0xff],
+                new_value: vec![0x2a, 0xff],
+            }];
+            assert_eq!(
+                watch_values_stop_reply_fields(&values),
+                "rd-watch-old:1000,00ff;rd-watch-new:1000,2aff;"
+            );
+        }
+
+        #[test]
+        fn qgettlsaddr_packets() {
+            let lm = RemotePtr::new_from_val(0x7f0010);
+            assert_eq!(
+                parse_qgettlsaddr("p3e8.3e9,10,7f0010"),
+                Some((1001, 16, lm))
+            );
+            assert_eq!(parse_qgettlsaddr("3e9,10,7f0010"), Some((1001, 16, lm)));
+            assert_eq!(parse_qgettlsaddr("p3e8,10,7f0010"), None);
+            assert_eq!(parse_qgettlsaddr("3e9,10"), None);
+            assert_eq!(parse_qgettlsaddr("3e9,10,7f0010,1"), None);
+            assert_eq!(parse_qgettlsaddr("3e9,xx,7f0010"), None);
+        }
+
+        #[test]
+        fn packet_framing() {
+            assert_eq!(frame_packet(b"OK"), b"$OK#9a".to_vec());
+            assert_eq!(frame_packet(b""), b"$#00".to_vec());
+
+            let mut input = b"+\x03$qC#b4$g#6".to_vec();
+            assert_eq!(take_packet(&mut input), Some(Ok(b"qC".to_vec())));
+            // Not all of the checksum is there yet.
+            assert_eq!(take_packet(&mut input), None);
+            input.extend_from_slice(b"7-$s#00");
+            assert_eq!(take_packet(&mut input), Some(Ok(b"g".to_vec())));
+            assert_eq!(take_packet(&mut input), Some(Err(())));
+            assert_eq!(take_packet(&mut input), None);
+            assert!(input.is_empty());
+        }
+
+        #[test]
+        fn thread_ids() {
+            assert_eq!(parse_thread_id("3e9"), Some(1001));
+            assert_eq!(parse_thread_id("p3e8.3e9"), Some(1001));
+            assert_eq!(parse_thread_id("p3e8"), None);
+            assert_eq!(parse_thread_id(""), None);
+        }
+
+        #[test]
+        fn all_register_replies() {
+            let mut regs = Registers::new(SupportedArch::X64);
+            regs.set_ip(RemoteCodePtr::from_val(0x401000));
+            let extra_regs = ExtraRegisters::new(SupportedArch::X64);
+            let reply = registers_reply(&regs, &extra_regs);
+            // 16 general purpose registers and rip come first, then eflags and
+            // the segment registers. There's no XSAVE data for the rest.
+            let rip = DREG_RIP.as_usize() * 16;
+            assert_eq!(&reply[rip..rip + 16], "0010400000000000");
+            assert_eq!(reply.len(), 16 * (16 + 1) + 8 * (1 + 6));
+        }
+
+        #[test]
+        fn exec_file_replies() {
+            let events = [TraceTaskEvent::for_exec(
+                10,
+                "/tmp/a$b".into(),
+                Vec::new(),
+                RemotePtr::new_from_val(0x400000),
+            )];
+            let history = ExecHistory::new(&events, &[1]);
+            let reply = |offset, length| qxfer_exec_file_reply(&history, 10, 1, offset, length);
+            assert_eq!(reply(0, 100), Some(b"l/tmp/a}\x04b".to_vec()));
+            assert_eq!(reply(0, 4), Some(b"m/tmp".to_vec()));
+            assert_eq!(reply(4, 100), Some(b"l/a}\x04b".to_vec()));
+            assert_eq!(reply(100, 4), Some(b"l".to_vec()));
+            assert_eq!(qxfer_exec_file_reply(&history, 11, 1, 0, 100), None);
+        }
+    }
 }
@@ -0,0 +1,272 @@
+//! Probes for the things about the host that decide whether rd can record
+//! on it: perf counters, the sysctls that gate them and ptrace, CPUID
+//! faulting, seccomp and the kernel version. `rd check` prints them all;
+//! recording only cares about the ones that make it impossible.
+
+use crate::{perf_counters::probe_hardware_counter, util::cpuid_faulting_works};
+use libc::{
+    EACCES,
+    EFAULT,
+    ENODEV,
+    ENOENT,
+    EOPNOTSUPP,
+    EPERM,
+    PR_SET_SECCOMP,
+    SECCOMP_MODE_FILTER,
+};
+use nix::{errno::errno, sys::utsname::uname, unistd::geteuid};
+use std::{
+    fmt::{self, Display, Formatter},
+    fs,
+    ptr,
+};
+
+/// The oldest kernel rd is expected to work on.
+const MIN_KERNEL_VERSION: (u32, u32) = (4, 7);
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum CheckStatus {
+    Ok,
+    /// rd works, but with a limitation the user should know about.
+    Warning,
+    /// rd can't record.
+    Error,
+}
+
+impl Display for CheckStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckStatus::Ok => write!(f, "ok"),
+            CheckStatus::Warning => write!(f, "warning"),
+            CheckStatus::Error => write!(f, "error"),
+        }
+    }
+}
+
+pub struct CheckResult {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    /// What we found.
+    pub detail: String,
+    /// What to do about it, if anything.
+    pub advice: Option<String>,
+}
+
+impl CheckResult {
+    fn ok(name: &'static str, detail: String) -> CheckResult {
+        CheckResult {
+            name,
+            status: CheckStatus::Ok,
+            detail,
+            advice: None,
+        }
+    }
+
+    fn problem(
+        name: &'static str,
+        status: CheckStatus,
+        detail: String,
+        advice: &str,
+    ) -> CheckResult {
+        CheckResult {
+            name,
+            status,
+            detail,
+            advice: Some(advice.into()),
+        }
+    }
+}
+
+/// Run all the probes. None of them are fatal, unlike the PMU
+/// initialization in `perf_counters`.
+pub fn check_host() -> Vec<CheckResult> {
+    vec![
+        check_perf_counters(),
+        check_perf_event_paranoid(),
+        check_ptrace_scope(),
+        check_cpuid_faulting(),
+        check_seccomp(),
+        check_kernel_version(),
+    ]
+}
+
+fn check_perf_counters() -> CheckResult {
+    let name = "perf counters";
+    match probe_hardware_counter() {
+        Ok(()) => CheckResult::ok(name, "hardware counters can be opened".into()),
+        Err(err) if err == EACCES || err == EPERM => CheckResult::problem(
+            name,
+            CheckStatus::Error,
+            "perf_event_open() was denied".into(),
+            "Lower /proc/sys/kernel/perf_event_paranoid (see below), or run rd with \
+             CAP_PERFMON",
+        ),
+        Err(err) if err == ENOENT || err == ENODEV || err == EOPNOTSUPP => CheckResult::problem(
+            name,
+            CheckStatus::Error,
+            "no hardware performance counters are available".into(),
+            "If this is a virtual machine, enable PMU virtualization (e.g. the VMware \
+             \"virtualize CPU performance counters\" setting, or `-cpu host` with KVM)",
+        ),
+        Err(err) => CheckResult::problem(
+            name,
+            CheckStatus::Error,
+            format!("perf_event_open() failed with errno {}", err),
+            "Check that `perf stat -e branches true` works",
+        ),
+    }
+}
+
+fn check_perf_event_paranoid() -> CheckResult {
+    let name = "perf_event_paranoid";
+    let level = match read_int_sysctl("/proc/sys/kernel/perf_event_paranoid") {
+        Some(level) => level,
+        None => {
+            return CheckResult::problem(
+                name,
+                CheckStatus::Warning,
+                "/proc/sys/kernel/perf_event_paranoid can't be read".into(),
+                "Check that the kernel is built with CONFIG_PERF_EVENTS",
+            )
+        }
+    };
+    // rd only counts user space events of its own tracees, which levels up
+    // to 2 allow. Some distributions add a level 3 that denies everything.
+    if level <= 2 || geteuid().is_root() {
+        CheckResult::ok(name, format!("{}", level))
+    } else {
+        CheckResult::problem(
+            name,
+            CheckStatus::Error,
+            format!("{} doesn't allow unprivileged use of perf counters", level),
+            "Run `sudo sysctl kernel.perf_event_paranoid=2`",
+        )
+    }
+}
+
+fn check_ptrace_scope() -> CheckResult {
+    let name = "ptrace_scope";
+    let scope = match read_int_sysctl("/proc/sys/kernel/yama/ptrace_scope") {
+        Some(scope) => scope,
+        None => return CheckResult::ok(name, "Yama isn't restricting ptrace".into()),
+    };
+    let advice = "Run `sudo sysctl kernel.yama.ptrace_scope=0`";
+    match scope {
+        0 => CheckResult::ok(name, "0".into()),
+        1 => CheckResult::problem(
+            name,
+            CheckStatus::Warning,
+            "1: rd can record processes it starts, but `rd record -p` can't attach".into(),
+            advice,
+        ),
+        2 if geteuid().is_root() => CheckResult::ok(name, "2 (running as root)".into()),
+        _ => CheckResult::problem(
+            name,
+            CheckStatus::Error,
+            format!("{} doesn't allow unprivileged use of ptrace", scope),
+            advice,
+        ),
+    }
+}
+
+fn check_cpuid_faulting() -> CheckResult {
+    let name = "CPUID faulting";
+    if cpuid_faulting_works() {
+        CheckResult::ok(name, "available".into())
+    } else {
+        CheckResult::problem(
+            name,
+            CheckStatus::Warning,
+            "unavailable, so traces can only be replayed on this CPU model".into(),
+            "Use an Intel CPU from Ivy Bridge on with Linux 4.12 or later; some \
+             hypervisors don't pass the feature through",
+        )
+    }
+}
+
+fn check_seccomp() -> CheckResult {
+    let name = "seccomp";
+    // A null filter makes the kernel fail with EFAULT if it supports
+    // seccomp-bpf at all, without installing anything.
+    let ret = unsafe { libc::prctl(PR_SET_SECCOMP, SECCOMP_MODE_FILTER, ptr::null::<u8>()) };
+    if ret == -1 && errno() == EFAULT {
+        CheckResult::ok(name, "seccomp-bpf is available".into())
+    } else {
+        CheckResult::problem(
+            name,
+            CheckStatus::Error,
+            "seccomp-bpf isn't available, so syscalls can't be buffered".into(),
+            "Use a kernel built with CONFIG_SECCOMP_FILTER",
+        )
+    }
+}
+
+fn check_kernel_version() -> CheckResult {
+    let name = "kernel version";
+    let uts = uname();
+    let release = uts.release();
+    let version = match parse_kernel_version(release) {
+        Some(version) => version,
+        None => {
+            return CheckResult::problem(
+                name,
+                CheckStatus::Warning,
+                format!("can't parse kernel release `{}`", release),
+                "Known kernel bugs can't be checked for",
+            )
+        }
+    };
+    if version < MIN_KERNEL_VERSION {
+        return CheckResult::problem(
+            name,
+            CheckStatus::Warning,
+            format!(
+                "{} is older than {}.{}, which rd isn't tested on",
+                release, MIN_KERNEL_VERSION.0, MIN_KERNEL_VERSION.1
+            ),
+            "Upgrade the kernel",
+        );
+    }
+    let mut notes = Vec::new();
+    if version < (4, 8) {
+        notes.push("seccomp stops come after syscall entry stops");
+    }
+    if version < (4, 12) {
+        notes.push("no CPUID faulting");
+    }
+    if notes.is_empty() {
+        CheckResult::ok(name, release.into())
+    } else {
+        CheckResult::ok(
+            name,
+            format!("{} (working around: {})", release, notes.join(", ")),
+        )
+    }
+}
+
+fn read_int_sysctl(path: &str) -> Option<i32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// The major and minor version from a kernel release like
+/// `5.10.0-8-amd64`.
+pub fn parse_kernel_version(release: &str) -> Option<(u32, u32)> {
+    let mut parts = release.split(|c: char| !c.is_ascii_digit());
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn kernel_version() {
+        assert_eq!(parse_kernel_version("5.10.0-8-amd64"), Some((5, 10)));
+        assert_eq!(parse_kernel_version("4.7.0"), Some((4, 7)));
+        assert_eq!(parse_kernel_version("6.1"), Some((6, 1)));
+        assert_eq!(parse_kernel_version("5"), None);
+        assert_eq!(parse_kernel_version("linux"), None);
+    }
+}
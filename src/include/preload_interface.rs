@@ -61,36 +61,40 @@ pub const PRELOAD_THREAD_LOCALS_SIZE: usize = 104;
 /// NB: magic syscalls must be positive, because with at least linux
 /// 3.8.0 / eglibc 2.17, rd only gets a trap for the *entry* of invalid
 /// syscalls, not the exit.  rd can't handle that yet.
+///
+/// They start at RD_CALL_BASE, rr's RR_CALL_BASE, well clear of the kernel's
+/// own syscall numbers so new syscalls don't collide with them.
+pub const RD_CALL_BASE: u32 = 1000;
 
 /// The preload library calls SYS_rdcall_init_preload during its
 /// initialization.
-pub const SYS_rdcall_init_preload: u32 = 442;
+pub const SYS_rdcall_init_preload: u32 = RD_CALL_BASE;
 
 /// The preload library calls SYS_rdcall_init_buffers in each thread that
 /// gets created (including the initial main thread).
-pub const SYS_rdcall_init_buffers: u32 = 443;
+pub const SYS_rdcall_init_buffers: u32 = RD_CALL_BASE + 1;
 
 /// The preload library calls SYS_rdcall_notify_syscall_hook_exit when
 /// unlocking the syscallbuf and notify_after_syscall_hook_exit has been set.
 /// The word at 4/8(sp) is returned in the syscall result and the word at
 /// 8/16(sp) is stored in original_syscallno.
-pub const SYS_rdcall_notify_syscall_hook_exit: u32 = 444;
+pub const SYS_rdcall_notify_syscall_hook_exit: u32 = RD_CALL_BASE + 2;
 
 /// When the preload library detects that control data has been received in a
 /// syscallbuf'ed recvmsg, it calls this syscall with a pointer to the
 /// 'struct msg' returned.
-pub const SYS_rdcall_notify_control_msg: u32 = 445;
+pub const SYS_rdcall_notify_control_msg: u32 = RD_CALL_BASE + 3;
 
 /// When rd replay has restored the auxv vectors for a new process (completing
 /// emulation of exec), it calls this syscall. It takes one parameter, the tid
 /// of the task that it has restored auxv vectors for.
-pub const SYS_rdcall_reload_auxv: u32 = 446;
+pub const SYS_rdcall_reload_auxv: u32 = RD_CALL_BASE + 4;
 
 /// When rd replay has flushed a syscallbuf 'mprotect' record, notify any outer
 /// rd of that flush. The first parameter is the tid of the task, the second
 /// parameter is the address, the third parameter is the length, and the
 /// fourth parameter is the prot.
-pub const SYS_rdcall_mprotect_record: u32 = 447;
+pub const SYS_rdcall_mprotect_record: u32 = RD_CALL_BASE + 5;
 
 /// To support syscall buffering, we replace syscall instructions with a "call"
 /// instruction that calls a hook in the preload library to handle the syscall.
@@ -0,0 +1,142 @@
+//! io_uring. The submission and completion queues are memory shared with the
+//! kernel, which consumes submissions and posts completions (and writes the
+//! data of reads) whenever it likes, so none of it shows up as syscalls rd
+//! can record. By default rd makes io_uring_setup() fail with ENOSYS, which
+//! programs and liburing take as "no io_uring here" and fall back to plain
+//! syscalls.
+//!
+//! With `--io-uring=record`, rings are set up with
+//! IORING_SETUP_DEFER_TASKRUN (Linux 6.1), so the kernel only posts
+//! completions while the task that set up the ring is in io_uring_enter().
+//! rd records what each io_uring_enter() changed in the rings, plus the
+//! buffers of completed reads, and replay emulates the whole thing. Only
+//! operations whose effects on memory rd knows how to record are allowed.
+//! Read buffers are written before their completion is posted, so a program
+//! that looks at one before reaping the completion sees different data
+//! during replay; that's a race in the program anyway.
+//!
+//! The constants aren't in the headers we generate bindings from on older
+//! systems.
+
+/// mmap() offsets of the parts of a ring.
+pub const IORING_OFF_SQ_RING: u64 = 0;
+pub const IORING_OFF_CQ_RING: u64 = 0x800_0000;
+pub const IORING_OFF_SQES: u64 = 0x1000_0000;
+
+pub const IORING_SETUP_SINGLE_ISSUER: u32 = 1 << 12;
+pub const IORING_SETUP_DEFER_TASKRUN: u32 = 1 << 13;
+
+/// Setup flags that don't get in the way of recording: size and sharing
+/// options. Polling, kernel submission threads and user-provided ring memory
+/// all let the kernel touch the rings outside io_uring_enter(). So does
+/// IORING_SETUP_TASKRUN_FLAG, and the bigger SQEs and CQEs just aren't
+/// supported.
+pub const RECORDABLE_SETUP_FLAGS: u32 = (1 << 3) // CQSIZE
+    | (1 << 4) // CLAMP
+    | (1 << 5) // ATTACH_WQ
+    | (1 << 6) // R_DISABLED
+    | (1 << 7) // SUBMIT_ALL
+    | (1 << 8) // COOP_TASKRUN
+    | IORING_SETUP_SINGLE_ISSUER
+    | IORING_SETUP_DEFER_TASKRUN;
+
+/// The sq and cq rings share one mapping, at IORING_OFF_SQ_RING.
+pub const IORING_FEAT_SINGLE_MMAP: u32 = 1 << 0;
+
+/// The kernel picks the buffer of the operation from a provided buffer
+/// group, so rd can't tell where its data went.
+pub const IOSQE_BUFFER_SELECT: u8 = 1 << 5;
+
+/// More completions will follow for this submission.
+pub const IORING_CQE_F_MORE: u32 = 1 << 1;
+
+pub const IORING_REGISTER_PROBE: u32 = 8;
+
+/// What an io_uring operation writes to tracee memory when it completes.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum IoUringOutput {
+    Nothing,
+    /// `res` bytes at `addr`.
+    Buffer,
+    /// `res` bytes spread over the `len` iovecs at `addr`.
+    Iovecs,
+}
+
+/// The output of `opcode`, or `None` if rd can't record it: it writes
+/// memory in ways we don't track, or creates or closes fds behind the fd
+/// table's back.
+pub fn io_uring_op_output(opcode: u8) -> Option<IoUringOutput> {
+    match opcode {
+        // READV
+        1 => Some(IoUringOutput::Iovecs),
+        // READ_FIXED, READ, RECV
+        4 | 22 | 27 => Some(IoUringOutput::Buffer),
+        // NOP, WRITEV, FSYNC, WRITE_FIXED, POLL_ADD, POLL_REMOVE,
+        // SYNC_FILE_RANGE, SENDMSG, TIMEOUT, TIMEOUT_REMOVE, ASYNC_CANCEL,
+        // LINK_TIMEOUT, CONNECT, FALLOCATE, WRITE, FADVISE, SEND, SHUTDOWN
+        0 | 2 | 3 | 5 | 6 | 7 | 8 | 9 | 11 | 12 | 14 | 15 | 16 | 17 | 23 | 24 | 26 | 34 => {
+            Some(IoUringOutput::Nothing)
+        }
+        _ => None,
+    }
+}
+
+/// Whether rd can record io_uring_register() `opcode`. Registered buffers
+/// and files are fine, the kernel only uses them on behalf of operations
+/// we check anyway. IORING_REGISTER_PROBE writes its argument.
+pub fn io_uring_register_opcode_recordable(opcode: u32) -> bool {
+    // REGISTER_BUFFERS, UNREGISTER_BUFFERS, REGISTER_FILES,
+    // UNREGISTER_FILES, REGISTER_EVENTFD, UNREGISTER_EVENTFD,
+    // REGISTER_FILES_UPDATE, REGISTER_EVENTFD_ASYNC, REGISTER_PROBE
+    opcode <= 8
+}
+
+/// Size of `struct io_uring_probe` with `nr_ops` entries.
+pub fn io_uring_probe_size(nr_ops: usize) -> usize {
+    16 + 8 * nr_ops
+}
+
+/// The entries from `head` up to `tail` of a ring of `entries` entries, as
+/// (first index, count) runs that are contiguous in memory.
+pub fn ring_runs(head: u32, tail: u32, entries: u32) -> Vec<(u32, u32)> {
+    debug_assert!(entries.is_power_of_two());
+    let mut runs = Vec::new();
+    let mut left = tail.wrapping_sub(head);
+    let mut index = head & (entries - 1);
+    while left > 0 {
+        let count = left.min(entries - index);
+        runs.push((index, count));
+        left -= count;
+        index = 0;
+    }
+    runs
+}
+
+/// What to do with a tracee's io_uring_setup(), `--io-uring`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum IoUringPolicy {
+    /// Fail it with ENOSYS.
+    Deny,
+    /// Let it through and record the rings.
+    Record,
+}
+
+impl Default for IoUringPolicy {
+    fn default() -> Self {
+        IoUringPolicy::Deny
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ring_runs_wrap() {
+        assert_eq!(ring_runs(0, 0, 8), vec![]);
+        assert_eq!(ring_runs(2, 5, 8), vec![(2, 3)]);
+        assert_eq!(ring_runs(6, 10, 8), vec![(6, 2), (0, 2)]);
+        assert_eq!(ring_runs(8, 16, 8), vec![(0, 8)]);
+        assert_eq!(ring_runs(u32::MAX, 1, 8), vec![(7, 1), (0, 1)]);
+    }
+}
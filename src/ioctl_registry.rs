@@ -0,0 +1,337 @@
+//! Recording ioctl()s. Which tracee memory an ioctl writes depends on the
+//! request and often the driver, so no list of requests will ever be
+//! complete. Instead of a match over every request rd knows, handlers are
+//! looked up in `IOCTL_HANDLERS`, first by the exact request and then by its
+//! _IOC type and number, ignoring the direction and size bits. A handler says
+//! which buffers to record once the ioctl has succeeded, or that the ioctl
+//! can't be recorded and must fail.
+//!
+//! Requests without a handler get `record_by_ioc_bits()`: most requests
+//! encode in their number whether the kernel writes through the argument
+//! and how much, so that's recorded, along with a warning that rd doesn't
+//! know the request, since older requests encode nothing and may write
+//! memory anyway. Supporting another ioctl means adding an entry to the
+//! table.
+
+use crate::{
+    remote_ptr::{RemotePtr, Void},
+    session::task::{task_common::read_val_mem, Task},
+};
+use drm::DRM_IOCTL_HANDLERS;
+use libc::{c_int, FIOCLEX, FIONBIO, FIONCLEX, FIONREAD};
+use std::{
+    collections::{HashMap, HashSet},
+    mem::size_of,
+    sync::Mutex,
+};
+use tty::TTY_IOCTL_HANDLERS;
+use v4l2::V4L2_IOCTL_HANDLERS;
+
+pub mod drm;
+pub mod tty;
+pub mod v4l2;
+
+const IOC_NRBITS: u32 = 8;
+const IOC_TYPEBITS: u32 = 8;
+const IOC_SIZEBITS: u32 = 14;
+
+const IOC_NRSHIFT: u32 = 0;
+const IOC_TYPESHIFT: u32 = IOC_NRSHIFT + IOC_NRBITS;
+const IOC_SIZESHIFT: u32 = IOC_TYPESHIFT + IOC_TYPEBITS;
+const IOC_DIRSHIFT: u32 = IOC_SIZESHIFT + IOC_SIZEBITS;
+
+/// The kernel writes to userspace, i.e. the tracee's memory changes.
+pub const IOC_READ: u32 = 2;
+/// The kernel reads from userspace.
+pub const IOC_WRITE: u32 = 1;
+
+/// A request number taken apart, as the kernel's _IOC_DIR(), _IOC_TYPE(),
+/// _IOC_NR() and _IOC_SIZE() would.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct IocBits {
+    pub dir: u32,
+    pub type_: u8,
+    pub nr: u8,
+    pub size: usize,
+}
+
+/// The request number _IOC() makes of its arguments.
+pub const fn ioc(dir: u32, type_: u8, nr: u8, size: usize) -> u32 {
+    (dir << IOC_DIRSHIFT)
+        | ((type_ as u32) << IOC_TYPESHIFT)
+        | ((nr as u32) << IOC_NRSHIFT)
+        | ((size as u32) << IOC_SIZESHIFT)
+}
+
+impl IocBits {
+    pub fn decode(request: u32) -> IocBits {
+        IocBits {
+            dir: request >> IOC_DIRSHIFT,
+            type_: (request >> IOC_TYPESHIFT) as u8,
+            nr: (request >> IOC_NRSHIFT) as u8,
+            size: ((request >> IOC_SIZESHIFT) & ((1 << IOC_SIZEBITS) - 1)) as usize,
+        }
+    }
+}
+
+/// A successful ioctl() whose effects are being recorded.
+#[derive(Copy, Clone, Debug)]
+pub struct IoctlCall {
+    pub fd: i32,
+    pub request: u32,
+    /// The third argument, a pointer for most requests.
+    pub arg: RemotePtr<Void>,
+    pub result: isize,
+}
+
+/// Tracee memory an ioctl wrote.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct IoctlBuffer {
+    pub addr: RemotePtr<Void>,
+    pub size: usize,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum IoctlKey {
+    Request(u32),
+    /// Requests of type `type_` numbered `first` to `last`, whatever their
+    /// direction and size bits say. For drivers whose requests are handled
+    /// alike, and for requests whose size differs between architectures.
+    Numbers {
+        type_: u8,
+        first: u8,
+        last: u8,
+    },
+}
+
+impl IoctlKey {
+    /// The request of type `type_` numbered `nr`, whatever its size.
+    pub const fn number(type_: u8, nr: u8) -> IoctlKey {
+        IoctlKey::Numbers {
+            type_,
+            first: nr,
+            last: nr,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub enum IoctlAction {
+    /// Let the ioctl run, then record the buffers this returns. The task is
+    /// stopped at syscall exit, so it can read structs that point to more
+    /// buffers.
+    Record(fn(&mut dyn Task, &IoctlCall) -> Vec<IoctlBuffer>),
+    /// Don't run the ioctl; fail it with this errno. For requests whose
+    /// effects rd can't record.
+    Deny(i32),
+}
+
+pub struct IoctlHandler {
+    pub key: IoctlKey,
+    pub name: &'static str,
+    pub action: IoctlAction,
+}
+
+/// Handlers for the requests rd knows. An exact request takes precedence
+/// over a range of numbers, and a narrower range over a wider one.
+static IOCTL_HANDLERS: &[IoctlHandler] = &[
+    IoctlHandler {
+        key: IoctlKey::Request(FIONREAD as u32),
+        name: "FIONREAD",
+        action: IoctlAction::Record(record_arg::<c_int>),
+    },
+    IoctlHandler {
+        key: IoctlKey::Request(FIONBIO as u32),
+        name: "FIONBIO",
+        action: IoctlAction::Record(record_nothing),
+    },
+    IoctlHandler {
+        key: IoctlKey::Request(FIOCLEX as u32),
+        name: "FIOCLEX",
+        action: IoctlAction::Record(record_nothing),
+    },
+    IoctlHandler {
+        key: IoctlKey::Request(FIONCLEX as u32),
+        name: "FIONCLEX",
+        action: IoctlAction::Record(record_nothing),
+    },
+    // perf_event fds. Their requests all encode what they write.
+    IoctlHandler {
+        key: IoctlKey::Numbers {
+            type_: b'$',
+            first: 0,
+            last: 0xff,
+        },
+        name: "PERF_EVENT_IOC_*",
+        action: IoctlAction::Record(record_by_ioc_bits),
+    },
+];
+
+lazy_static! {
+    static ref HANDLERS_BY_REQUEST: HashMap<u32, &'static IoctlHandler> = all_handlers()
+        .filter_map(|h| match h.key {
+            IoctlKey::Request(request) => Some((request, h)),
+            IoctlKey::Numbers { .. } => None,
+        })
+        .collect();
+    /// Unknown requests that have been warned about.
+    static ref WARNED_REQUESTS: Mutex<HashSet<u32>> = Mutex::new(HashSet::new());
+}
+
+fn all_handlers() -> impl Iterator<Item = &'static IoctlHandler> {
+    IOCTL_HANDLERS
+        .iter()
+        .chain(DRM_IOCTL_HANDLERS.iter())
+        .chain(TTY_IOCTL_HANDLERS.iter())
+        .chain(V4L2_IOCTL_HANDLERS.iter())
+}
+
+/// The handler for `request`, or `None` if rd doesn't know it and it should
+/// be recorded with `record_by_ioc_bits()`.
+pub fn ioctl_handler(request: u32) -> Option<&'static IoctlHandler> {
+    if let Some(&handler) = HANDLERS_BY_REQUEST.get(&request) {
+        return Some(handler);
+    }
+    let bits = IocBits::decode(request);
+    all_handlers()
+        .filter_map(|h| match h.key {
+            IoctlKey::Numbers { type_, first, last }
+                if type_ == bits.type_ && first <= bits.nr && bits.nr <= last =>
+            {
+                Some((last - first, h))
+            }
+            _ => None,
+        })
+        .min_by_key(|&(width, _)| width)
+        .map(|(_, h)| h)
+}
+
+/// What to do with `request`, and whether rd had to guess.
+pub fn ioctl_action(request: u32) -> (IoctlAction, bool) {
+    match ioctl_handler(request) {
+        Some(handler) => (handler.action, false),
+        None => (IoctlAction::Record(record_by_ioc_bits), true),
+    }
+}
+
+/// Whether to warn about the unknown `request`. True the first time only.
+pub fn should_warn_about_request(request: u32) -> bool {
+    WARNED_REQUESTS.lock().unwrap().insert(request)
+}
+
+/// The default handler. Records what the request's _IOC bits say the kernel
+/// writes through the argument, if anything.
+pub fn record_by_ioc_bits(_t: &mut dyn Task, call: &IoctlCall) -> Vec<IoctlBuffer> {
+    let bits = IocBits::decode(call.request);
+    if bits.dir & IOC_READ == 0 || bits.size == 0 {
+        return Vec::new();
+    }
+    vec![IoctlBuffer {
+        addr: call.arg,
+        size: bits.size,
+    }]
+}
+
+/// For requests that write a `T` through the argument.
+pub fn record_arg<T>(_t: &mut dyn Task, call: &IoctlCall) -> Vec<IoctlBuffer> {
+    vec![IoctlBuffer {
+        addr: call.arg,
+        size: size_of::<T>(),
+    }]
+}
+
+/// For requests that don't write tracee memory.
+pub fn record_nothing(_t: &mut dyn Task, _call: &IoctlCall) -> Vec<IoctlBuffer> {
+    Vec::new()
+}
+
+/// `count` elements of `elem_size` bytes at `addr` in the tracee, unless
+/// there are none.
+///
+/// The counts the kernel leaves in the structs are how many elements there
+/// are, even if the tracee passed a smaller array and got none of them. Then
+/// the buffer goes past the end of the array, but it's unchanged memory, so
+/// recording it does no harm.
+pub fn array(addr: u64, count: u64, elem_size: usize) -> Option<IoctlBuffer> {
+    if addr == 0 || count == 0 {
+        return None;
+    }
+    Some(IoctlBuffer {
+        addr: RemotePtr::new_from_val(addr as usize),
+        size: count as usize * elem_size,
+    })
+}
+
+/// The struct at the argument, whose size the request encodes.
+pub fn read_arg<T>(t: &mut dyn Task, call: &IoctlCall) -> T {
+    read_val_mem(t, RemotePtr::cast(call.arg), None)
+}
+
+/// Whether the request is for the x86 layout of `T`, a struct with `size_t`s
+/// or pointers in it, rather than the x86-64 one. Its size tells.
+pub fn is_32bit_layout<T>(call: &IoctlCall) -> bool {
+    IocBits::decode(call.request).size == size_of::<T>()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use libc::TCGETS;
+
+    #[test]
+    fn decode_ioc_bits() {
+        // FS_IOC_GETFLAGS on x86-64, _IOR('f', 1, long).
+        assert_eq!(
+            IocBits::decode(0x8008_6601),
+            IocBits {
+                dir: IOC_READ,
+                type_: b'f',
+                nr: 1,
+                size: 8
+            }
+        );
+        assert_eq!(ioc(IOC_READ, b'f', 1, 8), 0x8008_6601);
+        // TCGETS predates _IOC and encodes nothing but its type.
+        let bits = IocBits::decode(TCGETS as u32);
+        assert_eq!((bits.dir, bits.type_, bits.size), (0, b'T', 0));
+    }
+
+    #[test]
+    fn find_handlers() {
+        assert_eq!(ioctl_handler(TCGETS as u32).unwrap().name, "TCGETS");
+        // PERF_EVENT_IOC_ID, _IOR('$', 7, __u64 *).
+        assert_eq!(ioctl_handler(0x8008_2407).unwrap().name, "PERF_EVENT_IOC_*");
+        assert!(ioctl_handler(0x8008_6601).is_none());
+        let requests = all_handlers().filter(|h| match h.key {
+            IoctlKey::Request(_) => true,
+            IoctlKey::Numbers { .. } => false,
+        });
+        assert_eq!(
+            requests.count(),
+            HANDLERS_BY_REQUEST.len(),
+            "duplicate request"
+        );
+    }
+
+    #[test]
+    fn table_helpers() {
+        assert_eq!(
+            array(0x1000, 3, 16),
+            Some(IoctlBuffer {
+                addr: RemotePtr::new_from_val(0x1000),
+                size: 48
+            })
+        );
+        assert_eq!(array(0, 3, 16), None);
+        assert_eq!(array(0x1000, 0, 16), None);
+        let call = |size| IoctlCall {
+            fd: 3,
+            request: ioc(IOC_READ, b'V', 0x11, size),
+            arg: RemotePtr::null(),
+            result: 0,
+        };
+        // A struct with a pointer in it, 8 bytes on x86 and 16 on x86-64.
+        assert!(is_32bit_layout::<[u32; 2]>(&call(8)));
+        assert!(!is_32bit_layout::<[u32; 2]>(&call(16)));
+    }
+}
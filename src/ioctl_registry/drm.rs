@@ -0,0 +1,382 @@
+//! DRM, the GPU device nodes in /dev/dri. Programs often open one at
+//! startup just to see which driver it is and which displays it drives, and
+//! the core ioctls for that are recorded here, along with creating and
+//! sharing GEM buffer handles. Getting the GPU to do any work takes the
+//! driver's own ioctls, whose buffers rd doesn't know the layout of, and the
+//! GPU then writes memory behind rd's back, so those are denied. Mesa and
+//! friends take that as an unusable driver and render in software.
+
+use super::{
+    array,
+    is_32bit_layout,
+    read_arg,
+    record_by_ioc_bits,
+    IoctlAction,
+    IoctlBuffer,
+    IoctlCall,
+    IoctlHandler,
+    IoctlKey,
+};
+use crate::session::task::Task;
+use libc::EINVAL;
+use std::mem::size_of;
+
+const DRM_IOCTL_BASE: u8 = b'd';
+
+/// Driver-specific requests are numbered from here to `DRM_COMMAND_END`.
+const DRM_COMMAND_BASE: u8 = 0x40;
+const DRM_COMMAND_END: u8 = 0x9f;
+
+/// `struct drm_version`, with `Word` the size of a `size_t` and a pointer.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct drm_version<Word: Copy> {
+    version_major: i32,
+    version_minor: i32,
+    version_patchlevel: i32,
+    name_len: Word,
+    name: Word,
+    date_len: Word,
+    date: Word,
+    desc_len: Word,
+    desc: Word,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct drm_unique<Word: Copy> {
+    unique_len: Word,
+    unique: Word,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct drm_mode_card_res {
+    fb_id_ptr: u64,
+    crtc_id_ptr: u64,
+    connector_id_ptr: u64,
+    encoder_id_ptr: u64,
+    count_fbs: u32,
+    count_crtcs: u32,
+    count_connectors: u32,
+    count_encoders: u32,
+    min_width: u32,
+    max_width: u32,
+    min_height: u32,
+    max_height: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct drm_mode_get_connector {
+    encoders_ptr: u64,
+    modes_ptr: u64,
+    props_ptr: u64,
+    prop_values_ptr: u64,
+    count_modes: u32,
+    count_props: u32,
+    count_encoders: u32,
+    encoder_id: u32,
+    connector_id: u32,
+    connector_type: u32,
+    connector_type_id: u32,
+    connection: u32,
+    mm_width: u32,
+    mm_height: u32,
+    subpixel: u32,
+    pad: u32,
+}
+
+/// Size of `struct drm_mode_modeinfo`.
+const DRM_MODE_MODEINFO_SIZE: usize = 68;
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct drm_mode_get_property {
+    values_ptr: u64,
+    enum_blob_ptr: u64,
+    prop_id: u32,
+    flags: u32,
+    name: [u8; 32],
+    count_values: u32,
+    count_enum_blobs: u32,
+}
+
+/// Size of `struct drm_mode_property_enum`.
+const DRM_MODE_PROPERTY_ENUM_SIZE: usize = 40;
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct drm_mode_get_blob {
+    blob_id: u32,
+    length: u32,
+    data: u64,
+}
+
+/// Packed like on x86, where a u64 is only 4-aligned, so reading it never
+/// goes past the end of the struct.
+#[repr(C, packed(4))]
+#[derive(Copy, Clone, Default)]
+struct drm_mode_get_plane_res {
+    plane_id_ptr: u64,
+    count_planes: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct drm_mode_get_plane {
+    plane_id: u32,
+    crtc_id: u32,
+    fb_id: u32,
+    possible_crtcs: u32,
+    gamma_size: u32,
+    count_format_types: u32,
+    format_type_ptr: u64,
+}
+
+#[repr(C, packed(4))]
+#[derive(Copy, Clone, Default)]
+struct drm_mode_obj_get_properties {
+    props_ptr: u64,
+    prop_values_ptr: u64,
+    count_props: u32,
+    obj_id: u32,
+    obj_type: u32,
+}
+
+const fn drm(nr: u8) -> IoctlKey {
+    IoctlKey::number(DRM_IOCTL_BASE, nr)
+}
+
+/// The handler for a request whose _IOC bits say what it writes.
+macro_rules! drm_by_ioc_bits {
+    ($nr:expr, $name:expr) => {
+        IoctlHandler {
+            key: drm($nr),
+            name: $name,
+            action: IoctlAction::Record(record_by_ioc_bits),
+        }
+    };
+}
+
+pub static DRM_IOCTL_HANDLERS: &[IoctlHandler] = &[
+    IoctlHandler {
+        key: drm(0x00),
+        name: "DRM_IOCTL_VERSION",
+        action: IoctlAction::Record(record_version),
+    },
+    IoctlHandler {
+        key: drm(0x01),
+        name: "DRM_IOCTL_GET_UNIQUE",
+        action: IoctlAction::Record(record_unique),
+    },
+    drm_by_ioc_bits!(0x02, "DRM_IOCTL_GET_MAGIC"),
+    drm_by_ioc_bits!(0x09, "DRM_IOCTL_GEM_CLOSE"),
+    drm_by_ioc_bits!(0x0a, "DRM_IOCTL_GEM_FLINK"),
+    drm_by_ioc_bits!(0x0b, "DRM_IOCTL_GEM_OPEN"),
+    drm_by_ioc_bits!(0x0c, "DRM_IOCTL_GET_CAP"),
+    drm_by_ioc_bits!(0x0d, "DRM_IOCTL_SET_CLIENT_CAP"),
+    drm_by_ioc_bits!(0x2d, "DRM_IOCTL_PRIME_HANDLE_TO_FD"),
+    drm_by_ioc_bits!(0x2e, "DRM_IOCTL_PRIME_FD_TO_HANDLE"),
+    IoctlHandler {
+        key: drm(0xa0),
+        name: "DRM_IOCTL_MODE_GETRESOURCES",
+        action: IoctlAction::Record(record_card_res),
+    },
+    drm_by_ioc_bits!(0xa1, "DRM_IOCTL_MODE_GETCRTC"),
+    drm_by_ioc_bits!(0xa6, "DRM_IOCTL_MODE_GETENCODER"),
+    IoctlHandler {
+        key: drm(0xa7),
+        name: "DRM_IOCTL_MODE_GETCONNECTOR",
+        action: IoctlAction::Record(record_connector),
+    },
+    IoctlHandler {
+        key: drm(0xaa),
+        name: "DRM_IOCTL_MODE_GETPROPERTY",
+        action: IoctlAction::Record(record_property),
+    },
+    IoctlHandler {
+        key: drm(0xac),
+        name: "DRM_IOCTL_MODE_GETPROPBLOB",
+        action: IoctlAction::Record(record_prop_blob),
+    },
+    drm_by_ioc_bits!(0xb2, "DRM_IOCTL_MODE_CREATE_DUMB"),
+    drm_by_ioc_bits!(0xb3, "DRM_IOCTL_MODE_MAP_DUMB"),
+    drm_by_ioc_bits!(0xb4, "DRM_IOCTL_MODE_DESTROY_DUMB"),
+    IoctlHandler {
+        key: drm(0xb5),
+        name: "DRM_IOCTL_MODE_GETPLANERESOURCES",
+        action: IoctlAction::Record(record_plane_res),
+    },
+    IoctlHandler {
+        key: drm(0xb6),
+        name: "DRM_IOCTL_MODE_GETPLANE",
+        action: IoctlAction::Record(record_plane),
+    },
+    IoctlHandler {
+        key: drm(0xb9),
+        name: "DRM_IOCTL_MODE_OBJ_GETPROPERTIES",
+        action: IoctlAction::Record(record_obj_properties),
+    },
+    // Submitting GPU work, and everything else drivers add.
+    IoctlHandler {
+        key: IoctlKey::Numbers {
+            type_: DRM_IOCTL_BASE,
+            first: DRM_COMMAND_BASE,
+            last: DRM_COMMAND_END,
+        },
+        name: "driver-specific DRM ioctl",
+        action: IoctlAction::Deny(EINVAL),
+    },
+];
+
+fn record_version(t: &mut dyn Task, call: &IoctlCall) -> Vec<IoctlBuffer> {
+    if is_32bit_layout::<drm_version<u32>>(call) {
+        record_version_arch::<u32>(t, call)
+    } else {
+        record_version_arch::<u64>(t, call)
+    }
+}
+
+fn record_version_arch<Word: Copy + Into<u64>>(
+    t: &mut dyn Task,
+    call: &IoctlCall,
+) -> Vec<IoctlBuffer> {
+    let v: drm_version<Word> = read_arg(t, call);
+    let mut buffers = record_by_ioc_bits(t, call);
+    buffers.extend(array(v.name.into(), v.name_len.into(), 1));
+    buffers.extend(array(v.date.into(), v.date_len.into(), 1));
+    buffers.extend(array(v.desc.into(), v.desc_len.into(), 1));
+    buffers
+}
+
+fn record_unique(t: &mut dyn Task, call: &IoctlCall) -> Vec<IoctlBuffer> {
+    if is_32bit_layout::<drm_unique<u32>>(call) {
+        record_unique_arch::<u32>(t, call)
+    } else {
+        record_unique_arch::<u64>(t, call)
+    }
+}
+
+fn record_unique_arch<Word: Copy + Into<u64>>(
+    t: &mut dyn Task,
+    call: &IoctlCall,
+) -> Vec<IoctlBuffer> {
+    let u: drm_unique<Word> = read_arg(t, call);
+    let mut buffers = record_by_ioc_bits(t, call);
+    buffers.extend(array(u.unique.into(), u.unique_len.into(), 1));
+    buffers
+}
+
+fn record_card_res(t: &mut dyn Task, call: &IoctlCall) -> Vec<IoctlBuffer> {
+    let res: drm_mode_card_res = read_arg(t, call);
+    let mut buffers = record_by_ioc_bits(t, call);
+    let id = size_of::<u32>();
+    buffers.extend(array(res.fb_id_ptr, res.count_fbs.into(), id));
+    buffers.extend(array(res.crtc_id_ptr, res.count_crtcs.into(), id));
+    buffers.extend(array(res.connector_id_ptr, res.count_connectors.into(), id));
+    buffers.extend(array(res.encoder_id_ptr, res.count_encoders.into(), id));
+    buffers
+}
+
+fn record_connector(t: &mut dyn Task, call: &IoctlCall) -> Vec<IoctlBuffer> {
+    let c: drm_mode_get_connector = read_arg(t, call);
+    let mut buffers = record_by_ioc_bits(t, call);
+    buffers.extend(array(
+        c.encoders_ptr,
+        c.count_encoders.into(),
+        size_of::<u32>(),
+    ));
+    buffers.extend(array(
+        c.modes_ptr,
+        c.count_modes.into(),
+        DRM_MODE_MODEINFO_SIZE,
+    ));
+    buffers.extend(array(c.props_ptr, c.count_props.into(), size_of::<u32>()));
+    buffers.extend(array(
+        c.prop_values_ptr,
+        c.count_props.into(),
+        size_of::<u64>(),
+    ));
+    buffers
+}
+
+fn record_property(t: &mut dyn Task, call: &IoctlCall) -> Vec<IoctlBuffer> {
+    let p: drm_mode_get_property = read_arg(t, call);
+    let mut buffers = record_by_ioc_bits(t, call);
+    buffers.extend(array(p.values_ptr, p.count_values.into(), size_of::<u64>()));
+    buffers.extend(array(
+        p.enum_blob_ptr,
+        p.count_enum_blobs.into(),
+        DRM_MODE_PROPERTY_ENUM_SIZE,
+    ));
+    buffers
+}
+
+fn record_prop_blob(t: &mut dyn Task, call: &IoctlCall) -> Vec<IoctlBuffer> {
+    let b: drm_mode_get_blob = read_arg(t, call);
+    let mut buffers = record_by_ioc_bits(t, call);
+    buffers.extend(array(b.data, b.length.into(), 1));
+    buffers
+}
+
+fn record_plane_res(t: &mut dyn Task, call: &IoctlCall) -> Vec<IoctlBuffer> {
+    let res: drm_mode_get_plane_res = read_arg(t, call);
+    let mut buffers = record_by_ioc_bits(t, call);
+    buffers.extend(array(
+        res.plane_id_ptr,
+        res.count_planes.into(),
+        size_of::<u32>(),
+    ));
+    buffers
+}
+
+fn record_plane(t: &mut dyn Task, call: &IoctlCall) -> Vec<IoctlBuffer> {
+    let p: drm_mode_get_plane = read_arg(t, call);
+    let mut buffers = record_by_ioc_bits(t, call);
+    buffers.extend(array(
+        p.format_type_ptr,
+        p.count_format_types.into(),
+        size_of::<u32>(),
+    ));
+    buffers
+}
+
+fn record_obj_properties(t: &mut dyn Task, call: &IoctlCall) -> Vec<IoctlBuffer> {
+    let p: drm_mode_obj_get_properties = read_arg(t, call);
+    let mut buffers = record_by_ioc_bits(t, call);
+    buffers.extend(array(p.props_ptr, p.count_props.into(), size_of::<u32>()));
+    buffers.extend(array(
+        p.prop_values_ptr,
+        p.count_props.into(),
+        size_of::<u64>(),
+    ));
+    buffers
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ioctl_registry::{ioc, ioctl_handler, IOC_READ, IOC_WRITE};
+
+    #[test]
+    fn drm_handlers() {
+        let rw = IOC_READ | IOC_WRITE;
+        // The struct holds size_t's, so the request differs by architecture.
+        let version_x86 = ioc(rw, b'd', 0x00, 36);
+        let version_x64 = ioc(rw, b'd', 0x00, 64);
+        assert_eq!(size_of::<drm_version<u32>>(), 36);
+        assert_eq!(size_of::<drm_version<u64>>(), 64);
+        for &request in &[version_x86, version_x64] {
+            assert_eq!(ioctl_handler(request).unwrap().name, "DRM_IOCTL_VERSION");
+        }
+        // DRM_IOCTL_I915_GEM_EXECBUFFER2.
+        let execbuffer2 = ioc(IOC_WRITE, b'd', 0x69, 64);
+        match ioctl_handler(execbuffer2).unwrap().action {
+            IoctlAction::Deny(errno) => assert_eq!(errno, EINVAL),
+            IoctlAction::Record(_) => panic!("GPU work submission recorded"),
+        }
+        assert_eq!(size_of::<drm_mode_get_plane_res>(), 12);
+        assert_eq!(size_of::<drm_mode_obj_get_properties>(), 28);
+    }
+}
@@ -0,0 +1,170 @@
+//! Terminals. Every interactive program asks about its terminal at startup,
+//! isatty() alone is a TCGETS, and shells and full-screen programs keep
+//! changing its settings and the foreground process group as they go. Most
+//! of these requests predate _IOC and encode nothing but their type, 'T',
+//! and number, so each one needs an entry saying what it writes. The numbers
+//! are the same on x86 and x86-64.
+//!
+//! Replay doesn't run the requests, so the terminal it runs in is left
+//! alone. The tracees see the terminal as it was when recording.
+
+use super::{record_arg, record_by_ioc_bits, record_nothing, IoctlAction, IoctlHandler, IoctlKey};
+use crate::kernel_abi::x64::termio;
+use libc::{c_int, pid_t, termios, winsize};
+
+const TTY_IOCTL_BASE: u8 = b'T';
+
+/// A request numbered `nr`, whatever its size. For requests with _IOC bits.
+const fn tty(nr: u8) -> IoctlKey {
+    IoctlKey::number(TTY_IOCTL_BASE, nr)
+}
+
+/// A request from before _IOC numbered `nr`. It has to be matched exactly:
+/// a request with _IOC bits and the same number is a different one.
+const fn legacy(nr: u8) -> IoctlKey {
+    IoctlKey::Request(((TTY_IOCTL_BASE as u32) << 8) | nr as u32)
+}
+
+/// The handler for a request whose _IOC bits say what it writes.
+macro_rules! tty_by_ioc_bits {
+    ($nr:expr, $name:expr) => {
+        IoctlHandler {
+            key: tty($nr),
+            name: $name,
+            action: IoctlAction::Record(record_by_ioc_bits),
+        }
+    };
+}
+
+/// The handler for a request from before _IOC that writes nothing to tracee
+/// memory.
+macro_rules! tty_nothing {
+    ($nr:expr, $name:expr) => {
+        IoctlHandler {
+            key: legacy($nr),
+            name: $name,
+            action: IoctlAction::Record(record_nothing),
+        }
+    };
+}
+
+pub static TTY_IOCTL_HANDLERS: &[IoctlHandler] = &[
+    IoctlHandler {
+        key: legacy(0x01),
+        name: "TCGETS",
+        action: IoctlAction::Record(record_arg::<termios>),
+    },
+    tty_nothing!(0x02, "TCSETS"),
+    tty_nothing!(0x03, "TCSETSW"),
+    tty_nothing!(0x04, "TCSETSF"),
+    // The struct is the same on x86.
+    IoctlHandler {
+        key: legacy(0x05),
+        name: "TCGETA",
+        action: IoctlAction::Record(record_arg::<termio>),
+    },
+    tty_nothing!(0x06, "TCSETA"),
+    tty_nothing!(0x07, "TCSETAW"),
+    tty_nothing!(0x08, "TCSETAF"),
+    tty_nothing!(0x09, "TCSBRK"),
+    tty_nothing!(0x0a, "TCXONC"),
+    tty_nothing!(0x0b, "TCFLSH"),
+    tty_nothing!(0x0c, "TIOCEXCL"),
+    tty_nothing!(0x0d, "TIOCNXCL"),
+    tty_nothing!(0x0e, "TIOCSCTTY"),
+    IoctlHandler {
+        key: legacy(0x0f),
+        name: "TIOCGPGRP",
+        action: IoctlAction::Record(record_arg::<pid_t>),
+    },
+    tty_nothing!(0x10, "TIOCSPGRP"),
+    IoctlHandler {
+        key: legacy(0x11),
+        name: "TIOCOUTQ",
+        action: IoctlAction::Record(record_arg::<c_int>),
+    },
+    // The input it fakes was read from the terminal when recording, and is
+    // recorded with the read.
+    tty_nothing!(0x12, "TIOCSTI"),
+    IoctlHandler {
+        key: legacy(0x13),
+        name: "TIOCGWINSZ",
+        action: IoctlAction::Record(record_arg::<winsize>),
+    },
+    tty_nothing!(0x14, "TIOCSWINSZ"),
+    IoctlHandler {
+        key: legacy(0x15),
+        name: "TIOCMGET",
+        action: IoctlAction::Record(record_arg::<c_int>),
+    },
+    tty_nothing!(0x16, "TIOCMBIS"),
+    tty_nothing!(0x17, "TIOCMBIC"),
+    tty_nothing!(0x18, "TIOCMSET"),
+    IoctlHandler {
+        key: legacy(0x19),
+        name: "TIOCGSOFTCAR",
+        action: IoctlAction::Record(record_arg::<c_int>),
+    },
+    tty_nothing!(0x1a, "TIOCSSOFTCAR"),
+    tty_nothing!(0x1d, "TIOCCONS"),
+    tty_nothing!(0x20, "TIOCPKT"),
+    tty_nothing!(0x22, "TIOCNOTTY"),
+    tty_nothing!(0x23, "TIOCSETD"),
+    IoctlHandler {
+        key: legacy(0x24),
+        name: "TIOCGETD",
+        action: IoctlAction::Record(record_arg::<c_int>),
+    },
+    tty_nothing!(0x25, "TCSBRKP"),
+    tty_nothing!(0x27, "TIOCSBRK"),
+    tty_nothing!(0x28, "TIOCCBRK"),
+    IoctlHandler {
+        key: legacy(0x29),
+        name: "TIOCGSID",
+        action: IoctlAction::Record(record_arg::<pid_t>),
+    },
+    tty_by_ioc_bits!(0x2a, "TCGETS2"),
+    tty_by_ioc_bits!(0x2b, "TCSETS2"),
+    tty_by_ioc_bits!(0x2c, "TCSETSW2"),
+    tty_by_ioc_bits!(0x2d, "TCSETSF2"),
+    tty_by_ioc_bits!(0x30, "TIOCGPTN"),
+    tty_by_ioc_bits!(0x31, "TIOCSPTLCK"),
+    tty_by_ioc_bits!(0x32, "TIOCGDEV"),
+    tty_by_ioc_bits!(0x36, "TIOCSIG"),
+    tty_nothing!(0x37, "TIOCVHANGUP"),
+    tty_by_ioc_bits!(0x38, "TIOCGPKT"),
+    tty_by_ioc_bits!(0x39, "TIOCGPTLCK"),
+    tty_by_ioc_bits!(0x40, "TIOCGEXCL"),
+    // Returns an fd for the other end of a pty, and writes nothing.
+    tty_nothing!(0x41, "TIOCGPTPEER"),
+    IoctlHandler {
+        key: legacy(0x56),
+        name: "TIOCGLCKTRMIOS",
+        action: IoctlAction::Record(record_arg::<termios>),
+    },
+    tty_nothing!(0x57, "TIOCSLCKTRMIOS"),
+];
+
+#[cfg(test)]
+mod test {
+    use crate::ioctl_registry::{ioc, ioctl_handler, IOC_READ};
+    use libc::{TCGETS, TCSETS, TIOCGPGRP, TIOCGWINSZ, TIOCSCTTY, TIOCSTI};
+
+    #[test]
+    fn tty_handlers() {
+        for &(request, name) in &[
+            (TCGETS, "TCGETS"),
+            (TCSETS, "TCSETS"),
+            (TIOCGPGRP, "TIOCGPGRP"),
+            (TIOCSCTTY, "TIOCSCTTY"),
+            (TIOCSTI, "TIOCSTI"),
+            (TIOCGWINSZ, "TIOCGWINSZ"),
+        ] {
+            assert_eq!(ioctl_handler(request as u32).unwrap().name, name);
+        }
+        let tiocgptn = ioc(IOC_READ, b'T', 0x30, 4);
+        assert_eq!(ioctl_handler(tiocgptn).unwrap().name, "TIOCGPTN");
+        // Not TCGETA, which predates _IOC.
+        assert!(ioctl_handler(ioc(IOC_READ, b'T', 0x05, 64)).is_none());
+    }
+}
@@ -0,0 +1,227 @@
+//! Video4Linux, the camera and capture devices in /dev/video*. Programs that
+//! use a camera usually enumerate them at startup, asking each for its
+//! capabilities, formats and frame sizes, and those requests all say in
+//! their _IOC bits what they write. Captured frames are another matter: the
+//! driver fills the buffers the tracee mapped from the device (or handed it)
+//! by DMA, behind rd's back, and gives one to the tracee with VIDIOC_DQBUF,
+//! so that's when the frame gets recorded.
+
+use super::{
+    array,
+    is_32bit_layout,
+    read_arg,
+    record_by_ioc_bits,
+    IocBits,
+    IoctlAction,
+    IoctlBuffer,
+    IoctlCall,
+    IoctlHandler,
+    IoctlKey,
+};
+use crate::{
+    remote_ptr::RemotePtr,
+    session::task::{task_common::read_val_mem, Task},
+};
+use nix::sys::stat::stat;
+use std::{cmp::min, mem::size_of};
+
+const V4L2_IOCTL_BASE: u8 = b'V';
+
+const V4L2_BUF_TYPE_VIDEO_CAPTURE_MPLANE: u32 = 9;
+const V4L2_BUF_TYPE_VIDEO_OUTPUT_MPLANE: u32 = 10;
+
+const V4L2_MEMORY_MMAP: u32 = 1;
+const V4L2_MEMORY_USERPTR: u32 = 2;
+
+/// The most planes a multi-planar buffer has.
+const VIDEO_MAX_PLANES: u32 = 8;
+
+/// `struct v4l2_buffer`, with `Word` the size of a `long` and a pointer.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct v4l2_buffer<Word: Copy> {
+    index: u32,
+    type_: u32,
+    bytesused: u32,
+    flags: u32,
+    field: u32,
+    timestamp: [Word; 2],
+    timecode: [u32; 4],
+    sequence: u32,
+    memory: u32,
+    /// The union of `offset`, `userptr`, `planes` and `fd`.
+    m: Word,
+    /// The number of planes, for multi-planar buffers.
+    length: u32,
+    reserved2: u32,
+    request_fd: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct v4l2_plane<Word: Copy> {
+    bytesused: u32,
+    length: u32,
+    /// The union of `mem_offset`, `userptr` and `fd`.
+    m: Word,
+    data_offset: u32,
+    reserved: [u32; 11],
+}
+
+const fn v4l2(nr: u8) -> IoctlKey {
+    IoctlKey::number(V4L2_IOCTL_BASE, nr)
+}
+
+/// The handler for a request whose _IOC bits say what it writes.
+macro_rules! v4l2_by_ioc_bits {
+    ($nr:expr, $name:expr) => {
+        IoctlHandler {
+            key: v4l2($nr),
+            name: $name,
+            action: IoctlAction::Record(record_by_ioc_bits),
+        }
+    };
+}
+
+pub static V4L2_IOCTL_HANDLERS: &[IoctlHandler] = &[
+    v4l2_by_ioc_bits!(0x00, "VIDIOC_QUERYCAP"),
+    v4l2_by_ioc_bits!(0x02, "VIDIOC_ENUM_FMT"),
+    v4l2_by_ioc_bits!(0x04, "VIDIOC_G_FMT"),
+    v4l2_by_ioc_bits!(0x05, "VIDIOC_S_FMT"),
+    v4l2_by_ioc_bits!(0x08, "VIDIOC_REQBUFS"),
+    IoctlHandler {
+        key: v4l2(0x09),
+        name: "VIDIOC_QUERYBUF",
+        action: IoctlAction::Record(record_buffer),
+    },
+    IoctlHandler {
+        key: v4l2(0x0f),
+        name: "VIDIOC_QBUF",
+        action: IoctlAction::Record(record_buffer),
+    },
+    v4l2_by_ioc_bits!(0x10, "VIDIOC_EXPBUF"),
+    IoctlHandler {
+        key: v4l2(0x11),
+        name: "VIDIOC_DQBUF",
+        action: IoctlAction::Record(record_dequeued_buffer),
+    },
+    v4l2_by_ioc_bits!(0x12, "VIDIOC_STREAMON"),
+    v4l2_by_ioc_bits!(0x13, "VIDIOC_STREAMOFF"),
+    v4l2_by_ioc_bits!(0x15, "VIDIOC_G_PARM"),
+    v4l2_by_ioc_bits!(0x16, "VIDIOC_S_PARM"),
+    v4l2_by_ioc_bits!(0x1a, "VIDIOC_ENUMINPUT"),
+    v4l2_by_ioc_bits!(0x1b, "VIDIOC_G_CTRL"),
+    v4l2_by_ioc_bits!(0x1c, "VIDIOC_S_CTRL"),
+    v4l2_by_ioc_bits!(0x24, "VIDIOC_QUERYCTRL"),
+    v4l2_by_ioc_bits!(0x25, "VIDIOC_QUERYMENU"),
+    v4l2_by_ioc_bits!(0x26, "VIDIOC_G_INPUT"),
+    v4l2_by_ioc_bits!(0x27, "VIDIOC_S_INPUT"),
+    v4l2_by_ioc_bits!(0x4a, "VIDIOC_ENUM_FRAMESIZES"),
+    v4l2_by_ioc_bits!(0x4b, "VIDIOC_ENUM_FRAMEINTERVALS"),
+    v4l2_by_ioc_bits!(0x67, "VIDIOC_QUERY_EXT_CTRL"),
+];
+
+fn is_multiplanar(type_: u32) -> bool {
+    type_ == V4L2_BUF_TYPE_VIDEO_CAPTURE_MPLANE || type_ == V4L2_BUF_TYPE_VIDEO_OUTPUT_MPLANE
+}
+
+fn record_buffer(t: &mut dyn Task, call: &IoctlCall) -> Vec<IoctlBuffer> {
+    record_buffer_layout(t, call, false)
+}
+
+fn record_dequeued_buffer(t: &mut dyn Task, call: &IoctlCall) -> Vec<IoctlBuffer> {
+    record_buffer_layout(t, call, true)
+}
+
+fn record_buffer_layout(t: &mut dyn Task, call: &IoctlCall, dequeued: bool) -> Vec<IoctlBuffer> {
+    if is_32bit_layout::<v4l2_buffer<u32>>(call) {
+        record_buffer_arch::<u32>(t, call, dequeued)
+    } else if IocBits::decode(call.request).size == size_of::<v4l2_buffer<u64>>() {
+        record_buffer_arch::<u64>(t, call, dequeued)
+    } else {
+        // x86 programs built with a 64-bit time_t have a layout of their own.
+        // The struct is recorded, but not the planes and frames it points to.
+        record_by_ioc_bits(t, call)
+    }
+}
+
+fn record_buffer_arch<Word: Copy + Into<u64>>(
+    t: &mut dyn Task,
+    call: &IoctlCall,
+    dequeued: bool,
+) -> Vec<IoctlBuffer> {
+    let b: v4l2_buffer<Word> = read_arg(t, call);
+    let mut buffers = record_by_ioc_bits(t, call);
+    if !is_multiplanar(b.type_) {
+        if dequeued {
+            buffers.extend(frame(t, call.fd, b.memory, b.m.into(), b.bytesused));
+        }
+        return buffers;
+    }
+    let planes: u64 = b.m.into();
+    let count = min(b.length, VIDEO_MAX_PLANES) as usize;
+    let plane_size = size_of::<v4l2_plane<Word>>();
+    buffers.extend(array(planes, count as u64, plane_size));
+    if dequeued && planes != 0 {
+        for i in 0..count {
+            let addr = RemotePtr::new_from_val(planes as usize + i * plane_size);
+            let p: v4l2_plane<Word> = read_val_mem(t, addr, None);
+            buffers.extend(frame(t, call.fd, b.memory, p.m.into(), p.bytesused));
+        }
+    }
+    buffers
+}
+
+/// The frame the driver wrote to a dequeued buffer or plane, `bytesused`
+/// bytes of it. `m` is the buffer's or plane's `m` union.
+fn frame(t: &mut dyn Task, fd: i32, memory: u32, m: u64, bytesused: u32) -> Option<IoctlBuffer> {
+    match memory {
+        V4L2_MEMORY_MMAP => mapped_frame(t, fd, m as u32 as u64, bytesused as usize),
+        V4L2_MEMORY_USERPTR => array(m, bytesused.into(), 1),
+        // DMABUF frames are in memory another device exports. Tracees that
+        // map them get the frame through the mapping, which rd doesn't see.
+        _ => None,
+    }
+}
+
+/// The first `size` bytes of the tracee's mapping of `fd` at `offset`, the
+/// "offset" the driver gave the buffer for mmap().
+fn mapped_frame(t: &mut dyn Task, fd: i32, offset: u64, size: usize) -> Option<IoctlBuffer> {
+    if size == 0 {
+        return None;
+    }
+    let st = stat(format!("/proc/{}/fd/{}", t.tid, fd).as_str()).ok()?;
+    for (_, m) in &t.vm().maps() {
+        if m.map.device() == st.st_dev
+            && m.map.inode() == st.st_ino
+            && m.map.file_offset_bytes() == offset
+        {
+            return Some(IoctlBuffer {
+                addr: m.map.start(),
+                size: min(size, m.map.size()),
+            });
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ioctl_registry::{ioc, ioctl_handler, IOC_READ, IOC_WRITE};
+
+    #[test]
+    fn v4l2_handlers() {
+        let rw = IOC_READ | IOC_WRITE;
+        assert_eq!(size_of::<v4l2_buffer<u32>>(), 68);
+        assert_eq!(size_of::<v4l2_buffer<u64>>(), 88);
+        assert_eq!(size_of::<v4l2_plane<u32>>(), 60);
+        assert_eq!(size_of::<v4l2_plane<u64>>(), 64);
+        for &size in &[68, 88] {
+            let dqbuf = ioc(rw, b'V', 0x11, size);
+            assert_eq!(ioctl_handler(dqbuf).unwrap().name, "VIDIOC_DQBUF");
+        }
+        // VIDIOC_QUERYCAP, _IOR('V', 0, struct v4l2_capability).
+        assert_eq!(ioctl_handler(0x8068_5600).unwrap().name, "VIDIOC_QUERYCAP");
+    }
+}
@@ -16,7 +16,7 @@ use std::{
     marker::PhantomData,
 };
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub enum SupportedArch {
     X86,
     X64,
@@ -187,19 +187,31 @@ fn get_syscall_instruction_arch(
     if !ok {
         return false;
     }
-    match t.arch() {
+    match syscall_instruction_arch(t.arch(), &code) {
+        Some(syscall_arch) => {
+            *arch = syscall_arch;
+            true
+        }
+        None => false,
+    }
+}
+
+/// The architecture of the syscall that the instruction `code` makes in a
+/// task of architecture `task_arch`, or None if it isn't a syscall
+/// instruction.
+pub fn syscall_instruction_arch(task_arch: SupportedArch, code: &[u8]) -> Option<SupportedArch> {
+    match task_arch {
         // Compatibility mode switch can happen in user space (but even without
         // such tricks, int80, which uses the 32bit syscall table, can be invoked
         // from 64bit processes).
         SupportedArch::X86 | SupportedArch::X64 => {
             if code == INT80_INSN || code == SYSENTER_INSN {
-                *arch = SupportedArch::X86;
+                Some(SupportedArch::X86)
             } else if code == SYSCALL_INSN {
-                *arch = SupportedArch::X64;
+                Some(SupportedArch::X64)
             } else {
-                return false;
+                None
             }
-            return true;
         }
     }
 }
@@ -317,6 +329,145 @@ pub mod common {
     pub use super::aligned_u64;
     pub type ptr64<T> = super::Ptr<aligned_u64, T>;
 
+    /// The area a thread registers with rseq(). The layout is the same on all
+    /// architectures; pointers are always 64 bits.
+    #[repr(C, align(32))]
+    #[derive(Copy, Clone, Default)]
+    pub struct rseq {
+        pub cpu_id_start: uint32_t,
+        pub cpu_id: uint32_t,
+        /// Address of the `rseq_cs` of the critical section the thread is
+        /// in, or 0.
+        pub rseq_cs: uint64_t,
+        pub flags: uint32_t,
+    }
+
+    /// Describes a restartable sequence critical section.
+    #[repr(C, align(32))]
+    #[derive(Copy, Clone, Default)]
+    pub struct rseq_cs {
+        pub version: uint32_t,
+        pub flags: uint32_t,
+        pub start_ip: uint64_t,
+        /// The critical section is [start_ip, start_ip + post_commit_offset).
+        pub post_commit_offset: uint64_t,
+        pub abort_ip: uint64_t,
+    }
+
+    /// The argument struct of clone3(), as of Linux 5.7
+    /// (`CLONE_ARGS_SIZE_VER2`). Tracees may pass a shorter one; the missing
+    /// fields are zero. The layout is the same on all architectures.
+    #[repr(C)]
+    #[derive(Copy, Clone, Default)]
+    pub struct clone_args {
+        pub flags: uint64_t,
+        /// Where to store the pidfd, with CLONE_PIDFD.
+        pub pidfd: uint64_t,
+        pub child_tid: uint64_t,
+        pub parent_tid: uint64_t,
+        pub exit_signal: uint64_t,
+        /// The lowest address of the child's stack, unlike clone().
+        pub stack: uint64_t,
+        pub stack_size: uint64_t,
+        pub tls: uint64_t,
+        /// An array of the tids the child should get in each pid namespace,
+        /// innermost first.
+        pub set_tid: uint64_t,
+        pub set_tid_size: uint64_t,
+        /// A cgroup directory fd, with CLONE_INTO_CGROUP.
+        pub cgroup: uint64_t,
+    }
+
+    /// The argument struct of openat2(), as of Linux 5.6
+    /// (`OPEN_HOW_SIZE_VER0`). Like `clone_args` it may grow, and has the
+    /// same layout on all architectures.
+    #[repr(C)]
+    #[derive(Copy, Clone, Default)]
+    pub struct open_how {
+        /// The O_* flags of open().
+        pub flags: uint64_t,
+        pub mode: uint64_t,
+        /// RESOLVE_* flags restricting how the path is looked up.
+        pub resolve: uint64_t,
+    }
+
+    /// Where the fields of an io_uring submission queue ring are, relative
+    /// to the start of its mapping. The io_uring structs have the same
+    /// layout on all architectures.
+    #[repr(C)]
+    #[derive(Copy, Clone, Default)]
+    pub struct io_sqring_offsets {
+        pub head: uint32_t,
+        pub tail: uint32_t,
+        pub ring_mask: uint32_t,
+        pub ring_entries: uint32_t,
+        pub flags: uint32_t,
+        pub dropped: uint32_t,
+        pub array: uint32_t,
+        pub resv1: uint32_t,
+        pub user_addr: uint64_t,
+    }
+
+    /// Where the fields of an io_uring completion queue ring are.
+    #[repr(C)]
+    #[derive(Copy, Clone, Default)]
+    pub struct io_cqring_offsets {
+        pub head: uint32_t,
+        pub tail: uint32_t,
+        pub ring_mask: uint32_t,
+        pub ring_entries: uint32_t,
+        pub overflow: uint32_t,
+        pub cqes: uint32_t,
+        pub flags: uint32_t,
+        pub resv1: uint32_t,
+        pub user_addr: uint64_t,
+    }
+
+    /// The in/out argument of io_uring_setup().
+    #[repr(C)]
+    #[derive(Copy, Clone, Default)]
+    pub struct io_uring_params {
+        pub sq_entries: uint32_t,
+        pub cq_entries: uint32_t,
+        pub flags: uint32_t,
+        pub sq_thread_cpu: uint32_t,
+        pub sq_thread_idle: uint32_t,
+        pub features: uint32_t,
+        pub wq_fd: uint32_t,
+        pub resv: [uint32_t; 3],
+        pub sq_off: io_sqring_offsets,
+        pub cq_off: io_cqring_offsets,
+    }
+
+    /// A submission queue entry, with only the fields rd looks at named.
+    #[repr(C)]
+    #[derive(Copy, Clone, Default)]
+    pub struct io_uring_sqe {
+        pub opcode: uint8_t,
+        /// IOSQE_* flags.
+        pub flags: uint8_t,
+        pub ioprio: uint16_t,
+        pub fd: int32_t,
+        pub off: uint64_t,
+        /// The buffer, or iovec array, of the operation.
+        pub addr: uint64_t,
+        /// The length of the buffer, or the number of iovecs.
+        pub len: uint32_t,
+        pub op_flags: uint32_t,
+        pub user_data: uint64_t,
+        pub rest: [uint64_t; 3],
+    }
+
+    /// A completion queue entry.
+    #[repr(C)]
+    #[derive(Copy, Clone, Default)]
+    pub struct io_uring_cqe {
+        pub user_data: uint64_t,
+        pub res: int32_t,
+        /// IORING_CQE_F_* flags.
+        pub flags: uint32_t,
+    }
+
     // IMPORTANT ! ////////////////////////
     pub mod preload_interface {
         use super::*;
@@ -778,6 +929,12 @@ pub mod x64 {
 
         assert_eq_size!(kernel::epoll_event, epoll_event);
         assert_eq_align!(kernel::epoll_event, epoll_event);
+
+        assert_eq_size!(kernel::rseq, rseq);
+        assert_eq_align!(kernel::rseq, rseq);
+
+        assert_eq_size!(kernel::rseq_cs, rseq_cs);
+        assert_eq_align!(kernel::rseq_cs, rseq_cs);
     }
 }
 
@@ -1227,5 +1384,11 @@ pub mod x86 {
 
         assert_eq_size!(kernel::epoll_event, epoll_event);
         assert_eq_align!(kernel::epoll_event, epoll_event);
+
+        assert_eq_size!(kernel::rseq, rseq);
+        assert_eq_align!(kernel::rseq, rseq);
+
+        assert_eq_size!(kernel::rseq_cs, rseq_cs);
+        assert_eq_align!(kernel::rseq_cs, rseq_cs);
     }
 }
@@ -258,6 +258,13 @@ pub fn is_sigreturn(syscallno: i32, arch: SupportedArch) -> bool {
         || kernel_abi::is_rt_sigreturn_syscall(syscallno, arch)
 }
 
+/// execveat() behaves like execve() once the file to run has been found, so
+/// most code wants to treat them the same.
+pub fn is_exec(syscallno: i32, arch: SupportedArch) -> bool {
+    kernel_abi::is_execve_syscall(syscallno, arch)
+        || kernel_abi::is_execveat_syscall(syscallno, arch)
+}
+
 macro_rules! case {
     ($match_var:expr, $mod_name:ident, $sub_mod_name:ident, $($case_name:ident),+) => {{
         match $match_var {
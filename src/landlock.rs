@@ -0,0 +1,36 @@
+//! Landlock. Sandboxed programs call landlock_create_ruleset(),
+//! landlock_add_rule() and landlock_restrict_self() early on to give up
+//! filesystem (and network) access they don't need. Recording lets them
+//! through: the kernel enforces the restrictions, and the tracee sees the
+//! same results it would without rd. Replay emulates all three; what a
+//! replayed tracee reads comes from the trace, and restricting it for real
+//! could only get in the way of what rd does in it.
+//!
+//! Ruleset fds get a `LandlockMonitor` in the fd table, while recording and
+//! during replay, so the fd tables match.
+
+use crate::registers::Registers;
+
+/// landlock_create_ruleset() returns the highest Landlock ABI version the
+/// kernel supports instead of creating a ruleset.
+pub const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1 << 0;
+
+/// Whether landlock_create_ruleset() with `regs` creates a ruleset fd.
+pub fn creates_landlock_ruleset(regs: &Registers) -> bool {
+    regs.arg3() as u32 & LANDLOCK_CREATE_RULESET_VERSION == 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::kernel_abi::SupportedArch;
+
+    #[test]
+    fn landlock_create_ruleset() {
+        let mut regs = Registers::new(SupportedArch::X64);
+        regs.set_arg3(0);
+        assert!(creates_landlock_ruleset(&regs));
+        regs.set_arg3(LANDLOCK_CREATE_RULESET_VERSION as usize);
+        assert!(!creates_landlock_ruleset(&regs));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::kernel_metadata::errno_name;
+use crate::{kernel_metadata::errno_name, temp_resources::remove_process_dir};
 use backtrace::Backtrace;
 use nix::errno::errno;
 use std::{
@@ -350,6 +350,8 @@ macro_rules! clean_fatal {
 pub fn notifying_abort(bt: Backtrace) {
     // @TODO running under test monitor stuff.
     dump_rd_stack(bt);
+    // abort() skips atexit handlers.
+    remove_process_dir();
     std::process::abort();
 }
 
@@ -30,49 +30,92 @@ mod kernel_metadata;
 mod perf_counters;
 #[macro_use]
 mod registers;
+mod cancellation_token;
+mod capture_reads;
 mod commands;
 mod core;
 mod cpuid_bug_detector;
+mod dead_tasks;
+mod deferred_work;
+mod elf_symbols;
 mod emu_fs;
 mod event;
+mod exec_target;
 pub mod extra_registers;
 mod fast_forward;
 mod fd_table;
 mod file_monitor;
+mod fuse_files;
 mod gdb_register;
 mod gdb_server;
+mod host_check;
+mod io_uring;
+mod ioctl_registry;
 mod kernel_supplement;
+mod landlock;
+mod memfd;
+mod memory_checksum;
 mod monitored_shared_memory;
 mod monkey_patcher;
 mod rd;
+mod rd_mapping_placement;
+mod record_attach;
+mod record_environment;
+mod record_signal;
+mod record_syscall;
 mod remote_code_ptr;
 mod remote_ptr;
+mod replay_divergence;
 mod replay_syscall;
+mod replay_timeline;
+mod rseq;
 mod scheduler;
+mod scm_rights;
 mod scoped_fd;
 mod seccomp_bpf;
 mod seccomp_filter_rewriter;
+mod seccomp_unotify;
+mod seeded_random;
 mod session;
+mod skid_region;
+mod syscall_interception;
 mod taskish_uid;
+mod temp_resources;
 mod thread_group;
 mod ticks;
 mod trace;
 mod trace_capnp;
+mod unwinder;
 mod util;
+mod virtual_clock;
 mod wait_status;
 mod weak_ptr_set;
 
 use crate::{
     commands::{
+        backtrace_command::BacktraceCommand,
         build_id_command::BuildIdCommand,
+        check_command::CheckCommand,
+        doctor_command::DoctorCommand,
         dump_command::DumpCommand,
+        grep_command::GrepCommand,
+        lint_command::LintCommand,
+        maps_command::MapsCommand,
+        pack_command::PackCommand,
         ps_command::PsCommand,
         rd_options::{RdOptions, RdSubCommand},
+        record_command::RecordCommand,
         rerun_command::ReRunCommand,
+        stats_command::StatsCommand,
+        tasks_command::TasksCommand,
         trace_info_command::TraceInfoCommand,
+        tui_command::TuiCommand,
+        which_wrote_command::WhichWroteCommand,
         RdCommand,
     },
+    host_check::parse_kernel_version,
     perf_counters::init_pmu,
+    temp_resources::sweep_stale_process_dirs,
     util::raise_resource_limits,
 };
 use commands::replay_command::ReplayCommand;
@@ -84,18 +127,12 @@ pub fn assert_prerequisites(maybe_use_syscall_buffer: Option<bool>) {
     let use_syscall_buffer = maybe_use_syscall_buffer.unwrap_or(false);
     let unm = uname();
     let release = unm.release();
-    let parts: Vec<&str> = release.split('.').collect();
-    if parts.len() < 2 {
-        fatal!("Could not parse kernel version string. Got: `{}`", release);
-    }
-
-    let maybe_major = parts[0].parse::<u32>();
-    let maybe_minor = parts[1].parse::<u32>();
-    if maybe_major.is_err() || maybe_minor.is_err() {
-        fatal!("Could not parse kernel version string. Got: `{}`", release);
-    }
-
-    let (major, minor) = (maybe_major.unwrap(), maybe_minor.unwrap());
+    let (major, minor) = match parse_kernel_version(release) {
+        Some(version) => version,
+        None => {
+            clean_fatal!("Could not parse kernel version string. Got: `{}`", release);
+        }
+    };
     if (major, minor) < (3, 4) {
         fatal!("Kernel doesn't support necessary ptrace functionality; need 3.4.0 or better.");
     }
@@ -109,12 +146,22 @@ fn main() -> io::Result<()> {
     raise_resource_limits();
     let options = RdOptions::from_args();
 
+    // Probing the host has to work where initializing the PMU would die.
+    if let RdSubCommand::Check = options.cmd {
+        return CheckCommand::new().run();
+    }
+
     init_pmu();
+    // Clean up after rd processes that were killed before they could.
+    sweep_stale_process_dirs();
     match &options.cmd {
         RdSubCommand::BuildId => return BuildIdCommand::new().run(),
         RdSubCommand::Dump { .. } => {
             DumpCommand::new(&options).run()?;
         }
+        RdSubCommand::Record { .. } => {
+            RecordCommand::new(&options).run()?;
+        }
         RdSubCommand::ReRun { .. } => {
             ReRunCommand::new(&options).run()?;
         }
@@ -124,9 +171,39 @@ fn main() -> io::Result<()> {
         RdSubCommand::TraceInfo { .. } => {
             TraceInfoCommand::new(&options).run()?;
         }
+        RdSubCommand::Doctor { .. } => {
+            DoctorCommand::new(&options).run()?;
+        }
+        RdSubCommand::Lint { .. } => {
+            LintCommand::new(&options).run()?;
+        }
         RdSubCommand::Ps { .. } => {
             PsCommand::new(&options).run()?;
         }
+        RdSubCommand::Grep { .. } => {
+            GrepCommand::new(&options).run()?;
+        }
+        RdSubCommand::Maps { .. } => {
+            MapsCommand::new(&options).run()?;
+        }
+        RdSubCommand::Backtrace { .. } => {
+            BacktraceCommand::new(&options).run()?;
+        }
+        RdSubCommand::Tasks { .. } => {
+            TasksCommand::new(&options).run()?;
+        }
+        RdSubCommand::Tui { .. } => {
+            TuiCommand::new(&options).run()?;
+        }
+        RdSubCommand::WhichWrote { .. } => {
+            WhichWroteCommand::new(&options).run()?;
+        }
+        RdSubCommand::Pack { .. } => {
+            PackCommand::new(&options).run()?;
+        }
+        RdSubCommand::Stats { .. } => {
+            StatsCommand::new(&options).run()?;
+        }
         _ => (),
     }
 
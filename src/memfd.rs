@@ -0,0 +1,24 @@
+//! memfd_create() files. The tracee's memfds are shared memory like POSIX
+//! shm objects: their MAP_SHARED mappings are recorded into the trace (see
+//! `SharedMemoryKind::Memfd`) and replay gives every task mapping the same
+//! memfd the same emufs file, so writes through one mapping show up in the
+//! others. Private mappings are copied like any other file without a name
+//! on disk.
+//!
+//! Seals, whether asked for with MFD_ALLOW_SEALING and added with
+//! fcntl(F_ADD_SEALS) or implied by MFD_NOEXEC_SEAL, only restrict what
+//! the tracee can do with the file, and the kernel enforces them during
+//! recording. Replay emulates fcntl() and maps an unsealed emufs file, which
+//! doesn't behave any differently for what the tracee goes on to do.
+//!
+//! memfd_secret() memory is taken out of the kernel's direct map, so rd
+//! can't read it through /proc/<pid>/mem to record what syscalls wrote
+//! there. rd makes it fail with ENOSYS, what kernels without
+//! CONFIG_SECRETMEM return, and programs fall back to ordinary memory.
+//!
+//! The constants aren't in the headers we generate bindings from on older
+//! systems.
+
+/// The file may be mapped executable even where the vm.memfd_noexec sysctl
+/// makes new memfds non-executable by default (Linux 6.3).
+pub const MFD_EXEC: u32 = 0x10;
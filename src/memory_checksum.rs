@@ -0,0 +1,226 @@
+use crate::{
+    event::{Event, EventType, SyscallState},
+    flags::{Checksum, Flags},
+    log::LogLevel::{LogDebug, LogError},
+    remote_ptr::{RemotePtr, Void},
+    session::{
+        address_space::{address_space::Mapping, MappingFlags},
+        task::Task,
+    },
+    trace::trace_frame::FrameTime,
+};
+use nix::sys::mman::ProtFlags;
+use std::{
+    convert::TryInto,
+    ffi::OsStr,
+    fmt::Write as FmtWrite,
+    fs,
+    mem::size_of,
+};
+
+/// Should the memory of the task that `ev` happened to be checksummed (during
+/// recording) at global time `time`? Replay verifies whatever checksums the
+/// trace has, so this is only consulted while recording.
+pub fn should_checksum(ev: &Event, time: FrameTime) -> bool {
+    // The task is dead, there's no memory to look at.
+    if ev.event_type() == EventType::EvExit {
+        return false;
+    }
+    match Flags::get().checksum {
+        None => false,
+        Some(Checksum::ChecksumAll) => true,
+        Some(Checksum::ChecksumSyscall) => {
+            ev.is_syscall_event() && ev.syscall_event().state == SyscallState::ExitingSyscall
+        }
+        Some(Checksum::ChecksumAt(from)) => from <= time,
+        Some(Checksum::ChecksumEvery(n)) => time % n == 0,
+    }
+}
+
+/// Compute a checksum of each mapping of `t`'s address space and write them to
+/// `path`, one `<checksum> <start>-<end>` line per mapping.
+pub fn checksum_process_memory(t: &mut dyn Task, path: &OsStr) {
+    let mut out = String::new();
+    for (start, end, checksum) in compute_checksums(t) {
+        writeln!(
+            out,
+            "{:08x} {:x}-{:x}",
+            checksum,
+            start.as_usize(),
+            end.as_usize()
+        )
+        .unwrap();
+    }
+    if fs::write(path, out).is_err() {
+        fatal!("Unable to write memory checksums to {:?}", path);
+    }
+}
+
+/// A mapping whose contents (or extent) differ from the recording.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct MemoryMismatch {
+    pub start: usize,
+    pub end: usize,
+    pub recorded_checksum: u32,
+    /// `None` if the range isn't mapped the way it was during recording.
+    pub replay_checksum: Option<u32>,
+}
+
+/// Compare the memory of `t` with the checksums recorded in `path` at global
+/// time `time` and return the mappings that differ.
+pub fn memory_mismatches(t: &mut dyn Task, path: &OsStr, time: FrameTime) -> Vec<MemoryMismatch> {
+    let recorded = match fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(_) => {
+            fatal!("Unable to read memory checksums from {:?}", path);
+            unreachable!()
+        }
+    };
+    let recorded: Vec<(u32, usize, usize)> = recorded
+        .lines()
+        .map(|line| match parse_checksum_line(line) {
+            Some(parsed) => parsed,
+            None => {
+                fatal!("Malformed line `{}' in {:?}", line, path);
+                unreachable!()
+            }
+        })
+        .collect();
+    let mismatches = compare_checksums(&recorded, &compute_checksums(t));
+    for m in &mismatches {
+        log!(
+            LogError,
+            "Memory {:#x}-{:#x} diverged at event {}: recorded checksum {:08x}, now {:?}",
+            m.start,
+            m.end,
+            time,
+            m.recorded_checksum,
+            m.replay_checksum
+        );
+    }
+    if mismatches.is_empty() {
+        log!(LogDebug, "Memory checksums match at event {}", time);
+    }
+    mismatches
+}
+
+/// Mappings whose contents we don't expect to match between recording and
+/// replay.
+fn should_ignore_mapping(m: &Mapping) -> bool {
+    // The syscallbuf and thread locals are only partly restored by replay and
+    // the rd page differs by design.
+    m.flags.intersects(
+        MappingFlags::IS_SYSCALLBUF | MappingFlags::IS_THREAD_LOCALS | MappingFlags::IS_RD_PAGE,
+    ) || m.map.is_vdso()
+        || m.map.is_vvar()
+        || m.map.is_vsyscall()
+        || !m.map.prot().contains(ProtFlags::PROT_READ)
+}
+
+fn compute_checksums(t: &mut dyn Task) -> Vec<(RemotePtr<Void>, RemotePtr<Void>, u32)> {
+    let mut ranges: Vec<(RemotePtr<Void>, RemotePtr<Void>)> = Vec::new();
+    for (_, m) in &t.vm().maps() {
+        if !should_ignore_mapping(m) {
+            ranges.push((m.map.start(), m.map.end()));
+        }
+    }
+
+    let mut result = Vec::with_capacity(ranges.len());
+    let mut buf = Vec::new();
+    for (start, end) in ranges {
+        buf.resize(end.as_usize() - start.as_usize(), 0);
+        let valid = t
+            .read_bytes_fallible(RemotePtr::cast(start), &mut buf)
+            .unwrap_or(0);
+        result.push((start, end, checksum(&buf[0..valid])));
+    }
+    result
+}
+
+/// The `recorded` (checksum, start, end) mappings that aren't in `current`
+/// with the same extent and checksum.
+fn compare_checksums(
+    recorded: &[(u32, usize, usize)],
+    current: &[(RemotePtr<Void>, RemotePtr<Void>, u32)],
+) -> Vec<MemoryMismatch> {
+    let mut mismatches = Vec::new();
+    for &(checksum, start, end) in recorded {
+        let replay_checksum = current
+            .iter()
+            .find(|&&(s, e, _)| s.as_usize() == start && e.as_usize() == end)
+            .map(|&(_, _, c)| c);
+        if replay_checksum != Some(checksum) {
+            mismatches.push(MemoryMismatch {
+                start,
+                end,
+                recorded_checksum: checksum,
+                replay_checksum,
+            });
+        }
+    }
+    mismatches
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    data.chunks_exact(size_of::<u32>())
+        .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+        .fold(0u32, |sum, word| sum.wrapping_add(word))
+}
+
+fn parse_checksum_line(line: &str) -> Option<(u32, usize, usize)> {
+    let mut parts = line.split_whitespace();
+    let checksum = u32::from_str_radix(parts.next()?, 16).ok()?;
+    let mut range = parts.next()?.splitn(2, '-');
+    let start = usize::from_str_radix(range.next()?, 16).ok()?;
+    let end = usize::from_str_radix(range.next()?, 16).ok()?;
+    Some((checksum, start, end))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn checksum_line_round_trip() {
+        assert_eq!(
+            parse_checksum_line("0badf00d 7f0000001000-7f0000003000"),
+            Some((0xbadf00d, 0x7f0000001000, 0x7f0000003000))
+        );
+        assert_eq!(parse_checksum_line("xyz 1000-2000"), None);
+        assert_eq!(parse_checksum_line("1234"), None);
+    }
+
+    #[test]
+    fn checksum_mismatches() {
+        let p = |v: usize| RemotePtr::<Void>::new_from_val(v);
+        let current = [(p(0x1000), p(0x2000), 7), (p(0x3000), p(0x5000), 8)];
+        let recorded = [
+            (7, 0x1000, 0x2000),
+            (9, 0x3000, 0x5000),
+            (1, 0x3000, 0x4000),
+        ];
+        assert_eq!(
+            compare_checksums(&recorded, &current),
+            vec![
+                MemoryMismatch {
+                    start: 0x3000,
+                    end: 0x5000,
+                    recorded_checksum: 9,
+                    replay_checksum: Some(8),
+                },
+                MemoryMismatch {
+                    start: 0x3000,
+                    end: 0x4000,
+                    recorded_checksum: 1,
+                    replay_checksum: None,
+                },
+            ]
+        );
+        assert!(compare_checksums(&recorded[..1], &current).is_empty());
+    }
+
+    #[test]
+    fn checksum_ignores_trailing_bytes() {
+        assert_eq!(checksum(&[1, 0, 0, 0, 2, 0, 0, 0, 9]), 3);
+    }
+}
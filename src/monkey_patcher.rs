@@ -1,20 +1,620 @@
-use crate::{arch::Architecture, session::task::record_task::record_task::RecordTask};
+use crate::{
+    arch::Architecture,
+    auto_remote_syscalls::AutoRemoteSyscalls,
+    kernel_abi::{common::preload_interface::syscall_patch_hook, syscall_instruction_length},
+    kernel_metadata::syscall_name,
+    log::LogLevel::LogDebug,
+    remote_code_ptr::RemoteCodePtr,
+    remote_ptr::{RemotePtr, Void},
+    session::{
+        address_space::{kernel_mapping::KernelMapping, MappingFlags},
+        task::{
+            record_task::record_task::RecordTask,
+            task_common::{read_mem, read_val_mem},
+            task_inner::task_inner::WriteFlags,
+            Task,
+        },
+    },
+    trace::trace_writer::MappingOrigin,
+    util::page_size,
+};
+use goblin::elf::{program_header::PT_LOAD, Elf};
+use nix::sys::mman::{MapFlags, ProtFlags};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashSet,
+    ffi::OsStr,
+};
 
+/// The vdso functions we replace with a real syscall so that their results are
+/// recorded. The vdso also exports these without the `__vdso_` prefix but
+/// those are aliases for the same code.
+const VDSO_SYSCALLS_X86: [(&str, i32); 3] = [
+    ("__vdso_clock_gettime", crate::arch::X86Arch::CLOCK_GETTIME),
+    ("__vdso_gettimeofday", crate::arch::X86Arch::GETTIMEOFDAY),
+    ("__vdso_time", crate::arch::X86Arch::TIME),
+];
+
+const VDSO_SYSCALLS_X64: [(&str, i32); 4] = [
+    ("__vdso_clock_gettime", crate::arch::X64Arch::CLOCK_GETTIME),
+    ("__vdso_gettimeofday", crate::arch::X64Arch::GETTIMEOFDAY),
+    ("__vdso_time", crate::arch::X64Arch::TIME),
+    ("__vdso_getcpu", crate::arch::X64Arch::GETCPU),
+];
+
+/// Length of a `jmp rel32`
+const JUMP_PATCH_SIZE: usize = 5;
+
+/// The stub in an extended jump page that a patched x64 syscall jumps to.
+/// It skips the red zone, pushes the address to return to after the syscall
+/// and jumps to the syscall hook:
+///
+///   lea -128(%rsp),%rsp
+///   movl $return_addr_lo,(%rsp)
+///   movl $return_addr_hi,4(%rsp)
+///   jmp *0(%rip)
+///   .quad hook_address
+const X64_EXTENDED_JUMP_SIZE: usize = 34;
+
+/// One page of extended jump stubs. Stubs are never freed; the page lives as
+/// long as the address space.
 #[derive(Clone)]
-pub struct MonkeyPatcher {}
+struct ExtendedJumpPage {
+    addr: RemotePtr<u8>,
+    allocated: usize,
+}
+
+/// Patches code in the tracee to make recording cheaper or deterministic.
+/// There is one instance of this per tracee address space, and only during
+/// recording: everything we write to tracee memory is recorded, so replay
+/// restores the patches from the trace like any other data.
+///
+/// This does three things:
+/// 1) Patch the vdso's user-space-only implementation of certain system calls
+///    (e.g. gettimeofday) to do a proper kernel system call instead, so rd
+///    can trap and record it.
+/// 2) On x86, redirect the vdso's __kernel_vsyscall fast-system-call stub to
+///    our syscall hook in the preload library.
+/// 3) Patch syscall instructions whose following instructions match a known
+///    pattern to call the syscall hook.
+#[derive(Clone, Default)]
+pub struct MonkeyPatcher {
+    syscall_hooks: RefCell<Vec<syscall_patch_hook>>,
+    /// The addresses of the instructions following syscalls that we've tried
+    /// (or are currently trying) to patch. We only try once per address.
+    tried_to_patch_syscall_addresses: RefCell<HashSet<RemoteCodePtr>>,
+    extended_jump_pages: RefCell<Vec<ExtendedJumpPage>>,
+    x86_vsyscall: Cell<RemoteCodePtr>,
+}
 
 impl MonkeyPatcher {
     pub fn new() -> MonkeyPatcher {
-        MonkeyPatcher {}
+        MonkeyPatcher::default()
     }
-    pub fn patch_at_preload_init(&self, t: &RecordTask) {
+
+    /// Apply any necessary patching immediately after exec.
+    /// In this hook we patch everything that doesn't depend on the preload
+    /// library being loaded.
+    pub fn patch_after_exec(&self, t: &mut RecordTask) {
+        rd_arch_function_selfless!(patch_after_exec_arch, t.arch(), t, self);
+    }
+
+    pub fn patch_at_preload_init(&self, t: &mut RecordTask) {
         // NB: the tracee can't be interrupted with a signal while
         // we're processing the rdcall, because it's masked off all
         // signals.
         rd_arch_function_selfless!(patch_at_preload_init_arch, t.arch(), t, self);
     }
+
+    /// Try to patch the syscall instruction that `t` just entered. If this
+    /// returns false, patching failed and the syscall should be processed
+    /// as normal. If it returns true, patching succeeded and the syscall was
+    /// aborted; `t`'s ip() has been reset to the patched code so it will
+    /// reexecute the syscall through the hook.
+    pub fn try_patch_syscall(&self, t: &mut RecordTask) -> bool {
+        if self.syscall_hooks.borrow().is_empty() {
+            // Syscall hooks not set up yet. Don't spew warnings, and don't
+            // fill tried_to_patch_syscall_addresses with addresses that we might be
+            // able to patch later.
+            return false;
+        }
+        if t.emulated_ptracer.is_some() {
+            // Syscall patching can confuse ptracers, which may be surprised to see
+            // a syscall instruction at the current IP but then when running
+            // forwards, that the syscall occurs deep in the preload library instead.
+            return false;
+        }
+
+        let ip = t.ip();
+        if !self
+            .tried_to_patch_syscall_addresses
+            .borrow_mut()
+            .insert(ip)
+        {
+            return false;
+        }
+
+        // We could examine the current syscall number and if it's not one that
+        // we support syscall buffering for, refuse to patch the syscall instruction.
+        // That would save a useless trip through the syscall buffering logic, but
+        // we'd have to keep a list of buffered syscalls in sync with the preload
+        // library, which isn't worth it.
+        let mut following_bytes = [0u8; 256];
+        let bytes_count = t
+            .read_bytes_fallible(ip.to_data_ptr::<Void>(), &mut following_bytes)
+            .unwrap_or(0);
+        let following_bytes = &following_bytes[0..bytes_count];
+
+        let syscallno = t.regs_ref().original_syscallno() as i32;
+        let hooks = self.syscall_hooks.borrow().clone();
+        for hook in &hooks {
+            let len = hook.next_instruction_length as usize;
+            if bytes_count < len || following_bytes[0..len] != hook.next_instruction_bytes[0..len] {
+                continue;
+            }
+
+            if has_interfering_branch(following_bytes, hook) {
+                log!(
+                    LogDebug,
+                    "Found potential interfering branch after syscall at {}",
+                    ip
+                );
+                continue;
+            }
+
+            if !safe_for_syscall_patch(t, ip, hook) {
+                log!(
+                    LogDebug,
+                    "Temporarily declining to patch syscall at {} because a different task \
+                     has its ip in the patched range",
+                    ip
+                );
+                // Let us try again later.
+                self.tried_to_patch_syscall_addresses
+                    .borrow_mut()
+                    .remove(&ip);
+                return false;
+            }
+
+            // Get out of executing the current syscall before we patch it.
+            if !t.exit_syscall_and_prepare_restart() {
+                return false;
+            }
+
+            log!(
+                LogDebug,
+                "Patching syscall at {} syscall {} tid {} with hook at {:#x}",
+                ip,
+                syscall_name(syscallno, t.arch()),
+                t.tid,
+                hook.hook_address
+            );
+            return rd_arch_function_selfless!(
+                patch_syscall_with_hook_arch,
+                t.arch(),
+                self,
+                t,
+                hook
+            );
+        }
+
+        false
+    }
+
+    /// Is `ip` inside one of our extended jump stubs?
+    pub fn is_jump_stub_instruction(&self, ip: RemoteCodePtr) -> bool {
+        let pp = ip.to_data_ptr::<u8>();
+        self.extended_jump_pages
+            .borrow()
+            .iter()
+            .any(|p| p.addr <= pp && pp < p.addr + p.allocated)
+    }
+
+    fn init_dynamic_syscall_patching(
+        &self,
+        t: &mut RecordTask,
+        syscall_patch_hooks: RemotePtr<syscall_patch_hook>,
+        syscall_patch_hook_count: usize,
+    ) {
+        if syscall_patch_hook_count > 0 {
+            *self.syscall_hooks.borrow_mut() =
+                read_mem(t, syscall_patch_hooks, syscall_patch_hook_count, None);
+        }
+    }
+
+    /// Find `JUMP_PATCH_SIZE`-reachable space for a stub of `size` bytes in
+    /// an extended jump page, mapping a new page if necessary.
+    /// `from_end` is the end of the jump instruction that will jump to it.
+    fn allocate_extended_jump(
+        &self,
+        t: &mut RecordTask,
+        size: usize,
+        from_end: RemotePtr<u8>,
+    ) -> Option<RemotePtr<u8>> {
+        let reachable = |addr: RemotePtr<u8>| {
+            let offset = addr.as_usize() as i64 - from_end.as_usize() as i64;
+            offset as i32 as i64 == offset
+        };
+
+        let mut pages = self.extended_jump_pages.borrow_mut();
+        let found = pages
+            .iter()
+            .position(|p| reachable(p.addr + p.allocated) && p.allocated + size <= page_size());
+        let page_index = match found {
+            Some(i) => i,
+            None => {
+                // We're looking for a gap of three pages --- one page to allocate and
+                // a page on each side as a guard page.
+                let required_space = 3 * page_size();
+                let search_start = from_end
+                    .as_usize()
+                    .saturating_sub(i32::MAX as usize - required_space);
+                let free_mem = t
+                    .vm()
+                    .find_free_memory(required_space, Some(RemotePtr::new_from_val(search_start)));
+                let addr = RemotePtr::<u8>::cast(free_mem + page_size());
+                if !reachable(addr) {
+                    log!(LogDebug, "Can't find space close enough for the jump");
+                    return None;
+                }
+
+                let prot = ProtFlags::PROT_READ | ProtFlags::PROT_EXEC;
+                let flags = MapFlags::MAP_PRIVATE | MapFlags::MAP_ANONYMOUS;
+                let recorded = KernelMapping::new_with_opts(
+                    RemotePtr::cast(addr),
+                    RemotePtr::cast(addr + page_size()),
+                    OsStr::new(""),
+                    KernelMapping::NO_DEVICE,
+                    KernelMapping::NO_INODE,
+                    prot,
+                    flags,
+                    0,
+                );
+                {
+                    let mut remote = AutoRemoteSyscalls::new(t);
+                    remote.infallible_mmap_syscall(
+                        Some(RemotePtr::cast(addr)),
+                        page_size(),
+                        prot,
+                        flags | MapFlags::MAP_FIXED,
+                        -1,
+                        0,
+                    );
+                }
+                t.vm_shr_ptr().map(
+                    t,
+                    RemotePtr::cast(addr),
+                    page_size(),
+                    prot,
+                    flags,
+                    0,
+                    OsStr::new(""),
+                    KernelMapping::NO_DEVICE,
+                    KernelMapping::NO_INODE,
+                    None,
+                    Some(&recorded),
+                    None,
+                    None,
+                    None,
+                );
+                *t.vm().mapping_flags_of_mut(RemotePtr::cast(addr)) |= MappingFlags::IS_PATCH_STUBS;
+                t.trace_writer_mut().write_mapped_region(
+                    t,
+                    &recorded,
+                    &recorded.fake_stat(),
+                    &[],
+                    Some(MappingOrigin::PatchMapping),
+                    None,
+                );
+                pages.push(ExtendedJumpPage { addr, allocated: 0 });
+                pages.len() - 1
+            }
+        };
+
+        let page = &mut pages[page_index];
+        let jump_addr = page.addr + page.allocated;
+        page.allocated += size;
+        Some(jump_addr)
+    }
+}
+
+/// Search for a following short-jump instruction that targets an
+/// instruction after the syscall (and so inside the region we'd patch).
+/// False positives are OK. glibc's __clock_nanosleep needs this.
+fn has_interfering_branch(following_bytes: &[u8], hook: &syscall_patch_hook) -> bool {
+    for i in 0..following_bytes.len().saturating_sub(1) {
+        let b = following_bytes[i];
+        // Check for short conditional or unconditional jump
+        if b == 0xeb || (0x70..0x80).contains(&b) {
+            let offset = i as isize + 2 + following_bytes[i + 1] as i8 as isize;
+            let interferes = if hook.is_multi_instruction != 0 {
+                offset >= 0 && offset < hook.next_instruction_length as isize
+            } else {
+                offset == 0
+            };
+            if interferes {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Returns false if some other task in the address space has its ip inside
+/// the range of code we'd overwrite, in which case patching now would break
+/// that task.
+fn safe_for_syscall_patch(t: &RecordTask, ip: RemoteCodePtr, hook: &syscall_patch_hook) -> bool {
+    let patch_start = ip.decrement_by_syscall_insn_length(t.arch());
+    let patch_end = ip + hook.next_instruction_length as usize;
+    for tt in t.vm().task_set().iter_except(t.weak_self_ptr()) {
+        // Tasks we can't borrow are being operated on by someone up the
+        // stack; be conservative.
+        match tt.try_borrow() {
+            Ok(tt) => {
+                let tip = tt.ip();
+                if patch_start < tip && tip <= patch_end {
+                    return false;
+                }
+            }
+            Err(_) => return false,
+        }
+    }
+    true
+}
+
+fn write_and_record_bytes(t: &mut RecordTask, addr: RemotePtr<u8>, bytes: &[u8]) {
+    t.write_bytes_helper(RemotePtr::cast(addr), bytes, None, WriteFlags::empty());
+    t.record_local(RemotePtr::cast(addr), bytes);
+}
+
+/// Every patch we apply to vdso functions (for either architecture), so that
+/// tools reading a trace can tell whether a process had its vdso patched.
+pub fn vdso_syscall_patches() -> Vec<Vec<u8>> {
+    VDSO_SYSCALLS_X86
+        .iter()
+        .map(|&(_, syscallno)| x86_vdso_syscall_stub(syscallno))
+        .chain(
+            VDSO_SYSCALLS_X64
+                .iter()
+                .map(|&(_, syscallno)| x64_vdso_syscall_stub(syscallno)),
+        )
+        .collect()
 }
 
-fn patch_at_preload_init_arch<Arch: Architecture>(_t: &RecordTask, _patcher: &MonkeyPatcher) {
-    unimplemented!()
+/// `mov $syscallno,%eax; syscall; ret`
+fn x64_vdso_syscall_stub(syscallno: i32) -> Vec<u8> {
+    let mut stub = vec![0xb8];
+    stub.extend_from_slice(&(syscallno as u32).to_le_bytes());
+    stub.extend_from_slice(&[0x0f, 0x05, 0xc3]);
+    stub
+}
+
+/// The x86 vdso functions take their parameters on the stack, so load them
+/// into the syscall parameter registers first:
+/// `push %ebx; mov 8(%esp),%ebx; mov 12(%esp),%ecx; mov 16(%esp),%edx;
+///  mov $syscallno,%eax; int $0x80; pop %ebx; ret`
+fn x86_vdso_syscall_stub(syscallno: i32) -> Vec<u8> {
+    let mut stub = vec![
+        0x53, 0x8b, 0x5c, 0x24, 0x08, 0x8b, 0x4c, 0x24, 0x0c, 0x8b, 0x54, 0x24, 0x10, 0xb8,
+    ];
+    stub.extend_from_slice(&(syscallno as u32).to_le_bytes());
+    stub.extend_from_slice(&[0xcd, 0x80, 0x5b, 0xc3]);
+    stub
+}
+
+/// A function exported by the vdso: its absolute address in the tracee and
+/// its size.
+struct VdsoSymbol {
+    addr: RemotePtr<u8>,
+    size: usize,
+}
+
+/// Look up `names` in the dynamic symbol table of `t`'s vdso.
+fn find_vdso_symbols(t: &mut RecordTask, names: &[&str]) -> Vec<Option<VdsoSymbol>> {
+    let vdso = t.vm().vdso();
+    let data = read_mem(t, RemotePtr::<u8>::cast(vdso.start()), vdso.size(), None);
+    let elf = match Elf::parse(&data) {
+        Ok(elf) => elf,
+        Err(e) => {
+            log!(LogDebug, "Can't parse vdso: {:?}", e);
+            return names.iter().map(|_| None).collect();
+        }
+    };
+
+    // The vdso is mapped in its entirety starting at file offset 0, so
+    // translate symbol addresses into file offsets.
+    let addr_to_offset = |addr: u64| {
+        elf.program_headers
+            .iter()
+            .find(|ph| {
+                ph.p_type == PT_LOAD && ph.p_vaddr <= addr && addr < ph.p_vaddr + ph.p_filesz
+            })
+            .map(|ph| (addr - ph.p_vaddr + ph.p_offset) as usize)
+    };
+
+    names
+        .iter()
+        .map(|&name| {
+            elf.dynsyms
+                .iter()
+                .find(|sym| {
+                    matches!(elf.dynstrtab.get(sym.st_name), Some(Ok(sym_name)) if sym_name == name)
+                })
+                .and_then(|sym| {
+                    addr_to_offset(sym.st_value).map(|offset| VdsoSymbol {
+                        addr: RemotePtr::<u8>::cast(vdso.start()) + offset,
+                        size: sym.st_size as usize,
+                    })
+                })
+        })
+        .collect()
+}
+
+fn patch_vdso_syscalls(t: &mut RecordTask, syscalls: &[(&str, i32)], stub: fn(i32) -> Vec<u8>) {
+    let names: Vec<&str> = syscalls.iter().map(|s| s.0).collect();
+    let syms = find_vdso_symbols(t, &names);
+    for (&(name, syscallno), maybe_sym) in syscalls.iter().zip(syms) {
+        let sym = match maybe_sym {
+            Some(sym) => sym,
+            None => continue,
+        };
+        let patch = stub(syscallno);
+        if sym.size < patch.len() {
+            log!(
+                LogDebug,
+                "vdso function {} is too small ({} bytes) to monkeypatch",
+                name,
+                sym.size
+            );
+            continue;
+        }
+        write_and_record_bytes(t, sym.addr, &patch);
+        log!(
+            LogDebug,
+            "monkeypatched {} to syscall {}",
+            name,
+            syscall_name(syscallno, t.arch())
+        );
+    }
+}
+
+fn patch_after_exec_arch<Arch: Architecture>(t: &mut RecordTask, patcher: &MonkeyPatcher) {
+    if !t.vm().has_vdso() {
+        return;
+    }
+    match Arch::arch() {
+        crate::kernel_abi::SupportedArch::X86 => {
+            if let Some(sym) = find_vdso_symbols(t, &["__kernel_vsyscall"]).pop().unwrap() {
+                patcher.x86_vsyscall.set(sym.addr.to_code_ptr());
+            }
+            patch_vdso_syscalls(t, &VDSO_SYSCALLS_X86, x86_vdso_syscall_stub);
+        }
+        crate::kernel_abi::SupportedArch::X64 => {
+            patch_vdso_syscalls(t, &VDSO_SYSCALLS_X64, x64_vdso_syscall_stub);
+        }
+    }
+}
+
+fn patch_at_preload_init_arch<Arch: Architecture>(t: &mut RecordTask, patcher: &MonkeyPatcher) {
+    let params = read_val_mem(
+        t,
+        RemotePtr::<Arch::rdcall_init_preload_params>::new_from_val(t.regs_ref().arg1()),
+        None,
+    );
+    if !Arch::rdcall_init_preload_params_syscallbuf_enabled(&params) {
+        return;
+    }
+
+    let (hooks, hook_count, vsyscall_entry) = Arch::rdcall_init_preload_params_patch_hooks(&params);
+    patcher.init_dynamic_syscall_patching(t, hooks, hook_count);
+
+    let kernel_vsyscall = patcher.x86_vsyscall.get();
+    if Arch::arch() == crate::kernel_abi::SupportedArch::X86 && !kernel_vsyscall.is_null() {
+        // Redirect __kernel_vsyscall to the preload library's hook with
+        // `push $vsyscall_entry; ret`.
+        // Luckily, linux is happy for us to scribble directly over the vdso
+        // mapping's bytes without mprotecting the region.
+        let mut patch = vec![0x68];
+        patch.extend_from_slice(&(vsyscall_entry.register_value() as u32).to_le_bytes());
+        patch.push(0xc3);
+        write_and_record_bytes(t, kernel_vsyscall.to_data_ptr::<u8>(), &patch);
+        log!(
+            LogDebug,
+            "monkeypatched __kernel_vsyscall to jump to {}",
+            vsyscall_entry
+        );
+    }
+}
+
+fn patch_syscall_with_hook_arch<Arch: Architecture>(
+    patcher: &MonkeyPatcher,
+    t: &mut RecordTask,
+    hook: &syscall_patch_hook,
+) -> bool {
+    let arch = Arch::arch();
+    if arch == crate::kernel_abi::SupportedArch::X86 {
+        // On x86 syscalls go through __kernel_vsyscall, which we've already
+        // redirected to the hook.
+        return false;
+    }
+
+    // We're patching in a relative jump, so we need to compute the offset from
+    // the end of the jump to our actual destination.
+    // exit_syscall_and_prepare_restart() has moved ip back to the syscall
+    // instruction.
+    let jump_patch_start = t.ip().to_data_ptr::<u8>();
+    let patch_size = syscall_instruction_length(arch) + hook.next_instruction_length as usize;
+    ed_assert!(t, patch_size >= JUMP_PATCH_SIZE);
+    let jump_patch_end = jump_patch_start + JUMP_PATCH_SIZE;
+
+    let extended_jump_start =
+        match patcher.allocate_extended_jump(t, X64_EXTENDED_JUMP_SIZE, jump_patch_end) {
+            Some(addr) => addr,
+            None => return false,
+        };
+
+    let return_addr = (jump_patch_start + patch_size).as_usize() as u64;
+    let mut stub = vec![0x48, 0x8d, 0x64, 0x24, 0x80, 0xc7, 0x04, 0x24];
+    stub.extend_from_slice(&(return_addr as u32).to_le_bytes());
+    stub.extend_from_slice(&[0xc7, 0x44, 0x24, 0x04]);
+    stub.extend_from_slice(&((return_addr >> 32) as u32).to_le_bytes());
+    stub.extend_from_slice(&[0xff, 0x25, 0x00, 0x00, 0x00, 0x00]);
+    stub.extend_from_slice(&hook.hook_address.to_le_bytes());
+    debug_assert_eq!(stub.len(), X64_EXTENDED_JUMP_SIZE);
+    write_and_record_bytes(t, extended_jump_start, &stub);
+
+    let jump_offset = extended_jump_start.as_usize() as i64 - jump_patch_end.as_usize() as i64;
+    let jump_offset32 = jump_offset as i32;
+    ed_assert!(
+        t,
+        jump_offset32 as i64 == jump_offset,
+        "allocate_extended_jump didn't work"
+    );
+
+    // jmp rel32, padded with nops up to the end of the instruction we replaced
+    let mut jump_patch = vec![0xe9];
+    jump_patch.extend_from_slice(&jump_offset32.to_le_bytes());
+    jump_patch.resize(patch_size, 0x90);
+    write_and_record_bytes(t, jump_patch_start, &jump_patch);
+
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn hook(next_instruction: &[u8], is_multi_instruction: bool) -> syscall_patch_hook {
+        let mut next_instruction_bytes = [0u8; 14];
+        next_instruction_bytes[0..next_instruction.len()].copy_from_slice(next_instruction);
+        syscall_patch_hook {
+            is_multi_instruction: is_multi_instruction as u8,
+            next_instruction_length: next_instruction.len() as u8,
+            next_instruction_bytes,
+            hook_address: 0,
+        }
+    }
+
+    #[test]
+    fn interfering_branch() {
+        // cmp $-4095,%rax
+        let cmp = [0x48, 0x3d, 0x01, 0xf0, 0xff, 0xff];
+        let h = hook(&cmp, false);
+        let mut code = cmp.to_vec();
+        // jae +0: harmless
+        code.extend_from_slice(&[0x73, 0x00]);
+        assert!(!has_interfering_branch(&code, &h));
+        // jmp back to the cmp, which we would have overwritten
+        code.extend_from_slice(&[0xeb, 0xf6]);
+        assert!(has_interfering_branch(&code, &h));
+    }
+
+    #[test]
+    fn vdso_stubs() {
+        assert_eq!(
+            x64_vdso_syscall_stub(228),
+            vec![0xb8, 228, 0, 0, 0, 0x0f, 0x05, 0xc3]
+        );
+        assert_eq!(x86_vdso_syscall_stub(265).len(), 22);
+    }
 }
@@ -4,6 +4,7 @@ use crate::{
         perf_event::{
             perf_event_attr,
             perf_type_id,
+            PERF_COUNT_HW_BRANCH_INSTRUCTIONS,
             PERF_COUNT_HW_CPU_CYCLES,
             PERF_EVENT_IOC_DISABLE,
             PERF_EVENT_IOC_ENABLE,
@@ -19,7 +20,7 @@ use crate::{
     scoped_fd::ScopedFd,
     session::task::task_inner::task_inner::TaskInner,
     ticks::Ticks,
-    util::running_under_rd,
+    util::{allowed_cpus, running_under_rd},
 };
 use libc::{c_ulong, fcntl, ioctl, pid_t, F_SETFL, O_ASYNC};
 use nix::{
@@ -29,6 +30,8 @@ use nix::{
 };
 use raw_cpuid::CpuId;
 use std::{
+    fmt::{self, Display, Formatter},
+    fs,
     io::{stderr, Write},
     mem::size_of,
     os::unix::io::RawFd,
@@ -38,6 +41,7 @@ use std::{
 lazy_static! {
     static ref PMU_BRANCHES_ACCUMULATOR: Mutex<u32> = Mutex::new(0);
     static ref PMU_BUGS_AND_EXTRA: PmuBugsAndExtra = check_for_bugs_and_extra();
+    static ref HYBRID_PMU: Option<HybridPmu> = choose_hybrid_pmu();
     static ref PMU_ATTRIBUTES: PmuAttributes = get_init_attributes();
 }
 
@@ -65,6 +69,10 @@ const PERF_COUNT_RD: u32 = 0x72727272;
 /// hope that tracees don't either.
 pub const TIME_SLICE_SIGNAL: i32 = libc::SIGSTKFLT;
 
+/// On hybrid CPUs, PERF_TYPE_HARDWARE events name the PMU to use in the
+/// upper half of `config`.
+const PERF_PMU_TYPE_SHIFT: u32 = 32;
+
 const IN_TX: u64 = 1 << 32;
 const IN_TXCP: u64 = 1 << 33;
 
@@ -91,10 +99,13 @@ bitflags! {
 
         const PMU_TICKS_TAKEN_BRANCHES_WITH_SKIP_INTEL_BUG_CHECK =
             Self::PMU_TICKS_TAKEN_BRANCHES.bits | Self::PMU_SKIP_INTEL_BUG_CHECK.bits;
+
+        const PMU_TICKS_RCB_AND_TAKEN_BRANCHES_WITH_SKIP_INTEL_BUG_CHECK =
+            Self::PMU_TICKS_RCB.bits | Self::PMU_TICKS_TAKEN_BRANCHES_WITH_SKIP_INTEL_BUG_CHECK.bits;
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum TicksSemantics {
     TicksRetiredConditionalBranches,
     TicksTakenBranches,
@@ -102,6 +113,35 @@ pub enum TicksSemantics {
 
 use TicksSemantics::*;
 
+/// The kinds of core on a hybrid CPU (Intel Alder Lake and later). Each kind
+/// has its own PMU, with its own encoding of the events we use, so a tracee
+/// must not migrate between kinds while we're counting its ticks.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CoreType {
+    /// P-cores, with the `cpu_core` PMU.
+    Performance,
+    /// E-cores, with the `cpu_atom` PMU.
+    Efficiency,
+}
+
+impl Display for CoreType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CoreType::Performance => write!(f, "P-core"),
+            CoreType::Efficiency => write!(f, "E-core"),
+        }
+    }
+}
+
+/// The PMU of one kind of core on a hybrid CPU.
+struct HybridPmu {
+    core_type: CoreType,
+    /// What to put in `perf_event_attr::type_` for raw events on this PMU.
+    type_id: u32,
+    /// The CPUs with this kind of core.
+    cpus: Vec<u32>,
+}
+
 /// Find out the cpu model using the cpuid instruction.
 /// Full list of CPUIDs at http://sandpile.org/x86/cpuid.htm
 /// Another list at
@@ -122,8 +162,17 @@ enum CpuMicroarch {
     IntelGoldmont,
     IntelKabylake,
     IntelCometlake,
+    /// P-cores of Alder Lake.
+    IntelAlderlake,
+    /// P-cores of Raptor Lake.
+    IntelRaptorlake,
+    /// E-cores of Alder Lake and Raptor Lake. CPUID reports the same model on
+    /// both kinds of core, so this is never detected, only chosen for the
+    /// E-cores of a hybrid CPU.
+    IntelGracemont,
     AMDF15R30,
-    AMDRyzen,
+    /// Zen, Zen+, Zen 2, Zen 3 and Zen 4.
+    AMDZen,
 }
 use CpuMicroarch::*;
 
@@ -176,37 +225,136 @@ fn get_cpu_microarch() -> CpuMicroarch {
         0x506f0 => return IntelGoldmont,
         0x806e0 | 0x906e0 => return IntelKabylake,
         0xa0660 => return IntelCometlake,
+        0x90670 | 0x906a0 => return IntelAlderlake,
+        0xb0670 | 0xb06a0 | 0xb06f0 => return IntelRaptorlake,
         0x30f00 => return AMDF15R30,
-        0x00f10 => {
-            if ext_family == 8 {
-                if !Flags::get().suppress_environment_warnings {
-                    write!(
-                        stderr(),
-                        "You have a Ryzen CPU. The Ryzen\n\
-                     retired-conditional-branches hardware\n\
-                     performance counter is not accurate enough; rd will\n\
-                     be unreliable.\n\
-                     See https://github.com/mozilla/rr/issues/2034.\n"
-                    )
-                    .unwrap();
-                }
-                return AMDRyzen;
-            }
+        // Naples, Whitehaven, Summit Ridge, Snowy Owl (Zen), Milan (Zen 3)
+        0x00f10 | 0x00f20 if ext_family == 8 || ext_family == 0xa => return zen(),
+        // Raven Ridge, Dali (Zen), Picasso (Zen+), Rome, Castle Peak, Renoir,
+        // Lucienne, Matisse, Van Gogh (Zen 2)
+        0x10f10 | 0x10f80 | 0x20f00 | 0x30f10 | 0x60f00 | 0x60f80 | 0x70f10 | 0x90f00
+            if ext_family == 8 =>
+        {
+            return zen()
+        }
+        // Vermeer, Cezanne (Zen 3), Rembrandt (Zen 3+), Genoa, Raphael,
+        // Phoenix (Zen 4)
+        0x10f10 | 0x20f10 | 0x40f40 | 0x50f00 | 0x60f10 | 0x70f40 | 0xa0f10
+            if ext_family == 0xa =>
+        {
+            return zen()
         }
         _ => (),
     }
 
     if vendor_info_string == "AuthenticAMD" {
         clean_fatal!(
-            "AMD CPUs not supported.\n\
-             For Ryzen, see https://github.com/mozilla/rr/issues/2034.\n\
-             For post-Ryzen CPUs, please file a Github issue."
+            "AMD CPU type {:#x} (extended family {:#x}) unknown.\n\
+             AMD CPUs before Zen are not supported apart from Family 15h Revision 30h.",
+            cpu_type,
+            ext_family
         );
     } else {
         clean_fatal!("Intel CPU type {:#x} unknown", cpu_type);
     }
 }
 
+fn zen() -> CpuMicroarch {
+    if !Flags::get().suppress_environment_warnings {
+        write!(
+            stderr(),
+            "You have an AMD Zen CPU. Its retired-conditional-branches\n\
+             counter is only accurate with the SpecLockMap optimization\n\
+             disabled; otherwise rd will be unreliable.\n\
+             See https://github.com/rr-debugger/rr/wiki/Zen.\n"
+        )
+        .unwrap();
+    }
+    AMDZen
+}
+
+/// On a hybrid CPU, the PMU of the kind of core tracees will run on: the one
+/// given with `--core-type`, otherwise P-cores if we're allowed to run on any.
+/// `None` if the CPU isn't hybrid.
+fn choose_hybrid_pmu() -> Option<HybridPmu> {
+    let forced_core_type = Flags::get().forced_core_type;
+    let pmus: Vec<HybridPmu> = [
+        (CoreType::Performance, "cpu_core"),
+        (CoreType::Efficiency, "cpu_atom"),
+    ]
+    .iter()
+    .filter_map(|&(core_type, name)| read_hybrid_pmu(core_type, name))
+    .collect();
+    if pmus.is_empty() {
+        if let Some(core_type) = forced_core_type {
+            log!(
+                LogWarn,
+                "Ignoring --core-type={}: this CPU only has one kind of core",
+                core_type
+            );
+        }
+        return None;
+    }
+
+    let allowed = allowed_cpus();
+    let usable = |pmu: &HybridPmu| pmu.cpus.iter().any(|cpu| allowed.contains(cpu));
+    let maybe_pmu = match forced_core_type {
+        Some(core_type) => pmus.into_iter().find(|pmu| pmu.core_type == core_type),
+        None => pmus.into_iter().find(|pmu| usable(pmu)),
+    };
+    match maybe_pmu {
+        Some(pmu) if usable(&pmu) => {
+            log!(
+                LogInfo,
+                "Hybrid CPU: using the {} PMU, CPUs {:?}",
+                pmu.core_type,
+                pmu.cpus
+            );
+            Some(pmu)
+        }
+        _ => {
+            clean_fatal!(
+                "Hybrid CPU, but rd isn't allowed to run on any {}s.",
+                forced_core_type.unwrap_or(CoreType::Performance)
+            );
+        }
+    }
+}
+
+/// The PMU called `name` in sysfs, if there is one.
+fn read_hybrid_pmu(core_type: CoreType, name: &str) -> Option<HybridPmu> {
+    let dir = format!("/sys/devices/{}", name);
+    let type_id = fs::read_to_string(format!("{}/type", dir))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    let cpus = parse_cpu_list(&fs::read_to_string(format!("{}/cpus", dir)).ok()?)?;
+    Some(HybridPmu {
+        core_type,
+        type_id,
+        cpus,
+    })
+}
+
+/// Parse a list of CPUs in the kernel's format, e.g. `0-3,8,10-11`.
+fn parse_cpu_list(list: &str) -> Option<Vec<u32>> {
+    let mut cpus = Vec::new();
+    for part in list.trim().split(',').filter(|p| !p.is_empty()) {
+        let mut range = part.splitn(2, '-');
+        let first: u32 = range.next()?.parse().ok()?;
+        let last: u32 = match range.next() {
+            Some(last) => last.parse().ok()?,
+            None => first,
+        };
+        if last < first {
+            return None;
+        }
+        cpus.extend(first..=last);
+    }
+    Some(cpus)
+}
+
 struct PmuBugsAndExtra {
     has_ioc_period_bug: bool,
     supports_txcp: bool,
@@ -319,18 +467,56 @@ fn new_perf_event_attr(type_id: perf_type_id, config: u64) -> perf_event_attr {
     attr
 }
 
+/// A raw event on the PMU of the cores tracees run on.
+fn raw_event_attr(config: u64) -> perf_event_attr {
+    match &*HYBRID_PMU {
+        Some(pmu) => new_perf_event_attr(pmu.type_id, config),
+        None => new_perf_event_attr(PERF_TYPE_RAW, config),
+    }
+}
+
+/// A generic hardware event, counted by the PMU of the cores tracees run on.
+fn hardware_event_attr(config: u64) -> perf_event_attr {
+    match &*HYBRID_PMU {
+        Some(pmu) => new_perf_event_attr(
+            PERF_TYPE_HARDWARE,
+            config | ((pmu.type_id as u64) << PERF_PMU_TYPE_SHIFT),
+        ),
+        None => new_perf_event_attr(PERF_TYPE_HARDWARE, config),
+    }
+}
+
 struct PmuAttributes {
     pmu_flags: PmuFlags,
     skid_size: Ticks,
+    /// Counts ticks with the default semantics, see
+    /// `PerfCounters::default_ticks_semantics()`.
     ticks_attr: perf_event_attr,
+    /// Counts taken branches on CPUs where that's not the default.
+    taken_branches_attr: Option<perf_event_attr>,
     hw_interrupts_attr: Option<perf_event_attr>,
     cycles_attr: Option<perf_event_attr>,
+    /// Subtracted from taken branches.
     minus_ticks_attr: Option<perf_event_attr>,
 }
 
+/// The counter for ticks with `ticks_semantics`.
+fn ticks_attr(ticks_semantics: TicksSemantics) -> perf_event_attr {
+    match (ticks_semantics, PMU_ATTRIBUTES.taken_branches_attr) {
+        (TicksTakenBranches, Some(attr)) => attr,
+        _ => PMU_ATTRIBUTES.ticks_attr,
+    }
+}
+
 /// Gets the values for the lazy_static! global PMU_ATTRIBUTES.
 fn get_init_attributes() -> PmuAttributes {
-    let uarch = get_cpu_microarch();
+    let uarch = match &*HYBRID_PMU {
+        Some(HybridPmu {
+            core_type: CoreType::Efficiency,
+            ..
+        }) => IntelGracemont,
+        _ => get_cpu_microarch(),
+    };
     let mut maybe_pmu: Option<&PmuConfig> = None;
     for config in &PMU_CONFIGS {
         if uarch == config.uarch {
@@ -349,6 +535,7 @@ fn get_init_attributes() -> PmuAttributes {
     let pmu_flags;
     let skid_size;
     let ticks_attr;
+    let mut taken_branches_attr = None;
     let mut hw_interrupts_attr = None;
     let mut cycles_attr = None;
     let mut minus_ticks_attr = None;
@@ -359,20 +546,16 @@ fn get_init_attributes() -> PmuAttributes {
     } else {
         skid_size = pmu.skid_size;
         pmu_flags = pmu.flags;
-        ticks_attr = new_perf_event_attr(PERF_TYPE_RAW, pmu.rcb_cntr_event as u64);
+        ticks_attr = raw_event_attr(pmu.rcb_cntr_event as u64);
+        if pmu.taken_branches_cntr_event != 0 {
+            taken_branches_attr = Some(raw_event_attr(pmu.taken_branches_cntr_event as u64));
+        }
         if pmu.minus_ticks_cntr_event != 0 {
-            minus_ticks_attr = Some(new_perf_event_attr(
-                PERF_TYPE_RAW,
-                pmu.minus_ticks_cntr_event as u64,
-            ));
+            minus_ticks_attr = Some(raw_event_attr(pmu.minus_ticks_cntr_event as u64));
         }
 
-        cycles_attr = Some(new_perf_event_attr(
-            PERF_TYPE_HARDWARE,
-            PERF_COUNT_HW_CPU_CYCLES as u64,
-        ));
-        let mut hw_interrupts_attr_bare =
-            new_perf_event_attr(PERF_TYPE_RAW, pmu.hw_intr_cntr_event as u64);
+        cycles_attr = Some(hardware_event_attr(PERF_COUNT_HW_CPU_CYCLES as u64));
+        let mut hw_interrupts_attr_bare = raw_event_attr(pmu.hw_intr_cntr_event as u64);
         // libpfm encodes the event with this bit set, so we'll do the
         // same thing.  Unclear if necessary.
         hw_interrupts_attr_bare.set_exclude_hv(1);
@@ -383,6 +566,7 @@ fn get_init_attributes() -> PmuAttributes {
         pmu_flags,
         skid_size,
         ticks_attr,
+        taken_branches_attr,
         hw_interrupts_attr,
         cycles_attr,
         minus_ticks_attr,
@@ -400,11 +584,42 @@ fn get_init_attributes() -> PmuAttributes {
 /// - cb = eventsel for event HW_INTERRUPTS.RECEIVED
 /// See Intel 64 and IA32 Architectures Performance Monitoring Events.
 /// See check_events from libpfm4.
-const PMU_CONFIGS: [PmuConfig; 15] = [
+const PMU_CONFIGS: [PmuConfig; 18] = [
+    PmuConfig {
+        uarch: IntelRaptorlake,
+        name: "Intel Raptorlake",
+        rcb_cntr_event: 0x5111c4,
+        taken_branches_cntr_event: 0,
+        minus_ticks_cntr_event: 0,
+        hw_intr_cntr_event: 0x5301cb,
+        skid_size: 125,
+        flags: PmuFlags::PMU_TICKS_RCB,
+    },
+    PmuConfig {
+        uarch: IntelAlderlake,
+        name: "Intel Alderlake",
+        rcb_cntr_event: 0x5111c4,
+        taken_branches_cntr_event: 0,
+        minus_ticks_cntr_event: 0,
+        hw_intr_cntr_event: 0x5301cb,
+        skid_size: 125,
+        flags: PmuFlags::PMU_TICKS_RCB,
+    },
+    PmuConfig {
+        uarch: IntelGracemont,
+        name: "Intel Gracemont",
+        rcb_cntr_event: 0x517ec4,
+        taken_branches_cntr_event: 0,
+        minus_ticks_cntr_event: 0,
+        hw_intr_cntr_event: 0x5301cb,
+        skid_size: 125,
+        flags: PmuFlags::PMU_TICKS_RCB,
+    },
     PmuConfig {
         uarch: IntelCometlake,
         name: "Intel Cometlake",
         rcb_cntr_event: 0x5101c4,
+        taken_branches_cntr_event: 0,
         minus_ticks_cntr_event: 0,
         hw_intr_cntr_event: 0x5301cb,
         skid_size: 100,
@@ -414,6 +629,7 @@ const PMU_CONFIGS: [PmuConfig; 15] = [
         uarch: IntelKabylake,
         name: "Intel Kabylake",
         rcb_cntr_event: 0x5101c4,
+        taken_branches_cntr_event: 0,
         minus_ticks_cntr_event: 0,
         hw_intr_cntr_event: 0x5301cb,
         skid_size: 100,
@@ -423,6 +639,7 @@ const PMU_CONFIGS: [PmuConfig; 15] = [
         uarch: IntelSilvermont,
         name: "Intel Silvermont",
         rcb_cntr_event: 0x517ec4,
+        taken_branches_cntr_event: 0,
         minus_ticks_cntr_event: 0,
         hw_intr_cntr_event: 0x5301cb,
         skid_size: 100,
@@ -432,6 +649,7 @@ const PMU_CONFIGS: [PmuConfig; 15] = [
         uarch: IntelGoldmont,
         name: "Intel Goldmont",
         rcb_cntr_event: 0x517ec4,
+        taken_branches_cntr_event: 0,
         minus_ticks_cntr_event: 0,
         hw_intr_cntr_event: 0x5301cb,
         skid_size: 100,
@@ -441,6 +659,7 @@ const PMU_CONFIGS: [PmuConfig; 15] = [
         uarch: IntelSkylake,
         name: "Intel Skylake",
         rcb_cntr_event: 0x5101c4,
+        taken_branches_cntr_event: 0,
         minus_ticks_cntr_event: 0,
         hw_intr_cntr_event: 0x5301cb,
         skid_size: 100,
@@ -450,6 +669,7 @@ const PMU_CONFIGS: [PmuConfig; 15] = [
         uarch: IntelBroadwell,
         name: "Intel Broadwell",
         rcb_cntr_event: 0x5101c4,
+        taken_branches_cntr_event: 0,
         minus_ticks_cntr_event: 0,
         hw_intr_cntr_event: 0x5301cb,
         skid_size: 100,
@@ -459,6 +679,7 @@ const PMU_CONFIGS: [PmuConfig; 15] = [
         uarch: IntelHaswell,
         name: "Intel Haswell",
         rcb_cntr_event: 0x5101c4,
+        taken_branches_cntr_event: 0,
         minus_ticks_cntr_event: 0,
         hw_intr_cntr_event: 0x5301cb,
         skid_size: 100,
@@ -468,6 +689,7 @@ const PMU_CONFIGS: [PmuConfig; 15] = [
         uarch: IntelIvyBridge,
         name: "Intel Ivy Bridge",
         rcb_cntr_event: 0x5101c4,
+        taken_branches_cntr_event: 0,
         minus_ticks_cntr_event: 0,
         hw_intr_cntr_event: 0x5301cb,
         skid_size: 100,
@@ -477,6 +699,7 @@ const PMU_CONFIGS: [PmuConfig; 15] = [
         uarch: IntelSandyBridge,
         name: "Intel Sandy Bridge",
         rcb_cntr_event: 0x5101c4,
+        taken_branches_cntr_event: 0,
         minus_ticks_cntr_event: 0,
         hw_intr_cntr_event: 0x5301cb,
         skid_size: 100,
@@ -486,6 +709,7 @@ const PMU_CONFIGS: [PmuConfig; 15] = [
         uarch: IntelNehalem,
         name: "Intel Nehalem",
         rcb_cntr_event: 0x5101c4,
+        taken_branches_cntr_event: 0,
         minus_ticks_cntr_event: 0,
         hw_intr_cntr_event: 0x50011d,
         skid_size: 100,
@@ -495,6 +719,7 @@ const PMU_CONFIGS: [PmuConfig; 15] = [
         uarch: IntelWestmere,
         name: "Intel Westmere",
         rcb_cntr_event: 0x5101c4,
+        taken_branches_cntr_event: 0,
         minus_ticks_cntr_event: 0,
         hw_intr_cntr_event: 0x50011d,
         skid_size: 100,
@@ -504,6 +729,7 @@ const PMU_CONFIGS: [PmuConfig; 15] = [
         uarch: IntelPenryn,
         name: "Intel Penryn",
         rcb_cntr_event: 0,
+        taken_branches_cntr_event: 0,
         minus_ticks_cntr_event: 0,
         hw_intr_cntr_event: 0,
         skid_size: 100,
@@ -513,6 +739,7 @@ const PMU_CONFIGS: [PmuConfig; 15] = [
         uarch: IntelMerom,
         name: "Intel Merom",
         rcb_cntr_event: 0,
+        taken_branches_cntr_event: 0,
         minus_ticks_cntr_event: 0,
         hw_intr_cntr_event: 0,
         skid_size: 100,
@@ -522,28 +749,39 @@ const PMU_CONFIGS: [PmuConfig; 15] = [
         uarch: AMDF15R30,
         name: "AMD Family 15h Revision 30h",
         rcb_cntr_event: 0xc4,
+        taken_branches_cntr_event: 0,
         minus_ticks_cntr_event: 0xc6,
         hw_intr_cntr_event: 0,
         skid_size: 250,
         flags: PmuFlags::PMU_TICKS_TAKEN_BRANCHES_WITH_SKIP_INTEL_BUG_CHECK,
     },
     PmuConfig {
-        uarch: AMDRyzen,
-        name: "AMD Ryzen",
+        uarch: AMDZen,
+        name: "AMD Zen",
         rcb_cntr_event: 0x5100d1,
-        minus_ticks_cntr_event: 0,
+        taken_branches_cntr_event: 0x5100c4,
+        minus_ticks_cntr_event: 0x5100c6,
         hw_intr_cntr_event: 0,
-        skid_size: 1000,
-        flags: PmuFlags::PMU_TICKS_RCB,
+        skid_size: 10000,
+        flags: PmuFlags::PMU_TICKS_RCB_AND_TAKEN_BRANCHES_WITH_SKIP_INTEL_BUG_CHECK,
     },
 ];
 
 struct PmuConfig {
     uarch: CpuMicroarch,
     name: &'static str,
+    /// The ticks counter for the default semantics. Despite the name, this
+    /// counts taken branches on CPUs that only support those.
     rcb_cntr_event: u32,
+    /// Counts taken branches on CPUs that count retired conditional branches
+    /// by default but can do both.
+    taken_branches_cntr_event: u32,
+    /// Counts events to subtract from the taken branches count.
     minus_ticks_cntr_event: u32,
     hw_intr_cntr_event: u32,
+    /// How far past the programmed count the ticks interrupt may fire on this
+    /// microarchitecture. Found by trial and error; see
+    /// `PerfCounters::skid_size()`.
     skid_size: Ticks,
     flags: PmuFlags,
 }
@@ -588,7 +826,7 @@ fn start_counter(tid: pid_t, group_fd: i32, attr: &mut perf_event_attr) -> (Scop
     };
     if 0 >= fd
         && errno() == libc::EINVAL
-        && attr.type_ == PERF_TYPE_RAW
+        && attr.type_ != PERF_TYPE_HARDWARE
         && (attr.config & IN_TXCP == IN_TXCP)
     {
         // The kernel might not support IN_TXCP, so try again without it.
@@ -643,6 +881,30 @@ fn start_counter(tid: pid_t, group_fd: i32, attr: &mut perf_event_attr) -> (Scop
     (ScopedFd::from_raw(fd), disabled_txcp)
 }
 
+/// Try to open a generic hardware counter for this thread. Unlike
+/// `start_counter()` this doesn't die if that's not possible, it returns the
+/// errno perf_event_open() failed with.
+pub fn probe_hardware_counter() -> Result<(), i32> {
+    let mut attr =
+        new_perf_event_attr(PERF_TYPE_HARDWARE, PERF_COUNT_HW_BRANCH_INSTRUCTIONS as u64);
+    attr.set_disabled(1);
+    let fd: RawFd = unsafe {
+        libc::syscall(
+            libc::SYS_perf_event_open,
+            &mut attr as *mut perf_event_attr,
+            0,
+            -1,
+            -1,
+            0,
+        ) as RawFd
+    };
+    if fd < 0 {
+        return Err(errno());
+    }
+    drop(ScopedFd::from_raw(fd));
+    Ok(())
+}
+
 /// Wrapper for the libc ioctl call.
 fn perf_ioctl(fd: &ScopedFd, param1: c_ulong, param2: *const u64) -> i32 {
     unsafe { ioctl(fd.as_raw(), param1, param2) }
@@ -801,9 +1063,12 @@ impl PerfCounters {
                 ticks_period
             );
 
-            let mut attr = PMU_ATTRIBUTES.ticks_attr;
+            let mut attr = ticks_attr(self.ticks_semantics);
             // Note that perf_event_attr struct implements the `Copy` trait.
-            let maybe_minus_attr = PMU_ATTRIBUTES.minus_ticks_attr;
+            let maybe_minus_attr = match self.ticks_semantics {
+                TicksTakenBranches => PMU_ATTRIBUTES.minus_ticks_attr,
+                TicksRetiredConditionalBranches => None,
+            };
             attr.__bindgen_anon_1.sample_period = ticks_period;
             self.fd_ticks_interrupt = start_counter(self.tid, -1, &mut attr).0;
             match maybe_minus_attr {
@@ -971,26 +1236,18 @@ impl PerfCounters {
     }
 
     /// Return the number of ticks we need for an emulated branch.
-    pub fn ticks_for_unconditional_indirect_branch(_task: &TaskInner) -> Ticks {
-        if PMU_ATTRIBUTES
-            .pmu_flags
-            .contains(PmuFlags::PMU_TICKS_TAKEN_BRANCHES)
-        {
-            1
-        } else {
-            0
+    pub fn ticks_for_unconditional_indirect_branch(task: &TaskInner) -> Ticks {
+        match task.hpc.ticks_semantics() {
+            TicksTakenBranches => 1,
+            TicksRetiredConditionalBranches => 0,
         }
     }
 
     /// Return the number of ticks we need for a direct call.
-    pub fn ticks_for_direct_call(_task: &TaskInner) -> Ticks {
-        if PMU_ATTRIBUTES
-            .pmu_flags
-            .contains(PmuFlags::PMU_TICKS_TAKEN_BRANCHES)
-        {
-            1
-        } else {
-            0
+    pub fn ticks_for_direct_call(task: &TaskInner) -> Ticks {
+        match task.hpc.ticks_semantics() {
+            TicksTakenBranches => 1,
+            TicksRetiredConditionalBranches => 0,
         }
     }
 
@@ -1103,24 +1360,38 @@ impl PerfCounters {
         }
     }
 
+    /// Retired conditional branches where supported, since that doesn't need
+    /// adjusting for far branches.
     pub fn default_ticks_semantics() -> TicksSemantics {
+        if PMU_ATTRIBUTES.pmu_flags.contains(PmuFlags::PMU_TICKS_RCB) {
+            return TicksRetiredConditionalBranches;
+        }
         if PMU_ATTRIBUTES
             .pmu_flags
             .contains(PmuFlags::PMU_TICKS_TAKEN_BRANCHES)
         {
             return TicksTakenBranches;
         }
-        if PMU_ATTRIBUTES.pmu_flags.contains(PmuFlags::PMU_TICKS_RCB) {
-            return TicksRetiredConditionalBranches;
-        }
         fatal!("Unsupported architecture");
         unreachable!()
     }
 
     /// When an interrupt is requested, at most this many ticks may elapse before
-    /// the interrupt is delivered.
+    /// the interrupt is delivered. This is the value for the CPU's
+    /// microarchitecture unless overridden with `--skid-size`.
     pub fn skid_size() -> Ticks {
-        PMU_ATTRIBUTES.skid_size
+        Flags::get().skid_size.unwrap_or(PMU_ATTRIBUTES.skid_size)
+    }
+
+    /// On a hybrid CPU, the kind of core whose PMU we program. Tracees must
+    /// only run on cores of that kind.
+    pub fn core_type() -> Option<CoreType> {
+        HYBRID_PMU.as_ref().map(|pmu| pmu.core_type)
+    }
+
+    /// The CPUs of kind `core_type()`, on a hybrid CPU.
+    pub fn core_type_cpus() -> Option<&'static [u32]> {
+        HYBRID_PMU.as_ref().map(|pmu| pmu.cpus.as_slice())
     }
 
     /// Use a separate skid_size for recording since we seem to see more skid
@@ -1136,3 +1407,20 @@ impl Drop for PerfCounters {
         self.stop()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cpu_lists() {
+        assert_eq!(
+            parse_cpu_list("0-3,8,10-11\n"),
+            Some(vec![0, 1, 2, 3, 8, 10, 11])
+        );
+        assert_eq!(parse_cpu_list("16-23"), Some((16..24).collect()));
+        assert_eq!(parse_cpu_list(""), Some(vec![]));
+        assert_eq!(parse_cpu_list("3-1"), None);
+        assert_eq!(parse_cpu_list("a"), None);
+    }
+}
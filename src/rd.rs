@@ -25,6 +25,12 @@ pub const RD_RESERVED_ROOT_DIR_FD: i32 = 1000;
 /// Only the outermost rd uses this. Inner rd replays will use a different fd.
 pub const RD_RESERVED_SOCKET_FD: i32 = 1001;
 
+/// Fds rd opens in a tracee for its own use during replay are moved to this
+/// number or above, out of the way of the numbers programs normally get.
+/// Tracee fds keep the numbers they had during recording, and a program
+/// that compares or prints them mustn't be able to tell the difference.
+pub const RD_RESERVED_FD_FLOOR: i32 = RD_RESERVED_SOCKET_FD + 1;
+
 /// The preferred fd that rd uses to control tracee desched. Some software
 /// (e.g. the chromium IPC code) wants to have the first few fds all to itself,
 /// so we need to stay above some floor. Tracee close()es of the fd that is
@@ -0,0 +1,148 @@
+//! Where rd puts the mappings it creates in tracees: syscallbufs and scratch
+//! memory. Left to the kernel, their addresses follow the ASLR layout of the
+//! recording machine, and replay on another machine (or kernel) may find the
+//! recorded range already taken by a stack, vdso or heap the kernel placed
+//! differently there.
+//!
+//! So rd picks the addresses itself, in a window just below the rd page
+//! where the kernel doesn't put anything by default. The slot in the window
+//! is derived from what the mapping is for, the task's recorded tid and the
+//! exec count of its address space, none of which depend on the machine.
+//! If the slot is taken, e.g. by a tracee's MAP_FIXED mapping, the following
+//! slots are tried in order.
+//!
+//! The address that was picked is recorded in the trace along with the
+//! mapping and the number of slots skipped, and replay maps it there without
+//! choosing again. Whatever slots were skipped while recording, the result
+//! is a range the replay machine leaves free too.
+
+use crate::{
+    kernel_abi::common::preload_interface::RD_PAGE_ADDR,
+    remote_ptr::{RemotePtr, Void},
+    session::address_space::address_space::AddressSpace,
+    util::page_size,
+};
+use libc::pid_t;
+
+/// The window ends at the rd page.
+const WINDOW_END: usize = RD_PAGE_ADDR;
+const WINDOW_SIZE: usize = 0x1000_0000;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RdMappingKind {
+    Syscallbuf,
+    Scratch,
+}
+
+/// Slots in the window have the size of the mapping rounded up to a power of
+/// two, so each candidate is aligned to its own size.
+fn slot_size(size: usize) -> usize {
+    size.max(page_size()).next_power_of_two()
+}
+
+/// The slot (out of `slots`) to try at the `attempt`th attempt.
+fn candidate_slot(
+    kind: RdMappingKind,
+    rec_tid: pid_t,
+    exec_count: u32,
+    attempt: usize,
+    slots: usize,
+) -> usize {
+    // A fixed mix, not std's Hasher: recording and replay may be done by
+    // different builds of rd.
+    let mut x = (rec_tid as u32 as u64) << 32 | exec_count as u64;
+    x ^= kind as u64 * 0x9e37_79b9_7f4a_7c15;
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^= x >> 31;
+    (x as usize).wrapping_add(attempt) % slots
+}
+
+/// An address for a `kind` mapping of `size` bytes for the task with recorded
+/// tid `rec_tid` in `vm`, and the number of slots that had to be skipped
+/// because something was mapped there. Returns `None` if the mapping is
+/// bigger than the window or the whole window is taken; let the kernel
+/// choose then.
+pub fn choose_rd_mapping_address(
+    vm: &AddressSpace,
+    kind: RdMappingKind,
+    rec_tid: pid_t,
+    size: usize,
+) -> Option<(RemotePtr<Void>, usize)> {
+    choose_address(kind, rec_tid, vm.uid().exec_count(), size, |start, end| {
+        vm.maps_containing_or_after(start)
+            .into_iter()
+            .next()
+            .map_or(false, |(_, m)| m.map.start() < end)
+    })
+}
+
+/// `choose_rd_mapping_address()`, with `taken` saying whether anything is
+/// mapped in [start, end).
+fn choose_address<F: Fn(RemotePtr<Void>, RemotePtr<Void>) -> bool>(
+    kind: RdMappingKind,
+    rec_tid: pid_t,
+    exec_count: u32,
+    size: usize,
+    taken: F,
+) -> Option<(RemotePtr<Void>, usize)> {
+    let slot_size = slot_size(size);
+    let slots = WINDOW_SIZE / slot_size;
+    if slots == 0 {
+        return None;
+    }
+    let window_start = WINDOW_END - WINDOW_SIZE;
+    for attempt in 0..slots {
+        let slot = candidate_slot(kind, rec_tid, exec_count, attempt, slots);
+        let start = RemotePtr::<Void>::new_from_val(window_start + slot * slot_size);
+        if !taken(start, start + size) {
+            return Some((start, attempt));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn candidate_slots() {
+        let slots = 64;
+        let first = candidate_slot(RdMappingKind::Scratch, 1234, 1, 0, slots);
+        assert_eq!(
+            first,
+            candidate_slot(RdMappingKind::Scratch, 1234, 1, 0, slots)
+        );
+        assert_eq!(
+            (first + 1) % slots,
+            candidate_slot(RdMappingKind::Scratch, 1234, 1, 1, slots)
+        );
+        assert!(candidate_slot(RdMappingKind::Syscallbuf, 1234, 1, 0, slots) < slots);
+    }
+
+    #[test]
+    fn taken_slots_are_skipped() {
+        let size = 4 * page_size();
+        let choose = |taken: &dyn Fn(RemotePtr<Void>) -> bool| {
+            choose_address(RdMappingKind::Scratch, 1234, 1, size, |start, _| {
+                taken(start)
+            })
+        };
+        let (first, skipped) = choose(&|_| false).unwrap();
+        assert_eq!(skipped, 0);
+        assert!(first.as_usize() >= WINDOW_END - WINDOW_SIZE);
+        assert!(first.as_usize() + size <= WINDOW_END);
+        assert_eq!(first.as_usize() % slot_size(size), 0);
+
+        // With the first two candidates taken, the third is used.
+        let (second, _) = choose(&|start| start == first).unwrap();
+        let (third, skipped) = choose(&|start| start == first || start == second).unwrap();
+        assert_eq!(skipped, 2);
+        assert!(third != first && third != second);
+
+        assert!(choose(&|_| true).is_none());
+        let too_big = 2 * WINDOW_SIZE;
+        assert!(choose_address(RdMappingKind::Scratch, 1234, 1, too_big, |_, _| false).is_none());
+    }
+}
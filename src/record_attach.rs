@@ -0,0 +1,213 @@
+use crate::{
+    bindings::ptrace::{
+        ptrace,
+        PTRACE_INTERRUPT,
+        PTRACE_O_TRACECLONE,
+        PTRACE_O_TRACEEXEC,
+        PTRACE_O_TRACEEXIT,
+        PTRACE_O_TRACEFORK,
+        PTRACE_O_TRACESECCOMP,
+        PTRACE_O_TRACESYSGOOD,
+        PTRACE_O_TRACEVFORK,
+        PTRACE_O_TRACEVFORKDONE,
+        PTRACE_SEIZE,
+    },
+    flags::Flags,
+    kernel_metadata::errno_name,
+    log::LogLevel::LogDebug,
+    trace::trace_task_event::AttachedFd,
+    wait_status::WaitStatus,
+};
+use libc::{pid_t, waitpid, __WALL, EPERM, ESRCH};
+use nix::errno::errno;
+use std::{
+    ffi::{OsStr, OsString},
+    fs,
+    os::unix::ffi::OsStrExt,
+};
+
+/// A task of a process tree rd attached to with `rd record -p`, stopped and
+/// ready to be recorded.
+pub struct SeizedTask {
+    pub tid: pid_t,
+    pub tgid: pid_t,
+    /// The process that forked this one, or `None` for the process rd was
+    /// asked to attach to. Only meaningful for thread-group leaders.
+    pub parent: Option<pid_t>,
+    /// The stop the task reported after we interrupted it. Usually the
+    /// PTRACE_INTERRUPT stop, but a signal-delivery-stop or ptrace event that
+    /// was already pending wins over that.
+    pub status: WaitStatus,
+}
+
+/// Seize `pid` and all its threads and descendant processes with
+/// PTRACE_SEIZE and stop them with PTRACE_INTERRUPT. A process comes before
+/// its children and a thread-group leader before its other threads.
+///
+/// Seized tasks stay stopped, so the only way to race with us is to clone
+/// from a task we haven't got to yet; we keep rescanning /proc until a scan
+/// turns up no new tasks. Tasks that exit while we're at it are skipped.
+pub fn seize_process_tree(pid: pid_t) -> Vec<SeizedTask> {
+    let mut seized: Vec<SeizedTask> = Vec::new();
+    let mut pending: Vec<(pid_t, Option<pid_t>)> = vec![(pid, None)];
+    while let Some((tgid, parent)) = pending.pop() {
+        let first = seized.len();
+        loop {
+            let new_tids: Vec<pid_t> = thread_ids(tgid)
+                .into_iter()
+                .filter(|&tid| !seized[first..].iter().any(|s| s.tid == tid))
+                .collect();
+            if new_tids.is_empty() {
+                break;
+            }
+            for tid in new_tids {
+                if let Some(status) = seize_task(tid) {
+                    seized.push(SeizedTask {
+                        tid,
+                        tgid,
+                        parent,
+                        status,
+                    });
+                }
+            }
+        }
+        for i in first..seized.len() {
+            for child in child_pids(tgid, seized[i].tid) {
+                pending.push((child, Some(tgid)));
+            }
+        }
+    }
+    seized
+}
+
+/// The fds open in process `pid`.
+pub fn open_fds(pid: pid_t) -> Vec<AttachedFd> {
+    let mut fds = Vec::new();
+    let dir = match fs::read_dir(format!("/proc/{}/fd", pid)) {
+        Ok(dir) => dir,
+        Err(_) => return fds,
+    };
+    for entry in dir.flatten() {
+        let fd = match entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+            Some(fd) => fd,
+            None => continue,
+        };
+        // The fd may have been closed since we listed the directory.
+        if let Ok(path) = fs::read_link(entry.path()) {
+            fds.push(AttachedFd {
+                fd,
+                path: path.into_os_string(),
+            });
+        }
+    }
+    fds.sort_by_key(|f| f.fd);
+    fds
+}
+
+/// The command line of process `pid`.
+pub fn cmd_line(pid: pid_t) -> Vec<OsString> {
+    parse_cmd_line(&fs::read(format!("/proc/{}/cmdline", pid)).unwrap_or_default())
+}
+
+/// Split the contents of /proc/<pid>/cmdline into arguments. Every argument
+/// is NUL-terminated; empty ones are kept.
+fn parse_cmd_line(data: &[u8]) -> Vec<OsString> {
+    let data = match data.split_last() {
+        Some((0, args)) => args,
+        Some(_) => data,
+        None => return Vec::new(),
+    };
+    data.split(|&b| b == 0)
+        .map(|arg| OsStr::from_bytes(arg).to_os_string())
+        .collect()
+}
+
+/// Returns the stop `tid` reported after being interrupted, or `None` if it
+/// exited before we could seize it.
+fn seize_task(tid: pid_t) -> Option<WaitStatus> {
+    let mut options = PTRACE_O_TRACESYSGOOD
+        | PTRACE_O_TRACEFORK
+        | PTRACE_O_TRACECLONE
+        | PTRACE_O_TRACEVFORK
+        | PTRACE_O_TRACEVFORKDONE
+        | PTRACE_O_TRACESECCOMP
+        | PTRACE_O_TRACEEXEC;
+    if !Flags::get().disable_ptrace_exit_events {
+        options |= PTRACE_O_TRACEEXIT;
+    }
+    // No PTRACE_O_EXITKILL: these processes were running before rd came
+    // along and shouldn't die with it.
+    if unsafe { ptrace(PTRACE_SEIZE, tid, 0, options) } != 0 {
+        match errno() {
+            ESRCH => {
+                log!(LogDebug, "{} exited before we could seize it", tid);
+                return None;
+            }
+            EPERM => {
+                clean_fatal!(
+                    "Not allowed to attach to {}. Check /proc/sys/kernel/yama/ptrace_scope\n\
+                     and whether another debugger is attached.",
+                    tid
+                );
+            }
+            err => {
+                fatal!("PTRACE_SEIZE failed for tid `{}`: {}", tid, errno_name(err));
+            }
+        }
+    }
+    unsafe { ptrace(PTRACE_INTERRUPT, tid, 0, 0) };
+
+    let mut raw_status: i32 = 0;
+    if unsafe { waitpid(tid, &mut raw_status, __WALL) } != tid {
+        fatal!("waitpid({}) failed after PTRACE_INTERRUPT", tid);
+    }
+    let status = WaitStatus::new(raw_status);
+    if status.exit_code().is_some() || status.fatal_sig().is_some() {
+        log!(LogDebug, "{} exited while we were seizing it", tid);
+        return None;
+    }
+    if !status.is_ptrace_interrupt_stop() {
+        log!(
+            LogDebug,
+            "{} stopped with {} before our PTRACE_INTERRUPT",
+            tid,
+            status
+        );
+    }
+    Some(status)
+}
+
+/// The threads of `tgid`, leader first.
+fn thread_ids(tgid: pid_t) -> Vec<pid_t> {
+    let mut tids: Vec<pid_t> = match fs::read_dir(format!("/proc/{}/task", tgid)) {
+        Ok(dir) => dir
+            .flatten()
+            .filter_map(|entry| entry.file_name().to_str().and_then(|s| s.parse().ok()))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    tids.sort_by_key(|&tid| (tid != tgid, tid));
+    tids
+}
+
+/// The processes forked by thread `tid` of `tgid` that haven't been reaped.
+fn child_pids(tgid: pid_t, tid: pid_t) -> Vec<pid_t> {
+    fs::read_to_string(format!("/proc/{}/task/{}/children", tgid, tid))
+        .unwrap_or_default()
+        .split_whitespace()
+        .filter_map(|s| s.parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cmd_line_keeps_empty_args() {
+        let args: Vec<OsString> = vec!["prog".into(), "".into(), "x".into(), "".into()];
+        assert_eq!(parse_cmd_line(b"prog\0\0x\0\0"), args);
+        assert_eq!(parse_cmd_line(b"prog\0"), vec![OsString::from("prog")]);
+        assert!(parse_cmd_line(b"").is_empty());
+    }
+}
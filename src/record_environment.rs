@@ -0,0 +1,176 @@
+//! Things about how rd was run that don't stop it recording but change how
+//! the recording behaves: a cgroup CPU quota and the nice level tracees
+//! inherit. Both make tracees get descheduled more often and at different
+//! points, so timing-sensitive programs behave differently than they would
+//! otherwise, and PMU interrupts arrive later after the counter overflows.
+//!
+//! They're captured when recording starts and stored in the trace header,
+//! so replay and `rd doctor` can point out that a recording was made under
+//! them.
+
+use libc::{getpriority, PRIO_PROCESS};
+use nix::errno::{errno, Errno};
+use std::{fs, path::Path};
+
+/// Nice levels from here up get a warning. Below this tracees still get
+/// most of a CPU on an idle machine.
+const HIGH_NICE: i32 = 10;
+
+/// A cgroup's CPU bandwidth limit: its tasks may run for `quota_us` out of
+/// every `period_us`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct CpuQuota {
+    pub quota_us: u64,
+    pub period_us: u64,
+}
+
+impl CpuQuota {
+    /// How many CPUs' worth of time the quota allows.
+    pub fn cpus(&self) -> f64 {
+        self.quota_us as f64 / self.period_us as f64
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct RecordEnvironment {
+    /// The tightest quota of rd's cgroup and its ancestors, if any.
+    pub cpu_quota: Option<CpuQuota>,
+    pub nice: i32,
+}
+
+impl RecordEnvironment {
+    /// Look at rd's own cgroup and nice level, which tracees inherit.
+    pub fn capture() -> RecordEnvironment {
+        RecordEnvironment {
+            cpu_quota: own_cpu_quota(),
+            nice: own_nice(),
+        }
+    }
+
+    /// What a user should know about the recording, e.g. "at nice level 19",
+    /// one phrase each.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if let Some(quota) = self.cpu_quota {
+            warnings.push(format!(
+                "in a cgroup limited to {:.2} CPUs ({}us every {}us)",
+                quota.cpus(),
+                quota.quota_us,
+                quota.period_us
+            ));
+        }
+        if self.nice >= HIGH_NICE {
+            warnings.push(format!("at nice level {}", self.nice));
+        }
+        warnings
+    }
+}
+
+fn own_nice() -> i32 {
+    // -1 is a valid nice level, so errno tells errors apart.
+    let nice = unsafe {
+        Errno::clear();
+        getpriority(PRIO_PROCESS, 0)
+    };
+    if nice == -1 && errno() != 0 {
+        return 0;
+    }
+    nice
+}
+
+fn own_cpu_quota() -> Option<CpuQuota> {
+    let cgroups = fs::read_to_string("/proc/self/cgroup").ok()?;
+    let mut tightest: Option<CpuQuota> = None;
+    for (hierarchy, path) in cgroups.lines().filter_map(parse_cgroup_line) {
+        let root = if hierarchy.is_empty() {
+            // cgroup v2, the unified hierarchy.
+            Path::new("/sys/fs/cgroup").to_owned()
+        } else if hierarchy.split(',').any(|c| c == "cpu") {
+            Path::new("/sys/fs/cgroup").join(hierarchy)
+        } else {
+            continue;
+        };
+        // A limit on any ancestor applies too.
+        let mut dir = root.join(path.trim_start_matches('/'));
+        loop {
+            let quota = if hierarchy.is_empty() {
+                fs::read_to_string(dir.join("cpu.max"))
+                    .ok()
+                    .and_then(|s| parse_cpu_max(&s))
+            } else {
+                let quota = fs::read_to_string(dir.join("cpu.cfs_quota_us"));
+                let period = fs::read_to_string(dir.join("cpu.cfs_period_us"));
+                match (quota, period) {
+                    (Ok(q), Ok(p)) => parse_cfs_quota(&q, &p),
+                    _ => None,
+                }
+            };
+            if let Some(q) = quota {
+                if tightest.map_or(true, |t| q.cpus() < t.cpus()) {
+                    tightest = Some(q);
+                }
+            }
+            if dir == root || !dir.pop() {
+                break;
+            }
+        }
+    }
+    tightest
+}
+
+/// Split a line of /proc/self/cgroup, `<id>:<controllers>:<path>`, into the
+/// controllers and the path.
+fn parse_cgroup_line(line: &str) -> Option<(&str, &str)> {
+    let mut parts = line.splitn(3, ':');
+    parts.next()?;
+    Some((parts.next()?, parts.next()?))
+}
+
+/// Parse a cgroup v2 cpu.max, `<quota> <period>` with `max` for no limit.
+fn parse_cpu_max(s: &str) -> Option<CpuQuota> {
+    let mut parts = s.split_whitespace();
+    let quota_us = parts.next()?.parse().ok()?;
+    let period_us = parts.next()?.parse().ok().filter(|&p| p > 0)?;
+    Some(CpuQuota {
+        quota_us,
+        period_us,
+    })
+}
+
+/// Parse cgroup v1's cpu.cfs_quota_us, -1 for no limit, and
+/// cpu.cfs_period_us.
+fn parse_cfs_quota(quota: &str, period: &str) -> Option<CpuQuota> {
+    let quota_us = quota.trim().parse::<i64>().ok().filter(|&q| q > 0)?;
+    let period_us = period.trim().parse().ok().filter(|&p| p > 0)?;
+    Some(CpuQuota {
+        quota_us: quota_us as u64,
+        period_us,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_quotas() {
+        assert_eq!(parse_cpu_max("max 100000\n"), None);
+        assert_eq!(
+            parse_cpu_max("50000 100000\n"),
+            Some(CpuQuota {
+                quota_us: 50000,
+                period_us: 100000
+            })
+        );
+        assert_eq!(parse_cfs_quota("-1\n", "100000\n"), None);
+        assert_eq!(
+            parse_cfs_quota("200000\n", "100000\n").map(|q| q.cpus()),
+            Some(2.0)
+        );
+        assert_eq!(
+            parse_cgroup_line("4:cpu,cpuacct:/user.slice"),
+            Some(("cpu,cpuacct", "/user.slice"))
+        );
+        assert_eq!(parse_cgroup_line("0::/"), Some(("", "/")));
+    }
+}
@@ -0,0 +1,268 @@
+use crate::{
+    bindings::signal::{siginfo_t, SI_KERNEL, SI_TIMER},
+    event::{
+        Event,
+        EventType,
+        SignalDeterministic::{self, DeterministicSig, NondeterministicSig},
+        SignalEventData,
+        SignalResolvedDisposition,
+    },
+    kernel_metadata::signal_name,
+    log::LogLevel::LogDebug,
+    remote_ptr::RemotePtr,
+    session::task::{
+        record_task::record_task::RecordTask,
+        task_inner::{ResumeRequest, TicksRequest, WaitRequest},
+        Task,
+    },
+    util::{cpuid, rdtsc, trapped_instruction_at, trapped_instruction_len, TrappedInstruction},
+};
+use libc::{
+    PR_TSC_SIGSEGV,
+    SIGALRM,
+    SIGBUS,
+    SIGFPE,
+    SIGILL,
+    SIGPROF,
+    SIGSEGV,
+    SIGTRAP,
+    SIGVTALRM,
+    SIGWINCH,
+};
+use std::cmp::min;
+
+/// An upper bound on the size of the frame the kernel pushes for a signal
+/// handler, including the extended register state.
+const MAX_SIGFRAME_SIZE: usize = 64 * 1024;
+
+/// The tracees run with PR_SET_TSC(PR_TSC_SIGSEGV) so that RDTSC/RDTSCP
+/// trap, and with CPUID faulting enabled (if available) so that CPUID traps.
+/// Emulate the trapped instruction `t` is stopped at (if it is one we know
+/// how to emulate), record the result in an instruction trap event and
+/// return true. Return false if the SIGSEGV should be delivered to the
+/// tracee as usual, e.g. because the tracee itself asked for RDTSC or CPUID
+/// to trap.
+pub fn try_handle_trapped_instruction(t: &mut RecordTask, si: &siginfo_t) -> bool {
+    ed_assert!(t, si.si_signo == SIGSEGV);
+
+    let ip = t.ip();
+    let trapped_instruction = trapped_instruction_at(t, ip);
+    match trapped_instruction {
+        TrappedInstruction::Rdtsc | TrappedInstruction::Rdtscp => {
+            if t.tsc_mode == PR_TSC_SIGSEGV {
+                return false;
+            }
+        }
+        TrappedInstruction::CpuId => {
+            if t.cpuid_mode == 0 {
+                return false;
+            }
+        }
+        _ => return false,
+    }
+
+    let len = trapped_instruction_len(trapped_instruction);
+    ed_assert!(t, len > 0);
+
+    let mut r = t.regs_ref().clone();
+    if trapped_instruction == TrappedInstruction::CpuId {
+        let eax = r.ax() as u32;
+        let ecx = r.cx() as u32;
+        let mut cpuid_data = cpuid(eax, ecx);
+        t.session()
+            .as_record()
+            .unwrap()
+            .disable_cpuid_features()
+            .amend_cpuid_data(eax, ecx, &mut cpuid_data);
+        r.set_cpuid_output(
+            cpuid_data.eax,
+            cpuid_data.ebx,
+            cpuid_data.ecx,
+            cpuid_data.edx,
+        );
+        log!(LogDebug, "  trapped for cpuid: {:#x}:{:#x}", eax, ecx);
+    } else {
+        let current_time = rdtsc();
+        r.set_rdtsc_output(current_time);
+        if trapped_instruction == TrappedInstruction::Rdtscp {
+            // Report TSC_AUX (i.e. the cpu/node id) as 0. Tracees are bound to a
+            // single cpu anyway and this keeps the value the same during replay.
+            r.set_cx(0);
+        }
+        log!(LogDebug, "  trapped for rdtsc: returning {}", current_time);
+    }
+
+    r.set_ip(r.ip() + len);
+    t.set_regs(&r);
+    // The registers in the frame carry the emulated result; replay restores
+    // them when it reaches this event.
+    t.record_event(&Event::instruction_trap(), None, None, None);
+    true
+}
+
+/// Whether `si` will be raised again when replay executes the same
+/// instruction, as faults and traps are, rather than arriving at a point the
+/// trace has to pin down. The kernel also uses SI_KERNEL for the signals of
+/// setitimer() and alarm(), so the signal number matters as well. SIGSYS
+/// isn't one of them: replay doesn't install the tracee's seccomp filters, so
+/// nothing raises it again.
+pub fn signal_deterministic(si: &siginfo_t) -> SignalDeterministic {
+    match si.si_signo {
+        SIGSEGV | SIGBUS | SIGFPE | SIGILL | SIGTRAP if si.si_code > 0 => DeterministicSig,
+        _ => NondeterministicSig,
+    }
+}
+
+/// Whether `si` was sent by a timer: an interval timer set with setitimer()
+/// or alarm(), or a POSIX timer.
+pub fn is_timer_signal(si: &siginfo_t) -> bool {
+    match si.si_signo {
+        SIGALRM | SIGVTALRM | SIGPROF if si.si_code == SI_KERNEL => true,
+        _ => si.si_code == SI_TIMER,
+    }
+}
+
+/// `t` is stopped for the delivery of the timer signal `si`. Replay emulates
+/// setitimer(), alarm() and timer_settime(), so no timer is armed then and
+/// nothing would send the signal. Record it with `t`'s current tick count
+/// instead, so replay injects it at exactly this point, like any other
+/// asynchronous signal.
+pub fn record_timer_signal(
+    t: &mut RecordTask,
+    si: &siginfo_t,
+    disposition: SignalResolvedDisposition,
+) {
+    ed_assert!(t, is_timer_signal(si));
+    log!(
+        LogDebug,
+        "  {} from a timer at {} ticks",
+        signal_name(si.si_signo),
+        t.tick_count()
+    );
+    record_signal_to_inject(t, si, disposition);
+}
+
+/// Whether `si` is the SIGWINCH a terminal sends its foreground process
+/// group when the window size changes.
+pub fn is_window_size_signal(si: &siginfo_t) -> bool {
+    si.si_signo == SIGWINCH && si.si_code == SI_KERNEL
+}
+
+/// `t` is stopped for the delivery of `si`, the terminal's window size
+/// changed. Replay runs the tracees in a session of their own, away from the
+/// terminal, and emulates TIOCSWINSZ, so nothing would send the signal then.
+/// Like a timer signal, record it with `t`'s current tick count so replay
+/// injects it at exactly this point. The TIOCGWINSZ a program makes in its
+/// handler then returns the recorded size.
+pub fn record_window_size_signal(
+    t: &mut RecordTask,
+    si: &siginfo_t,
+    disposition: SignalResolvedDisposition,
+) {
+    ed_assert!(t, is_window_size_signal(si));
+    log!(
+        LogDebug,
+        "  SIGWINCH from the terminal at {} ticks",
+        t.tick_count()
+    );
+    record_signal_to_inject(t, si, disposition);
+}
+
+fn record_signal_to_inject(
+    t: &mut RecordTask,
+    si: &siginfo_t,
+    disposition: SignalResolvedDisposition,
+) {
+    let ev = Event::new_signal_event(
+        EventType::EvSignal,
+        SignalEventData::new(si, NondeterministicSig, disposition),
+    );
+    t.record_event(&ev, None, None, None);
+}
+
+/// `t` is stopped for the delivery of `si`. Record the signal and, if `t`
+/// has a handler for it, deliver it and record the signal frame the kernel
+/// set up. Returns the signal to resume `t` with, if it still has to be
+/// delivered.
+pub fn handle_signal(t: &mut RecordTask, si: &siginfo_t) -> Option<i32> {
+    let sig = si.si_signo;
+    let deterministic = signal_deterministic(si);
+    let disposition = t.sig_resolved_disposition(sig, deterministic);
+    log!(LogDebug, "{}: handling {}", t.tid, signal_name(sig));
+    if is_timer_signal(si) {
+        record_timer_signal(t, si, disposition);
+    } else if is_window_size_signal(si) {
+        record_window_size_signal(t, si, disposition);
+    } else {
+        let ev = Event::new_signal_event(
+            EventType::EvSignal,
+            SignalEventData::new(si, deterministic, disposition),
+        );
+        t.record_event(&ev, None, None, None);
+    }
+
+    if disposition != SignalResolvedDisposition::DispositionUserHandler {
+        // Replay expects a data record for the delivery either way.
+        t.record_remote_even_if_null(RemotePtr::null(), 0);
+        let ev = Event::new_signal_event(
+            EventType::EvSignalDelivery,
+            SignalEventData::new(si, deterministic, disposition),
+        );
+        t.record_event(&ev, None, None, None);
+        t.signal_delivered(sig);
+        return Some(sig);
+    }
+
+    // Stop at the first instruction of the handler, with the frame set up.
+    let old_sp = t.regs_ref().sp();
+    t.resume_execution(
+        ResumeRequest::ResumeSinglestep,
+        WaitRequest::ResumeWait,
+        TicksRequest::ResumeNoTicks,
+        Some(sig),
+    );
+    let new_sp = t.regs_ref().sp();
+    // On an alternate signal stack the frame isn't below the old stack
+    // pointer. `record_remote_writable()` stops at the end of the stack.
+    let frame_size = if new_sp < old_sp {
+        min(old_sp - new_sp, MAX_SIGFRAME_SIZE)
+    } else {
+        MAX_SIGFRAME_SIZE
+    };
+    t.record_remote_writable(new_sp, frame_size);
+    let ev = Event::new_signal_event(
+        EventType::EvSignalHandler,
+        SignalEventData::new(si, deterministic, disposition),
+    );
+    t.record_event(&ev, None, None, None);
+    t.signal_delivered(sig);
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use libc::SIGUSR1;
+    use std::mem::zeroed;
+
+    fn siginfo(signo: i32, code: i32) -> siginfo_t {
+        let mut si: siginfo_t = unsafe { zeroed() };
+        si.si_signo = signo;
+        si.si_code = code;
+        si
+    }
+
+    #[test]
+    fn timer_signals() {
+        let alarm = siginfo(SIGALRM, SI_KERNEL);
+        assert!(is_timer_signal(&alarm));
+        assert!(signal_deterministic(&alarm) == NondeterministicSig);
+        assert!(is_timer_signal(&siginfo(SIGUSR1, SI_TIMER)));
+        assert!(!is_timer_signal(&siginfo(SIGALRM, 0)));
+        assert!(signal_deterministic(&siginfo(SIGSEGV, SI_KERNEL)) == DeterministicSig);
+        assert!(signal_deterministic(&siginfo(SIGSEGV, 0)) == NondeterministicSig);
+        assert!(signal_deterministic(&siginfo(libc::SIGSYS, 1)) == NondeterministicSig);
+        assert!(is_window_size_signal(&siginfo(SIGWINCH, SI_KERNEL)));
+        assert!(!is_window_size_signal(&siginfo(SIGWINCH, 0)));
+    }
+}
@@ -0,0 +1,3574 @@
+use crate::{
+    arch::{Architecture, X86Arch},
+    auto_remote_syscalls::{AutoRemoteSyscalls, AutoRestoreMem},
+    bindings::{
+        kernel::{sock_filter, statx, user_desc},
+        perf_event::perf_event_attr,
+        ptrace::{
+            PTRACE_EVENT_CLONE,
+            PTRACE_EVENT_FORK,
+            PTRACE_EVENT_SECCOMP,
+            PTRACE_EVENT_VFORK,
+        },
+        signal::{siginfo_t, SI_USER},
+    },
+    event::{OpenedFd, SignalDeterministic, Switchable},
+    fd_table::{FdTableSharedPtr, ReservedFd},
+    file_monitor::{
+        dir_read_monitor::DirReadMonitor,
+        io_uring_monitor::IoUringMonitor,
+        landlock_monitor::LandlockMonitor,
+        pidfd_monitor::PidFdMonitor,
+        random_device_monitor::RandomDeviceMonitor,
+        seccomp_notify_monitor::SeccompNotifyMonitor,
+        signalfd_monitor::{to_signalfd_siginfo, SignalFdMonitor},
+        timerfd_monitor::TimerfdMonitor,
+        virtual_perf_counter_monitor::VirtualPerfCounterMonitor,
+        FileMonitorSharedPtr,
+        FileMonitorType,
+        LazyOffset,
+    },
+    flags::Flags,
+    fuse_files::is_fuse_file,
+    io_uring::{
+        io_uring_probe_size,
+        io_uring_register_opcode_recordable,
+        IoUringPolicy,
+        IORING_REGISTER_PROBE,
+        IORING_SETUP_DEFER_TASKRUN,
+        IORING_SETUP_SINGLE_ISSUER,
+        RECORDABLE_SETUP_FLAGS,
+    },
+    ioctl_registry::{
+        ioctl_action,
+        ioctl_handler,
+        should_warn_about_request,
+        IoctlAction,
+        IoctlCall,
+    },
+    kernel_abi::{
+        self,
+        common::{clone_args, io_uring_params, rseq},
+        syscall_number_for_close_range,
+        syscall_number_for_fcntl,
+        syscall_number_for_getrandom,
+        syscall_number_for_gettid,
+        syscall_number_for_munmap,
+        syscall_number_for_openat,
+        x86,
+        CloneTLSType,
+        MmapCallingSemantics,
+        SupportedArch,
+    },
+    kernel_metadata::{errno_name, syscall_name},
+    kernel_supplement::{
+        btrfs_ioctl_clone_range_args,
+        sig_set_t,
+        BTRFS_IOC_CLONE_RANGE_,
+        CLONE_PIDFD,
+        ERESTART_RESTARTBLOCK,
+        KEYCTL_CAPABILITIES,
+        KEYCTL_DESCRIBE,
+        KEYCTL_DH_COMPUTE,
+        KEYCTL_GET_SECURITY,
+        KEYCTL_PKEY_DECRYPT,
+        KEYCTL_PKEY_ENCRYPT,
+        KEYCTL_PKEY_QUERY,
+        KEYCTL_PKEY_SIGN,
+        KEYCTL_READ,
+        RSEQ_CPU_ID_UNINITIALIZED,
+        RSEQ_FLAG_UNREGISTER,
+        SECCOMP_FILTER_FLAG_NEW_LISTENER,
+        SECCOMP_SET_MODE_FILTER,
+    },
+    landlock::creates_landlock_ruleset,
+    log::LogLevel::{LogDebug, LogWarn},
+    rd::RD_RESERVED_FD_FLOOR,
+    rd_mapping_placement::{choose_rd_mapping_address, RdMappingKind},
+    registers::{MismatchBehavior, Registers},
+    remote_ptr::{RemotePtr, Void},
+    rseq::RseqState,
+    seccomp_bpf::SeccompTraceRoute,
+    seccomp_filter_rewriter::SECCOMP_MAGIC_SKIP_ORIGINAL_SYSCALLNO,
+    seccomp_unotify::{
+        seccomp_notif,
+        seccomp_notif_addfd,
+        seccomp_notif_resp,
+        SECCOMP_IOCTL_NOTIF_ADDFD,
+        SECCOMP_IOCTL_NOTIF_RECV,
+        SECCOMP_IOCTL_NOTIF_SEND,
+    },
+    seeded_random::is_random_device,
+    session::{
+        address_space::{
+            address_space::AddressSpace,
+            kernel_mapping::KernelMapping,
+            memory_range::MemoryRange,
+        },
+        session_inner::session_inner::PtraceSyscallSeccompOrdering,
+        task::{
+            record_task::record_task::RecordTask,
+            task_common::{read_c_str_fallible, read_mem, read_val_mem, write_mem, write_val_mem},
+            task_inner::{ResumeRequest, TicksRequest, WaitRequest},
+            Task,
+        },
+    },
+    trace::{
+        trace_task_event::TraceTaskEvent,
+        trace_writer::{MappingOrigin, RecordInTrace},
+    },
+    util::{
+        ceil_page_size,
+        clone3_parameters,
+        clone_flags_to_task_flags,
+        extract_clone_parameters,
+        page_size,
+        read_clone_args,
+        CloneParameters,
+    },
+};
+use libc::{
+    c_ulong,
+    clockid_t,
+    ioctl,
+    pid_t,
+    signalfd_siginfo,
+    CLOCK_REALTIME,
+    CLONE_PARENT,
+    CLONE_THREAD,
+    CLONE_UNTRACED,
+    CLONE_VFORK,
+    CLONE_VM,
+    EACCES,
+    EBADF,
+    EBUSY,
+    EFAULT,
+    EINVAL,
+    ENOSPC,
+    ENOSYS,
+    ENOMEM,
+    EPERM,
+    ESRCH,
+    FD_CLOEXEC,
+    F_GETFD,
+    MREMAP_FIXED,
+    O_RDONLY,
+    PATH_MAX,
+    PR_SET_SECCOMP,
+    SECCOMP_MODE_FILTER,
+    SIGCHLD,
+    SIGCONT,
+    SIGKILL,
+    SIGSTOP,
+};
+use nix::{
+    fcntl::OFlag,
+    sys::{
+        mman::{MapFlags, ProtFlags},
+        stat::stat,
+    },
+};
+use std::{
+    cmp::min,
+    convert::TryInto,
+    ffi::{OsStr, OsString},
+    fs,
+    mem::{align_of, size_of, zeroed},
+    os::unix::ffi::OsStrExt,
+    path::Path,
+    slice,
+};
+
+/// The kernel never returns an errno larger than this.
+const MAX_ERRNO: isize = 4095;
+
+/// clock_nanosleep() flag: the requested time is absolute, so there's no
+/// remaining time to report.
+const TIMER_ABSTIME: usize = 1;
+
+/// The size of the scratch area mapped into every tracee, in pages.
+const SCRATCH_SIZE_PAGES: usize = 512;
+
+/// Reads smaller than this are copied into the trace. Cloning costs an
+/// ioctl and at least a block of the cloned-data file, which only pays off
+/// for big sequential reads.
+const MIN_CLONED_READ_SIZE: usize = 64 * 1024;
+
+/// Syscalls the syscallbuf in the preload library can handle without a trip
+/// through rd. Keep this in sync with the preload library's syscall hook.
+///
+/// rd's seccomp filter routes these with `SeccompTraceRoute::Bufferable`
+/// when they're made from anywhere but the untraced entry points in the rd
+/// page, so we know patching the call site is worth a try.
+pub fn bufferable_syscalls<Arch: Architecture>() -> Vec<i32> {
+    vec![
+        Arch::ACCESS,
+        Arch::CLOCK_GETTIME,
+        Arch::CLOSE,
+        Arch::CREAT,
+        Arch::DUP,
+        Arch::DUP2,
+        Arch::DUP3,
+        Arch::EPOLL_WAIT,
+        Arch::FACCESSAT,
+        Arch::FCNTL,
+        Arch::FCNTL64,
+        Arch::FGETXATTR,
+        Arch::FLISTXATTR,
+        Arch::FSETXATTR,
+        Arch::FSTAT,
+        Arch::FSTAT64,
+        Arch::FTRUNCATE,
+        Arch::FTRUNCATE64,
+        Arch::FUTEX,
+        Arch::GETDENTS,
+        Arch::GETDENTS64,
+        Arch::GETEGID,
+        Arch::GETEUID,
+        Arch::GETGID,
+        Arch::GETPID,
+        Arch::GETPPID,
+        Arch::GETRANDOM,
+        Arch::GETRUSAGE,
+        Arch::GETSOCKNAME,
+        Arch::GETSOCKOPT,
+        Arch::GETTID,
+        Arch::GETTIMEOFDAY,
+        Arch::GETUID,
+        Arch::GETXATTR,
+        Arch::IOCTL,
+        Arch::LGETXATTR,
+        Arch::LISTXATTR,
+        Arch::LLISTXATTR,
+        Arch::_LLSEEK,
+        Arch::LSEEK,
+        Arch::LSTAT,
+        Arch::LSTAT64,
+        Arch::MADVISE,
+        Arch::MKDIR,
+        Arch::MKDIRAT,
+        Arch::MPROTECT,
+        Arch::OPEN,
+        Arch::OPENAT,
+        Arch::POLL,
+        Arch::PPOLL,
+        Arch::PRCTL,
+        Arch::PREAD64,
+        Arch::PREADV,
+        Arch::PWRITE64,
+        Arch::PWRITEV,
+        Arch::READ,
+        Arch::READLINK,
+        Arch::READLINKAT,
+        Arch::READV,
+        Arch::RECVFROM,
+        Arch::RECVMSG,
+        Arch::RT_SIGPROCMASK,
+        Arch::SCHED_YIELD,
+        Arch::SENDMSG,
+        Arch::SENDTO,
+        Arch::SETSOCKOPT,
+        Arch::SOCKETCALL,
+        Arch::SOCKETPAIR,
+        Arch::STAT,
+        Arch::STAT64,
+        Arch::STATX,
+        Arch::SYMLINK,
+        Arch::TIME,
+        Arch::UNAME,
+        Arch::UTIMENSAT,
+        Arch::WRITE,
+        Arch::WRITEV,
+    ]
+}
+
+/// What to do with a PTRACE_EVENT_SECCOMP stop.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SeccompStopAction {
+    /// The call site was patched to go through the syscallbuf and the
+    /// syscall was aborted. Resume the task; it will reexecute the syscall
+    /// through the hook.
+    Patched,
+    /// Record the syscall the slow way.
+    SlowPath,
+    /// The stop was requested by a filter the tracee installed. The value is
+    /// that filter's SECCOMP_RET_DATA, to be mapped back to the result the
+    /// tracee's filter asked for.
+    TraceeFilter(u16),
+}
+
+/// Route a PTRACE_EVENT_SECCOMP stop of `t` by the SECCOMP_RET_DATA our
+/// filter attached to it.
+pub fn route_seccomp_stop(t: &mut RecordTask) -> SeccompStopAction {
+    match SeccompTraceRoute::from_ret_data(t.get_ptrace_eventmsg_seccomp_data()) {
+        SeccompTraceRoute::AlwaysTrace => SeccompStopAction::SlowPath,
+        SeccompTraceRoute::TraceeFilter(data) => SeccompStopAction::TraceeFilter(data),
+        SeccompTraceRoute::Bufferable
+            if answers_from_virtual_clock(t) || answers_from_seeded_random(t) =>
+        {
+            // The syscallbuf would ask the kernel.
+            SeccompStopAction::SlowPath
+        }
+        SeccompTraceRoute::Bufferable => {
+            let vm = t.vm_shr_ptr();
+            match vm.monkeypatcher() {
+                Some(patcher) if patcher.try_patch_syscall(t) => SeccompStopAction::Patched,
+                _ => {
+                    log!(
+                        LogDebug,
+                        "{}: taking the slow path for bufferable syscall at {}",
+                        t.tid,
+                        t.ip()
+                    );
+                    SeccompStopAction::SlowPath
+                }
+            }
+        }
+    }
+}
+
+/// The two kinds of stop the kernel can report on entry to a syscall once
+/// the tracee has a seccomp filter installed.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SyscallEntryStop {
+    /// PTRACE_EVENT_SECCOMP, from a SECCOMP_RET_TRACE filter result.
+    Seccomp,
+    /// The syscall-entry stop of a PTRACE_SYSCALL resume.
+    PtraceSyscall,
+}
+
+/// What to do with a syscall entry stop.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SyscallEntryAction {
+    /// The first stop for this syscall: process the syscall entry.
+    Process,
+    /// The second stop for a syscall whose entry we've already processed.
+    /// Resume the task without recording anything.
+    Duplicate,
+}
+
+/// If we resume a task with PTRACE_SYSCALL and its seccomp filter traces the
+/// syscall, the kernel reports both a seccomp stop and a syscall-entry stop
+/// for it, in an order that depends on the kernel version (see
+/// `PtraceSyscallSeccompOrdering`). Only the first of these must be
+/// processed; doing it for both records the syscall twice.
+///
+/// Call this at every syscall entry stop of `t`, and
+/// `note_syscall_exit_stop()` at the syscall-exit stop. The first time both
+/// stops are seen for one syscall the session's ordering is learned;
+/// afterwards any sequence that contradicts it is a fatal error.
+pub fn on_syscall_entry_stop(t: &mut RecordTask, stop: SyscallEntryStop) -> SyscallEntryAction {
+    ed_assert!(
+        t,
+        t.seccomp_bpf_enabled || stop == SyscallEntryStop::PtraceSyscall,
+        "Seccomp stop for a task without a seccomp filter"
+    );
+
+    let prev = match t.syscall_entry_stop {
+        None => {
+            t.syscall_entry_stop = Some(stop);
+            return SyscallEntryAction::Process;
+        }
+        Some(prev) => prev,
+    };
+    ed_assert!(
+        t,
+        prev != stop,
+        "Got two {:?} stops without a syscall exit in between",
+        stop
+    );
+
+    let session = t.session();
+    match learned_stop_ordering(session.syscall_seccomp_ordering(), prev) {
+        Ok(Some(ordering)) => {
+            log!(
+                LogDebug,
+                "Learned syscall/seccomp stop ordering: {:?} stop comes first",
+                prev
+            );
+            session.set_syscall_seccomp_ordering(ordering);
+        }
+        Ok(None) => (),
+        Err(()) => {
+            ed_assert!(
+                t,
+                false,
+                "{:?} stop followed by {:?} stop contradicts the known ordering",
+                prev,
+                stop
+            );
+        }
+    }
+    SyscallEntryAction::Duplicate
+}
+
+/// Both entry stops have been seen for one syscall, `first` first. Returns
+/// the ordering this shows if the `known` one was still unknown, or an error
+/// if it contradicts it.
+fn learned_stop_ordering(
+    known: PtraceSyscallSeccompOrdering,
+    first: SyscallEntryStop,
+) -> Result<Option<PtraceSyscallSeccompOrdering>, ()> {
+    let observed = match first {
+        SyscallEntryStop::Seccomp => PtraceSyscallSeccompOrdering::SeccompBeforeSyscall,
+        SyscallEntryStop::PtraceSyscall => PtraceSyscallSeccompOrdering::SyscallBeforeSeccomp,
+    };
+    if known == PtraceSyscallSeccompOrdering::SyscallBeforeSeccompUnknown {
+        Ok(Some(observed))
+    } else if known == observed {
+        Ok(None)
+    } else {
+        Err(())
+    }
+}
+
+/// Whether the syscall entry stop `t` is at is the second one for a syscall
+/// whose entry has already been processed, see `on_syscall_entry_stop()`.
+/// Only needed to tell a PTRACE_SYSCALL stop that follows a seccomp stop
+/// from the syscall-exit stop, which look the same.
+pub fn is_duplicate_syscall_entry_stop(t: &RecordTask) -> bool {
+    t.syscall_entry_stop == Some(SyscallEntryStop::Seccomp)
+        && t.session().syscall_seccomp_ordering()
+            != PtraceSyscallSeccompOrdering::SyscallBeforeSeccomp
+        && t.regs_ref().syscall_result_signed() == -ENOSYS as isize
+}
+
+/// `t` has reached the syscall-exit stop of the syscall it was in.
+pub fn note_syscall_exit_stop(t: &mut RecordTask) {
+    t.syscall_entry_stop = None;
+}
+
+/// Sanity-check the registers the kernel reported at the exit of the syscall
+/// `t` has just completed. `entry_regs` are the registers at syscall entry,
+/// with any rewriting of the arguments (e.g. to point at scratch memory)
+/// already undone.
+///
+/// Only does anything when `--check-syscall-regs` was given. Problems are
+/// reported as warnings: they usually mean something changed tracee state
+/// behind our back (a seccomp filter, a bad syscall patch, a kernel quirk)
+/// and are a lot easier to diagnose here than as a replay divergence later.
+pub fn check_syscall_exit_regs(t: &RecordTask, entry_regs: &Registers) {
+    if !Flags::get().check_syscall_regs {
+        return;
+    }
+    let arch = t.arch();
+    rd_arch_function_selfless!(check_syscall_exit_regs_arch, arch, t, entry_regs)
+}
+
+fn check_syscall_exit_regs_arch<Arch: Architecture>(t: &RecordTask, entry_regs: &Registers) {
+    let sys = entry_regs.original_syscallno() as i32;
+    if sys < 0 {
+        return;
+    }
+    // These legitimately replace (parts of) the register file.
+    if sys == Arch::EXECVE
+        || sys == Arch::EXECVEAT
+        || sys == Arch::SIGRETURN
+        || sys == Arch::RT_SIGRETURN
+        || sys == Arch::ARCH_PRCTL
+        || sys == Arch::RESTART_SYSCALL
+    {
+        return;
+    }
+
+    let exit_regs = t.regs_ref();
+    let returns_address = sys == Arch::MMAP
+        || sys == Arch::MMAP2
+        || sys == Arch::MREMAP
+        || sys == Arch::BRK
+        || sys == Arch::SHMAT;
+    if !returns_address && exit_regs.syscall_result_signed() < -MAX_ERRNO {
+        log!(
+            LogWarn,
+            "{}: {} returned {:#x}, which is neither a valid result nor an errno",
+            t.tid,
+            syscall_name(sys, Arch::arch()),
+            exit_regs.syscall_result()
+        );
+    }
+
+    // Only the result register may change. The x86-64 `syscall` instruction
+    // additionally clobbers rcx (return address) and r11 (flags).
+    let mut expected = entry_regs.clone();
+    expected.set_syscall_result(exit_regs.syscall_result());
+    if Arch::arch() == SupportedArch::X64 {
+        expected.set_cx(exit_regs.cx());
+        expected.set_r11(exit_regs.r11());
+    }
+    if !expected.matches(exit_regs) {
+        log!(
+            LogWarn,
+            "{}: unexpected register changes across {}",
+            t.tid,
+            syscall_name(sys, Arch::arch())
+        );
+        Registers::compare_register_files(
+            None,
+            "expected",
+            &expected,
+            "syscall exit",
+            exit_regs,
+            MismatchBehavior::LogMismatches,
+        );
+    }
+}
+
+/// Where the sleeping syscall `sys`, made with `regs`, reports how much of its
+/// timeout was left when a signal interrupted it, and the size of that
+/// `struct timespec`. `None` if it has nowhere to report that.
+///
+/// nanosleep() and friends are interrupted with ERESTART_RESTARTBLOCK: the
+/// kernel keeps the remaining timeout in the task's restart_block and
+/// restart_syscall(2) carries on from there, writing the out-parameter of the
+/// original syscall again when it returns.
+pub fn remaining_time_outparam<Arch: Architecture>(
+    sys: i32,
+    regs: &Registers,
+) -> Option<(RemotePtr<Void>, usize)> {
+    let timespec_size = 2 * size_of::<Arch::signed_long>();
+    let (addr, size) = if sys == Arch::NANOSLEEP {
+        (regs.arg2(), timespec_size)
+    } else if sys == Arch::CLOCK_NANOSLEEP || sys == Arch::CLOCK_NANOSLEEP_TIME64 {
+        if regs.arg2() & TIMER_ABSTIME != 0 {
+            return None;
+        }
+        let size = if sys == Arch::CLOCK_NANOSLEEP {
+            timespec_size
+        } else {
+            2 * size_of::<i64>()
+        };
+        (regs.arg4(), size)
+    } else if sys == Arch::PPOLL {
+        (regs.arg3(), timespec_size)
+    } else if sys == Arch::PPOLL_TIME64 {
+        (regs.arg3(), 2 * size_of::<i64>())
+    } else {
+        return None;
+    };
+    if addr == 0 {
+        None
+    } else {
+        Some((RemotePtr::new_from_val(addr), size))
+    }
+}
+
+/// Record the remaining time a sleeping syscall of `t` that has just exited
+/// wrote back. `entry_regs` are the registers at syscall entry.
+///
+/// When the syscall is a restart_syscall(2) the out-parameter is the one of
+/// the syscall it's continuing, so the registers of a syscall that exits with
+/// ERESTART_RESTARTBLOCK are kept in `t.restart_block_regs` until then.
+/// Without this, replay would hand the tracee whatever the replaying kernel
+/// thought was left, which differs from what the recorded program saw.
+pub fn record_remaining_time(t: &mut RecordTask, entry_regs: &Registers) {
+    let arch = t.arch();
+    rd_arch_function_selfless!(record_remaining_time_arch, arch, t, entry_regs)
+}
+
+fn record_remaining_time_arch<Arch: Architecture>(t: &mut RecordTask, entry_regs: &Registers) {
+    let sys = entry_regs.original_syscallno() as i32;
+    let syscall_regs = if sys == Arch::RESTART_SYSCALL {
+        match t.restart_block_regs.take() {
+            Some(regs) => regs,
+            None => {
+                log!(
+                    LogDebug,
+                    "{}: restart_syscall without an interrupted syscall we know of",
+                    t.tid
+                );
+                return;
+            }
+        }
+    } else {
+        t.restart_block_regs = None;
+        entry_regs.clone()
+    };
+    if -t.regs_ref().syscall_result_signed() == ERESTART_RESTARTBLOCK as isize {
+        t.restart_block_regs = Some(syscall_regs.clone());
+    }
+    let interrupted_sys = syscall_regs.original_syscallno() as i32;
+    if let Some((addr, size)) = remaining_time_outparam::<Arch>(interrupted_sys, &syscall_regs) {
+        t.record_remote(addr, size);
+    }
+}
+
+/// Record the entries getdents()/getdents64() returned to `t`. `regs` are
+/// the registers at syscall exit.
+///
+/// The dirent buffer is recorded byte for byte and replay hands it back
+/// verbatim. Inode numbers, d_off cookies and the order of the entries depend
+/// on the filesystem and differ between machines (and on some filesystems
+/// between runs), so listing the directory again during replay couldn't
+/// reproduce them.
+pub fn record_getdents(t: &mut RecordTask, regs: &Registers) {
+    let nread = regs.syscall_result_signed();
+    if nread <= 0 {
+        return;
+    }
+    let fd = regs.arg1_signed() as i32;
+    t.fd_table_shr_ptr().borrow().filter_getdents(fd, t);
+    t.record_remote(RemotePtr::new_from_val(regs.arg2()), nread as usize);
+
+    let monitor = t.fd_table_shr_ptr().borrow().get_monitor(fd);
+    if let Some(m) = &monitor {
+        if m.borrow().file_monitor_type() == FileMonitorType::DirRead {
+            return;
+        }
+    }
+    let path = t.file_name_of_fd(fd);
+    t.session().as_record().unwrap().note_directory_read(&path);
+    // Other monitors, e.g. the one hiding rd's fds in /proc/<pid>/fd, stay.
+    if monitor.is_none() {
+        t.fd_table_shr_ptr()
+            .borrow_mut()
+            .add_monitor(t, fd, Box::new(DirReadMonitor::new(&path)));
+    }
+}
+
+/// Record the struct a statx() by `t` filled in. `regs` are the registers at
+/// syscall exit. The struct has the same layout on all architectures.
+pub fn record_statx(t: &mut RecordTask, regs: &Registers) {
+    if regs.syscall_failed() {
+        return;
+    }
+    t.record_remote(RemotePtr::new_from_val(regs.arg5()), size_of::<statx>());
+}
+
+/// Record an mmap() by `t` that the kernel satisfied with anonymous memory:
+/// MAP_ANONYMOUS mappings and MAP_SHARED mappings of /dev/zero. `addr` is
+/// the syscall result.
+///
+/// Nothing about the contents needs recording since they start out zeroed.
+/// For shared mappings though the kernel creates a shmem file, and its
+/// device and inode are what every task that inherits the mapping sees. We
+/// record those so that replay maps one emulated file into all of them and
+/// writes through one mapping show up in the others.
+pub fn record_anonymous_mmap(
+    t: &mut RecordTask,
+    addr: RemotePtr<Void>,
+    size: usize,
+    prot: ProtFlags,
+    flags: MapFlags,
+) {
+    let km = if !flags.contains(MapFlags::MAP_SHARED) {
+        t.vm().map(
+            t,
+            addr,
+            size,
+            prot,
+            flags,
+            0,
+            OsStr::new(""),
+            KernelMapping::NO_DEVICE,
+            KernelMapping::NO_INODE,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )
+    } else {
+        ed_assert!(t, !flags.contains(MapFlags::MAP_GROWSDOWN));
+        // There doesn't seem to be any way to get at the shmem file's device
+        // and inode other than the kernel's view of the mapping.
+        let kernel_info = AddressSpace::read_kernel_mapping(t, addr);
+        t.vm().map(
+            t,
+            addr,
+            size,
+            prot,
+            flags,
+            0,
+            kernel_info.fsname(),
+            kernel_info.device(),
+            kernel_info.inode(),
+            None,
+            None,
+            None,
+            None,
+            None,
+        )
+    };
+    let record_in_trace =
+        t.trace_writer_mut()
+            .write_mapped_region(t, &km, &km.fake_stat(), &[], None, None);
+    ed_assert!(t, record_in_trace == RecordInTrace::DontRecordInTrace);
+}
+
+/// If the syscall `t` is entering would close or dup over one of the fds rd
+/// keeps open in the tracee, returns that fd. The caller should then
+/// `migrate_reserved_fd()` before letting the syscall run.
+pub fn reserved_fd_conflict(t: &RecordTask, regs: &Registers) -> Option<i32> {
+    let arch = t.arch();
+    rd_arch_function_selfless!(reserved_fd_conflict_arch, arch, t, regs)
+}
+
+fn reserved_fd_conflict_arch<Arch: Architecture>(t: &RecordTask, regs: &Registers) -> Option<i32> {
+    let fd = closed_fd_arch::<Arch>(regs)?;
+    t.fd_table().reserved_fd_kind(fd).map(|_| fd)
+}
+
+/// The fd a close(), dup2() or dup3() entered with `regs` closes, if any.
+fn closed_fd_arch<Arch: Architecture>(regs: &Registers) -> Option<i32> {
+    let sys = regs.original_syscallno() as i32;
+    if sys == Arch::CLOSE {
+        Some(regs.arg1_signed() as i32)
+    } else if sys == Arch::DUP2 || sys == Arch::DUP3 {
+        Some(regs.arg2_signed() as i32)
+    } else {
+        None
+    }
+}
+
+/// Move rd's fd `fd` to a free number at or above RD_RESERVED_FD_FLOOR
+/// because `t` is about to close or dup over it. The tracee never knew about
+/// our fd, so after this its syscall does what it would have done without
+/// rd: close() fails with EBADF and dup2()/dup3() get the number.
+pub fn migrate_reserved_fd(t: &mut RecordTask, fd: i32) {
+    let kind = match t.fd_table().reserved_fd_kind(fd) {
+        Some(kind) => kind,
+        None => return,
+    };
+    // The root dir fd and the socket have to survive exec. The per-task fds
+    // are set up again by the preload library after exec.
+    let flags = match kind {
+        ReservedFd::RootDir | ReservedFd::TraceeSocket => OFlag::empty(),
+        ReservedFd::DeschedEvent(_) | ReservedFd::ClonedFileData(_) => OFlag::O_CLOEXEC,
+    };
+    let target_fd = t.find_free_file_descriptor(RD_RESERVED_FD_FLOOR);
+    let new_fd = AutoRemoteSyscalls::new(t).infallible_move_fd(fd, target_fd, flags);
+    log!(
+        LogWarn,
+        "Task {} tried to use rd's {:?} fd {}; moved it to {}",
+        t.tid,
+        kind,
+        fd,
+        new_fd
+    );
+    t.fd_table_shr_ptr()
+        .borrow_mut()
+        .did_move_reserved_fd(fd, new_fd, t);
+
+    let owner_tid = match kind {
+        ReservedFd::DeschedEvent(tid) | ReservedFd::ClonedFileData(tid) => tid,
+        ReservedFd::RootDir | ReservedFd::TraceeSocket => return,
+    };
+    let update_owner = |owner: &mut RecordTask| {
+        match kind {
+            ReservedFd::DeschedEvent(_) => owner.desched_fd_child = new_fd,
+            _ => owner.cloned_file_data_fd_child = new_fd,
+        }
+        owner.update_preload_thread_locals_fds();
+    };
+    if owner_tid == t.rec_tid {
+        update_owner(t);
+    } else if let Some(owner) = t.session().find_task_from_rec_tid(owner_tid) {
+        update_owner(owner.borrow_mut().as_record_task_mut().unwrap());
+    }
+}
+
+/// Split the close_range() `t` is entering around rd's fds, which
+/// programs about to exec close with `close_range(3, ~0U, 0)`. Moving our
+/// fds out of the way like `migrate_reserved_fd()` does can't help there.
+/// `regs` are the registers at syscall entry.
+///
+/// Returns `None` if the range holds none of rd's fds and the syscall runs
+/// as is. Otherwise the syscall is rewritten to cover only the first of the
+/// returned ranges, or to do nothing if there are none, and
+/// `finish_close_range()` must deal with the rest.
+pub fn prepare_close_range(t: &mut RecordTask, regs: &Registers) -> Option<Vec<(u32, u32)>> {
+    let first = regs.arg1() as u32;
+    let last = regs.arg2() as u32;
+    let reserved = t.fd_table().reserved_fds_in_range(first, last);
+    if reserved.is_empty() {
+        return None;
+    }
+    log!(
+        LogDebug,
+        "{}: close_range({}, {}) would close rd's fds {:?}",
+        t.tid,
+        first,
+        last,
+        reserved
+    );
+    let ranges = close_range_gaps(first, last, &reserved);
+    let mut r = regs.clone();
+    match ranges.first() {
+        Some(&(first, last)) => {
+            r.set_arg1(first as usize);
+            r.set_arg2(last as usize);
+        }
+        None => r.set_original_syscallno(syscall_number_for_gettid(t.arch()) as isize),
+    }
+    t.set_regs(&r);
+    Some(ranges)
+}
+
+/// `t` has completed the close_range() `prepare_close_range()` returned
+/// `ranges` for. Close the ranges the syscall didn't cover and restore the
+/// syscall so it's recorded as the tracee made it.
+pub fn finish_close_range(t: &mut RecordTask, entry_regs: &Registers, ranges: &[(u32, u32)]) {
+    let mut r = t.regs_ref().clone();
+    r.set_original_syscallno(entry_regs.original_syscallno());
+    r.set_arg1(entry_regs.arg1());
+    r.set_arg2(entry_regs.arg2());
+    if ranges.is_empty() {
+        r.set_syscall_result(0);
+    } else if !r.syscall_failed() {
+        let flags = entry_regs.arg3();
+        let mut remote = AutoRemoteSyscalls::new(t);
+        let arch = remote.arch();
+        for &(first, last) in &ranges[1..] {
+            rd_infallible_syscall!(
+                remote,
+                syscall_number_for_close_range(arch),
+                first,
+                last,
+                flags
+            );
+        }
+    }
+    t.set_regs(&r);
+}
+
+/// The parts of [first, last] that aren't in `reserved`, which is sorted.
+fn close_range_gaps(first: u32, last: u32, reserved: &[i32]) -> Vec<(u32, u32)> {
+    let mut ranges = Vec::new();
+    let mut start = first as u64;
+    for &fd in reserved {
+        let fd = fd as u64;
+        if fd > start {
+            ranges.push((start as u32, fd as u32 - 1));
+        }
+        start = fd + 1;
+    }
+    if start <= last as u64 {
+        ranges.push((start as u32, last));
+    }
+    ranges
+}
+
+/// What to do with a perf_event_open() a tracee is entering. Real counter
+/// values depend on everything else running on the machine and can't be
+/// reproduced, so the tracee never gets a real counter.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PerfEventOpenAction {
+    /// Make an inotify_init1() of the syscall, to allocate an fd, and emulate
+    /// the counter with a `VirtualPerfCounterMonitor` on it.
+    Virtualize,
+    /// Make the syscall a no-op that fails with this errno.
+    Deny(i32),
+}
+
+/// Decide what to do with the perf_event_open() `t` is entering and rewrite
+/// the syscall accordingly. `regs` are the registers at syscall entry; pass
+/// them and the result to `finish_perf_event_open()` at syscall exit.
+///
+/// We deny with EACCES, which is what programs get when
+/// /proc/sys/kernel/perf_event_paranoid doesn't let them use counters, and
+/// most know to carry on without them.
+pub fn prepare_perf_event_open(t: &mut RecordTask, regs: &Registers) -> PerfEventOpenAction {
+    let arch = t.arch();
+    rd_arch_function_selfless!(prepare_perf_event_open_arch, arch, t, regs)
+}
+
+fn prepare_perf_event_open_arch<Arch: Architecture>(
+    t: &mut RecordTask,
+    regs: &Registers,
+) -> PerfEventOpenAction {
+    let maybe_attr = read_perf_event_attr(t, RemotePtr::new_from_val(regs.arg1()));
+    let attr = maybe_attr.unwrap_or_default();
+    let pid = regs.arg2_signed() as pid_t;
+    let cpu = regs.arg3_signed();
+    let group_fd = regs.arg4_signed();
+    let flags = regs.arg5();
+    let target_exists =
+        pid == 0 || pid == t.tid || t.session().find_task_from_rec_tid(pid).is_some();
+    let action = if maybe_attr.is_none() {
+        PerfEventOpenAction::Deny(EFAULT)
+    } else if target_exists
+        && cpu == -1
+        && group_fd == -1
+        && flags == 0
+        && VirtualPerfCounterMonitor::should_virtualize(&attr)
+    {
+        PerfEventOpenAction::Virtualize
+    } else {
+        PerfEventOpenAction::Deny(EACCES)
+    };
+    log!(
+        LogDebug,
+        "{}: perf_event_open(type {}, config {:#x}, pid {}, cpu {}): {:?}",
+        t.tid,
+        attr.type_,
+        attr.config,
+        pid,
+        cpu,
+        action
+    );
+
+    let mut r = regs.clone();
+    match action {
+        PerfEventOpenAction::Virtualize => {
+            r.set_original_syscallno(Arch::INOTIFY_INIT1 as isize);
+            r.set_arg1(0);
+        }
+        PerfEventOpenAction::Deny(_) => r.set_original_syscallno(Arch::GETTID as isize),
+    }
+    t.set_regs(&r);
+    action
+}
+
+/// `t` has completed the syscall `prepare_perf_event_open()` rewrote. Restore
+/// the perf_event_open() so it's what gets recorded, with the result the
+/// tracee should see.
+pub fn finish_perf_event_open(
+    t: &mut RecordTask,
+    entry_regs: &Registers,
+    action: PerfEventOpenAction,
+) {
+    let mut r = t.regs_ref().clone();
+    r.set_original_syscallno(entry_regs.original_syscallno());
+    r.set_arg1(entry_regs.arg1());
+    match action {
+        PerfEventOpenAction::Virtualize => {
+            let fd = r.syscall_result_signed() as i32;
+            if fd >= 0 {
+                let attr = read_perf_event_attr(t, RemotePtr::new_from_val(entry_regs.arg1()));
+                add_virtual_perf_counter_monitor(
+                    t,
+                    entry_regs.arg2_signed() as pid_t,
+                    fd,
+                    &attr.unwrap(),
+                );
+            }
+        }
+        PerfEventOpenAction::Deny(errno) => r.set_syscall_result_signed(-errno as isize),
+    }
+    t.set_regs(&r);
+}
+
+/// The size of the first version of `struct perf_event_attr`, which is what
+/// a `size` of 0 means.
+const PERF_ATTR_SIZE_VER0: usize = 64;
+
+/// Read the perf_event_attr at `addr`. Its `size` field says which version
+/// of the struct the tracee was built with: fields older versions don't have
+/// read as zero and anything past the fields we know of is left unread, as
+/// the kernel does. Returns `None` if the struct can't be read.
+pub fn read_perf_event_attr(
+    t: &mut dyn Task,
+    addr: RemotePtr<perf_event_attr>,
+) -> Option<perf_event_attr> {
+    let size_addr =
+        RemotePtr::<u32>::new_from_val(addr.as_usize() + offset_of!(perf_event_attr, size));
+    let mut ok = true;
+    let size = match read_val_mem(t, size_addr, Some(&mut ok)) {
+        _ if !ok => return None,
+        0 => PERF_ATTR_SIZE_VER0,
+        size => size as usize,
+    };
+    let mut attr = perf_event_attr::default();
+    let len = min(size, size_of::<perf_event_attr>());
+    let buf = unsafe { slice::from_raw_parts_mut(&raw mut attr as *mut u8, len) };
+    match t.read_bytes_fallible(RemotePtr::cast(addr), buf) {
+        Ok(nread) if nread == len => Some(attr),
+        _ => None,
+    }
+}
+
+/// Monitor `fd`, which a perf_event_open() by `t` for (recorded) task `pid`
+/// returned, as a virtual perf counter. Needed during replay too so the fd
+/// table matches the recording.
+pub fn add_virtual_perf_counter_monitor(
+    t: &mut dyn Task,
+    pid: pid_t,
+    fd: i32,
+    attr: &perf_event_attr,
+) {
+    let (tuid, ticks) = if pid == 0 || pid == t.rec_tid {
+        (t.tuid(), t.tick_count())
+    } else {
+        match t.session().find_task_from_rec_tid(pid) {
+            Some(target) => {
+                let target = target.borrow();
+                (target.tuid(), target.tick_count())
+            }
+            None => {
+                fatal!("perf_event_open() target {} went away", pid);
+                unreachable!()
+            }
+        }
+    };
+    let monitor = VirtualPerfCounterMonitor::new(tuid, ticks, attr);
+    t.fd_table_shr_ptr()
+        .borrow_mut()
+        .add_monitor(t, fd, Box::new(monitor));
+}
+
+/// If the read() `t` is entering is from a virtual perf counter, write the
+/// count to the tracee's buffer and return the result the read() should
+/// have. The caller makes the syscall a no-op with that result.
+pub fn emulate_virtual_perf_counter_read(t: &mut RecordTask, regs: &Registers) -> Option<isize> {
+    let fd = regs.arg1_signed() as i32;
+    let monitor = t.fd_table_shr_ptr().borrow().get_monitor(fd)?;
+    let value = monitor.borrow().as_virtual_perf_counter_monitor()?.value(t);
+    let buf = RemotePtr::<Void>::new_from_val(regs.arg2());
+    if regs.arg3() < size_of::<u64>() {
+        return Some(-ENOSPC as isize);
+    }
+    t.write_bytes(RemotePtr::cast(buf), &value.to_ne_bytes());
+    t.record_remote(buf, size_of::<u64>());
+    Some(size_of::<u64>() as isize)
+}
+
+/// Emulate the rseq() `t` is entering, see `rseq` for why, and make the
+/// syscall a no-op. Returns the result the tracee should get; pass it to
+/// `finish_rseq()` at syscall exit.
+///
+/// Registering writes the CPU tracees are bound to into the area, which is
+/// all the kernel would ever put there since tracees don't migrate.
+pub fn prepare_rseq(t: &mut RecordTask, regs: &Registers) -> isize {
+    let arch = t.arch();
+    rd_arch_function_selfless!(prepare_rseq_arch, arch, t, regs)
+}
+
+fn prepare_rseq_arch<Arch: Architecture>(t: &mut RecordTask, regs: &Registers) -> isize {
+    let ptr = RemotePtr::<rseq>::new_from_val(regs.arg1());
+    let len = regs.arg2();
+    let flags = regs.arg3() as u32;
+    let sig = regs.arg4() as u32;
+    let state = t.rseq_state;
+    let result = match state {
+        _ if flags & !RSEQ_FLAG_UNREGISTER != 0 => -EINVAL,
+        Some(state) if state.ptr != ptr || len != size_of::<rseq>() => -EINVAL,
+        Some(state) if state.abort_prefix_signature != sig => -EPERM,
+        Some(_) if flags & RSEQ_FLAG_UNREGISTER == 0 => -EBUSY,
+        Some(_) => {
+            if write_rseq_cpu_ids(t, ptr, 0, RSEQ_CPU_ID_UNINITIALIZED as u32) {
+                t.rseq_state = None;
+                0
+            } else {
+                -EFAULT
+            }
+        }
+        None if flags & RSEQ_FLAG_UNREGISTER != 0 => -EINVAL,
+        None if ptr.as_usize() % align_of::<rseq>() != 0 || len != size_of::<rseq>() => -EINVAL,
+        None => {
+            let cpu = t.session().as_record().unwrap().bound_cpu().unwrap_or(0);
+            if write_rseq_cpu_ids(t, ptr, cpu, cpu) {
+                t.rseq_state = Some(RseqState {
+                    ptr,
+                    abort_prefix_signature: sig,
+                });
+                0
+            } else {
+                -EFAULT
+            }
+        }
+    };
+    log!(
+        LogDebug,
+        "{}: rseq({}, {}, {:#x}, {:#x}) = {}",
+        t.tid,
+        ptr,
+        len,
+        flags,
+        sig,
+        result
+    );
+
+    let mut r = regs.clone();
+    r.set_original_syscallno(Arch::GETTID as isize);
+    t.set_regs(&r);
+    result as isize
+}
+
+fn write_rseq_cpu_ids(t: &mut RecordTask, ptr: RemotePtr<rseq>, start: u32, cpu: u32) -> bool {
+    let ids = RemotePtr::<[u32; 2]>::cast(ptr);
+    let mut ok = true;
+    write_val_mem(t, ids, &[start, cpu], Some(&mut ok));
+    if ok {
+        t.record_remote(RemotePtr::cast(ids), size_of::<[u32; 2]>());
+    }
+    ok
+}
+
+/// `t` has completed the syscall `prepare_rseq()` made a no-op. Restore the
+/// rseq() so it's what gets recorded, with `result`.
+pub fn finish_rseq(t: &mut RecordTask, entry_regs: &Registers, result: isize) {
+    let mut r = t.regs_ref().clone();
+    r.set_original_syscallno(entry_regs.original_syscallno());
+    r.set_syscall_result_signed(result);
+    t.set_regs(&r);
+}
+
+/// Check the clone3() `t` is entering and make sure the child stays traced,
+/// by clearing CLONE_UNTRACED in the tracee's argument struct. Returns the
+/// arguments as the tracee passed them, for `finish_clone3()`, or `None` if
+/// the kernel is going to fail the syscall before creating a task.
+///
+/// set_tid and CLONE_INTO_CGROUP are left to the kernel. The tids the child
+/// gets are recorded as for any clone, and replay creates the child with
+/// whatever tids it gets and maps them to the recorded ones.
+pub fn prepare_clone3(t: &mut RecordTask, regs: &Registers) -> Option<clone_args> {
+    let addr = RemotePtr::<Void>::from(regs.arg1());
+    let args = match read_clone_args(t, addr, regs.arg2()) {
+        Ok(args) => args,
+        Err(errno) => {
+            log!(
+                LogDebug,
+                "{}: clone3() will fail with {}",
+                t.tid,
+                errno_name(errno)
+            );
+            return None;
+        }
+    };
+    let untraced = CLONE_UNTRACED as u64;
+    if args.flags & untraced != 0 {
+        let flags = args.flags & !untraced;
+        write_val_mem(t, RemotePtr::<u64>::cast(addr), &flags, None);
+    }
+    Some(args)
+}
+
+/// `t` has completed the clone3() `prepare_clone3()` returned `args` for,
+/// creating `new_task` unless it failed. `regs` are the registers at syscall
+/// entry. Restores the argument struct and records what the kernel wrote
+/// into the two tasks' memory, in the order replay reads it back:
+///
+/// * parent: parent_tid, pidfd, then the TLS `user_desc` on x86
+/// * child: the TLS `user_desc` on x86, parent_tid, child_tid
+pub fn finish_clone3(
+    t: &mut RecordTask,
+    new_task: Option<&mut RecordTask>,
+    regs: &Registers,
+    args: &clone_args,
+) {
+    let arch = t.arch();
+    rd_arch_function_selfless!(finish_clone3_arch, arch, t, new_task, regs, args)
+}
+
+fn finish_clone3_arch<Arch: Architecture>(
+    t: &mut RecordTask,
+    new_task: Option<&mut RecordTask>,
+    regs: &Registers,
+    args: &clone_args,
+) {
+    if args.flags & CLONE_UNTRACED as u64 != 0 {
+        let addr = RemotePtr::<u64>::from(regs.arg1());
+        write_val_mem(t, addr, &args.flags, None);
+    }
+    let new_task = match new_task {
+        Some(new_task) => new_task,
+        None => return,
+    };
+    let params = clone3_parameters(args);
+    let pidfd = if args.flags & CLONE_PIDFD as u64 != 0 {
+        RemotePtr::new_from_val(args.pidfd as usize)
+    } else {
+        RemotePtr::null()
+    };
+    t.record_remote_even_if_null(RemotePtr::cast(params.ptid), size_of::<i32>());
+    t.record_remote_even_if_null(pidfd, size_of::<i32>());
+    if Arch::CLONE_TLS_TYPE == CloneTLSType::UserDescPointer {
+        t.record_remote_even_if_null(params.tls, size_of::<user_desc>());
+        new_task.record_remote_even_if_null(params.tls, size_of::<user_desc>());
+    }
+    new_task.record_remote_even_if_null(RemotePtr::cast(params.ptid), size_of::<i32>());
+    new_task.record_remote_even_if_null(RemotePtr::cast(params.ctid), size_of::<i32>());
+}
+
+/// What to do with an io_uring_setup() a tracee is entering, see `io_uring`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum IoUringSetupAction {
+    /// Let it run with IORING_SETUP_SINGLE_ISSUER and
+    /// IORING_SETUP_DEFER_TASKRUN added to `tracee_flags`, the flags the
+    /// tracee passed.
+    Record { tracee_flags: u32 },
+    /// Make the syscall a no-op that fails with this errno.
+    Deny(i32),
+}
+
+/// Decide what to do with the io_uring_setup() `t` is entering, following
+/// the session's `IoUringPolicy`, and rewrite the syscall accordingly.
+/// `regs` are the registers at syscall entry; pass them and the result to
+/// `finish_io_uring_setup()` at syscall exit.
+///
+/// Rings with setup flags that let the kernel touch them outside
+/// io_uring_enter() are refused with EINVAL, like kernels that don't know
+/// the flags do.
+pub fn prepare_io_uring_setup(t: &mut RecordTask, regs: &Registers) -> IoUringSetupAction {
+    let policy = t.session().as_record().unwrap().io_uring_policy();
+    let flags_addr = io_uring_setup_flags_addr(regs);
+    let mut ok = true;
+    let tracee_flags = read_val_mem(t, flags_addr, Some(&mut ok));
+    let action = match policy {
+        IoUringPolicy::Deny => IoUringSetupAction::Deny(ENOSYS),
+        IoUringPolicy::Record if !ok => IoUringSetupAction::Deny(EFAULT),
+        IoUringPolicy::Record if tracee_flags & !RECORDABLE_SETUP_FLAGS != 0 => {
+            IoUringSetupAction::Deny(EINVAL)
+        }
+        IoUringPolicy::Record => IoUringSetupAction::Record { tracee_flags },
+    };
+    log!(
+        LogDebug,
+        "{}: io_uring_setup(flags {:#x}): {:?}",
+        t.tid,
+        tracee_flags,
+        action
+    );
+
+    match action {
+        IoUringSetupAction::Record { tracee_flags } => {
+            let flags = tracee_flags | IORING_SETUP_SINGLE_ISSUER | IORING_SETUP_DEFER_TASKRUN;
+            write_val_mem(t, flags_addr, &flags, None);
+        }
+        IoUringSetupAction::Deny(_) => make_syscall_no_op(t, regs),
+    }
+    action
+}
+
+/// `t` has completed the syscall `prepare_io_uring_setup()` prepared.
+/// Restore what the tracee passed, record what the kernel wrote back and
+/// start monitoring the new ring.
+pub fn finish_io_uring_setup(
+    t: &mut RecordTask,
+    entry_regs: &Registers,
+    action: IoUringSetupAction,
+) {
+    let tracee_flags = match action {
+        IoUringSetupAction::Record { tracee_flags } => tracee_flags,
+        IoUringSetupAction::Deny(errno) => return fail_no_op_syscall(t, entry_regs, errno),
+    };
+    let flags_addr = io_uring_setup_flags_addr(entry_regs);
+    write_val_mem(t, flags_addr, &tracee_flags, None);
+    let fd = t.regs_ref().syscall_result_signed() as i32;
+    if fd < 0 {
+        return;
+    }
+    let params_addr = RemotePtr::<io_uring_params>::from(entry_regs.arg2());
+    t.record_remote(RemotePtr::cast(params_addr), size_of::<io_uring_params>());
+    add_io_uring_monitor(t, fd, params_addr);
+}
+
+/// Monitor `fd`, which an io_uring_setup() by `t` with `params` returned.
+/// Needed during replay too so the fd table matches the recording.
+pub fn add_io_uring_monitor(t: &mut dyn Task, fd: i32, params: RemotePtr<io_uring_params>) {
+    let params = read_val_mem(t, params, None);
+    t.fd_table_shr_ptr()
+        .borrow_mut()
+        .add_monitor(t, fd, Box::new(IoUringMonitor::new(params)));
+}
+
+fn io_uring_setup_flags_addr(regs: &Registers) -> RemotePtr<u32> {
+    RemotePtr::new_from_val(regs.arg2() + offset_of!(io_uring_params, flags))
+}
+
+/// `t` mapped the part of a ring at mmap() offset `offset` (in bytes) at
+/// `addr`, if `fd` is a ring rd records.
+pub fn note_io_uring_mmap(t: &mut RecordTask, fd: i32, offset: u64, addr: RemotePtr<Void>) {
+    if let Some(monitor) = io_uring_monitor(t, fd) {
+        let mut monitor = monitor.borrow_mut();
+        monitor
+            .as_io_uring_monitor_mut()
+            .unwrap()
+            .did_mmap(offset, addr);
+    }
+}
+
+/// Check what the io_uring_enter() `t` is entering submits. Returns false,
+/// after making the syscall a no-op, if it would submit operations rd can't
+/// record. Pass the result to `finish_io_uring_enter()` at syscall exit.
+pub fn prepare_io_uring_enter(t: &mut RecordTask, regs: &Registers) -> bool {
+    let monitor = match io_uring_monitor(t, regs.arg1_signed() as i32) {
+        Some(monitor) => monitor,
+        None => return true,
+    };
+    let mut monitor = monitor.borrow_mut();
+    let ring = monitor.as_io_uring_monitor_mut().unwrap();
+    match ring.will_submit(t, regs.arg2() as u32) {
+        Ok(()) => true,
+        Err(opcode) => {
+            log!(
+                LogWarn,
+                "{}: can't record io_uring operation {}, failing io_uring_enter() with EINVAL",
+                t.tid,
+                opcode
+            );
+            make_syscall_no_op(t, regs);
+            false
+        }
+    }
+}
+
+/// `t` has completed the io_uring_enter() `prepare_io_uring_enter()`
+/// returned `allowed` for. Record what it did to the ring.
+pub fn finish_io_uring_enter(t: &mut RecordTask, entry_regs: &Registers, allowed: bool) {
+    if !allowed {
+        return fail_no_op_syscall(t, entry_regs, EINVAL);
+    }
+    if let Some(monitor) = io_uring_monitor(t, entry_regs.arg1_signed() as i32) {
+        let mut monitor = monitor.borrow_mut();
+        monitor.as_io_uring_monitor_mut().unwrap().did_enter(t);
+    }
+}
+
+/// Whether `fd` of `t` is a ring rd records. Replay maps these differently,
+/// see `replay_syscall::finish_io_uring_mmap()`.
+pub fn is_io_uring_fd(t: &dyn Task, fd: i32) -> bool {
+    io_uring_monitor(t, fd).is_some()
+}
+
+/// The monitor of `fd`, if it's a ring rd records.
+fn io_uring_monitor(t: &dyn Task, fd: i32) -> Option<FileMonitorSharedPtr> {
+    let monitor = t.fd_table_shr_ptr().borrow().get_monitor(fd)?;
+    if monitor.borrow().file_monitor_type() == FileMonitorType::IoUring {
+        Some(monitor)
+    } else {
+        None
+    }
+}
+
+/// Check the io_uring_register() `t` is entering. Returns false, after
+/// making the syscall a no-op, if rd can't record it. Pass the result to
+/// `finish_io_uring_register()` at syscall exit.
+pub fn prepare_io_uring_register(t: &mut RecordTask, regs: &Registers) -> bool {
+    let opcode = regs.arg2() as u32;
+    if io_uring_register_opcode_recordable(opcode) {
+        return true;
+    }
+    log!(
+        LogWarn,
+        "{}: can't record io_uring_register() opcode {}, failing it with EINVAL",
+        t.tid,
+        opcode
+    );
+    make_syscall_no_op(t, regs);
+    false
+}
+
+/// `t` has completed the io_uring_register() `prepare_io_uring_register()`
+/// returned `allowed` for.
+pub fn finish_io_uring_register(t: &mut RecordTask, entry_regs: &Registers, allowed: bool) {
+    if !allowed {
+        return fail_no_op_syscall(t, entry_regs, EINVAL);
+    }
+    if entry_regs.arg2() as u32 == IORING_REGISTER_PROBE && !t.regs_ref().syscall_failed() {
+        t.record_remote(
+            RemotePtr::from(entry_regs.arg3()),
+            io_uring_probe_size(entry_regs.arg4()),
+        );
+    }
+}
+
+/// `t` is entering memfd_secret(), see `memfd`. Call
+/// `finish_memfd_secret()` at syscall exit.
+pub fn prepare_memfd_secret(t: &mut RecordTask, regs: &Registers) {
+    log!(LogDebug, "{}: denying memfd_secret()", t.tid);
+    make_syscall_no_op(t, regs);
+}
+
+pub fn finish_memfd_secret(t: &mut RecordTask, entry_regs: &Registers) {
+    fail_no_op_syscall(t, entry_regs, ENOSYS);
+}
+
+/// Size of `struct keyctl_pkey_query`.
+const KEYCTL_PKEY_QUERY_SIZE: usize = 56;
+
+/// Check the keyctl() `t` is entering. Returns false, after making the
+/// syscall a no-op, for operations newer than rd knows, which might write
+/// memory we don't record. Pass the result to `finish_keyctl()` at syscall
+/// exit.
+///
+/// Key serial numbers are whatever the kernel hands out, but like any
+/// syscall result they come from the trace during replay.
+pub fn prepare_keyctl(t: &mut RecordTask, regs: &Registers) -> bool {
+    let op = regs.arg1() as u32;
+    if op <= KEYCTL_CAPABILITIES {
+        return true;
+    }
+    log!(
+        LogWarn,
+        "{}: can't record keyctl() operation {}, failing it with EINVAL",
+        t.tid,
+        op
+    );
+    make_syscall_no_op(t, regs);
+    false
+}
+
+/// `t` has completed the keyctl() `prepare_keyctl()` returned `allowed` for.
+/// Record what the operation wrote to `t`'s memory.
+pub fn finish_keyctl(t: &mut RecordTask, entry_regs: &Registers, allowed: bool) {
+    if !allowed {
+        return fail_no_op_syscall(t, entry_regs, EINVAL);
+    }
+    let result = t.regs_ref().syscall_result_signed();
+    if result < 0 {
+        return;
+    }
+    if let Some((addr, size)) = keyctl_outparam(entry_regs, result as usize) {
+        t.record_remote(addr, size);
+    }
+}
+
+/// The memory the keyctl() entered with `regs` wrote when it returned
+/// `result`, if any.
+fn keyctl_outparam(regs: &Registers, result: usize) -> Option<(RemotePtr<Void>, usize)> {
+    match regs.arg1() as u32 {
+        // These return the size of the whole thing and copy as much of it as
+        // fits.
+        KEYCTL_DESCRIBE | KEYCTL_READ | KEYCTL_GET_SECURITY | KEYCTL_DH_COMPUTE
+            if regs.arg3() != 0 =>
+        {
+            Some((RemotePtr::from(regs.arg3()), min(result, regs.arg4())))
+        }
+        KEYCTL_CAPABILITIES if regs.arg2() != 0 => {
+            Some((RemotePtr::from(regs.arg2()), min(result, regs.arg3())))
+        }
+        KEYCTL_PKEY_QUERY => Some((RemotePtr::from(regs.arg5()), KEYCTL_PKEY_QUERY_SIZE)),
+        KEYCTL_PKEY_ENCRYPT | KEYCTL_PKEY_DECRYPT | KEYCTL_PKEY_SIGN => {
+            Some((RemotePtr::from(regs.arg5()), result))
+        }
+        // The rest only read memory, if they touch it at all.
+        _ => None,
+    }
+}
+
+/// Check whether the mmap(), mremap(), munmap() or mprotect() `t` is
+/// entering would replace, move, unmap or change the protection of rd's own
+/// mappings: the rd page, the preload thread locals or a syscallbuf. Programs
+/// that place MAP_FIXED mappings wherever they like, or tear down their
+/// whole address space, would otherwise crash in the syscallbuf or leave rd
+/// looking at the wrong memory.
+///
+/// Such a syscall is made a no-op and `finish_rd_mapping_guard()` must fail
+/// it at syscall exit; then this returns true. Nothing is ever relocated,
+/// since a MAP_FIXED caller relies on getting exactly the address it asked
+/// for.
+pub fn prepare_rd_mapping_guard(t: &mut RecordTask, regs: &Registers) -> bool {
+    let arch = t.arch();
+    let mut old_mmap_args = None;
+    if arch == SupportedArch::X86 && regs.original_syscallno() as i32 == X86Arch::MMAP {
+        let mut ok = true;
+        let args = read_val_mem::<x86::mmap_args>(t, RemotePtr::from(regs.arg1()), Some(&mut ok));
+        // The kernel fails it with EFAULT if the struct can't be read.
+        if ok {
+            old_mmap_args = Some(args);
+        }
+    }
+    let ranges = rd_arch_function_selfless!(guarded_ranges_arch, arch, regs, old_mmap_args);
+    let vm = t.vm_shr_ptr();
+    let conflict = match ranges.into_iter().find(|r| vm.has_rd_mapping_in(*r)) {
+        Some(r) => r,
+        None => return false,
+    };
+    log!(
+        LogWarn,
+        "{}: {} would clobber rd's mappings in {}, failing it with ENOMEM",
+        t.tid,
+        syscall_name(regs.original_syscallno() as i32, arch),
+        conflict
+    );
+    make_syscall_no_op(t, regs);
+    true
+}
+
+/// The address ranges the syscall entered with `regs` changes, if it's one
+/// `prepare_rd_mapping_guard()` checks. `old_mmap_args` are the arguments
+/// of an old-style x86 mmap(), which passes them in a struct.
+fn guarded_ranges_arch<Arch: Architecture>(
+    regs: &Registers,
+    old_mmap_args: Option<x86::mmap_args>,
+) -> Vec<MemoryRange> {
+    let sys = regs.original_syscallno() as i32;
+    let range = |addr: usize, len: usize| {
+        MemoryRange::from_range(
+            RemotePtr::new_from_val(addr),
+            RemotePtr::new_from_val(addr.saturating_add(len)),
+        )
+    };
+    let register_mmap =
+        sys == Arch::MMAP && Arch::MMAP_SEMANTICS == MmapCallingSemantics::RegisterArguments;
+    // The address, length and flags of an mmap().
+    let mmap = if register_mmap || sys == Arch::MMAP2 {
+        Some((regs.arg1(), regs.arg2(), regs.arg4() as i32))
+    } else if sys == Arch::MMAP {
+        old_mmap_args.map(|args| (args.addr.rptr().as_usize(), args.len as usize, args.flags))
+    } else {
+        None
+    };
+    if let Some((addr, len, flags)) = mmap {
+        // Without MAP_FIXED the kernel picks a free range.
+        if MapFlags::from_bits_truncate(flags).contains(MapFlags::MAP_FIXED) {
+            vec![range(addr, len)]
+        } else {
+            Vec::new()
+        }
+    } else if sys == Arch::MUNMAP || sys == Arch::MPROTECT || sys == Arch::PKEY_MPROTECT {
+        vec![range(regs.arg1(), regs.arg2())]
+    } else if sys == Arch::MREMAP {
+        let mut ranges = vec![range(regs.arg1(), regs.arg2())];
+        if regs.arg4() as i32 & MREMAP_FIXED != 0 {
+            ranges.push(range(regs.arg5(), regs.arg3()));
+        }
+        ranges
+    } else {
+        Vec::new()
+    }
+}
+
+/// `t` has completed the syscall `prepare_rd_mapping_guard()` refused. It
+/// fails with ENOMEM, and is recorded as vetoed so replay doesn't perform it
+/// either.
+pub fn finish_rd_mapping_guard(t: &mut RecordTask) {
+    let mut r = t.regs_ref().clone();
+    r.set_original_syscallno(SECCOMP_MAGIC_SKIP_ORIGINAL_SYSCALLNO);
+    r.set_syscall_result_signed(-ENOMEM as isize);
+    t.set_regs(&r);
+}
+
+/// Turn the syscall `t` is entering into a gettid(), which can't fail or
+/// block, for `fail_no_op_syscall()` to fail at syscall exit.
+fn make_syscall_no_op(t: &mut RecordTask, regs: &Registers) {
+    let mut r = regs.clone();
+    r.set_original_syscallno(syscall_number_for_gettid(t.arch()) as isize);
+    t.set_regs(&r);
+}
+
+/// Restore the syscall `make_syscall_no_op()` replaced, failing with
+/// `errno`, so that's what gets recorded.
+fn fail_no_op_syscall(t: &mut RecordTask, entry_regs: &Registers, errno: i32) {
+    finish_no_op_syscall(t, entry_regs, -errno as isize)
+}
+
+/// Restore the syscall `make_syscall_no_op()` replaced, returning `result`.
+fn finish_no_op_syscall(t: &mut RecordTask, entry_regs: &Registers, result: isize) {
+    let mut r = t.regs_ref().clone();
+    r.set_original_syscallno(entry_regs.original_syscallno());
+    r.set_syscall_result_signed(result);
+    t.set_regs(&r);
+}
+
+/// The process the pidfd `fd` of `t` refers to, if it is one.
+pub fn pidfd_target(t: &dyn Task, fd: i32) -> Option<pid_t> {
+    let monitor = t.fd_table().get_monitor(fd)?;
+    let pid = monitor.borrow().as_pidfd_monitor()?.pid();
+    Some(pid)
+}
+
+/// Monitor `fd`, which a pidfd_open() of `pid` by `t` returned. Needed
+/// during replay too so the fd table matches the recording.
+pub fn add_pidfd_monitor(t: &mut dyn Task, fd: i32, pid: pid_t) {
+    t.fd_table_shr_ptr()
+        .borrow_mut()
+        .add_monitor(t, fd, Box::new(PidFdMonitor::new(pid)));
+}
+
+/// Where the clone() or clone3() with `flags` has the kernel store the new
+/// task's pidfd, if it asks for one: the `pidfd` field of clone3()'s
+/// `clone3_args`, or clone()'s parent_tid, from `params`.
+pub fn clone_pidfd_addr(
+    flags: u64,
+    clone3_args: Option<&clone_args>,
+    params: &CloneParameters,
+) -> RemotePtr<i32> {
+    if flags & CLONE_PIDFD as u64 == 0 {
+        return RemotePtr::null();
+    }
+    match clone3_args {
+        Some(args) => RemotePtr::new_from_val(args.pidfd as usize),
+        None => params.ptid,
+    }
+}
+
+/// Monitor the pidfd of the task `new_rec_tid` that the kernel stored at
+/// `addr` for a clone() or clone3() by `t`, like one from pidfd_open().
+/// Needed during replay too, once the pidfd is restored from the trace.
+pub fn note_clone_pidfd(t: &mut dyn Task, addr: RemotePtr<i32>, new_rec_tid: pid_t) {
+    let fd = read_val_mem(t, addr, None);
+    add_pidfd_monitor(t, fd, new_rec_tid);
+}
+
+/// `t` has completed a pidfd_open().
+pub fn finish_pidfd_open(t: &mut RecordTask) {
+    let regs = t.regs_ref().clone();
+    let fd = regs.syscall_result_signed() as i32;
+    if fd >= 0 {
+        add_pidfd_monitor(t, fd, regs.arg1_signed() as pid_t);
+    }
+}
+
+/// Check the pidfd_send_signal() `t` is entering, which is a kill() of the
+/// pidfd's process as far as we're concerned, see `prepare_kill()`. Returns
+/// whether another task may run while the syscall is in progress, and the
+/// siginfo to pass to `finish_pidfd_send_signal()` at syscall exit if the
+/// signal is to be delivered by rd.
+///
+/// A plain signal to `t`'s own process that `t` doesn't block is one the
+/// kernel would deliver to `t` as the syscall returns. We make the syscall a
+/// no-op and stash the signal instead, so it is delivered like the other
+/// signals rd holds back, once the syscall is recorded.
+pub fn prepare_pidfd_send_signal(
+    t: &mut RecordTask,
+    regs: &Registers,
+) -> (Switchable, Option<siginfo_t>) {
+    let pid = match pidfd_target(t, regs.arg1_signed() as i32) {
+        Some(pid) => pid,
+        // Not a pidfd, so the kernel fails it without sending anything.
+        None => return (Switchable::PreventSwitch, None),
+    };
+    let sig = regs.arg2_signed() as i32;
+    let switchable = prepare_kill(t, pid, sig);
+    let plain = regs.arg3() == 0 && regs.arg4() == 0;
+    if pid != t.tgid()
+        || !plain
+        || sig <= 0
+        || sig == SIGKILL
+        || sig == SIGSTOP
+        || t.is_sig_blocked(sig)
+    {
+        return (switchable, None);
+    }
+    // What the kernel fills in for a NULL siginfo.
+    let mut si: siginfo_t = unsafe { zeroed() };
+    si.si_signo = sig;
+    si.si_code = SI_USER;
+    si._sifields._kill.si_pid = t.thread_group().real_tgid_own_namespace;
+    si._sifields._kill.si_uid = t.getuid();
+    log!(
+        LogDebug,
+        "{}: emulating pidfd_send_signal() of {} to itself",
+        t.tid,
+        sig
+    );
+    make_syscall_no_op(t, regs);
+    (switchable, Some(si))
+}
+
+/// `t` has completed the pidfd_send_signal() `prepare_pidfd_send_signal()`
+/// returned `emulated` for.
+pub fn finish_pidfd_send_signal(
+    t: &mut RecordTask,
+    entry_regs: &Registers,
+    emulated: Option<siginfo_t>,
+) {
+    if let Some(si) = emulated {
+        finish_no_op_syscall(t, entry_regs, 0);
+        t.stash_synthetic_sig(&si, SignalDeterministic::NondeterministicSig);
+    }
+}
+
+/// `t` is about to send `sig` to the process `pid`, or, with tkill() and
+/// tgkill(), the thread, with kill() or pidfd_send_signal(). A `pid` that
+/// isn't positive is a process group, which may include `t`'s process.
+/// Returns whether another task may run while the syscall is in progress.
+///
+/// A signal to `t`'s own process is delivered as the syscall returns, so we
+/// must see it before anything else runs, as during replay. SIGCONT also
+/// ends the target process's emulated group stop, if it's in one.
+pub fn prepare_kill(t: &mut RecordTask, pid: pid_t, sig: i32) -> Switchable {
+    // Any tid names its thread's process. `t` itself is borrowed already.
+    let target = if pid > 0 && pid != t.tid {
+        t.session().find_task_from_rec_tid(pid)
+    } else {
+        None
+    };
+    let to_self = pid <= 0
+        || pid == t.tid
+        || target
+            .as_ref()
+            .map_or(false, |target| target.borrow().tgid() == t.tgid());
+    if sig == SIGCONT {
+        if to_self {
+            t.emulate_sigcont();
+        } else if target.is_some() {
+            // Nothing can see the group stop end until `t` is done with.
+            t.session().defer(move |sess| {
+                if let Some(target) = sess.find_task_from_rec_tid(pid) {
+                    if let Some(rt) = target.borrow_mut().as_record_task_mut() {
+                        rt.emulate_sigcont();
+                    }
+                }
+            });
+        }
+    }
+    if to_self {
+        Switchable::PreventSwitch
+    } else {
+        Switchable::AllowSwitch
+    }
+}
+
+/// Check the pidfd_getfd() `t` is entering. Tracees can't close rd's own
+/// fds, so they don't get to copy them out of other tracees either. Returns
+/// false, after making the syscall a no-op, if that's what it would do.
+/// Pass the result to `finish_pidfd_getfd()` at syscall exit.
+pub fn prepare_pidfd_getfd(t: &mut RecordTask, regs: &Registers) -> bool {
+    let table = match pidfd_target(t, regs.arg1_signed() as i32)
+        .and_then(|pid| fd_table_of_process(t, pid))
+    {
+        Some(table) => table,
+        None => return true,
+    };
+    let target_fd = regs.arg2_signed() as i32;
+    {
+        let table = table.borrow();
+        if !table.is_rd_fd(target_fd) && table.reserved_fd_kind(target_fd).is_none() {
+            return true;
+        }
+    }
+    log!(
+        LogDebug,
+        "{}: pidfd_getfd() of rd's fd {}",
+        t.tid,
+        target_fd
+    );
+    make_syscall_no_op(t, regs);
+    false
+}
+
+/// `t` has completed the pidfd_getfd() `prepare_pidfd_getfd()` returned
+/// `allowed` for.
+pub fn finish_pidfd_getfd(t: &mut RecordTask, entry_regs: &Registers, allowed: bool) {
+    if !allowed {
+        return fail_no_op_syscall(t, entry_regs, EBADF);
+    }
+    let fd = t.regs_ref().syscall_result_signed() as i32;
+    if fd >= 0 {
+        import_pidfd_getfd_monitor(
+            t,
+            entry_regs.arg1_signed() as i32,
+            entry_regs.arg2_signed() as i32,
+            fd,
+        );
+    }
+}
+
+/// `fd` is the copy a pidfd_getfd() by `t` made of `target_fd` of the
+/// process of `pidfd`. It shares the monitor of the fd it copies, if any.
+/// Needed during replay too so the fd table matches the recording.
+pub fn import_pidfd_getfd_monitor(t: &mut dyn Task, pidfd: i32, target_fd: i32, fd: i32) {
+    let monitor = match pidfd_target(t, pidfd).and_then(|pid| fd_table_of_process(t, pid)) {
+        Some(table) => table.borrow().get_monitor(target_fd),
+        None => None,
+    };
+    t.fd_table_shr_ptr()
+        .borrow_mut()
+        .did_import(fd, monitor, t);
+}
+
+/// The fd table of the tracee process `pid`, which may be `t`'s.
+fn fd_table_of_process(t: &dyn Task, pid: pid_t) -> Option<FdTableSharedPtr> {
+    if pid == t.tgid() || pid == t.rec_tid {
+        return Some(t.fd_table_shr_ptr());
+    }
+    let target = t.session().find_task_from_rec_tid(pid)?;
+    let table = target.borrow().fd_table_shr_ptr();
+    Some(table)
+}
+
+/// The signals a read() of the signalfd `fd` of `t` dequeues, if it is one.
+pub fn signalfd_mask(t: &dyn Task, fd: i32) -> Option<sig_set_t> {
+    let monitor = t.fd_table().get_monitor(fd)?;
+    let mask = monitor.borrow().as_signalfd_monitor()?.mask();
+    Some(mask)
+}
+
+/// `fd` is the signalfd of the signals in `mask` that a signalfd() or
+/// signalfd4() by `t` returned, either a new one or one whose mask it
+/// replaced. Needed during replay too so the fd table matches the recording.
+pub fn note_signalfd(t: &mut dyn Task, fd: i32, mask: sig_set_t) {
+    // The kernel silently ignores these.
+    let mask = mask & !(1 << (SIGKILL - 1) | 1 << (SIGSTOP - 1));
+    if let Some(monitor) = t.fd_table().get_monitor(fd) {
+        if let Some(m) = monitor.borrow_mut().as_signalfd_monitor_mut() {
+            m.set_mask(mask);
+            return;
+        }
+    }
+    t.fd_table_shr_ptr()
+        .borrow_mut()
+        .add_monitor(t, fd, Box::new(SignalFdMonitor::new(mask)));
+}
+
+/// `t` has completed a signalfd() or signalfd4().
+pub fn finish_signalfd(t: &mut RecordTask, entry_regs: &Registers) {
+    let fd = t.regs_ref().syscall_result_signed() as i32;
+    if fd >= 0 {
+        let mask = read_val_mem::<sig_set_t>(t, RemotePtr::from(entry_regs.arg2()), None);
+        note_signalfd(t, fd, mask);
+    }
+}
+
+/// Check the read() `t` is entering. If it's of a signalfd and `t` has
+/// stashed signals the signalfd dequeues, the kernel doesn't have those
+/// pending any more, and the read() would miss them or block. So copy them
+/// into the buffer here instead, like the kernel would have, and make the
+/// syscall a no-op. Returns the number of bytes written, for
+/// `finish_signalfd_read()`.
+///
+/// The buffer is recorded at syscall exit like that of any other read(), so
+/// replay doesn't need to know about this.
+pub fn prepare_signalfd_read(t: &mut RecordTask, regs: &Registers) -> Option<usize> {
+    let mask = signalfd_mask(t, regs.arg1_signed() as i32)?;
+    let max = regs.arg3() / size_of::<signalfd_siginfo>();
+    let sigs = t.take_stashed_sigs_for_signalfd(mask, max);
+    if sigs.is_empty() {
+        return None;
+    }
+    let infos: Vec<signalfd_siginfo> = sigs.iter().map(to_signalfd_siginfo).collect();
+    write_mem(t, RemotePtr::from(regs.arg2()), &infos, None);
+    make_syscall_no_op(t, regs);
+    Some(infos.len() * size_of::<signalfd_siginfo>())
+}
+
+/// Restore the read() `prepare_signalfd_read()` made a no-op, returning
+/// `len`.
+pub fn finish_signalfd_read(t: &mut RecordTask, entry_regs: &Registers, len: usize) {
+    let mut r = t.regs_ref().clone();
+    r.set_original_syscallno(entry_regs.original_syscallno());
+    r.set_syscall_result(len);
+    t.set_regs(&r);
+}
+
+/// Monitor `fd`, the timerfd a timerfd_create() by `t` returned. Needed
+/// during replay too so the fd table matches the recording.
+pub fn add_timerfd_monitor(t: &mut dyn Task, fd: i32) {
+    t.fd_table_shr_ptr()
+        .borrow_mut()
+        .add_monitor(t, fd, Box::new(TimerfdMonitor::new()));
+}
+
+/// `t` has completed a timerfd_create().
+pub fn finish_timerfd_create(t: &mut RecordTask) {
+    let fd = t.regs_ref().syscall_result_signed() as i32;
+    if fd >= 0 {
+        add_timerfd_monitor(t, fd);
+    }
+}
+
+/// `t` has completed a read(). If it was of a timerfd, keep track of the
+/// number of expirations it returned.
+pub fn finish_timerfd_read(t: &mut RecordTask, entry_regs: &Registers) {
+    if t.regs_ref().syscall_failed() {
+        return;
+    }
+    let fd = entry_regs.arg1_signed() as i32;
+    let monitor = match t.fd_table().get_monitor(fd) {
+        Some(monitor) => monitor,
+        None => return,
+    };
+    let mut monitor = monitor.borrow_mut();
+    let timerfd = match monitor.as_timerfd_monitor_mut() {
+        Some(timerfd) => timerfd,
+        None => return,
+    };
+    // A successful read() of a timerfd always returns exactly one count,
+    // which `record_read()` has recorded like any other read buffer.
+    let addr = RemotePtr::<u64>::from(entry_regs.arg2());
+    let count = read_val_mem(t, addr, None);
+    timerfd.did_read(count);
+    log!(
+        LogDebug,
+        "timerfd {} expired {} times, {} in total",
+        fd,
+        count,
+        timerfd.expirations()
+    );
+}
+
+/// Whether the syscall `t` is entering is one `--virtual-clock` answers,
+/// and the session has it on.
+fn answers_from_virtual_clock(t: &RecordTask) -> bool {
+    if t.session().as_record().unwrap().virtual_clock().is_none() {
+        return false;
+    }
+    let sys = t.regs_ref().original_syscallno() as i32;
+    let arch = t.arch();
+    rd_arch_function_selfless!(is_virtual_clock_syscall, arch, sys)
+}
+
+fn is_virtual_clock_syscall<Arch: Architecture>(sys: i32) -> bool {
+    sys == Arch::CLOCK_GETTIME
+        || sys == Arch::CLOCK_GETTIME64
+        || sys == Arch::GETTIMEOFDAY
+        || sys == Arch::TIME
+}
+
+/// What `prepare_virtual_clock()` handed a tracee instead of the kernel's
+/// answer.
+pub struct VirtualClockAnswer {
+    result: isize,
+    /// The memory it wrote the time to.
+    written: Vec<(RemotePtr<Void>, usize)>,
+}
+
+/// If the session has a virtual clock, answer the clock_gettime(),
+/// gettimeofday() or time() `t` is entering with the registers `regs` from
+/// it: write the time to the tracee and make the syscall a no-op. Returns
+/// `None` if the kernel should answer, e.g. for clocks the virtual clock
+/// doesn't cover.
+pub fn prepare_virtual_clock(t: &mut RecordTask, regs: &Registers) -> Option<VirtualClockAnswer> {
+    let arch = t.arch();
+    rd_arch_function_selfless!(prepare_virtual_clock_arch, arch, t, regs)
+}
+
+fn prepare_virtual_clock_arch<Arch: Architecture>(
+    t: &mut RecordTask,
+    regs: &Registers,
+) -> Option<VirtualClockAnswer> {
+    let clock = t.session().as_record().unwrap().virtual_clock()?;
+    let time = t.trace_writer().time();
+    let sys = regs.original_syscallno() as i32;
+    let long_size = size_of::<Arch::signed_long>();
+    // (address, size of each value, values) of the structs to fill in.
+    let mut out: Vec<(RemotePtr<Void>, usize, Vec<u64>)> = Vec::new();
+    let mut result = 0;
+    if sys == Arch::CLOCK_GETTIME || sys == Arch::CLOCK_GETTIME64 {
+        let (secs, nsecs) = clock.now(regs.arg1_signed() as clockid_t, time)?;
+        // clock_gettime64() takes a 64-bit timespec everywhere.
+        let size = if sys == Arch::CLOCK_GETTIME64 {
+            size_of::<u64>()
+        } else {
+            long_size
+        };
+        out.push((RemotePtr::from(regs.arg2()), size, vec![secs, nsecs]));
+    } else if sys == Arch::GETTIMEOFDAY {
+        let (secs, nsecs) = clock.now(CLOCK_REALTIME, time)?;
+        let tv = vec![secs, nsecs / 1000];
+        out.push((RemotePtr::from(regs.arg1()), long_size, tv));
+        // A struct timezone, which has been all zeroes for a long time.
+        out.push((RemotePtr::from(regs.arg2()), size_of::<i32>(), vec![0, 0]));
+    } else if sys == Arch::TIME {
+        let (secs, _) = clock.now(CLOCK_REALTIME, time)?;
+        out.push((RemotePtr::from(regs.arg1()), long_size, vec![secs]));
+        result = secs as isize;
+    } else {
+        return None;
+    }
+
+    let mut written = Vec::new();
+    for (addr, size, values) in out {
+        if addr.is_null() {
+            // Only clock_gettime() requires its struct; the kernel will fail
+            // it with EFAULT.
+            if sys == Arch::CLOCK_GETTIME || sys == Arch::CLOCK_GETTIME64 {
+                return None;
+            }
+            continue;
+        }
+        let bytes: Vec<u8> = values
+            .iter()
+            .flat_map(|v| v.to_le_bytes()[..size].to_vec())
+            .collect();
+        let mut ok = true;
+        write_mem(t, RemotePtr::<u8>::cast(addr), &bytes, Some(&mut ok));
+        if !ok {
+            result = -(EFAULT as isize);
+            written.clear();
+            break;
+        }
+        written.push((addr, bytes.len()));
+    }
+    make_syscall_no_op(t, regs);
+    Some(VirtualClockAnswer { result, written })
+}
+
+/// Finish a syscall `prepare_virtual_clock()` answered, once the no-op has
+/// exited: restore the syscall so it's what gets recorded, with the
+/// virtual clock's result, and record the memory it wrote.
+pub fn finish_virtual_clock(
+    t: &mut RecordTask,
+    entry_regs: &Registers,
+    answer: VirtualClockAnswer,
+) {
+    let mut r = t.regs_ref().clone();
+    r.set_original_syscallno(entry_regs.original_syscallno());
+    r.set_syscall_result_signed(answer.result);
+    t.set_regs(&r);
+    for (addr, size) in answer.written {
+        t.record_remote(addr, size);
+    }
+}
+
+/// The most a getrandom() or read() gets from the seeded generator at once.
+/// getrandom() returns at most this much from the kernel too.
+const MAX_SEEDED_RANDOM_BYTES: usize = 33_554_431;
+
+/// Whether the syscall `t` is entering is a getrandom() `--random-seed`
+/// answers. Reads of random devices don't get here: their fds aren't
+/// buffered.
+fn answers_from_seeded_random(t: &RecordTask) -> bool {
+    t.session().as_record().unwrap().seeded_random().is_some()
+        && t.regs_ref().original_syscallno() as i32 == syscall_number_for_getrandom(t.arch())
+}
+
+/// `t` has just opened `fd`. If the session has a random seed and that's a
+/// random device, monitor it so its reads are answered by
+/// `prepare_seeded_random()`. Returns the file for the syscall event's
+/// `opened` list, so replay monitors it too.
+pub fn maybe_monitor_random_device(t: &mut RecordTask, fd: i32) -> Option<OpenedFd> {
+    if t.session().as_record().unwrap().seeded_random().is_none() {
+        return None;
+    }
+    let path = t.file_name_of_fd(fd);
+    if !is_random_device(&path) {
+        return None;
+    }
+    let st = stat(path.as_os_str()).ok()?;
+    t.fd_table_shr_ptr()
+        .borrow_mut()
+        .add_monitor(t, fd, Box::new(RandomDeviceMonitor::new()));
+    Some(OpenedFd {
+        path,
+        fd,
+        device: st.st_dev,
+        inode: st.st_ino,
+    })
+}
+
+/// `t` has just opened `fd`. If its path matches one of the session's
+/// `--capture-reads` patterns, monitor it so reads of it aren't buffered.
+/// Returns the file for the syscall event's `opened` list, so replay
+/// monitors it too.
+pub fn maybe_capture_reads(t: &mut RecordTask, fd: i32) -> Option<OpenedFd> {
+    let patterns = t.session().as_record().unwrap().capture_reads().to_vec();
+    if patterns.is_empty() {
+        return None;
+    }
+    let path = t.file_name_of_fd(fd);
+    let fd_table = t.fd_table_shr_ptr();
+    if !fd_table
+        .borrow_mut()
+        .capture_reads_if_matching(t, fd, &path, &patterns)
+    {
+        return None;
+    }
+    let st = stat(path.as_os_str()).ok()?;
+    Some(OpenedFd {
+        path,
+        fd,
+        device: st.st_dev,
+        inode: st.st_ino,
+    })
+}
+
+/// `t` has just finished a read() or pread64(). Record the data it read.
+/// Big reads of files on the trace's filesystem are cloned from the file
+/// into the task's cloned-data file, so the trace shares the file's blocks
+/// instead of holding a copy; replay reads the data back from there.
+pub fn record_read(t: &mut RecordTask, entry_regs: &Registers) {
+    let nread = t.regs_ref().syscall_result_signed();
+    if nread <= 0 {
+        return;
+    }
+    let buf = RemotePtr::<Void>::new_from_val(entry_regs.arg2());
+    if !try_clone_read_data(t, entry_regs, buf, nread as usize) {
+        t.record_remote(buf, nread as usize);
+    }
+}
+
+fn try_clone_read_data(
+    t: &mut RecordTask,
+    entry_regs: &Registers,
+    buf: RemotePtr<Void>,
+    size: usize,
+) -> bool {
+    if size < MIN_CLONED_READ_SIZE
+        || t.cloned_file_data_fd_child < 0
+        || !t.session().as_record().unwrap().use_read_cloning()
+        || !t.trace_writer().supports_file_data_cloning()
+    {
+        return false;
+    }
+    let fd = entry_regs.arg1_signed() as i32;
+    // Monitored files need their reads recorded in full, or handled by the
+    // monitor.
+    if t.fd_table().get_monitor(fd).is_some() {
+        return false;
+    }
+    let regs = t.regs_ref().clone();
+    let syscallno = regs.original_syscallno() as i32;
+    let src_offset = match LazyOffset::new(t, &regs, syscallno).retrieve(false) {
+        Some(offset) => offset,
+        None => return false,
+    };
+    let src = t.open_fd(fd, OFlag::O_RDONLY);
+    let dest = t.open_fd(t.cloned_file_data_fd_child, OFlag::O_WRONLY);
+    if !src.is_open() || !dest.is_open() {
+        return false;
+    }
+    // The file could have changed since the read if another process is
+    // writing it, but then the read raced with the writer anyway.
+    let dest_offset = t.cloned_file_data_offset;
+    let mut args = btrfs_ioctl_clone_range_args::default();
+    args.src_fd = src.as_raw() as i64;
+    args.src_offset = src_offset;
+    args.src_length = size as u64;
+    args.dest_offset = dest_offset;
+    let ret = unsafe { ioctl(dest.as_raw(), BTRFS_IOC_CLONE_RANGE_, &raw const args) };
+    if ret < 0 {
+        // The range isn't block aligned, or the file isn't on the trace's
+        // filesystem, or that filesystem can't clone.
+        log!(
+            LogDebug,
+            "Can't clone {} bytes of fd {} at {}, copying them",
+            size,
+            fd,
+            src_offset
+        );
+        return false;
+    }
+    // Clones must start on a block boundary, so the next one starts on the
+    // page after this one ends.
+    t.cloned_file_data_offset = dest_offset + ceil_page_size(size) as u64;
+    let cloned_path = t.file_name_of_fd(t.cloned_file_data_fd_child);
+    let file_name = Path::new(&cloned_path).file_name().unwrap().to_owned();
+    let rec_tid = t.rec_tid;
+    t.trace_writer_mut()
+        .write_cloned_raw(rec_tid, buf, size, &file_name, dest_offset);
+    true
+}
+
+fn is_random_device_fd(t: &RecordTask, fd: i32) -> bool {
+    t.fd_table().get_monitor(fd).map_or(false, |m| {
+        m.borrow().file_monitor_type() == FileMonitorType::RandomDevice
+    })
+}
+
+/// If the session has a random seed, answer the getrandom() or read() of a
+/// random device `t` is entering with the registers `regs` from the seeded
+/// generator: write the bytes to the tracee and make the syscall a no-op.
+/// Returns where the bytes went and the syscall result, for
+/// `finish_seeded_random()`.
+pub fn prepare_seeded_random(
+    t: &mut RecordTask,
+    regs: &Registers,
+) -> Option<(RemotePtr<Void>, isize)> {
+    let arch = t.arch();
+    rd_arch_function_selfless!(prepare_seeded_random_arch, arch, t, regs)
+}
+
+fn prepare_seeded_random_arch<Arch: Architecture>(
+    t: &mut RecordTask,
+    regs: &Registers,
+) -> Option<(RemotePtr<Void>, isize)> {
+    if t.session().as_record().unwrap().seeded_random().is_none() {
+        return None;
+    }
+    let sys = regs.original_syscallno() as i32;
+    let (addr, len) = if sys == Arch::GETRANDOM {
+        (RemotePtr::<Void>::from(regs.arg1()), regs.arg2())
+    } else if sys == Arch::READ && is_random_device_fd(t, regs.arg1_signed() as i32) {
+        (RemotePtr::<Void>::from(regs.arg2()), regs.arg3())
+    } else {
+        return None;
+    };
+    let mut bytes = vec![0u8; min(len, MAX_SEEDED_RANDOM_BYTES)];
+    t.session()
+        .as_record()
+        .unwrap()
+        .seeded_random()?
+        .fill(&mut bytes);
+    let mut ok = true;
+    write_mem(t, RemotePtr::<u8>::cast(addr), &bytes, Some(&mut ok));
+    let result = if ok {
+        bytes.len() as isize
+    } else {
+        -(EFAULT as isize)
+    };
+    make_syscall_no_op(t, regs);
+    Some((addr, result))
+}
+
+/// Finish a syscall `prepare_seeded_random()` answered, once the no-op has
+/// exited: restore the syscall so it's what gets recorded, with `result`,
+/// and record the bytes written at `addr`.
+pub fn finish_seeded_random(
+    t: &mut RecordTask,
+    entry_regs: &Registers,
+    (addr, result): (RemotePtr<Void>, isize),
+) {
+    let mut r = t.regs_ref().clone();
+    r.set_original_syscallno(entry_regs.original_syscallno());
+    r.set_syscall_result_signed(result);
+    t.set_regs(&r);
+    if result > 0 {
+        t.record_remote(addr, result as usize);
+    }
+}
+
+/// Monitor `fd`, the ruleset a landlock_create_ruleset() by `t` returned.
+/// Needed during replay too so the fd table matches the recording.
+pub fn add_landlock_monitor(t: &mut dyn Task, fd: i32) {
+    t.fd_table_shr_ptr()
+        .borrow_mut()
+        .add_monitor(t, fd, Box::new(LandlockMonitor::new()));
+}
+
+/// `t` has completed a landlock_create_ruleset(), see `landlock`.
+pub fn finish_landlock_create_ruleset(t: &mut RecordTask, entry_regs: &Registers) {
+    let fd = t.regs_ref().syscall_result_signed() as i32;
+    if fd >= 0 && creates_landlock_ruleset(entry_regs) {
+        add_landlock_monitor(t, fd);
+    }
+}
+
+/// Monitor `fd`, the listener a seccomp(SECCOMP_SET_MODE_FILTER) with
+/// SECCOMP_FILTER_FLAG_NEW_LISTENER by `t` returned. Needed during replay
+/// too so the fd table matches the recording.
+pub fn add_seccomp_notify_monitor(t: &mut dyn Task, fd: i32) {
+    t.fd_table_shr_ptr()
+        .borrow_mut()
+        .add_monitor(t, fd, Box::new(SeccompNotifyMonitor::new()));
+}
+
+/// The address of the `struct sock_fprog` of the seccomp filter the
+/// seccomp() or prctl() `sys`, entered with `regs`, installs, if it
+/// installs one.
+fn seccomp_filter_prog_addr<Arch: Architecture>(
+    sys: i32,
+    regs: &Registers,
+) -> Option<RemotePtr<u8>> {
+    let installs = if sys == Arch::SECCOMP {
+        regs.arg1() == SECCOMP_SET_MODE_FILTER as usize
+    } else if sys == Arch::PRCTL {
+        regs.arg1() == PR_SET_SECCOMP as usize && regs.arg2() == SECCOMP_MODE_FILTER as usize
+    } else {
+        false
+    };
+    if installs {
+        Some(RemotePtr::from(regs.arg3()))
+    } else {
+        None
+    }
+}
+
+/// Install the seccomp filter whose `struct sock_fprog` is at `prog_addr`,
+/// which `t` is entering a seccomp() or prctl() with, rewritten by the
+/// session's `SeccompFilterRewriter`, and make the syscall a no-op. Returns
+/// the result for `finish_seccomp_filter()` to return instead.
+fn install_patched_seccomp_filter<Arch: Architecture>(
+    t: &mut RecordTask,
+    regs: &Registers,
+    prog_addr: RemotePtr<u8>,
+) -> isize {
+    let word = size_of::<Arch::unsigned_word>();
+    let mut ok = true;
+    let len = read_val_mem::<u16>(t, RemotePtr::cast(prog_addr), Some(&mut ok)) as usize;
+    let filter =
+        read_val_mem::<Arch::unsigned_word>(t, RemotePtr::cast(prog_addr + word), Some(&mut ok));
+    let mut code: Vec<sock_filter> = if ok {
+        let filter_addr = RemotePtr::new_from_val(filter.try_into().unwrap());
+        read_mem(t, filter_addr, len, Some(&mut ok))
+    } else {
+        Vec::new()
+    };
+    make_syscall_no_op(t, regs);
+    if !ok {
+        return -EFAULT as isize;
+    }
+    let notifies = t
+        .session()
+        .as_record()
+        .unwrap()
+        .seccomp_filter_rewriter_mut()
+        .rewrite(&mut code);
+
+    // A `struct sock_fprog` (the length, padded to a word, and a pointer) for
+    // the rewritten code, which follows it.
+    let code_bytes = unsafe {
+        slice::from_raw_parts(
+            code.as_ptr() as *const u8,
+            code.len() * size_of::<sock_filter>(),
+        )
+    };
+    let mut prog = vec![0u8; 2 * word];
+    let result = {
+        let mut remote = AutoRemoteSyscalls::new(t);
+        let mut mem = AutoRestoreMem::new(&mut remote, None, prog.len() + code_bytes.len());
+        let addr = match mem.get() {
+            Some(addr) => RemotePtr::<u8>::cast(addr),
+            // The task is dead.
+            None => return -ESRCH as isize,
+        };
+        let code_addr = addr + prog.len();
+        prog[..2].copy_from_slice(&(len as u16).to_le_bytes());
+        prog[word..].copy_from_slice(&code_addr.as_usize().to_le_bytes()[..word]);
+        write_mem(mem.task_mut(), addr, &prog, None);
+        write_mem(mem.task_mut(), code_addr, code_bytes, None);
+        mem.syscall(
+            regs.original_syscallno() as i32,
+            &[regs.arg1(), regs.arg2(), addr.as_usize()],
+        )
+    };
+    log!(
+        LogDebug,
+        "{}: installed rewritten seccomp filter of {} instructions: {}",
+        t.tid,
+        len,
+        result
+    );
+    if result >= 0 {
+        t.prctl_seccomp_status = 2;
+        if notifies {
+            t.session()
+                .as_record()
+                .unwrap()
+                .note_notifying_seccomp_filter();
+        }
+    }
+    result
+}
+
+/// `t` has completed the seccomp() or prctl() `install_patched_seccomp_filter()`
+/// returned `result` for. If it asked for a `listener`, the fd it returned
+/// gets a `SeccompNotifyMonitor`.
+fn finish_seccomp_filter(
+    t: &mut RecordTask,
+    entry_regs: &Registers,
+    result: isize,
+    listener: bool,
+) {
+    finish_no_op_syscall(t, entry_regs, result);
+    if listener && result >= 0 {
+        add_seccomp_notify_monitor(t, result as i32);
+    }
+}
+
+/// Check the ioctl() `t` is entering against `ioctl_registry`. Returns
+/// false, after making the syscall a no-op, for requests rd can't record,
+/// like submitting GPU work. Pass the result to `finish_ioctl()` at syscall
+/// exit.
+pub fn prepare_ioctl(t: &mut RecordTask, regs: &Registers) -> bool {
+    let request = regs.arg2() as u32;
+    let errno = match ioctl_action(request).0 {
+        IoctlAction::Deny(errno) => errno,
+        IoctlAction::Record(_) => return true,
+    };
+    log!(
+        LogDebug,
+        "{}: can't record ioctl request {:#x} ({}) on {:?}, failing it with errno {}",
+        t.tid,
+        request,
+        ioctl_handler(request).map_or("?", |h| h.name),
+        t.file_name_of_fd(regs.arg1_signed() as i32),
+        errno
+    );
+    make_syscall_no_op(t, regs);
+    false
+}
+
+/// `t` has completed the ioctl() `prepare_ioctl()` returned `allowed` for.
+/// Record the memory it wrote, as the `ioctl_registry` handler for the
+/// request says, or as its _IOC bits say if rd doesn't know the request.
+pub fn finish_ioctl(t: &mut RecordTask, entry_regs: &Registers, allowed: bool) {
+    let request = entry_regs.arg2() as u32;
+    let (action, guessed) = ioctl_action(request);
+    let record = match action {
+        IoctlAction::Deny(errno) => {
+            debug_assert!(!allowed);
+            return fail_no_op_syscall(t, entry_regs, errno);
+        }
+        IoctlAction::Record(record) => record,
+    };
+    let fd = entry_regs.arg1_signed() as i32;
+    if is_seccomp_listener(t, fd) {
+        finish_seccomp_notify_ioctl(t, entry_regs);
+        return;
+    }
+    if t.regs_ref().syscall_failed() {
+        return;
+    }
+    let call = IoctlCall {
+        fd,
+        request,
+        arg: RemotePtr::from(entry_regs.arg3()),
+        result: t.regs_ref().syscall_result_signed(),
+    };
+    if guessed && should_warn_about_request(request) {
+        log!(
+            LogWarn,
+            "Unknown ioctl request {:#x} on {:?}; recording what its _IOC bits say it writes, \
+             replay may diverge if that's not all",
+            request,
+            t.file_name_of_fd(fd)
+        );
+    }
+    // Handlers may ask for more than the tracee's buffers when it's simpler
+    // than working out what the kernel wrote, see `ioctl_registry::drm`, so a
+    // buffer can run off the end of the tracee's memory.
+    for buf in record(t, &call) {
+        match t.record_remote_fallible(buf.addr, buf.size) {
+            Ok(recorded) if recorded == buf.size => (),
+            result => log!(
+                LogWarn,
+                "Recorded only {} of {} bytes at {} for ioctl request {:#x} on {:?}; \
+                 replay may diverge if the kernel wrote the rest",
+                result.unwrap_or(0),
+                buf.size,
+                buf.addr,
+                request,
+                t.file_name_of_fd(fd)
+            ),
+        }
+    }
+}
+
+/// `t` has completed an ioctl(). If it was on a seccomp listener, record the
+/// notification SECCOMP_IOCTL_NOTIF_RECV wrote and keep track of what was
+/// received and answered.
+pub fn finish_seccomp_notify_ioctl(t: &mut RecordTask, entry_regs: &Registers) {
+    if t.regs_ref().syscall_failed() || !is_seccomp_listener(t, entry_regs.arg1_signed() as i32) {
+        return;
+    }
+    if entry_regs.arg2() as c_ulong == SECCOMP_IOCTL_NOTIF_RECV {
+        t.record_remote(
+            RemotePtr::from(entry_regs.arg3()),
+            size_of::<seccomp_notif>(),
+        );
+    }
+    note_seccomp_notify_ioctl(t, entry_regs);
+}
+
+/// `t` has successfully completed the ioctl() on the seccomp listener
+/// `regs.arg1()`. A fd SECCOMP_IOCTL_NOTIF_ADDFD installed in the notifying
+/// task shares the monitor of the supervisor's fd it copies, if any. Needed
+/// during replay too, after the notification has been written back to
+/// memory.
+pub fn note_seccomp_notify_ioctl(t: &mut dyn Task, regs: &Registers) {
+    let monitor = match t.fd_table().get_monitor(regs.arg1_signed() as i32) {
+        Some(monitor) => monitor,
+        None => return,
+    };
+    match regs.arg2() as c_ulong {
+        SECCOMP_IOCTL_NOTIF_RECV => {
+            let notif: seccomp_notif = read_val_mem(t, RemotePtr::from(regs.arg3()), None);
+            if let Some(m) = monitor.borrow_mut().as_seccomp_notify_monitor_mut() {
+                m.did_recv(notif.id, notif.pid as pid_t);
+            }
+        }
+        SECCOMP_IOCTL_NOTIF_SEND => {
+            let resp: seccomp_notif_resp = read_val_mem(t, RemotePtr::from(regs.arg3()), None);
+            if let Some(m) = monitor.borrow_mut().as_seccomp_notify_monitor_mut() {
+                m.did_answer(resp.id);
+            }
+        }
+        SECCOMP_IOCTL_NOTIF_ADDFD => {
+            let addfd: seccomp_notif_addfd = read_val_mem(t, RemotePtr::from(regs.arg3()), None);
+            let target = match monitor.borrow_mut().as_seccomp_notify_monitor_mut() {
+                Some(m) => m.target(addfd.id),
+                None => None,
+            };
+            let table = match target.and_then(|pid| fd_table_of_process(t, pid)) {
+                Some(table) => table,
+                None => return,
+            };
+            let fd = t.regs_ref().syscall_result_signed() as i32;
+            let src_monitor = t.fd_table().get_monitor(addfd.srcfd as i32);
+            table.borrow_mut().did_import(fd, src_monitor, t);
+        }
+        _ => (),
+    }
+}
+
+/// Whether `fd` of `t` is a seccomp listener.
+pub fn is_seccomp_listener(t: &dyn Task, fd: i32) -> bool {
+    match t.fd_table().get_monitor(fd) {
+        Some(monitor) => monitor.borrow().file_monitor_type() == FileMonitorType::SeccompNotify,
+        None => false,
+    }
+}
+
+/// What a tracee reads from `path` while rd pretends there are `num_cores`
+/// CPUs, or `None` if `path` isn't one of the files programs (and libc's
+/// sysconf(_SC_NPROCESSORS_*)) count CPUs with.
+pub fn virtual_cpu_file_contents(path: &OsStr, num_cores: u32) -> Option<Vec<u8>> {
+    match path.as_bytes() {
+        b"/sys/devices/system/cpu/online"
+        | b"/sys/devices/system/cpu/possible"
+        | b"/sys/devices/system/cpu/present" => Some(cpu_list(num_cores).into_bytes()),
+        b"/proc/cpuinfo" => {
+            let cpuinfo = fs::read_to_string("/proc/cpuinfo").ok()?;
+            Some(truncate_cpuinfo(&cpuinfo, num_cores).into_bytes())
+        }
+        _ => None,
+    }
+}
+
+/// `t` has just opened `fd`. If that's one of the files that tell how many
+/// CPUs there are, swap it for a file with the session's virtual CPU count.
+/// Reads from it are recorded as usual, so replay needs nothing special.
+pub fn maybe_virtualize_cpu_file(t: &mut RecordTask, fd: i32) {
+    let num_cores = t
+        .session()
+        .as_record()
+        .unwrap()
+        .scheduler()
+        .pretend_num_cores();
+    let contents = match virtual_cpu_file_contents(&t.file_name_of_fd(fd), num_cores) {
+        Some(contents) => contents,
+        None => return,
+    };
+    // The tracee opens it by name, so O_TMPFILE won't do. It's removed below,
+    // or with the session's directory if we don't get that far.
+    let mut path = t.session().temp_resources().dir();
+    path.push(format!("/rd-cpu-file-{}-{}", t.tid, fd));
+    if fs::write(&path, contents).is_err() {
+        fatal!("Can't write {:?}", path);
+    }
+
+    let root_dir_fd = t.reserved_root_dir_fd();
+    {
+        let mut remote = AutoRemoteSyscalls::new(t);
+        let arch = remote.arch();
+        let fd_flags = rd_infallible_syscall!(remote, syscall_number_for_fcntl(arch), fd, F_GETFD);
+        let new_fd = {
+            let mut child_path = AutoRestoreMem::push_cstr(&mut remote, path.as_os_str());
+            // skip leading '/' since we want the path to be relative to the root fd
+            let path_addr_val = (child_path.get().unwrap() + 1usize).as_usize();
+            rd_infallible_syscall!(
+                child_path,
+                syscall_number_for_openat(arch),
+                root_dir_fd,
+                path_addr_val,
+                O_RDONLY
+            ) as i32
+        };
+        let flags = if fd_flags as i32 & FD_CLOEXEC != 0 {
+            OFlag::O_CLOEXEC
+        } else {
+            OFlag::empty()
+        };
+        remote.infallible_move_fd(new_fd, fd, flags);
+    }
+    fs::remove_file(&path).ok();
+}
+
+/// `t` has just opened `fd`. If it's on a FUSE filesystem, warn that it may
+/// not be there at replay, or with `--copy-fuse-files` copy it into the
+/// trace while it can still be read.
+pub fn note_fuse_file_open(t: &mut RecordTask, fd: i32) {
+    let path = OsString::from(format!("/proc/{}/fd/{}", t.tid, fd));
+    if !is_fuse_file(&path) {
+        return;
+    }
+    let st = match stat(path.as_os_str()) {
+        Ok(st) => st,
+        Err(_) => return,
+    };
+    let name = t.file_name_of_fd(fd);
+    t.trace_writer_mut().note_fuse_file(&name, &path, &st);
+}
+
+/// `t` has just completed the sched_getaffinity() in `regs`. Before the mask
+/// is recorded, make it say the task may run on CPUs 0 to `num_cores - 1`,
+/// like the files `maybe_virtualize_cpu_file()` fakes.
+pub fn virtualize_sched_getaffinity(t: &mut RecordTask, regs: &Registers) {
+    let size = regs.syscall_result_signed();
+    if size <= 0 {
+        return;
+    }
+    let num_cores = t
+        .session()
+        .as_record()
+        .unwrap()
+        .scheduler()
+        .pretend_num_cores();
+    let mut mask = vec![0u8; size as usize];
+    for cpu in 0..min(num_cores as usize, mask.len() * 8) {
+        mask[cpu / 8] |= 1 << (cpu % 8);
+    }
+    t.write_bytes(RemotePtr::new_from_val(regs.arg3()), &mask);
+}
+
+/// The kernel's format for a list of CPUs, for CPUs 0 to `num_cores - 1`.
+fn cpu_list(num_cores: u32) -> String {
+    if num_cores <= 1 {
+        "0\n".to_owned()
+    } else {
+        format!("0-{}\n", num_cores - 1)
+    }
+}
+
+/// The entries for the first `num_cores` processors of /proc/cpuinfo. We
+/// can't make up entries for processors that aren't there.
+fn truncate_cpuinfo(cpuinfo: &str, num_cores: u32) -> String {
+    cpuinfo
+        .split_terminator("\n\n")
+        .take(num_cores as usize)
+        .map(|entry| format!("{}\n\n", entry))
+        .collect()
+}
+
+/// Do whatever has to happen before the kernel runs the syscall `t` is
+/// entering. Its syscall event has the registers at syscall entry. Returns
+/// whether other tasks may run while `t` is in the syscall.
+pub fn rec_prepare_syscall(t: &mut RecordTask) -> Switchable {
+    let arch = t.ev().syscall_event().arch();
+    rd_arch_function_selfless!(rec_prepare_syscall_arch, arch, t)
+}
+
+fn rec_prepare_syscall_arch<Arch: Architecture>(t: &mut RecordTask) -> Switchable {
+    let sys = t.ev().syscall_event().number;
+    if sys == Arch::CLONE || sys == Arch::CLONE3 || sys == Arch::FORK || sys == Arch::VFORK {
+        prepare_clone::<Arch>(t);
+        // A vfork() parent stays in the kernel until its child has exec'd or
+        // exited, which the child can only do if it runs.
+        return if sys == Arch::VFORK {
+            Switchable::AllowSwitch
+        } else {
+            Switchable::PreventSwitch
+        };
+    }
+    if sys == Arch::EXECVE || sys == Arch::EXECVEAT {
+        t.prepare_exec();
+        return Switchable::PreventSwitch;
+    }
+    let regs = t.regs_ref().clone();
+    if let Some(fd) = reserved_fd_conflict(t, &regs) {
+        migrate_reserved_fd(t, fd);
+    }
+    if is_virtual_clock_syscall::<Arch>(sys) {
+        if let Some(answer) = prepare_virtual_clock(t, &regs) {
+            t.prepared_syscall = Some(PreparedSyscall::VirtualClock(answer));
+            return Switchable::PreventSwitch;
+        }
+    }
+    if prepare_rd_mapping_guard(t, &regs) {
+        t.prepared_syscall = Some(PreparedSyscall::RdMappingGuard);
+        return Switchable::PreventSwitch;
+    }
+    if let Some(fd) = reserved_fd_conflict(t, &regs) {
+        migrate_reserved_fd(t, fd);
+    }
+    if sys == Arch::CLOSE_RANGE {
+        if let Some(ranges) = prepare_close_range(t, &regs) {
+            t.prepared_syscall = Some(PreparedSyscall::CloseRange(ranges));
+            return Switchable::PreventSwitch;
+        }
+    }
+    if sys == Arch::READ {
+        if let Some(len) = prepare_signalfd_read(t, &regs) {
+            t.prepared_syscall = Some(PreparedSyscall::SignalfdRead(len));
+            return Switchable::PreventSwitch;
+        }
+    }
+    if sys == Arch::GETRANDOM || sys == Arch::READ {
+        if let Some(answer) = prepare_seeded_random(t, &regs) {
+            t.prepared_syscall = Some(PreparedSyscall::SeededRandom(answer));
+            return Switchable::PreventSwitch;
+        }
+    }
+    if sys == Arch::RSEQ {
+        t.prepared_syscall = Some(PreparedSyscall::Rseq(prepare_rseq(t, &regs)));
+        return Switchable::PreventSwitch;
+    }
+    if sys == Arch::IO_URING_SETUP {
+        let action = prepare_io_uring_setup(t, &regs);
+        t.prepared_syscall = Some(PreparedSyscall::IoUringSetup(action));
+        return Switchable::PreventSwitch;
+    }
+    if sys == Arch::IO_URING_ENTER {
+        // May wait for completions.
+        let allowed = prepare_io_uring_enter(t, &regs);
+        t.prepared_syscall = Some(PreparedSyscall::IoUringEnter(allowed));
+        return Switchable::AllowSwitch;
+    }
+    if sys == Arch::IO_URING_REGISTER {
+        let allowed = prepare_io_uring_register(t, &regs);
+        t.prepared_syscall = Some(PreparedSyscall::IoUringRegister(allowed));
+        return Switchable::PreventSwitch;
+    }
+    if sys == Arch::IOCTL {
+        // May wait for a device or a seccomp notification.
+        let allowed = prepare_ioctl(t, &regs);
+        t.prepared_syscall = Some(PreparedSyscall::Ioctl(allowed));
+        return Switchable::AllowSwitch;
+    }
+    if let Some(prog_addr) = seccomp_filter_prog_addr::<Arch>(sys, &regs) {
+        let result = install_patched_seccomp_filter::<Arch>(t, &regs, prog_addr);
+        let listener =
+            sys == Arch::SECCOMP && regs.arg2() & SECCOMP_FILTER_FLAG_NEW_LISTENER as usize != 0;
+        t.prepared_syscall = Some(PreparedSyscall::SeccompFilter { result, listener });
+        return Switchable::PreventSwitch;
+    }
+    if sys == Arch::KILL || sys == Arch::TKILL || sys == Arch::RT_SIGQUEUEINFO {
+        return prepare_kill(t, regs.arg1_signed() as pid_t, regs.arg2_signed() as i32);
+    }
+    if sys == Arch::TGKILL || sys == Arch::RT_TGSIGQUEUEINFO {
+        return prepare_kill(t, regs.arg2_signed() as pid_t, regs.arg3_signed() as i32);
+    }
+    if sys == Arch::PIDFD_SEND_SIGNAL {
+        let (switchable, emulated) = prepare_pidfd_send_signal(t, &regs);
+        t.prepared_syscall = Some(PreparedSyscall::PidfdSendSignal(emulated));
+        return switchable;
+    }
+    if sys == Arch::MEMFD_SECRET {
+        prepare_memfd_secret(t, &regs);
+        t.prepared_syscall = Some(PreparedSyscall::MemfdSecret);
+        return Switchable::PreventSwitch;
+    }
+    if sys == Arch::KEYCTL {
+        let allowed = prepare_keyctl(t, &regs);
+        t.prepared_syscall = Some(PreparedSyscall::Keyctl(allowed));
+        return Switchable::PreventSwitch;
+    }
+    if sys == Arch::PIDFD_GETFD {
+        let allowed = prepare_pidfd_getfd(t, &regs);
+        t.prepared_syscall = Some(PreparedSyscall::PidfdGetfd(allowed));
+        return Switchable::PreventSwitch;
+    }
+    // The syscall may block on another tracee.
+    Switchable::AllowSwitch
+}
+
+/// What `rec_prepare_syscall()` did to a syscall, with whatever its
+/// `finish_*()` counterpart needs at the syscall's exit.
+pub enum PreparedSyscall {
+    Rseq(isize),
+    IoUringSetup(IoUringSetupAction),
+    IoUringEnter(bool),
+    IoUringRegister(bool),
+    Ioctl(bool),
+    PidfdSendSignal(Option<siginfo_t>),
+    PidfdGetfd(bool),
+    SeccompFilter { result: isize, listener: bool },
+    VirtualClock(VirtualClockAnswer),
+    SeededRandom((RemotePtr<Void>, isize)),
+    RdMappingGuard,
+    SignalfdRead(usize),
+    Keyctl(bool),
+    MemfdSecret,
+    CloseRange(Vec<(u32, u32)>),
+}
+
+fn finish_prepared_syscall(t: &mut RecordTask, entry_regs: &Registers, prepared: PreparedSyscall) {
+    match prepared {
+        PreparedSyscall::Rseq(result) => finish_rseq(t, entry_regs, result),
+        PreparedSyscall::IoUringSetup(action) => finish_io_uring_setup(t, entry_regs, action),
+        PreparedSyscall::IoUringEnter(allowed) => finish_io_uring_enter(t, entry_regs, allowed),
+        PreparedSyscall::IoUringRegister(allowed) => {
+            finish_io_uring_register(t, entry_regs, allowed)
+        }
+        PreparedSyscall::Ioctl(allowed) => finish_ioctl(t, entry_regs, allowed),
+        PreparedSyscall::PidfdSendSignal(emulated) => {
+            finish_pidfd_send_signal(t, entry_regs, emulated)
+        }
+        PreparedSyscall::PidfdGetfd(allowed) => finish_pidfd_getfd(t, entry_regs, allowed),
+        PreparedSyscall::SeccompFilter { result, listener } => {
+            finish_seccomp_filter(t, entry_regs, result, listener)
+        }
+        PreparedSyscall::VirtualClock(answer) => finish_virtual_clock(t, entry_regs, answer),
+        PreparedSyscall::SeededRandom(answer) => finish_seeded_random(t, entry_regs, answer),
+        PreparedSyscall::RdMappingGuard => finish_rd_mapping_guard(t),
+        PreparedSyscall::SignalfdRead(len) => finish_signalfd_read(t, entry_regs, len),
+        PreparedSyscall::Keyctl(allowed) => finish_keyctl(t, entry_regs, allowed),
+        PreparedSyscall::MemfdSecret => finish_memfd_secret(t, entry_regs),
+        PreparedSyscall::CloseRange(ranges) => finish_close_range(t, entry_regs, &ranges),
+    }
+}
+
+/// Run the clone(), clone3(), fork() or vfork() `t` is entering until the
+/// kernel has created the new task, and set that task up. If the kernel
+/// fails the syscall instead, `t` is left at its exit with
+/// `failed_during_preparation` set.
+///
+/// Everything recorded here belongs to the syscall's entry, where replay
+/// creates the new task.
+fn prepare_clone<Arch: Architecture>(t: &mut RecordTask) {
+    let entry_regs = t.ev().syscall_event().regs.clone();
+    let sys = entry_regs.original_syscallno() as i32;
+    let mut maybe_clone3_args: Option<clone_args> = None;
+    let flags = if sys == Arch::CLONE {
+        // A CLONE_UNTRACED child would escape rd.
+        let flags = entry_regs.arg1() as i32;
+        if flags & CLONE_UNTRACED != 0 {
+            let mut r = entry_regs.clone();
+            r.set_arg1((flags & !CLONE_UNTRACED) as usize);
+            t.set_regs(&r);
+        }
+        flags & !CLONE_UNTRACED
+    } else if sys == Arch::CLONE3 {
+        maybe_clone3_args = prepare_clone3(t, &entry_regs);
+        maybe_clone3_args
+            .as_ref()
+            .map_or(0, |args| args.flags as i32 & !CLONE_UNTRACED)
+    } else if sys == Arch::VFORK {
+        CLONE_VM | CLONE_VFORK
+    } else {
+        0
+    };
+
+    loop {
+        t.resume_execution(
+            ResumeRequest::ResumeSyscall,
+            WaitRequest::ResumeWait,
+            TicksRequest::ResumeNoTicks,
+            None,
+        );
+        let event = t.maybe_ptrace_event();
+        if event == PTRACE_EVENT_CLONE || event == PTRACE_EVENT_FORK || event == PTRACE_EVENT_VFORK
+        {
+            break;
+        }
+        if event == PTRACE_EVENT_SECCOMP {
+            on_syscall_entry_stop(t, SyscallEntryStop::Seccomp);
+            continue;
+        }
+        if t.maybe_stop_sig().is_sig() {
+            // It's delivered once the syscall is recorded.
+            t.stash_sig();
+            continue;
+        }
+        if is_duplicate_syscall_entry_stop(t) {
+            on_syscall_entry_stop(t, SyscallEntryStop::PtraceSyscall);
+            continue;
+        }
+        ed_assert!(t, t.status().is_syscall(), "Unexpected {}", t.status());
+        // The syscall-exit stop: the kernel failed the syscall.
+        if let Some(args) = &maybe_clone3_args {
+            finish_clone3(t, None, &entry_regs, args);
+        } else if sys == Arch::CLONE {
+            let mut r = t.regs_ref().clone();
+            r.set_arg1(entry_regs.arg1());
+            t.set_regs(&r);
+        }
+        t.ev_mut().syscall_event_mut().failed_during_preparation = true;
+        return;
+    }
+
+    let new_tid = if flags & CLONE_THREAD != 0 {
+        t.find_newborn_thread()
+    } else {
+        let child_parent = if flags & CLONE_PARENT != 0 {
+            t.get_parent_pid()
+        } else {
+            t.real_tgid()
+        };
+        t.find_newborn_process(child_parent)
+    };
+    let params = match &maybe_clone3_args {
+        Some(args) => clone3_parameters(args),
+        None if sys == Arch::CLONE => extract_clone_parameters(t),
+        None => CloneParameters::default(),
+    };
+    let session = t.session();
+    let new_task_rc = session.clone_task(
+        t,
+        clone_flags_to_task_flags(flags),
+        params.stack,
+        params.tls,
+        params.ctid,
+        new_tid,
+        None,
+    );
+    let mut new_task_ref = new_task_rc.borrow_mut();
+    let new_task = new_task_ref.as_record_task_mut().unwrap();
+
+    // Replay sets up the new task's registers the same way.
+    let mut new_r = new_task.regs_ref().clone();
+    new_r.set_original_syscallno(entry_regs.original_syscallno());
+    new_r.set_arg1(entry_regs.arg1());
+    new_r.set_arg2(entry_regs.arg2());
+    new_task.set_regs(&new_r);
+    new_task.canonicalize_regs(Arch::arch());
+    let termination_signal = match &maybe_clone3_args {
+        Some(args) => args.exit_signal as i32,
+        None if sys == Arch::CLONE => entry_regs.arg1() as i32 & 0xff,
+        None => SIGCHLD,
+    };
+    new_task.set_termination_signal(termination_signal);
+
+    t.trace_writer_mut()
+        .write_task_event(&TraceTaskEvent::for_clone(
+            new_task.tid,
+            t.tid,
+            new_task.own_namespace_tid(),
+            flags,
+        ));
+    if let Some(args) = &maybe_clone3_args {
+        finish_clone3(t, Some(&mut *new_task), &entry_regs, args);
+    } else if sys == Arch::CLONE {
+        // In the order `finish_clone3()` records them.
+        t.record_remote_even_if_null(RemotePtr::cast(params.ptid), size_of::<i32>());
+        if Arch::CLONE_TLS_TYPE == CloneTLSType::UserDescPointer {
+            t.record_remote_even_if_null(params.tls, size_of::<user_desc>());
+            new_task.record_remote_even_if_null(params.tls, size_of::<user_desc>());
+        }
+        new_task.record_remote_even_if_null(RemotePtr::cast(params.ptid), size_of::<i32>());
+        new_task.record_remote_even_if_null(RemotePtr::cast(params.ctid), size_of::<i32>());
+
+        let mut r = t.regs_ref().clone();
+        r.set_arg1(entry_regs.arg1());
+        t.set_regs(&r);
+    }
+    let pidfd_addr = clone_pidfd_addr(flags as u64, maybe_clone3_args.as_ref(), &params);
+    let new_rec_tid = new_task.rec_tid;
+    init_scratch_memory(new_task);
+    drop(new_task_ref);
+    if !pidfd_addr.is_null() {
+        note_clone_pidfd(t, pidfd_addr, new_rec_tid);
+    }
+
+    if sys == Arch::VFORK {
+        session.as_record().unwrap().note_vfork(t, new_tid);
+    }
+}
+
+/// Map the scratch area rd uses for `t`'s syscall outparameters and record
+/// the mapping, which replay maps at the same address. The address is picked
+/// by `choose_rd_mapping_address()` unless its window is full.
+fn init_scratch_memory(t: &mut RecordTask) {
+    let size = SCRATCH_SIZE_PAGES * page_size();
+    let prot = ProtFlags::PROT_READ | ProtFlags::PROT_WRITE;
+    let flags = MapFlags::MAP_PRIVATE | MapFlags::MAP_ANONYMOUS;
+    let chosen = choose_rd_mapping_address(t.vm(), RdMappingKind::Scratch, t.rec_tid, size);
+    let slots_skipped = chosen.map_or(0, |(_, skipped)| skipped as u32);
+    let addr = {
+        let mut remote = AutoRemoteSyscalls::new(t);
+        match chosen {
+            // Nothing is mapped there, so MAP_FIXED doesn't clobber anything.
+            Some((addr, _)) => remote.infallible_mmap_syscall(
+                Some(addr),
+                size,
+                prot,
+                flags | MapFlags::MAP_FIXED,
+                -1,
+                0,
+            ),
+            None => remote.infallible_mmap_syscall(None, size, prot, flags, -1, 0),
+        }
+    };
+    if slots_skipped > 0 {
+        log!(
+            LogDebug,
+            "{}: scratch at {} after skipping {} taken slots",
+            t.tid,
+            addr,
+            slots_skipped
+        );
+    }
+    t.scratch_ptr = addr;
+    t.scratch_size = size;
+    t.setup_preload_thread_locals();
+
+    let km = t.vm().map(
+        t,
+        addr,
+        size,
+        prot,
+        flags,
+        0,
+        OsStr::new(""),
+        KernelMapping::NO_DEVICE,
+        KernelMapping::NO_INODE,
+        None,
+        None,
+        None,
+        None,
+        None,
+    );
+    let record_in_trace = t.trace_writer_mut().write_mapped_region(
+        t,
+        &km,
+        &km.fake_stat(),
+        &[],
+        Some(MappingOrigin::RdBufferMapping { slots_skipped }),
+        None,
+    );
+    ed_assert!(t, record_in_trace == RecordInTrace::DontRecordInTrace);
+}
+
+/// Record what the syscall `t` has just completed did. Its syscall event has
+/// the registers at syscall entry.
+pub fn rec_process_syscall(t: &mut RecordTask) {
+    let arch = t.ev().syscall_event().arch();
+    rd_arch_function_selfless!(rec_process_syscall_arch, arch, t)
+}
+
+fn rec_process_syscall_arch<Arch: Architecture>(t: &mut RecordTask) {
+    let sys = t.ev().syscall_event().number;
+    let entry_regs = t.ev().syscall_event().regs.clone();
+    let mut answered_by_rd = false;
+    if let Some(prepared) = t.prepared_syscall.take() {
+        // These already recorded what they wrote.
+        answered_by_rd = matches!(
+            prepared,
+            PreparedSyscall::VirtualClock(_) | PreparedSyscall::SeededRandom(_)
+        );
+        finish_prepared_syscall(t, &entry_regs, prepared);
+    }
+    let regs = t.regs_ref().clone();
+    // Keeps our view of the tracee's mappings, signal handlers etc. up to
+    // date, e.g. for munmap() and mprotect(), which replay executes.
+    t.on_syscall_exit(sys, Arch::arch(), &regs);
+
+    if sys == Arch::EXECVE || sys == Arch::EXECVEAT {
+        process_execve(t);
+        return;
+    }
+    if !answered_by_rd {
+        // Sleeps write back the remaining time when interrupted, i.e. when
+        // they fail.
+        record_remaining_time(t, &entry_regs);
+    }
+    // Even when the syscall failed: a file that wasn't there is often what
+    // `rd grep` is looking for.
+    record_path_args(t, sys, &entry_regs);
+    if regs.syscall_failed() || answered_by_rd {
+        return;
+    }
+    if sys == Arch::BRK {
+        process_brk(t);
+    } else if sys == Arch::MMAP && Arch::MMAP_SEMANTICS == MmapCallingSemantics::RegisterArguments {
+        process_mmap(t, &entry_regs, (entry_regs.arg6() / page_size()) as u64);
+    } else if sys == Arch::MMAP2 {
+        process_mmap(t, &entry_regs, entry_regs.arg6() as u64);
+    } else if sys == Arch::READ || sys == Arch::PREAD64 {
+        record_read(t, &entry_regs);
+        if sys == Arch::READ {
+            finish_timerfd_read(t, &entry_regs);
+        }
+    } else if sys == Arch::OPEN || sys == Arch::OPENAT || sys == Arch::OPENAT2 {
+        process_open(t, regs.syscall_result_signed() as i32);
+    } else if sys == Arch::LANDLOCK_CREATE_RULESET {
+        finish_landlock_create_ruleset(t, &entry_regs);
+    } else if sys == Arch::SIGNALFD || sys == Arch::SIGNALFD4 {
+        finish_signalfd(t, &entry_regs);
+    } else if sys == Arch::TIMERFD_CREATE {
+        finish_timerfd_create(t);
+    } else if sys == Arch::PIDFD_OPEN {
+        finish_pidfd_open(t);
+    } else if sys == Arch::GETDENTS || sys == Arch::GETDENTS64 {
+        record_getdents(t, &regs);
+    } else if sys == Arch::STATX {
+        record_statx(t, &regs);
+    } else if sys == Arch::SCHED_GETAFFINITY {
+        virtualize_sched_getaffinity(t, &regs);
+        t.record_remote(RemotePtr::new_from_val(regs.arg3()), regs.syscall_result());
+    } else {
+        for (addr, size) in syscall_outparams::<Arch>(sys, &entry_regs, regs.syscall_result()) {
+            t.record_remote(addr, size);
+        }
+    }
+}
+
+/// `t` has just opened `fd` with open(), openat() or openat2(). Monitor it
+/// if rd has to treat it specially; replay does the same with the files
+/// listed in the syscall event, see `replay_syscall::handle_opened_files()`.
+fn process_open(t: &mut RecordTask, fd: i32) {
+    maybe_virtualize_cpu_file(t, fd);
+    note_fuse_file_open(t, fd);
+    // In the order replay checks them.
+    let opened = maybe_monitor_random_device(t, fd).or_else(|| maybe_capture_reads(t, fd));
+    if let Some(opened) = opened {
+        t.ev_mut().syscall_event_mut().opened.push(opened);
+    }
+}
+
+/// The addresses of the paths passed to the syscall `sys`, entered with
+/// `regs`, for the syscalls that look up paths without opening them. They're
+/// recorded with the syscall so `rd grep` can find them; replay writes back
+/// the bytes that were already there.
+pub fn syscall_path_args(sys: i32, arch: SupportedArch, regs: &Registers) -> Vec<usize> {
+    if kernel_abi::is_rename_syscall(sys, arch) {
+        vec![regs.arg1(), regs.arg2()]
+    } else if kernel_abi::is_renameat_syscall(sys, arch)
+        || kernel_abi::is_renameat2_syscall(sys, arch)
+    {
+        vec![regs.arg2(), regs.arg4()]
+    } else if kernel_abi::is_stat_syscall(sys, arch)
+        || kernel_abi::is_lstat_syscall(sys, arch)
+        || kernel_abi::is_stat64_syscall(sys, arch)
+        || kernel_abi::is_lstat64_syscall(sys, arch)
+        || kernel_abi::is_access_syscall(sys, arch)
+        || kernel_abi::is_readlink_syscall(sys, arch)
+        || kernel_abi::is_unlink_syscall(sys, arch)
+        || kernel_abi::is_mkdir_syscall(sys, arch)
+        || kernel_abi::is_rmdir_syscall(sys, arch)
+    {
+        vec![regs.arg1()]
+    } else if kernel_abi::is_fstatat64_syscall(sys, arch)
+        || kernel_abi::is_statx_syscall(sys, arch)
+        || kernel_abi::is_faccessat_syscall(sys, arch)
+        || kernel_abi::is_faccessat2_syscall(sys, arch)
+        || kernel_abi::is_readlinkat_syscall(sys, arch)
+        || kernel_abi::is_unlinkat_syscall(sys, arch)
+        || kernel_abi::is_mkdirat_syscall(sys, arch)
+    {
+        vec![regs.arg2()]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Record the paths `t` passed to the syscall `sys` it entered with `regs`,
+/// see `syscall_path_args()`.
+fn record_path_args(t: &mut RecordTask, sys: i32, regs: &Registers) {
+    for addr in syscall_path_args(sys, regs.arch(), regs) {
+        let addr = RemotePtr::<u8>::new_from_val(addr);
+        if let Ok(path) = read_c_str_fallible(t, addr, Some(PATH_MAX as usize - 1)) {
+            t.record_remote(RemotePtr::cast(addr), path.as_bytes_with_nul().len());
+        }
+    }
+}
+
+/// The memory the syscall `sys`, entered with `regs`, wrote when it
+/// returned `result`, for the syscalls whose outparameters are plain
+/// buffers. Syscalls not listed here don't have their effects on memory
+/// recorded yet.
+fn syscall_outparams<Arch: Architecture>(
+    sys: i32,
+    regs: &Registers,
+    result: usize,
+) -> Vec<(RemotePtr<Void>, usize)> {
+    let word = size_of::<Arch::unsigned_long>();
+    let stat_size = match Arch::arch() {
+        SupportedArch::X64 => size_of::<libc::stat>(),
+        SupportedArch::X86 => size_of::<x86::stat64>(),
+    };
+    let addr = |arg: usize| RemotePtr::<Void>::new_from_val(arg);
+    if (Arch::arch() == SupportedArch::X64
+        && (sys == Arch::STAT || sys == Arch::LSTAT || sys == Arch::FSTAT))
+        || sys == Arch::STAT64
+        || sys == Arch::LSTAT64
+        || sys == Arch::FSTAT64
+    {
+        vec![(addr(regs.arg2()), stat_size)]
+    } else if sys == Arch::FSTATAT64 {
+        vec![(addr(regs.arg3()), stat_size)]
+    } else if sys == Arch::UNAME {
+        vec![(addr(regs.arg1()), size_of::<libc::utsname>())]
+    } else if sys == Arch::CLOCK_GETTIME {
+        // A struct timespec.
+        vec![(addr(regs.arg2()), 2 * word)]
+    } else if sys == Arch::CLOCK_GETTIME64 {
+        vec![(addr(regs.arg2()), 16)]
+    } else if sys == Arch::GETTIMEOFDAY {
+        // A struct timeval and a struct timezone.
+        vec![(addr(regs.arg1()), 2 * word), (addr(regs.arg2()), 8)]
+    } else if sys == Arch::RT_SIGACTION {
+        vec![(addr(regs.arg3()), size_of::<Arch::kernel_sigaction>())]
+    } else if sys == Arch::RT_SIGPROCMASK {
+        vec![(addr(regs.arg3()), regs.arg4())]
+    } else if sys == Arch::PIPE || sys == Arch::PIPE2 {
+        vec![(addr(regs.arg1()), 2 * size_of::<i32>())]
+    } else if sys == Arch::GETCWD {
+        vec![(addr(regs.arg1()), result)]
+    } else if sys == Arch::READLINK {
+        vec![(addr(regs.arg2()), result)]
+    } else if sys == Arch::READLINKAT {
+        vec![(addr(regs.arg3()), result)]
+    } else if sys == Arch::WAIT4 {
+        // The status and a struct rusage, which is 18 words.
+        vec![
+            (addr(regs.arg2()), size_of::<i32>()),
+            (addr(regs.arg4()), 18 * word),
+        ]
+    } else if sys == Arch::GETRLIMIT || sys == Arch::UGETRLIMIT {
+        vec![(addr(regs.arg2()), 2 * word)]
+    } else if sys == Arch::PRLIMIT64 {
+        vec![(addr(regs.arg4()), 16)]
+    } else {
+        Vec::new()
+    }
+}
+
+/// `t` has just completed an execve() or execveat(). If it succeeded, record
+/// the new address space: a task event for the exec and a mapping record
+/// for each mapping, the stack first, with the data replay can't get from
+/// the mapped files. Replay reads them in `replay_syscall::process_execve()`.
+fn process_execve(t: &mut RecordTask) {
+    if t.regs_ref().syscall_failed() {
+        return;
+    }
+    t.post_exec_syscall();
+    let fds_to_close = t.fd_table_shr_ptr().borrow_mut().fds_to_close_after_exec(t);
+    t.ev_mut().syscall_event_mut().exec_fds_to_close = fds_to_close;
+
+    let mut stack = None;
+    let mut vvar = None;
+    let mut kms: Vec<KernelMapping> = Vec::new();
+    for (_, m) in &t.vm().maps() {
+        let km = &m.map;
+        if km.is_stack() {
+            stack = Some(km.clone());
+        } else if km.is_vvar() {
+            vvar = Some(km.clone());
+        } else if !km.is_vsyscall()
+            && km.start() != AddressSpace::rd_page_start()
+            && km.start() != AddressSpace::preload_thread_locals_start()
+        {
+            // Replay maps the rd page and the thread locals itself.
+            kms.push(km.clone());
+        }
+    }
+    if let Some(vvar) = vvar {
+        // Replay can't reproduce [vvar], so the tracee has to do without.
+        let arch = t.arch();
+        let mut remote = AutoRemoteSyscalls::new(t);
+        rd_infallible_syscall!(
+            remote,
+            syscall_number_for_munmap(arch),
+            vvar.start().as_usize(),
+            vvar.size()
+        );
+        remote
+            .task()
+            .vm_shr_ptr()
+            .unmap(remote.task(), vvar.start(), vvar.size());
+    }
+
+    let stack = match stack {
+        Some(stack) => stack,
+        None => {
+            ed_assert!(t, false, "No stack after exec");
+            unreachable!()
+        }
+    };
+    let record_in_trace = t.trace_writer_mut().write_mapped_region(
+        t,
+        &stack,
+        &stack.fake_stat(),
+        &[],
+        Some(MappingOrigin::ExecMapping),
+        None,
+    );
+    ed_assert!(t, record_in_trace == RecordInTrace::RecordInTrace);
+    t.record_remote(stack.start(), stack.size());
+
+    let exe = fs::read_link(format!("/proc/{}/exe", t.tid))
+        .map(|p| p.into_os_string())
+        .unwrap_or_default();
+    let mut exe_base = RemotePtr::null();
+    for km in &kms {
+        if km.fsname() == exe.as_os_str() && (exe_base.is_null() || km.start() < exe_base) {
+            exe_base = km.start();
+        }
+        let st = if km.fsname().as_bytes().starts_with(b"/") {
+            stat(km.fsname()).ok()
+        } else {
+            None
+        };
+        let st = st.unwrap_or_else(|| unsafe { zeroed() });
+        // How much of the mapping is backed by the file.
+        let file_bytes = if st.st_size > 0 {
+            min(
+                km.size() as u64,
+                (st.st_size as u64).saturating_sub(km.file_offset_bytes()),
+            ) as usize
+        } else {
+            km.size()
+        };
+        let record_in_trace = t.trace_writer_mut().write_mapped_region(
+            t,
+            km,
+            &st,
+            &[],
+            Some(MappingOrigin::ExecMapping),
+            None,
+        );
+        if !km.prot().contains(ProtFlags::PROT_READ) {
+            continue;
+        }
+        if record_in_trace == RecordInTrace::RecordInTrace {
+            t.record_remote(km.start(), file_bytes);
+        } else if km.flags().contains(MapFlags::MAP_PRIVATE)
+            && km.prot().contains(ProtFlags::PROT_WRITE)
+        {
+            // The ELF loader zeroes the end of the last page of a data
+            // segment, past its file data. Mapping the file during replay
+            // doesn't.
+            t.record_remote(km.end() - page_size(), page_size());
+        }
+    }
+
+    let event = t.exec_task_event(exe_base);
+    t.trace_writer_mut().write_task_event(&event);
+    init_scratch_memory(t);
+}
+
+/// `t` has just completed a brk(). Record the part of the heap it mapped
+/// or unmapped.
+fn process_brk(t: &mut RecordTask) {
+    let brk = RemotePtr::<Void>::new_from_val(t.regs_ref().syscall_result());
+    let old_brk = ceil_page_size(t.vm().current_brk());
+    let new_brk = ceil_page_size(brk);
+    let km = if old_brk < new_brk {
+        // The kernel's view of the heap is the only place to get its
+        // protection from.
+        AddressSpace::read_kernel_mapping(t, old_brk).subrange(old_brk, new_brk)
+    } else {
+        // A mapping without flags tells replay to unmap the range.
+        KernelMapping::new_with_opts(
+            new_brk,
+            old_brk,
+            OsStr::new(""),
+            KernelMapping::NO_DEVICE,
+            KernelMapping::NO_INODE,
+            ProtFlags::empty(),
+            MapFlags::empty(),
+            0,
+        )
+    };
+    let record_in_trace =
+        t.trace_writer_mut()
+            .write_mapped_region(t, &km, &km.fake_stat(), &[], None, None);
+    ed_assert!(t, record_in_trace == RecordInTrace::DontRecordInTrace);
+    t.vm().brk(t, brk, km.prot());
+}
+
+/// `t` has just completed an mmap() or mmap2(), entered with `entry_regs`,
+/// that maps `offset_pages` pages into the file. Record the new mapping and
+/// the data replay can't get from the file.
+fn process_mmap(t: &mut RecordTask, entry_regs: &Registers, offset_pages: u64) {
+    let addr = RemotePtr::<Void>::new_from_val(t.regs_ref().syscall_result());
+    let size = ceil_page_size(entry_regs.arg2());
+    let prot = ProtFlags::from_bits_truncate(entry_regs.arg3() as i32);
+    let flags = MapFlags::from_bits_truncate(entry_regs.arg4() as i32);
+    if flags.contains(MapFlags::MAP_ANONYMOUS) {
+        record_anonymous_mmap(t, addr, size, prot, flags);
+        return;
+    }
+
+    let fd = entry_regs.arg5_signed() as i32;
+    let offset = offset_pages * page_size() as u64;
+    let st = t.stat_fd(fd);
+    let file_name = t.file_name_of_fd(fd);
+    let km = t.vm().map(
+        t,
+        addr,
+        size,
+        prot,
+        flags,
+        offset,
+        &file_name,
+        st.st_dev,
+        st.st_ino,
+        Some(st),
+        None,
+        None,
+        None,
+        None,
+    );
+    let record_in_trace = t
+        .trace_writer_mut()
+        .write_mapped_region(t, &km, &st, &[], None, None);
+    if record_in_trace == RecordInTrace::RecordInTrace && prot.contains(ProtFlags::PROT_READ) {
+        // Device files may be mappable but have no size.
+        let size = if st.st_size > 0 {
+            min(size as u64, (st.st_size as u64).saturating_sub(offset)) as usize
+        } else {
+            size
+        };
+        t.record_remote(addr, size);
+    }
+    note_io_uring_mmap(t, fd, offset, addr);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{arch::X64Arch, kernel_supplement::KEYCTL_REVOKE};
+
+    #[test]
+    fn virtual_cpu_lists() {
+        assert_eq!(cpu_list(1), "0\n");
+        assert_eq!(cpu_list(4), "0-3\n");
+        assert_eq!(
+            truncate_cpuinfo("processor\t: 0\n\nprocessor\t: 1\n\n", 1),
+            "processor\t: 0\n\n"
+        );
+    }
+
+    #[test]
+    fn close_range_around_reserved_fds() {
+        assert_eq!(
+            close_range_gaps(3, !0, &[100, 1000]),
+            vec![(3, 99), (101, 999), (1001, !0)]
+        );
+        assert_eq!(close_range_gaps(100, 101, &[100, 101]), vec![]);
+        assert_eq!(close_range_gaps(100, 200, &[100]), vec![(101, 200)]);
+    }
+
+    #[test]
+    fn clone_pidfd_addresses() {
+        let params = CloneParameters {
+            ptid: RemotePtr::new_from_val(0x1000),
+            ..Default::default()
+        };
+        let mut args: clone_args = unsafe { zeroed() };
+        args.pidfd = 0x2000;
+        let pidfd = CLONE_PIDFD as u64;
+        assert!(clone_pidfd_addr(0, None, &params).is_null());
+        assert!(clone_pidfd_addr(0, Some(&args), &params).is_null());
+        assert_eq!(clone_pidfd_addr(pidfd, None, &params).as_usize(), 0x1000);
+        assert_eq!(
+            clone_pidfd_addr(pidfd, Some(&args), &params).as_usize(),
+            0x2000
+        );
+    }
+
+    #[test]
+    fn seccomp_filter_installs() {
+        let mut regs = Registers::new(SupportedArch::X64);
+        regs.set_arg1(SECCOMP_SET_MODE_FILTER as usize);
+        regs.set_arg3(0x3000);
+        let prog = seccomp_filter_prog_addr::<X64Arch>(X64Arch::SECCOMP, &regs);
+        assert_eq!(prog.map(|addr| addr.as_usize()), Some(0x3000));
+        assert!(seccomp_filter_prog_addr::<X64Arch>(X64Arch::PRCTL, &regs).is_none());
+
+        regs.set_arg1(PR_SET_SECCOMP as usize);
+        regs.set_arg2(SECCOMP_MODE_FILTER as usize);
+        let prog = seccomp_filter_prog_addr::<X64Arch>(X64Arch::PRCTL, &regs);
+        assert_eq!(prog.map(|addr| addr.as_usize()), Some(0x3000));
+        assert!(seccomp_filter_prog_addr::<X64Arch>(X64Arch::SECCOMP, &regs).is_none());
+    }
+
+    #[test]
+    fn rd_mapping_guard_ranges() {
+        let range = |start: usize, end: usize| {
+            MemoryRange::from_range(RemotePtr::new_from_val(start), RemotePtr::new_from_val(end))
+        };
+        let mut regs = Registers::new(SupportedArch::X64);
+        regs.set_original_syscallno(X64Arch::MMAP as isize);
+        regs.set_arg1(0x7000_0000);
+        regs.set_arg2(0x2000);
+        regs.set_arg4(MapFlags::MAP_PRIVATE.bits() as usize);
+        assert!(guarded_ranges_arch::<X64Arch>(&regs, None).is_empty());
+        regs.set_arg4((MapFlags::MAP_PRIVATE | MapFlags::MAP_FIXED).bits() as usize);
+        assert_eq!(
+            guarded_ranges_arch::<X64Arch>(&regs, None),
+            vec![range(0x7000_0000, 0x7000_2000)]
+        );
+
+        regs.set_original_syscallno(X64Arch::MREMAP as isize);
+        regs.set_arg3(0x1000);
+        regs.set_arg4(MREMAP_FIXED as usize);
+        regs.set_arg5(0x5000_0000);
+        assert_eq!(
+            guarded_ranges_arch::<X64Arch>(&regs, None),
+            vec![
+                range(0x7000_0000, 0x7000_2000),
+                range(0x5000_0000, 0x5000_1000)
+            ]
+        );
+
+        // Old-style x86 mmap() passes its arguments in a struct.
+        let mut regs = Registers::new(SupportedArch::X86);
+        regs.set_original_syscallno(X86Arch::MMAP as isize);
+        let mut args = x86::mmap_args::default();
+        args.addr = RemotePtr::<u8>::new_from_val(0x7000_0000).into();
+        args.len = 0x1000;
+        args.flags = (MapFlags::MAP_PRIVATE | MapFlags::MAP_FIXED).bits();
+        assert_eq!(
+            guarded_ranges_arch::<X86Arch>(&regs, Some(args)),
+            vec![range(0x7000_0000, 0x7000_1000)]
+        );
+        args.flags = MapFlags::MAP_PRIVATE.bits();
+        assert!(guarded_ranges_arch::<X86Arch>(&regs, Some(args)).is_empty());
+        assert!(guarded_ranges_arch::<X86Arch>(&regs, None).is_empty());
+    }
+
+    #[test]
+    fn fds_closed_by_syscalls() {
+        let mut regs = Registers::new(SupportedArch::X64);
+        regs.set_arg1(3);
+        regs.set_arg2(1000);
+        regs.set_original_syscallno(X64Arch::CLOSE as isize);
+        assert_eq!(closed_fd_arch::<X64Arch>(&regs), Some(3));
+        regs.set_original_syscallno(X64Arch::DUP2 as isize);
+        assert_eq!(closed_fd_arch::<X64Arch>(&regs), Some(1000));
+        regs.set_original_syscallno(X64Arch::DUP3 as isize);
+        assert_eq!(closed_fd_arch::<X64Arch>(&regs), Some(1000));
+        // dup() always gets a free fd.
+        regs.set_original_syscallno(X64Arch::DUP as isize);
+        assert_eq!(closed_fd_arch::<X64Arch>(&regs), None);
+    }
+
+    #[test]
+    fn syscall_entry_stop_orderings() {
+        let seccomp = SyscallEntryStop::Seccomp;
+        let syscall = SyscallEntryStop::PtraceSyscall;
+        let unknown = PtraceSyscallSeccompOrdering::SyscallBeforeSeccompUnknown;
+        let seccomp_first = PtraceSyscallSeccompOrdering::SeccompBeforeSyscall;
+        let syscall_first = PtraceSyscallSeccompOrdering::SyscallBeforeSeccomp;
+        assert_eq!(
+            learned_stop_ordering(unknown, seccomp),
+            Ok(Some(seccomp_first))
+        );
+        assert_eq!(
+            learned_stop_ordering(unknown, syscall),
+            Ok(Some(syscall_first))
+        );
+        assert_eq!(learned_stop_ordering(seccomp_first, seccomp), Ok(None));
+        assert_eq!(learned_stop_ordering(syscall_first, syscall), Ok(None));
+        assert_eq!(learned_stop_ordering(seccomp_first, syscall), Err(()));
+        assert_eq!(learned_stop_ordering(syscall_first, seccomp), Err(()));
+    }
+
+    #[test]
+    fn keyctl_outparams() {
+        let mut regs = Registers::new(SupportedArch::X64);
+        regs.set_arg1(KEYCTL_READ as usize);
+        regs.set_arg3(0x2000);
+        regs.set_arg4(64);
+        // The key is bigger than the buffer.
+        assert_eq!(
+            keyctl_outparam(&regs, 100),
+            Some((RemotePtr::new_from_val(0x2000), 64))
+        );
+        assert_eq!(
+            keyctl_outparam(&regs, 10),
+            Some((RemotePtr::new_from_val(0x2000), 10))
+        );
+        // Just asking for the size.
+        regs.set_arg3(0);
+        assert_eq!(keyctl_outparam(&regs, 100), None);
+
+        regs.set_arg1(KEYCTL_PKEY_QUERY as usize);
+        regs.set_arg5(0x3000);
+        assert_eq!(
+            keyctl_outparam(&regs, 0),
+            Some((RemotePtr::new_from_val(0x3000), KEYCTL_PKEY_QUERY_SIZE))
+        );
+        regs.set_arg1(KEYCTL_REVOKE as usize);
+        assert_eq!(keyctl_outparam(&regs, 0), None);
+    }
+
+    #[test]
+    fn remaining_time_outparams() {
+        let mut regs = Registers::new(SupportedArch::X64);
+        regs.set_arg2(0x2000);
+        assert_eq!(
+            remaining_time_outparam::<X64Arch>(X64Arch::NANOSLEEP, &regs),
+            Some((RemotePtr::new_from_val(0x2000), 16))
+        );
+
+        regs.set_arg2(0);
+        regs.set_arg4(0x3000);
+        assert_eq!(
+            remaining_time_outparam::<X64Arch>(X64Arch::CLOCK_NANOSLEEP, &regs),
+            Some((RemotePtr::new_from_val(0x3000), 16))
+        );
+        regs.set_arg2(TIMER_ABSTIME);
+        assert_eq!(
+            remaining_time_outparam::<X64Arch>(X64Arch::CLOCK_NANOSLEEP, &regs),
+            None
+        );
+
+        regs.set_arg2(0);
+        assert_eq!(
+            remaining_time_outparam::<X64Arch>(X64Arch::NANOSLEEP, &regs),
+            None
+        );
+        assert_eq!(
+            remaining_time_outparam::<X64Arch>(X64Arch::READ, &regs),
+            None
+        );
+    }
+}
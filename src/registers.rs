@@ -63,6 +63,14 @@ macro_rules! rd_get_reg_signed {
     };
 }
 
+/// A register whose value differs between two register files.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RegisterMismatch {
+    pub name: &'static str,
+    pub value1: u64,
+    pub value2: u64,
+}
+
 #[derive(Copy, Clone, PartialEq, PartialOrd)]
 pub enum MismatchBehavior {
     ExpectMismatches = 1,
@@ -158,7 +166,17 @@ impl Registers {
         regs2: &Registers,
         mismatch_behavior: MismatchBehavior,
     ) -> bool {
-        let mut match_ = true;
+        let mismatches = Registers::mismatches(regs1, regs2);
+        for m in &mismatches {
+            maybe_log_reg_mismatch(mismatch_behavior, m.name, name1, m.value1, name2, m.value2);
+        }
+        mismatches.is_empty()
+    }
+
+    /// The registers whose values differ between `regs1` and `regs2`, ignoring
+    /// the bits that `compare_register_files()` ignores.
+    pub fn mismatches(regs1: &Registers, regs2: &Registers) -> Vec<RegisterMismatch> {
+        let mut mismatches = Vec::new();
         debug_assert!(regs1.arch() == regs2.arch());
         let regs_info = regs1.get_regs_info();
 
@@ -171,15 +189,11 @@ impl Registers {
                 // they reflect original syscall numbers, in which case both will be positive.
                 if regs1_x86.orig_eax >= 0 && regs2_x86.orig_eax > 0 {
                     if regs1_x86.orig_eax != regs2_x86.orig_eax {
-                        maybe_log_reg_mismatch(
-                            mismatch_behavior,
-                            "orig_eax",
-                            name1,
-                            regs1_x86.orig_eax as u64,
-                            name2,
-                            regs2_x86.orig_eax as u64,
-                        );
-                        match_ = false;
+                        mismatches.push(RegisterMismatch {
+                            name: "orig_eax",
+                            value1: regs1_x86.orig_eax as u64,
+                            value2: regs2_x86.orig_eax as u64,
+                        });
                     }
                 }
             }
@@ -188,15 +202,11 @@ impl Registers {
                 // See comment in the x86 case
                 if (regs1_x64.orig_rax as i64) >= 0 && (regs2_x64.orig_rax as i64) > 0 {
                     if regs1_x64.orig_rax != regs2_x64.orig_rax {
-                        maybe_log_reg_mismatch(
-                            mismatch_behavior,
-                            "orig_rax",
-                            name1,
-                            regs1_x64.orig_rax,
-                            name2,
-                            regs2_x64.orig_rax,
-                        );
-                        match_ = false;
+                        mismatches.push(RegisterMismatch {
+                            name: "orig_rax",
+                            value1: regs1_x64.orig_rax,
+                            value2: regs2_x64.orig_rax,
+                        });
                     }
                 }
             }
@@ -240,12 +250,15 @@ impl Registers {
             }
 
             if val1 & rv.comparison_mask != val2 & rv.comparison_mask {
-                maybe_log_reg_mismatch(mismatch_behavior, rv.name, name1, val1, name2, val2);
-                match_ = false;
+                mismatches.push(RegisterMismatch {
+                    name: rv.name,
+                    value1: val1,
+                    value2: val2,
+                });
             }
         }
 
-        match_
+        mismatches
     }
 
     fn compare_register_files_internal(
@@ -320,7 +333,7 @@ impl Registers {
     /// be large enough to hold any register supported by the target.
     /// Return the size of the register in bytes. If None is returned it
     /// indicates that no value was written to `buf`.
-    fn read_register(&self, buf: &mut [u8], regno: GdbRegister) -> Option<usize> {
+    pub fn read_register(&self, buf: &mut [u8], regno: GdbRegister) -> Option<usize> {
         let regs = self.get_regs_info();
         if let Some(rv) = regs.get(&regno) {
             match rv.nbytes {
@@ -830,6 +843,10 @@ impl Registers {
         x64.r10 = value;
     }
 
+    pub fn r11(&self) -> u64 {
+        self.x64().r11
+    }
+
     pub fn set_r11(&mut self, value: u64) {
         let mut x64 = self.x64_mut();
         x64.r11 = value;
@@ -1517,3 +1534,50 @@ impl Default for Registers {
         Registers::X86(x86::user_regs_struct::default())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn register_mismatches() {
+        let recorded = Registers::new(SupportedArch::X64);
+        let mut replay = recorded.clone();
+        replay.set_original_syscallno(-1);
+        // The stack pointer and flags aren't compared.
+        replay.set_sp(RemotePtr::new_from_val(0x7000));
+        replay.set_flags(0x246);
+        assert!(Registers::mismatches(&replay, &recorded).is_empty());
+
+        replay.set_arg1(5);
+        replay.set_ip(RemoteCodePtr::from_val(0x1000));
+        assert_eq!(
+            Registers::mismatches(&replay, &recorded),
+            vec![
+                RegisterMismatch {
+                    name: "rdi",
+                    value1: 5,
+                    value2: 0,
+                },
+                RegisterMismatch {
+                    name: "rip",
+                    value1: 0x1000,
+                    value2: 0,
+                },
+            ]
+        );
+
+        let mut recorded = recorded;
+        recorded.set_original_syscallno(3);
+        replay = recorded.clone();
+        replay.set_original_syscallno(4);
+        assert_eq!(
+            Registers::mismatches(&replay, &recorded),
+            vec![RegisterMismatch {
+                name: "orig_rax",
+                value1: 4,
+                value2: 3,
+            }]
+        );
+    }
+}
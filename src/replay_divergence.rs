@@ -0,0 +1,223 @@
+use crate::{
+    elf_symbols::{nearest_symbol, ElfFiles},
+    memory_checksum::MemoryMismatch,
+    registers::RegisterMismatch,
+    remote_code_ptr::RemoteCodePtr,
+    session::task::replay_task::ReplayTask,
+    skid_region::SkidStats,
+    ticks::Ticks,
+    trace::{trace_frame::FrameTime, trace_reader::TraceReader},
+};
+use libc::pid_t;
+use std::fmt::{self, Display, Formatter};
+
+/// How many of the events leading up to a divergence the report shows.
+const RECENT_EVENT_COUNT: FrameTime = 5;
+
+/// What didn't match the recording.
+#[derive(Clone, Debug)]
+pub enum DivergenceKind {
+    /// Registers at the end of an event. `value1` of each mismatch is the
+    /// replay value and `value2` the recorded one.
+    Registers(Vec<RegisterMismatch>),
+    /// Memory checksums taken during recording.
+    Memory(Vec<MemoryMismatch>),
+    /// Replay ran past the point where the recorded event happened without
+    /// reaching the recorded state.
+    /// `skid_margin` is how far short of the target the task's interrupts
+    /// were programmed.
+    TicksOvershoot {
+        target: Ticks,
+        overshoot: Ticks,
+        skid_margin: Ticks,
+        skid_stats: SkidStats,
+    },
+}
+
+/// A description of where and how replay stopped matching the recording, for
+/// callers of `ReplaySession::replay_step()` to inspect (or print) instead of
+/// rd dying with an assertion.
+#[derive(Clone, Debug)]
+pub struct DivergenceReport {
+    pub time: FrameTime,
+    pub rec_tid: pid_t,
+    pub ticks: Ticks,
+    pub kind: DivergenceKind,
+    /// The nearest symbol (if any) for the replay ip and, if different, the
+    /// recorded ip.
+    pub symbols: Vec<(RemoteCodePtr, String)>,
+    /// The last few events up to and including the one that diverged, oldest
+    /// first.
+    pub recent_events: Vec<String>,
+}
+
+impl DivergenceReport {
+    pub fn new(t: &ReplayTask, trace: &TraceReader, kind: DivergenceKind) -> DivergenceReport {
+        let time = t.current_frame_time();
+        let replay_ip = t.ip();
+        let recorded_ip = t.current_trace_frame().regs_ref().ip();
+        let mut files = ElfFiles::default();
+        let mut symbols = Vec::new();
+        for &ip in &[replay_ip, recorded_ip] {
+            if symbols.iter().any(|&(addr, _)| addr == ip) {
+                continue;
+            }
+            if let Some(s) = nearest_symbol(&mut files, t, ip) {
+                symbols.push((ip, s));
+            }
+        }
+        DivergenceReport {
+            time,
+            rec_tid: t.rec_tid,
+            ticks: t.tick_count(),
+            kind,
+            symbols,
+            recent_events: recent_events(trace, time),
+        }
+    }
+}
+
+impl Display for DivergenceReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Replay diverged at event {} in task {} (ticks {})",
+            self.time, self.rec_tid, self.ticks
+        )?;
+        match &self.kind {
+            DivergenceKind::Registers(mismatches) => {
+                writeln!(f, "Registers differ (replay vs. recorded):")?;
+                for m in mismatches {
+                    writeln!(f, "  {:<10} {:#18x} {:#18x}", m.name, m.value1, m.value2)?;
+                }
+            }
+            DivergenceKind::Memory(mismatches) => {
+                writeln!(f, "Memory differs (replay vs. recorded checksum):")?;
+                for m in mismatches {
+                    match m.replay_checksum {
+                        Some(c) => writeln!(
+                            f,
+                            "  {:#x}-{:#x} {:08x} {:08x}",
+                            m.start, m.end, c, m.recorded_checksum
+                        )?,
+                        None => writeln!(f, "  {:#x}-{:#x} not mapped", m.start, m.end)?,
+                    }
+                }
+            }
+            DivergenceKind::TicksOvershoot {
+                target,
+                overshoot,
+                skid_margin,
+                skid_stats,
+            } => {
+                writeln!(
+                    f,
+                    "Overshot target ticks {} by {} without reaching the recorded state",
+                    target, overshoot
+                )?;
+                writeln!(f, "Skid so far: {}", skid_stats)?;
+                writeln!(
+                    f,
+                    "If nothing diverged before this, the ticks interrupt may have skidded \
+                     too far; retry with --skid-size above {}",
+                    skid_margin + overshoot
+                )?;
+            }
+        }
+        for (ip, s) in &self.symbols {
+            writeln!(f, "{} is in {}", ip, s)?;
+        }
+        writeln!(f, "Recent events:")?;
+        for e in &self.recent_events {
+            writeln!(f, "  {}", e)?;
+        }
+        Ok(())
+    }
+}
+
+fn recent_events(trace: &TraceReader, time: FrameTime) -> Vec<String> {
+    let mut trace = trace.clone();
+    let first = time.saturating_sub(RECENT_EVENT_COUNT - 1).max(1);
+    let mut events = Vec::new();
+    if !trace.seek_to_event(first) {
+        return events;
+    }
+    while !trace.at_end() {
+        let frame = trace.read_frame();
+        if frame.time() > time {
+            break;
+        }
+        events.push(format!(
+            "{} tid {}: {}",
+            frame.time(),
+            frame.tid(),
+            frame.event()
+        ));
+        while trace.read_raw_data_metadata_for_frame().is_some() {}
+    }
+    events
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn report(kind: DivergenceKind) -> DivergenceReport {
+        DivergenceReport {
+            time: 42,
+            rec_tid: 1000,
+            ticks: 12345,
+            kind,
+            symbols: vec![(RemoteCodePtr::from_val(0x401000), "main+0x10".into())],
+            recent_events: vec!["41 tid 1000: SYSCALL: write".into()],
+        }
+    }
+
+    #[test]
+    fn register_report() {
+        let kind = DivergenceKind::Registers(vec![RegisterMismatch {
+            name: "rip",
+            value1: 0x401000,
+            value2: 0x401004,
+        }]);
+        let text = report(kind).to_string();
+        assert!(text.starts_with("Replay diverged at event 42 in task 1000 (ticks 12345)\n"));
+        assert!(text.contains("  rip                  0x401000           0x401004\n"));
+        assert!(text.contains("0x401000 is in main+0x10\n"));
+        assert!(text.ends_with("Recent events:\n  41 tid 1000: SYSCALL: write\n"));
+    }
+
+    #[test]
+    fn memory_report() {
+        let kind = DivergenceKind::Memory(vec![
+            MemoryMismatch {
+                start: 0x1000,
+                end: 0x2000,
+                recorded_checksum: 1,
+                replay_checksum: Some(2),
+            },
+            MemoryMismatch {
+                start: 0x3000,
+                end: 0x4000,
+                recorded_checksum: 1,
+                replay_checksum: None,
+            },
+        ]);
+        let text = report(kind).to_string();
+        assert!(text.contains("  0x1000-0x2000 00000002 00000001\n"));
+        assert!(text.contains("  0x3000-0x4000 not mapped\n"));
+    }
+
+    #[test]
+    fn ticks_overshoot_report() {
+        let kind = DivergenceKind::TicksOvershoot {
+            target: 1000,
+            overshoot: 30,
+            skid_margin: 70,
+            skid_stats: Default::default(),
+        };
+        let text = report(kind).to_string();
+        assert!(text.contains("Overshot target ticks 1000 by 30 "));
+        assert!(text.contains("retry with --skid-size above 100\n"));
+    }
+}
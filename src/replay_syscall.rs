@@ -25,18 +25,24 @@ use crate::{
             PTRACE_SYSEMU_SINGLESTEP,
         },
     },
+    capture_reads::matches_any,
     emu_fs::EmuFileSharedPtr,
     file_monitor::{
         base_file_monitor::BaseFileMonitor,
+        capture_reads_monitor::CaptureReadsMonitor,
         mmapped_file_monitor::MmappedFileMonitor,
         proc_fd_dir_monitor::ProcFdDirMonitor,
         proc_mem_monitor::ProcMemMonitor,
+        random_device_monitor::RandomDeviceMonitor,
         stdio_monitor::StdioMonitor,
         FileMonitor,
         FileMonitorType,
     },
     kernel_abi::{
-        common::preload_interface::{syscallbuf_hdr, SYS_rdcall_reload_auxv},
+        common::{
+            clone_args,
+            preload_interface::{syscallbuf_hdr, SYS_rdcall_reload_auxv},
+        },
         is_rdcall_notify_syscall_hook_exit_syscall,
         is_restart_syscall_syscall,
         is_write_syscall,
@@ -50,13 +56,41 @@ use crate::{
         SupportedArch,
         RD_NATIVE_ARCH,
     },
-    kernel_metadata::{is_sigreturn, shm_flags_to_mmap_prot, syscall_name},
-    kernel_supplement::{ARCH_GET_CPUID, ARCH_SET_CPUID},
+    kernel_metadata::{errno_name, is_sigreturn, shm_flags_to_mmap_prot, syscall_name},
+    kernel_supplement::{
+        sig_set_t,
+        ARCH_GET_CPUID,
+        ARCH_SET_CPUID,
+        CLONE_INTO_CGROUP,
+        CLONE_PIDFD,
+        RSEQ_FLAG_UNREGISTER,
+        SECCOMP_FILTER_FLAG_NEW_LISTENER,
+        SECCOMP_SET_MODE_FILTER,
+    },
+    landlock::creates_landlock_ruleset,
     log::LogLevel::LogDebug,
+    record_syscall::{
+        add_io_uring_monitor,
+        add_landlock_monitor,
+        add_pidfd_monitor,
+        add_seccomp_notify_monitor,
+        add_timerfd_monitor,
+        add_virtual_perf_counter_monitor,
+        clone_pidfd_addr,
+        import_pidfd_getfd_monitor,
+        is_io_uring_fd,
+        is_seccomp_listener,
+        note_clone_pidfd,
+        note_seccomp_notify_ioctl,
+        note_signalfd,
+        read_perf_event_attr,
+    },
     registers::{with_converted_registers, Registers},
     remote_ptr::{RemotePtr, Void},
+    rseq::RseqState,
     scoped_fd::ScopedFd,
     seccomp_filter_rewriter::SECCOMP_MAGIC_SKIP_ORIGINAL_SYSCALLNO,
+    seeded_random::is_random_device,
     session::{
         address_space::{
             address_space::AddressSpace,
@@ -88,13 +122,17 @@ use crate::{
     },
     util::{
         ceil_page_size,
+        ceil_page_size_for,
         ceil_page_u64,
+        clone3_parameters,
         clone_flags_to_task_flags,
         extract_clone_parameters,
         floor_page_size,
         is_proc_fd_dir,
         is_proc_mem_file,
         page_size,
+        read_clone_args,
+        read_open_how,
         resource_path,
         CloneParameters,
     },
@@ -139,12 +177,12 @@ use nix::{
     unistd::{access, lseek, read, AccessFlags, Whence},
 };
 use std::{
-    cell::RefMut,
     cmp::min,
     convert::TryInto,
     ffi::{CString, OsStr, OsString},
     mem::size_of,
     os::unix::ffi::{OsStrExt, OsStringExt},
+    slice,
 };
 use trace_stream::{MappedDataSource, TraceRemoteFd};
 
@@ -248,6 +286,14 @@ fn init_scratch_memory(t: &mut ReplayTask, km: &KernelMapping, data: &trace_stre
 
     t.scratch_ptr = km.start();
     t.scratch_size = km.size();
+    if data.rd_mapping_slots_skipped > 0 {
+        log!(
+            LogDebug,
+            "Scratch at {} was placed after skipping {} taken slots while recording",
+            km.start(),
+            data.rd_mapping_slots_skipped
+        );
+    }
     let sz = t.scratch_size;
     let scratch_ptr = t.scratch_ptr;
     // Make the scratch buffer read/write during replay so that
@@ -342,6 +388,14 @@ fn read_task_trace_event(t: &ReplayTask, task_event_type: TraceTaskEventType) ->
     tte.unwrap()
 }
 
+/// Write the first `size` bytes of `args`, which is as much as the tracee
+/// passed, to `addr`.
+fn write_clone_args(t: &mut ReplayTask, addr: RemotePtr<Void>, args: &clone_args, size: usize) {
+    let len = min(size, size_of::<clone_args>());
+    let bytes = unsafe { slice::from_raw_parts(args as *const clone_args as *const u8, len) };
+    write_mem(t, RemotePtr::<u8>::cast(addr), bytes, None);
+}
+
 fn prepare_clone<Arch: Architecture>(t: &mut ReplayTask) {
     let trace_frame = t.current_trace_frame();
     let trace_frame_regs = trace_frame.regs_ref().clone();
@@ -360,25 +414,57 @@ fn prepare_clone<Arch: Architecture>(t: &mut ReplayTask) {
     let mut r = t.regs_ref().clone();
     let mut sys: i32 = r.original_syscallno() as i32;
     let mut flags: i32 = 0;
+    // If we allow CLONE_UNTRACED then the child would escape from rd control
+    // and we can't allow that.
+    // Block CLONE_CHILD_CLEARTID because we'll emulate that ourselves.
+    // Block CLONE_VFORK for the reasons below.
+    // Block CLONE_NEW* from replay, any effects it had were dealt with during
+    // recording.
+    // Block CLONE_PIDFD because the pidfd the tracee got is restored from the
+    // trace below.
+    let disallowed_clone_flags = CLONE_UNTRACED
+        | CLONE_CHILD_CLEARTID
+        | CLONE_VFORK
+        | CLONE_PIDFD as i32
+        | CLONE_NEWIPC
+        | CLONE_NEWNET
+        | CLONE_NEWNS
+        | CLONE_NEWPID
+        | CLONE_NEWUSER
+        | CLONE_NEWUTS
+        | CLONE_NEWCGROUP;
+    // For clone3(), the argument struct as the tracee passed it and its size.
+    let mut maybe_clone3_args: Option<(clone_args, usize)> = None;
     if Arch::CLONE == sys {
-        // If we allow CLONE_UNTRACED then the child would escape from rd control
-        // and we can't allow that.
-        // Block CLONE_CHILD_CLEARTID because we'll emulate that ourselves.
-        // Block CLONE_VFORK for the reasons below.
-        // Block CLONE_NEW* from replay, any effects it had were dealt with during
-        // recording.
-        let disallowed_clone_flags = CLONE_UNTRACED
-            | CLONE_CHILD_CLEARTID
-            | CLONE_VFORK
-            | CLONE_NEWIPC
-            | CLONE_NEWNET
-            | CLONE_NEWNS
-            | CLONE_NEWPID
-            | CLONE_NEWUSER
-            | CLONE_NEWUTS
-            | CLONE_NEWCGROUP;
         flags = r.arg1() as i32 & !disallowed_clone_flags;
         r.set_arg1(flags as usize);
+    } else if Arch::CLONE3 == sys {
+        let addr = RemotePtr::<Void>::from(r.arg1());
+        let size = r.arg2();
+        let args = match read_clone_args(t, addr, size) {
+            Ok(args) => args,
+            Err(errno) => {
+                ed_assert!(
+                    t,
+                    false,
+                    "clone3() arguments that worked during recording fail with {}",
+                    errno_name(errno)
+                );
+                unreachable!()
+            }
+        };
+        // Also block the things that only make sense for the recorded tids
+        // and fds: the tids asked for with set_tid may be taken (the child's
+        // recorded tid comes from the trace anyway) and the cgroup fd isn't
+        // open during replay.
+        let mut replay_args = args;
+        replay_args.flags &= !(disallowed_clone_flags as u64 | CLONE_INTO_CGROUP);
+        replay_args.set_tid = 0;
+        replay_args.set_tid_size = 0;
+        replay_args.cgroup = 0;
+        write_clone_args(t, addr, &replay_args, size);
+        flags = replay_args.flags as i32;
+        maybe_clone3_args = Some((args, size));
     } else if Arch::VFORK == sys {
         // We can't perform a real vfork, because the kernel won't let the vfork
         // parent return from the syscall until the vfork child has execed or
@@ -446,6 +532,9 @@ fn prepare_clone<Arch: Architecture>(t: &mut ReplayTask) {
         t.maybe_ptrace_event()
     );
 
+    if let Some((args, size)) = maybe_clone3_args {
+        write_clone_args(t, RemotePtr::from(trace_frame_regs.arg1()), &args, size);
+    }
     r = t.regs_ref().clone();
     // Restore the saved flags, to hide the fact that we may have
     // masked out CLONE_UNTRACED/CLONE_CHILD_CLEARTID or changed from vfork to
@@ -474,6 +563,8 @@ fn prepare_clone<Arch: Architecture>(t: &mut ReplayTask) {
     let mut params: CloneParameters = Default::default();
     if Arch::CLONE as isize == t.regs_ref().original_syscallno() {
         params = extract_clone_parameters(t);
+    } else if let Some((args, _)) = maybe_clone3_args {
+        params = clone3_parameters(&args);
     }
     let shr_ptr = t.session();
 
@@ -490,9 +581,14 @@ fn prepare_clone<Arch: Architecture>(t: &mut ReplayTask) {
     let mut new_task_ref = new_task_shr_ptr.borrow_mut();
     let new_task: &mut ReplayTask = new_task_ref.as_replay_task_mut().unwrap();
 
-    if Arch::CLONE as isize == t.regs_ref().original_syscallno() {
+    // See `finish_clone3()` for the order of clone3()'s data.
+    if Arch::CLONE as isize == t.regs_ref().original_syscallno() || maybe_clone3_args.is_some() {
         // FIXME: what if registers are non-null and contain an invalid address?
         t.set_data_from_trace();
+        if maybe_clone3_args.is_some() {
+            // The pidfd.
+            t.set_data_from_trace();
+        }
 
         if Arch::CLONE_TLS_TYPE == CloneTLSType::UserDescPointer {
             t.set_data_from_trace();
@@ -503,6 +599,18 @@ fn prepare_clone<Arch: Architecture>(t: &mut ReplayTask) {
         new_task.set_data_from_trace();
         new_task.set_data_from_trace();
     }
+    let recorded_flags = match &maybe_clone3_args {
+        Some((args, _)) => args.flags,
+        None if Arch::CLONE as isize == trace_frame_regs.original_syscallno() => {
+            trace_frame_regs.arg1() as u64
+        }
+        None => 0,
+    };
+    let pidfd_addr = clone_pidfd_addr(
+        recorded_flags,
+        maybe_clone3_args.as_ref().map(|(args, _)| args),
+        &params,
+    );
 
     // Fix registers in new task
     let mut new_r = new_task.regs_ref().clone();
@@ -513,9 +621,14 @@ fn prepare_clone<Arch: Architecture>(t: &mut ReplayTask) {
     new_task.set_regs(&new_r);
     new_task.canonicalize_regs(new_task_arch);
 
-    if Arch::CLONE as isize != t.regs_ref().original_syscallno()
-        || !(CLONE_VM as usize & r.arg1() == CLONE_VM as usize)
-    {
+    let shares_vm = match maybe_clone3_args {
+        Some((args, _)) => args.flags & CLONE_VM as u64 != 0,
+        None => {
+            Arch::CLONE as isize == t.regs_ref().original_syscallno()
+                && CLONE_VM as usize & r.arg1() == CLONE_VM as usize
+        }
+    };
+    if !shares_vm {
         // It's hard to imagine a scenario in which it would
         // be useful to inherit breakpoints (along with their
         // refcounts) across a non-VM-sharing clone, but for
@@ -553,6 +666,9 @@ fn prepare_clone<Arch: Architecture>(t: &mut ReplayTask) {
     init_scratch_memory(new_task, &km, &data);
 
     new_task.vm_shr_ptr().after_clone(new_task, Some(t));
+    if !pidfd_addr.is_null() {
+        note_clone_pidfd(t, pidfd_addr, rec_tid);
+    }
 }
 
 /// DIFF NOTE: This simply returns a ReplayTraceStep instead of modifying one.
@@ -660,14 +776,11 @@ fn rep_process_syscall_arch<Arch: Architecture>(
     }
 
     let nsys: i32 = non_negative_syscall(sys);
-    if trace_regs.syscall_failed() {
-        if nsys != Arch::MADVISE
-            && nsys != Arch::MPROTECT
-            && nsys != Arch::SIGRETURN
-            && nsys != Arch::RT_SIGRETURN
-        {
-            return;
-        }
+    if trace_regs.syscall_failed()
+        && !replay_executes_failed_syscall::<Arch>(nsys)
+        && !is_sigreturn(nsys, Arch::arch())
+    {
+        return;
     }
 
     // Manual implementations of irregular syscalls that need to do more during
@@ -676,7 +789,9 @@ fn rep_process_syscall_arch<Arch: Architecture>(
     // system call that we assigned a negative number because it doesn't
     // exist in this architecture.
     // All invalid/unsupported syscalls get the default emulation treatment.
-    if nsys == Arch::EXECVE {
+    // execveat() is replayed exactly like execve(): we exec the recorded
+    // image directly, so how the tracee named the file doesn't matter.
+    if nsys == Arch::EXECVE || nsys == Arch::EXECVEAT {
         return process_execve(t, step);
     }
 
@@ -741,13 +856,7 @@ fn rep_process_syscall_arch<Arch: Architecture>(
         }
     }
 
-    if nsys == Arch::MADVISE
-        || nsys == Arch::ARCH_PRCTL
-        || nsys == Arch::MUNMAP
-        || nsys == Arch::MPROTECT
-        || nsys == Arch::MODIFY_LDT
-        || nsys == Arch::SET_THREAD_AREA
-    {
+    if replay_executes_syscall::<Arch>(nsys) {
         // Using AutoRemoteSyscalls here fails for arch_prctl, not sure why.
         let mut r: Registers = t.regs_ref().clone();
         r.set_syscallno(t.regs_ref().original_syscallno());
@@ -804,7 +913,115 @@ fn rep_process_syscall_arch<Arch: Architecture>(
     }
 
     if nsys == Arch::PERF_EVENT_OPEN {
-        unimplemented!();
+        // Recording only ever lets the tracee have virtual counters.
+        let fd = t.regs_ref().syscall_result_signed() as i32;
+        if fd >= 0 {
+            let attr = read_perf_event_attr(t, RemotePtr::new_from_val(trace_regs.arg1()));
+            ed_assert!(t, attr.is_some(), "Can't read perf_event_attr");
+            add_virtual_perf_counter_monitor(
+                t,
+                trace_regs.arg2_signed() as pid_t,
+                fd,
+                &attr.unwrap(),
+            );
+        }
+    }
+
+    if nsys == Arch::PIDFD_OPEN {
+        let fd = t.regs_ref().syscall_result_signed() as i32;
+        if fd >= 0 {
+            add_pidfd_monitor(t, fd, trace_regs.arg1_signed() as pid_t);
+        }
+        return;
+    }
+
+    if nsys == Arch::TIMERFD_CREATE {
+        let fd = t.regs_ref().syscall_result_signed() as i32;
+        if fd >= 0 {
+            add_timerfd_monitor(t, fd);
+        }
+        return;
+    }
+
+    if nsys == Arch::SIGNALFD || nsys == Arch::SIGNALFD4 {
+        let fd = t.regs_ref().syscall_result_signed() as i32;
+        if fd >= 0 {
+            let mask = read_val_mem::<sig_set_t>(t, RemotePtr::from(trace_regs.arg2()), None);
+            note_signalfd(t, fd, mask);
+        }
+        return;
+    }
+
+    if nsys == Arch::PIDFD_GETFD {
+        let fd = t.regs_ref().syscall_result_signed() as i32;
+        if fd >= 0 {
+            import_pidfd_getfd_monitor(
+                t,
+                trace_regs.arg1_signed() as i32,
+                trace_regs.arg2_signed() as i32,
+                fd,
+            );
+        }
+        return;
+    }
+
+    if nsys == Arch::SECCOMP {
+        // The listener fd of a filter that notifies. The filter itself was
+        // installed during recording only.
+        let fd = t.regs_ref().syscall_result_signed() as i32;
+        if trace_regs.arg1() == SECCOMP_SET_MODE_FILTER as usize
+            && trace_regs.arg2() & SECCOMP_FILTER_FLAG_NEW_LISTENER as usize != 0
+            && fd >= 0
+        {
+            add_seccomp_notify_monitor(t, fd);
+        }
+        return;
+    }
+
+    if nsys == Arch::IOCTL {
+        if is_seccomp_listener(t, trace_regs.arg1_signed() as i32) {
+            // A received notification is only written back at syscall exit.
+            t.apply_all_data_records_from_trace();
+            note_seccomp_notify_ioctl(t, trace_regs);
+        }
+        return;
+    }
+
+    if nsys == Arch::IO_URING_SETUP {
+        // The monitor needs the params the kernel wrote back, which are in
+        // the recorded data.
+        let fd = t.regs_ref().syscall_result_signed() as i32;
+        if fd >= 0 {
+            t.apply_all_data_records_from_trace();
+            add_io_uring_monitor(t, fd, RemotePtr::from(trace_regs.arg2()));
+        }
+        return;
+    }
+
+    if nsys == Arch::RSEQ {
+        // rseq() was emulated during recording. Track the registration the
+        // same way so critical sections are aborted at the same points.
+        if trace_regs.syscall_result_signed() != 0 {
+            return;
+        }
+        if trace_regs.arg3() as u32 & RSEQ_FLAG_UNREGISTER != 0 {
+            t.rseq_state = None;
+        } else {
+            t.rseq_state = Some(RseqState {
+                ptr: RemotePtr::new_from_val(trace_regs.arg1()),
+                abort_prefix_signature: trace_regs.arg4() as u32,
+            });
+        }
+        return;
+    }
+
+    if nsys == Arch::LANDLOCK_CREATE_RULESET {
+        // Emulated, see `landlock`.
+        let fd = t.regs_ref().syscall_result_signed() as i32;
+        if creates_landlock_ruleset(trace_regs) && fd >= 0 {
+            add_landlock_monitor(t, fd);
+        }
+        return;
     }
 
     if nsys == Arch::PERF_EVENT_OPEN
@@ -827,6 +1044,13 @@ fn rep_process_syscall_arch<Arch: Architecture>(
         return;
     }
 
+    if nsys == Arch::OPENAT2 {
+        let addr = RemotePtr::<Void>::from(trace_regs.arg3());
+        let flags = read_open_how(t, addr, trace_regs.arg4()).map_or(0, |how| how.flags as i32);
+        handle_opened_files(t, flags);
+        return;
+    }
+
     if nsys == Arch::WRITE || nsys == Arch::WRITEV {
         // write*() can be desched'd, but don't use scratch,
         // so we might have saved 0 bytes of scratch after a
@@ -838,28 +1062,13 @@ fn rep_process_syscall_arch<Arch: Architecture>(
     if nsys == Arch::PROCESS_VM_WRITEV {
         let dest_pid = t.regs_ref().arg1() as pid_t;
         let iov_cnt = t.regs_ref().arg5();
-        let t_rc: TaskSharedPtr;
-        let mut t_b: RefMut<Box<dyn Task>>;
-        let maybe_dest = if dest_pid == t.rec_tid {
-            Some(t)
-        } else {
-            // Recorded data records may be for another process.
-            match t.session().find_task_from_rec_tid(dest_pid) {
-                Some(found_rc) => {
-                    t_rc = found_rc;
-                    t_b = t_rc.borrow_mut();
-                    Some(t_b.as_replay_task_mut().unwrap())
-                }
-                None => None,
-            }
-        };
-        match maybe_dest {
-            Some(dest) => {
+        // Recorded data records may be for another process.
+        if let Some(dest) = t.session().find_task_handle(dest_pid) {
+            dest.with_mut(t, |dest| {
                 for _ in 0..iov_cnt {
-                    dest.set_data_from_trace();
+                    dest.as_replay_task_mut().unwrap().set_data_from_trace();
                 }
-            }
-            None => (),
+            });
         }
         return;
     }
@@ -949,6 +1158,31 @@ fn process_brk(t: &mut ReplayTask) {
     }
 }
 
+/// Whether the generic code in `rep_process_syscall_arch()` reproduces the
+/// syscall `sys` by running it in the tracee rather than by emulating it from
+/// the trace. This is only done for syscalls that change nothing but the
+/// tracee's own mappings and thread state, and that do it the same way every
+/// time given the same arguments, so no trace data is needed for their
+/// effects.
+///
+/// mmap()s never get here: `process_mmap()` executes anonymous ones at the
+/// recorded address itself.
+fn replay_executes_syscall<Arch: Architecture>(sys: i32) -> bool {
+    sys == Arch::MADVISE
+        || sys == Arch::ARCH_PRCTL
+        || sys == Arch::MUNMAP
+        || sys == Arch::MPROTECT
+        || sys == Arch::MODIFY_LDT
+        || sys == Arch::SET_THREAD_AREA
+}
+
+/// Whether replay executes `sys` even if it failed during recording. madvise()
+/// and mprotect() may have changed part of the range before failing, and
+/// doing the same thing again fails the same way.
+fn replay_executes_failed_syscall<Arch: Architecture>(sys: i32) -> bool {
+    sys == Arch::MADVISE || sys == Arch::MPROTECT
+}
+
 fn non_negative_syscall(sys: i32) -> i32 {
     if sys < 0 {
         i32::MAX
@@ -970,7 +1204,7 @@ fn rep_after_enter_syscall_arch<Arch: Architecture>(t: &mut ReplayTask) {
         t.fd_table().will_write(t, fd);
     }
 
-    if sys == Arch::CLONE || sys == Arch::VFORK || sys == Arch::FORK {
+    if sys == Arch::CLONE || sys == Arch::CLONE3 || sys == Arch::VFORK || sys == Arch::FORK {
         // Create the new task now. It needs to exist before clone/fork/vfork
         // returns so that a ptracer can touch it during PTRACE_EVENT handling.
         prepare_clone::<Arch>(t);
@@ -980,7 +1214,7 @@ fn rep_after_enter_syscall_arch<Arch: Architecture>(t: &mut ReplayTask) {
         let pid: pid_t = t.regs_ref().arg2_signed() as pid_t;
         // DIFF NOTE: This assertion is not there in rr.
         ed_assert!(t, pid != t.rec_tid);
-        let maybe_target = t.session().find_task_from_rec_tid(pid);
+        let maybe_target = t.session().find_task_handle(pid);
         match maybe_target {
             None => (),
             Some(target) => match t.regs_ref().arg1() as u32 {
@@ -998,9 +1232,13 @@ fn rep_after_enter_syscall_arch<Arch: Architecture>(t: &mut ReplayTask) {
                 | PTRACE_CONT
                 | PTRACE_DETACH => {
                     let command = t.regs_ref().arg1() as u32;
-                    target
-                        .borrow()
-                        .set_syscallbuf_locked(command != PTRACE_CONT && command != PTRACE_DETACH);
+                    let locked = command != PTRACE_CONT && command != PTRACE_DETACH;
+                    // The tracee is stopped, so this can wait until we're done with `t`.
+                    t.session().defer(move |sess| {
+                        if let Some(target) = sess.find_task_handle(pid) {
+                            target.borrow().set_syscallbuf_locked(locked);
+                        }
+                    });
                 }
                 PTRACE_SET_THREAD_AREA => {
                     let mut ok = true;
@@ -1008,9 +1246,12 @@ fn rep_after_enter_syscall_arch<Arch: Architecture>(t: &mut ReplayTask) {
                     let desc: user_desc =
                         read_val_mem(t, RemotePtr::<user_desc>::from(child_addr), Some(&mut ok));
                     if ok {
-                        target
-                            .borrow_mut()
-                            .emulate_set_thread_area(t.regs_ref().arg3() as u32, desc);
+                        let idx = t.regs_ref().arg3() as u32;
+                        t.session().defer(move |sess| {
+                            if let Some(target) = sess.find_task_handle(pid) {
+                                target.borrow_mut().emulate_set_thread_area(idx, desc);
+                            }
+                        });
                     }
                 }
                 _ => (),
@@ -1149,6 +1390,12 @@ pub fn process_execve(t: &mut ReplayTask, step: &mut ReplayTraceStep) {
 
     let maybe_exec = read_task_trace_event(t, TraceTaskEventType::Exec);
     let tte = maybe_exec.exec_variant();
+    if tte.attached_fds().is_some() {
+        clean_fatal!(
+            "This trace was recorded by attaching to a running process (`rd record -p`),\n\
+             which rd can't replay yet."
+        );
+    }
 
     // Find the text mapping of the main executable. This is complicated by the
     // fact that the kernel also loads the dynamic linker (if the main
@@ -1415,6 +1662,8 @@ fn finish_direct_mmap(
             backing_file_open_flags.bits()
         ) as i32;
     }
+    // Keep the fd numbers the recorded program knows about free.
+    let fd = remote.infallible_move_fd_to_reserved_range(fd);
     // And mmap that file.
     remote.infallible_mmap_syscall(
         Some(rec_addr),
@@ -1483,6 +1732,18 @@ fn handle_opened_files(t: &mut ReplayTask, flags_raw: i32) {
             file_monitor = Box::new(ProcMemMonitor::new(t, &o.path));
         } else if is_proc_fd_dir(&o.path) {
             file_monitor = Box::new(ProcFdDirMonitor::new(t, &o.path));
+        } else if is_random_device(&o.path) {
+            // Only there if the recording had a random seed.
+            file_monitor = Box::new(RandomDeviceMonitor::new());
+        } else if matches_any(
+            t.session()
+                .as_replay()
+                .unwrap()
+                .trace_reader()
+                .capture_reads(),
+            &o.path,
+        ) {
+            file_monitor = Box::new(CaptureReadsMonitor::new());
         } else if flags.contains(OFlag::O_DIRECT) {
             file_monitor = Box::new(BaseFileMonitor::new())
         } else {
@@ -1537,6 +1798,8 @@ fn process_mmap(
                 prot,
                 flags,
             );
+        } else if is_io_uring_fd(remote.task(), fd) {
+            finish_io_uring_mmap(&mut remote, addr, prot, flags);
         } else {
             let mut data = MappedData::default();
             let mut extra_fds: Vec<TraceRemoteFd> = Vec::new();
@@ -1772,9 +2035,9 @@ fn finish_shared_mmap<'a>(
         if remote.task().rec_tid == fd.tid {
             process(remote.task_mut(), fd);
         } else {
-            match remote.task().session().find_task_from_rec_tid(fd.tid) {
-                Some(shr_ptr) => {
-                    let mut t_b = shr_ptr.borrow_mut();
+            match remote.task().session().find_task_handle(fd.tid) {
+                Some(handle) => {
+                    let mut t_b = handle.borrow_mut();
                     process(t_b.as_mut(), fd);
                 }
                 None => {
@@ -1889,6 +2152,58 @@ fn write_mapped_data(
     }
 }
 
+/// Map the part of an io_uring ring the tracee mapped during recording.
+/// There's no ring during replay, see `io_uring`: shared anonymous memory
+/// with what the kernel had put in the ring stands in for it, and replaying
+/// io_uring_enter() writes the rest.
+fn finish_io_uring_mmap(
+    remote: &mut AutoRemoteSyscalls,
+    rec_addr: RemotePtr<Void>,
+    prot: ProtFlags,
+    flags: MapFlags,
+) {
+    let mut data = MappedData::default();
+    let recorded_km: KernelMapping = remote
+        .task()
+        .as_replay_task()
+        .unwrap()
+        .trace_reader_mut()
+        .read_mapped_region(Some(&mut data), None, None, None, None)
+        .unwrap();
+    ed_assert!(remote.task(), data.source == MappedDataSource::SourceTrace);
+    let flags = flags | MapFlags::MAP_ANONYMOUS;
+    remote.infallible_mmap_syscall(
+        Some(rec_addr),
+        recorded_km.size(),
+        prot,
+        flags | MapFlags::MAP_FIXED,
+        -1,
+        0,
+    );
+    remote.task().vm_shr_ptr().map(
+        remote.task(),
+        rec_addr,
+        recorded_km.size(),
+        prot,
+        flags,
+        0,
+        OsStr::new(""),
+        KernelMapping::NO_DEVICE,
+        KernelMapping::NO_INODE,
+        None,
+        Some(&recorded_km),
+        None,
+        None,
+        None,
+    );
+    write_mapped_data(
+        remote.task_mut().as_replay_task_mut().unwrap(),
+        rec_addr,
+        recorded_km.size(),
+        &data,
+    );
+}
+
 fn finish_anonymous_mmap(
     remote: &mut AutoRemoteSyscalls,
     rec_addr: RemotePtr<Void>,
@@ -1970,9 +2285,11 @@ fn process_mremap(t: &mut ReplayTask, trace_regs: &Registers, step: &mut ReplayT
 
     let original_syscallno: i32 = trace_regs.original_syscallno() as i32;
     let old_addr: RemotePtr<Void> = trace_regs.arg1().into();
-    let old_size: usize = ceil_page_size(trace_regs.arg2());
+    // The recording kernel rounded the sizes up to its own page size.
+    let trace_page_size = t.trace_reader().page_size();
+    let old_size: usize = ceil_page_size_for(trace_regs.arg2(), trace_page_size);
     let new_addr: RemotePtr<Void> = trace_regs.syscall_result().into();
-    let new_size: usize = ceil_page_size(trace_regs.arg3());
+    let new_size: usize = ceil_page_size_for(trace_regs.arg3(), trace_page_size);
 
     // The recorded mremap call succeeded, so we know the original mapping can be
     // treated as a single mapping.
@@ -2187,3 +2504,22 @@ fn process_shmdt(
     }
     t.validate_regs(ReplayTaskIgnore::default());
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arch::X64Arch;
+
+    #[test]
+    fn executed_syscalls() {
+        assert!(replay_executes_syscall::<X64Arch>(X64Arch::MPROTECT));
+        assert!(replay_executes_syscall::<X64Arch>(X64Arch::MUNMAP));
+        assert!(replay_executes_syscall::<X64Arch>(X64Arch::MADVISE));
+        assert!(!replay_executes_syscall::<X64Arch>(X64Arch::READ));
+        assert!(!replay_executes_syscall::<X64Arch>(X64Arch::MREMAP));
+        assert!(!replay_executes_syscall::<X64Arch>(X64Arch::MMAP));
+
+        assert!(replay_executes_failed_syscall::<X64Arch>(X64Arch::MPROTECT));
+        assert!(!replay_executes_failed_syscall::<X64Arch>(X64Arch::MUNMAP));
+    }
+}
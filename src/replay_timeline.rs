@@ -0,0 +1,397 @@
+use crate::{
+    cancellation_token::CancellationToken,
+    log::LogLevel::LogDebug,
+    remote_code_ptr::RemoteCodePtr,
+    remote_ptr::{RemotePtr, Void},
+    session::{
+        address_space::{AddressSpace, BreakpointCondition, BreakpointType, WatchType},
+        replay_session::{ReplayResult, ReplayStatus, StepConstraints},
+        session_inner::RunCommand,
+        Session,
+        SessionSharedPtr,
+    },
+    taskish_uid::AddressSpaceUid,
+    ticks::Ticks,
+    trace::{trace_frame::FrameTime, trace_reader::first_event_with_ticks},
+};
+use libc::pid_t;
+use std::{collections::HashMap, rc::Rc};
+
+/// Moves a replay to points given as a task's tick count, which is much
+/// finer-grained than events: every retired conditional branch (or taken
+/// branch, depending on the trace's ticks semantics) is a possible target.
+/// Seeking backwards starts over with a new session on the same trace.
+///
+/// Seeks can take minutes on long traces; they check a `CancellationToken`
+/// between replay steps and give up when it's cancelled.
+///
+/// User breakpoints and watchpoints are set through the timeline, which
+/// remembers them (by address space, whose uid is the same in every session
+/// on the trace) and sets them again in the session a seek backwards starts,
+/// as soon as their address space exists there.
+pub struct ReplayTimeline {
+    current: SessionSharedPtr,
+    /// `TraceReader::task_ticks()` by task, which takes reading the whole
+    /// trace.
+    task_ticks_in_trace: HashMap<pid_t, Vec<(FrameTime, Ticks)>>,
+    breakpoints: Vec<UserBreakpoint>,
+    watchpoints: Vec<UserWatchpoint>,
+    /// The last step stopped at a user breakpoint, which needs stepping over
+    /// before replay can go on.
+    at_breakpoint: bool,
+}
+
+struct UserBreakpoint {
+    vm: AddressSpaceUid,
+    addr: RemoteCodePtr,
+    condition: Option<BreakpointCondition>,
+    /// Set in the current session.
+    is_set: bool,
+}
+
+struct UserWatchpoint {
+    vm: AddressSpaceUid,
+    addr: RemotePtr<Void>,
+    num_bytes: usize,
+    type_: WatchType,
+    /// Set in the current session.
+    is_set: bool,
+}
+
+impl ReplayTimeline {
+    pub fn new(session: SessionSharedPtr) -> ReplayTimeline {
+        debug_assert!(session.as_replay().is_some());
+        ReplayTimeline {
+            current: session,
+            task_ticks_in_trace: HashMap::new(),
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            at_breakpoint: false,
+        }
+    }
+
+    pub fn current_session(&self) -> SessionSharedPtr {
+        self.current.clone()
+    }
+
+    /// The tick count of (recorded) task `tid` in the current session, or
+    /// `None` if it doesn't exist at this point of the replay.
+    pub fn task_ticks(&self, tid: pid_t) -> Option<Ticks> {
+        self.current
+            .find_task_from_rec_tid(tid)
+            .map(|t| t.borrow().tick_count())
+    }
+
+    /// Set a user breakpoint at `addr` in the address space of (recorded)
+    /// task `tid`. Returns false if there's no such task or the breakpoint
+    /// can't be set.
+    pub fn add_breakpoint(&mut self, tid: pid_t, addr: RemoteCodePtr) -> bool {
+        let t = match self.current.find_task_from_rec_tid(tid) {
+            Some(t) => t,
+            None => return false,
+        };
+        let vm = t.borrow().vm_shr_ptr();
+        if !vm.add_breakpoint(t.borrow_mut().as_mut(), addr, BreakpointType::BkptUser) {
+            return false;
+        }
+        self.breakpoints.push(UserBreakpoint {
+            vm: vm.uid(),
+            addr,
+            condition: None,
+            is_set: true,
+        });
+        true
+    }
+
+    pub fn remove_breakpoint(&mut self, tid: pid_t, addr: RemoteCodePtr) {
+        let t = match self.current.find_task_from_rec_tid(tid) {
+            Some(t) => t,
+            None => return,
+        };
+        let vm = t.borrow().vm_shr_ptr();
+        let uid = vm.uid();
+        let maybe_i = self
+            .breakpoints
+            .iter()
+            .position(|bp| bp.vm == uid && bp.addr == addr);
+        if let Some(i) = maybe_i {
+            if self.breakpoints.remove(i).is_set {
+                vm.remove_breakpoint(addr, BreakpointType::BkptUser, t.borrow_mut().as_mut());
+            }
+        }
+    }
+
+    /// Set a watchpoint on `num_bytes` at `addr` in the address space of
+    /// (recorded) task `tid`. Returns false if there's no such task or the
+    /// watchpoint can't be set.
+    pub fn add_watchpoint(
+        &mut self,
+        tid: pid_t,
+        addr: RemotePtr<Void>,
+        num_bytes: usize,
+        type_: WatchType,
+    ) -> bool {
+        let t = match self.current.find_task_from_rec_tid(tid) {
+            Some(t) => t,
+            None => return false,
+        };
+        let vm = t.borrow().vm_shr_ptr();
+        if !vm.add_watchpoint(addr, num_bytes, type_, t.borrow_mut().as_mut()) {
+            return false;
+        }
+        self.watchpoints.push(UserWatchpoint {
+            vm: vm.uid(),
+            addr,
+            num_bytes,
+            type_,
+            is_set: true,
+        });
+        true
+    }
+
+    pub fn remove_watchpoint(
+        &mut self,
+        tid: pid_t,
+        addr: RemotePtr<Void>,
+        num_bytes: usize,
+        type_: WatchType,
+    ) {
+        let t = match self.current.find_task_from_rec_tid(tid) {
+            Some(t) => t,
+            None => return,
+        };
+        let vm = t.borrow().vm_shr_ptr();
+        let uid = vm.uid();
+        let maybe_i = self.watchpoints.iter().position(|wp| {
+            wp.vm == uid && wp.addr == addr && wp.num_bytes == num_bytes && wp.type_ == type_
+        });
+        if let Some(i) = maybe_i {
+            if self.watchpoints.remove(i).is_set {
+                vm.remove_watchpoint(addr, num_bytes, type_, t.borrow_mut().as_mut());
+            }
+        }
+    }
+
+    /// Take one replay step forward.
+    pub fn replay_step(&mut self, command: RunCommand) -> ReplayResult {
+        self.step(StepConstraints::new(command))
+    }
+
+    /// Take one replay step with `constraints`, with the user breakpoints
+    /// and watchpoints set. If the last step stopped at a user breakpoint,
+    /// the breakpoint is stepped over first so that resuming doesn't report
+    /// it again.
+    fn step(&mut self, constraints: StepConstraints) -> ReplayResult {
+        self.set_breakpoints_and_watchpoints();
+        let replay = self.current.as_replay().unwrap();
+        let maybe_t = replay.current_task();
+        if let (true, Some(t)) = (self.at_breakpoint, maybe_t) {
+            self.at_breakpoint = false;
+            let ip = t.borrow().ip();
+            let vm = t.borrow().vm_shr_ptr();
+            if vm.get_breakpoint_type_at_addr(ip) == BreakpointType::BkptUser {
+                vm.suspend_breakpoint_at(ip);
+                let mut result = replay.replay_step(RunCommand::RunSinglestep);
+                vm.restore_breakpoint_at(ip);
+                if constraints.is_singlestep() {
+                    return result;
+                }
+                result.break_status.singlestep_complete = false;
+                if result.status != ReplayStatus::ReplayContinue || result.break_status.any_break()
+                {
+                    return result;
+                }
+            }
+        }
+        let result = replay.replay_step_with_constraints(constraints);
+        self.at_breakpoint = result.break_status.breakpoint_hit;
+        result
+    }
+
+    /// Set the user breakpoints and watchpoints that aren't set in the
+    /// current session yet in their address space, if it exists by now.
+    fn set_breakpoints_and_watchpoints(&mut self) {
+        let pending = self.breakpoints.iter().any(|bp| !bp.is_set)
+            || self.watchpoints.iter().any(|wp| !wp.is_set);
+        if !pending {
+            return;
+        }
+        let vms = self.current.as_session_inner().vms();
+        let find_vm = |uid: AddressSpaceUid| -> Option<Rc<AddressSpace>> {
+            vms.iter().find(|vm| vm.uid() == uid).cloned()
+        };
+        for bp in self.breakpoints.iter_mut().filter(|bp| !bp.is_set) {
+            let vm = match find_vm(bp.vm) {
+                Some(vm) => vm,
+                None => continue,
+            };
+            let maybe_t = vm.task_set().iter().next();
+            // This fails until the memory is mapped, so keep trying.
+            if let Some(t) = maybe_t {
+                let mut t_ref = t.borrow_mut();
+                bp.is_set = vm.add_breakpoint(t_ref.as_mut(), bp.addr, BreakpointType::BkptUser);
+                if bp.is_set {
+                    vm.set_breakpoint_condition(bp.addr, bp.condition);
+                }
+            }
+        }
+        for wp in self.watchpoints.iter_mut().filter(|wp| !wp.is_set) {
+            let vm = match find_vm(wp.vm) {
+                Some(vm) => vm,
+                None => continue,
+            };
+            let maybe_t = vm.task_set().iter().next();
+            if let Some(t) = maybe_t {
+                let mut t_ref = t.borrow_mut();
+                wp.is_set = vm.add_watchpoint(wp.addr, wp.num_bytes, wp.type_, t_ref.as_mut());
+            }
+        }
+    }
+
+    /// Replace the current session with a new one at the start of the trace,
+    /// where the user breakpoints and watchpoints are set again as replay
+    /// gets to them.
+    fn restart(&mut self) {
+        self.current = self.current.as_replay().unwrap().create_on_same_trace();
+        self.at_breakpoint = false;
+        for bp in &mut self.breakpoints {
+            bp.is_set = false;
+        }
+        for wp in &mut self.watchpoints {
+            wp.is_set = false;
+        }
+    }
+
+    /// Attach `condition` to the user breakpoint at `addr` in the address
+    /// space of (recorded) task `tid`, see
+    /// `AddressSpace::set_breakpoint_condition()`. Returns false if there's no
+    /// such task or breakpoint.
+    pub fn set_breakpoint_condition(
+        &mut self,
+        tid: pid_t,
+        addr: RemoteCodePtr,
+        condition: Option<BreakpointCondition>,
+    ) -> bool {
+        let vm = match self.current.find_task_from_rec_tid(tid) {
+            Some(t) => t.borrow().vm_shr_ptr(),
+            None => return false,
+        };
+        if !vm.set_breakpoint_condition(addr, condition) {
+            return false;
+        }
+        let uid = vm.uid();
+        for bp in &mut self.breakpoints {
+            if bp.vm == uid && bp.addr == addr {
+                bp.condition = condition;
+            }
+        }
+        true
+    }
+
+    /// Replay to the start of `event`, i.e. until it's the next event to be
+    /// replayed, restarting if we're already past it. Returns false if replay
+    /// stopped (exited, diverged) or was cancelled before getting there.
+    pub fn seek_to_event(&mut self, event: FrameTime, cancel: &CancellationToken) -> bool {
+        if self.current.as_replay().unwrap().current_frame_time() > event {
+            log!(
+                LogDebug,
+                "Restarting replay to seek back to event {}",
+                event
+            );
+            self.restart();
+        }
+        self.replay_to_event(event, cancel)
+    }
+
+    /// Replay to the point where task `tid` has executed exactly `ticks`
+    /// ticks. We replay to the event during which it gets there, run until
+    /// the ticks counter interrupt says we're close, then singlestep.
+    ///
+    /// Returns false if the task never gets to `ticks`, or replay stopped
+    /// (exited, diverged), moved past the event, switched to another task or
+    /// was cancelled on the way; the session is left wherever that happened.
+    pub fn seek_to_ticks(&mut self, tid: pid_t, ticks: Ticks, cancel: &CancellationToken) -> bool {
+        let current = self.current.clone();
+        let task_ticks = self
+            .task_ticks_in_trace
+            .entry(tid)
+            .or_insert_with(|| current.as_replay().unwrap().trace_reader().task_ticks(tid));
+        let target_event = match first_event_with_ticks(task_ticks, ticks) {
+            Some(event) => event,
+            None => return false,
+        };
+        let current_time = self.current.as_replay().unwrap().current_frame_time();
+        let past_target = match self.task_ticks(tid) {
+            Some(current_ticks) => current_ticks > ticks,
+            None => false,
+        };
+        if current_time > target_event || past_target {
+            log!(
+                LogDebug,
+                "Restarting replay to seek back to {} ticks",
+                ticks
+            );
+            self.restart();
+        }
+
+        if !self.replay_to_event(target_event, cancel) {
+            return false;
+        }
+
+        // Get close with the ticks counter interrupt...
+        let mut constraints = StepConstraints::new(RunCommand::RunContinue);
+        constraints.ticks_target = ticks;
+        while self.task_ticks(tid).map_or(false, |t| t < ticks) {
+            if cancel.is_cancelled() || !self.is_current_task(tid) {
+                return false;
+            }
+            let result = self.step(constraints.clone());
+            if result.status != ReplayStatus::ReplayContinue || self.moved_past(target_event) {
+                return false;
+            }
+            if result.break_status.approaching_ticks_target {
+                break;
+            }
+        }
+        // ... and cover the rest (at most the skid) one instruction at a time.
+        while self.task_ticks(tid).map_or(false, |t| t < ticks) {
+            // Singlestepping steps whichever task replay runs next, which
+            // must be `tid` for it to get any closer.
+            if cancel.is_cancelled() || !self.is_current_task(tid) {
+                return false;
+            }
+            let result = self.replay_step(RunCommand::RunSinglestep);
+            if result.status != ReplayStatus::ReplayContinue || self.moved_past(target_event) {
+                return false;
+            }
+        }
+        self.task_ticks(tid) == Some(ticks)
+    }
+
+    /// Replay until `event` is the next event to be replayed.
+    fn replay_to_event(&mut self, event: FrameTime, cancel: &CancellationToken) -> bool {
+        let mut constraints = StepConstraints::new(RunCommand::RunContinue);
+        constraints.stop_at_time = event;
+        while self.current.as_replay().unwrap().current_frame_time() < event {
+            if cancel.is_cancelled() {
+                log!(LogDebug, "Seek to event {} cancelled", event);
+                return false;
+            }
+            let result = self.step(constraints.clone());
+            if result.status != ReplayStatus::ReplayContinue {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Whether (recorded) task `tid` is the task replay runs next.
+    fn is_current_task(&self, tid: pid_t) -> bool {
+        let maybe_t = self.current.as_replay().unwrap().current_task();
+        maybe_t.map_or(false, |t| t.borrow().rec_tid == tid)
+    }
+
+    fn moved_past(&self, event: FrameTime) -> bool {
+        self.current.as_replay().unwrap().current_frame_time() > event
+    }
+}
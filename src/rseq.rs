@@ -0,0 +1,84 @@
+//! Restartable sequences. Glibc registers an rseq area for every thread at
+//! startup. rd emulates the rseq() syscall instead of letting the kernel see
+//! the registration: the kernel aborts critical sections whenever a thread is
+//! preempted, migrated or signalled, which depends on things outside the
+//! recording. rd aborts them itself at the points where it preempts tasks,
+//! during recording and at the same points during replay.
+
+use crate::{
+    kernel_abi::common::{rseq, rseq_cs},
+    log::LogLevel::{LogDebug, LogWarn},
+    remote_code_ptr::RemoteCodePtr,
+    remote_ptr::RemotePtr,
+    session::task::{
+        task_common::{read_val_mem, write_val_mem},
+        Task,
+    },
+};
+use std::mem::size_of;
+
+/// A thread's rseq() registration.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct RseqState {
+    pub ptr: RemotePtr<rseq>,
+    /// The 4 bytes that must precede every abort handler.
+    pub abort_prefix_signature: u32,
+}
+
+/// If `t` is inside an rseq critical section, abort it the way the kernel
+/// would: clear `rseq_cs` and move the ip to the abort handler. Returns true
+/// if the ip moved.
+///
+/// Call this when `t` is preempted, after the event for the preemption has
+/// been recorded (or replayed), and before a signal handler frame is set up
+/// for it.
+pub fn abort_rseq_critical_section(t: &mut dyn Task) -> bool {
+    let state = match t.rseq_state {
+        Some(state) => state,
+        None => return false,
+    };
+    let rseq_cs_field =
+        RemotePtr::<u64>::new_from_val(state.ptr.as_usize() + offset_of!(rseq, rseq_cs));
+    let mut ok = true;
+    let cs_addr = read_val_mem(t, rseq_cs_field, Some(&mut ok));
+    if !ok || cs_addr == 0 {
+        return false;
+    }
+    let cs: rseq_cs = read_val_mem(t, RemotePtr::new_from_val(cs_addr as usize), Some(&mut ok));
+    if !ok {
+        log!(LogWarn, "Can't read rseq_cs at {:#x}", cs_addr);
+        return false;
+    }
+    // The kernel clears rseq_cs whenever it looks at it, whether or not it
+    // aborts.
+    write_val_mem(t, rseq_cs_field, &0u64, None);
+
+    let ip = t.ip().register_value() as u64;
+    if ip < cs.start_ip || ip - cs.start_ip >= cs.post_commit_offset {
+        return false;
+    }
+    let signature_addr = RemotePtr::<u32>::new_from_val(cs.abort_ip as usize - size_of::<u32>());
+    let signature = read_val_mem(t, signature_addr, Some(&mut ok));
+    if !ok || signature != state.abort_prefix_signature {
+        // The kernel would SIGSEGV the thread.
+        log!(
+            LogWarn,
+            "rseq abort handler at {:#x} has signature {:#x}, expected {:#x}",
+            cs.abort_ip,
+            signature,
+            state.abort_prefix_signature
+        );
+        return false;
+    }
+    log!(
+        LogDebug,
+        "{}: aborting rseq critical section at {:#x}, resuming at {:#x}",
+        t.tid,
+        ip,
+        cs.abort_ip
+    );
+    let mut r = t.regs_ref().clone();
+    r.set_ip(RemoteCodePtr::from_val(cs.abort_ip as usize));
+    t.set_regs(&r);
+    true
+}
@@ -18,11 +18,11 @@
 //! The scheduler only runs during recording. During replay we're just replaying
 //! the recorded scheduling decisions.
 //!
-//! The main interface to the scheduler is `get_next_thread`. This gets called
+//! The main interface to the scheduler is `reschedule`. This gets called
 //! after every rd event to decide which task to run next.
 //!
 //! The scheduler gives the current task a 'timeslice', a ticks deadline after
-//! which we will try to switch to another task. So `get_next_thread` first
+//! which we will try to switch to another task. So `reschedule` first
 //! checks whether the currently running task has exceeded that deadline. If
 //! not, and the current task is runnable, we schedule it again. If it's blocked
 //! or has exceeded its deadline, we search for another task to run:
@@ -33,19 +33,105 @@
 //! current task (so equal priority tasks run in round-robin order).
 //!
 //! The main parameter to the scheduler is `max_ticks`, which controls the
-//! length of each timeslice.
+//! length of each timeslice. In chaos mode timeslice lengths are drawn from
+//! `chaos_ticks_range` instead, which defaults to 0..`max_ticks`.
+//!
+//! In round-robin mode (see `set_round_robin_quantum`) priorities are ignored
+//! altogether: every task lives on the round-robin queue and runnable tasks
+//! take turns in queue order, each for exactly the same number of ticks. This
+//! makes hangs that depend on some thread getting to run reproducible.
+//!
+//! A vfork() parent is never runnable between its PTRACE_EVENT_VFORK and the
+//! exec or exit of its child: the kernel won't let it return from vfork()
+//! any earlier, so scheduling it would just wait for the child to give up
+//! the CPU. Replay follows the recorded schedule and so runs the child first
+//! too.
+//!
+//! In chaos mode the scheduler deliberately makes bad decisions to shake out
+//! intermittent bugs: timeslice lengths are randomized over several orders of
+//! magnitude, task priorities are periodically rerandomized and there are
+//! randomly placed intervals during which only the highest priority tasks may
+//! run (starving everyone else). All randomness comes from a single seeded
+//! RNG so a problematic schedule can be reproduced by recording again with the
+//! same seed.
 
-use crate::{session::task::record_task::record_task::RecordTask, ticks::Ticks};
-use libc::cpu_set_t;
+use crate::{
+    event::Switchable,
+    log::LogLevel::LogDebug,
+    perf_counters::TIME_SLICE_SIGNAL,
+    session::task::{
+        record_task::{record_task::RecordTask, EmulatedStopType},
+        task_inner::{TicksRequest, MAX_TICKS_REQUEST},
+        Task,
+        TaskSharedPtr,
+        TaskSharedWeakPtr,
+    },
+    ticks::Ticks,
+    util::monotonic_now_sec,
+    weak_ptr_set::WeakPtrWrap,
+};
+use libc::{
+    cpu_set_t,
+    pid_t,
+    poll,
+    pollfd,
+    siginfo_t,
+    waitid,
+    CPU_SET,
+    CPU_SETSIZE,
+    EINTR,
+    POLLIN,
+    P_ALL,
+    SIGCONT,
+    WEXITED,
+    WNOHANG,
+    WNOWAIT,
+    WSTOPPED,
+    __WALL,
+};
+use nix::errno::errno;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use std::{
-    cell::RefCell,
+    cmp::min,
     collections::{BTreeSet, VecDeque},
-    rc::{Rc, Weak},
+    mem::{take, zeroed},
+    os::unix::io::RawFd,
+    rc::Rc,
+    thread::sleep,
+    time::Duration,
 };
 
 // Tasks sorted by priority.
-type TaskPrioritySet = BTreeSet<(i32, Weak<RefCell<RecordTask>>)>;
-type TaskQueue = VecDeque<Weak<RefCell<RecordTask>>>;
+type TaskPrioritySet = BTreeSet<(i32, WeakPtrWrap<Box<dyn Task>>)>;
+type TaskQueue = VecDeque<TaskSharedWeakPtr>;
+
+/// In chaos mode, the smallest interval (in seconds) during which only high
+/// priority tasks are allowed to run.
+const MIN_HIGH_PRIORITY_ONLY_DURATION: f64 = 0.2;
+/// In chaos mode, how many doublings of `MIN_HIGH_PRIORITY_ONLY_DURATION` an
+/// interval may have.
+const HIGH_PRIORITY_ONLY_DURATION_STEPS: u32 = 7;
+/// In chaos mode, the largest fraction of time we spend in high priority only
+/// intervals.
+const MAX_HIGH_PRIORITY_ONLY_FRACTION: f64 = 0.5;
+/// In chaos mode, how often we check whether a high priority only interval
+/// has ended, while only low priority tasks are runnable.
+const HIGH_PRIORITY_ONLY_POLL_INTERVAL: Duration = Duration::from_millis(1);
+/// How often we check for child state changes while also waiting on the
+/// interception backend.
+const INTERCEPTION_POLL_INTERVAL: Duration = Duration::from_millis(1);
+/// In chaos mode, how often (at most, in seconds) we pick new high priority
+/// only interval parameters.
+const HIGH_PRIORITY_ONLY_INTERVALS_REFRESH_MAX: f64 = 20.0;
+/// In chaos mode, how often (at most, in seconds) we rerandomize priorities.
+const PRIORITIES_REFRESH_MAX_INTERVAL: f64 = 20.0;
+/// In chaos mode, timeslices are shortened by a random power of ten up to
+/// this exponent.
+const CHAOS_TIMESLICE_MAX_EXPONENT: u32 = 6;
+
+/// Priorities chosen by chaos mode. Lower values mean higher priority.
+const CHAOS_HIGH_PRIORITY: i32 = 0;
+const CHAOS_LOW_PRIORITY: i32 = 1;
 
 pub struct Scheduler {
     // @TODO figure this out. Currently Session owns a scheduler
@@ -60,9 +146,9 @@ pub struct Scheduler {
     task_priority_set: TaskPrioritySet,
     task_round_robin_queue: TaskQueue,
 
-    /// The currently scheduled task. This may be `None` if the last scheduled
-    /// task has been destroyed.
-    current_: Option<Rc<RefCell<RecordTask>>>,
+    /// The currently scheduled task. The pointer is dead if the last
+    /// scheduled task has been destroyed.
+    current_: Option<TaskSharedWeakPtr>,
     current_timeslice_end_: Ticks,
 
     /// At this time (or later) we should refresh these values.
@@ -74,17 +160,37 @@ pub struct Scheduler {
     priorities_refresh_time: f64,
 
     max_ticks_: Ticks,
+    /// The bounds of timeslice lengths in chaos mode, if set explicitly.
+    /// Otherwise they're 0 and `max_ticks_`.
+    chaos_ticks_range_: Option<(Ticks, Ticks)>,
+    /// The fixed timeslice length in round-robin mode, or `None` when tasks
+    /// are scheduled by priority.
+    round_robin_quantum_: Option<Ticks>,
+    /// In chaos mode, the CPUs tasks are moved between as their timeslices
+    /// start. Empty unless migration was asked for.
+    chaos_migration_cpus_: Vec<u32>,
 
-    must_run_task: RecordTask,
+    /// DIFF NOTE: This is a raw pointer in rr that may be null.
+    must_run_task: Option<TaskSharedWeakPtr>,
 
     pretend_affinity_mask_: cpu_set_t,
     pretend_num_cores_: u32,
 
     /// When true, context switch at every possible point.
     always_switch: bool,
+    /// The interception backend's fd, to watch along with waitpid() when
+    /// every task is blocked. Tasks in syscalls it intercepts are blocked
+    /// without any ptrace stop to come.
+    interception_fd: Option<RawFd>,
     /// When true, make random scheduling decisions to try to increase the
     /// probability of finding buggy schedules.
     enable_chaos: bool,
+    /// The seed `rng` was created from. Printed so that a chaos mode recording
+    /// can be repeated with the same scheduling decisions (modulo the timing
+    /// based ones).
+    chaos_seed: u64,
+    /// DIFF NOTE: rr uses the libc random() for chaos decisions.
+    rng: StdRng,
 
     enable_poll: bool,
     last_reschedule_in_high_priority_only_interval: bool,
@@ -111,8 +217,695 @@ enum TickHowMany {
     DefaultMaxTicks = 500000,
 }
 
+/// What `Scheduler::reschedule()` decided.
+pub enum Rescheduled {
+    /// Run `t` next. It's stopped, with a status to process. If
+    /// `started_new_timeslice` it wasn't the current task, or the current
+    /// task's timeslice had expired.
+    Task {
+        t: TaskSharedPtr,
+        started_new_timeslice: bool,
+    },
+    /// No task is runnable and waitpid() has a status for `tid`, which
+    /// isn't a task the scheduler knows about: a zombie thread group leader,
+    /// or a new task whose creation hasn't been processed yet. The status
+    /// hasn't been reaped.
+    UnknownTid(pid_t),
+    /// No task is runnable and the interception backend has syscalls to
+    /// deal with.
+    Intercepted,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Scheduler {
+    pub fn new() -> Scheduler {
+        let chaos_seed = rand::random::<u64>();
+        Scheduler {
+            task_priority_set: Default::default(),
+            task_round_robin_queue: Default::default(),
+            current_: None,
+            current_timeslice_end_: 0,
+            high_priority_only_intervals_refresh_time: 0.0,
+            high_priority_only_intervals_start: 0.0,
+            high_priority_only_intervals_duration: 0.0,
+            high_priority_only_intervals_period: 0.0,
+            priorities_refresh_time: 0.0,
+            max_ticks_: TickHowMany::DefaultMaxTicks as Ticks,
+            chaos_ticks_range_: None,
+            round_robin_quantum_: None,
+            chaos_migration_cpus_: Vec::new(),
+            must_run_task: None,
+            pretend_affinity_mask_: unsafe { zeroed() },
+            pretend_num_cores_: 1,
+            always_switch: false,
+            interception_fd: None,
+            enable_chaos: false,
+            chaos_seed,
+            rng: StdRng::seed_from_u64(chaos_seed),
+            enable_poll: false,
+            last_reschedule_in_high_priority_only_interval: false,
+        }
+    }
+
+    /// The task most recently returned by `reschedule()`, if it's still
+    /// alive.
+    pub fn current(&self) -> Option<TaskSharedPtr> {
+        self.current_.as_ref().and_then(|w| w.upgrade())
+    }
+
+    /// Make `t` run before any other task, even when switching is allowed,
+    /// until `reschedule()` returns it. rd uses this to process events it
+    /// has made pending for `t` itself.
+    pub fn set_must_run_task(&mut self, t: &TaskSharedPtr) {
+        self.must_run_task = Some(Rc::downgrade(t));
+    }
+
     pub fn expire_timeslice(&mut self) {
         self.current_timeslice_end_ = 0;
     }
+
+    pub fn current_timeslice_end(&self) -> Ticks {
+        self.current_timeslice_end_
+    }
+
+    pub fn max_ticks(&self) -> Ticks {
+        self.max_ticks_
+    }
+
+    pub fn set_max_ticks(&mut self, max_ticks: Ticks) {
+        debug_assert!(max_ticks > 0 && max_ticks <= MAX_TICKS_REQUEST);
+        self.max_ticks_ = max_ticks;
+    }
+
+    pub fn chaos_ticks_range(&self) -> (Ticks, Ticks) {
+        self.chaos_ticks_range_.unwrap_or((0, self.max_ticks_))
+    }
+
+    /// In chaos mode, make timeslices between `low` and `high` ticks long.
+    pub fn set_chaos_ticks_range(&mut self, low: Ticks, high: Ticks) {
+        debug_assert!(low < high && high <= MAX_TICKS_REQUEST);
+        self.chaos_ticks_range_ = Some((low, high));
+    }
+
+    /// The ticks request to resume the current task `t` with so that it's
+    /// interrupted at the end of its timeslice.
+    pub fn timeslice_ticks_request(&self, t: &RecordTask) -> TicksRequest {
+        let remaining = self
+            .current_timeslice_end_
+            .saturating_sub(t.tick_count())
+            .max(1)
+            .min(MAX_TICKS_REQUEST);
+        TicksRequest::ResumeWithTicksRequest(remaining)
+    }
+
+    pub fn round_robin_quantum(&self) -> Option<Ticks> {
+        self.round_robin_quantum_
+    }
+
+    /// Switch to round-robin mode with timeslices of `quantum` ticks, or back
+    /// to priority scheduling with `None`. Tasks keep their current order.
+    /// Tasks queued by sched_yield() stay queued unless we leave round-robin
+    /// mode, which had every task in the queue.
+    pub fn set_round_robin_quantum(&mut self, quantum: Option<Ticks>) {
+        debug_assert!(quantum.map_or(true, |q| q > 0 && q <= MAX_TICKS_REQUEST));
+        debug_assert!(quantum.is_none() || !self.enable_chaos);
+        let was_round_robin = self.round_robin_quantum_.is_some();
+        self.round_robin_quantum_ = quantum;
+        if was_round_robin == quantum.is_some() {
+            return;
+        }
+        if quantum.is_some() {
+            for (_, w) in take(&mut self.task_priority_set) {
+                let t = w.upgrade().unwrap();
+                t.borrow_mut()
+                    .as_record_task_mut()
+                    .unwrap()
+                    .in_round_robin_queue = true;
+                self.task_round_robin_queue.push_back(w.0);
+            }
+        } else {
+            for w in take(&mut self.task_round_robin_queue) {
+                let t = w.upgrade().unwrap();
+                let mut t_ref = t.borrow_mut();
+                let rt = t_ref.as_record_task_mut().unwrap();
+                rt.in_round_robin_queue = false;
+                self.task_priority_set.insert((rt.priority, WeakPtrWrap(w)));
+            }
+        }
+    }
+
+    /// Decide which task to run next. Unless `switchable` forbids it, or the
+    /// current task's timeslice hasn't run out and it's still runnable, that
+    /// is the next runnable task in round-robin order, or else in priority
+    /// order. Blocks until some task is runnable.
+    ///
+    /// When the current task can't be switched away from, it's waited for.
+    pub fn reschedule(&mut self, switchable: Switchable) -> Rescheduled {
+        let now = monotonic_now_sec();
+        self.maybe_reset_priorities(now);
+        self.maybe_reset_high_priority_only_intervals(now);
+        let high_priority_only = self.in_high_priority_only_interval(now);
+        if high_priority_only != self.last_reschedule_in_high_priority_only_interval {
+            log!(
+                LogDebug,
+                "{} high priority only interval",
+                if high_priority_only {
+                    "Entering"
+                } else {
+                    "Leaving"
+                }
+            );
+            self.last_reschedule_in_high_priority_only_interval = high_priority_only;
+        }
+
+        if let Some(t) = self.must_run_task.take().and_then(|w| w.upgrade()) {
+            return self.keep_running(t);
+        }
+        if let Some(current) = self.current() {
+            if switchable == Switchable::PreventSwitch {
+                return self.keep_running(current);
+            }
+            let current_priority = current.borrow().as_record_task().unwrap().priority;
+            let current_ticks = current.borrow().tick_count();
+            // A task that was interrupted for the end of its timeslice while
+            // the scheduler was busy couldn't expire it, so check its stop
+            // signal too.
+            let keep_current = !self.always_switch
+                && current_ticks < self.current_timeslice_end_
+                && (!high_priority_only || Some(current_priority) == self.top_priority())
+                && is_task_runnable(&current)
+                && current.borrow().status().maybe_stop_sig() != TIME_SLICE_SIGNAL;
+            if keep_current {
+                return Rescheduled::Task {
+                    t: current,
+                    started_new_timeslice: false,
+                };
+            }
+        }
+
+        let mut high_priority_only = high_priority_only;
+        loop {
+            let maybe_next = if self.round_robin_quantum_.is_some() {
+                self.next_round_robin_task(is_task_runnable)
+            } else {
+                self.next_task_by_priority(high_priority_only)
+            };
+            if let Some(t) = maybe_next {
+                log!(LogDebug, "Scheduling {}", t.borrow().tid);
+                self.current_ = Some(Rc::downgrade(&t));
+                self.setup_new_timeslice();
+                return Rescheduled::Task {
+                    t,
+                    started_new_timeslice: true,
+                };
+            }
+
+            if high_priority_only {
+                // Low priority tasks may be runnable, but have to starve until
+                // the interval ends.
+                sleep(HIGH_PRIORITY_ONLY_POLL_INTERVAL);
+                high_priority_only = self.in_high_priority_only_interval(monotonic_now_sec());
+                continue;
+            }
+
+            // Every task is blocked. Sleep until one of them, or some process
+            // we don't know about yet, changes state, or a task is blocked in
+            // an intercepted syscall.
+            match wait_for_any_child(self.interception_fd) {
+                Some(tid) if !self.knows_tid(tid) => return Rescheduled::UnknownTid(tid),
+                Some(_) => (),
+                None => return Rescheduled::Intercepted,
+            }
+        }
+    }
+
+    /// In round-robin mode, the task to run next: the first task in the queue
+    /// that `is_runnable`, which then goes to the back of the queue. Tasks
+    /// skipped over keep their places.
+    pub fn next_round_robin_task<F: FnMut(&TaskSharedPtr) -> bool>(
+        &mut self,
+        mut is_runnable: F,
+    ) -> Option<TaskSharedPtr> {
+        debug_assert!(self.round_robin_quantum_.is_some());
+        let pos = self
+            .task_round_robin_queue
+            .iter()
+            .position(|w| is_runnable(&w.upgrade().unwrap()))?;
+        let w = self.task_round_robin_queue.remove(pos).unwrap();
+        let t = w.upgrade().unwrap();
+        self.task_round_robin_queue.push_back(w);
+        Some(t)
+    }
+
+    /// The highest priority runnable task. Among tasks of the current task's
+    /// priority, the first one after it, so that they take turns. In a high
+    /// priority only interval only tasks of the highest priority may run.
+    fn next_task_by_priority(&mut self, high_priority_only: bool) -> Option<TaskSharedPtr> {
+        let current = self.current_.clone();
+        let current_priority = self
+            .current()
+            .map(|t| t.borrow().as_record_task().unwrap().priority);
+        let mut candidates: Vec<(i32, TaskSharedWeakPtr)> = self
+            .task_priority_set
+            .iter()
+            .map(|(priority, w)| (*priority, w.0.clone()))
+            .collect();
+        if let (Some(current), Some(current_priority)) = (current, current_priority) {
+            // Rotate the tasks with the current task's priority so that the
+            // ones after it come first.
+            let same: Vec<usize> = (0..candidates.len())
+                .filter(|&i| candidates[i].0 == current_priority)
+                .collect();
+            if let Some(pos) = same.iter().position(|&i| candidates[i].1.ptr_eq(&current)) {
+                let mut rotated: Vec<(i32, TaskSharedWeakPtr)> =
+                    same.iter().map(|&i| candidates[i].clone()).collect();
+                rotated.rotate_left(pos + 1);
+                for (&i, c) in same.iter().zip(rotated) {
+                    candidates[i] = c;
+                }
+            }
+        }
+
+        let top_priority = self.top_priority();
+        candidates
+            .into_iter()
+            .filter(|(priority, _)| !high_priority_only || Some(*priority) == top_priority)
+            .map(|(_, w)| w.upgrade().unwrap())
+            .find(is_task_runnable)
+    }
+
+    /// The highest priority any task has.
+    fn top_priority(&self) -> Option<i32> {
+        self.task_priority_set
+            .iter()
+            .next()
+            .map(|(priority, _)| *priority)
+    }
+
+    fn keep_running(&mut self, t: TaskSharedPtr) -> Rescheduled {
+        if t.borrow().is_running() {
+            t.borrow_mut().wait(None);
+        }
+        self.current_ = Some(Rc::downgrade(&t));
+        Rescheduled::Task {
+            t,
+            started_new_timeslice: false,
+        }
+    }
+
+    fn knows_tid(&self, tid: pid_t) -> bool {
+        self.task_priority_set
+            .iter()
+            .map(|(_, w)| &w.0)
+            .chain(self.task_round_robin_queue.iter())
+            .filter_map(|w| w.upgrade())
+            .any(|t| t.borrow().tid == tid)
+    }
+
+    pub fn set_interception_fd(&mut self, fd: Option<RawFd>) {
+        self.interception_fd = fd;
+    }
+
+    pub fn set_always_switch(&mut self, always_switch: bool) {
+        self.always_switch = always_switch;
+    }
+
+    pub fn enable_chaos(&self) -> bool {
+        self.enable_chaos
+    }
+
+    pub fn set_enable_chaos(&mut self, enable_chaos: bool) {
+        self.enable_chaos = enable_chaos;
+        // Force the next call to `maybe_reset_priorities()` and
+        // `maybe_reset_high_priority_only_intervals()` to pick new values.
+        self.priorities_refresh_time = 0.0;
+        self.high_priority_only_intervals_refresh_time = 0.0;
+        self.high_priority_only_intervals_start = 0.0;
+        self.high_priority_only_intervals_duration = 0.0;
+        self.high_priority_only_intervals_period = 0.0;
+    }
+
+    pub fn chaos_seed(&self) -> u64 {
+        self.chaos_seed
+    }
+
+    /// Reseed the RNG that drives all chaos mode decisions.
+    pub fn set_chaos_seed(&mut self, seed: u64) {
+        self.chaos_seed = seed;
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// In chaos mode, move tasks between `cpus` (if there's more than one).
+    pub fn set_chaos_migration_cpus(&mut self, cpus: Vec<u32>) {
+        self.chaos_migration_cpus_ = cpus;
+    }
+
+    /// The CPU to move a task that's starting a new timeslice to, if it
+    /// should move. In chaos mode with migration enabled, that's a random
+    /// one of the migration CPUs half the time.
+    pub fn choose_migration_cpu(&mut self) -> Option<u32> {
+        if !self.enable_chaos || self.chaos_migration_cpus_.len() < 2 || self.rng.gen_bool(0.5) {
+            return None;
+        }
+        let i = self.rng.gen_range(0, self.chaos_migration_cpus_.len());
+        Some(self.chaos_migration_cpus_[i])
+    }
+
+    /// How many CPUs tracees see in sched_getaffinity() and the /proc and
+    /// /sys files programs count CPUs with. By default 1, since rd runs
+    /// only one tracee at a time anyway.
+    pub fn pretend_num_cores(&self) -> u32 {
+        self.pretend_num_cores_
+    }
+
+    pub fn set_pretend_num_cores(&mut self, num_cores: u32) {
+        debug_assert!(num_cores > 0);
+        self.pretend_num_cores_ = num_cores;
+        let mut mask: cpu_set_t = unsafe { zeroed() };
+        for cpu in 0..min(num_cores as usize, CPU_SETSIZE as usize) {
+            unsafe { CPU_SET(cpu, &mut mask) };
+        }
+        self.pretend_affinity_mask_ = mask;
+    }
+
+    pub fn pretend_affinity_mask(&self) -> &cpu_set_t {
+        &self.pretend_affinity_mask_
+    }
+
+    /// Add `t` to the set of tasks the scheduler knows about.
+    pub fn on_create(&mut self, t: &TaskSharedPtr) {
+        let mut t_ref = t.borrow_mut();
+        let rt = t_ref.as_record_task_mut().unwrap();
+        debug_assert!(!rt.in_round_robin_queue);
+        if self.round_robin_quantum_.is_some() {
+            // New tasks wait for their turn behind everyone else.
+            rt.in_round_robin_queue = true;
+            self.task_round_robin_queue.push_back(Rc::downgrade(t));
+            return;
+        }
+        if self.enable_chaos {
+            // new tasks get a random priority
+            rt.priority = self.choose_random_priority();
+        }
+        self.task_priority_set
+            .insert((rt.priority, WeakPtrWrap(Rc::downgrade(t))));
+    }
+
+    /// De-register tasks that have been destroyed. Called while a task is
+    /// being dropped, when it can't be borrowed any more but its weak
+    /// pointers are already dead.
+    pub fn on_destroy(&mut self) {
+        if self
+            .current_
+            .as_ref()
+            .map_or(false, |current| current.strong_count() == 0)
+        {
+            self.current_ = None;
+        }
+        if self
+            .must_run_task
+            .as_ref()
+            .map_or(false, |must_run| must_run.strong_count() == 0)
+        {
+            self.must_run_task = None;
+        }
+        self.task_round_robin_queue.retain(|w| w.strong_count() > 0);
+        self.task_priority_set.retain(|(_, w)| w.strong_count() > 0);
+    }
+
+    /// Set the priority of `t` to `value` and update related state.
+    pub fn update_task_priority(&mut self, t: &TaskSharedPtr, value: i32) {
+        if !self.enable_chaos {
+            self.update_task_priority_internal(t, value);
+        }
+    }
+
+    /// Start a new timeslice for the current task. In round-robin mode it's
+    /// always the quantum long. In chaos mode the length of
+    /// the timeslice is random: some bugs only show up with very short
+    /// timeslices but we don't want the average timeslice to be too small.
+    /// So the top of the chaos range is divided by a random power of 10 (but
+    /// not below the bottom of the range) and then the actual length is
+    /// chosen uniformly between the bottom of the range and that.
+    pub fn setup_new_timeslice(&mut self) {
+        let start = self.current().map_or(0, |t| t.borrow().tick_count());
+        let duration = if let Some(quantum) = self.round_robin_quantum_ {
+            quantum
+        } else if self.enable_chaos {
+            let (low, high) = self.chaos_ticks_range();
+            let exponent = self.rng.gen_range(0, CHAOS_TIMESLICE_MAX_EXPONENT);
+            let max_timeslice_duration = (high / 10u64.pow(exponent)).max(low + 1);
+            self.rng.gen_range(low, max_timeslice_duration)
+        } else {
+            self.max_ticks_
+        };
+        self.current_timeslice_end_ = start + duration;
+    }
+
+    /// In chaos mode, periodically give every task a new random priority.
+    pub fn maybe_reset_priorities(&mut self, now: f64) {
+        if !self.enable_chaos || self.priorities_refresh_time > now {
+            return;
+        }
+        // Reset task priorities again at some point in the future.
+        self.priorities_refresh_time = now + self.random_frac() * PRIORITIES_REFRESH_MAX_INTERVAL;
+
+        let mut tasks: Vec<TaskSharedPtr> = Vec::new();
+        for (_, w) in &self.task_priority_set {
+            tasks.push(w.upgrade().unwrap());
+        }
+        for w in &self.task_round_robin_queue {
+            tasks.push(w.upgrade().unwrap());
+        }
+        for t in tasks {
+            let priority = self.choose_random_priority();
+            self.update_task_priority_internal(&t, priority);
+        }
+    }
+
+    /// In chaos mode, periodically pick a new duration, period and phase for
+    /// the intervals during which only high priority tasks may run.
+    pub fn maybe_reset_high_priority_only_intervals(&mut self, now: f64) {
+        if !self.enable_chaos || self.high_priority_only_intervals_refresh_time > now {
+            return;
+        }
+        let duration_step = self.rng.gen_range(0, HIGH_PRIORITY_ONLY_DURATION_STEPS);
+        self.high_priority_only_intervals_duration =
+            MIN_HIGH_PRIORITY_ONLY_DURATION * (1u32 << duration_step) as f64;
+        let period_step = self.rng.gen_range(1, 5);
+        // The period is at least twice the duration so that we're never in a
+        // high priority only interval more than MAX_HIGH_PRIORITY_ONLY_FRACTION
+        // of the time.
+        self.high_priority_only_intervals_period = self.high_priority_only_intervals_duration
+            / MAX_HIGH_PRIORITY_ONLY_FRACTION
+            * period_step as f64;
+        self.high_priority_only_intervals_start =
+            now + self.random_frac() * self.high_priority_only_intervals_period;
+        self.high_priority_only_intervals_refresh_time = now
+            + self.high_priority_only_intervals_period
+            + self.random_frac() * HIGH_PRIORITY_ONLY_INTERVALS_REFRESH_MAX;
+    }
+
+    /// True if at time `now` only high priority tasks should be scheduled.
+    /// Low priority tasks are starved for the duration of the interval.
+    pub fn in_high_priority_only_interval(&self, now: f64) -> bool {
+        if !self.enable_chaos
+            || now < self.high_priority_only_intervals_start
+            || self.high_priority_only_intervals_period <= 0.0
+        {
+            return false;
+        }
+        let offset = (now - self.high_priority_only_intervals_start)
+            % self.high_priority_only_intervals_period;
+        offset < self.high_priority_only_intervals_duration
+    }
+
+    /// True if `t` has the highest priority of all tasks and there's more than
+    /// one priority level in use.
+    pub fn treat_as_high_priority(&self, t: &RecordTask) -> bool {
+        match (
+            self.task_priority_set.iter().next(),
+            self.task_priority_set.iter().next_back(),
+        ) {
+            (Some((first, _)), Some((last, _))) => first != last && t.priority == *first,
+            _ => false,
+        }
+    }
+
+    fn choose_random_priority(&mut self) -> i32 {
+        if self.rng.gen_bool(0.5) {
+            CHAOS_HIGH_PRIORITY
+        } else {
+            CHAOS_LOW_PRIORITY
+        }
+    }
+
+    fn random_frac(&mut self) -> f64 {
+        self.rng.gen::<f64>()
+    }
+
+    fn update_task_priority_internal(&mut self, t: &TaskSharedPtr, value: i32) {
+        let mut t_ref = t.borrow_mut();
+        let rt = t_ref.as_record_task_mut().unwrap();
+        if rt.priority == value {
+            return;
+        }
+        if rt.in_round_robin_queue {
+            rt.priority = value;
+            return;
+        }
+        self.task_priority_set
+            .remove(&(rt.priority, WeakPtrWrap(Rc::downgrade(t))));
+        rt.priority = value;
+        self.task_priority_set
+            .insert((rt.priority, WeakPtrWrap(Rc::downgrade(t))));
+    }
+}
+
+/// True if `t` can be run right away: it's stopped, or has just stopped,
+/// with a status for the record loop to process. Tasks in an emulated
+/// group stop only run again when they get a SIGCONT; vfork() parents wait
+/// for their child.
+fn is_task_runnable(t: &TaskSharedPtr) -> bool {
+    let mut t_ref = t.borrow_mut();
+    let rt = t_ref.as_record_task_mut().unwrap();
+    if rt.is_suspended_for_vfork() {
+        return false;
+    }
+    if rt.emulated_stop_type != EmulatedStopType::NotStopped {
+        return rt.is_signal_pending(SIGCONT);
+    }
+    !rt.is_running() || rt.try_wait()
+}
+
+/// Block until some child has a status for waitpid(), and return its tid.
+/// The status is left for waitpid() to reap. With `interception_fd`, returns
+/// None instead once that is readable.
+fn wait_for_any_child(interception_fd: Option<RawFd>) -> Option<pid_t> {
+    let mut options = WEXITED | WSTOPPED | WNOWAIT | __WALL;
+    if interception_fd.is_some() {
+        options |= WNOHANG;
+    }
+    loop {
+        let mut info: siginfo_t = unsafe { zeroed() };
+        let ret = unsafe { waitid(P_ALL, 0, &mut info, options) };
+        if ret == 0 {
+            let tid = unsafe { info.si_pid() };
+            // With WNOHANG, no child had a status.
+            if tid != 0 {
+                return Some(tid);
+            }
+        } else if errno() != EINTR {
+            fatal!("waitid() for any child failed with errno {}", errno());
+        }
+        if let Some(fd) = interception_fd {
+            // There's no fd for child state changes to poll along with it,
+            // so poll with a timeout and check for those again.
+            let mut pfd = pollfd {
+                fd,
+                events: POLLIN,
+                revents: 0,
+            };
+            let timeout = INTERCEPTION_POLL_INTERVAL.as_millis() as i32;
+            if unsafe { poll(&mut pfd, 1, timeout) } > 0 && pfd.revents & POLLIN != 0 {
+                return None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeslice_lengths() {
+        let mut s = Scheduler::new();
+        s.set_max_ticks(1000);
+        s.setup_new_timeslice();
+        assert_eq!(s.current_timeslice_end(), 1000);
+
+        s.set_round_robin_quantum(Some(100));
+        s.setup_new_timeslice();
+        assert_eq!(s.current_timeslice_end(), 100);
+        s.set_round_robin_quantum(None);
+        s.setup_new_timeslice();
+        assert_eq!(s.current_timeslice_end(), 1000);
+
+        s.set_enable_chaos(true);
+        s.set_chaos_seed(7);
+        s.set_chaos_ticks_range(10, 500);
+        for _ in 0..100 {
+            s.setup_new_timeslice();
+            let end = s.current_timeslice_end();
+            assert!(end >= 10 && end < 500);
+        }
+    }
+
+    #[test]
+    fn migration_only_in_chaos_mode_with_several_cpus() {
+        let mut s = Scheduler::new();
+        s.set_chaos_migration_cpus(vec![2, 5]);
+        assert!((0..100).all(|_| s.choose_migration_cpu().is_none()));
+
+        s.set_enable_chaos(true);
+        s.set_chaos_migration_cpus(vec![3]);
+        assert!((0..100).all(|_| s.choose_migration_cpu().is_none()));
+
+        s.set_chaos_migration_cpus(vec![2, 5]);
+        let cpus: Vec<Option<u32>> = (0..100).map(|_| s.choose_migration_cpu()).collect();
+        assert!(cpus
+            .iter()
+            .all(|c| c.map_or(true, |cpu| cpu == 2 || cpu == 5)));
+        assert!(cpus.contains(&Some(2)) && cpus.contains(&Some(5)));
+        assert!(cpus.contains(&None));
+    }
+
+    #[test]
+    fn chaos_decisions_repeat_with_the_same_seed() {
+        let draws = || {
+            let mut s = Scheduler::new();
+            s.set_enable_chaos(true);
+            s.set_chaos_seed(42);
+            s.set_chaos_migration_cpus(vec![0, 1, 2, 3]);
+            (0..20)
+                .map(|_| s.choose_migration_cpu())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(draws(), draws());
+    }
+
+    #[test]
+    fn high_priority_only_intervals() {
+        let mut s = Scheduler::new();
+        s.maybe_reset_high_priority_only_intervals(0.0);
+        assert!(!s.in_high_priority_only_interval(0.0));
+
+        s.set_enable_chaos(true);
+        s.set_chaos_seed(1);
+        s.maybe_reset_high_priority_only_intervals(0.0);
+        let start = s.high_priority_only_intervals_start;
+        let duration = s.high_priority_only_intervals_duration;
+        let period = s.high_priority_only_intervals_period;
+        assert!(duration >= MIN_HIGH_PRIORITY_ONLY_DURATION);
+        assert!(duration <= period * MAX_HIGH_PRIORITY_ONLY_FRACTION);
+        assert!(!s.in_high_priority_only_interval(start - 0.01));
+        assert!(s.in_high_priority_only_interval(start));
+        assert!(s.in_high_priority_only_interval(start + duration / 2.0));
+        assert!(!s.in_high_priority_only_interval(start + duration * 1.5));
+        assert!(s.in_high_priority_only_interval(start + period + duration / 2.0));
+    }
+
+    #[test]
+    fn no_tasks_to_destroy() {
+        let mut s = Scheduler::new();
+        s.on_destroy();
+        assert!(s.current().is_none());
+        assert!(!s.knows_tid(1));
+    }
 }
@@ -0,0 +1,170 @@
+//! Fds passed between tracees over unix sockets with SCM_RIGHTS. The
+//! receiver gets new fds for files the sender has open, and the sender's
+//! `FileMonitor`s for those files have to follow them, or e.g. a pidfd or a
+//! /proc/<pid>/mem fd stops being handled once it's been passed on.
+//!
+//! The session keeps the batches of fds sent and not yet received. During
+//! recording each batch also gets the identity (device and inode) of the
+//! files it holds, and a recvmsg() takes the oldest batch whose files are the
+//! ones it got. That finds the right sendmsg() without knowing which sockets
+//! are connected to which: batches sent over unrelated sockets hold other
+//! files. Which batch that was is recorded with the recvmsg(), since replay
+//! has no files to compare, and replay takes the same one.
+
+use crate::{
+    arch::Architecture,
+    file_monitor::FileMonitorSharedPtr,
+    registers::Registers,
+    remote_ptr::RemotePtr,
+    session::task::{
+        task_common::{read_mem, read_val_mem},
+        Task,
+    },
+};
+use libc::{dev_t, ino_t, pid_t, SCM_RIGHTS, SOL_SOCKET};
+use nix::sys::stat::stat;
+use std::{cell::RefCell, collections::VecDeque, mem::size_of};
+
+/// Batches that are never received (the receiver exited, or used
+/// recvmmsg()) are forgotten, oldest first, beyond this many.
+const MAX_IN_FLIGHT: usize = 256;
+
+/// The device and inode of an open file.
+pub type FileIdentity = (dev_t, ino_t);
+
+struct Batch {
+    /// The identity of each file sent. Only known during recording.
+    files: Vec<Option<FileIdentity>>,
+    /// The monitor of each fd sent.
+    monitors: Vec<Option<FileMonitorSharedPtr>>,
+}
+
+/// The fds that have been sent and not yet received, oldest first.
+#[derive(Default)]
+pub struct InFlightFds {
+    batches: RefCell<VecDeque<Batch>>,
+}
+
+impl InFlightFds {
+    pub fn new() -> InFlightFds {
+        Default::default()
+    }
+
+    fn send(&self, files: Vec<Option<FileIdentity>>, monitors: Vec<Option<FileMonitorSharedPtr>>) {
+        let mut batches = self.batches.borrow_mut();
+        if batches.len() >= MAX_IN_FLIGHT {
+            batches.pop_front();
+        }
+        batches.push_back(Batch { files, monitors });
+    }
+
+    /// The index of the oldest batch holding exactly `files`.
+    fn find(&self, files: &[Option<FileIdentity>]) -> Option<usize> {
+        if files.iter().any(|f| f.is_none()) {
+            return None;
+        }
+        self.batches.borrow().iter().position(|b| b.files == files)
+    }
+
+    fn receive(&self, index: usize, count: usize) -> Option<Vec<Option<FileMonitorSharedPtr>>> {
+        let mut batches = self.batches.borrow_mut();
+        if batches.get(index)?.monitors.len() != count {
+            return None;
+        }
+        batches.remove(index).map(|b| b.monitors)
+    }
+}
+
+/// `t` has completed a sendmsg(). `regs` are the registers at syscall exit.
+pub fn did_sendmsg<Arch: Architecture>(t: &mut dyn Task, regs: &Registers) {
+    let fds = scm_rights_fds::<Arch>(t, RemotePtr::from(regs.arg2()));
+    if fds.is_empty() {
+        return;
+    }
+    let files = if t.session().is_recording() {
+        fds.iter().map(|&fd| file_identity(t.tid, fd)).collect()
+    } else {
+        vec![None; fds.len()]
+    };
+    let monitors: Vec<_> = fds.iter().map(|&fd| t.fd_table().get_monitor(fd)).collect();
+    t.session().in_flight_fds().send(files, monitors);
+}
+
+/// `t` has completed a recvmsg(). `regs` are the registers at syscall exit.
+/// During replay the msghdr must have been restored from the trace already.
+/// During recording the index of the batch received is stored in `t`'s
+/// syscall event.
+pub fn did_recvmsg<Arch: Architecture>(t: &mut dyn Task, regs: &Registers) {
+    let fds = scm_rights_fds::<Arch>(t, RemotePtr::from(regs.arg2()));
+    if fds.is_empty() {
+        return;
+    }
+    let index = if t.session().is_replaying() {
+        let rt = t.as_replay_task().unwrap();
+        rt.current_trace_frame().event().syscall().scm_rights_batch
+    } else {
+        let files: Vec<_> = fds.iter().map(|&fd| file_identity(t.tid, fd)).collect();
+        let index = t.session().in_flight_fds().find(&files);
+        let rt = t.as_record_task_mut().unwrap();
+        rt.ev_mut().syscall_mut().scm_rights_batch = index;
+        index
+    };
+    let monitors = index
+        .and_then(|i| t.session().in_flight_fds().receive(i, fds.len()))
+        .unwrap_or_else(|| vec![None; fds.len()]);
+    for (fd, monitor) in fds.into_iter().zip(monitors) {
+        t.fd_table_shr_ptr().borrow_mut().did_import(fd, monitor, t);
+    }
+}
+
+/// The identity of the file `fd` of task `tid` refers to.
+fn file_identity(tid: pid_t, fd: i32) -> Option<FileIdentity> {
+    stat(format!("/proc/{}/fd/{}", tid, fd).as_str())
+        .ok()
+        .map(|st| (st.st_dev, st.st_ino))
+}
+
+/// The fds in the SCM_RIGHTS control messages of the msghdr at `msg`.
+fn scm_rights_fds<Arch: Architecture>(t: &mut dyn Task, msg: RemotePtr<Arch::msghdr>) -> Vec<i32> {
+    let (control, controllen) = Arch::get_msghdr_control(&read_val_mem(t, msg, None));
+    let mut fds = Vec::new();
+    if control.is_null() {
+        return fds;
+    }
+    let align = size_of::<Arch::unsigned_word>();
+    let header_size = size_of::<Arch::cmsghdr>();
+    let mut offset = 0;
+    while offset + header_size <= controllen {
+        let header = RemotePtr::<Arch::cmsghdr>::cast(control + offset);
+        let (len, level, kind) = Arch::get_cmsghdr(&read_val_mem(t, header, None));
+        if len < header_size || offset + len > controllen {
+            break;
+        }
+        if level == SOL_SOCKET && kind == SCM_RIGHTS {
+            let data = RemotePtr::<i32>::cast(control + offset + header_size);
+            let count = (len - header_size) / size_of::<i32>();
+            fds.extend(read_mem(t, data, count, None));
+        }
+        offset += (len + align - 1) & !(align - 1);
+    }
+    fds
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn receive_matches_files() {
+        let in_flight = InFlightFds::new();
+        in_flight.send(vec![Some((1, 10))], vec![None]);
+        in_flight.send(vec![Some((1, 20))], vec![None]);
+        assert_eq!(in_flight.find(&[Some((1, 20))]), Some(1));
+        assert_eq!(in_flight.find(&[Some((1, 30))]), None);
+        assert_eq!(in_flight.find(&[None]), None);
+        assert!(in_flight.receive(1, 2).is_none());
+        assert!(in_flight.receive(1, 1).is_some());
+        assert_eq!(in_flight.find(&[Some((1, 20))]), None);
+        assert_eq!(in_flight.find(&[Some((1, 10))]), Some(0));
+    }
+}
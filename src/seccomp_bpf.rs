@@ -1,6 +1,23 @@
 use crate::{
-    bindings::kernel::{sock_filter, BPF_ABS, BPF_JEQ, BPF_JMP, BPF_K, BPF_LD, BPF_RET, BPF_W},
-    kernel_supplement::{seccomp_data, SECCOMP_RET_ALLOW, SECCOMP_RET_DATA, SECCOMP_RET_TRACE},
+    bindings::kernel::{
+        sock_filter,
+        BPF_ABS,
+        BPF_JA,
+        BPF_JEQ,
+        BPF_JMP,
+        BPF_K,
+        BPF_LD,
+        BPF_RET,
+        BPF_W,
+    },
+    kernel_abi::SupportedArch,
+    kernel_supplement::{
+        seccomp_data,
+        SECCOMP_RET_ALLOW,
+        SECCOMP_RET_DATA,
+        SECCOMP_RET_TRACE,
+        SECCOMP_RET_USER_NOTIF,
+    },
     remote_code_ptr::RemoteCodePtr,
 };
 use std::convert::TryInto;
@@ -18,6 +35,57 @@ use std::convert::TryInto;
  * starting point for developing applications using mode 2 seccomp.
  */
 
+const AUDIT_ARCH_I386: u32 = 0x4000_0003;
+const AUDIT_ARCH_X86_64: u32 = 0xc000_003e;
+
+/// The value of `seccomp_data.arch` for syscalls made with the ABI of `arch`.
+pub fn audit_arch(arch: SupportedArch) -> u32 {
+    match arch {
+        SupportedArch::X86 => AUDIT_ARCH_I386,
+        SupportedArch::X64 => AUDIT_ARCH_X86_64,
+    }
+}
+
+/// What rd's own filter puts in SECCOMP_RET_DATA when it asks for a syscall
+/// to be traced. rd's routes use the top of the 16-bit range; lower values
+/// are left for the results of tracee filters, which the
+/// `SeccompFilterRewriter` turns into SECCOMP_RET_TRACE as well.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SeccompTraceRoute {
+    /// rd always needs to see this syscall, wherever it was made from.
+    AlwaysTrace,
+    /// The syscallbuf can handle this syscall, but it wasn't made through
+    /// one of the untraced entry points in the rd page. Patching the call
+    /// site may move it onto the fast path.
+    Bufferable,
+    /// Not one of ours: the SECCOMP_RET_DATA of a tracee filter.
+    TraceeFilter(u16),
+}
+
+const ROUTE_ALWAYS_TRACE: u16 = SECCOMP_RET_DATA as u16;
+const ROUTE_BUFFERABLE: u16 = ROUTE_ALWAYS_TRACE - 1;
+
+impl SeccompTraceRoute {
+    pub fn from_ret_data(data: u16) -> SeccompTraceRoute {
+        match data {
+            ROUTE_ALWAYS_TRACE => SeccompTraceRoute::AlwaysTrace,
+            ROUTE_BUFFERABLE => SeccompTraceRoute::Bufferable,
+            _ => SeccompTraceRoute::TraceeFilter(data),
+        }
+    }
+
+    pub fn ret_data(self) -> u16 {
+        match self {
+            SeccompTraceRoute::AlwaysTrace => ROUTE_ALWAYS_TRACE,
+            SeccompTraceRoute::Bufferable => ROUTE_BUFFERABLE,
+            SeccompTraceRoute::TraceeFilter(data) => {
+                debug_assert!(data < ROUTE_BUFFERABLE);
+                data
+            }
+        }
+    }
+}
+
 fn bpf_stmt(code: u16, k: u32) -> sock_filter {
     sock_filter {
         code,
@@ -55,12 +123,22 @@ impl SeccompFilter {
     }
 
     pub fn trace(&mut self) {
+        self.trace_with_route(SeccompTraceRoute::AlwaysTrace);
+    }
+
+    pub fn trace_with_route(&mut self, route: SeccompTraceRoute) {
         self.filters.push(bpf_stmt(
             (BPF_RET + BPF_K) as u16,
-            SECCOMP_RET_TRACE | SECCOMP_RET_DATA,
+            SECCOMP_RET_TRACE | route.ret_data() as u32,
         ));
     }
 
+    /// Block the syscall and hand it to the filter's notification listener.
+    pub fn notify(&mut self) {
+        self.filters
+            .push(bpf_stmt((BPF_RET + BPF_K) as u16, SECCOMP_RET_USER_NOTIF));
+    }
+
     pub fn allow_syscalls_from_callsite(&mut self, ip: RemoteCodePtr) {
         let inst_ptr: u32 = offset_of!(seccomp_data, instruction_pointer) as u32;
         let v: u32 = ip.register_value().try_into().unwrap();
@@ -70,4 +148,119 @@ impl SeccompFilter {
             .push(bpf_jump((BPF_JMP + BPF_JEQ + BPF_K) as u16, v, 0, 1));
         self.allow()
     }
+
+    /// For syscalls made with the ABI identified by `audit_arch`, trace
+    /// those in `bufferable` with `SeccompTraceRoute::Bufferable` and
+    /// everything else with `SeccompTraceRoute::AlwaysTrace`. Syscalls made
+    /// with any other ABI fall through to the rest of the filter.
+    /// Entries of `bufferable` that are negative (i.e. syscalls that don't
+    /// exist for this ABI) are ignored.
+    pub fn route_syscalls_for_arch(&mut self, audit_arch: u32, bufferable: &[i32]) {
+        let mut block = SeccompFilter::new();
+        let nr: u32 = offset_of!(seccomp_data, nr) as u32;
+        block
+            .filters
+            .push(bpf_stmt((BPF_LD + BPF_W + BPF_ABS) as u16, nr));
+        for &syscallno in bufferable.iter().filter(|&&no| no >= 0) {
+            block.filters.push(bpf_jump(
+                (BPF_JMP + BPF_JEQ + BPF_K) as u16,
+                syscallno as u32,
+                0,
+                1,
+            ));
+            block.trace_with_route(SeccompTraceRoute::Bufferable);
+        }
+        block.trace();
+        self.push_arch_block(audit_arch, block);
+    }
+
+    /// For syscalls made with the ABI identified by `audit_arch`, notify
+    /// the listener of those in `notified` and allow everything else.
+    /// Syscalls made with any other ABI fall through to the rest of the
+    /// filter. Negative entries of `notified` are ignored.
+    pub fn notify_syscalls_for_arch(&mut self, audit_arch: u32, notified: &[i32]) {
+        let mut block = SeccompFilter::new();
+        let nr: u32 = offset_of!(seccomp_data, nr) as u32;
+        block
+            .filters
+            .push(bpf_stmt((BPF_LD + BPF_W + BPF_ABS) as u16, nr));
+        for &syscallno in notified.iter().filter(|&&no| no >= 0) {
+            block.filters.push(bpf_jump(
+                (BPF_JMP + BPF_JEQ + BPF_K) as u16,
+                syscallno as u32,
+                0,
+                1,
+            ));
+            block.notify();
+        }
+        block.allow();
+        self.push_arch_block(audit_arch, block);
+    }
+
+    /// Append `block`, to be run only for syscalls made with the ABI
+    /// identified by `audit_arch`. `block` must end in a return.
+    fn push_arch_block(&mut self, audit_arch: u32, block: SeccompFilter) {
+        let arch: u32 = offset_of!(seccomp_data, arch) as u32;
+        self.filters
+            .push(bpf_stmt((BPF_LD + BPF_W + BPF_ABS) as u16, arch));
+        match block.filters.len().try_into() {
+            Ok(len) => self.filters.push(bpf_jump(
+                (BPF_JMP + BPF_JEQ + BPF_K) as u16,
+                audit_arch,
+                0,
+                len,
+            )),
+            Err(_) => {
+                // Conditional jumps only reach 255 instructions ahead. Skip
+                // over the block with an unconditional jump, which takes a
+                // 32-bit offset, instead.
+                self.filters.push(bpf_jump(
+                    (BPF_JMP + BPF_JEQ + BPF_K) as u16,
+                    audit_arch,
+                    1,
+                    0,
+                ));
+                self.filters.push(bpf_stmt(
+                    (BPF_JMP + BPF_JA) as u16,
+                    block.filters.len().try_into().unwrap(),
+                ));
+            }
+        }
+        self.filters.extend(block.filters);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Where a jump at `pc` goes to, for each of its outcomes.
+    fn targets(f: &sock_filter, pc: usize) -> Vec<usize> {
+        if f.code == (BPF_JMP + BPF_JA) as u16 {
+            vec![pc + 1 + f.k as usize]
+        } else {
+            vec![pc + 1 + f.jt as usize, pc + 1 + f.jf as usize]
+        }
+    }
+
+    #[test]
+    fn long_arch_block_is_skipped_with_unconditional_jump() {
+        let notified: Vec<i32> = (0..200).collect();
+        let mut filter = SeccompFilter::new();
+        filter.notify_syscalls_for_arch(audit_arch(SupportedArch::X64), &notified);
+        filter.trace();
+        let last = filter.filters.len() - 1;
+        // The ABI check must lead either into the block or past its end.
+        assert_eq!(targets(&filter.filters[1], 1), vec![3, 2]);
+        assert_eq!(targets(&filter.filters[2], 2), vec![last]);
+    }
+
+    #[test]
+    fn short_arch_block_is_skipped_with_conditional_jump() {
+        let mut filter = SeccompFilter::new();
+        filter.notify_syscalls_for_arch(audit_arch(SupportedArch::X64), &[1, 2]);
+        filter.trace();
+        let last = filter.filters.len() - 1;
+        assert_eq!(targets(&filter.filters[1], 1), vec![2, last]);
+    }
 }
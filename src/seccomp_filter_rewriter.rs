@@ -1,3 +1,15 @@
+use crate::{
+    bindings::kernel::{sock_filter, BPF_K, BPF_RET},
+    kernel_supplement::{
+        SECCOMP_RET_ACTION_FULL,
+        SECCOMP_RET_ALLOW,
+        SECCOMP_RET_TRACE,
+        SECCOMP_RET_USER_NOTIF,
+    },
+    seccomp_bpf::SeccompTraceRoute,
+};
+use std::collections::HashMap;
+
 /// When seccomp decides not to execute a syscall the kernel returns to userspace
 /// without modifying the registers. There is no negative return value to
 /// indicate that whatever side effects the syscall would happen did not take
@@ -15,4 +27,101 @@
 /// kernel itself.
 pub const SECCOMP_MAGIC_SKIP_ORIGINAL_SYSCALLNO: isize = -2;
 
-pub struct SeccompFilterRewriter;
+/// Rewrites the filters tracees install so rd sees the syscalls they act on.
+/// Every result but SECCOMP_RET_ALLOW becomes a SECCOMP_RET_TRACE whose
+/// SECCOMP_RET_DATA indexes the result the filter asked for, which rd then
+/// emulates at the seccomp stop (see `SeccompStopAction::TraceeFilter`).
+///
+/// SECCOMP_RET_USER_NOTIF is left alone, since only the kernel can queue the
+/// notification on the tracee's listener. It takes precedence over rd's
+/// SECCOMP_RET_TRACE, so notified syscalls don't stop at entry: tasks with
+/// such a filter have to be resumed with PTRACE_SYSCALL to record their
+/// result at syscall exit. The supervisor's side is ioctls on the listener,
+/// see `SeccompNotifyMonitor`.
+#[derive(Default)]
+pub struct SeccompFilterRewriter {
+    /// The results tracee filters asked for, by the SECCOMP_RET_DATA of the
+    /// SECCOMP_RET_TRACE they were rewritten to.
+    index_to_result: Vec<u32>,
+    result_to_index: HashMap<u32, u16>,
+}
+
+impl SeccompFilterRewriter {
+    /// Rewrite the tracee filter `code` in place. Returns whether it can
+    /// hand syscalls to a notification listener.
+    pub fn rewrite(&mut self, code: &mut [sock_filter]) -> bool {
+        let mut notifies = false;
+        for insn in code.iter_mut() {
+            // Results computed at run time (BPF_RET | BPF_A) can't be
+            // rewritten. Filters in practice return constants.
+            if insn.code != (BPF_RET + BPF_K) as u16 {
+                continue;
+            }
+            match insn.k & SECCOMP_RET_ACTION_FULL {
+                SECCOMP_RET_ALLOW => (),
+                SECCOMP_RET_USER_NOTIF => notifies = true,
+                _ => {
+                    let route = SeccompTraceRoute::TraceeFilter(self.index_of(insn.k));
+                    insn.k = SECCOMP_RET_TRACE | route.ret_data() as u32;
+                }
+            }
+        }
+        notifies
+    }
+
+    /// The result a tracee filter asked for, from the SECCOMP_RET_DATA of a
+    /// `SeccompStopAction::TraceeFilter` stop.
+    pub fn map_filter_data_to_real_result(&self, data: u16) -> Option<u32> {
+        self.index_to_result.get(data as usize).copied()
+    }
+
+    fn index_of(&mut self, result: u32) -> u16 {
+        if let Some(&index) = self.result_to_index.get(&result) {
+            return index;
+        }
+        let index = self.index_to_result.len();
+        if index >= SeccompTraceRoute::Bufferable.ret_data() as usize {
+            fatal!("Too many distinct results in tracee seccomp filters");
+        }
+        self.index_to_result.push(result);
+        self.result_to_index.insert(result, index as u16);
+        index as u16
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::kernel_supplement::SECCOMP_RET_ERRNO;
+
+    fn ret(k: u32) -> sock_filter {
+        sock_filter {
+            code: (BPF_RET + BPF_K) as u16,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+
+    #[test]
+    fn rewrite_keeps_allow_and_user_notif() {
+        let eperm = SECCOMP_RET_ERRNO | 1;
+        let mut code = [
+            ret(SECCOMP_RET_ALLOW),
+            ret(eperm),
+            ret(SECCOMP_RET_USER_NOTIF),
+            ret(eperm),
+        ];
+        let mut rewriter = SeccompFilterRewriter::default();
+        assert!(rewriter.rewrite(&mut code));
+        assert_eq!(code[0].k, SECCOMP_RET_ALLOW);
+        assert_eq!(code[1].k, SECCOMP_RET_TRACE);
+        assert_eq!(code[2].k, SECCOMP_RET_USER_NOTIF);
+        assert_eq!(code[3].k, SECCOMP_RET_TRACE);
+        assert_eq!(rewriter.map_filter_data_to_real_result(0), Some(eperm));
+        assert_eq!(rewriter.map_filter_data_to_real_result(1), None);
+
+        let mut code = [ret(SECCOMP_RET_ALLOW)];
+        assert!(!rewriter.rewrite(&mut code));
+    }
+}
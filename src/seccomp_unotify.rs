@@ -0,0 +1,144 @@
+//! The seccomp user notification interface (Linux 5.0 and later). A filter
+//! returning SECCOMP_RET_USER_NOTIF blocks the syscall in the kernel and
+//! queues a notification on a listener fd. Whoever holds the fd can answer
+//! the syscall with a result, let it run with SECCOMP_USER_NOTIF_FLAG_CONTINUE
+//! (Linux 5.5), or install an fd in the caller first (Linux 5.9), all without
+//! a ptrace stop.
+//!
+//! The structs aren't in the headers we generate bindings from on older
+//! systems, and the ioctl numbers are function-like macros bindgen can't
+//! evaluate.
+
+use crate::{
+    kernel_supplement::{seccomp_data, SECCOMP_ADDFD_FLAG_SETFD, SECCOMP_USER_NOTIF_FLAG_CONTINUE},
+    scoped_fd::ScopedFd,
+};
+use libc::{c_int, c_ulong};
+use nix::errno::errno;
+use std::{mem::zeroed, os::unix::io::RawFd};
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct seccomp_notif {
+    pub id: u64,
+    pub pid: u32,
+    pub flags: u32,
+    pub data: seccomp_data,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct seccomp_notif_resp {
+    pub id: u64,
+    pub val: i64,
+    pub error: i32,
+    pub flags: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct seccomp_notif_addfd {
+    pub id: u64,
+    pub flags: u32,
+    pub srcfd: u32,
+    pub newfd: u32,
+    pub newfd_flags: u32,
+}
+
+// _IOWR('!', 0, struct seccomp_notif)
+pub const SECCOMP_IOCTL_NOTIF_RECV: c_ulong = 0xc050_2100;
+// _IOWR('!', 1, struct seccomp_notif_resp)
+pub const SECCOMP_IOCTL_NOTIF_SEND: c_ulong = 0xc018_2101;
+// _IOW('!', 2, __u64). Linux 5.0 to 5.6 defined it with _IOR; later
+// kernels accept both numbers.
+pub const SECCOMP_IOCTL_NOTIF_ID_VALID: c_ulong = 0x4008_2102;
+// _IOW('!', 3, struct seccomp_notif_addfd)
+pub const SECCOMP_IOCTL_NOTIF_ADDFD: c_ulong = 0x4018_2103;
+
+/// The listener fd returned by installing a filter with
+/// SECCOMP_FILTER_FLAG_NEW_LISTENER. Every method fails with ENOENT if the
+/// notification is gone, e.g. because the task was killed or a signal
+/// interrupted the syscall.
+pub struct SeccompNotifyListener {
+    fd: ScopedFd,
+}
+
+impl SeccompNotifyListener {
+    pub fn new(fd: ScopedFd) -> SeccompNotifyListener {
+        debug_assert!(fd.is_open());
+        SeccompNotifyListener { fd }
+    }
+
+    /// For poll(): the fd becomes readable when a notification is pending.
+    pub fn as_raw(&self) -> RawFd {
+        self.fd.as_raw()
+    }
+
+    /// Take the next notification, blocking until there is one.
+    pub fn recv(&self) -> Result<seccomp_notif, i32> {
+        // The kernel insists on a zeroed buffer.
+        let mut notif: seccomp_notif = unsafe { zeroed() };
+        self.ioctl(SECCOMP_IOCTL_NOTIF_RECV, &mut notif)?;
+        Ok(notif)
+    }
+
+    /// Whether the syscall of notification `id` is still waiting for an
+    /// answer. Anything read from the task while answering (e.g. from /proc)
+    /// is only known to belong to it if this is still true afterwards.
+    pub fn id_valid(&self, id: u64) -> bool {
+        let mut id = id;
+        self.ioctl(SECCOMP_IOCTL_NOTIF_ID_VALID, &mut id).is_ok()
+    }
+
+    /// Complete the syscall without running it: it returns `val`, or fails
+    /// with the (positive) errno `error` if that isn't 0.
+    pub fn respond(&self, id: u64, val: i64, error: i32) -> Result<(), i32> {
+        let mut resp = seccomp_notif_resp {
+            id,
+            val,
+            error: -error,
+            flags: 0,
+        };
+        self.ioctl(SECCOMP_IOCTL_NOTIF_SEND, &mut resp).map(|_| ())
+    }
+
+    /// Let the syscall run as if the filter had allowed it.
+    pub fn continue_syscall(&self, id: u64) -> Result<(), i32> {
+        let mut resp = seccomp_notif_resp {
+            id,
+            flags: SECCOMP_USER_NOTIF_FLAG_CONTINUE as u32,
+            ..Default::default()
+        };
+        self.ioctl(SECCOMP_IOCTL_NOTIF_SEND, &mut resp).map(|_| ())
+    }
+
+    /// Install a copy of our `srcfd` in the task of notification `id`, at
+    /// `newfd` if given (replacing whatever is there), otherwise at the
+    /// lowest free fd. `newfd_flags` can be O_CLOEXEC. Returns the fd number
+    /// in the task.
+    pub fn add_fd(
+        &self,
+        id: u64,
+        srcfd: RawFd,
+        newfd: Option<RawFd>,
+        newfd_flags: u32,
+    ) -> Result<RawFd, i32> {
+        let mut addfd = seccomp_notif_addfd {
+            id,
+            flags: newfd.map_or(0, |_| SECCOMP_ADDFD_FLAG_SETFD as u32),
+            srcfd: srcfd as u32,
+            newfd: newfd.unwrap_or(0) as u32,
+            newfd_flags,
+        };
+        self.ioctl(SECCOMP_IOCTL_NOTIF_ADDFD, &mut addfd)
+    }
+
+    fn ioctl<T>(&self, request: c_ulong, arg: &mut T) -> Result<c_int, i32> {
+        let ret = unsafe { libc::ioctl(self.fd.as_raw(), request, arg as *mut T) };
+        if ret >= 0 {
+            Ok(ret)
+        } else {
+            Err(errno())
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! `rd record --random-seed`. Tracees normally get their randomness from
+//! getrandom() and /dev/urandom, and rd records what they got so replay hands
+//! back the same bytes. That reproduces a failure only from its trace.
+//!
+//! With a seed, rd answers getrandom() and reads of /dev/urandom and
+//! /dev/random itself, from a generator seeded with it. Recording the
+//! program again with the same seed gives it the same bytes in the same
+//! order, so a fuzzer that logs its seeds can reproduce a failing run
+//! without keeping a trace of it. Other sources of randomness, like the
+//! AT_RANDOM bytes the kernel puts in the auxv, are left alone.
+//!
+//! The bytes are recorded like any other syscall result, so replay doesn't
+//! need the seed.
+
+use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+/// SplitMix64. A fixed algorithm, not one from the `rand` crate: a seed
+/// has to give the same bytes with every build of rd.
+#[derive(Clone, Debug)]
+pub struct SeededRandom {
+    state: u64,
+}
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> SeededRandom {
+        SeededRandom { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut x = self.state;
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        x ^ (x >> 31)
+    }
+
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+/// Whether reads of the file at `path` are answered from the seeded
+/// generator.
+pub fn is_random_device(path: &OsStr) -> bool {
+    let path = path.as_bytes();
+    path == b"/dev/urandom" || path == b"/dev/random"
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_bytes() {
+        let mut buf = [0u8; 12];
+        SeededRandom::new(0).fill(&mut buf);
+        assert_eq!(buf[..8], 0xe220_a839_7b1d_cdafu64.to_le_bytes());
+
+        let mut again = [0u8; 12];
+        SeededRandom::new(0).fill(&mut again);
+        assert_eq!(buf, again);
+        SeededRandom::new(1).fill(&mut again);
+        assert_ne!(buf, again);
+    }
+}
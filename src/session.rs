@@ -11,6 +11,7 @@ use crate::{
         session_inner::session_inner::{AddressSpaceMap, SessionInner, TaskMap, ThreadGroupMap},
         task::{
             task_common,
+            task_handle::TaskHandle,
             task_inner::{task_inner::WriteFlags, CloneFlags},
             Task,
             TaskSharedPtr,
@@ -222,6 +223,28 @@ pub trait Session: DerefMut<Target = SessionInner> {
         self.find_task_from_rec_tid(tuid.tid())
     }
 
+    /// Like find_task_from_rec_tid() but for code that is processing some
+    /// other task's event. See TaskHandle.
+    fn find_task_handle(&self, rec_tid: pid_t) -> Option<TaskHandle> {
+        self.find_task_from_rec_tid(rec_tid)
+            .map(|ptr| TaskHandle::new(rec_tid, ptr))
+    }
+
+    /// Check that nothing is holding on to a borrow of any of our tasks.
+    /// Call this where rd is between task events, so that a borrow leaked by
+    /// the previous event is reported here rather than as a confusing
+    /// BorrowMutError much later.
+    fn assert_no_tasks_borrowed(&self) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        for (rec_tid, t) in self.tasks().iter() {
+            if t.try_borrow_mut().is_err() {
+                fatal!("Task {} is still borrowed between events", rec_tid);
+            }
+        }
+    }
+
     /// Return the thread group whose unique ID is `tguid`, or None if no such
     /// thread group exists.
     /// NOTE: Method is simply called Session::find thread_group() in rr
@@ -38,10 +38,11 @@ use nix::{
 };
 use std::{
     cmp::min,
-    collections::BTreeSet,
+    collections::{hash_map::DefaultHasher, BTreeMap, BTreeSet},
     convert::TryInto,
     ffi::{OsStr, OsString},
-    mem::size_of,
+    hash::{Hash, Hasher},
+    mem::{replace, size_of},
     os::unix::ffi::{OsStrExt, OsStringExt},
 };
 
@@ -55,6 +56,18 @@ pub enum BreakpointType {
     BkptUser = 2,
 }
 
+/// Restricts which hits of a user breakpoint stop replay. Conditions are
+/// evaluated by rd, so a client driving replay over the remote protocol isn't
+/// woken up for (and doesn't pay a round trip for) every hit in a hot loop.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct BreakpointCondition {
+    /// Only hits by this (recorded) tid count.
+    pub tid: Option<pid_t>,
+    /// Only stop on the nth (1-based) hit that counts. Without this, every
+    /// hit that counts stops.
+    pub nth_hit: Option<u64>,
+}
+
 /// NB: these random-looking enumeration values are chosen to
 /// match the numbers programmed into x86 debug registers.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -111,6 +124,9 @@ enum HandleHeap {
     RespectHeap,
 }
 
+/// How much memory `AddressSpace::snapshot()` reads at a time.
+const SNAPSHOT_CHUNK_SIZE: usize = 1 << 20;
+
 /// Must match generate_rr_page.py
 const ENTRY_POINTS: [SyscallType; 8] = [
     SyscallType::new(
@@ -191,6 +207,78 @@ impl WatchConfig {
     }
 }
 
+/// The contents of a watched range of memory before and after the access
+/// that triggered a watchpoint on it. They're the same for a read or exec
+/// watchpoint. Bytes that couldn't be read are 0xff.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WatchValues {
+    pub addr: RemotePtr<Void>,
+    pub old_value: Vec<u8>,
+    pub new_value: Vec<u8>,
+}
+
+/// The contents of an address space at some point, as a hash of each page,
+/// see `AddressSpace::snapshot()`. Much smaller than a copy of the memory,
+/// and enough to tell what a stretch of execution wrote.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AddressSpaceSnapshot {
+    page_size: usize,
+    /// Page address -> hash of the page's contents.
+    pages: BTreeMap<usize, u64>,
+}
+
+impl AddressSpaceSnapshot {
+    pub fn new(page_size: usize) -> AddressSpaceSnapshot {
+        AddressSpaceSnapshot {
+            page_size,
+            pages: BTreeMap::new(),
+        }
+    }
+
+    /// Add the page-aligned `data` at `addr`. A partial page at the end is
+    /// left out.
+    pub fn add_pages(&mut self, addr: usize, data: &[u8]) {
+        debug_assert!(addr % self.page_size == 0);
+        for (i, page) in data.chunks_exact(self.page_size).enumerate() {
+            let mut hasher = DefaultHasher::new();
+            page.hash(&mut hasher);
+            self.pages
+                .insert(addr + i * self.page_size, hasher.finish());
+        }
+    }
+
+    /// The ranges whose contents differ in `later`, including pages that are
+    /// only in one of the snapshots (mapped or unmapped in between).
+    /// Adjacent pages are merged into one range.
+    pub fn diff(&self, later: &AddressSpaceSnapshot) -> Vec<MemoryRange> {
+        debug_assert_eq!(self.page_size, later.page_size);
+        let mut changed: BTreeSet<usize> = self
+            .pages
+            .iter()
+            .filter(|&(addr, hash)| later.pages.get(addr) != Some(hash))
+            .map(|(&addr, _)| addr)
+            .collect();
+        changed.extend(
+            later
+                .pages
+                .keys()
+                .filter(|addr| !self.pages.contains_key(*addr)),
+        );
+
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+        for addr in changed {
+            match ranges.last_mut() {
+                Some((_, end)) if *end == addr => *end += self.page_size,
+                _ => ranges.push((addr, addr + self.page_size)),
+            }
+        }
+        ranges
+            .into_iter()
+            .map(|(start, end)| MemoryRange::from_range(start.into(), end.into()))
+            .collect()
+    }
+}
+
 pub mod address_space {
     use super::*;
     use crate::{
@@ -206,6 +294,7 @@ pub mod address_space {
             syscall_instruction,
             syscall_number_for_brk,
             syscall_number_for_close,
+            syscall_number_for_mprotect,
             syscall_number_for_munmap,
             syscall_number_for_openat,
             SupportedArch,
@@ -213,7 +302,6 @@ pub mod address_space {
         log::LogLevel::LogDebug,
         monitored_shared_memory::MonitoredSharedMemorySharedPtr,
         monkey_patcher::MonkeyPatcher,
-        rd::RD_RESERVED_ROOT_DIR_FD,
         registers::Registers,
         remote_code_ptr::RemoteCodePtr,
         remote_ptr::RemotePtr,
@@ -229,7 +317,12 @@ pub mod address_space {
             task::{
                 record_task::record_task::RecordTask,
                 task_common::{read_mem, read_val_mem, write_val_mem, write_val_mem_with_flags},
-                task_inner::task_inner::WriteFlags,
+                task_inner::{
+                    task_inner::WriteFlags,
+                    ResumeRequest,
+                    TicksRequest,
+                    WaitRequest,
+                },
                 Task,
                 TaskSharedPtr,
             },
@@ -237,8 +330,16 @@ pub mod address_space {
             SessionSharedWeakPtr,
         },
         taskish_uid::{AddressSpaceUid, TaskUid},
+        ticks::Ticks,
         trace::trace_frame::FrameTime,
-        util::{ceil_page_size, floor_page_size, page_size, read_auxv, uses_invisible_guard_page},
+        util::{
+            ceil_page_size,
+            floor_page_size,
+            for_each_readable_chunk,
+            page_size,
+            read_auxv,
+            uses_invisible_guard_page,
+        },
         weak_ptr_set::WeakPtrSet,
     };
     use core::ffi::c_void;
@@ -254,6 +355,7 @@ pub mod address_space {
         O_RDONLY,
         PROT_GROWSDOWN,
         PROT_GROWSUP,
+        SIGTRAP,
     };
     use nix::{fcntl::OFlag, sys::mman::munmap, unistd::getpid};
     use std::{
@@ -452,6 +554,13 @@ pub mod address_space {
         pub internal_count: u32,
         pub user_count: u32,
         pub overwritten_data: u8,
+        pub condition: Option<BreakpointCondition>,
+        /// Hits that counted towards `condition` so far.
+        pub hits: u64,
+        /// Where the last counted hit happened, so that reporting the same hit
+        /// twice (e.g. when resuming at the breakpoint is a noop) doesn't count
+        /// it twice.
+        pub last_hit: Option<(FrameTime, Ticks, pid_t)>,
     }
 
     /// In rr there are a lot of DEBUG_ASSERTs but we don't need them
@@ -463,6 +572,9 @@ pub mod address_space {
                 internal_count: 0,
                 user_count: 0,
                 overwritten_data,
+                condition: None,
+                hits: 0,
+                last_hit: None,
             }
         }
 
@@ -476,9 +588,36 @@ pub mod address_space {
         pub fn do_unref(&mut self, which: BreakpointType) -> u32 {
             let v: &mut u32 = self.counter(which);
             *v -= 1;
+            if self.user_count == 0 {
+                self.set_condition(None);
+            }
             self.internal_count + self.user_count
         }
 
+        pub fn set_condition(&mut self, condition: Option<BreakpointCondition>) {
+            self.condition = condition;
+            self.hits = 0;
+            self.last_hit = None;
+        }
+
+        /// Count a hit by `tid` at trace position (`time`, `ticks`) and return
+        /// whether it should stop replay.
+        pub fn count_hit(&mut self, time: FrameTime, ticks: Ticks, tid: pid_t) -> bool {
+            let condition = match self.condition {
+                None => return true,
+                Some(condition) => condition,
+            };
+            if condition.tid.map_or(false, |only_tid| only_tid != tid) {
+                return false;
+            }
+            let position = (time, ticks, tid);
+            if self.last_hit != Some(position) {
+                self.last_hit = Some(position);
+                self.hits += 1;
+            }
+            condition.nth_hit.map_or(true, |n| self.hits == n)
+        }
+
         /// Called Breakpoint::type() in rr.
         pub fn bp_type(&self) -> BreakpointType {
             // NB: USER breakpoints need to be processed before
@@ -539,6 +678,9 @@ pub mod address_space {
         /// @TODO might we want to have some of these as Option types?
         pub debug_regs_for_exec_read: Vec<u8>,
         pub value_bytes: Vec<u8>,
+        /// What value_bytes was before the change that set `changed`. Equal to
+        /// value_bytes while `changed` is false.
+        pub old_value_bytes: Vec<u8>,
         pub valid: bool,
         pub changed: bool,
     }
@@ -551,7 +693,8 @@ pub mod address_space {
                 write_count: 0,
                 // @TODO is this default what we really need?
                 debug_regs_for_exec_read: Vec::new(),
-                value_bytes: Vec::with_capacity(num_bytes),
+                value_bytes: vec![0u8; num_bytes],
+                old_value_bytes: vec![0u8; num_bytes],
                 valid: false,
                 changed: false,
             }
@@ -664,6 +807,10 @@ pub mod address_space {
         /// behalf of debuggers that assume that model.
         watchpoints: RefCell<HashMap<MemoryRange, Watchpoint>>,
         saved_watchpoints: RefCell<Vec<HashMap<MemoryRange, Watchpoint>>>,
+        /// Pages we've mprotect'ed because the watchpoints didn't fit in the
+        /// debug registers, with the protection we gave them. See
+        /// `protect_software_watchpoints()`.
+        sw_watched_pages: RefCell<BTreeMap<RemotePtr<Void>, ProtFlags>>,
         /// Tracee memory is read and written through this fd, which is
         /// opened for the tracee's magic /proc/{tid}/mem device.  The
         /// advantage of this over ptrace is that we can access it even
@@ -1063,6 +1210,27 @@ pub mod address_space {
             found_mapping.is_some()
         }
 
+        /// Whether any of rd's own mappings, the rd page, the preload thread
+        /// locals and syscallbufs, overlap `range`.
+        pub fn has_rd_mapping_in(&self, range: MemoryRange) -> bool {
+            if range.size() == 0 {
+                return false;
+            }
+            for (_, m) in &self.maps_containing_or_after(range.start()) {
+                if m.map.start() >= range.end() {
+                    break;
+                }
+                if m.flags.intersects(
+                    MappingFlags::IS_SYSCALLBUF
+                        | MappingFlags::IS_THREAD_LOCALS
+                        | MappingFlags::IS_RD_PAGE,
+                ) {
+                    return true;
+                }
+            }
+            false
+        }
+
         pub fn maps(&self) -> Maps {
             Maps::starting_at(self, RemotePtr::null())
         }
@@ -1361,6 +1529,38 @@ pub mod address_space {
             true
         }
 
+        /// Attach `condition` to the user breakpoint at `addr` (or remove its
+        /// condition, if `None`), restarting its hit count. Returns false if
+        /// there is no user breakpoint at `addr`.
+        ///
+        /// The hit count is part of the address space, so a new session starts
+        /// counting from zero. `ReplayTimeline` sets its breakpoints and their
+        /// conditions again in the session a seek backwards starts, and replays
+        /// to the seek target with them set, so the hits on the way are counted
+        /// again and "the nth hit" always means the same point in the replay.
+        pub fn set_breakpoint_condition(
+            &self,
+            addr: RemoteCodePtr,
+            condition: Option<BreakpointCondition>,
+        ) -> bool {
+            match self.breakpoints.borrow_mut().get_mut(&addr) {
+                Some(bp) if bp.user_count > 0 => {
+                    bp.set_condition(condition);
+                    true
+                }
+                _ => false,
+            }
+        }
+
+        /// `t` has hit the user breakpoint at `addr`. Count the hit and return
+        /// whether the breakpoint's condition (if any) says replay should stop.
+        pub fn user_breakpoint_condition_holds(&self, addr: RemoteCodePtr, t: &dyn Task) -> bool {
+            match self.breakpoints.borrow_mut().get_mut(&addr) {
+                Some(bp) => bp.count_hit(t.trace_time(), t.tick_count(), t.rec_tid),
+                None => true,
+            }
+        }
+
         /// Remove a `type` reference to the breakpoint at `addr`.  If
         /// the removed reference was the last, the breakpoint is
         /// destroyed.
@@ -1579,6 +1779,22 @@ pub mod address_space {
             false
         }
 
+        /// The old and new values of every watched range with a watchpoint that
+        /// has fired since the last consume_watchpoint_changes(). Call this
+        /// before consuming the changes.
+        pub fn changed_watchpoint_values(&self) -> Vec<WatchValues> {
+            self.watchpoints
+                .borrow()
+                .iter()
+                .filter(|(_, w)| w.changed)
+                .map(|(r, w)| WatchValues {
+                    addr: r.start(),
+                    old_value: w.old_value_bytes.clone(),
+                    new_value: w.value_bytes.clone(),
+                })
+                .collect()
+        }
+
         /// Return all changed watchpoints in `watches` and clear their changed flags.
         pub fn consume_watchpoint_changes(&self) -> Vec<WatchConfig> {
             self.get_watchpoints_internal(WatchPointFilter::ChangedWatchpoints)
@@ -1632,6 +1848,10 @@ pub mod address_space {
             }
         }
 
+        pub fn has_vdso(&self) -> bool {
+            !self.vdso_start_addr.get().is_null()
+        }
+
         /// Return the vdso mapping of this.
         ///
         /// Panics if there is no Mapping of the vdso
@@ -1643,6 +1863,44 @@ pub mod address_space {
                 .clone()
         }
 
+        /// Hash the contents of every readable mapping, so `diff()` can tell
+        /// later what changed. rd's own mappings and the vvar page, which
+        /// change behind the tracee's back, are left out, and so are pages
+        /// that can't be read (e.g. past the end of a mapped file).
+        pub fn snapshot(&self, t: &mut dyn Task) -> AddressSpaceSnapshot {
+            let mut ranges: Vec<MemoryRange> = Vec::new();
+            for (_, m) in &self.maps() {
+                if m.flags.intersects(
+                    MappingFlags::IS_SYSCALLBUF
+                        | MappingFlags::IS_THREAD_LOCALS
+                        | MappingFlags::IS_RD_PAGE,
+                ) || m.map.is_vvar()
+                    || !m.map.prot().contains(ProtFlags::PROT_READ)
+                {
+                    continue;
+                }
+                ranges.push(MemoryRange::from_range(m.map.start(), m.map.end()));
+            }
+
+            let mut snapshot = AddressSpaceSnapshot::new(page_size());
+            for range in ranges {
+                for_each_readable_chunk(
+                    t,
+                    RemotePtr::cast(range.start()),
+                    range.size(),
+                    SNAPSHOT_CHUNK_SIZE,
+                    |addr, data| snapshot.add_pages(addr.as_usize(), data),
+                );
+            }
+            snapshot
+        }
+
+        /// The ranges of memory whose contents changed since `snapshot` was
+        /// taken of this address space, or that were mapped or unmapped since.
+        pub fn diff(&self, t: &mut dyn Task, snapshot: &AddressSpaceSnapshot) -> Vec<MemoryRange> {
+            snapshot.diff(&self.snapshot(t))
+        }
+
         /// Verify that this cached address space matches what the
         /// kernel thinks it should be.
         pub fn verify(&self, t: &dyn Task) {
@@ -2127,6 +2385,7 @@ pub mod address_space {
                 monitored_mem: Default::default(),
                 dont_fork: Default::default(),
                 saved_watchpoints: Default::default(),
+                sw_watched_pages: Default::default(),
                 child_mem_fd: Default::default(),
                 privileged_traced_syscall_ip_: Default::default(),
                 saved_auxv_: Default::default(),
@@ -2177,7 +2436,7 @@ pub mod address_space {
                 leader_serial,
                 exec_count,
                 brk_start: o.brk_start.clone(),
-                brk_end: o.brk_start.clone(),
+                brk_end: o.brk_end.clone(),
                 mem: o.mem.clone(),
                 shm_sizes: o.shm_sizes.clone(),
                 monitored_mem: o.monitored_mem.clone(),
@@ -2190,6 +2449,8 @@ pub mod address_space {
                 saved_auxv_: o.saved_auxv_.clone(),
                 first_run_event_: Default::default(),
                 watchpoints: o.watchpoints.clone(),
+                // The page protections are inherited along with the memory.
+                sw_watched_pages: o.sw_watched_pages.clone(),
                 breakpoints: o.breakpoints.clone(),
                 // rr does not explicitly initialize these.
                 child_mem_fd: Default::default(),
@@ -2359,13 +2620,14 @@ pub mod address_space {
             let arch = remote.arch();
 
             let path = find_rd_page_file(remote.task());
+            let root_dir_fd = remote.task().reserved_root_dir_fd();
             let mut child_path = AutoRestoreMem::push_cstr(remote, path.as_os_str());
             // skip leading '/' since we want the path to be relative to the root fd
             let remote_path_addr = child_path.get().unwrap() + 1usize;
             let child_fd: i32 = rd_syscall!(
                 child_path,
                 syscall_number_for_openat(arch),
-                RD_RESERVED_ROOT_DIR_FD,
+                root_dir_fd,
                 remote_path_addr.as_usize(),
                 O_RDONLY
             ) as i32;
@@ -2503,7 +2765,10 @@ pub mod address_space {
                     .unwrap()
                     .value_bytes
                     .clone();
-                let t = self.task_set().iter().next().unwrap();
+                // Our caller may have the active task borrowed (e.g. to pass it
+                // to add_watchpoint()), so read through another task of this
+                // address space if there is one, or the mem fd directly.
+                let maybe_t = self.task_set().iter().find(|t| t.try_borrow_mut().is_ok());
                 for i in 0..value_bytes.len() {
                     value_bytes[i] = 0xFF;
                 }
@@ -2512,9 +2777,11 @@ pub mod address_space {
                 let mut bytes_read: usize;
                 while num_bytes > 0 {
                     let buf_pos = addr.as_usize() - watchpoint_range.start().as_usize();
-                    let bytes_read_res = t
-                        .borrow_mut()
-                        .read_bytes_fallible(addr, &mut value_bytes[buf_pos..buf_pos + num_bytes]);
+                    let buf = &mut value_bytes[buf_pos..buf_pos + num_bytes];
+                    let bytes_read_res = match &maybe_t {
+                        Some(t) => t.borrow_mut().read_bytes_fallible(addr, buf),
+                        None => self.read_bytes_from_mem_fd(addr, buf),
+                    };
                     match bytes_read_res {
                         Ok(0) | Err(_) => {
                             valid = false;
@@ -2543,14 +2810,40 @@ pub mod address_space {
             let mut mbm = self.watchpoints.borrow_mut();
             let mut watchpoint_original_mut = mbm.get_mut(watchpoint_range).unwrap();
             watchpoint_original_mut.valid = valid;
-            watchpoint_original_mut.value_bytes = value_bytes;
+            let old_value_bytes = replace(&mut watchpoint_original_mut.value_bytes, value_bytes);
             if mark_changed_if_changed && changed {
+                if !watchpoint_original_mut.changed {
+                    watchpoint_original_mut.old_value_bytes = old_value_bytes;
+                }
                 watchpoint_original_mut.changed = true;
+            } else if !watchpoint_original_mut.changed {
+                watchpoint_original_mut.old_value_bytes =
+                    watchpoint_original_mut.value_bytes.clone();
             }
 
             changed
         }
 
+        fn read_bytes_from_mem_fd(
+            &self,
+            addr: RemotePtr<Void>,
+            buf: &mut [u8],
+        ) -> Result<usize, ()> {
+            let nread = unsafe {
+                libc::pread64(
+                    self.mem_fd().as_raw(),
+                    buf.as_mut_ptr().cast(),
+                    buf.len(),
+                    addr.as_usize() as isize as _,
+                )
+            };
+            if nread < 0 {
+                Err(())
+            } else {
+                Ok(nread as usize)
+            }
+        }
+
         fn update_watchpoint_values(&self, start: RemotePtr<Void>, end: RemotePtr<Void>) {
             let r = MemoryRange::from_range(start, end);
             let mut intersects: Vec<MemoryRange> = Vec::new();
@@ -2572,6 +2865,7 @@ pub mod address_space {
                         continue;
                     }
                     v.changed = false;
+                    v.old_value_bytes = v.value_bytes.clone();
                 }
                 let watching = v.watched_bits();
                 if watching.contains(RwxBits::EXEC_BIT) {
@@ -2675,6 +2969,9 @@ pub mod address_space {
                     }
                 }
                 if ok {
+                    if !self.sw_watched_pages.borrow().is_empty() {
+                        self.set_software_watchpoint_protection(active_task, BTreeMap::new());
+                    }
                     return true;
                 }
             }
@@ -2695,7 +2992,162 @@ pub mod address_space {
                 v.debug_regs_for_exec_read.clear();
             }
 
-            false
+            // Out of debug registers (or the kernel/hypervisor won't let us set
+            // them): watch the pages instead.
+            self.protect_software_watchpoints(active_task)
+        }
+
+        /// Fallback for watchpoints that can't be programmed into the debug
+        /// registers: mprotect every page a watchpoint touches so that accesses
+        /// to it fault. Pages with a read or exec watchpoint lose all access,
+        /// pages with only write watchpoints lose write access. The faults are
+        /// picked up by `handle_software_watchpoint_fault()`.
+        ///
+        /// Only the tracee's pages change; `mem` keeps describing the mappings
+        /// the way the tracee set them up. Note that if the tracee mprotects or
+        /// remaps a watched page the protection is lost until the watchpoints
+        /// are next reallocated.
+        ///
+        /// Returns false if a watched range isn't (entirely) mapped.
+        fn protect_software_watchpoints(&self, active_task: &mut dyn Task) -> bool {
+            let watched: Vec<(MemoryRange, bool)> = self
+                .watchpoints
+                .borrow()
+                .iter()
+                .map(|(range, w)| {
+                    let reads = RwxBits::READ_BIT | RwxBits::EXEC_BIT;
+                    (*range, w.watched_bits().intersects(reads))
+                })
+                .collect();
+            let maybe_wanted = software_watchpoint_pages(&watched, page_size(), |page| {
+                self.mapping_of(page).map(|m| m.map.prot())
+            });
+            if maybe_wanted.is_none() {
+                log!(LogDebug, "Can't watch unmapped memory by page protection");
+            }
+            let all_mapped = maybe_wanted.is_some();
+            self.set_software_watchpoint_protection(active_task, maybe_wanted.unwrap_or_default());
+            all_mapped
+        }
+
+        /// Make the tracee's page protections match `wanted`, restoring the
+        /// original protection of any page we no longer need to watch.
+        fn set_software_watchpoint_protection(
+            &self,
+            active_task: &mut dyn Task,
+            wanted: BTreeMap<RemotePtr<Void>, ProtFlags>,
+        ) {
+            if *self.sw_watched_pages.borrow() == wanted {
+                return;
+            }
+            if !self.task_set().has(active_task.weak_self_ptr()) {
+                match self.any_task_from_task_set() {
+                    Some(t) => {
+                        self.set_software_watchpoint_protection(t.borrow_mut().as_mut(), wanted)
+                    }
+                    None => *self.sw_watched_pages.borrow_mut() = wanted,
+                }
+                return;
+            }
+
+            let mut changes: Vec<(RemotePtr<Void>, ProtFlags)> = Vec::new();
+            for page in self.sw_watched_pages.borrow().keys() {
+                if !wanted.contains_key(page) {
+                    if let Some(m) = self.mapping_of(*page) {
+                        changes.push((*page, m.map.prot()));
+                    }
+                }
+            }
+            for (page, prot) in wanted.iter() {
+                if self.sw_watched_pages.borrow().get(page) != Some(prot) {
+                    changes.push((*page, *prot));
+                }
+            }
+
+            let mut remote = AutoRemoteSyscalls::new(active_task);
+            let arch = remote.arch();
+            for (page, prot) in changes {
+                log!(
+                    LogDebug,
+                    "mprotect({}, {}, {:?}) for watchpoints",
+                    page,
+                    page_size(),
+                    prot
+                );
+                rd_infallible_syscall!(
+                    remote,
+                    syscall_number_for_mprotect(arch),
+                    page.as_usize(),
+                    page_size(),
+                    prot.bits()
+                );
+            }
+            *self.sw_watched_pages.borrow_mut() = wanted;
+        }
+
+        /// `t` is stopped with a SIGSEGV. If it was caused by touching a page
+        /// protected by `protect_software_watchpoints()`, let the access
+        /// happen by singlestepping `t` over the faulting instruction with the
+        /// protection lifted, note which watchpoints fired and return true. `t`
+        /// is then left at the singlestep's SIGTRAP, so the hits show up via
+        /// `has_any_watchpoint_changes()`/`consume_watchpoint_changes()` like
+        /// those of hardware watchpoints do.
+        pub fn handle_software_watchpoint_fault(&self, t: &mut dyn Task) -> bool {
+            let si_addr = unsafe { t.get_siginfo()._sifields._sigfault.si_addr } as usize;
+            let addr = RemotePtr::<Void>::new_from_val(si_addr);
+            if !self
+                .sw_watched_pages
+                .borrow()
+                .contains_key(&floor_page_size(addr))
+            {
+                return false;
+            }
+
+            log!(
+                LogDebug,
+                "Access to {} at {} hit a page watched by protection",
+                addr,
+                t.ip()
+            );
+            self.set_software_watchpoint_protection(t, BTreeMap::new());
+            t.resume_execution(
+                ResumeRequest::ResumeSinglestep,
+                WaitRequest::ResumeWait,
+                TicksRequest::ResumeUnlimitedTicks,
+                None,
+            );
+            ed_assert!(
+                t,
+                t.maybe_stop_sig() == SIGTRAP,
+                "Stepping over watched access stopped with {:?}",
+                t.maybe_stop_sig()
+            );
+
+            for (range, w) in self.watchpoints.borrow_mut().iter_mut() {
+                let watched_bits = w.watched_bits();
+                if watched_bits.intersects(RwxBits::READ_BIT | RwxBits::EXEC_BIT)
+                    && range.contains_ptr(addr)
+                {
+                    w.changed = true;
+                } else if watched_bits.contains(RwxBits::WRITE_BIT) {
+                    let mut value_bytes = vec![0u8; w.value_bytes.len()];
+                    let valid = t.read_bytes_fallible(range.start(), &mut value_bytes)
+                        == Ok(value_bytes.len());
+                    if valid != w.valid || (valid && value_bytes != w.value_bytes) {
+                        w.valid = valid;
+                        if valid {
+                            let old_value_bytes = replace(&mut w.value_bytes, value_bytes);
+                            if !w.changed {
+                                w.old_value_bytes = old_value_bytes;
+                            }
+                        }
+                        w.changed = true;
+                    }
+                }
+            }
+
+            self.protect_software_watchpoints(t);
+            true
         }
 
         /// Merge the mappings adjacent to `key` in memory that are
@@ -2966,7 +3418,7 @@ pub mod address_space {
                 self.monkeypatch_state
                     .as_ref()
                     .unwrap()
-                    .patch_at_preload_init(t.as_record_task().unwrap());
+                    .patch_at_preload_init(t.as_record_task_mut().unwrap());
             }
         }
 
@@ -3446,6 +3898,35 @@ pub extern "C" fn rd_syscall_addr() {
     }
 }
 
+/// The pages to protect so that accesses to the `watched` ranges fault, with
+/// the protection to give each. `watched` has each range and whether reads
+/// (and execution) of it must fault, not just writes. `prot_of` is the
+/// protection the tracee mapped a page with. Returns `None` if a watched
+/// page isn't mapped.
+fn software_watchpoint_pages(
+    watched: &[(MemoryRange, bool)],
+    page_size: usize,
+    prot_of: impl Fn(RemotePtr<Void>) -> Option<ProtFlags>,
+) -> Option<BTreeMap<RemotePtr<Void>, ProtFlags>> {
+    let mut pages: BTreeMap<RemotePtr<Void>, ProtFlags> = BTreeMap::new();
+    for &(range, reads) in watched {
+        let mut page = RemotePtr::new_from_val(range.start().as_usize() / page_size * page_size);
+        while page < range.end() {
+            let original = prot_of(page)?;
+            let prot = if reads {
+                ProtFlags::PROT_NONE
+            } else {
+                original - ProtFlags::PROT_WRITE
+            };
+            if prot != original {
+                *pages.entry(page).or_insert(prot) &= prot;
+            }
+            page += page_size;
+        }
+    }
+    Some(pages)
+}
+
 /// DIFF NOTE: n is signed in rr
 const fn dr_watchpoint(n: u32) -> u32 {
     return 1u32 << n;
@@ -3462,3 +3943,55 @@ fn watchpoint_triggered(debug_status: usize, regs: &[u8]) -> bool {
     }
     false
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn snapshot_diff_merges_pages() {
+        let page = |b: u8| vec![b; 16];
+        let mut before = AddressSpaceSnapshot::new(16);
+        before.add_pages(0x100, &[page(0), page(1), page(2), page(3)].concat());
+        before.add_pages(0x200, &page(9));
+        let mut after = AddressSpaceSnapshot::new(16);
+        after.add_pages(0x100, &[page(0), page(5), page(6), page(3)].concat());
+        after.add_pages(0x300, &[page(7), vec![7; 8]].concat());
+        assert!(before.diff(&before).is_empty());
+        assert_eq!(
+            before.diff(&after),
+            vec![
+                MemoryRange::from_range(0x110usize.into(), 0x130usize.into()),
+                MemoryRange::from_range(0x200usize.into(), 0x210usize.into()),
+                MemoryRange::from_range(0x300usize.into(), 0x310usize.into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn software_watchpoint_protection() {
+        let range = |start: usize, end: usize| MemoryRange::from_range(start.into(), end.into());
+        let rw = ProtFlags::PROT_READ | ProtFlags::PROT_WRITE;
+        let mapped = |page: RemotePtr<Void>| match page.as_usize() {
+            0x100..=0x1ff => Some(rw),
+            0x200..=0x2ff => Some(ProtFlags::PROT_READ),
+            _ => None,
+        };
+        // A write watchpoint across two pages only needs to fault on the
+        // first, which is writable. A read watchpoint makes the page fault on
+        // everything.
+        let watched = [(range(0x1f8, 0x208), false), (range(0x180, 0x184), true)];
+        let pages = software_watchpoint_pages(&watched, 0x100, mapped).unwrap();
+        assert_eq!(
+            pages.into_iter().collect::<Vec<_>>(),
+            vec![(RemotePtr::new_from_val(0x100), ProtFlags::PROT_NONE)]
+        );
+        let pages = software_watchpoint_pages(&watched[..1], 0x100, mapped).unwrap();
+        assert_eq!(
+            pages.into_iter().collect::<Vec<_>>(),
+            vec![(RemotePtr::new_from_val(0x100), ProtFlags::PROT_READ)]
+        );
+        let unmapped = [(range(0x2f0, 0x310), false)];
+        assert!(software_watchpoint_pages(&unmapped, 0x100, mapped).is_none());
+    }
+}
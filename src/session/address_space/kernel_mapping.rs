@@ -27,6 +27,24 @@ use std::{
     os::unix::ffi::OsStrExt,
 };
 
+/// Shared memory with no file behind it that outlives the recording, so
+/// replay has to recreate it (see `KernelMapping::shared_memory_kind()`).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SharedMemoryKind {
+    /// MAP_SHARED | MAP_ANONYMOUS, or a MAP_SHARED mapping of /dev/zero. The
+    /// kernel backs both with a fresh shmem file it calls
+    /// "/dev/zero (deleted)", so the memory starts out zeroed.
+    Anonymous,
+    /// A MAP_SHARED mapping of a POSIX shared memory object, i.e. a file
+    /// created with shm_open() under /dev/shm. It may well have been written
+    /// before it was mapped and is usually unlinked long before replay.
+    PosixShm,
+    /// A MAP_SHARED mapping of a memfd_create() file, which the kernel calls
+    /// "/memfd:<name> (deleted)". Like `PosixShm` it may have been written
+    /// before it was mapped, and it never had a name on disk.
+    Memfd,
+}
+
 /// Clone trait is manually derived. See below.
 /// This type cannot be Copy as fsname_, an OsString, is not Copy.
 #[derive(Debug)]
@@ -207,6 +225,28 @@ impl KernelMapping {
         self.fsname() == "[vsyscall]"
     }
 
+    /// What kind of shared memory without a lasting backing file this is, if
+    /// any. All tasks mapping the same (device, inode) share the contents,
+    /// which is what replay relies on to keep them in sync.
+    pub fn shared_memory_kind(&self) -> Option<SharedMemoryKind> {
+        if !self.flags_.contains(MapFlags::MAP_SHARED) {
+            return None;
+        }
+        let name = self.fsname().as_bytes();
+        if self.flags_.contains(MapFlags::MAP_ANONYMOUS) || name.starts_with(b"/dev/zero") {
+            Some(SharedMemoryKind::Anonymous)
+        } else if name.starts_with(b"/dev/shm/") {
+            Some(SharedMemoryKind::PosixShm)
+        } else if name.starts_with(b"/memfd:") {
+            Some(SharedMemoryKind::Memfd)
+        } else {
+            None
+        }
+    }
+    pub fn is_shared_anonymous(&self) -> bool {
+        self.shared_memory_kind() == Some(SharedMemoryKind::Anonymous)
+    }
+
     pub fn fake_stat(&self) -> stat {
         let mut fake_stat: stat = unsafe { zeroed() };
         fake_stat.st_dev = self.device();
@@ -299,3 +339,56 @@ impl Display for KernelMapping {
         write!(f, "{}", self.str())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(fsname: &str, flags: MapFlags) -> KernelMapping {
+        KernelMapping::new_with_opts(
+            RemotePtr::new_from_val(0x10000),
+            RemotePtr::new_from_val(0x10000 + page_size()),
+            OsStr::new(fsname),
+            KernelMapping::NO_DEVICE,
+            KernelMapping::NO_INODE,
+            ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+            flags,
+            0,
+        )
+    }
+
+    #[test]
+    fn shared_memory_kind() {
+        let shared = MapFlags::MAP_SHARED;
+        let anonymous = MapFlags::MAP_SHARED | MapFlags::MAP_ANONYMOUS;
+        assert_eq!(
+            mapping("", anonymous).shared_memory_kind(),
+            Some(SharedMemoryKind::Anonymous)
+        );
+        assert_eq!(
+            mapping("/dev/zero (deleted)", shared).shared_memory_kind(),
+            Some(SharedMemoryKind::Anonymous)
+        );
+        assert_eq!(
+            mapping("/dev/shm/queue", shared).shared_memory_kind(),
+            Some(SharedMemoryKind::PosixShm)
+        );
+        assert_eq!(
+            mapping("/memfd:ring (deleted)", shared).shared_memory_kind(),
+            Some(SharedMemoryKind::Memfd)
+        );
+        assert!(mapping("", anonymous).is_shared_anonymous());
+
+        // Files that outlive the recording, and private mappings of
+        // anything, aren't shared memory replay has to recreate.
+        let lasting = mapping("/usr/lib/libc.so.6", shared);
+        assert!(lasting.shared_memory_kind().is_none());
+        let private = MapFlags::MAP_PRIVATE;
+        for fsname in &["", "/dev/zero (deleted)", "/dev/shm/queue", "/memfd:ring"] {
+            assert!(mapping(fsname, private).shared_memory_kind().is_none());
+        }
+        let private_anonymous = mapping("", private | MapFlags::MAP_ANONYMOUS);
+        assert!(private_anonymous.shared_memory_kind().is_none());
+        assert!(!mapping("/dev/shm/queue", shared).is_shared_anonymous());
+    }
+}
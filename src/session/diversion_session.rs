@@ -1,14 +1,34 @@
 use super::session_common::kill_all_tasks;
 use crate::{
+    auto_remote_syscalls::AutoRemoteSyscalls,
+    bindings::ptrace::PTRACE_EVENT_EXIT,
     emu_fs::{EmuFs, EmuFsSharedPtr},
+    kernel_abi::{
+        is_clone_syscall,
+        is_fork_syscall,
+        is_kill_syscall,
+        is_rt_sigqueueinfo_syscall,
+        is_rt_tgsigqueueinfo_syscall,
+        is_tgkill_syscall,
+        is_tkill_syscall,
+        is_vfork_syscall,
+        SupportedArch,
+    },
+    kernel_metadata::syscall_name,
+    log::LogLevel::LogDebug,
+    registers::Registers,
     session::{
         session_inner::{session_inner::SessionInner, BreakStatus, RunCommand},
-        task::Task,
+        task::{
+            task_inner::{ResumeRequest, TicksRequest, WaitRequest},
+            Task,
+        },
         Session,
     },
 };
 use std::{
     cell::{Ref, RefCell, RefMut},
+    collections::HashMap,
     ops::{Deref, DerefMut},
     rc::Rc,
 };
@@ -31,11 +51,23 @@ use std::{
 pub struct DiversionSession {
     session_inner: SessionInner,
     emu_fs: EmuFsSharedPtr,
+    syscall_hooks: RefCell<HashMap<(SupportedArch, i32), Rc<RefCell<SyscallHook>>>>,
 }
 
 impl Drop for DiversionSession {
     fn drop(&mut self) {
-        unimplemented!()
+        // We won't permanently leak any OS resources by not ensuring
+        // we've cleaned up here, but sessions can be created and
+        // destroyed many times, and we don't want to temporarily hog
+        // resources.
+        self.kill_all_tasks();
+        debug_assert!(self.task_map.borrow().is_empty());
+        debug_assert!(self.vm_map.borrow().is_empty());
+        log!(
+            LogDebug,
+            "DiversionSession {:?} destroyed",
+            self as *const Self
+        );
     }
 }
 
@@ -53,6 +85,30 @@ pub struct DiversionResult {
 
 pub type DiversionSessionSharedPtr = Rc<RefCell<DiversionSession>>;
 
+/// What a `SyscallHook` wants done with the syscall it intercepted.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SyscallHookAction {
+    /// Let the diverter deal with the syscall as it normally would.
+    Proceed,
+    /// Don't perform the syscall; make it return this value instead (a
+    /// negated errno to make it fail).
+    Return(isize),
+}
+
+/// Called with the diverted task and its registers at entry to a syscall
+/// the hook was registered for. The hook may read and write the task's
+/// memory, e.g. to fill in an outparam of a syscall it spoofs.
+pub type SyscallHook = Box<dyn FnMut(&mut dyn Task, &Registers) -> SyscallHookAction>;
+
+/// A `SyscallHook` for write-like syscalls (write, pwrite64, ...) that
+/// reports all bytes as written without writing anything.
+pub fn write_succeeds_without_side_effects(
+    _t: &mut dyn Task,
+    regs: &Registers,
+) -> SyscallHookAction {
+    SyscallHookAction::Return(regs.arg3_signed())
+}
+
 impl DiversionSession {
     pub fn emufs(&self) -> Ref<'_, EmuFs> {
         self.emu_fs.borrow()
@@ -61,16 +117,171 @@ impl DiversionSession {
         self.emu_fs.borrow_mut()
     }
     pub fn new() -> DiversionSession {
-        unimplemented!()
+        DiversionSession {
+            session_inner: SessionInner::new(),
+            emu_fs: EmuFs::create(),
+            syscall_hooks: Default::default(),
+        }
+    }
+    /// Intercept syscall `syscallno` (of architecture `arch`) made by any
+    /// diverted tracee with `hook`, replacing any hook already registered
+    /// for it.
+    pub fn set_syscall_hook(&self, arch: SupportedArch, syscallno: i32, hook: SyscallHook) {
+        self.syscall_hooks
+            .borrow_mut()
+            .insert((arch, syscallno), Rc::new(RefCell::new(hook)));
+    }
+
+    pub fn clear_syscall_hook(&self, arch: SupportedArch, syscallno: i32) {
+        self.syscall_hooks.borrow_mut().remove(&(arch, syscallno));
+    }
+
+    /// `t` is at entry to a syscall. If a hook spoofs its result, set up
+    /// `t`'s registers as if the syscall had returned that and return true;
+    /// the caller must then not perform the syscall.
+    pub fn maybe_spoof_syscall(&self, t: &mut dyn Task) -> bool {
+        let regs = t.regs_ref().clone();
+        let arch = t.arch();
+        let syscallno = regs.original_syscallno() as i32;
+        // Don't hold the map borrowed while the hook runs, so it can
+        // register, replace or clear hooks (including itself).
+        let hook = match self.syscall_hooks.borrow().get(&(arch, syscallno)) {
+            Some(hook) => hook.clone(),
+            None => return false,
+        };
+        let action = (&mut *hook.borrow_mut())(t, &regs);
+
+        match action {
+            SyscallHookAction::Proceed => false,
+            SyscallHookAction::Return(result) => {
+                log!(
+                    LogDebug,
+                    "Spoofing result {} for {} in diverted task {}",
+                    result,
+                    syscall_name(syscallno, arch),
+                    t.tid
+                );
+                let mut r = regs;
+                r.set_syscall_result_signed(result);
+                t.set_regs(&r);
+                true
+            }
+        }
     }
+
     /// Try make progress in this diversion session. Run task t if possible.
     pub fn diversion_step(
         &self,
-        _t: &mut dyn Task,
-        _command: Option<RunCommand>,
-        _signal_to_deliver: Option<i32>,
+        t: &mut dyn Task,
+        maybe_command: Option<RunCommand>,
+        signal_to_deliver: Option<i32>,
     ) -> DiversionResult {
-        unimplemented!()
+        self.assert_fully_initialized();
+        let command = maybe_command.unwrap_or(RunCommand::RunContinue);
+        let mut result = DiversionResult {
+            status: DiversionStatus::DiversionContinue,
+            break_status: BreakStatus::new(),
+        };
+
+        // An exit might have occurred while processing a previous syscall.
+        if t.maybe_ptrace_event() == PTRACE_EVENT_EXIT {
+            result.status = DiversionStatus::DiversionExited;
+            return result;
+        }
+
+        let how = match command {
+            RunCommand::RunContinue => {
+                log!(LogDebug, "Continuing to next syscall");
+                ResumeRequest::ResumeSysemu
+            }
+            RunCommand::RunSinglestep => {
+                log!(LogDebug, "Stepping to next insn/syscall");
+                ResumeRequest::ResumeSysemuSinglestep
+            }
+            RunCommand::RunSinglestepFastForward => {
+                fatal!("Illegal run command RunSinglestepFastForward")
+            }
+        };
+        t.resume_execution(
+            how,
+            WaitRequest::ResumeWait,
+            TicksRequest::ResumeUnlimitedTicks,
+            signal_to_deliver,
+        );
+
+        if t.maybe_ptrace_event() == PTRACE_EVENT_EXIT {
+            result.status = DiversionStatus::DiversionExited;
+            result.break_status.task_exit = true;
+            return result;
+        }
+
+        if t.maybe_stop_sig().is_sig() {
+            log!(LogDebug, "Pending signal: {:?}", t.get_siginfo());
+            result.break_status = self.diagnose_debugger_trap(t, command);
+            log!(
+                LogDebug,
+                "Diversion break at ip={}; break={}, watch={}, singlestep={}",
+                t.ip(),
+                result.break_status.breakpoint_hit,
+                !result.break_status.watchpoints_hit.is_empty(),
+                result.break_status.singlestep_complete
+            );
+            return result;
+        }
+
+        self.process_syscall(t);
+        self.check_for_watchpoint_changes(t, &mut result.break_status);
+        result
+    }
+
+    /// `t` is at entry to a syscall under sysemu. Spoof, suppress or perform
+    /// it and leave `t` just after the syscall instruction.
+    fn process_syscall(&self, t: &mut dyn Task) {
+        let arch = t.arch();
+        let syscallno = t.regs_ref().original_syscallno() as i32;
+        log!(LogDebug, "Processing {}", syscall_name(syscallno, arch));
+
+        if self.maybe_spoof_syscall(t) {
+            let r = t.regs_ref().clone();
+            t.finish_emulated_syscall();
+            t.set_regs(&r);
+            return;
+        }
+
+        // The params of these include namespaced thread ids, which aren't
+        // meaningful in a diversion. New tasks would escape the diversion.
+        if is_kill_syscall(syscallno, arch)
+            || is_tkill_syscall(syscallno, arch)
+            || is_tgkill_syscall(syscallno, arch)
+            || is_rt_sigqueueinfo_syscall(syscallno, arch)
+            || is_rt_tgsigqueueinfo_syscall(syscallno, arch)
+            || is_clone_syscall(syscallno, arch)
+            || is_fork_syscall(syscallno, arch)
+            || is_vfork_syscall(syscallno, arch)
+        {
+            t.finish_emulated_syscall();
+            let mut r = t.regs_ref().clone();
+            r.set_syscall_result_signed(-libc::ENOSYS as isize);
+            t.set_regs(&r);
+            return;
+        }
+
+        // Perform the syscall for real.
+        t.finish_emulated_syscall();
+        let mut remote = AutoRemoteSyscalls::new(t);
+        let regs = remote.initial_regs_ref().clone();
+        let result = remote.syscall(
+            regs.original_syscallno() as i32,
+            &[
+                regs.arg1(),
+                regs.arg2(),
+                regs.arg3(),
+                regs.arg4(),
+                regs.arg5(),
+                regs.arg6(),
+            ],
+        );
+        remote.initial_regs_mut().set_syscall_result_signed(result);
     }
 }
 
@@ -1,23 +1,141 @@
-use super::session_common::kill_all_tasks;
+use super::session_common::{forget_vms_and_thread_groups, kill_all_tasks};
 use crate::{
-    event::Switchable,
-    kernel_abi::SupportedArch,
-    scheduler::Scheduler,
-    seccomp_filter_rewriter::SeccompFilterRewriter,
+    auto_remote_syscalls::{AutoRemoteSyscalls, AutoRestoreMem},
+    bindings::{
+        ptrace::{
+            ptrace,
+            PTRACE_CONT,
+            PTRACE_DETACH,
+            PTRACE_EVENT_EXEC,
+            PTRACE_EVENT_EXIT,
+            PTRACE_EVENT_SECCOMP,
+            PTRACE_EVENT_VFORK_DONE,
+            PTRACE_GETEVENTMSG,
+            PTRACE_LISTEN,
+        },
+        signal::siginfo_t,
+    },
+    event::{Event, EventType, InterceptedSyscallEventData, Switchable, SyscallState},
+    fd_table::FdTable,
+    fuse_files::FuseFilePolicy,
+    host_check::{check_host, CheckStatus},
+    io_uring::IoUringPolicy,
+    kernel_abi::{
+        is_exit_group_syscall,
+        is_exit_syscall,
+        syscall_number_for_munmap,
+        syscall_number_for_rt_tgsigqueueinfo,
+        syscall_number_for_tgkill,
+        SupportedArch,
+        RD_NATIVE_ARCH,
+    },
+    kernel_metadata::syscall_name,
+    kernel_supplement::{
+        SECCOMP_RET_ACTION_FULL,
+        SECCOMP_RET_DATA,
+        SECCOMP_RET_ERRNO,
+        SECCOMP_RET_LOG,
+    },
+    log::LogLevel::{LogDebug, LogWarn},
+    memory_checksum::{checksum_process_memory, should_checksum},
+    perf_counters::{PerfCounters, TicksSemantics, TIME_SLICE_SIGNAL},
+    record_attach::{cmd_line, open_fds, seize_process_tree},
+    record_environment::RecordEnvironment,
+    record_signal::{handle_signal, try_handle_trapped_instruction},
+    record_syscall::{
+        check_syscall_exit_regs,
+        is_duplicate_syscall_entry_stop,
+        note_syscall_exit_stop,
+        on_syscall_entry_stop,
+        rec_prepare_syscall,
+        rec_process_syscall,
+        route_seccomp_stop,
+        SeccompStopAction,
+        SyscallEntryAction,
+        SyscallEntryStop,
+    },
+    remote_ptr::{RemotePtr, Void},
+    rseq::abort_rseq_critical_section,
+    scheduler::{Rescheduled, Scheduler},
+    seccomp_filter_rewriter::{SeccompFilterRewriter, SECCOMP_MAGIC_SKIP_ORIGINAL_SYSCALLNO},
+    seccomp_unotify::SeccompNotifyListener,
+    seeded_random::SeededRandom,
     session::{
-        session_inner::session_inner::SessionInner,
-        task::{Task, TaskSharedPtr},
+        address_space::{address_space::AddressSpace, kernel_mapping::KernelMapping, MappingFlags},
+        session_inner::session_inner::{PtraceSyscallSeccompOrdering, SessionInner},
+        task::{
+            record_task::record_task::{AllowSyscallbufReset, FlushSyscallbuf, RecordTask},
+            task_common::clone_task_common,
+            task_inner::{
+                task_inner::{CloneReason, PtraceData, SaveTraceeFdNumber, TaskInner},
+                CloneFlags,
+                ResumeRequest,
+                TicksRequest,
+                WaitRequest,
+            },
+            Task,
+            TaskSharedPtr,
+        },
         Session,
+        SessionSharedPtr,
+    },
+    syscall_interception::{InterceptedSyscall, InterceptionBackend, PtraceInterception},
+    taskish_uid::{AddressSpaceUid, TaskUid},
+    thread_group::{ThreadGroup, ThreadGroupSharedPtr},
+    ticks::Ticks,
+    trace::{
+        trace_stream::TraceStream,
+        trace_task_event::TraceTaskEvent,
+        trace_writer::{CloseStatus, MappingOrigin, RecordInTrace, TraceWriter},
     },
-    taskish_uid::TaskUid,
-    thread_group::ThreadGroupSharedPtr,
-    trace::{trace_stream::TraceStream, trace_writer::TraceWriter},
-    util::{good_random, CPUIDData, CPUID_GETEXTENDEDFEATURES, CPUID_GETFEATURES, CPUID_GETXSAVE},
+    util::{
+        choose_cpu,
+        for_each_readable_chunk,
+        good_random,
+        is_uninterruptible_sleep,
+        set_task_cpu_affinity,
+        tracee_cpus,
+        u8_raw_slice_mut,
+        BindCPU,
+        CPUIDData,
+        CPUID_GETEXTENDEDFEATURES,
+        CPUID_GETFEATURES,
+        CPUID_GETXSAVE,
+    },
+    virtual_clock::VirtualClock,
+    wait_status::{WaitStatus, WaitType},
+};
+use libc::{
+    pid_t,
+    waitpid,
+    CLONE_FILES,
+    CLONE_FS,
+    CLONE_SIGHAND,
+    CLONE_SYSVSEM,
+    CLONE_THREAD,
+    CLONE_VM,
+    EINTR,
+    ESRCH,
+    SIGCHLD,
+    SIGKILL,
+    SIGPWR,
+    SIGSEGV,
+    WNOHANG,
+    __WALL,
 };
-use libc::pid_t;
+use nix::{errno::errno, sys::stat::stat};
 use std::{
-    cell::{Ref, RefCell, RefMut},
+    cell::{Cell, Ref, RefCell, RefMut},
+    collections::HashMap,
+    env,
+    ffi::{OsStr, OsString},
+    fs,
+    io::{self, ErrorKind},
+    mem::{size_of, zeroed},
     ops::{Deref, DerefMut},
+    os::unix::ffi::OsStrExt,
+    rc::Rc,
+    slice,
 };
 
 #[derive(Clone, Eq, PartialEq)]
@@ -38,6 +156,9 @@ const CPUID_RTM_FLAG: u32 = 1 << 11;
 const CPUID_RDSEED_FLAG: u32 = 1 << 18;
 const CPUID_XSAVEOPT_FLAG: u32 = 1 << 0;
 
+/// How much of a mapping `capture_attached_process()` copies at a time.
+const ATTACH_COPY_CHUNK_SIZE: usize = 1 << 20;
+
 impl Default for DisableCPUIDFeatures {
     fn default() -> Self {
         Self::new()
@@ -112,16 +233,23 @@ impl TraceUuid {
 
 pub struct RecordSession {
     session_inner: SessionInner,
-    trace_out: TraceWriter,
+    trace_out: RefCell<TraceWriter>,
     scheduler_: RefCell<Scheduler>,
-    initial_thread_group: ThreadGroupSharedPtr,
-    seccomp_filter_rewriter_: SeccompFilterRewriter,
+    /// How the record loop learns about syscalls. Ptrace stops unless
+    /// `--experimental-unotify` was given.
+    interception_: RefCell<Box<dyn InterceptionBackend>>,
+    /// The thread group of the initial tracee. Its exit status is rd's.
+    initial_thread_group: RefCell<Option<ThreadGroupSharedPtr>>,
+    seccomp_filter_rewriter_: RefCell<SeccompFilterRewriter>,
+    /// Set once a tracee installs a seccomp filter that can hand syscalls to
+    /// a notification listener, see `SeccompFilterRewriter`.
+    notifying_seccomp_filter: Cell<bool>,
     // DIFF NOTE: This is a unique_ptr in rr
     trace_id: TraceUuid,
     disable_cpuid_features_: DisableCPUIDFeatures,
     ignore_sig: i32,
     continue_through_sig: i32,
-    last_task_switchable: Switchable,
+    last_task_switchable: Cell<Switchable>,
     syscall_buffer_size_: usize,
     syscallbuf_desched_sig_: u8,
     use_syscall_buffer_: bool,
@@ -133,17 +261,235 @@ pub struct RecordSession {
     asan_active_: bool,
     /// When true, wait for all tracees to exit before finishing recording.
     wait_for_all_: bool,
-
-    output_trace_dir: String,
+    io_uring_policy_: IoUringPolicy,
+    /// Set by `--virtual-clock`.
+    virtual_clock_: Option<VirtualClock>,
+    /// Set by `--random-seed`.
+    seeded_random_: Option<RefCell<SeededRandom>>,
+    /// Set by `--capture-reads`.
+    capture_reads_: Vec<OsString>,
+    /// Thread groups whose leader exited before their other threads, by
+    /// tgid (see `ThreadGroup::zombie_leader`). Kept until waitpid() reports
+    /// the leader, once the rest are gone too.
+    zombie_leaders: RefCell<HashMap<pid_t, ThreadGroupSharedPtr>>,
 }
 
 impl Drop for RecordSession {
     fn drop(&mut self) {
-        unimplemented!()
+        self.kill_all_tasks();
     }
 }
 
+/// What `RecordSession::record_step()` did.
+pub enum RecordResult {
+    /// Recording continues.
+    StepContinue,
+    /// All tracees have exited. The status is the initial tracee's.
+    StepExited(WaitStatus),
+}
+
 impl RecordSession {
+    /// A session that will record `exe_args` to `output_trace_dir`, or to a
+    /// new directory in the trace save directory if that's `None`, bound to
+    /// the CPU `bind_cpu` picks, with default options. Set any others, then
+    /// `start()` it.
+    pub fn new(
+        exe_args: &[OsString],
+        output_trace_dir: Option<&OsStr>,
+        bind_cpu: BindCPU,
+    ) -> RecordSession {
+        RecordSession {
+            session_inner: Default::default(),
+            trace_out: RefCell::new(TraceWriter::new(
+                &exe_args[0],
+                choose_cpu(bind_cpu),
+                output_trace_dir.unwrap_or_else(|| OsStr::new("")),
+                PerfCounters::default_ticks_semantics(),
+            )),
+            scheduler_: Default::default(),
+            interception_: RefCell::new(Box::new(PtraceInterception)),
+            initial_thread_group: Default::default(),
+            seccomp_filter_rewriter_: Default::default(),
+            notifying_seccomp_filter: Cell::new(false),
+            trace_id: TraceUuid::new(),
+            disable_cpuid_features_: DisableCPUIDFeatures::new(),
+            ignore_sig: 0,
+            continue_through_sig: 0,
+            last_task_switchable: Cell::new(Switchable::PreventSwitch),
+            syscall_buffer_size_: 0,
+            syscallbuf_desched_sig_: SIGPWR as u8,
+            use_syscall_buffer_: false,
+            use_file_cloning_: true,
+            use_read_cloning_: true,
+            enable_chaos_: false,
+            asan_active_: false,
+            wait_for_all_: false,
+            io_uring_policy_: Default::default(),
+            zombie_leaders: Default::default(),
+            virtual_clock_: None,
+            seeded_random_: None,
+            capture_reads_: Vec::new(),
+        }
+    }
+
+    /// Spawn the initial tracee, running `exe_args` with the environment
+    /// `envp` ("NAME=value" strings). Recording starts with the first
+    /// `record_step()`.
+    pub fn start(self, exe_args: &[OsString], envp: &[OsString]) -> SessionSharedPtr {
+        let exe_path = lookup_by_path(&exe_args[0]);
+        let error_fd = self.create_spawn_task_error_pipe();
+        let sock_fd_out = self.tracee_socket_fd();
+
+        let mut rc: SessionSharedPtr = Rc::new(Box::new(self));
+        let weak_self = Rc::downgrade(&rc);
+        // We never change the weak_self pointer so its a good idea to use
+        // a bit of unsafe here.
+        unsafe { Rc::get_mut_unchecked(&mut rc) }.weak_self = weak_self;
+        // The rec_tid is ignored: recorded tids are the real ones.
+        let t = TaskInner::spawn(
+            (*rc).as_ref(),
+            &error_fd,
+            sock_fd_out,
+            SaveTraceeFdNumber::SaveToSession,
+            &exe_path,
+            exe_args,
+            envp,
+            0,
+        );
+        rc.on_create(t);
+
+        rc
+    }
+
+    /// Attach to the running process `pid` and its threads and descendants
+    /// (see `seize_process_tree()`), and write what they look like now to
+    /// the trace. Recording continues from there with the first
+    /// `record_step()`.
+    pub fn attach(self, pid: pid_t) -> SessionSharedPtr {
+        let seized = seize_process_tree(pid);
+        if seized.is_empty() {
+            clean_fatal!("Couldn't attach to process {}", pid);
+        }
+
+        let mut rc: SessionSharedPtr = Rc::new(Box::new(self));
+        let weak_self = Rc::downgrade(&rc);
+        unsafe { Rc::get_mut_unchecked(&mut rc) }.weak_self = weak_self;
+        let session = rc.as_record().unwrap();
+        for s in &seized {
+            let maybe_leader = if s.tid == s.tgid {
+                None
+            } else {
+                match session.find_task_from_rec_tid(s.tgid) {
+                    Some(leader) => Some(leader),
+                    None => {
+                        // The leader exited before we got to it.
+                        log!(
+                            LogWarn,
+                            "Not recording {}: its thread group leader is gone",
+                            s.tid
+                        );
+                        unsafe { ptrace(PTRACE_DETACH, s.tid, 0, 0) };
+                        continue;
+                    }
+                }
+            };
+
+            let serial = session.next_task_serial();
+            let t = session.new_task(s.tid, Some(s.tid), serial, RD_NATIVE_ARCH);
+            let wrapped_t = Rc::new(RefCell::new(t));
+            let weak_t = Rc::downgrade(&wrapped_t);
+            wrapped_t.borrow_mut().weak_self = weak_t.clone();
+            match maybe_leader {
+                None => {
+                    let maybe_parent_tg = s
+                        .parent
+                        .and_then(|parent| session.find_thread_group_from_pid(parent))
+                        .map(|tg| Rc::downgrade(&tg));
+                    let tg = ThreadGroup::new(
+                        session.weak_self.clone(),
+                        maybe_parent_tg,
+                        s.tid,
+                        s.tid,
+                        s.tid,
+                        serial,
+                    );
+                    tg.borrow_mut().task_set_mut().insert(weak_t.clone());
+                    wrapped_t.borrow_mut().tg = Some(tg);
+                    let exe = fs::read_link(format!("/proc/{}/exe", s.tid))
+                        .map(|p| p.into_os_string())
+                        .unwrap_or_default();
+                    let vm = session.create_vm(
+                        wrapped_t.borrow_mut().as_mut(),
+                        Some(exe.as_os_str()),
+                        None,
+                    );
+                    wrapped_t.borrow_mut().as_ = Some(vm);
+                    wrapped_t.borrow_mut().fds = Some(FdTable::create(weak_t.clone()));
+                }
+                Some(leader_rc) => {
+                    let leader = leader_rc.borrow();
+                    let mut t = wrapped_t.borrow_mut();
+                    t.tg = leader.tg.clone();
+                    t.as_ = leader.as_.clone();
+                    t.fds = leader.fds.clone();
+                    t.thread_group_shr_ptr()
+                        .borrow_mut()
+                        .task_set_mut()
+                        .insert(weak_t.clone());
+                    t.vm_shr_ptr().task_set_mut().insert(weak_t.clone());
+                    t.fd_table_shr_ptr()
+                        .borrow_mut()
+                        .task_set_mut()
+                        .insert(weak_t.clone());
+                }
+            }
+            {
+                let mut t = wrapped_t.borrow_mut();
+                // seize_process_tree() has reaped the stop already.
+                t.did_waitpid(s.status);
+                t.open_mem_fd_if_needed();
+            }
+            session.on_create(wrapped_t.clone());
+
+            let mut t_ref = wrapped_t.borrow_mut();
+            let t = t_ref.as_record_task_mut().unwrap();
+            if s.tid == s.tgid {
+                session.capture_attached_process(t, s.parent);
+            } else {
+                session.capture_attached_thread(t, s.tgid);
+            }
+        }
+
+        rc
+    }
+
+    /// Make sure the host can record before starting: die with advice if it
+    /// can't, and log the limitations `rd check` would warn about.
+    pub fn check_host_for_recording() {
+        for r in check_host() {
+            let advice = r.advice.unwrap_or_default();
+            match r.status {
+                CheckStatus::Ok => (),
+                CheckStatus::Warning => log!(LogWarn, "{}: {}. {}", r.name, r.detail, advice),
+                CheckStatus::Error => {
+                    clean_fatal!(
+                        "{}: {}. {}\nRun `rd check` for a full report.",
+                        r.name,
+                        r.detail,
+                        advice
+                    );
+                }
+            }
+        }
+        for warning in RecordEnvironment::capture().warnings() {
+            log!(
+                LogWarn,
+                "Recording {}; timing-sensitive tracees may behave differently",
+                warning
+            );
+        }
+    }
+
     pub fn scheduler(&self) -> Ref<'_, Scheduler> {
         self.scheduler_.borrow()
     }
@@ -151,21 +497,1149 @@ impl RecordSession {
         self.scheduler_.borrow_mut()
     }
 
+    pub fn interception(&self) -> Ref<'_, Box<dyn InterceptionBackend>> {
+        self.interception_.borrow()
+    }
+    pub fn interception_mut(&self) -> RefMut<'_, Box<dyn InterceptionBackend>> {
+        self.interception_.borrow_mut()
+    }
+
+    /// Switch to another interception backend, e.g. `UnotifyInterception`
+    /// for `--experimental-unotify`. Must be done before the initial tracee
+    /// is spawned, since that's when the backend's filter is installed.
+    pub fn set_interception(&self, backend: Box<dyn InterceptionBackend>) {
+        debug_assert!(self.tasks().is_empty());
+        *self.interception_.borrow_mut() = backend;
+    }
+
+    /// Give the interception backend the listener of its filter, which the
+    /// initial tracee just installed, and have the scheduler watch it.
+    pub fn set_interception_listener(&self, listener: SeccompNotifyListener) {
+        let mut interception = self.interception_mut();
+        interception.set_listener(listener);
+        self.scheduler_mut()
+            .set_interception_fd(interception.poll_fd());
+    }
+
+    /// Answer the syscalls waiting on the interception backend, recording
+    /// each before its task can see the result.
+    fn handle_intercepted_syscalls(&self) {
+        self.interception_mut()
+            .handle_pending(&mut |syscall| self.record_intercepted_syscall(syscall));
+    }
+
+    fn record_intercepted_syscall(&self, syscall: &InterceptedSyscall) {
+        let t_rc = match self.find_task_from_rec_tid(syscall.tid) {
+            Some(t_rc) => t_rc,
+            None => {
+                fatal!("Intercepted a syscall of unknown task {}", syscall.tid);
+                unreachable!()
+            }
+        };
+        let mut t_ref = t_rc.borrow_mut();
+        let t = t_ref.as_record_task_mut().unwrap();
+        let ev = Event::new_intercepted_syscall_event(InterceptedSyscallEventData {
+            arch: syscall.arch,
+            number: syscall.syscallno,
+            result: syscall.result,
+        });
+        t.record_event(&ev, None, None, None);
+    }
+
+    pub fn enable_chaos(&self) -> bool {
+        self.enable_chaos_
+    }
+
+    /// Count ticks with `ticks_semantics` instead of the CPU's default, and
+    /// say so in the trace header. Must be called before any tasks are
+    /// created.
+    pub fn set_ticks_semantics(&mut self, ticks_semantics: TicksSemantics) {
+        debug_assert!(self.task_map.borrow().is_empty());
+        if !PerfCounters::supports_ticks_semantics(ticks_semantics) {
+            clean_fatal!(
+                "Ticks semantics {:?} not supported on this CPU",
+                ticks_semantics
+            );
+        }
+        self.ticks_semantics_ = ticks_semantics;
+        self.trace_writer_mut().set_ticks_semantics(ticks_semantics);
+    }
+
+    /// Turn chaos mode on or off. When `maybe_seed` is provided the scheduler
+    /// RNG is reseeded with it, otherwise the randomly chosen seed is kept.
+    /// Either way the seed in use is printed when chaos mode is enabled so
+    /// that a recording that exposed a bug can be retried with the same
+    /// scheduling decisions.
+    pub fn set_enable_chaos(&mut self, enable_chaos: bool, maybe_seed: Option<u64>) {
+        let seed = {
+            let mut scheduler = self.scheduler_mut();
+            scheduler.set_enable_chaos(enable_chaos);
+            if let Some(seed) = maybe_seed {
+                scheduler.set_chaos_seed(seed);
+            }
+            scheduler.chaos_seed()
+        };
+        self.enable_chaos_ = enable_chaos;
+        if enable_chaos {
+            eprintln!("rd: Chaos mode enabled. Seed: {}", seed);
+        }
+    }
+
+    /// What tracees' io_uring_setup() calls get, see `io_uring`.
+    pub fn io_uring_policy(&self) -> IoUringPolicy {
+        self.io_uring_policy_
+    }
+
+    pub fn set_io_uring_policy(&mut self, policy: IoUringPolicy) {
+        self.io_uring_policy_ = policy;
+    }
+
+    /// The clock tracees' clock_gettime(), gettimeofday() and time() calls
+    /// read, if they don't read the host's. See `virtual_clock`.
+    pub fn virtual_clock(&self) -> Option<VirtualClock> {
+        self.virtual_clock_
+    }
+
+    pub fn set_virtual_clock(&mut self, clock: Option<VirtualClock>) {
+        self.virtual_clock_ = clock;
+    }
+
+    /// The generator tracees' getrandom() calls and reads of /dev/urandom
+    /// get their bytes from, if they don't get them from the kernel. See
+    /// `seeded_random`.
+    pub fn seeded_random(&self) -> Option<RefMut<'_, SeededRandom>> {
+        self.seeded_random_.as_ref().map(|r| r.borrow_mut())
+    }
+
+    pub fn set_random_seed(&mut self, seed: Option<u64>) {
+        self.seeded_random_ = seed.map(|s| RefCell::new(SeededRandom::new(s)));
+    }
+
+    /// Patterns of paths whose reads are recorded in full, without syscall
+    /// buffering. See `capture_reads`.
+    pub fn capture_reads(&self) -> &[OsString] {
+        &self.capture_reads_
+    }
+
+    pub fn set_capture_reads(&mut self, patterns: Vec<OsString>) {
+        self.trace_writer_mut().set_capture_reads(patterns.clone());
+        self.capture_reads_ = patterns;
+    }
+
+    /// What happens to files tracees open or map on FUSE filesystems, see
+    /// `fuse_files`.
+    pub fn fuse_file_policy(&self) -> FuseFilePolicy {
+        self.trace_writer().fuse_file_policy()
+    }
+
+    pub fn set_fuse_file_policy(&mut self, policy: FuseFilePolicy) {
+        self.trace_writer_mut().set_fuse_file_policy(policy);
+    }
+
+    pub fn seccomp_filter_rewriter(&self) -> Ref<'_, SeccompFilterRewriter> {
+        self.seccomp_filter_rewriter_.borrow()
+    }
+    pub fn seccomp_filter_rewriter_mut(&self) -> RefMut<'_, SeccompFilterRewriter> {
+        self.seccomp_filter_rewriter_.borrow_mut()
+    }
+
+    /// A tracee installed a seccomp filter that can hand syscalls to a
+    /// notification listener. Those syscalls never stop at a seccomp stop,
+    /// so from now on tasks are resumed to every syscall's entry.
+    pub fn note_notifying_seccomp_filter(&self) {
+        self.notifying_seccomp_filter.set(true);
+    }
+
+    /// Interrupt tasks after at most `max_ticks` ticks so another task can be
+    /// scheduled.
+    pub fn set_max_ticks(&self, max_ticks: Ticks) {
+        self.scheduler_mut().set_max_ticks(max_ticks);
+    }
+
+    /// In chaos mode, make timeslices between `low` and `high` ticks long
+    /// instead of anything up to the maximum.
+    pub fn set_chaos_ticks_range(&self, low: Ticks, high: Ticks) {
+        self.scheduler_mut().set_chaos_ticks_range(low, high);
+    }
+
+    /// Schedule tasks in strict round-robin order, each for exactly `quantum`
+    /// ticks, instead of by priority. The trace header notes the quantum.
+    /// Not compatible with chaos mode.
+    pub fn set_round_robin_quantum(&mut self, quantum: Option<Ticks>) {
+        debug_assert!(quantum.is_none() || !self.enable_chaos_);
+        self.scheduler_mut().set_round_robin_quantum(quantum);
+        self.trace_writer_mut().set_round_robin_quantum(quantum);
+    }
+
+    /// The CPU rd and all tracees are bound to, if any. It's recorded in the
+    /// trace header so that replay binds to the same one: tracees can tell
+    /// which CPU they're on, and tick counts are only reliable when tasks
+    /// don't wander between cores.
+    pub fn bound_cpu(&self) -> Option<u32> {
+        self.trace_writer().bound_to_cpu()
+    }
+
+    /// In chaos mode, move tasks to random CPUs as their timeslices start
+    /// (see `maybe_migrate_task()`) to shake out bugs that depend on where
+    /// threads run. Only for recordings that aren't bound to a CPU.
+    pub fn set_chaos_cpu_migration(&self, enable: bool) {
+        debug_assert!(!enable || self.bound_cpu().is_none());
+        let cpus = if enable { tracee_cpus() } else { Vec::new() };
+        self.scheduler_mut().set_chaos_migration_cpus(cpus);
+    }
+
+    /// Call when `t` starts a new timeslice. Moves it to another CPU if the
+    /// chaos mode scheduler says so.
+    pub fn maybe_migrate_task(&self, t: &RecordTask) {
+        let maybe_cpu = self.scheduler_mut().choose_migration_cpu();
+        if let Some(cpu) = maybe_cpu {
+            if set_task_cpu_affinity(t.tid, cpu) {
+                log!(LogDebug, "Moved {} to CPU {}", t.tid, cpu);
+            }
+        }
+    }
+
     pub fn syscallbuf_desched_sig(&self) -> u8 {
         self.syscallbuf_desched_sig_
     }
 
+    pub fn disable_cpuid_features(&self) -> &DisableCPUIDFeatures {
+        &self.disable_cpuid_features_
+    }
+
     pub fn use_file_cloning(&self) -> bool {
         self.use_file_cloning_
     }
+    /// Whether big reads of files may be cloned into the trace rather than
+    /// copied, see `record_syscall::record_read()`.
+    pub fn use_read_cloning(&self) -> bool {
+        self.use_read_cloning_
+    }
     pub fn use_syscall_buffer(&self) -> bool {
         self.use_syscall_buffer_
     }
-    pub fn trace_stream(&self) -> Option<&TraceStream> {
-        Some(&self.trace_out)
+    pub fn trace_writer(&self) -> Ref<'_, TraceWriter> {
+        self.trace_out.borrow()
     }
-    pub fn trace_stream_mut(&mut self) -> Option<&mut TraceStream> {
-        Some(&mut self.trace_out)
+    pub fn trace_writer_mut(&self) -> RefMut<'_, TraceWriter> {
+        self.trace_out.borrow_mut()
+    }
+
+    /// Checksum the memory of `t` if `--checksum` asks for it at `ev`. Call
+    /// this just before the frame for `ev` is written, so that the checksums
+    /// are filed under the event's time.
+    pub fn checksum_memory_for_event(&self, t: &mut RecordTask, ev: &Event) {
+        let time = self.trace_writer().time();
+        if self.done_initial_exec() && should_checksum(ev, time) {
+            checksum_process_memory(t, &self.trace_writer().checksum_path(time, t.rec_tid));
+        }
+    }
+
+    /// See `TraceWriter::note_directory_read()`.
+    pub fn note_directory_read(&self, path: &OsStr) {
+        self.trace_writer().note_directory_read(path)
+    }
+
+    /// Write what `rd record -p` found in `t`, the leader of a thread group
+    /// that was already running when rd attached to it, to the trace, as if
+    /// `parent` (if it's not the process rd was asked to attach to) had just
+    /// forked it and it had just exec'd: a task event with its executable,
+    /// command line and open fds, a mapping record (with the contents, where
+    /// replay can't get them from a file) for each of its mappings, and a frame
+    /// with its registers.
+    pub fn capture_attached_process(&self, t: &mut RecordTask, parent: Option<pid_t>) {
+        if let Some(parent) = parent {
+            self.trace_writer_mut()
+                .write_task_event(&TraceTaskEvent::for_clone(
+                    t.tid,
+                    parent,
+                    t.own_namespace_tid(),
+                    SIGCHLD,
+                ));
+        }
+
+        let exe = fs::read_link(format!("/proc/{}/exe", t.tid))
+            .map(|p| p.into_os_string())
+            .unwrap_or_default();
+        let mut exe_base = RemotePtr::null();
+        let mut kms: Vec<KernelMapping> = Vec::new();
+        for (_, m) in &t.vm().maps() {
+            // The kernel provides these afresh for every process.
+            if m.map.is_vsyscall() || m.map.is_vvar() {
+                continue;
+            }
+            if m.map.fsname() == exe.as_os_str() && (exe_base.is_null() || m.map.start() < exe_base)
+            {
+                exe_base = m.map.start();
+            }
+            kms.push(m.map.clone());
+        }
+        self.trace_writer_mut()
+            .write_task_event(&TraceTaskEvent::for_attach(
+                t.tid,
+                exe,
+                cmd_line(t.tid),
+                exe_base,
+                open_fds(t.tid),
+            ));
+
+        for km in &kms {
+            let st = if km.fsname().as_bytes().starts_with(b"/") {
+                stat(km.fsname()).ok()
+            } else {
+                None
+            };
+            let st = st.unwrap_or_else(|| unsafe { zeroed() });
+            if self.trace_writer_mut().write_mapped_region(
+                t,
+                km,
+                &st,
+                &[],
+                Some(MappingOrigin::AttachMapping),
+                None,
+            ) == RecordInTrace::RecordInTrace
+            {
+                // Pages that can't be read (e.g. PROT_NONE guard pages) get no
+                // data record.
+                let rec_tid = t.rec_tid;
+                let mut trace_out = self.trace_writer_mut();
+                for_each_readable_chunk(
+                    t,
+                    km.start(),
+                    km.size(),
+                    ATTACH_COPY_CHUNK_SIZE,
+                    |addr, data| trace_out.write_raw(rec_tid, data, addr),
+                );
+            }
+        }
+        t.record_event(&Event::sched(), None, None, None);
+    }
+
+    /// Write a task event for `t`, a thread of the attached process led by
+    /// `leader`, as if `leader` had just created it, and a frame with its
+    /// registers.
+    pub fn capture_attached_thread(&self, t: &mut RecordTask, leader: pid_t) {
+        let flags =
+            CLONE_VM | CLONE_FS | CLONE_FILES | CLONE_SIGHAND | CLONE_THREAD | CLONE_SYSVSEM;
+        self.trace_writer_mut()
+            .write_task_event(&TraceTaskEvent::for_clone(
+                t.tid,
+                leader,
+                t.own_namespace_tid(),
+                flags,
+            ));
+        t.record_event(&Event::sched(), None, None, None);
+    }
+
+    /// `t` is exiting with `exit_status`: record its exit. If it's the leader
+    /// and other threads of its process are still running, the kernel keeps
+    /// it as a zombie and waitpid() reports nothing for it until they've
+    /// exited; the thread group is kept until then, see
+    /// `reap_zombie_leader()`. Replay ends the leader at the same point and
+    /// gets the same zombie.
+    pub fn record_task_exit(&self, t: &mut RecordTask, exit_status: WaitStatus) {
+        t.record_event(&Event::exit(), None, None, None);
+        self.trace_writer_mut()
+            .write_task_event(&TraceTaskEvent::for_exit(t.tid, exit_status));
+        self.release_vfork_parent(t.tid);
+        let tg_rc = t.thread_group_shr_ptr();
+        let mut tg = tg_rc.borrow_mut();
+        if t.tid == tg.real_tgid && !tg.is_only_task(t.weak_self_ptr()) {
+            log!(
+                LogDebug,
+                "{} exited before the rest of its thread group",
+                t.tid
+            );
+            tg.zombie_leader = true;
+            drop(tg);
+            self.zombie_leaders.borrow_mut().insert(t.tid, tg_rc);
+        }
+    }
+
+    /// `t` died while rd had events in progress for it, e.g. it was
+    /// SIGKILLed, or another thread exec'd or exited the process, in the
+    /// middle of a syscall (`Task::wait_unexpected_exit()`). Nothing more
+    /// can be recorded for those events, so they're dropped. A syscall whose
+    /// entry is already in the trace gets an exit event with the registers
+    /// it was entered with and the syscall number replaced by
+    /// `SECCOMP_MAGIC_SKIP_ORIGINAL_SYSCALLNO`, so replay finishes the
+    /// syscall without running or emulating anything and the task's last
+    /// frame isn't half a syscall. Then the exit is recorded as usual.
+    pub fn record_unexpected_task_exit(&self, t: &mut RecordTask, exit_status: WaitStatus) {
+        let abandoned: Vec<Event> = t
+            .pending_events
+            .iter()
+            .filter(|ev| ev.event_type() != EventType::EvSentinel)
+            .cloned()
+            .collect();
+        t.pending_events
+            .retain(|ev| ev.event_type() == EventType::EvSentinel);
+        // Innermost first, the order they would have finished in.
+        for mut ev in abandoned.into_iter().rev() {
+            log!(LogDebug, "{} died during {}", t.tid, ev);
+            if !ev.is_syscall_event() {
+                continue;
+            }
+            let state = ev.syscall_event().state;
+            if state != SyscallState::ProcessingSyscall && state != SyscallState::ExitingSyscall {
+                continue;
+            }
+            let mut regs = ev.syscall_event().regs.clone();
+            regs.set_original_syscallno(SECCOMP_MAGIC_SKIP_ORIGINAL_SYSCALLNO);
+            ev.syscall_event_mut().state = SyscallState::ExitingSyscall;
+            t.record_event(
+                &ev,
+                Some(FlushSyscallbuf::DontFlushSyscallbuf),
+                Some(AllowSyscallbufReset::DontResetSyscallbuf),
+                Some(&regs),
+            );
+        }
+        self.record_task_exit(t, exit_status);
+    }
+
+    /// `parent` is stopped at the PTRACE_EVENT_VFORK of the vfork() that
+    /// created `child`. The kernel suspends `parent` until `child` has exec'd
+    /// or exited, so the scheduler won't pick it until then. Its syscall exit
+    /// is recorded after whatever `child` did in the meantime, which is the
+    /// order replay runs them in.
+    pub fn note_vfork(&self, parent: &mut RecordTask, child: pid_t) {
+        log!(
+            LogDebug,
+            "{} suspended until vfork child {} execs or exits",
+            parent.tid,
+            child
+        );
+        parent.vfork_child = Some(child);
+    }
+
+    /// `t` is stopped at PTRACE_EVENT_VFORK_DONE: its vfork() child has exec'd
+    /// or exited. Nothing is recorded for the stop; the record loop resumes
+    /// `t` to the exit of its vfork().
+    pub fn vfork_done(&self, t: &mut RecordTask) {
+        log!(LogDebug, "{} done waiting for its vfork child", t.tid);
+        t.vfork_child = None;
+    }
+
+    /// The vfork() child `child` has exec'd or exited, so its parent can run
+    /// again. The kernel may report the parent's PTRACE_EVENT_VFORK_DONE
+    /// before or after the child's stop, so either one releases it.
+    fn release_vfork_parent(&self, child: pid_t) {
+        for t_rc in self.task_map.borrow().values() {
+            // The task being processed, which may be `child` itself, is
+            // already borrowed. It isn't the parent.
+            let mut t_ref = match t_rc.try_borrow_mut() {
+                Ok(t_ref) => t_ref,
+                Err(_) => continue,
+            };
+            if let Some(t) = t_ref.as_record_task_mut() {
+                if t.vfork_child == Some(child) {
+                    log!(LogDebug, "vfork child {} released {}", child, t.tid);
+                    t.vfork_child = None;
+                }
+            }
+        }
+    }
+
+    /// waitpid() reported `tid`, which has no task. Returns true if it was a
+    /// zombie leader, whose thread group has now exited entirely. Its exit
+    /// was recorded when the leader thread exited, so the record loop
+    /// should ignore the status.
+    pub fn reap_zombie_leader(&self, tid: pid_t) -> bool {
+        match self.zombie_leaders.borrow_mut().remove(&tid) {
+            Some(tg) => {
+                log!(LogDebug, "reaped zombie leader {}", tid);
+                tg.borrow_mut().zombie_leader = false;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Called when waitpid() reports PTRACE_EVENT_EXEC for `tid`, before the
+    /// stop is handed to a task. When a thread other than the leader execs,
+    /// the kernel kills every other thread of the process, the leader
+    /// included, and the exec'ing thread takes over the leader's tid, which
+    /// is the one the stop is reported for. Its former tid is the event
+    /// message.
+    ///
+    /// Catch the session up: the tasks of the other threads go away, see
+    /// `retire_task_killed_by_exec()`, and the exec'ing task carries on under
+    /// the leader's tid. From the exec on the trace only knows that tid;
+    /// replay makes the same switch in `ReplaySession::revive_task_for_exec()`
+    /// and `process_execve()`.
+    ///
+    /// Returns the task that exec'd.
+    pub fn task_for_exec_stop(&self, tid: pid_t) -> TaskSharedPtr {
+        let mut former_tid: usize = 0;
+        if unsafe { ptrace(PTRACE_GETEVENTMSG, tid, 0, &mut former_tid as *mut usize) } != 0 {
+            fatal!("PTRACE_GETEVENTMSG failed for exec'ing {}", tid);
+        }
+        let former_tid = former_tid as pid_t;
+        self.release_vfork_parent(former_tid);
+        let t_rc = match self.find_task_from_rec_tid(former_tid) {
+            Some(t_rc) => t_rc,
+            None => {
+                fatal!("Task {} that exec'd as {} is unknown", former_tid, tid);
+                unreachable!()
+            }
+        };
+        let others: Vec<TaskSharedPtr> = {
+            let t = t_rc.borrow();
+            let others = t
+                .thread_group()
+                .task_set()
+                .iter_except(t.weak_self_ptr())
+                .collect();
+            others
+        };
+        for other_rc in others {
+            let mut other_ref = other_rc.borrow_mut();
+            self.retire_task_killed_by_exec(other_ref.as_record_task_mut().unwrap());
+        }
+        if former_tid == tid {
+            return t_rc;
+        }
+
+        log!(
+            LogDebug,
+            "{} exec'd and took over the tid of its leader {}",
+            former_tid,
+            tid
+        );
+        self.task_map.borrow_mut().remove(&former_tid);
+        {
+            let mut t_ref = t_rc.borrow_mut();
+            let t = t_ref.as_record_task_mut().unwrap();
+            let own_namespace_tid = t.thread_group().real_tgid_own_namespace;
+            t.set_tid_and_update_serial(tid, own_namespace_tid);
+        }
+        self.task_map.borrow_mut().insert(tid, t_rc.clone());
+        t_rc
+    }
+
+    /// `t` is a thread the exec of another thread of its process killed, see
+    /// `task_for_exec_stop()`. Record its exit if its PTRACE_EVENT_EXIT hasn't
+    /// been handled yet (it can race with the exec) and forget it. Whatever
+    /// waitpid() still reports for its tid goes to `reap_unknown_tid()`.
+    fn retire_task_killed_by_exec(&self, t: &mut RecordTask) {
+        let tid = t.tid;
+        if !t.seen_ptrace_exit_event {
+            log!(LogDebug, "  recording the exit of {} ourselves", tid);
+            t.record_event(&Event::exit(), None, None, None);
+            self.trace_writer_mut()
+                .write_task_event(&TraceTaskEvent::for_exit(
+                    tid,
+                    WaitStatus::for_fatal_sig(SIGKILL),
+                ));
+        }
+        // The leader's tid belongs to the exec'ing task now, and the other
+        // tids may be reused, so nothing may ptrace them through this task.
+        t.unstable.set(true);
+        self.task_map.borrow_mut().remove(&tid);
+        if tid == t.tgid() {
+            // If it was a zombie, the exec released it without a wait status.
+            self.zombie_leaders.borrow_mut().remove(&tid);
+            t.thread_group_shr_ptr().borrow_mut().zombie_leader = false;
+        }
+        t.thread_group_shr_ptr()
+            .borrow_mut()
+            .task_set_mut()
+            .erase(t.weak_self_ptr());
+    }
+
+    /// End the recording but let the tracees carry on unrecorded: flush and
+    /// tear down each task's syscallbuf and scratch memory, requeue the
+    /// signals rd stashed, PTRACE_DETACH every task with its pending signal
+    /// and finalize the trace. The trace replays up to this point.
+    ///
+    /// A seccomp filter can't be removed once installed, and without a tracer
+    /// rd's filter makes every syscall it traps fail with ENOSYS. So tasks
+    /// that have it stay attached, and once the trace is closed this keeps
+    /// resuming them, recording nothing, until they've all exited. Their
+    /// address spaces keep the rd page, whose untraced entry point the filter
+    /// lets through.
+    pub fn detach_all_tasks(&self) -> io::Result<()> {
+        let tasks: Vec<TaskSharedPtr> = self.task_map.borrow().values().cloned().collect();
+        for t in &tasks {
+            let t = t.borrow();
+            if !t.is_stopped {
+                let why = if is_uninterruptible_sleep(t.tid) {
+                    "it's in uninterruptible sleep, probably waiting on NFS or FUSE. \
+                     Try again once that operation finishes"
+                } else {
+                    "it isn't stopped"
+                };
+                return Err(io::Error::new(
+                    ErrorKind::Other,
+                    format!("Can't detach from {}: {}", t.tid, why),
+                ));
+            }
+        }
+
+        // The rd page has to stay wherever a task still runs under rd's
+        // seccomp filter.
+        let mut seccomp_vms: Vec<AddressSpaceUid> = Vec::new();
+        for t in &tasks {
+            let t = t.borrow();
+            if t.seccomp_bpf_enabled && !seccomp_vms.contains(&t.vm().uid()) {
+                seccomp_vms.push(t.vm().uid());
+            }
+        }
+
+        let mut done_vms: Vec<AddressSpaceUid> = Vec::new();
+        let mut shepherded: Vec<TaskSharedPtr> = Vec::new();
+        let mut errors: Vec<String> = Vec::new();
+        for t_rc in &tasks {
+            let mut t_ref = t_rc.borrow_mut();
+            let t = t_ref.as_record_task_mut().unwrap();
+            if !t.syscallbuf_child.is_null() {
+                t.maybe_flush_syscallbuf();
+            }
+            if !t.syscallbuf_child.is_null() || !t.scratch_ptr.is_null() {
+                t.destroy_buffers();
+            }
+            requeue_stashed_signals(t);
+            let vm_uid = t.vm().uid();
+            if !seccomp_vms.contains(&vm_uid) && !done_vms.contains(&vm_uid) {
+                done_vms.push(vm_uid);
+                unmap_rd_pages(t);
+            }
+            // Don't wait for the task to exit when it's dropped.
+            t.unstable.set(true);
+            if t.seccomp_bpf_enabled {
+                shepherded.push(t_rc.clone());
+                continue;
+            }
+
+            let sig = t.status().maybe_stop_sig();
+            let sig = if sig.is_sig() { sig.unwrap_sig() } else { 0 };
+            log!(LogDebug, "detaching from {} with signal {}", t.tid, sig);
+            if t.fallible_ptrace(
+                PTRACE_DETACH,
+                RemotePtr::null(),
+                PtraceData::ReadWord(sig as usize),
+            ) < 0
+            {
+                let err = errno();
+                // The task may have been killed since it stopped.
+                if err != ESRCH {
+                    errors.push(format!(
+                        "Can't detach from {}: {}",
+                        t.tid,
+                        io::Error::from_raw_os_error(err)
+                    ));
+                }
+            }
+        }
+        drop(tasks);
+        self.task_map.borrow_mut().clear();
+        forget_vms_and_thread_groups(self);
+
+        let trace_id = self.trace_id.clone();
+        self.trace_writer_mut()
+            .close(CloseStatus::CloseOk, Some(trace_id));
+
+        if !shepherded.is_empty() {
+            let tids: Vec<pid_t> = shepherded.iter().map(|t| t.borrow().tid).collect();
+            for &tid in &tids {
+                unsafe { ptrace(PTRACE_CONT, tid, 0, 0) };
+            }
+            shepherd_tasks(&tids);
+            // They've all exited, so dropping them doesn't detach anything.
+            drop(shepherded);
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(io::Error::new(ErrorKind::Other, errors.join("; ")))
+        }
+    }
+
+    /// End the current task's timeslice. When the scheduler is busy, i.e.
+    /// the task stopped while it was looking for a runnable task, it notices
+    /// the TIME_SLICE_SIGNAL stop itself.
+    pub fn expire_timeslice(&self) {
+        if let Ok(mut scheduler) = self.scheduler_.try_borrow_mut() {
+            scheduler.expire_timeslice();
+        }
+    }
+
+    /// Let a tracee run until its next stop and record what it did. Returns
+    /// `StepExited` with the initial tracee's exit status once every tracee
+    /// is gone.
+    pub fn record_step(&self) -> RecordResult {
+        let result = self.record_one_step();
+        // All task borrows of the step have been released by now.
+        self.run_deferred_work();
+        result
+    }
+
+    fn record_one_step(&self) -> RecordResult {
+        if self.task_map.borrow().is_empty() {
+            let exit_status = self
+                .initial_thread_group
+                .borrow()
+                .as_ref()
+                .map_or_else(WaitStatus::default, |tg| tg.borrow().exit_status);
+            return RecordResult::StepExited(exit_status);
+        }
+        // Tasks blocked in intercepted syscalls shouldn't have to wait until
+        // every other task is blocked too.
+        self.handle_intercepted_syscalls();
+
+        let rescheduled = self
+            .scheduler_mut()
+            .reschedule(self.last_task_switchable.get());
+        let (t_rc, started_new_timeslice) = match rescheduled {
+            Rescheduled::Task {
+                t,
+                started_new_timeslice,
+            } => (t, started_new_timeslice),
+            Rescheduled::UnknownTid(tid) => {
+                self.reap_unknown_tid(tid);
+                return RecordResult::StepContinue;
+            }
+            Rescheduled::Intercepted => {
+                self.handle_intercepted_syscalls();
+                return RecordResult::StepContinue;
+            }
+        };
+
+        let status = t_rc.borrow().status();
+        let t_rc = if status.maybe_ptrace_event() == PTRACE_EVENT_EXEC {
+            let tid = t_rc.borrow().tid;
+            let exec_rc = self.task_for_exec_stop(tid);
+            if !Rc::ptr_eq(&exec_rc, &t_rc) {
+                // The stop was reported for the leader's tid, which the
+                // exec'ing task has now.
+                exec_rc.borrow_mut().did_waitpid(status);
+            }
+            exec_rc
+        } else {
+            t_rc
+        };
+
+        let mut t_ref = t_rc.borrow_mut();
+        let t = t_ref.as_record_task_mut().unwrap();
+        if started_new_timeslice {
+            self.maybe_migrate_task(t);
+        }
+        if status == WaitStatus::default() {
+            // A new task, stopped where it was created.
+            self.resume(t, None);
+            return RecordResult::StepContinue;
+        }
+        match status.wait_type() {
+            WaitType::PtraceEvent => {
+                let event = status.maybe_ptrace_event();
+                if event == PTRACE_EVENT_EXIT {
+                    self.handle_exit_event(t);
+                } else if event == PTRACE_EVENT_SECCOMP {
+                    self.handle_seccomp_stop(t);
+                } else {
+                    if event == PTRACE_EVENT_EXEC {
+                        t.post_exec();
+                    } else if event == PTRACE_EVENT_VFORK_DONE {
+                        self.vfork_done(t);
+                    } else {
+                        log!(LogDebug, "{}: ignoring {}", t.tid, status);
+                    }
+                    self.resume(t, None);
+                }
+            }
+            WaitType::SyscallStop => self.handle_syscall_stop(t),
+            WaitType::SignalStop => self.handle_signal_stop(t),
+            WaitType::GroupStop => self.resume(t, None),
+            WaitType::Exit | WaitType::FatalSignal => {
+                ed_assert!(t, false, "Unexpected {} for {}", status, t.tid);
+            }
+        }
+        RecordResult::StepContinue
+    }
+
+    /// Finish the trace once `record_step()` has returned `StepExited`.
+    pub fn terminate_recording(&self) {
+        let trace_id = self.trace_id.clone();
+        self.trace_writer_mut()
+            .close(CloseStatus::CloseOk, Some(trace_id));
+    }
+
+    /// waitpid() has a status for `tid`, which isn't a task (any more).
+    fn reap_unknown_tid(&self, tid: pid_t) {
+        if !self.reap_zombie_leader(tid) {
+            log!(
+                LogDebug,
+                "Discarding a status for {}, which isn't a tracee",
+                tid
+            );
+        }
+        let mut raw_status = 0;
+        unsafe { waitpid(tid, &mut raw_status, __WALL | WNOHANG) };
+        // A thread an exec killed may stop at its PTRACE_EVENT_EXIT after
+        // we've forgotten it; let it finish exiting.
+        if WaitStatus::new(raw_status).maybe_ptrace_event() == PTRACE_EVENT_EXIT {
+            unsafe { ptrace(PTRACE_CONT, tid, 0, 0) };
+        }
+    }
+
+    /// Resume `t` until its next stop, with `maybe_sig` if it is to get a
+    /// signal. In a syscall it runs to the syscall's exit, otherwise until
+    /// its next syscall or the end of its timeslice.
+    fn resume(&self, t: &mut RecordTask, maybe_sig: Option<i32>) {
+        if t.ev().is_syscall_event() {
+            t.resume_execution(
+                ResumeRequest::ResumeSyscall,
+                WaitRequest::ResumeNonblocking,
+                TicksRequest::ResumeNoTicks,
+                maybe_sig,
+            );
+            return;
+        }
+        requeue_stashed_signals(t);
+        // Until we know which of its two stops the kernel reports first, a
+        // syscall-entry stop for every syscall tells us.
+        let how = if t.seccomp_bpf_enabled
+            && !self.notifying_seccomp_filter.get()
+            && self.syscall_seccomp_ordering()
+                != PtraceSyscallSeccompOrdering::SyscallBeforeSeccompUnknown
+        {
+            ResumeRequest::ResumeCont
+        } else {
+            ResumeRequest::ResumeSyscall
+        };
+        let ticks = self.scheduler().timeslice_ticks_request(t);
+        t.resume_execution(how, WaitRequest::ResumeNonblocking, ticks, maybe_sig);
+    }
+
+    /// `t` is at its PTRACE_EVENT_EXIT. Record its exit and forget it.
+    fn handle_exit_event(&self, t: &mut RecordTask) {
+        let mut raw_status: usize = 0;
+        let exit_status = if t.fallible_ptrace(
+            PTRACE_GETEVENTMSG,
+            RemotePtr::<Void>::null(),
+            PtraceData::WriteInto(u8_raw_slice_mut(&mut raw_status)),
+        ) == 0
+        {
+            WaitStatus::new(raw_status as i32)
+        } else {
+            // It's already gone.
+            WaitStatus::for_fatal_sig(SIGKILL)
+        };
+        if t.ev().is_syscall_event() {
+            let syscall = t.ev().syscall_event();
+            let arch = syscall.arch();
+            if is_exit_syscall(syscall.number, arch) || is_exit_group_syscall(syscall.number, arch)
+            {
+                // These never return. Replay ends the task at the entry.
+                t.pop_syscall();
+                note_syscall_exit_stop(t);
+            }
+        }
+        if t.pending_events
+            .iter()
+            .any(|ev| ev.event_type() != EventType::EvSentinel)
+        {
+            self.record_unexpected_task_exit(t, exit_status);
+        } else {
+            self.record_task_exit(t, exit_status);
+        }
+        t.thread_group_shr_ptr().borrow_mut().exit_status = exit_status;
+        t.destroy();
+        self.last_task_switchable.set(Switchable::AllowSwitch);
+    }
+
+    fn handle_seccomp_stop(&self, t: &mut RecordTask) {
+        t.seccomp_bpf_enabled = true;
+        if t.ev().is_syscall_event() {
+            // The syscall-entry stop came first and its entry is processed.
+            let action = on_syscall_entry_stop(t, SyscallEntryStop::Seccomp);
+            ed_assert!(t, action == SyscallEntryAction::Duplicate);
+            self.resume(t, None);
+            return;
+        }
+        match route_seccomp_stop(t) {
+            SeccompStopAction::Patched => self.resume(t, None),
+            SeccompStopAction::SlowPath => self.syscall_entry(t, SyscallEntryStop::Seccomp),
+            SeccompStopAction::TraceeFilter(data) => {
+                let maybe_result = self
+                    .seccomp_filter_rewriter()
+                    .map_filter_data_to_real_result(data);
+                let result = match maybe_result {
+                    Some(result) => result,
+                    None => {
+                        ed_assert!(t, false, "Unknown tracee filter data {}", data);
+                        unreachable!()
+                    }
+                };
+                match result & SECCOMP_RET_ACTION_FULL {
+                    SECCOMP_RET_ERRNO => {
+                        let errno = (result & SECCOMP_RET_DATA) as i32;
+                        self.syscall_entry_failed_by_filter(t, errno)
+                    }
+                    SECCOMP_RET_LOG => self.syscall_entry(t, SyscallEntryStop::Seccomp),
+                    action => {
+                        log!(
+                            LogWarn,
+                            "{}: can't emulate seccomp filter action {:#x} for {}; running the \
+                             syscall",
+                            t.tid,
+                            action,
+                            syscall_name(t.regs_ref().original_syscallno() as i32, t.arch())
+                        );
+                        self.syscall_entry(t, SyscallEntryStop::Seccomp)
+                    }
+                }
+            }
+        }
+    }
+
+    /// `t` is at the seccomp stop of a syscall a filter it installed fails
+    /// with `errno`. The kernel would return the error without running the
+    /// syscall, so record the syscall's entry and skip it with
+    /// `SECCOMP_MAGIC_SKIP_ORIGINAL_SYSCALLNO`, which replay skips too.
+    /// Nothing is prepared for the syscall, as it never runs.
+    fn syscall_entry_failed_by_filter(&self, t: &mut RecordTask, errno: i32) {
+        if on_syscall_entry_stop(t, SyscallEntryStop::Seccomp) == SyscallEntryAction::Duplicate {
+            self.resume(t, None);
+            return;
+        }
+        log!(
+            LogDebug,
+            "{}: seccomp filter fails the syscall with errno {}",
+            t.tid,
+            errno
+        );
+        let regs = t.regs_ref().clone();
+        t.push_syscall_event(regs.original_syscallno() as i32);
+        {
+            let syscall = t.ev_mut().syscall_event_mut();
+            syscall.state = SyscallState::EnteringSyscall;
+            syscall.regs = regs.clone();
+            syscall.switchable = Switchable::PreventSwitch;
+            // Tells replay not to create a task for a clone() that never ran.
+            syscall.failed_during_preparation = true;
+        }
+        let ev = t.ev().clone();
+        t.record_event(&ev, None, None, Some(&regs));
+        t.ev_mut().syscall_event_mut().state = SyscallState::ProcessingSyscall;
+        self.last_task_switchable.set(Switchable::PreventSwitch);
+        let mut r = regs;
+        r.set_original_syscallno(SECCOMP_MAGIC_SKIP_ORIGINAL_SYSCALLNO);
+        r.set_syscall_result_signed(-errno as isize);
+        t.set_regs(&r);
+        self.resume(t, None);
+    }
+
+    fn handle_syscall_stop(&self, t: &mut RecordTask) {
+        if !t.ev().is_syscall_event() {
+            self.syscall_entry(t, SyscallEntryStop::PtraceSyscall);
+            return;
+        }
+        if is_duplicate_syscall_entry_stop(t) {
+            on_syscall_entry_stop(t, SyscallEntryStop::PtraceSyscall);
+        } else {
+            self.syscall_exit(t);
+        }
+        self.resume(t, None);
+    }
+
+    fn handle_signal_stop(&self, t: &mut RecordTask) {
+        let si = t.get_siginfo().clone();
+        let sig = si.si_signo;
+        if sig == TIME_SLICE_SIGNAL
+            && unsafe { si._sifields._sigpoll.si_fd } == t.hpc.ticks_interrupt_fd()
+        {
+            // Our own interrupt for the end of the timeslice. Replay aborts
+            // rseq critical sections at the same event.
+            t.record_event(&Event::sched(), None, None, None);
+            abort_rseq_critical_section(t);
+            self.resume(t, None);
+            return;
+        }
+        if t.ev().is_syscall_event() {
+            // It's delivered once the syscall is recorded.
+            t.stash_sig();
+            self.resume(t, None);
+            return;
+        }
+        if sig == SIGSEGV && try_handle_trapped_instruction(t, &si) {
+            self.resume(t, None);
+            return;
+        }
+        let maybe_sig = handle_signal(t, &si);
+        self.resume(t, maybe_sig);
+    }
+
+    /// `t` is entering a syscall. Record the entry, doing whatever has to
+    /// happen before the kernel runs it, and resume it into the syscall.
+    fn syscall_entry(&self, t: &mut RecordTask, stop: SyscallEntryStop) {
+        if on_syscall_entry_stop(t, stop) == SyscallEntryAction::Duplicate {
+            self.resume(t, None);
+            return;
+        }
+        let regs = t.regs_ref().clone();
+        t.push_syscall_event(regs.original_syscallno() as i32);
+        {
+            let syscall = t.ev_mut().syscall_event_mut();
+            syscall.state = SyscallState::EnteringSyscall;
+            syscall.regs = regs.clone();
+        }
+        let switchable = rec_prepare_syscall(t);
+        t.ev_mut().syscall_event_mut().switchable = switchable;
+        let ev = t.ev().clone();
+        t.record_event(&ev, None, None, Some(&regs));
+        t.ev_mut().syscall_event_mut().state = SyscallState::ProcessingSyscall;
+        self.last_task_switchable.set(switchable);
+        if t.ev().syscall_event().failed_during_preparation {
+            // `t` is at the syscall's exit already.
+            self.syscall_exit(t);
+        }
+        self.resume(t, None);
+    }
+
+    /// `t` is at the exit of the syscall it's in. Record what it did.
+    fn syscall_exit(&self, t: &mut RecordTask) {
+        let entry_regs = t.ev().syscall_event().regs.clone();
+        t.ev_mut().syscall_event_mut().state = SyscallState::ExitingSyscall;
+        rec_process_syscall(t);
+        check_syscall_exit_regs(t, &entry_regs);
+        note_syscall_exit_stop(t);
+        t.record_current_event();
+        t.pop_syscall();
+        self.last_task_switchable.set(Switchable::AllowSwitch);
+    }
+}
+
+/// `name` as execvp() would find it: on $PATH unless it contains a '/'.
+fn lookup_by_path(name: &OsStr) -> OsString {
+    if name.as_bytes().contains(&b'/') {
+        return name.to_owned();
+    }
+    if let Some(path) = env::var_os("PATH") {
+        for dir in env::split_paths(&path) {
+            let candidate = dir.join(name);
+            if candidate.is_file() {
+                return candidate.into_os_string();
+            }
+        }
+    }
+    name.to_owned()
+}
+
+/// Put the signals rd dequeued from `t` but hasn't delivered yet back on its
+/// queue, so it gets them once rd is gone.
+fn requeue_stashed_signals(t: &mut RecordTask) {
+    let siginfos = t.take_stashed_signals();
+    if siginfos.is_empty() {
+        return;
+    }
+    let arch = t.arch();
+    let tgid = t.tgid();
+    let tid = t.tid;
+    let mut remote = AutoRemoteSyscalls::new(t);
+    for si in siginfos {
+        // The task queues the signal to itself, so the kernel accepts the
+        // siginfo as is. rd only has it in the native layout.
+        let ret = if arch == RD_NATIVE_ARCH {
+            let bytes = unsafe {
+                slice::from_raw_parts(&si as *const siginfo_t as *const u8, size_of::<siginfo_t>())
+            };
+            let mut mem = AutoRestoreMem::new(&mut remote, Some(bytes), bytes.len());
+            let addr = mem.get().unwrap();
+            mem.syscall(
+                syscall_number_for_rt_tgsigqueueinfo(arch),
+                &[
+                    tgid as usize,
+                    tid as usize,
+                    si.si_signo as usize,
+                    addr.as_usize(),
+                ],
+            )
+        } else {
+            remote.syscall(
+                syscall_number_for_tgkill(arch),
+                &[tgid as usize, tid as usize, si.si_signo as usize],
+            )
+        };
+        if ret < 0 {
+            log!(
+                LogWarn,
+                "Couldn't requeue signal {} for {}: {}",
+                si.si_signo,
+                tid,
+                io::Error::from_raw_os_error(-ret as i32)
+            );
+        }
+    }
+}
+
+/// Resume the stopped `tids`, and whatever they fork, each time they stop
+/// until they have all exited, passing signals through. Tracing them is all
+/// that keeps the syscalls rd's seccomp filter traps from failing.
+fn shepherd_tasks(tids: &[pid_t]) {
+    log!(
+        LogDebug,
+        "shepherding {:?} until they exit; they keep rd's seccomp filter",
+        tids
+    );
+    loop {
+        let mut raw_status = 0;
+        let tid = unsafe { waitpid(-1, &mut raw_status, __WALL) };
+        if tid < 0 {
+            if errno() == EINTR {
+                continue;
+            }
+            // ECHILD: everything has exited.
+            return;
+        }
+        let status = WaitStatus::new(raw_status);
+        let (request, sig) = match status.wait_type() {
+            WaitType::Exit | WaitType::FatalSignal => continue,
+            WaitType::SignalStop => (PTRACE_CONT, status.maybe_stop_sig().unwrap_sig()),
+            // Leave the group stopped until something continues it.
+            WaitType::GroupStop => (PTRACE_LISTEN, 0),
+            WaitType::SyscallStop | WaitType::PtraceEvent => (PTRACE_CONT, 0),
+        };
+        unsafe { ptrace(request, tid, 0, sig as usize) };
+    }
+}
+
+/// Unmap the rd page and the preload thread-locals page from `t`'s address
+/// space, where rd mapped them.
+fn unmap_rd_pages(t: &mut RecordTask) {
+    let ranges: Vec<(RemotePtr<Void>, usize)> = {
+        let vm = t.vm();
+        [
+            (
+                AddressSpace::rd_page_start(),
+                AddressSpace::rd_page_size(),
+                MappingFlags::IS_RD_PAGE,
+            ),
+            (
+                AddressSpace::preload_thread_locals_start(),
+                AddressSpace::preload_thread_locals_size(),
+                MappingFlags::IS_THREAD_LOCALS,
+            ),
+        ]
+        .iter()
+        .filter(|&&(start, _, flag)| {
+            vm.mapping_of(start)
+                .map_or(false, |m| m.flags.contains(flag))
+        })
+        .map(|&(start, size, _)| (start, size))
+        .collect()
+    };
+    let arch = t.arch();
+    let mut remote = AutoRemoteSyscalls::new(t);
+    for (start, size) in ranges {
+        rd_infallible_syscall!(
+            remote,
+            syscall_number_for_munmap(arch),
+            start.as_usize(),
+            size
+        );
+        remote.task().vm_shr_ptr().unmap(remote.task(), start, size);
     }
 }
 
@@ -186,11 +1660,20 @@ impl DerefMut for RecordSession {
 impl Session for RecordSession {
     /// Forwarded method
     fn kill_all_tasks(&self) {
-        kill_all_tasks(self)
+        kill_all_tasks(self);
+        self.zombie_leaders.borrow_mut().clear();
     }
 
     fn on_destroy_task(&self, _t: TaskUid) {
-        unimplemented!()
+        // Tasks are dropped while the scheduler looks for a runnable task
+        // too. It forgets them once it's done.
+        if let Ok(mut scheduler) = self.scheduler_.try_borrow_mut() {
+            scheduler.on_destroy();
+        }
+    }
+
+    fn as_record(&self) -> Option<&RecordSession> {
+        Some(self)
     }
 
     fn as_session_inner(&self) -> &SessionInner {
@@ -203,15 +1686,83 @@ impl Session for RecordSession {
 
     fn new_task(
         &self,
-        _tid: pid_t,
+        tid: pid_t,
         _rec_tid: Option<pid_t>,
-        _serial: u32,
-        _a: SupportedArch,
+        serial: u32,
+        a: SupportedArch,
     ) -> Box<dyn Task> {
-        unimplemented!()
+        Box::new(RecordTask::new(self, tid, serial, a))
+    }
+
+    fn trace_stream(&self) -> Option<Ref<'_, TraceStream>> {
+        let w = self.trace_out.borrow();
+        Some(Ref::map(w, |t| t.deref()))
     }
 
-    fn on_create(&self, _t: TaskSharedPtr) {
-        unimplemented!()
+    fn trace_stream_mut(&self) -> Option<RefMut<'_, TraceStream>> {
+        let w = self.trace_out.borrow_mut();
+        Some(RefMut::map(w, |t| t.deref_mut()))
+    }
+
+    fn on_create(&self, t: TaskSharedPtr) {
+        let rec_tid = t.borrow().rec_tid;
+        self.task_map.borrow_mut().insert(rec_tid, t.clone());
+        self.scheduler_mut().on_create(&t);
+        let mut initial_thread_group = self.initial_thread_group.borrow_mut();
+        if initial_thread_group.is_none() {
+            *initial_thread_group = Some(t.borrow().thread_group_shr_ptr());
+        }
+    }
+
+    /// Like `Session::clone_task()`, and the child inherits the parent's
+    /// record-only state the way the kernel's child does: signal handlers
+    /// (shared with CLONE_SIGHAND), priority, TSC and CPUID modes, the
+    /// seccomp status and the robust futex list.
+    fn clone_task(
+        &self,
+        p: &mut dyn Task,
+        flags: CloneFlags,
+        stack: RemotePtr<Void>,
+        tls: RemotePtr<Void>,
+        cleartid_addr: RemotePtr<i32>,
+        new_tid: pid_t,
+        new_rec_tid: Option<pid_t>,
+    ) -> TaskSharedPtr {
+        self.assert_fully_initialized();
+        let c = clone_task_common(
+            p,
+            CloneReason::TraceeClone,
+            flags,
+            stack,
+            tls,
+            cleartid_addr,
+            new_tid,
+            new_rec_tid,
+            self.next_task_serial(),
+            None,
+        );
+        {
+            let parent = p.as_record_task().unwrap();
+            let mut c_ref = c.borrow_mut();
+            let child = c_ref.as_record_task_mut().unwrap();
+            child.sighandlers = if flags.contains(CloneFlags::CLONE_SHARE_SIGHANDLERS) {
+                parent.sighandlers.clone()
+            } else {
+                Rc::new(RefCell::new(parent.sighandlers.borrow().clone()))
+            };
+            child.priority = parent.priority;
+            child.tsc_mode = parent.tsc_mode;
+            child.cpuid_mode = parent.cpuid_mode;
+            child.prctl_seccomp_status = parent.prctl_seccomp_status;
+            child.robust_futex_list = parent.robust_futex_list;
+            child.robust_futex_list_len = parent.robust_futex_list_len;
+            if flags.contains(CloneFlags::CLONE_CLEARTID) {
+                child.tid_futex = cleartid_addr;
+            }
+            child.update_own_namespace_tid();
+            child.clear_wait_status();
+        }
+        self.on_create(c.clone());
+        c
     }
 }
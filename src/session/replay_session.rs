@@ -14,30 +14,39 @@ use crate::{
         ptrace::{PTRACE_EVENT_EXIT, PTRACE_EVENT_SECCOMP},
         signal::siginfo_t,
     },
+    cancellation_token::CancellationToken,
     cpuid_bug_detector::CPUIDBugDetector,
+    dead_tasks::{DeadTaskPolicy, DeadTasks},
     emu_fs::{EmuFs, EmuFsSharedPtr},
     event::{Event, EventType, SignalDeterministic, SignalEventData, SyscallState},
     fast_forward::{fast_forward_through_instruction, FastForwardStatus},
     flags::Flags as ProgramFlags,
     kernel_abi::{
-        common::preload_interface::syscallbuf_hdr,
-        is_execve_syscall,
+        common::preload_interface::{
+            mprotect_record,
+            preload_globals,
+            syscallbuf_hdr,
+            SYS_rdcall_mprotect_record,
+        },
         syscall_number_for_exit,
         SupportedArch,
     },
-    kernel_metadata::{signal_name, syscall_name},
-    log::LogLevel::{LogDebug, LogError},
+    kernel_metadata::{is_exec, signal_name, syscall_name},
+    log::LogLevel::{LogDebug, LogError, LogInfo, LogWarn},
+    memory_checksum::memory_mismatches,
     perf_counters,
     perf_counters::{PerfCounters, TIME_SLICE_SIGNAL},
     registers::{MismatchBehavior, Registers},
     remote_code_ptr::RemoteCodePtr,
-    remote_ptr::RemotePtr,
+    remote_ptr::{RemotePtr, Void},
+    replay_divergence::{DivergenceKind, DivergenceReport},
     replay_syscall::{
         rep_after_enter_syscall,
         rep_prepare_run_to_syscall,
         rep_process_syscall,
         restore_mapped_region,
     },
+    rseq::abort_rseq_critical_section,
     scoped_fd::ScopedFd,
     session::{
         address_space::{
@@ -45,15 +54,16 @@ use crate::{
             BreakpointType,
             Enabled,
             Traced,
+            WatchType,
         },
         diversion_session::DiversionSessionSharedPtr,
         replay_session::ReplayTraceStepType::TstepNone,
         session_inner::{session_inner::SessionInner, BreakStatus, RunCommand},
         task::{
             replay_task::ReplayTask,
-            task_common::write_val_mem,
+            task_common::{read_mem, read_val_mem, write_val_mem},
             task_inner::{
-                task_inner::{SaveTraceeFdNumber, TaskInner},
+                task_inner::{SaveTraceeFdNumber, TaskInner, WriteFlags},
                 ResumeRequest,
                 TicksRequest,
                 WaitRequest,
@@ -64,6 +74,7 @@ use crate::{
         Session,
         SessionSharedPtr,
     },
+    skid_region::SkidStats,
     thread_group::ThreadGroupSharedPtr,
     ticks::Ticks,
     trace::{
@@ -74,8 +85,11 @@ use crate::{
     util::{
         cpuid,
         cpuid_compatible,
+        cpuid_mismatches,
         default_action,
         find_cpuid_record,
+        page_size,
+        running_under_rd,
         should_dump_memory,
         trapped_instruction_at,
         trapped_instruction_len,
@@ -93,7 +107,7 @@ use crate::{
     wait_status::WaitStatus,
 };
 use libc::{pid_t, ENOSYS, SIGBUS, SIGSEGV, SIGTRAP};
-use nix::sys::mman::MapFlags;
+use nix::sys::mman::{MapFlags, ProtFlags};
 use std::{
     cell::{Cell, Ref, RefCell, RefMut},
     cmp::min,
@@ -101,7 +115,9 @@ use std::{
     ffi::{OsStr, OsString},
     io,
     io::Write,
+    mem::size_of,
     ops::{Deref, DerefMut},
+    path::Path,
     rc::Rc,
 };
 
@@ -143,6 +159,11 @@ pub enum ReplayTraceStepType {
     /// Replay until we enter the next syscall, then patch it.
     TstepPatchSyscall,
 
+    /// Replay until we enter the syscall `syscall`, which rd answered through
+    /// a seccomp user notification during recording, then skip it and give it
+    /// the recorded result.
+    TstepInterceptedSyscall,
+
     /// Exit the task
     TstepExitTask,
 
@@ -252,6 +273,9 @@ pub enum ReplayStatus {
     ReplayContinue,
     /// All tracees are dead. replay_step() should not be called again.
     ReplayExited,
+    /// Replay no longer matches the recording; see `ReplayResult::divergence`.
+    /// replay_step() should not be called again.
+    ReplayDiverged,
 }
 
 #[derive(Clone)]
@@ -293,6 +317,8 @@ pub struct ReplayResult {
     /// one iteration to go. did_fast_forward may be false in this case if the
     /// instruction executes exactly twice.
     pub incomplete_fast_forward: bool,
+    /// Set if and only if status is ReplayDiverged.
+    pub divergence: Option<Box<DivergenceReport>>,
 }
 
 impl ReplayResult {
@@ -302,6 +328,7 @@ impl ReplayResult {
             break_status: BreakStatus::new(),
             did_fast_forward: false,
             incomplete_fast_forward: false,
+            divergence: None,
         }
     }
 }
@@ -357,6 +384,14 @@ pub struct ReplaySession {
     syscall_bp_vm: RefCell<Option<AddressSpaceSharedPtr>>,
     // @TODO Set to the 0 address on init. More principled solution?!
     syscall_bp_addr: Cell<RemoteCodePtr>,
+    /// The first divergence detected, if any. Once set, replay stops.
+    divergence: RefCell<Option<Box<DivergenceReport>>>,
+    skid_stats: Cell<SkidStats>,
+    /// What tasks looked like when they exited, for post-mortem inspection.
+    dead_tasks: RefCell<DeadTasks>,
+    /// Whether the trace has memory checksums to verify, so we don't look for
+    /// one at every event when it has none.
+    has_checksums: bool,
 }
 
 #[derive(Copy, Clone)]
@@ -364,6 +399,8 @@ pub struct Flags {
     pub redirect_stdio: bool,
     pub share_private_mappings: bool,
     pub cpu_unbound: bool,
+    /// How much of exited tasks to keep around, see `dead_tasks`.
+    pub dead_tasks: DeadTaskPolicy,
 }
 
 impl Drop for ReplaySession {
@@ -382,8 +419,9 @@ impl Drop for ReplaySession {
         debug_assert!(self.emufs().size() == 0);
         log!(
             LogDebug,
-            "ReplaySession {:?} destroyed",
-            self as *const Self
+            "ReplaySession {:?} destroyed: {}",
+            self as *const Self,
+            self.skid_stats.get()
         );
     }
 }
@@ -469,10 +507,28 @@ impl ReplaySession {
         &self.flags_
     }
 
+    /// How precisely ticks interrupts have landed so far.
+    pub fn skid_stats(&self) -> SkidStats {
+        self.skid_stats.get()
+    }
+
+    /// The final state of tasks whose exit has been replayed.
+    pub fn dead_tasks(&self) -> Ref<'_, DeadTasks> {
+        self.dead_tasks.borrow()
+    }
+
+    fn update_skid_stats<F: FnOnce(&mut SkidStats)>(&self, f: F) {
+        let mut stats = self.skid_stats.get();
+        f(&mut stats);
+        self.skid_stats.set(stats);
+    }
+
     fn new<T: AsRef<OsStr>>(dir: Option<&T>, flags: Flags) -> ReplaySession {
+        let trace_in = TraceReader::new(dir);
+        let has_checksums = trace_in.has_checksums();
         let mut rs = ReplaySession {
             emu_fs: EmuFs::create(),
-            trace_in: RefCell::new(TraceReader::new(dir)),
+            trace_in: RefCell::new(trace_in),
             trace_frame: Default::default(),
             current_step: Default::default(),
             ticks_at_start_of_event: Default::default(),
@@ -484,6 +540,10 @@ impl ReplaySession {
             fast_forward_status: Default::default(),
             syscall_bp_vm: Default::default(),
             syscall_bp_addr: Default::default(),
+            divergence: Default::default(),
+            skid_stats: Default::default(),
+            dead_tasks: RefCell::new(DeadTasks::new(flags.dead_tasks)),
+            has_checksums,
         };
 
         let semantics = rs.trace_in.borrow().ticks_semantics();
@@ -498,12 +558,32 @@ impl ReplaySession {
                           system does not support CPUID faulting."
             );
         }
+        let mismatches = cpuid_mismatches(rs.trace_in.borrow().cpuid_records());
+        for (rec, current) in &mismatches {
+            log!(
+                LogInfo,
+                "CPUID {:#x}:{:#x} differs: recorded {:#x} {:#x} {:#x} {:#x}, \
+                 now {:#x} {:#x} {:#x} {:#x}",
+                rec.eax_in,
+                rec.ecx_in,
+                rec.out.eax,
+                rec.out.ebx,
+                rec.out.ecx,
+                rec.out.edx,
+                current.eax,
+                current.ebx,
+                current.ecx,
+                current.edx
+            );
+        }
         if !SessionInner::has_cpuid_faulting()
             && !cpuid_compatible(rs.trace_in.borrow().cpuid_records())
         {
             clean_fatal!(
                 "Trace was recorded on a machine with different CPUID values\n\
-                          and CPUID faulting is not enabled; replay will not work."
+                          and CPUID faulting is not enabled; replay will not work.\n\
+                          {} CPUID leaves differ (run with RD_LOG=all:info for details).",
+                mismatches.len()
             );
         }
         if !PerfCounters::supports_ticks_semantics(rs.ticks_semantics_) {
@@ -513,7 +593,24 @@ impl ReplaySession {
             );
         }
 
+        let recorded_core_type = rs.trace_in.borrow().core_type();
+        if let (Some(recorded), Some(current)) = (recorded_core_type, PerfCounters::core_type()) {
+            if recorded != current {
+                clean_fatal!(
+                    "Trace was recorded on {}s of a hybrid CPU; replay with --core-type={}.",
+                    recorded,
+                    recorded.to_string().to_lowercase()
+                );
+            }
+        }
+
         check_xsave_compatibility(&rs.trace_in.borrow());
+        check_page_size_compatibility(&rs.trace_in.borrow());
+        // Replay doesn't care, but whoever is looking into what the recording
+        // did might.
+        for warning in rs.trace_in.borrow().record_environment().warnings() {
+            log!(LogWarn, "Trace was recorded {}", warning);
+        }
         rs
     }
 
@@ -572,6 +669,66 @@ impl ReplaySession {
         rc
     }
 
+    /// Create a new replay session, starting from the beginning, on the same
+    /// trace as this one.
+    ///
+    /// The new session is completely independent of this one: it has its own
+    /// TraceReader (with its own file descriptors and read positions), its own
+    /// EmuFs and its own tracees. Both sessions only ever read the trace, so
+    /// they can be stepped in any interleaving, e.g. to compare two points of
+    /// the same execution side by side.
+    pub fn create_on_same_trace(&self) -> SessionSharedPtr {
+        let dir = self.trace_in.borrow().dir().to_owned();
+        ReplaySession::create(Some(&dir), self.flags_)
+    }
+
+    /// Replay until `constraints` are met or something breaks. User
+    /// breakpoints whose condition (see `AddressSpace::set_breakpoint_condition`)
+    /// doesn't hold for a hit are stepped over without stopping.
+    pub fn replay_step_with_constraints(&self, constraints: StepConstraints) -> ReplayResult {
+        loop {
+            let mut result = self.replay_step_unconditional(constraints.clone());
+            if !result.break_status.breakpoint_hit {
+                return result;
+            }
+            let t = result
+                .break_status
+                .task
+                .as_ref()
+                .unwrap()
+                .upgrade()
+                .unwrap();
+            let ip = t.borrow().ip();
+            let vm = t.borrow().vm_shr_ptr();
+            if vm.user_breakpoint_condition_holds(ip, t.borrow().as_ref()) {
+                return result;
+            }
+
+            result.break_status.breakpoint_hit = false;
+            if result.break_status.any_break() {
+                return result;
+            }
+            log!(
+                LogDebug,
+                "Breakpoint condition at {} doesn't hold; stepping over",
+                ip
+            );
+            vm.suspend_breakpoint_at(ip);
+            let mut step_result =
+                self.replay_step_unconditional(StepConstraints::new(RunCommand::RunSinglestep));
+            vm.restore_breakpoint_at(ip);
+            if constraints.is_singlestep() {
+                return step_result;
+            }
+            step_result.break_status.singlestep_complete = false;
+            if step_result.status != ReplayStatus::ReplayContinue
+                || step_result.break_status.any_break()
+            {
+                return step_result;
+            }
+        }
+    }
+
     /// Take a single replay step.
     /// Ensure we stop at event stop_at_time. If this is not specified,
     /// optimizations may cause a replay_step to pass straight through
@@ -584,8 +741,39 @@ impl ReplaySession {
     /// reaches ticks_target (but not too far before, unless we hit a breakpoint
     /// or stop_at_time). Only useful for RUN_CONTINUE.
     /// Always stops on a switch to a new task.
-    pub fn replay_step_with_constraints(&self, constraints: StepConstraints) -> ReplayResult {
+    fn replay_step_unconditional(&self, constraints: StepConstraints) -> ReplayResult {
+        if let Some(result) = self.diverged_result() {
+            return result;
+        }
         self.finish_initializing();
+        self.assert_no_tasks_borrowed();
+        let result = self.replay_one_step(constraints);
+        // All task borrows of the step have been released by now.
+        self.run_deferred_work();
+        self.diverged_result().unwrap_or(result)
+    }
+
+    /// Record that replay of `t` no longer matches the recording. The step in
+    /// progress is cut short and it, and any later step, returns a
+    /// `ReplayDiverged` result. Only the first divergence is kept; whatever
+    /// follows it is most likely a consequence.
+    pub fn report_divergence(&self, t: &ReplayTask, kind: DivergenceKind) {
+        if self.divergence.borrow().is_some() {
+            return;
+        }
+        let report = DivergenceReport::new(t, &self.trace_in.borrow(), kind);
+        log!(LogError, "{}", report);
+        *self.divergence.borrow_mut() = Some(Box::new(report));
+    }
+
+    fn diverged_result(&self) -> Option<ReplayResult> {
+        let report = self.divergence.borrow().clone()?;
+        let mut result = ReplayResult::new(ReplayStatus::ReplayDiverged);
+        result.divergence = Some(report);
+        Some(result)
+    }
+
+    fn replay_one_step(&self, constraints: StepConstraints) -> ReplayResult {
         let mut result = ReplayResult::new(ReplayStatus::ReplayContinue);
         let mut maybe_rc_t = self.current_task();
 
@@ -616,6 +804,9 @@ impl ReplaySession {
             let t = dt.as_replay_task_mut().unwrap();
             // Advance towards fulfilling `current_step`.
             if self.try_one_trace_step(t, &constraints) == Completion::Incomplete {
+                if self.divergence.borrow().is_some() {
+                    return result;
+                }
                 if EventType::EvTraceTermination == self.current_trace_frame().event().event_type()
                 {
                     // An irregular trace step had to read the
@@ -652,6 +843,11 @@ impl ReplaySession {
             match self.current_step.get().action {
                 ReplayTraceStepType::TstepDeterministicSignal
                 | ReplayTraceStepType::TstepProgramAsyncSignalInterrupt => {
+                    if self.current_trace_frame().event().event_type() == EventType::EvSched {
+                        // Recording aborted any rseq critical section the task
+                        // was preempted in right after this event.
+                        abort_rseq_critical_section(t);
+                    }
                     if self.current_step.get().target().signo != 0 {
                         if self.current_trace_frame().event().event_type()
                             != EventType::EvInstructionTrap
@@ -775,7 +971,23 @@ impl ReplaySession {
         );
 
         if !t.syscallbuf_child.is_null() {
-            unimplemented!()
+            let hdr = RemotePtr::<u8>::cast(t.syscallbuf_child);
+            let num_rec_bytes: u32 = read_val_mem(
+                t,
+                RemotePtr::cast(hdr + offset_of!(syscallbuf_hdr, num_rec_bytes)),
+                None,
+            );
+            let abort_commit: u8 =
+                read_val_mem(t, hdr + offset_of!(syscallbuf_hdr, abort_commit), None);
+            // Read `locked` as a plain u8: it's a bit set, not a single enum value.
+            let locked: u8 = read_val_mem(t, hdr + offset_of!(syscallbuf_hdr, locked), None);
+            log!(
+                LogDebug,
+                "    (syscllbufsz:{}, abrtcmt:{}, locked:{})",
+                num_rec_bytes,
+                abort_commit != 0,
+                locked
+            );
         }
 
         // Ask the trace-interpretation code what to do next in order
@@ -794,8 +1006,7 @@ impl ReplaySession {
             }
             EventType::EvSyscallbufFlush => {
                 current_step.action = ReplayTraceStepType::TstepFlushSyscallbuf;
-
-                self.prepare_syscallbuf_records(t);
+                current_step.data = ReplayTraceStepData::Flush(self.prepare_syscallbuf_records(t));
             }
             EventType::EvSyscallbufReset => {
                 // Reset syscallbuf_hdr->num_rec_bytes and zero out the recorded data.
@@ -878,6 +1089,16 @@ impl ReplaySession {
                     }
                 }
             }
+            EventType::EvInterceptedSyscall => {
+                let e = ev.intercepted_syscall_event();
+                current_step = ReplayTraceStep {
+                    action: ReplayTraceStepType::TstepInterceptedSyscall,
+                    data: ReplayTraceStepData::Syscall(ReplayTraceStepSyscall {
+                        arch: e.arch,
+                        number: e.number,
+                    }),
+                };
+            }
             EventType::EvUnassigned
             | EventType::EvSentinel
             | EventType::EvNoop
@@ -895,12 +1116,50 @@ impl ReplaySession {
         t_shr_ptr
     }
 
-    fn prepare_syscallbuf_records(&self, _t: &ReplayTask) {
-        unimplemented!()
+    /// Read the recorded syscall buffer back into the buffer region of `t` and
+    /// work out where the replay of the flush has to stop.
+    fn prepare_syscallbuf_records(&self, t: &mut ReplayTask) -> ReplayFlushBufferedSyscallState {
+        let buf = self.trace_in.borrow_mut().read_raw_data();
+        ed_assert!(t, buf.data.len() >= size_of::<syscallbuf_hdr>());
+        ed_assert!(t, buf.data.len() <= t.syscallbuf_size);
+        ed_assert!(t, buf.addr == RemotePtr::cast(t.syscallbuf_child));
+
+        let num_rec_bytes_offset = offset_of!(syscallbuf_hdr, num_rec_bytes);
+        let num_rec_bytes = u32::from_ne_bytes(
+            buf.data[num_rec_bytes_offset..num_rec_bytes_offset + size_of::<u32>()]
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        // Don't overwrite syscallbuf_hdr. That needs to keep tracking the current
+        // syscallbuf state.
+        let records_addr = RemotePtr::cast(t.syscallbuf_child + 1usize);
+        t.write_bytes_helper(
+            records_addr,
+            &buf.data[size_of::<syscallbuf_hdr>()..],
+            None,
+            WriteFlags::empty(),
+        );
+
+        ed_assert!(
+            t,
+            num_rec_bytes + size_of::<syscallbuf_hdr>() <= t.syscallbuf_size
+        );
+
+        // There is one stopping breakpoint per 8 bytes of records: the preload
+        // library jumps to the one corresponding to the end of the buffer once it
+        // has replayed all the records.
+        let stop_breakpoint_addr = t.stopping_breakpoint_table.as_usize()
+            + (num_rec_bytes / 8) * t.stopping_breakpoint_table_entry_size;
+
+        log!(LogDebug, "Prepared {} bytes of syscall records", num_rec_bytes);
+
+        ReplayFlushBufferedSyscallState {
+            stop_breakpoint_addr,
+        }
     }
 
     fn revive_task_for_exec(&self, ev: &Event, trace_frame_tid: pid_t) -> TaskSharedPtr {
-        if !ev.is_syscall_event() || !is_execve_syscall(ev.syscall().number, ev.syscall().arch()) {
+        if !ev.is_syscall_event() || !is_exec(ev.syscall().number, ev.syscall().arch()) {
             fatal!("Can't find task, but we're not in an execve");
         }
 
@@ -919,6 +1178,8 @@ impl ReplaySession {
         if tg.borrow().task_set().len() != 1 {
             fatal!("Should only be one task left in the taskgroup");
         }
+        // The exec released the leader if it was a zombie.
+        tg.borrow_mut().zombie_leader = false;
 
         let t_rc = tg.borrow().task_set().iter().next().unwrap();
         let t_rec_tid = t_rc.borrow().rec_tid;
@@ -940,6 +1201,73 @@ impl ReplaySession {
         self.replay_step_with_constraints(StepConstraints::new(command))
     }
 
+    /// Replay until [addr, addr + num_bytes) in the address space of the
+    /// current task is written to with a different value, or until anything
+    /// else (a breakpoint, a signal, the end of the trace, ...) stops replay.
+    /// The range can be any size: it's watched with debug registers as far as
+    /// they go and with page write-protection beyond that (see
+    /// `AddressSpace::add_watchpoint()`). Returns `None` if there's no current
+    /// task or the range can't be watched at all.
+    ///
+    /// Only writes by tasks sharing the address space are seen, not writes
+    /// through shared memory from other processes.
+    ///
+    /// If `cancel` is cancelled we stop after the current step and return its
+    /// result, with the watchpoint removed as usual.
+    pub fn continue_until_write(
+        &self,
+        addr: RemotePtr<Void>,
+        num_bytes: usize,
+        cancel: &CancellationToken,
+    ) -> Option<ReplayResult> {
+        let t = self.current_task()?;
+        let vm = t.borrow().vm_shr_ptr();
+        if !vm.add_watchpoint(
+            addr,
+            num_bytes,
+            WatchType::WatchWrite,
+            t.borrow_mut().as_mut(),
+        ) {
+            log!(
+                LogDebug,
+                "Can't watch {} bytes at {} for writes",
+                num_bytes,
+                addr
+            );
+            vm.remove_watchpoint(
+                addr,
+                num_bytes,
+                WatchType::WatchWrite,
+                t.borrow_mut().as_mut(),
+            );
+            return None;
+        }
+
+        let result = loop {
+            let result = self.replay_step(RunCommand::RunContinue);
+            if result.status != ReplayStatus::ReplayContinue
+                || result.break_status.any_break()
+                || result.break_status.task_exit
+                || cancel.is_cancelled()
+            {
+                break result;
+            }
+        };
+
+        // The task we started with may be gone by now; any task of the
+        // address space will do for reprogramming the debug registers.
+        let maybe_t = vm.task_set().iter().next();
+        if let Some(t) = maybe_t {
+            vm.remove_watchpoint(
+                addr,
+                num_bytes,
+                WatchType::WatchWrite,
+                t.borrow_mut().as_mut(),
+            );
+        }
+        Some(result)
+    }
+
     fn emulate_signal_delivery(&self, t: &mut ReplayTask, sig: i32) -> Completion {
         let maybe_t = self.current_task();
         match maybe_t {
@@ -1038,7 +1366,9 @@ impl ReplaySession {
                 return Completion::Incomplete;
             }
             SIGSEGV => {
-                if self.handle_unrecorded_cpuid_fault(t, constraints) {
+                if self.handle_unrecorded_cpuid_fault(t, constraints)
+                    || self.handle_software_watchpoint_fault(t, constraints)
+                {
                     return Completion::Incomplete;
                 }
             }
@@ -1197,10 +1527,44 @@ impl ReplaySession {
         Completion::Complete
     }
 
+    /// There are no recorded registers to run to; the tick count and the
+    /// syscall number have to do.
+    fn replay_intercepted_syscall(
+        &self,
+        t: &mut ReplayTask,
+        constraints: &StepConstraints,
+    ) -> Completion {
+        if self.cont_syscall_boundary(t, constraints) == Completion::Incomplete {
+            return Completion::Incomplete;
+        }
+
+        let e = *self
+            .current_trace_frame()
+            .event()
+            .intercepted_syscall_event();
+        t.canonicalize_regs(e.arch);
+        ed_assert!(
+            t,
+            t.regs_ref().original_syscallno() == e.number as isize,
+            "Expected to enter {}, entered {}",
+            syscall_name(e.number, e.arch),
+            syscall_name(t.regs_ref().original_syscallno() as i32, e.arch)
+        );
+        ed_assert!(t, t.tick_count() == self.current_trace_frame().ticks());
+        t.finish_emulated_syscall();
+        let mut r = t.regs_ref().clone();
+        r.set_syscall_result_signed(e.result as isize);
+        t.set_regs(&r);
+        Completion::Complete
+    }
+
     fn exit_task(&self, t: &mut ReplayTask) -> Completion {
         ed_assert!(t, !t.seen_ptrace_exit_event);
         // Apply robust-futex updates captured during recording.
         t.apply_all_data_records_from_trace();
+        self.dead_tasks
+            .borrow_mut()
+            .task_exiting(t, self.current_frame_time());
         end_task(t);
         // |t| is dead now.
         Completion::Complete
@@ -1247,6 +1611,26 @@ impl ReplaySession {
         });
         true
     }
+
+    /// A SIGSEGV that hit a page we protected to implement watchpoints
+    /// without debug registers. See `AddressSpace::protect_software_watchpoints()`.
+    fn handle_software_watchpoint_fault(
+        &self,
+        t: &mut ReplayTask,
+        constraints: &StepConstraints,
+    ) -> bool {
+        if t.maybe_stop_sig() != SIGSEGV || !t.vm_shr_ptr().handle_software_watchpoint_fault(t) {
+            return false;
+        }
+        // We're at the SIGTRAP of stepping over the access. If no watchpoint
+        // fired, that's as uninteresting as the SIGSEGV was unless the
+        // debugger asked for the step.
+        if !constraints.is_singlestep() && !t.vm().has_any_watchpoint_changes() {
+            t.set_status(WaitStatus::default());
+        }
+        true
+    }
+
     fn check_ticks_consistency(&self, t: &ReplayTask, ev: &Event) {
         if !self.done_initial_exec() {
             return;
@@ -1309,7 +1693,9 @@ impl ReplaySession {
                 tick_request,
                 None,
             );
-            self.handle_unrecorded_cpuid_fault(t, constraints);
+            if !self.handle_unrecorded_cpuid_fault(t, constraints) {
+                self.handle_software_watchpoint_fault(t, constraints);
+            }
         } else if constraints.command == RunCommand::RunSinglestepFastForward {
             self.fast_forward_status.set(
                 self.fast_forward_status.get()
@@ -1319,7 +1705,9 @@ impl ReplaySession {
                         &constraints.stop_before_states,
                     ),
             );
-            self.handle_unrecorded_cpuid_fault(t, constraints);
+            if !self.handle_unrecorded_cpuid_fault(t, constraints) {
+                self.handle_software_watchpoint_fault(t, constraints);
+            }
         } else {
             t.resume_execution(resume_how, WaitRequest::ResumeWait, tick_request, None);
             if t.maybe_stop_sig().is_not_sig() {
@@ -1345,19 +1733,36 @@ impl ReplaySession {
                     }
                     _ => (),
                 }
-            } else if self.handle_unrecorded_cpuid_fault(t, constraints) {
+            } else if self.handle_unrecorded_cpuid_fault(t, constraints)
+                || self.handle_software_watchpoint_fault(t, constraints)
+            {
                 return Completion::Incomplete;
             }
         }
         self.check_pending_sig(t);
         Completion::Complete
     }
+    /// Run `t` up to `constraints.ticks_target`, which comes before the end
+    /// of the current step. Stops short of the target by the task's skid
+    /// region; the caller gets `BreakStatus::approaching_ticks_target` and
+    /// takes it from there.
     fn advance_to_ticks_target(
         &self,
-        _t: &ReplayTask,
-        _constraints: &StepConstraints,
+        t: &mut ReplayTask,
+        constraints: &StepConstraints,
     ) -> Completion {
-        unimplemented!();
+        loop {
+            let mut ticks_request = TicksRequest::ResumeUnlimitedTicks;
+            if !compute_ticks_request(t, constraints, &mut ticks_request) {
+                return Completion::Incomplete;
+            }
+            self.continue_or_step(t, constraints, ticks_request, None);
+            if t.maybe_stop_sig() == SIGTRAP {
+                // A debugger breakpoint or singlestep.
+                return Completion::Incomplete;
+            }
+            guard_unexpected_signal(t);
+        }
     }
     fn emulate_deterministic_signal(
         &self,
@@ -1446,26 +1851,38 @@ impl ReplaySession {
         );
 
         // XXX should we only do this if ticks > 10000?
-        while ticks_left > 2 * PerfCounters::skid_size() as i64 {
-            log!(
-                LogDebug,
-                "  programming interrupt for {} ticks",
-                ticks_left - PerfCounters::skid_size() as i64
-            );
+        while ticks_left > 2 * t.skid_region.margin() as i64 {
+            let request = min(MAX_TICKS_REQUEST, ticks_left as u64) - t.skid_region.margin();
+            log!(LogDebug, "  programming interrupt for {} ticks", request);
 
             // Avoid overflow. If ticks_left > MAX_TICKS_REQUEST, execution will stop
             // early but we'll treat that just like a stray TIME_SLICE_SIGNAL and
             // continue as needed.
+            let interrupt_at = t.tick_count() + request;
             self.continue_or_step(
                 t,
                 constraints,
-                TicksRequest::ResumeWithTicksRequest(
-                    min(MAX_TICKS_REQUEST, ticks_left as u64) - PerfCounters::skid_size(),
-                ),
+                TicksRequest::ResumeWithTicksRequest(request),
                 None,
             );
             guard_unexpected_signal(t);
 
+            // Stops short of the interrupt are for other reasons, e.g. a
+            // debugger breakpoint, and say nothing about skid.
+            if t.tick_count() >= interrupt_at {
+                let skid = t.tick_count() - interrupt_at;
+                let widened = t.skid_region.observe(skid);
+                if widened {
+                    log!(
+                        LogDebug,
+                        "  stopped {} ticks past the interrupt; skid region now {}",
+                        skid,
+                        t.skid_region.margin()
+                    );
+                }
+                self.update_skid_stats(|stats| stats.note_interrupt(skid, widened));
+            }
+
             // Update ticks_left
             ticks_left = ticks as i64 - t.tick_count() as i64;
 
@@ -1480,7 +1897,9 @@ impl ReplaySession {
                 return Completion::Incomplete;
             }
         }
-        guard_overshoot(t, &regs, ticks, ticks_left, None);
+        if guard_overshoot(t, &regs, ticks, ticks_left, None) {
+            return Completion::Incomplete;
+        }
 
         // True when our advancing has triggered a tracee SIGTRAP that needs to
         // be dealt with.
@@ -1502,6 +1921,10 @@ impl ReplaySession {
         // retired-instruction interrupt and do away with all this
         // cruft.
         let mut mismatched_regs: Option<Registers> = None;
+        // How much of the remaining way was done by breaking on the target
+        // $ip and by single-stepping over it.
+        let mut breakpoint_stops: u32 = 0;
+        let mut steps_over_target: u32 = 0;
         loop {
             // Invariants here are
             //  o ticks_left is up-to-date
@@ -1564,6 +1987,7 @@ impl ReplaySession {
                     debug_assert!(!at_target);
 
                     pending_SIGTRAP = false;
+                    breakpoint_stops += 1;
                     t.move_ip_before_breakpoint();
                     // We just backed up the $ip, but
                     // rewound it over an |int $3|
@@ -1601,6 +2025,14 @@ impl ReplaySession {
 
             if at_target {
                 // Case (2) above: done.
+                log!(
+                    LogDebug,
+                    "  reached {}/{} after {} stops at the target $ip and {} steps over it",
+                    ticks,
+                    ip,
+                    breakpoint_stops,
+                    steps_over_target
+                );
                 return Completion::Complete;
             }
 
@@ -1659,6 +2091,7 @@ impl ReplaySession {
                             ),
                     );
                     SIGTRAP_run_command = RunCommand::RunSinglestepFastForward;
+                    steps_over_target += 1;
                     self.check_pending_sig(t);
                 }
             }
@@ -1678,11 +2111,100 @@ impl ReplaySession {
                 guard_unexpected_signal(t);
             }
 
-            guard_overshoot(t, &regs, ticks, ticks_left, mismatched_regs.as_ref());
+            if guard_overshoot(t, &regs, ticks, ticks_left, mismatched_regs.as_ref()) {
+                return Completion::Incomplete;
+            }
         }
     }
-    fn flush_syscallbuf(&self, _t: &ReplayTask, _constraints: &StepConstraints) -> Completion {
-        unimplemented!();
+    /// Let the preload library replay the buffered syscalls of the current
+    /// flush event, stopping at the breakpoint computed by
+    /// `prepare_syscallbuf_records()`.
+    fn flush_syscallbuf(&self, t: &mut ReplayTask, constraints: &StepConstraints) -> Completion {
+        let mut user_breakpoint_at_addr: bool;
+        let hdr = RemotePtr::<u8>::cast(t.syscallbuf_child);
+
+        loop {
+            let mut next_rec = t.next_syscallbuf_record();
+            let skip_mprotect_records: u32 = read_val_mem(
+                t,
+                RemotePtr::cast(hdr + offset_of!(syscallbuf_hdr, mprotect_record_count_completed)),
+                None,
+            );
+
+            let mut ticks_request = TicksRequest::ResumeUnlimitedTicks;
+            if !compute_ticks_request(t, constraints, &mut ticks_request) {
+                return Completion::Incomplete;
+            }
+
+            let bp_addr =
+                RemoteCodePtr::from_val(self.current_step.get().flush().stop_breakpoint_addr);
+            let added = t
+                .vm_shr_ptr()
+                .add_breakpoint(t, bp_addr, BreakpointType::BkptInternal);
+            ed_assert!(t, added);
+            self.continue_or_step(
+                t,
+                constraints,
+                ticks_request,
+                Some(ResumeRequest::ResumeCont),
+            );
+            user_breakpoint_at_addr =
+                t.vm().get_breakpoint_type_at_addr(bp_addr) != BreakpointType::BkptInternal;
+            t.vm_shr_ptr()
+                .remove_breakpoint(bp_addr, BreakpointType::BkptInternal, t);
+
+            // Account for buffered syscalls just completed
+            let end_rec = t.next_syscallbuf_record();
+            while next_rec != end_rec {
+                self.accumulate_syscall_performed();
+                let rec_size = t.stored_record_size(next_rec) as usize;
+                next_rec = RemotePtr::cast(RemotePtr::<u8>::cast(next_rec) + rec_size);
+            }
+
+            // Apply the mprotect records we just completed.
+            apply_mprotect_records(t, skip_mprotect_records);
+
+            if t.maybe_stop_sig() == TIME_SLICE_SIGNAL {
+                // This would normally be triggered by constraints.ticks_target but it's
+                // also possible to get stray signals here.
+                return Completion::Incomplete;
+            }
+
+            if ReplaySession::is_ignored_signal(t.maybe_stop_sig().get_raw_repr()) {
+                continue;
+            }
+
+            ed_assert!(
+                t,
+                t.maybe_stop_sig() == SIGTRAP,
+                "Replay got unexpected signal (or none) {}",
+                t.maybe_stop_sig().get_raw_repr()
+            );
+            let arch = t.arch();
+            if t.ip().decrement_by_bkpt_insn_length(arch) == bp_addr {
+                let mut r = t.regs_ref().clone();
+                r.set_ip(bp_addr);
+                t.set_regs(&r);
+                break;
+            }
+
+            let break_status = self.diagnose_debugger_trap(t, constraints.command);
+            ed_assert!(
+                t,
+                break_status.signal.is_none(),
+                "Expected either SIGTRAP at $ip {} or USER breakpoint just after it",
+                t.ip()
+            );
+            if break_status.any_break() {
+                return Completion::Incomplete;
+            }
+        }
+
+        if user_breakpoint_at_addr {
+            Completion::Incomplete
+        } else {
+            Completion::Complete
+        }
     }
     fn patch_next_syscall(&self, t: &mut ReplayTask, constraints: &StepConstraints) -> Completion {
         if self.cont_syscall_boundary(t, constraints) == Completion::Incomplete {
@@ -1782,6 +2304,9 @@ impl ReplaySession {
             }
             ReplayTraceStepType::TstepFlushSyscallbuf => self.flush_syscallbuf(t, &constraints),
             ReplayTraceStepType::TstepPatchSyscall => self.patch_next_syscall(t, &constraints),
+            ReplayTraceStepType::TstepInterceptedSyscall => {
+                self.replay_intercepted_syscall(t, &constraints)
+            }
             ReplayTraceStepType::TstepExitTask => self.exit_task(t),
             _ => {
                 fatal!("Unhandled step type: {:?}", self.current_step.get().action);
@@ -1797,7 +2322,7 @@ impl ReplaySession {
     ) {
         if constraints.ticks_target > 0 {
             let ticks_left = constraints.ticks_target - t.tick_count();
-            if ticks_left <= PerfCounters::skid_size() {
+            if ticks_left <= t.skid_region.margin() {
                 break_status.approaching_ticks_target = true;
             }
         }
@@ -1915,6 +2440,31 @@ fn tracee_xsave_enabled(trace_in: &TraceReader) -> bool {
     maybe_record.unwrap().out.ecx & OSXSAVE_FEATURE_FLAG != 0
 }
 
+/// Every mapping in a trace recorded with pages of N bytes is aligned to and
+/// a multiple of N bytes, so if our page size divides N we can reproduce the
+/// recorded mappings exactly (e.g. a 16K-page trace on a 4K-page machine). The
+/// reverse would need mappings finer than our pages, so refuse to replay.
+fn check_page_size_compatibility(trace_in: &TraceReader) {
+    let trace_page_size = trace_in.page_size();
+    if trace_page_size == page_size() {
+        return;
+    }
+    if trace_page_size < page_size() || trace_page_size % page_size() != 0 {
+        clean_fatal!(
+            "Trace was recorded on a machine with {}-byte pages, but this\n\
+                      machine uses {}-byte pages; replay will not work.",
+            trace_page_size,
+            page_size()
+        );
+    }
+    log!(
+        LogInfo,
+        "Replaying a trace recorded with {}-byte pages on {}-byte pages",
+        trace_page_size,
+        page_size()
+    );
+}
+
 fn check_xsave_compatibility(trace_in: &TraceReader) {
     if !tracee_xsave_enabled(trace_in) {
         // Tracee couldn't use XSAVE so everything should be fine.
@@ -2004,6 +2554,44 @@ fn treat_signal_event_as_deterministic(ev: &SignalEventData) -> bool {
     ev.deterministic == SignalDeterministic::DeterministicSig && ev.siginfo.si_signo != SIGBUS
 }
 
+/// Update our model of `t`'s address space for the mprotect()s done by buffered
+/// syscalls since the last flush, skipping the first `skip_mprotect_records`
+/// which have already been applied.
+fn apply_mprotect_records(t: &mut ReplayTask, skip_mprotect_records: u32) {
+    let hdr = RemotePtr::<u8>::cast(t.syscallbuf_child);
+    let final_mprotect_record_count: u32 = read_val_mem(
+        t,
+        RemotePtr::cast(hdr + offset_of!(syscallbuf_hdr, mprotect_record_count)),
+        None,
+    );
+    if skip_mprotect_records >= final_mprotect_record_count {
+        return;
+    }
+
+    let records_addr: RemotePtr<mprotect_record> = RemotePtr::cast(
+        RemotePtr::<u8>::cast(t.preload_globals.unwrap())
+            + offset_of!(preload_globals, mprotect_records),
+    );
+    let records = read_mem(t, records_addr, final_mprotect_record_count as usize, None);
+    for r in &records[skip_mprotect_records as usize..] {
+        let start = RemotePtr::<Void>::new_from_val(r.start as usize);
+        let prot = ProtFlags::from_bits_truncate(r.prot);
+        t.vm().protect(t, start, r.size as usize, prot);
+        if running_under_rd() {
+            // Let an outer rd know about the change too.
+            unsafe {
+                libc::syscall(
+                    SYS_rdcall_mprotect_record as i64,
+                    t.tid,
+                    r.start as usize,
+                    r.size as usize,
+                    r.prot,
+                );
+            }
+        }
+    }
+}
+
 fn perform_interrupted_syscall(_t: &mut ReplayTask) {
     unimplemented!()
 }
@@ -2014,9 +2602,11 @@ fn perform_interrupted_syscall(_t: &mut ReplayTask) {
 /// This means that an interrupt programmed for retired branch k might
 /// fire at `k + 50`, for example.  To counteract the slack, we program
 /// interrupts just short of our target, by the `SKID_SIZE` region
-/// below, and then more slowly advance to the real target.
+/// (`PerfCounters::skid_size()`), and then more slowly advance to the real
+/// target. A task whose interrupts land close to the edge of that region
+/// gets a wider one of its own (see `SkidRegion`).
 ///
-/// How was this magic number determined?  Trial and error: we want it
+/// How was the size for each CPU determined?  Trial and error: we want it
 /// to be as small as possible for efficiency, but not so small that
 /// overshoots are observed.  If all other possible causes of overshoot
 /// have been ruled out, like memory divergence, then you'll know that
@@ -2036,7 +2626,8 @@ fn perform_interrupted_syscall(_t: &mut ReplayTask) {
 /// skidded too far past the programmed target for rd to handle it.
 ///
 /// If that occurs, the SKID_SIZE needs to be increased by at least
-/// `i`.
+/// `i`. Replaying with `--skid-size` confirms that before the CPU's entry
+/// in the PMU configuration table is changed.
 ///
 /// NB: there are probably deeper reasons for the target slack that
 /// could perhaps let it be deduced instead of arrived at empirically;
@@ -2049,9 +2640,8 @@ fn compute_ticks_request(
 ) -> bool {
     *ticks_request = TicksRequest::ResumeUnlimitedTicks;
     if constraints.ticks_target > 0 {
-        let ticks_period = constraints.ticks_target as i64
-            - PerfCounters::skid_size() as i64
-            - t.tick_count() as i64;
+        let ticks_period =
+            constraints.ticks_target as i64 - t.skid_region.margin() as i64 - t.tick_count() as i64;
         if ticks_period <= 0 {
             // Behave as if we actually executed something. Callers assume we did.
             t.clear_wait_status();
@@ -2087,11 +2677,24 @@ fn has_deterministic_ticks(ev: &Event, step: ReplayTraceStep) -> bool {
     ReplayTraceStepType::TstepProgramAsyncSignalInterrupt != step.action
 }
 
-fn debug_memory(t: &ReplayTask) {
+fn debug_memory(t: &mut ReplayTask) {
     let current_time = t.current_frame_time();
     if should_dump_memory(t.current_trace_frame().event(), current_time) {
         unimplemented!()
     }
+    if t.session().done_initial_exec() && t.session().as_replay().unwrap().has_checksums {
+        // Verify the checksums taken during recording, if any were.
+        let path = t.trace_reader().checksum_path(current_time, t.rec_tid);
+        if Path::new(&path).exists() {
+            let mismatches = memory_mismatches(t, &path, current_time);
+            if !mismatches.is_empty() {
+                t.session()
+                    .as_replay()
+                    .unwrap()
+                    .report_divergence(t, DivergenceKind::Memory(mismatches));
+            }
+        }
+    }
 }
 
 fn guard_unexpected_signal(t: &mut ReplayTask) {
@@ -2167,13 +2770,15 @@ fn is_same_execution_point(
     true
 }
 
+/// Returns true (after reporting the divergence) if `t` has run past the
+/// target ticks.
 fn guard_overshoot(
     t: &mut ReplayTask,
     target_regs: &Registers,
     target_ticks: Ticks,
     remaining_ticks: i64,
     closest_matching_regs: Option<&Registers>,
-) {
+) -> bool {
     if remaining_ticks < 0 {
         let target_ip: RemoteCodePtr = target_regs.ip();
 
@@ -2210,12 +2815,19 @@ fn guard_overshoot(
                 MismatchBehavior::LogMismatches,
             );
         }
-        ed_assert!(
+        let session = t.session();
+        let replay_session = session.as_replay().unwrap();
+        replay_session.update_skid_stats(|stats| stats.overshoots += 1);
+        replay_session.report_divergence(
             t,
-            false,
-            "overshot target ticks={} by {}",
-            target_ticks,
-            -remaining_ticks
+            DivergenceKind::TicksOvershoot {
+                target: target_ticks,
+                overshoot: (-remaining_ticks) as Ticks,
+                skid_margin: t.skid_region.margin(),
+                skid_stats: replay_session.skid_stats(),
+            },
         );
+        return true;
     }
+    false
 }
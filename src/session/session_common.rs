@@ -1,18 +1,44 @@
 use crate::{
     bindings::ptrace::PTRACE_DETACH,
     kernel_abi::syscall_number_for_exit,
-    log::LogDebug,
+    log::{LogDebug, LogWarn},
     remote_ptr::RemotePtr,
     session::{task::task_inner::task_inner::PtraceData, Session},
     taskish_uid::{AddressSpaceUid, ThreadGroupUid},
-    util::is_zombie_process,
+    util::{is_uninterruptible_sleep, is_zombie_process},
 };
-use libc::{syscall, SYS_tgkill, ESRCH, SIGKILL};
+use libc::{pid_t, syscall, waitpid, SYS_tgkill, __WALL, ESRCH, SIGKILL, WNOHANG};
 use nix::errno::errno;
+use std::{
+    ptr,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+/// How long to keep trying to detach from a task, or to wait for a killed
+/// zombie leader, before giving up on it. Tasks blocked in uninterruptible
+/// sleep (on NFS or FUSE, typically) can't be made to do anything until
+/// whatever they're waiting for completes.
+const STUCK_TASK_TIMEOUT: Duration = Duration::from_secs(3);
+const STUCK_TASK_RETRY_INTERVAL: Duration = Duration::from_millis(10);
 
 /// Forwarded method definition
 ///
 pub(super) fn kill_all_tasks<S: Session>(sess: &S) {
+    let zombie_leaders: Vec<pid_t> = sess
+        .thread_group_map()
+        .values()
+        .filter_map(|tg| {
+            let tg = tg.upgrade()?;
+            let tg = tg.borrow();
+            if tg.zombie_leader {
+                Some(tg.real_tgid)
+            } else {
+                None
+            }
+        })
+        .collect();
+    let mut killed_tgids = Vec::new();
     for (_, t) in sess.task_map.borrow().iter() {
         if !t.borrow().is_stopped {
             // During recording we might be aborting the recording, in which case
@@ -53,6 +79,7 @@ pub(super) fn kill_all_tasks<S: Session>(sess: &S) {
         t.borrow_mut().set_regs(&r);
         t.borrow_mut().flush_regs();
         let mut result: isize;
+        let deadline = Instant::now() + STUCK_TASK_TIMEOUT;
         loop {
             // We have observed this failing with an ESRCH when the thread clearly
             // still exists and is ptraced. Retrying the PTRACE_DETACH seems to
@@ -69,6 +96,15 @@ pub(super) fn kill_all_tasks<S: Session>(sess: &S) {
             if result >= 0 {
                 break;
             }
+            // Or because it's in uninterruptible sleep and not in a ptrace
+            // stop anymore. The SIGKILL below will get it when it wakes up.
+            if Instant::now() >= deadline {
+                note_stuck_task(t.borrow().tid, "detaching from");
+                break;
+            }
+            if is_uninterruptible_sleep(t.borrow().tid) {
+                sleep(STUCK_TASK_RETRY_INTERVAL);
+            }
         }
     }
     while !sess.task_map.borrow().is_empty() {
@@ -91,10 +127,65 @@ pub(super) fn kill_all_tasks<S: Session>(sess: &S) {
                 syscall(SYS_tgkill, t.borrow().real_tgid(), t.borrow().tid, SIGKILL);
             }
             t.borrow().thread_group().destabilize();
+            killed_tgids.push(t.borrow().real_tgid());
         }
         // NOTE: It is NOT necessary to call destroy() on the task here.
     }
 
+    // A leader that exited before the rest of its threads can be reaped once
+    // the SIGKILL has taken them down too. Otherwise waiting would block.
+    for tgid in zombie_leaders {
+        if killed_tgids.contains(&tgid) {
+            log!(LogDebug, "reaping zombie leader {} ...", tgid);
+            reap_with_timeout(tgid);
+        }
+    }
+
+    forget_vms_and_thread_groups(sess);
+}
+
+/// Wait for `pid` to exit and reap it, for up to `STUCK_TASK_TIMEOUT`.
+/// A task in uninterruptible sleep ignores SIGKILL until it wakes up, and
+/// blocking on it would hang rd for as long.
+fn reap_with_timeout(pid: pid_t) {
+    let deadline = Instant::now() + STUCK_TASK_TIMEOUT;
+    // 0 means it hasn't exited yet. Anything else, including errors
+    // like ECHILD for a process that's already been reaped, means there's
+    // nothing to wait for.
+    while unsafe { waitpid(pid, ptr::null_mut(), __WALL | WNOHANG) } == 0 {
+        if Instant::now() >= deadline {
+            note_stuck_task(pid, "reaping");
+            return;
+        }
+        sleep(STUCK_TASK_RETRY_INTERVAL);
+    }
+}
+
+/// Tell the user rd is leaving `tid` behind, rather than hanging while
+/// `doing` it.
+fn note_stuck_task(tid: pid_t, doing: &str) {
+    if is_uninterruptible_sleep(tid) {
+        log!(
+            LogWarn,
+            "Gave up {} {}: it's in uninterruptible sleep, probably waiting on NFS or \
+             FUSE. Its SIGKILL takes effect once that operation finishes.",
+            doing,
+            tid
+        );
+    } else {
+        log!(
+            LogWarn,
+            "Gave up {} {} after {:?}",
+            doing,
+            tid,
+            STUCK_TASK_TIMEOUT
+        );
+    }
+}
+
+/// Forget the address spaces and thread groups of `sess` once all its tasks
+/// are gone.
+pub(super) fn forget_vms_and_thread_groups<S: Session>(sess: &S) {
     // Manually clean up the vm map and thread group map
     // We have to do this ourselves because the session is probably
     // getting drop()-ed and the thread group and address spaces would
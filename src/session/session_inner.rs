@@ -1,6 +1,9 @@
 use crate::{
     bindings::signal::siginfo_t,
-    session::{address_space::WatchConfig, task::TaskSharedWeakPtr},
+    session::{
+        address_space::{WatchConfig, WatchValues},
+        task::TaskSharedWeakPtr,
+    },
 };
 
 #[derive(Clone)]
@@ -12,6 +15,9 @@ pub struct BreakStatus {
     /// List of watchpoints hit; any watchpoint hit causes a stop after the
     /// instruction that triggered the watchpoint has completed.
     pub watchpoints_hit: Vec<WatchConfig>,
+    /// The contents of each range in `watchpoints_hit` before and after the
+    /// triggering instruction, so debuggers don't have to read them back.
+    pub watchpoint_values: Vec<WatchValues>,
     /// When non-`None`, we stopped because a signal was delivered to `task`.
     pub signal: Option<Box<siginfo_t>>,
     /// True when we stopped because we hit a software breakpoint at `task`'s
@@ -36,6 +42,7 @@ impl BreakStatus {
             approaching_ticks_target: false,
             task_exit: false,
             watchpoints_hit: vec![],
+            watchpoint_values: vec![],
             signal: None,
         }
     }
@@ -80,10 +87,12 @@ pub fn is_singlestep(command: RunCommand) -> bool {
 pub mod session_inner {
     use super::{is_singlestep, BreakStatus, RunCommand};
     use crate::{
+        deferred_work::DeferredWorkQueue,
         flags::Flags,
         log::LogLevel::LogDebug,
         perf_counters::{self, PerfCounters, TicksSemantics},
         remote_ptr::{RemotePtr, Void},
+        scm_rights::InFlightFds,
         scoped_fd::ScopedFd,
         session::{
             address_space::{
@@ -96,9 +105,11 @@ pub mod session_inner {
                 TaskSharedPtr,
                 TaskSharedWeakPtr,
             },
+            Session,
             SessionSharedWeakPtr,
         },
         taskish_uid::{AddressSpaceUid, ThreadGroupUid},
+        temp_resources::TempResources,
         thread_group::{ThreadGroup, ThreadGroupSharedPtr, ThreadGroupSharedWeakPtr},
         ticks::Ticks,
         util::cpuid_faulting_works,
@@ -114,8 +125,18 @@ pub mod session_inner {
         ffi::{OsStr, OsString},
         os::unix::ffi::OsStringExt,
         rc::Rc,
+        sync::atomic::{AtomicU32, Ordering},
     };
 
+    /// Source of `SessionInner::unique_id()`. Apart from this, what sessions
+    /// share process-wide is either fixed facts about the machine (the PMU,
+    /// page size, etc.) or rd's own resources, like the temp dir in
+    /// `temp_resources` and whether `util` has found process_vm_readv() usable.
+    /// Everything about the tracees lives in the session itself so that any
+    /// number of sessions (e.g. several replays of the same trace) can be used
+    /// side by side.
+    static NEXT_SESSION_ID: AtomicU32 = AtomicU32::new(1);
+
     /// AddressSpaces and ThreadGroups are indexed by their first task's TaskUid
     /// (effectively), so that if the first task dies and its tid is recycled,
     /// we don't get confused. TaskMap is indexed by tid since there can never be
@@ -124,7 +145,7 @@ pub mod session_inner {
     pub type TaskMap = BTreeMap<pid_t, TaskSharedPtr>;
     pub type ThreadGroupMap = HashMap<ThreadGroupUid, ThreadGroupSharedWeakPtr>;
 
-    #[derive(Copy, Clone, Eq, PartialEq)]
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
     pub enum PtraceSyscallSeccompOrdering {
         SyscallBeforeSeccomp,
         SeccompBeforeSyscall,
@@ -254,6 +275,51 @@ pub mod session_inner {
             tg
         }
 
+        /// Identifies this session among all the sessions created by this process,
+        /// including clones and checkpoints. Never reused.
+        pub fn unique_id(&self) -> u32 {
+            self.unique_id_
+        }
+
+        /// Where to create files that this session's tracees open by name.
+        /// They should be unlinked as soon as the tracee has them open;
+        /// anything left over is removed with the session.
+        pub fn temp_resources(&self) -> &TempResources {
+            &self.temp_resources
+        }
+
+        /// See `scm_rights`.
+        pub fn in_flight_fds(&self) -> &InFlightFds {
+            &self.in_flight_fds
+        }
+
+        /// Queue `work` to run at the next safe point, i.e. when rd is between
+        /// task events and holds no borrows of any task of this session.
+        ///
+        /// Use this from code handling one task's event that needs to change
+        /// another task in a way that can wait until the event is done (e.g.
+        /// the effects of ptrace requests on the tracee), instead of borrowing
+        /// the other task while the current one is borrowed. Work is run in
+        /// the order it was queued.
+        pub fn defer<F: FnOnce(&dyn Session) + 'static>(&self, work: F) {
+            self.deferred_work.push(work);
+        }
+
+        pub fn has_deferred_work(&self) -> bool {
+            !self.deferred_work.is_empty()
+        }
+
+        /// Run all queued deferred work, including any work queued while doing
+        /// so. Only call this at a safe point.
+        pub fn run_deferred_work(&self) {
+            if self.deferred_work.is_empty() {
+                return;
+            }
+            let sess = self.weak_self.upgrade().unwrap();
+            sess.assert_no_tasks_borrowed();
+            self.deferred_work.run(&**sess);
+        }
+
         pub fn next_task_serial(&self) -> u32 {
             let val = self.next_task_serial_.get();
             self.next_task_serial_.set(val + 1);
@@ -331,6 +397,10 @@ pub mod session_inner {
             self.syscall_seccomp_ordering_.get()
         }
 
+        pub fn set_syscall_seccomp_ordering(&self, ordering: PtraceSyscallSeccompOrdering) {
+            self.syscall_seccomp_ordering_.set(ordering)
+        }
+
         pub fn has_cpuid_faulting() -> bool {
             !Flags::get().disable_cpuid_faulting && cpuid_faulting_works()
         }
@@ -351,6 +421,7 @@ pub mod session_inner {
         }
 
         pub(in super::super) fn new() -> SessionInner {
+            let unique_id = NEXT_SESSION_ID.fetch_add(1, Ordering::SeqCst);
             let s = SessionInner {
                 weak_self: Default::default(),
                 vm_map: Default::default(),
@@ -361,13 +432,17 @@ pub mod session_inner {
                 tracee_socket: Default::default(),
                 tracee_socket_fd_number: Cell::new(-1),
                 next_task_serial_: Cell::new(1),
+                unique_id_: unique_id,
                 spawned_task_error_fd_: Default::default(),
                 syscall_seccomp_ordering_: Default::default(),
                 ticks_semantics_: PerfCounters::default_ticks_semantics(),
                 done_initial_exec_: Default::default(),
                 visible_execution_: true,
+                deferred_work: Default::default(),
+                temp_resources: TempResources::new(unique_id),
+                in_flight_fds: InFlightFds::new(),
             };
-            log!(LogDebug, "Session @TODO unique identifier created");
+            log!(LogDebug, "Session {} created", s.unique_id_);
             s
         }
 
@@ -475,7 +550,9 @@ pub mod session_inner {
             break_status: &mut BreakStatus,
         ) {
             self.assert_fully_initialized();
-            break_status.watchpoints_hit = t.vm().consume_watchpoint_changes();
+            let vm = t.vm();
+            break_status.watchpoint_values = vm.changed_watchpoint_values();
+            break_status.watchpoints_hit = vm.consume_watchpoint_changes();
         }
 
         /// XXX Move CloneCompletion/CaptureState etc to ReplayTask/ReplaySession
@@ -545,6 +622,7 @@ pub mod session_inner {
         pub(in super::super) tracee_socket: Rc<RefCell<ScopedFd>>,
         pub(in super::super) tracee_socket_fd_number: Cell<i32>,
         pub(in super::super) next_task_serial_: Cell<u32>,
+        pub(in super::super) unique_id_: u32,
         // @TODO Should this be an Option?
         pub(in super::super) spawned_task_error_fd_: RefCell<ScopedFd>,
 
@@ -558,6 +636,15 @@ pub mod session_inner {
 
         /// True while the execution of this session is visible to users.
         pub(in super::super) visible_execution_: bool,
+
+        /// See `defer()`.
+        pub(in super::super) deferred_work: DeferredWorkQueue<dyn Session>,
+
+        /// Shared memory segments and scratch files for this session's tracees.
+        pub(in super::super) temp_resources: TempResources,
+
+        /// Fds tracees have sent with SCM_RIGHTS and not yet received.
+        pub(in super::super) in_flight_fds: InFlightFds,
     }
 
     impl Default for SessionInner {
@@ -29,9 +29,9 @@ use crate::{
         },
     },
     util::{is_zombie_process, to_timeval},
-    wait_status::{MaybeStopSignal, WaitStatus},
+    wait_status::WaitStatus,
 };
-use libc::{pid_t, waitpid, ENOSYS, SIGSTOP, SIGTRAP};
+use libc::{pid_t, waitpid, ENOSYS};
 use nix::errno::errno;
 use std::{
     cell::RefCell,
@@ -47,6 +47,7 @@ use task_inner::TrapReasons;
 pub mod record_task;
 pub mod replay_task;
 pub mod task_common;
+pub mod task_handle;
 pub mod task_inner;
 
 pub type TaskSharedPtr = Rc<RefCell<Box<dyn Task>>>;
@@ -203,7 +204,7 @@ pub trait Task: DerefMut<Target = TaskInner> {
             }
             ed_assert!(self, !self.maybe_ptrace_event().is_ptrace_event());
             if self.session().is_recording() && self.maybe_group_stop_sig().is_sig() {
-                self.as_record_task().unwrap().stash_group_stop();
+                self.as_record_task_mut().unwrap().stash_group_stop();
                 continue;
             }
 
@@ -227,7 +228,7 @@ pub trait Task: DerefMut<Target = TaskInner> {
             {
                 continue;
             }
-            self.as_record_task().unwrap().stash_sig();
+            self.as_record_task_mut().unwrap().stash_sig();
         }
     }
 
@@ -271,7 +272,7 @@ pub trait Task: DerefMut<Target = TaskInner> {
                 continue;
             }
             ed_assert!(self, self.session().is_recording());
-            self.as_record_task().unwrap().stash_sig();
+            self.as_record_task_mut().unwrap().stash_sig();
         }
         true
     }
@@ -335,8 +336,38 @@ pub trait Task: DerefMut<Target = TaskInner> {
 
     /// Return true if the status of this has changed, but don't
     /// block.
-    fn try_wait(&self) -> bool {
-        unimplemented!()
+    fn try_wait(&mut self) -> bool {
+        if self.wait_unexpected_exit() {
+            return true;
+        }
+
+        let mut raw_status: i32 = 0;
+        let ret = unsafe {
+            waitpid(
+                self.tid,
+                &mut raw_status,
+                libc::WNOHANG | libc::__WALL | libc::WSTOPPED,
+            )
+        };
+        ed_assert!(
+            self,
+            ret >= 0,
+            "waitpid({}, NOHANG) failed with {}",
+            self.tid,
+            ret
+        );
+        log!(
+            LogDebug,
+            "waitpid({}, NOHANG) returns {}, status {}",
+            self.tid,
+            ret,
+            WaitStatus::new(raw_status)
+        );
+        if ret == self.tid {
+            self.did_waitpid(WaitStatus::new(raw_status));
+            return true;
+        }
+        false
     }
 
     /// Block until the status of this changes. wait() expects the wait to end
@@ -447,7 +478,7 @@ pub trait Task: DerefMut<Target = TaskInner> {
 
         if sent_wait_interrupt {
             log!(LogWarn, "Forced to PTRACE_INTERRUPT tracee");
-            if !is_signal_triggered_by_ptrace_interrupt(status.maybe_group_stop_sig()) {
+            if !status.is_ptrace_interrupt_stop() {
                 log!(
                     LogWarn,
                     "  PTRACE_INTERRUPT raced with another event {:?}",
@@ -511,12 +542,6 @@ pub trait Task: DerefMut<Target = TaskInner> {
     fn set_thread_area(&mut self, tls: RemotePtr<user_desc>);
 }
 
-fn is_signal_triggered_by_ptrace_interrupt(group_stop_sig: MaybeStopSignal) -> bool {
-    // We sometimes see SIGSTOP at interrupts, though the
-    // docs don't mention that.
-    group_stop_sig == SIGTRAP || group_stop_sig == SIGSTOP
-}
-
 fn is_singlestep_resume(request: ResumeRequest) -> bool {
     request == ResumeRequest::ResumeSinglestep || request == ResumeRequest::ResumeSysemuSinglestep
 }
@@ -210,39 +210,70 @@ pub enum SignalDisposition {
 pub mod record_task {
     use super::*;
     use crate::{
-        bindings::{kernel::user_desc, signal::siginfo_t},
-        event::{Event, EventType, SignalDeterministic, SignalResolvedDisposition},
-        kernel_abi::{common::preload_interface::syscallbuf_record, SupportedArch},
+        bindings::{
+            kernel::user_desc,
+            ptrace::{PTRACE_EVENT_CLONE, PTRACE_GETEVENTMSG, PTRACE_SETSIGINFO},
+            signal::siginfo_t,
+        },
+        event::{
+            Event,
+            EventType,
+            SignalDeterministic,
+            SignalResolvedDisposition,
+            SyscallEventData,
+            SyscallState,
+            SyscallbufFlushEventData,
+        },
+        exec_target::{exec_target, ExecTarget},
+        kernel_abi::{
+            common::preload_interface::{syscallbuf_hdr, syscallbuf_record},
+            is_execve_syscall,
+            is_execveat_syscall,
+            is_restart_syscall_syscall,
+            SupportedArch,
+        },
+        kernel_metadata::syscall_name,
         kernel_supplement::sig_set_t,
-        registers::Registers,
+        log::LogLevel::LogDebug,
+        record_attach::cmd_line,
+        record_signal::signal_deterministic,
+        record_syscall::{PreparedSyscall, SyscallEntryStop},
+        registers::{with_converted_registers, Registers},
         remote_code_ptr::RemoteCodePtr,
         remote_ptr::{RemotePtr, Void},
         scoped_fd::ScopedFd,
         session::{
-            address_space::memory_range::MemoryRange,
+            address_space::{address_space::AddressSpace, memory_range::MemoryRange},
             record_session::RecordSession,
+            session_common::SessionSharedPtr,
             task::{
                 task_common::{
+                    at_preload_init_common,
                     compute_trap_reasons,
                     destroy_buffers,
                     did_waitpid,
                     next_syscallbuf_record,
+                    on_syscall_exit,
                     open_mem_fd,
                     post_exec_for_exe,
                     post_exec_syscall,
+                    post_vm_clone_common,
                     read_bytes_fallible,
                     read_bytes_helper,
                     read_bytes_helper_for,
                     read_c_str,
+                    read_c_str_fallible,
                     resume_execution,
                     set_thread_area,
                     stored_record_size,
                     syscallbuf_data_size,
+                    task_drop_common,
                     write_bytes,
                     write_bytes_helper,
+                    write_val_mem,
                 },
                 task_inner::{
-                    task_inner::{CloneReason, TaskInner, WriteFlags},
+                    task_inner::{CloneReason, PtraceData, TaskInner, WriteFlags},
                     CloneFlags,
                     ResumeRequest,
                     TicksRequest,
@@ -253,21 +284,63 @@ pub mod record_task {
             },
         },
         ticks::Ticks,
-        trace::{trace_frame::FrameTime, trace_writer::TraceWriter},
+        trace::{
+            trace_frame::FrameTime,
+            trace_task_event::TraceTaskEvent,
+            trace_writer::{MappingOrigin, RecordInTrace, TraceWriter},
+        },
+        util::{
+            default_action,
+            read_proc_status_fields,
+            u8_raw_slice,
+            u8_raw_slice_mut,
+            SignalAction,
+        },
         wait_status::WaitStatus,
     };
-    use libc::pid_t;
+    use libc::{pid_t, AT_FDCWD, PATH_MAX, PR_TSC_ENABLE, SIGCHLD, SIGKILL, SIGSTOP};
+    use nix::sys::mman::ProtFlags;
+    use owning_ref::OwningHandle;
     use std::{
-        cell::RefCell,
+        cell::{Ref, RefCell, RefMut},
+        cmp::min,
         collections::{HashSet, VecDeque},
         ffi::{CString, OsStr},
+        fs::{read, read_link, read_to_string},
         ops::{Deref, DerefMut},
+        os::unix::ffi::OsStrExt,
+        path::Path,
         rc::{Rc, Weak},
+        slice,
     };
 
+    fn is_unstoppable_signal(sig: i32) -> bool {
+        sig == SIGKILL || sig == SIGSTOP
+    }
+
+    fn signal_bit(sig: i32) -> sig_set_t {
+        1 << (sig - 1)
+    }
+
+    /// Parse a hex signal mask like the SigBlk: line in /proc/<tid>/status.
+    fn parse_sig_set(mask: &OsStr) -> sig_set_t {
+        let s = mask.to_str().unwrap().trim();
+        sig_set_t::from_str_radix(s, 16).unwrap()
+    }
+
+    fn parent_pid_of(tid: pid_t) -> pid_t {
+        let results = read_proc_status_fields(tid, &[b"PPid"]).unwrap();
+        results[0]
+            .to_str()
+            .unwrap()
+            .trim()
+            .parse::<pid_t>()
+            .unwrap()
+    }
+
     pub struct StashedSignal {
-        siginfo: siginfo_t,
-        deterministic: SignalDeterministic,
+        pub siginfo: siginfo_t,
+        pub deterministic: SignalDeterministic,
     }
 
     #[derive(Copy, Clone, Eq, PartialEq)]
@@ -380,6 +453,12 @@ pub mod record_task {
         pub delay_syscallbuf_reset_for_seccomp_trap: bool,
         /// Value to return from PR_GET_SECCOMP
         pub prctl_seccomp_status: u8,
+        /// The entry stop we processed for the syscall this task is in, if any.
+        /// The kernel may report a second entry stop for the same syscall.
+        pub syscall_entry_stop: Option<SyscallEntryStop>,
+        /// The entry registers of a sleeping syscall that was interrupted with
+        /// ERESTART_RESTARTBLOCK, for when restart_syscall(2) continues it.
+        pub restart_block_regs: Option<Registers>,
 
         /// Mirrored kernel state
         /// This state agrees with kernel-internal values
@@ -425,6 +504,26 @@ pub mod record_task {
         pub next_pmc_interrupt_is_for_user: bool,
 
         pub did_record_robust_futex_changes: bool,
+
+        /// What the execve()/execveat() this task is currently in is going to
+        /// run. Computed at syscall entry by `prepare_exec()`, consumed by
+        /// `post_exec()`.
+        pub exec_target: Option<ExecTarget>,
+
+        /// The vfork() child this task is suspended for. The kernel doesn't let
+        /// it return from vfork() until the child has exec'd or exited, so the
+        /// scheduler leaves it alone until then.
+        pub vfork_child: Option<pid_t>,
+
+        /// What `rec_prepare_syscall()` did to the syscall this task is in
+        /// that has to be finished at its exit.
+        pub prepared_syscall: Option<PreparedSyscall>,
+    }
+
+    impl Drop for RecordTask {
+        fn drop(&mut self) {
+            task_drop_common(self);
+        }
     }
 
     impl Deref for RecordTask {
@@ -494,16 +593,20 @@ pub mod record_task {
             Some(self)
         }
 
-        fn on_syscall_exit(&mut self, _syscallno: i32, _arch: SupportedArch, _regs: &Registers) {
-            unimplemented!()
+        fn on_syscall_exit(&mut self, syscallno: i32, arch: SupportedArch, regs: &Registers) {
+            on_syscall_exit(self, syscallno, arch, regs);
+            with_converted_registers(regs, arch, |regs| {
+                rd_arch_function!(self, on_syscall_exit_arch, arch, syscallno, regs)
+            })
         }
 
-        fn did_wait(&mut self) {
-            unimplemented!()
+        fn own_namespace_tid(&self) -> pid_t {
+            self.own_namespace_rec_tid
         }
 
+        // Forwarded method
         fn at_preload_init(&mut self) {
-            unimplemented!()
+            at_preload_init_common(self)
         }
 
         /// Forwarded method
@@ -567,11 +670,30 @@ pub mod record_task {
 
         fn post_vm_clone(
             &mut self,
-            _reason: CloneReason,
-            _flags: CloneFlags,
-            _origin: &mut dyn Task,
+            reason: CloneReason,
+            flags: CloneFlags,
+            origin: &mut dyn Task,
         ) -> bool {
-            unimplemented!()
+            if post_vm_clone_common(self, reason, flags, origin) {
+                let km = self
+                    .vm()
+                    .mapping_of(AddressSpace::preload_thread_locals_start())
+                    .unwrap()
+                    .map
+                    .clone();
+                let mode = self.trace_writer_mut().write_mapped_region(
+                    self,
+                    &km,
+                    &km.fake_stat(),
+                    &[],
+                    Some(MappingOrigin::RdBufferMapping { slots_skipped: 0 }),
+                    None,
+                );
+                ed_assert!(self, mode == RecordInTrace::DontRecordInTrace);
+                true
+            } else {
+                false
+            }
         }
 
         // Forwarded method
@@ -584,12 +706,82 @@ pub mod record_task {
         /// Every Task owned by a RecordSession is a RecordTask. Functionality that
         /// only applies during recording belongs here.
         pub fn new(
-            _session: &RecordSession,
-            _tid: pid_t,
-            _serial: u32,
-            _a: SupportedArch,
+            session: &RecordSession,
+            tid: pid_t,
+            serial: u32,
+            a: SupportedArch,
         ) -> RecordTask {
-            unimplemented!()
+            let mut t = RecordTask {
+                task_inner: TaskInner::new(session, tid, None, serial, a),
+                ticks_at_last_recorded_syscall_exit: 0,
+                registers_at_start_of_last_timeslice: Registers::new(a),
+                time_at_start_of_last_timeslice: 0,
+                priority: 0,
+                in_round_robin_queue: false,
+                emulated_ptracer: None,
+                emulated_ptrace_tracees: HashSet::new(),
+                emulated_ptrace_event_msg: 0,
+                saved_ptrace_siginfos: Vec::new(),
+                emulated_stop_code: Default::default(),
+                emulated_ptrace_options: None,
+                emulated_ptrace_cont_command: None,
+                emulated_stop_pending: false,
+                emulated_ptrace_sigchld_pending: false,
+                emulated_sigchld_pending: false,
+                emulated_ptrace_seized: false,
+                emulated_ptrace_queued_exit_stop: false,
+                in_wait_type: WaitType::WaitTypeNone,
+                in_wait_pid: 0,
+                sighandlers: Default::default(),
+                emulated_stop_type: EmulatedStopType::NotStopped,
+                blocked_sigs_dirty: true,
+                blocked_sigs: 0,
+                syscallbuf_blocked_sigs_generation: 0,
+                syscallbuf_code_layout: SyscallbufCodeLayout {
+                    syscallbuf_code_start: RemoteCodePtr::null(),
+                    syscallbuf_code_end: RemoteCodePtr::null(),
+                    get_pc_thunks_start: RemoteCodePtr::null(),
+                    get_pc_thunks_end: RemoteCodePtr::null(),
+                    syscallbuf_final_exit_instruction: RemoteCodePtr::null(),
+                },
+                desched_fd: ScopedFd::new(),
+                flushed_num_rec_bytes: 0,
+                flushed_syscallbuf: false,
+                delay_syscallbuf_reset_for_desched: false,
+                delay_syscallbuf_reset_for_seccomp_trap: false,
+                prctl_seccomp_status: 0,
+                syscall_entry_stop: None,
+                restart_block_regs: None,
+                robust_futex_list: RemotePtr::null(),
+                robust_futex_list_len: 0,
+                tid_futex: RemotePtr::null(),
+                own_namespace_rec_tid: tid,
+                exit_code: 0,
+                termination_signal: None,
+                tsc_mode: PR_TSC_ENABLE,
+                cpuid_mode: 1,
+                pending_events: VecDeque::new(),
+                stashed_signals: VecDeque::new(),
+                stashed_signals_blocking_more_signals: false,
+                stashed_group_stop: false,
+                break_at_syscallbuf_traced_syscalls: false,
+                break_at_syscallbuf_untraced_syscalls: false,
+                break_at_syscallbuf_final_instruction: false,
+                next_pmc_interrupt_is_for_user: false,
+                did_record_robust_futex_changes: false,
+                exec_target: None,
+                vfork_child: None,
+                prepared_syscall: None,
+            };
+            t.push_event(Event::sentinel());
+            if session.tasks().is_empty() {
+                // The very first task we fork inherits the signal
+                // dispositions of the current OS process (which should all be
+                // default at this point, but ...).  From there on, new tasks
+                // will transitively inherit from this first task.
+                t.sighandlers.borrow_mut().init_from_current_process();
+            }
+            t
         }
 
         // @TODO clone_task() ??
@@ -611,12 +803,112 @@ pub mod record_task {
         pub fn init_buffers(&self) {
             unimplemented!()
         }
-        pub fn post_exec(&self) {
-            unimplemented!()
+        /// Call at entry to execve() or execveat() to work out what the exec is
+        /// going to run. This has to happen before the exec because an
+        /// execveat() dirfd may be close-on-exec and the cwd used to resolve
+        /// relative paths can't be observed afterwards.
+        pub fn prepare_exec(&mut self) {
+            let arch = self.arch();
+            let sys = self.regs_ref().original_syscallno() as i32;
+            let (dirfd, path_addr, flags) = if is_execveat_syscall(sys, arch) {
+                (
+                    self.regs_ref().arg1_signed() as i32,
+                    self.regs_ref().arg2(),
+                    self.regs_ref().arg5() as i32,
+                )
+            } else {
+                debug_assert!(is_execve_syscall(sys, arch));
+                (AT_FDCWD, self.regs_ref().arg1(), 0)
+            };
+            // A bad path makes the exec fail; `post_exec()` copes without a target.
+            let path = match read_c_str_fallible(
+                self,
+                RemotePtr::new_from_val(path_addr),
+                Some(PATH_MAX as usize - 1),
+            ) {
+                Ok(path) => path,
+                Err(e) => {
+                    log!(LogDebug, "{} execs a bad path: {:?}", self.tid, e);
+                    self.exec_target = None;
+                    return;
+                }
+            };
+            let target = exec_target(self.tid, dirfd, OsStr::from_bytes(path.as_bytes()), flags);
+            log!(
+                LogDebug,
+                "{} about to exec {:?} (image {:?})",
+                self.tid,
+                target.file_name,
+                target.exe_image
+            );
+            self.exec_target = Some(target);
         }
 
-        pub fn trace_writer(&self) -> &TraceWriter {
-            unimplemented!()
+        /// Call after a successful execve()/execveat().
+        ///
+        /// The new address space gets the image that was actually loaded: for
+        /// scripts that's the (innermost) interpreter, not the script. We fall
+        /// back to /proc/<tid>/exe if `prepare_exec()` didn't run or couldn't
+        /// see the file. prname is taken from the kernel, which derives it from
+        /// the name the exec was invoked with rather than the loaded image.
+        pub fn post_exec(&mut self) {
+            let exe_image = match self.exec_target.as_ref() {
+                Some(target) if Path::new(&target.exe_image).is_absolute() => {
+                    target.exe_image.clone()
+                }
+                _ => read_link(format!("/proc/{}/exe", self.tid))
+                    .map(|p| p.into_os_string())
+                    .unwrap_or_default(),
+            };
+            post_exec_for_exe(self, &exe_image);
+            if let Ok(comm) = read(format!("/proc/{}/comm", self.tid)) {
+                let len = if comm.last() == Some(&b'\n') {
+                    comm.len() - 1
+                } else {
+                    comm.len()
+                };
+                self.prname = OsStr::from_bytes(&comm[0..len]).to_owned();
+            }
+            // The patches are recorded with the exec event. Replay restores them
+            // from there when it applies the final data records of the exec.
+            let vm = self.vm_shr_ptr();
+            if let Some(patcher) = vm.monkeypatcher() {
+                patcher.patch_after_exec(self);
+            }
+        }
+
+        /// The TraceTaskEvent to record for the exec that just completed.
+        /// The command line is read back from the kernel so that it reflects
+        /// any argv rewriting done when running a script.
+        pub fn exec_task_event(&mut self, exe_base: RemotePtr<Void>) -> TraceTaskEvent {
+            let target = self.exec_target.take();
+            let file_name = match &target {
+                Some(target) => target.file_name.clone(),
+                None => self.vm().exe_image().to_owned(),
+            };
+            TraceTaskEvent::for_exec(self.tid, file_name, cmd_line(self.tid), exe_base)
+        }
+
+        /// Whether this task is a vfork() parent whose child hasn't exec'd or
+        /// exited yet.
+        pub fn is_suspended_for_vfork(&self) -> bool {
+            self.vfork_child.is_some()
+        }
+
+        pub fn trace_writer(&self) -> OwningHandle<SessionSharedPtr, Ref<'_, TraceWriter>> {
+            let sess = self.session();
+            let owning_handle = OwningHandle::new_with_fn(sess, |o| {
+                unsafe { (*o).as_record() }.unwrap().trace_writer()
+            });
+            owning_handle
+        }
+
+        pub fn trace_writer_mut(&self) -> OwningHandle<SessionSharedPtr, RefMut<'_, TraceWriter>> {
+            let sess = self.session();
+            let owning_handle = OwningHandle::new_with_fn(sess, |o| {
+                unsafe { (*o).as_record() }.unwrap().trace_writer_mut()
+            });
+            owning_handle
         }
 
         /// Emulate 'tracer' ptracing this task.
@@ -699,89 +991,158 @@ pub mod record_task {
 
         /// Call this after `sig` is delivered to this task.  Emulate
         /// sighandler updates induced by the signal delivery.
-        pub fn signal_delivered(&self, _sig: i32) {
-            unimplemented!()
+        pub fn signal_delivered(&mut self, sig: i32) {
+            let arch = self.arch();
+            let mut sighandlers = self.sighandlers.borrow_mut();
+            let h = sighandlers.get_mut(sig as usize);
+            if h.resethand {
+                reset_handler(h, arch);
+            }
         }
 
         /// Return true if `sig` is pending but hasn't been reported to ptrace yet
-        pub fn is_signal_pending(&self, _sig: i32) -> bool {
-            unimplemented!()
+        pub fn is_signal_pending(&self, sig: i32) -> bool {
+            let results = read_proc_status_fields(self.tid, &[b"SigPnd", b"ShdPnd"]).unwrap();
+            ed_assert!(self, results.len() == 2);
+            let mask_individual = parse_sig_set(&results[0]);
+            let mask_group = parse_sig_set(&results[1]);
+            (mask_individual | mask_group) & signal_bit(sig) != 0
         }
 
         /// Return true if there are any signals pending that are not blocked.
         pub fn has_any_actionable_signal(&self) -> bool {
-            unimplemented!()
+            let results =
+                read_proc_status_fields(self.tid, &[b"SigPnd", b"ShdPnd", b"SigBlk"]).unwrap();
+            ed_assert!(self, results.len() == 3);
+            let mask_individual = parse_sig_set(&results[0]);
+            let mask_group = parse_sig_set(&results[1]);
+            let mask_blocked = parse_sig_set(&results[2]);
+            (mask_individual | mask_group) & !mask_blocked != 0
         }
 
         /// Get all threads out of an emulated GROUP_STOP
-        pub fn emulate_sigcont(&self) {
-            unimplemented!()
+        pub fn emulate_sigcont(&mut self) {
+            // All threads in the process are resumed.
+            let others: Vec<_> = self
+                .thread_group()
+                .task_set()
+                .iter_except(self.weak_self_ptr())
+                .collect();
+            self.clear_emulated_stop();
+            for rc_t in others {
+                if let Some(rt) = rc_t.borrow_mut().as_record_task_mut() {
+                    rt.clear_emulated_stop();
+                }
+            }
+        }
+
+        fn clear_emulated_stop(&mut self) {
+            log!(
+                LogDebug,
+                "setting {} to NOT_STOPPED due to SIGCONT",
+                self.tid
+            );
+            self.clear_stashed_group_stop();
+            self.emulated_stop_pending = false;
+            self.emulated_stop_type = EmulatedStopType::NotStopped;
         }
 
         /// Return true if the disposition of `sig` in `table` isn't
         /// SIG_IGN or SIG_DFL, that is, if a user sighandler will be
         /// invoked when `sig` is received.
-        pub fn signal_has_user_handler(&self, _sig: i32) -> bool {
-            unimplemented!()
+        pub fn signal_has_user_handler(&self, sig: i32) -> bool {
+            self.sig_disposition(sig) == SignalDisposition::SignalHandler
         }
 
         /// If signal_has_user_handler(sig) is true, return the address of the
         /// user handler, otherwise return null.
-        pub fn get_signal_user_handler(&self, _sig: i32) -> RemoteCodePtr {
-            unimplemented!()
+        pub fn get_signal_user_handler(&self, sig: i32) -> RemoteCodePtr {
+            self.sighandlers
+                .borrow()
+                .get(sig as usize)
+                .get_user_handler()
+                .unwrap_or_else(RemoteCodePtr::null)
         }
 
         /// Return true if the signal handler for `sig` takes a &siginfo_t
         /// parameter.
-        pub fn signal_handler_takes_siginfo(&self, _sig: i32) -> bool {
-            unimplemented!()
+        pub fn signal_handler_takes_siginfo(&self, sig: i32) -> bool {
+            self.sighandlers.borrow().get(sig as usize).takes_siginfo
         }
 
         /// Return `sig`'s current sigaction. Returned as raw bytes since the
         /// data is architecture-dependent.
-        pub fn signal_action(&self, _sig: i32) -> &[u8] {
-            unimplemented!()
+        pub fn signal_action(&self, sig: i32) -> Vec<u8> {
+            self.sighandlers.borrow().get(sig as usize).sa.clone()
         }
 
         /// Return true iff `sig` is blocked for this.
-        pub fn is_sig_blocked(&self, _sig: i32) -> bool {
-            unimplemented!()
+        pub fn is_sig_blocked(&self, sig: i32) -> bool {
+            if is_unstoppable_signal(sig) {
+                return false;
+            }
+            let mask = if self.blocked_sigs_dirty {
+                self.read_sigmask_from_process()
+            } else {
+                self.blocked_sigs
+            };
+            mask & signal_bit(sig) != 0
         }
 
         /// Return true iff `sig` is SIG_IGN, or it's SIG_DFL and the
         /// default disposition is "ignore".
-        pub fn is_sig_ignored(&self, _sig: i32) -> bool {
-            unimplemented!()
+        pub fn is_sig_ignored(&self, sig: i32) -> bool {
+            if is_unstoppable_signal(sig) {
+                return false;
+            }
+            match self.sig_disposition(sig) {
+                SignalDisposition::SignalIgnore => true,
+                SignalDisposition::SignalDefault => default_action(sig) == SignalAction::Ignore,
+                SignalDisposition::SignalHandler => false,
+            }
         }
 
         /// Return the applications current disposition of `sig`.
-        pub fn sig_disposition(&self, _sig: i32) -> SignalDisposition {
-            unimplemented!()
+        pub fn sig_disposition(&self, sig: i32) -> SignalDisposition {
+            self.sighandlers.borrow().get(sig as usize).disposition()
         }
 
         /// Return the resolved disposition --- what this signal will actually do,
         /// taking into account the default behavior.
         pub fn sig_resolved_disposition(
             &self,
-            _sig: i32,
-            _deterministic: SignalDeterministic,
+            sig: i32,
+            deterministic: SignalDeterministic,
         ) -> SignalResolvedDisposition {
-            unimplemented!()
+            if self.is_fatal_signal(sig, deterministic) {
+                return SignalResolvedDisposition::DispositionFatal;
+            }
+            if self.signal_has_user_handler(sig) && !self.is_sig_blocked(sig) {
+                return SignalResolvedDisposition::DispositionUserHandler;
+            }
+            SignalResolvedDisposition::DispositionIgnored
         }
 
         /// Set the siginfo for the signal-stop of this.
-        pub fn set_siginfo(&self, _si: &siginfo_t) {
-            unimplemented!()
+        pub fn set_siginfo(&mut self, si: &siginfo_t) {
+            self.pending_siginfo = *si;
+            self.xptrace(
+                PTRACE_SETSIGINFO,
+                RemotePtr::null(),
+                PtraceData::ReadFrom(u8_raw_slice(si)),
+            );
         }
 
         /// Note that the task sigmask needs to be refetched.
-        pub fn invalidate_sigmask(&self) {
-            unimplemented!()
+        pub fn invalidate_sigmask(&mut self) {
+            self.blocked_sigs_dirty = true;
         }
 
         /// Reset the signal handler for this signal to the default.
-        pub fn did_set_sig_handler_default(&self, _sig: i32) {
-            unimplemented!()
+        pub fn did_set_sig_handler_default(&mut self, sig: i32) {
+            let arch = self.arch();
+            let mut sighandlers = self.sighandlers.borrow_mut();
+            reset_handler(sighandlers.get_mut(sig as usize), arch);
         }
 
         /// Check that our status for `sig` matches what's in /proc/<pid>/status.
@@ -807,48 +1168,169 @@ pub mod record_task {
         ///
         /// If the process unexpectedly died (due to SIGKILL), we don't
         /// stash anything.
-        pub fn stash_sig(&self) {
-            unimplemented!()
-        }
-        pub fn stash_synthetic_sig(&self, _si: &siginfo_t, _deterministic: SignalDeterministic) {
-            unimplemented!()
+        pub fn stash_sig(&mut self) {
+            let sig = self.maybe_stop_sig();
+            ed_assert!(self, sig.is_sig());
+            let si = self.get_siginfo().clone();
+            let deterministic = signal_deterministic(&si);
+            self.stash_synthetic_sig(&si, deterministic);
+        }
+        pub fn stash_synthetic_sig(&mut self, si: &siginfo_t, deterministic: SignalDeterministic) {
+            let sig = si.si_signo;
+            ed_assert!(self, sig > 0);
+            // Multiple non-RT signals (those below 32) coalesce.
+            if sig < 32 {
+                let maybe_pos = self
+                    .stashed_signals
+                    .iter()
+                    .position(|s| s.siginfo.si_signo == sig);
+                if let Some(pos) = maybe_pos {
+                    if deterministic == SignalDeterministic::DeterministicSig
+                        && self.stashed_signals[pos].deterministic
+                            == SignalDeterministic::NondeterministicSig
+                    {
+                        self.stashed_signals.remove(pos);
+                    } else {
+                        log!(
+                            LogDebug,
+                            "discarding stashed signal {} since we already have one pending",
+                            sig
+                        );
+                        return;
+                    }
+                }
+            }
+            let stashed = StashedSignal {
+                siginfo: si.clone(),
+                deterministic,
+            };
+            // Deterministic signals are raised by the instruction the task
+            // is at, so they go first.
+            if deterministic == SignalDeterministic::DeterministicSig {
+                self.stashed_signals.push_front(stashed);
+            } else {
+                self.stashed_signals.push_back(stashed);
+            }
+            self.stashed_signals_blocking_more_signals = true;
         }
         pub fn has_any_stashed_sig(&self) -> bool {
-            unimplemented!()
-        }
-        pub fn stashed_sig_not_synthetic_sigchld(&self) -> &siginfo_t {
-            unimplemented!()
-        }
-        pub fn has_stashed_sig(&self, _sig: i32) -> bool {
-            unimplemented!()
+            !self.stashed_signals.is_empty()
+        }
+        pub fn has_stashed_sig(&self, sig: i32) -> bool {
+            self.stashed_signals
+                .iter()
+                .any(|s| s.siginfo.si_signo == sig)
+        }
+        pub fn peek_stashed_sig_to_deliver(&self) -> Option<&StashedSignal> {
+            self.stashed_signals.front()
+        }
+        /// Remove the signal `peek_stashed_sig_to_deliver()` returned.
+        pub fn pop_stash_sig(&mut self) -> StashedSignal {
+            match self.stashed_signals.pop_front() {
+                Some(stashed) => stashed,
+                None => {
+                    ed_assert!(self, false, "No stashed signal to pop");
+                    unreachable!()
+                }
+            }
         }
-        pub fn peek_stashed_sig_to_deliver(&self) -> &StashedSignal {
-            unimplemented!()
+        pub fn stashed_signal_processed(&mut self) {
+            self.stashed_signals_blocking_more_signals = self.has_any_stashed_sig();
         }
-        pub fn pop_stash_sig(&self, _stashed: &StashedSignal) {
-            unimplemented!()
+        /// Take up to `max` of the stashed signals in `mask`, oldest first,
+        /// for a read() of a signalfd. Reading a signalfd dequeues signals
+        /// instead of delivering them, and rd has already dequeued these
+        /// from the kernel.
+        pub fn take_stashed_sigs_for_signalfd(
+            &mut self,
+            mask: sig_set_t,
+            max: usize,
+        ) -> Vec<siginfo_t> {
+            let mut taken = Vec::new();
+            let mut i = 0;
+            while i < self.stashed_signals.len() && taken.len() < max {
+                let sig = self.stashed_signals[i].siginfo.si_signo;
+                if mask & (1 << (sig - 1)) != 0 {
+                    taken.push(self.stashed_signals.remove(i).unwrap().siginfo);
+                } else {
+                    i += 1;
+                }
+            }
+            taken
         }
-        pub fn stashed_signal_processed(&self) {
-            unimplemented!()
+
+        /// Take all the stashed signals, oldest first.
+        pub fn take_stashed_signals(&mut self) -> Vec<siginfo_t> {
+            self.stashed_signals.drain(..).map(|s| s.siginfo).collect()
         }
 
         /// If a group-stop occurs at an inconvenient time, stash it and
         /// process it later.
-        pub fn stash_group_stop(&self) {
-            unimplemented!()
+        pub fn stash_group_stop(&mut self) {
+            self.stashed_group_stop = true;
         }
-        pub fn clear_stashed_group_stop(&self) {
-            unimplemented!()
+        pub fn clear_stashed_group_stop(&mut self) {
+            self.stashed_group_stop = false;
         }
         pub fn has_stashed_group_stop(&self) -> bool {
-            unimplemented!()
+            self.stashed_group_stop
         }
 
         /// Return true if the current state of this looks like the
         /// interrupted syscall at the top of our event stack, if there
         /// is one.
         pub fn is_syscall_restart(&self) -> bool {
-            unimplemented!()
+            if self.ev().event_type() != EventType::EvSyscallInterruption {
+                return false;
+            }
+            let syscallno = self.regs_ref().original_syscallno() as i32;
+            let syscall_arch = self.ev().syscall_event().arch();
+            let call_name = syscall_name(syscallno, syscall_arch);
+            log!(
+                LogDebug,
+                "  is syscall interruption of recorded {}? (now {})",
+                self.ev(),
+                call_name
+            );
+            // It's possible for the tracee to resume after a sighandler
+            // with a fresh syscall that happens to be the same as the one
+            // that was interrupted.  So we check here if the args are the
+            // same.
+            //
+            // Of course, it's possible (but less likely) for the tracee
+            // to incidentally resume with a fresh syscall that just
+            // happens to have the same *arguments* too.  But in that
+            // case, we would usually set up scratch buffers etc the same
+            // was as for the original interrupted syscall, so we just
+            // save a step here.
+            if is_restart_syscall_syscall(syscallno, syscall_arch) {
+                log!(LogDebug, "  (SYS_restart_syscall)");
+                return true;
+            }
+            if self.ev().syscall_event().number != syscallno {
+                log!(LogDebug, "  interrupted {} != {}", self.ev(), call_name);
+                return false;
+            }
+            let old_regs = &self.ev().syscall_event().regs;
+            let regs = self.regs_ref();
+            if old_regs.arg1() != regs.arg1()
+                || old_regs.arg2() != regs.arg2()
+                || old_regs.arg3() != regs.arg3()
+                || old_regs.arg4() != regs.arg4()
+                || old_regs.arg5() != regs.arg5()
+                || old_regs.arg6() != regs.arg6()
+            {
+                log!(
+                    LogDebug,
+                    "  regs different at interrupted {}: {} vs {}",
+                    call_name,
+                    old_regs,
+                    regs
+                );
+                return false;
+            }
+            log!(LogDebug, "  restart of {}", call_name);
+            true
         }
 
         /// Return true iff this is at an execution state where
@@ -860,7 +1342,17 @@ pub mod record_task {
         /// then delivering the signal may restart the first syscall
         /// and this method will return true.
         pub fn at_may_restart_syscall(&self) -> bool {
-            unimplemented!()
+            let depth = self.pending_events.len();
+            let maybe_prev_ev = if depth > 2 {
+                self.pending_events.get(depth - 2)
+            } else {
+                None
+            };
+            self.ev().event_type() == EventType::EvSyscallInterruption
+                || (self.ev().event_type() == EventType::EvSignalDelivery
+                    && maybe_prev_ev.map_or(false, |prev_ev| {
+                        prev_ev.event_type() == EventType::EvSyscallInterruption
+                    }))
         }
 
         /// Return true if this is at an arm-desched-event syscall.
@@ -880,7 +1372,12 @@ pub mod record_task {
         /// that the task is no longer possibly-blocked before resuming
         /// its execution.
         pub fn may_be_blocked(&self) -> bool {
-            unimplemented!()
+            (self.ev().event_type() == EventType::EvSyscall
+                && self.ev().syscall_event().state == SyscallState::ProcessingSyscall)
+                || self.emulated_stop_type != EmulatedStopType::NotStopped
+                || (self.ev().event_type() == EventType::EvSignalDelivery
+                    && self.ev().signal_event().disposition
+                        == SignalResolvedDisposition::DispositionFatal)
         }
 
         /// Returns true if it looks like this task has been spinning on an atomic
@@ -892,7 +1389,15 @@ pub mod record_task {
         /// Return true if this is within the syscallbuf library.  This
         /// *does not* imply that $ip is at a buffered syscall.
         pub fn is_in_syscallbuf(&self) -> bool {
-            unimplemented!()
+            if self.syscallbuf_child.is_null() {
+                return false;
+            }
+            let mut p = self.ip();
+            if self.is_in_traced_syscall() || self.is_in_untraced_syscall() {
+                p = p.decrement_by_syscall_insn_length(self.arch());
+            }
+            self.syscallbuf_code_layout.syscallbuf_code_start <= p
+                && p < self.syscallbuf_code_layout.syscallbuf_code_end
         }
 
         /// Shortcut to the most recent `pending_event->desched.rec` when
@@ -900,16 +1405,41 @@ pub mod record_task {
         /// Exists just so that clients don't need to dig around in the
         /// event stack to find this record.
         pub fn desched_rec(&self) -> RemotePtr<syscallbuf_record> {
-            unimplemented!()
+            if self.ev().is_syscall_event() {
+                self.ev()
+                    .syscall_event()
+                    .desched_rec
+                    .unwrap_or_else(RemotePtr::null)
+            } else if self.ev().event_type() == EventType::EvDesched {
+                self.ev().desched_event().rec
+            } else {
+                RemotePtr::null()
+            }
         }
 
         /// Returns true when the task is in a signal handler in an interrupted
         /// system call being handled by syscall buffering.
         pub fn running_inside_desched(&self) -> bool {
-            unimplemented!()
+            let desched_rec = self.desched_rec();
+            match self
+                .pending_events
+                .iter()
+                .find(|e| e.event_type() == EventType::EvDesched)
+            {
+                Some(e) => e.desched_event().rec != desched_rec,
+                None => false,
+            }
         }
+        /// Return the SECCOMP_RET_DATA of the filter result that caused the
+        /// current PTRACE_EVENT_SECCOMP stop.
         pub fn get_ptrace_eventmsg_seccomp_data(&self) -> u16 {
-            unimplemented!()
+            let mut data: usize = 0;
+            self.xptrace(
+                PTRACE_GETEVENTMSG,
+                RemotePtr::from(0usize),
+                PtraceData::WriteInto(u8_raw_slice_mut(&mut data)),
+            );
+            data as u16
         }
 
         /// Save tracee data to the trace.  `addr` is the address in
@@ -920,106 +1450,175 @@ pub mod record_task {
         /// If 'addr' is null then no record is written.
         /// DIFF NOTE: @TODO In the rr implementation ssize_t is being used instead of size_t
         /// for the record_* methods in many places. Why??
-        pub fn record_local(&self, _addr: RemotePtr<Void>, _buf: &[u8]) {
-            unimplemented!()
+        pub fn record_local(&mut self, addr: RemotePtr<Void>, buf: &[u8]) {
+            self.maybe_flush_syscallbuf();
+            if addr.is_null() {
+                return;
+            }
+            self.trace_writer_mut().write_raw(self.rec_tid, buf, addr);
         }
-        pub fn record_local_for<T>(_addr: RemotePtr<T>, _data: &T) {
-            unimplemented!()
+        pub fn record_local_for<T>(&mut self, addr: RemotePtr<T>, data: &T) {
+            let buf =
+                unsafe { slice::from_raw_parts(data as *const T as *const u8, size_of::<T>()) };
+            self.record_local(RemotePtr::cast(addr), buf);
         }
-        pub fn record_local_for_slice<T>(_addr: RemotePtr<T>, _buf: &[T]) {
-            unimplemented!()
+        pub fn record_local_for_slice<T>(&mut self, addr: RemotePtr<T>, data: &[T]) {
+            let buf = unsafe {
+                slice::from_raw_parts(data.as_ptr() as *const u8, data.len() * size_of::<T>())
+            };
+            self.record_local(RemotePtr::cast(addr), buf);
         }
 
-        pub fn record_remote(&self, _addr: RemotePtr<Void>, _num_bytes: usize) {
-            unimplemented!()
+        pub fn record_remote(&mut self, addr: RemotePtr<Void>, num_bytes: usize) {
+            self.maybe_flush_syscallbuf();
+            if addr.is_null() {
+                return;
+            }
+            if self.record_remote_by_local_map(addr, num_bytes) {
+                return;
+            }
+            let mut buf = vec![0u8; num_bytes];
+            self.read_bytes_helper(addr, &mut buf, None);
+            self.trace_writer_mut().write_raw(self.rec_tid, &buf, addr);
         }
-        pub fn record_remote_for<T>(_addr: RemotePtr<T>) {
-            unimplemented!()
+        pub fn record_remote_for<T>(&mut self, addr: RemotePtr<T>) {
+            self.record_remote(RemotePtr::cast(addr), size_of::<T>());
         }
-        pub fn record_remote_range(&mut self, _range: MemoryRange) {
-            unimplemented!()
+        pub fn record_remote_range(&mut self, range: MemoryRange) {
+            self.record_remote(range.start(), range.size());
         }
-        pub fn record_remote_range_fallible(&mut self, _range: MemoryRange) -> Result<usize, ()> {
-            unimplemented!()
+        pub fn record_remote_range_fallible(&mut self, range: MemoryRange) -> Result<usize, ()> {
+            self.record_remote_fallible(range.start(), range.size())
         }
 
         /// Record as much as we can of the bytes in this range. Will record only
         /// contiguous mapped data starting at `addr`.
         pub fn record_remote_fallible(
             &mut self,
-            _addr: RemotePtr<Void>,
-            _num_bytes: usize,
+            addr: RemotePtr<Void>,
+            num_bytes: usize,
         ) -> Result<usize, ()> {
-            unimplemented!()
+            self.maybe_flush_syscallbuf();
+            if self.record_remote_by_local_map(addr, num_bytes) {
+                return Ok(num_bytes);
+            }
+            let mut buf = Vec::new();
+            let mut result = Ok(0);
+            if !addr.is_null() {
+                buf.resize(num_bytes, 0u8);
+                result = self.read_bytes_fallible(addr, &mut buf);
+                buf.truncate(*result.as_ref().unwrap_or(&0));
+            }
+            self.trace_writer_mut().write_raw(self.rec_tid, &buf, addr);
+            result
         }
 
         /// Record as much as we can of the bytes in this range. Will record only
         /// contiguous mapped-writable data starting at `addr`.
-        pub fn record_remote_writable(&self, _addr: RemotePtr<Void>, _num_bytes: usize) {
-            unimplemented!()
+        pub fn record_remote_writable(&mut self, addr: RemotePtr<Void>, num_bytes: usize) {
+            let mut p = addr;
+            {
+                let vm = self.vm();
+                let mut seen_rd_mapping = false;
+                let mut mapping_count = 0;
+                while p < addr + num_bytes {
+                    let m = match vm.mapping_of(p) {
+                        Some(m) => m,
+                        None => break,
+                    };
+                    mapping_count += 1;
+                    if !m.flags.is_empty() {
+                        seen_rd_mapping = true;
+                    }
+                    if !m.map.prot().contains(ProtFlags::PROT_WRITE)
+                        || (seen_rd_mapping && mapping_count > 1)
+                    {
+                        break;
+                    }
+                    p = m.map.end();
+                }
+            }
+            let num_bytes = min(num_bytes, p - addr);
+            self.record_remote(addr, num_bytes);
         }
 
         /// Simple helper that attempts to use the local mapping to record if one
         /// exists
-        pub fn record_remote_by_local_map(
-            &self,
-            _addr: RemotePtr<Void>,
-            _num_bytes: usize,
-        ) -> bool {
-            unimplemented!()
+        pub fn record_remote_by_local_map(&self, addr: RemotePtr<Void>, num_bytes: usize) -> bool {
+            match self.vm().local_mapping(addr, num_bytes) {
+                Some(local) => {
+                    self.trace_writer_mut()
+                        .write_raw(self.rec_tid, &local[0..num_bytes], addr);
+                    true
+                }
+                None => false,
+            }
         }
 
         /// Save tracee data to the trace.  `addr` is the address in
         /// the address space of this task.
         /// If 'addr' is null then a zero-length record is written.
-        pub fn record_remote_even_if_null(&self, _addr: RemotePtr<Void>, _num_bytes: usize) {
-            unimplemented!()
+        pub fn record_remote_even_if_null(&mut self, addr: RemotePtr<Void>, num_bytes: usize) {
+            self.maybe_flush_syscallbuf();
+            if addr.is_null() {
+                self.trace_writer_mut().write_raw(self.rec_tid, &[], addr);
+                return;
+            }
+            self.record_remote(addr, num_bytes);
         }
-        pub fn record_remote_even_if_null_for<T>(_addr: RemotePtr<T>) {
-            unimplemented!()
+        pub fn record_remote_even_if_null_for<T>(&mut self, addr: RemotePtr<T>) {
+            self.record_remote_even_if_null(RemotePtr::cast(addr), size_of::<T>());
         }
 
         /// Manage pending events.  `push_event()` pushes the given
         /// event onto the top of the event stack.  The `pop_*()`
         /// helpers pop the event at top of the stack, which must be of
         /// the specified type.
-        pub fn push_event(&self, _ev: &Event) {
-            unimplemented!()
-        }
-        pub fn push_syscall_eventsyscallno(&self, _no: i32) {
-            unimplemented!()
+        pub fn push_event(&mut self, ev: Event) {
+            self.pending_events.push_back(ev);
+        }
+        pub fn push_syscall_event(&mut self, no: i32) {
+            let arch = self.detect_syscall_arch();
+            self.push_event(Event::new_syscall_event(SyscallEventData::new(no, arch)));
+        }
+        pub fn pop_event(&mut self, expected_type: EventType) {
+            ed_assert!(
+                self,
+                self.ev().event_type() == expected_type,
+                "Expected {} at the top of the event stack, found {}",
+                expected_type,
+                self.ev()
+            );
+            self.pending_events.pop_back();
         }
-        pub fn pop_eventexpected_type(&self) -> EventType {
-            unimplemented!()
+        pub fn pop_noop(&mut self) {
+            self.pop_event(EventType::EvNoop);
         }
-        pub fn pop_noop(&self) {
-            unimplemented!()
+        pub fn pop_desched(&mut self) {
+            self.pop_event(EventType::EvDesched);
         }
-        pub fn pop_desched(&self) {
-            unimplemented!()
+        pub fn pop_seccomp_trap(&mut self) {
+            self.pop_event(EventType::EvSeccompTrap);
         }
-        pub fn pop_seccomp_trap(&self) {
-            unimplemented!()
+        pub fn pop_signal_delivery(&mut self) {
+            self.pop_event(EventType::EvSignalDelivery);
         }
-        pub fn pop_signal_delivery(&self) {
-            unimplemented!()
+        pub fn pop_signal_handler(&mut self) {
+            self.pop_event(EventType::EvSignalHandler);
         }
-        pub fn pop_signal_handler(&self) {
-            unimplemented!()
-        }
-        pub fn pop_syscall(&self) {
-            unimplemented!()
+        pub fn pop_syscall(&mut self) {
+            self.pop_event(EventType::EvSyscall);
         }
-        pub fn pop_syscall_interruption(&self) {
-            unimplemented!()
+        pub fn pop_syscall_interruption(&mut self) {
+            self.pop_event(EventType::EvSyscallInterruption);
         }
         /// Return the event at the top of this's stack.
         pub fn ev(&self) -> &Event {
-            unimplemented!()
+            self.pending_events.back().unwrap()
         }
 
         pub fn ev_mut(&mut self) -> &mut Event {
-            unimplemented!()
+            self.pending_events.back_mut().unwrap()
         }
 
         /// Call this before recording events or data.  Records
@@ -1033,15 +1632,67 @@ pub mod record_task {
         /// a chance to reset the syscallbuf (i.e. record some other kind of event)
         /// before the tracee runs again in a way that might append another buffered
         /// syscall --- so we can't flush too early
-        pub fn maybe_flush_syscallbuf(&self) {
-            unimplemented!()
+        pub fn maybe_flush_syscallbuf(&mut self) {
+            if self.ev().event_type() == EventType::EvSyscallbufFlush {
+                // Already flushing.
+                return;
+            }
+            if self.syscallbuf_child.is_null() {
+                return;
+            }
+            let num_rec_bytes = self.syscallbuf_data_size() as u32;
+            if self.flushed_syscallbuf
+                || self.delay_syscallbuf_reset_for_desched
+                || num_rec_bytes == 0
+            {
+                // no records, or we've already flushed.
+                return;
+            }
+
+            self.push_event(Event::new_syscallbuf_flush_event(
+                SyscallbufFlushEventData::new(),
+            ));
+            // Write the entire buffer in one shot without parsing it,
+            // because replay will take care of that.
+            let size = size_of::<syscallbuf_hdr>() + num_rec_bytes as usize;
+            self.record_remote(RemotePtr::cast(self.syscallbuf_child), size);
+            self.record_current_event();
+            self.pop_event(EventType::EvSyscallbufFlush);
+
+            self.flushed_syscallbuf = true;
+            self.flushed_num_rec_bytes = num_rec_bytes;
+            log!(
+                LogDebug,
+                "Syscallbuf flushed with num_rec_bytes={}",
+                num_rec_bytes
+            );
         }
 
         /// Call this after recording an event when it might be safe to reset the
         /// syscallbuf. It must be after recording an event to ensure during replay
         /// we run past any syscallbuf after-syscall code that uses the buffer data.
-        pub fn maybe_reset_syscallbuf(&self) {
-            unimplemented!()
+        pub fn maybe_reset_syscallbuf(&mut self) {
+            if self.flushed_syscallbuf
+                && !self.delay_syscallbuf_reset_for_desched
+                && !self.delay_syscallbuf_reset_for_seccomp_trap
+            {
+                self.flushed_syscallbuf = false;
+                log!(LogDebug, "Syscallbuf reset");
+                let hdr = RemotePtr::<u8>::cast(self.syscallbuf_child);
+                for &offset in &[
+                    offset_of!(syscallbuf_hdr, num_rec_bytes),
+                    offset_of!(syscallbuf_hdr, mprotect_record_count),
+                    offset_of!(syscallbuf_hdr, mprotect_record_count_completed),
+                ] {
+                    write_val_mem(self, RemotePtr::<u32>::cast(hdr + offset), &0u32, None);
+                }
+                self.record_event(
+                    &Event::syscallbuf_reset(),
+                    Some(FlushSyscallbuf::DontFlushSyscallbuf),
+                    None,
+                    None,
+                );
+            }
         }
 
         /// Record an event on behalf of this.  Record the registers of
@@ -1050,68 +1701,142 @@ pub mod record_task {
         /// and meaningful at this's current execution point.
         /// `record_current_event()` record `this->ev()`, and
         /// `record_event()` records the specified event.
-        pub fn record_current_event(&self) {
-            unimplemented!()
+        pub fn record_current_event(&mut self) {
+            let ev = self.ev().clone();
+            self.record_event(&ev, None, None, None);
         }
         pub fn record_event(
-            &self,
-            _ev: &Event,
-            _flush: Option<FlushSyscallbuf>,
-            _reset: Option<AllowSyscallbufReset>,
-            _registers: Option<&Registers>,
+            &mut self,
+            ev: &Event,
+            maybe_flush: Option<FlushSyscallbuf>,
+            maybe_reset: Option<AllowSyscallbufReset>,
+            maybe_registers: Option<&Registers>,
         ) {
-            unimplemented!()
+            let flush = maybe_flush.unwrap_or(FlushSyscallbuf::FlushSyscallbuf);
+            let reset = maybe_reset.unwrap_or(AllowSyscallbufReset::AllowResetSyscallbuf);
+            if flush == FlushSyscallbuf::FlushSyscallbuf {
+                self.maybe_flush_syscallbuf();
+            }
+
+            let session = self.session();
+            session
+                .as_record()
+                .unwrap()
+                .checksum_memory_for_event(self, ev);
+
+            let mut registers = None;
+            let mut extra_registers = None;
+            if ev.record_regs() {
+                registers = Some(match maybe_registers {
+                    Some(regs) => regs.clone(),
+                    None => self.regs_ref().clone(),
+                });
+                if ev.record_extra_regs() {
+                    extra_registers = Some(self.extra_regs_ref().clone());
+                }
+            }
+
+            if ev.is_syscall_event() && ev.syscall_event().state == SyscallState::ExitingSyscall {
+                self.ticks_at_last_recorded_syscall_exit = self.tick_count();
+            }
+
+            self.trace_writer_mut().write_frame(
+                self,
+                ev,
+                registers.as_ref(),
+                extra_registers.as_ref(),
+            );
+            log!(
+                LogDebug,
+                "Wrote event {} for time {}",
+                ev,
+                self.trace_writer().time() - 1
+            );
+
+            if !ev.has_ticks_slop() && reset == AllowSyscallbufReset::AllowResetSyscallbuf {
+                ed_assert!(self, flush == FlushSyscallbuf::FlushSyscallbuf);
+                self.maybe_reset_syscallbuf();
+            }
         }
 
-        pub fn is_fatal_signal(&self, _sig: i32, _deterministic: SignalDeterministic) -> bool {
-            unimplemented!()
+        pub fn is_fatal_signal(&self, sig: i32, deterministic: SignalDeterministic) -> bool {
+            if self.thread_group().received_sigframe_sigsegv {
+                // Can't be blocked, caught or ignored
+                return true;
+            }
+            let action = default_action(sig);
+            if action != SignalAction::DumpCore && action != SignalAction::Terminate {
+                // If the default action doesn't kill the process, it won't die.
+                return false;
+            }
+            if self.is_sig_ignored(sig) {
+                // Deterministic fatal signals can't be ignored.
+                return deterministic == SignalDeterministic::DeterministicSig;
+            }
+            // If there's a signal handler, the signal won't be fatal.
+            !self.signal_has_user_handler(sig)
         }
 
         /// Return the pid of the newborn thread created by this task.
         /// Called when this task has a PTRACE_CLONE_EVENT with CLONE_THREAD.
         pub fn find_newborn_thread(&self) -> pid_t {
-            unimplemented!()
+            ed_assert!(self, self.maybe_ptrace_event() == PTRACE_EVENT_CLONE);
+            self.get_ptrace_eventmsg_pid()
         }
 
         /// Return the pid of the newborn process (whose parent has pid `parent_pid`,
         /// which need not be the same as the current task's pid, due to CLONE_PARENT)
         /// created by this task. Called when this task has a PTRACE_CLONE_EVENT
         /// without CLONE_THREAD, or PTRACE_FORK_EVENT.
-        pub fn find_newborn_process(&self, _child_parent: pid_t) -> pid_t {
-            unimplemented!()
+        pub fn find_newborn_process(&self, child_parent: pid_t) -> pid_t {
+            let pid = self.get_ptrace_eventmsg_pid();
+            ed_assert!(
+                self,
+                parent_pid_of(pid) == child_parent,
+                "Newborn process {} isn't a child of {}",
+                pid,
+                child_parent
+            );
+            pid
         }
 
         /// Do a tgkill to send a specific signal to this task.
-        pub fn tgkill(&self, _sig: i32) {
-            unimplemented!()
+        pub fn tgkill(&self, sig: i32) {
+            log!(LogDebug, "Sending {} to tid {}", sig, self.tid);
+            let ret = unsafe { libc::syscall(libc::SYS_tgkill, self.real_tgid(), self.tid, sig) };
+            ed_assert!(self, ret == 0, "tgkill of {} failed", self.tid);
         }
 
         /// If the process looks alive, kill it. It is recommended to call try_wait(),
         /// on this task before, to make sure liveness is correctly reflected when
         /// making this decision
-        pub fn kill_if_alive(&self) {
-            unimplemented!()
+        pub fn kill_if_alive(&self) -> bool {
+            if !self.is_dying() {
+                self.tgkill(SIGKILL);
+                return true;
+            }
+            false
         }
 
         pub fn robust_list(&self) -> RemotePtr<Void> {
-            unimplemented!()
+            self.robust_futex_list
         }
         pub fn robust_list_len(&self) -> usize {
-            unimplemented!()
+            self.robust_futex_list_len
         }
 
         /// Uses /proc so not trivially cheap.
         pub fn get_parent_pid(&self) -> pid_t {
-            unimplemented!()
+            parent_pid_of(self.tid)
         }
 
         /// Return true if this is a "clone child" per the wait(2) man page.
         pub fn is_clone_child(&self) -> bool {
-            unimplemented!()
+            self.termination_signal != Some(SIGCHLD)
         }
 
-        pub fn set_termination_signal(&self, _sig: i32) {
-            unimplemented!()
+        pub fn set_termination_signal(&mut self, sig: i32) {
+            self.termination_signal = Some(sig);
         }
 
         /// When a signal triggers an emulated a ptrace-stop for this task,
@@ -1124,18 +1849,29 @@ pub mod record_task {
         /// Tasks normally can't change their tid. There is one very special situation
         /// where they can: when a non-main-thread does an execve, its tid changes
         /// to the tid of the thread-group leader.
-        pub fn set_tid_and_update_serial(&self, _tid: pid_t, _own_namespace_tid: pid_t) {
-            unimplemented!()
+        pub fn set_tid_and_update_serial(&mut self, tid: pid_t, own_namespace_tid: pid_t) {
+            self.hpc.set_tid(tid);
+            self.tid = tid;
+            self.rec_tid = tid;
+            self.serial = self.session().next_task_serial();
+            self.own_namespace_rec_tid = own_namespace_tid;
         }
 
         /// Return our cached copy of the signal mask, updating it if necessary.
-        pub fn get_sigmask(&self) -> sig_set_t {
-            unimplemented!()
+        pub fn get_sigmask(&mut self) -> sig_set_t {
+            if self.blocked_sigs_dirty {
+                self.blocked_sigs = self.read_sigmask_from_process();
+                log!(LogDebug, "Refreshed sigmask, now {:#x}", self.blocked_sigs);
+                self.blocked_sigs_dirty = false;
+            }
+            self.blocked_sigs
         }
 
         /// Just get the signal mask of the process.
         pub fn read_sigmask_from_process(&self) -> sig_set_t {
-            unimplemented!()
+            let results = read_proc_status_fields(self.tid, &[b"SigBlk"]).unwrap();
+            ed_assert!(self, results.len() == 1);
+            parse_sig_set(&results[0])
         }
 
         /// Unblock the signal for the process.
@@ -1153,8 +1889,16 @@ pub mod record_task {
         }
 
         /// Retrieve the tid of this task from the tracee and store it
-        fn update_own_namespace_tid(&self) {
-            unimplemented!()
+        pub fn update_own_namespace_tid(&mut self) {
+            // The last NSpid entry is the tid in the task's own pid namespace. The
+            // entries are tab separated so read_proc_status_fields() can't be used.
+            let status = read_to_string(format!("/proc/{}/status", self.tid)).unwrap_or_default();
+            let maybe_tid = status
+                .lines()
+                .find_map(|line| line.strip_prefix("NSpid:"))
+                .and_then(|pids| pids.split_whitespace().last())
+                .and_then(|pid| pid.parse::<pid_t>().ok());
+            self.own_namespace_rec_tid = maybe_tid.unwrap_or(self.tid);
         }
 
         /// Wait for `futex` in this address space to have the value
@@ -1177,21 +1921,39 @@ pub mod record_task {
         }
 
         /// Call this when SYS_sigaction is finishing with `regs`.
-        fn update_sigaction(&self, _regs: &Registers) {
-            unimplemented!()
+        fn update_sigaction(&mut self, regs: &Registers) {
+            rd_arch_function!(self, update_sigaction_arch, regs.arch(), regs)
         }
 
         /// Update the futex robust list head pointer to `list` (which
         /// is of size `len`).
-        fn set_robust_list(&self, _list: RemotePtr<Void>, _len: usize) {
-            unimplemented!()
+        fn set_robust_list(&mut self, list: RemotePtr<Void>, len: usize) {
+            self.robust_futex_list = list;
+            self.robust_futex_list_len = len;
         }
 
         fn init_buffers_arch<Arch>(&self) {
             unimplemented!()
         }
-        fn on_syscall_exit_arch<Arch>(&self, _syscallno: i32, _regs: &Registers) {
-            unimplemented!()
+        fn on_syscall_exit_arch<Arch: Architecture>(&mut self, sys: i32, regs: &Registers) {
+            if sys == Arch::SET_ROBUST_LIST {
+                self.set_robust_list(RemotePtr::new_from_val(regs.arg1()), regs.arg2());
+            } else if sys == Arch::SIGACTION || sys == Arch::RT_SIGACTION {
+                // TODO: SYS_signal
+                self.update_sigaction(regs);
+            } else if sys == Arch::SET_TID_ADDRESS {
+                self.set_tid_addr(RemotePtr::new_from_val(regs.arg1()));
+            } else if sys == Arch::SIGSUSPEND
+                || sys == Arch::RT_SIGSUSPEND
+                || sys == Arch::SIGPROCMASK
+                || sys == Arch::RT_SIGPROCMASK
+                || sys == Arch::PSELECT6
+                || sys == Arch::PSELECT6_TIME64
+                || sys == Arch::PPOLL
+                || sys == Arch::PPOLL_TIME64
+            {
+                self.invalidate_sigmask();
+            }
         }
 
         /// Helper function for update_sigaction.
@@ -1219,8 +1981,9 @@ pub mod record_task {
         }
 
         /// Update the clear-tid futex to `tid_addr`.
-        fn set_tid_addr(&self, _tid_addr: RemotePtr<i32>) {
-            unimplemented!()
+        fn set_tid_addr(&mut self, tid_addr: RemotePtr<i32>) {
+            log!(LogDebug, "updating cleartid futex to {}", tid_addr);
+            self.tid_futex = tid_addr;
         }
     }
 }
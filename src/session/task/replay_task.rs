@@ -16,8 +16,9 @@ use crate::{
     bindings::kernel::user_desc,
     kernel_abi::{common::preload_interface::syscallbuf_record, SupportedArch},
     log::LogLevel::LogWarn,
-    registers::{MismatchBehavior, Registers},
+    registers::Registers,
     remote_ptr::{RemotePtr, Void},
+    replay_divergence::DivergenceKind,
     session::{
         address_space::address_space::AddressSpace,
         task::{
@@ -46,12 +47,12 @@ use crate::{
         Session,
         SessionSharedPtr,
     },
+    skid_region::SkidRegion,
     trace::{
         trace_frame::{FrameTime, TraceFrame},
         trace_reader::{RawData, TraceReader},
         trace_stream::MappedData,
     },
-    util::page_size,
     wait_status::WaitStatus,
 };
 use libc::pid_t;
@@ -64,6 +65,8 @@ use std::{
 
 pub struct ReplayTask {
     pub task_inner: TaskInner,
+    /// How far short of a ticks target to program interrupts for this task.
+    pub skid_region: SkidRegion,
 }
 
 impl Deref for ReplayTask {
@@ -110,6 +113,7 @@ impl ReplayTask {
     ) -> ReplayTask {
         ReplayTask {
             task_inner: TaskInner::new(session, tid, rec_tid, serial, arch),
+            skid_region: Default::default(),
         }
     }
 
@@ -173,16 +177,14 @@ impl ReplayTask {
         }
 
         // TODO: add perf counter validations (hw int, page faults, insts)
-        let trace_frame = self.current_trace_frame();
-        let rec_regs = trace_frame.regs_ref();
-        Registers::compare_register_files(
-            Some(self),
-            "replaying",
-            self.regs_ref(),
-            "recorded",
-            rec_regs,
-            MismatchBehavior::BailOnMismatch,
-        );
+        let mismatches =
+            Registers::mismatches(self.regs_ref(), self.current_trace_frame().regs_ref());
+        if !mismatches.is_empty() {
+            self.session()
+                .as_replay()
+                .unwrap()
+                .report_divergence(self, DivergenceKind::Registers(mismatches));
+        }
     }
 
     pub fn current_trace_frame(&self) -> OwningHandle<SessionSharedPtr, Ref<'_, TraceFrame>> {
@@ -222,13 +224,12 @@ impl ReplayTask {
                     buf.data.len(),
                 );
             } else {
-                let t = self.session().find_task_from_rec_tid(buf.rec_tid).unwrap();
-
-                t.borrow_mut()
-                    .write_bytes_helper(buf.addr, &buf.data, None, WriteFlags::empty());
-                let vm_shr_ptr = t.borrow().vm_shr_ptr();
+                let t_handle = self.session().find_task_handle(buf.rec_tid).unwrap();
+                let mut t = t_handle.borrow_mut();
+                t.write_bytes_helper(buf.addr, &buf.data, None, WriteFlags::empty());
+                let vm_shr_ptr = t.vm_shr_ptr();
                 vm_shr_ptr.maybe_update_breakpoints(
-                    t.borrow_mut().as_mut(),
+                    t.as_mut(),
                     RemotePtr::cast::<u8>(buf.addr),
                     buf.data.len(),
                 );
@@ -269,19 +270,11 @@ impl ReplayTask {
                                 buf.data.len(),
                             );
                         } else {
-                            let t = self.session().find_task_from_rec_tid(buf.rec_tid).unwrap();
-                            t.borrow_mut().write_bytes_helper(
-                                buf.addr,
-                                &buf.data,
-                                None,
-                                WriteFlags::empty(),
-                            );
-                            let vm_shr = t.borrow().vm_shr_ptr();
-                            vm_shr.maybe_update_breakpoints(
-                                t.borrow_mut().as_mut(),
-                                buf.addr,
-                                buf.data.len(),
-                            );
+                            let t_handle = self.session().find_task_handle(buf.rec_tid).unwrap();
+                            let mut t = t_handle.borrow_mut();
+                            t.write_bytes_helper(buf.addr, &buf.data, None, WriteFlags::empty());
+                            let vm_shr = t.vm_shr_ptr();
+                            vm_shr.maybe_update_breakpoints(t.as_mut(), buf.addr, buf.data.len());
                         };
                     }
                 }
@@ -453,7 +446,7 @@ impl Task for ReplayTask {
             ed_assert!(
                 self,
                 km.start() == AddressSpace::preload_thread_locals_start()
-                    && km.size() == page_size()
+                    && km.size() == self.trace_reader().page_size()
             );
             true
         } else {
@@ -62,13 +62,13 @@ use crate::{
         SupportedArch,
     },
     kernel_metadata::{ptrace_req_name, signal_name},
-    kernel_supplement::ARCH_SET_CPUID,
+    kernel_supplement::{ARCH_SET_CPUID, CLOSE_RANGE_CLOEXEC, CLOSE_RANGE_UNSHARE},
     log::LogLevel::{LogDebug, LogInfo, LogWarn},
     perf_counters::TIME_SLICE_SIGNAL,
-    rd::RD_RESERVED_ROOT_DIR_FD,
     registers::{with_converted_registers, Registers, X86_TF_FLAG},
     remote_code_ptr::RemoteCodePtr,
     remote_ptr::{RemotePtr, Void},
+    scm_rights::{did_recvmsg, did_sendmsg},
     scoped_fd::ScopedFd,
     seccomp_filter_rewriter::SECCOMP_MAGIC_SKIP_ORIGINAL_SYSCALLNO,
     session::{
@@ -81,7 +81,6 @@ use crate::{
         },
         session_inner::session_inner::SessionInner,
         task::{
-            is_signal_triggered_by_ptrace_interrupt,
             is_singlestep_resume,
             task_inner::{
                 task_inner::{CapturedState, CloneReason, PtraceData, WriteFlags},
@@ -104,10 +103,11 @@ use crate::{
         cpuid,
         floor_page_size,
         is_kernel_trap,
+        process_vm_read,
+        process_vm_write,
         pwrite_all_fallible,
         trapped_instruction_at,
         trapped_instruction_len,
-        u8_raw_slice,
         u8_raw_slice_mut,
         xsave_layout_from_trace,
         xsave_native_layout,
@@ -178,6 +178,7 @@ pub(super) fn open_mem_fd<T: Task>(task: &mut T) -> bool {
     let path = CStr::from_bytes_with_nul(b"/proc/self/mem\0").unwrap();
 
     let arch = task.arch();
+    let root_dir_fd = task.reserved_root_dir_fd();
     let mut remote = AutoRemoteSyscalls::new(task);
     let remote_fd: i32;
     {
@@ -191,7 +192,7 @@ pub(super) fn open_mem_fd<T: Task>(task: &mut T) -> bool {
             remote_fd = rd_syscall!(
                 remote_path,
                 syscall_number_for_openat(remote_arch),
-                RD_RESERVED_ROOT_DIR_FD,
+                root_dir_fd,
                 // Skip the leading '/' in the path as this is a relative path.
                 (remote_addr + 1usize).as_usize(),
                 libc::O_RDWR
@@ -250,6 +251,19 @@ pub(super) fn read_bytes_fallible<T: Task>(
         None => (),
     }
 
+    // process_vm_readv() is a lot cheaper than /proc/<pid>/mem, but stops at
+    // the first page the tracee couldn't read itself. The rest is read below.
+    let nread_fast = process_vm_read(task.tid, addr, buf).unwrap_or(0);
+    if nread_fast == buf.len() {
+        return Ok(nread_fast);
+    }
+    if nread_fast > 0 {
+        let rest = task
+            .read_bytes_fallible(addr + nread_fast, &mut buf[nread_fast..])
+            .unwrap_or(0);
+        return Ok(nread_fast + rest);
+    }
+
     if !task.vm().mem_fd().is_open() {
         return Ok(task.read_bytes_ptrace(addr, buf));
     }
@@ -357,9 +371,40 @@ pub fn read_bytes_helper_for<T: Task, D>(
 /// Read and return the C string located at `child_addr` in
 /// this address space.
 pub(super) fn read_c_str<T: Task>(task: &mut T, child_addr: RemotePtr<u8>) -> CString {
-    // XXX handle invalid C strings
-    // e.g. c-strings that don't end even when an unmapped region of memory
-    // is reached.
+    match read_c_str_fallible(task, child_addr, None) {
+        Ok(s) => s,
+        Err(e) => {
+            ed_assert!(
+                task,
+                false,
+                "Couldn't read C string at {}: {:?}",
+                child_addr,
+                e
+            );
+            unreachable!()
+        }
+    }
+}
+
+/// Why `read_c_str_fallible()` failed.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ReadCStrError {
+    /// The memory at `addr` couldn't be read and there was no NUL before it.
+    Unreadable { addr: RemotePtr<Void> },
+    /// There was no NUL within the maximum length.
+    TooLong,
+}
+
+/// NOT Forwarded method definition
+///
+/// Like `read_c_str()`, but for pointers the tracee handed us that may well be
+/// bad: an unreadable string, or one that doesn't end within `max_len` bytes
+/// (not counting the NUL), is an error instead of an assertion failure.
+pub fn read_c_str_fallible(
+    task: &mut dyn Task,
+    child_addr: RemotePtr<u8>,
+    max_len: Option<usize>,
+) -> Result<CString, ReadCStrError> {
     let mut p = child_addr;
     let mut s: Vec<u8> = Vec::new();
     loop {
@@ -368,18 +413,26 @@ pub(super) fn read_c_str<T: Task>(task: &mut T, child_addr: RemotePtr<u8>) -> CS
         // end of the page. In case it _hasn't_ ended then we try on the
         // next page and so forth.
         let end_of_page: RemotePtr<Void> = ceil_page_size(p.as_usize() + 1).into();
-        let nbytes: usize = end_of_page - p;
-        let mut buf = Vec::<u8>::with_capacity(nbytes);
-        buf.resize(nbytes, 0);
-        task.read_bytes_helper(p, &mut buf, None);
-        for i in 0..nbytes {
-            if 0 == buf[i] {
-                // We have already checked it so unsafe is OK!
-                return unsafe { CString::from_vec_unchecked(s) };
-            }
-            s.push(buf[i]);
+        let mut nbytes: usize = end_of_page - p;
+        if let Some(max_len) = max_len {
+            // Leave room for the NUL.
+            nbytes = min(nbytes, max_len + 1 - s.len());
+        }
+        let mut buf = vec![0u8; nbytes];
+        let nread = task.read_bytes_fallible(p, &mut buf).unwrap_or(0);
+        if let Some(i) = buf[0..nread].iter().position(|&b| b == 0) {
+            s.extend_from_slice(&buf[0..i]);
+            // We have already checked it so unsafe is OK!
+            return Ok(unsafe { CString::from_vec_unchecked(s) });
+        }
+        s.extend_from_slice(&buf[0..nread]);
+        if nread < nbytes {
+            return Err(ReadCStrError::Unreadable { addr: p + nread });
         }
-        p = end_of_page;
+        if max_len.map_or(false, |max_len| s.len() > max_len) {
+            return Err(ReadCStrError::TooLong);
+        }
+        p = p + nbytes;
     }
 }
 
@@ -466,6 +519,17 @@ pub(super) fn write_bytes_helper<T: Task>(
         return;
     }
 
+    // Likewise process_vm_writev() stops at pages the tracee can't write
+    // itself, e.g. code we set breakpoints in. Those are written below.
+    let nwritten_fast = process_vm_write(task.tid, addr, buf).unwrap_or(0);
+    if nwritten_fast > 0 {
+        task.vm().notify_written(addr, nwritten_fast, flags);
+        if nwritten_fast < buf_size {
+            task.write_bytes_helper(addr + nwritten_fast, &buf[nwritten_fast..], ok, flags);
+        }
+        return;
+    }
+
     if !task.vm().mem_fd().is_open() {
         let nwritten = task.write_bytes_ptrace(addr, buf);
         if nwritten > 0 {
@@ -658,16 +722,12 @@ pub(super) fn did_waitpid<T: Task>(task: &mut T, mut status: WaitStatus) {
     let mut siginfo_overriden = false;
     if task.expecting_ptrace_interrupt_stop > 0 {
         task.expecting_ptrace_interrupt_stop -= 1;
-        if is_signal_triggered_by_ptrace_interrupt(status.maybe_group_stop_sig()) {
+        if status.is_ptrace_interrupt_stop() {
             // Assume this was PTRACE_INTERRUPT and thus treat this as
             // TIME_SLICE_SIGNAL instead.
             if task.session().is_recording() {
                 // Force this timeslice to end
-                task.session()
-                    .as_record_mut()
-                    .unwrap()
-                    .scheduler_mut()
-                    .expire_timeslice();
+                task.session().as_record().unwrap().expire_timeslice();
             }
             status = WaitStatus::for_stop_sig(TIME_SLICE_SIGNAL);
             task.pending_siginfo = Default::default();
@@ -981,7 +1041,7 @@ pub(super) fn resume_execution<T: Task>(
                 task.ptrace_if_alive(
                     how as u32,
                     RemotePtr::null(),
-                    PtraceData::ReadFrom(u8_raw_slice(&sig)),
+                    PtraceData::ReadWord(sig as usize),
                 );
             }
         }
@@ -1150,6 +1210,31 @@ fn on_syscall_exit_arch<Arch: Architecture>(t: &mut dyn Task, sys: i32, regs: &R
         return;
     }
 
+    if sys == Arch::SENDMSG {
+        return did_sendmsg::<Arch>(t, regs);
+    }
+
+    if sys == Arch::RECVMSG {
+        return did_recvmsg::<Arch>(t, regs);
+    }
+
+    if sys == Arch::CLOSE_RANGE {
+        let flags = regs.arg3() as u32;
+        if flags & CLOSE_RANGE_UNSHARE != 0 {
+            t.fd_table_mut().task_set_mut().erase(t.weak_self_ptr());
+            t.fds = Some(t.fd_table_shr_ptr().borrow().clone_into_task(t));
+        }
+        // Close-on-exec fds stay open until an exec, which finds them then.
+        if flags & CLOSE_RANGE_CLOEXEC == 0 {
+            t.fd_table_shr_ptr().borrow_mut().did_close_range(
+                regs.arg1() as u32,
+                regs.arg2() as u32,
+                t,
+            );
+        }
+        return;
+    }
+
     if sys == Arch::UNSHARE {
         if regs.arg1() & CLONE_FILES as usize != 0 {
             t.fd_table_mut().task_set_mut().erase(t.weak_self_ptr());
@@ -1234,6 +1319,7 @@ pub(super) fn post_exec_syscall(t: &mut dyn Task) {
     let arch = t.arch();
     t.canonicalize_regs(arch);
     t.vm_shr_ptr().post_exec_syscall(t);
+    t.rseq_state = None;
 
     if SessionInner::has_cpuid_faulting() {
         let mut remote = AutoRemoteSyscalls::new(t);
@@ -1561,6 +1647,11 @@ pub(in super::super) fn clone_task_common(
     t.stopping_breakpoint_table_entry_size = clone_this.stopping_breakpoint_table_entry_size;
     t.preload_globals = clone_this.preload_globals;
     t.seccomp_bpf_enabled = clone_this.seccomp_bpf_enabled;
+    // New threads start without an rseq registration, forked processes keep
+    // their parent's.
+    if !flags.contains(CloneFlags::CLONE_SHARE_VM) {
+        t.rseq_state = clone_this.rseq_state;
+    }
 
     let rc_t: TaskSharedPtr = Rc::new(RefCell::new(t));
     let weak_self_ptr = Rc::downgrade(&rc_t);
@@ -1826,14 +1917,28 @@ pub(super) fn task_drop_common<T: Task>(t: &T) {
         ed_assert!(t, t.seen_ptrace_exit_event);
         ed_assert!(t, t.syscallbuf_child.is_null());
 
-        if t.thread_group().task_set().is_empty() && !t.session().is_recording() {
-            // Reap the zombie.
-            let ret = unsafe { waitpid(t.thread_group().real_tgid, ptr::null_mut(), __WALL) };
+        let tg_rc = t.thread_group_shr_ptr();
+        let mut tg = tg_rc.borrow_mut();
+        if !tg.is_only_task(t.weak_self_ptr()) {
+            // Waiting for the process now would block until the other
+            // threads are gone.
+            if t.tid == tg.real_tgid {
+                log!(
+                    LogDebug,
+                    "  {} is a zombie until the rest of its thread group exits",
+                    t.tid
+                );
+                tg.zombie_leader = true;
+            }
+        } else if !t.session().is_recording() {
+            // Reap the zombie. That's the leader's, if it exited first.
+            let ret = unsafe { waitpid(tg.real_tgid, ptr::null_mut(), __WALL) };
             if ret == -1 {
                 ed_assert!(t, errno() == ECHILD || errno() == ESRCH);
             } else {
-                ed_assert!(t, ret == t.thread_group().real_tgid);
+                ed_assert!(t, ret == tg.real_tgid);
             }
+            tg.zombie_leader = false;
         }
     }
 
@@ -1879,7 +1984,7 @@ fn process_shmdt(t: &dyn Task, addr: RemotePtr<Void>) {
 
 fn process_ptrace<Arch: Architecture>(regs: &Registers, t: &mut dyn Task) {
     let pid = regs.arg2_signed() as pid_t;
-    let maybe_tracee = t.session().find_task_from_rec_tid(pid);
+    let maybe_tracee = t.session().find_task_handle(pid);
     match regs.arg1() as u32 {
         PTRACE_SETREGS => {
             let tracee_rc = maybe_tracee.unwrap();
@@ -1896,26 +2001,30 @@ fn process_ptrace<Arch: Architecture>(regs: &Registers, t: &mut dyn Task) {
             return;
         }
         PTRACE_SETFPREGS => {
+            let tracee_rc = maybe_tracee.unwrap();
+            let mut tracee = tracee_rc.borrow_mut();
             let data = read_mem(
                 t,
                 RemotePtr::<u8>::from(regs.arg4()),
                 size_of::<Arch::user_fpregs_struct>(),
                 None,
             );
-            let mut r = t.extra_regs_ref().clone();
+            let mut r = tracee.extra_regs_ref().clone();
             r.set_user_fpregs_struct(t, Arch::arch(), &data);
-            t.set_extra_regs(&r);
+            tracee.set_extra_regs(&r);
             return;
         }
         PTRACE_SETFPXREGS => {
+            let tracee_rc = maybe_tracee.unwrap();
+            let mut tracee = tracee_rc.borrow_mut();
             let data = read_val_mem(
                 t,
                 RemotePtr::<x86::user_fpxregs_struct>::from(regs.arg4()),
                 None,
             );
-            let mut r = t.extra_regs_ref().clone();
+            let mut r = tracee.extra_regs_ref().clone();
             r.set_user_fpxregs_struct(t, &data);
-            t.set_extra_regs(&r);
+            tracee.set_extra_regs(&r);
             return;
         }
         PTRACE_SETREGSET => {
@@ -0,0 +1,85 @@
+use crate::session::task::{Task, TaskSharedPtr};
+use libc::pid_t;
+use std::{
+    cell::{Ref, RefMut},
+    rc::Rc,
+};
+
+/// A facade over a `TaskSharedPtr` for code that operates on a task other
+/// than the one it was handed.
+///
+/// While rd processes an event for one task, that task is mutably borrowed
+/// further up the stack. Emulating ptrace, applying data records recorded
+/// for other tasks and the like need to get at a second task, and a plain
+/// `borrow_mut()` on that task panics with an anonymous `BorrowMutError` if
+/// it's the current task or is borrowed by someone else. `TaskHandle` makes
+/// the borrow explicit: use `try_borrow_mut()` if the task may legitimately
+/// be busy, `with_mut()` if it may be the current task, and `borrow_mut()`
+/// only where a conflict is a bug. In the last case the panic at least says
+/// which task was involved.
+#[derive(Clone)]
+pub struct TaskHandle {
+    rec_tid: pid_t,
+    ptr: TaskSharedPtr,
+}
+
+impl TaskHandle {
+    pub fn new(rec_tid: pid_t, ptr: TaskSharedPtr) -> TaskHandle {
+        TaskHandle { rec_tid, ptr }
+    }
+
+    pub fn rec_tid(&self) -> pid_t {
+        self.rec_tid
+    }
+
+    pub fn shared_ptr(&self) -> &TaskSharedPtr {
+        &self.ptr
+    }
+
+    /// Is this the task `t`?
+    pub fn is(&self, t: &dyn Task) -> bool {
+        t.weak_self_ptr().ptr_eq(&Rc::downgrade(&self.ptr))
+    }
+
+    /// Is anyone holding a borrow (shared or mutable) of this task?
+    pub fn is_borrowed(&self) -> bool {
+        self.ptr.try_borrow_mut().is_err()
+    }
+
+    pub fn try_borrow(&self) -> Option<Ref<'_, Box<dyn Task>>> {
+        self.ptr.try_borrow().ok()
+    }
+
+    pub fn try_borrow_mut(&self) -> Option<RefMut<'_, Box<dyn Task>>> {
+        self.ptr.try_borrow_mut().ok()
+    }
+
+    pub fn borrow(&self) -> Ref<'_, Box<dyn Task>> {
+        match self.ptr.try_borrow() {
+            Ok(t) => t,
+            Err(_) => fatal!("Task {} is already mutably borrowed", self.rec_tid),
+        }
+    }
+
+    pub fn borrow_mut(&self) -> RefMut<'_, Box<dyn Task>> {
+        match self.ptr.try_borrow_mut() {
+            Ok(t) => t,
+            Err(_) => fatal!(
+                "Task {} is already borrowed. If it can be the task currently being \
+                 processed, use TaskHandle::with_mut()",
+                self.rec_tid
+            ),
+        }
+    }
+
+    /// Run `f` on this task. `current` is the task the caller is processing
+    /// (and so already has mutably borrowed); if this handle refers to
+    /// `current`, `f` is run on it directly instead of borrowing again.
+    pub fn with_mut<R>(&self, current: &mut dyn Task, f: impl FnOnce(&mut dyn Task) -> R) -> R {
+        if self.is(current) {
+            f(current)
+        } else {
+            f(self.borrow_mut().as_mut())
+        }
+    }
+}
@@ -111,7 +111,8 @@ pub struct TrapReasons {
 pub mod task_inner {
     use super::*;
     use crate::{
-        auto_remote_syscalls::AutoRemoteSyscalls,
+        arch::{X64Arch, X86Arch},
+        auto_remote_syscalls::{recvmsg_socket, AutoRemoteSyscalls},
         bindings::{
             kernel::{sock_fprog, user, user_desc, CAP_SYS_ADMIN, NT_X86_XSTATE},
             ptrace::{
@@ -131,6 +132,7 @@ pub mod task_inner {
                 PTRACE_O_TRACESECCOMP,
                 PTRACE_O_TRACESYSGOOD,
                 PTRACE_O_TRACEVFORK,
+                PTRACE_O_TRACEVFORKDONE,
                 PTRACE_PEEKDATA,
                 PTRACE_PEEKUSER,
                 PTRACE_POKEDATA,
@@ -143,28 +145,32 @@ pub mod task_inner {
         },
         cpuid_bug_detector::CPUIDBugDetector,
         extra_registers::{ExtraRegisters, Format},
-        fd_table::{FdTable, FdTableRef, FdTableRefMut, FdTableSharedPtr},
-        file_monitor::{
-            magic_save_data_monitor::MagicSaveDataMonitor,
-            preserve_file_monitor::PreserveFileMonitor,
-            stdio_monitor::StdioMonitor,
-        },
+        fd_table::{FdTable, FdTableRef, FdTableRefMut, FdTableSharedPtr, ReservedFd},
+        file_monitor::{magic_save_data_monitor::MagicSaveDataMonitor, stdio_monitor::StdioMonitor},
         flags::Flags,
         kernel_abi::{
             common::preload_interface::{preload_globals, syscallbuf_hdr},
+            syscall_instruction_arch,
             SupportedArch,
             RD_NATIVE_ARCH,
         },
         kernel_metadata::{errno_name, ptrace_req_name, syscall_name},
-        kernel_supplement::PTRACE_EVENT_SECCOMP_OBSOLETE,
+        kernel_supplement::{
+            PTRACE_EVENT_SECCOMP_OBSOLETE,
+            SECCOMP_FILTER_FLAG_NEW_LISTENER,
+            SECCOMP_SET_MODE_FILTER,
+        },
         log::LogLevel::{LogDebug, LogWarn},
         perf_counters::PerfCounters,
         rd::{RD_MAGIC_SAVE_DATA_FD, RD_RESERVED_ROOT_DIR_FD, RD_RESERVED_SOCKET_FD},
+        record_syscall::bufferable_syscalls,
         registers::Registers,
         remote_code_ptr::RemoteCodePtr,
         remote_ptr::{RemotePtr, Void},
+        rseq::RseqState,
         scoped_fd::ScopedFd,
-        seccomp_bpf::SeccompFilter,
+        seccomp_bpf::{audit_arch, SeccompFilter},
+        seccomp_unotify::SeccompNotifyListener,
         session::{
             address_space::{
                 address_space::{AddressSpace, AddressSpaceSharedPtr},
@@ -189,7 +195,9 @@ pub mod task_inner {
             restore_initial_resource_limits,
             running_under_rd,
             set_cpu_affinity,
+            set_cpus_affinity,
             to_cstr_array,
+            tracee_cpus,
             to_cstring_array,
             u8_raw_slice,
             u8_raw_slice_mut,
@@ -210,6 +218,7 @@ pub mod task_inner {
         prctl,
         syscall,
         uid_t,
+        SYS_seccomp,
         SYS_write,
         EAGAIN,
         EBADF,
@@ -223,9 +232,11 @@ pub mod task_inner {
         PR_SET_SECCOMP,
         PR_SET_TSC,
         PR_TSC_SIGSEGV,
+        SCM_RIGHTS,
         SECCOMP_MODE_FILTER,
         SIGKILL,
         SIGSTOP,
+        SOL_SOCKET,
         STDERR_FILENO,
         STDOUT_FILENO,
     };
@@ -246,7 +257,8 @@ pub mod task_inner {
         cell::{Cell, Ref, RefCell},
         cmp::min,
         ffi::{CStr, CString, OsStr, OsString},
-        mem::{size_of, size_of_val},
+        fs,
+        mem::{size_of, size_of_val, zeroed},
         ops::Deref,
         os::{raw::c_int, unix::ffi::OsStrExt},
         ptr,
@@ -417,6 +429,8 @@ pub mod task_inner {
         /// in the first system call issued by the initial tracee (after it returns
         /// from kill(SIGSTOP) to synchronize with the tracer).
         pub(in super::super::super) seccomp_bpf_enabled: bool,
+        /// The task's rseq() registration, which rd emulates. See `rseq`.
+        pub rseq_state: Option<RseqState>,
         /// True when we consumed a PTRACE_EVENT_EXIT that was about to race with
         /// a resume_execution, that was issued while stopped (i.e. SIGKILL).
         pub(in super::super::super) detected_unexpected_exit: bool,
@@ -608,6 +622,16 @@ pub mod task_inner {
             ScopedFd::open_path(path.as_str(), flags)
         }
 
+        /// The lowest fd number at or above `starting_from` that isn't open in
+        /// this task's fd table.
+        pub fn find_free_file_descriptor(&self, starting_from: i32) -> i32 {
+            let mut fd = starting_from;
+            while fs::symlink_metadata(format!("/proc/{}/fd/{}", self.tid, fd)).is_ok() {
+                fd += 1;
+            }
+            fd
+        }
+
         /// Get the name of the file referenced by `fd` in the context of this
         /// task's fd table.
         pub fn file_name_of_fd(&self, fd: i32) -> OsString {
@@ -733,11 +757,17 @@ pub mod task_inner {
         /// is implied by this. Note that once we've entered the traced syscall,
         /// ip() is immediately after the syscall instruction.
         pub fn is_in_traced_syscall(&self) -> bool {
-            unimplemented!()
+            match AddressSpace::rd_page_syscall_from_exit_point(self.ip()) {
+                Some(syscall_type) => syscall_type.traced == Traced::Traced,
+                None => false,
+            }
         }
 
         pub fn is_at_traced_syscall_entry(&self) -> bool {
-            unimplemented!()
+            match AddressSpace::rd_page_syscall_from_entry_point(self.ip()) {
+                Some(syscall_type) => syscall_type.traced == Traced::Traced,
+                None => false,
+            }
         }
 
         /// Return true when this task is in an untraced syscall, i.e. one
@@ -886,11 +916,7 @@ pub mod task_inner {
         /// in resume_execution() before we resume, so it always only reflects the
         /// events since the last resume.
         pub fn debug_status(&self) -> usize {
-            self.fallible_ptrace(
-                PTRACE_PEEKUSER,
-                RemotePtr::new_from_val(dr_user_word_offset(6)),
-                PtraceData::None,
-            ) as usize
+            self.get_debug_reg(6)
         }
 
         /// Set the debug status (DR6 on x86).
@@ -1028,8 +1054,19 @@ pub mod task_inner {
         }
 
         /// @TODO should this be a GdbRegister type?
-        pub fn get_debug_reg(&self, _regno: usize) -> usize {
-            unimplemented!()
+        pub fn get_debug_reg(&self, regno: usize) -> usize {
+            unsafe { Errno::clear() };
+            let result = self.fallible_ptrace(
+                PTRACE_PEEKUSER,
+                dr_user_word_offset(regno).into(),
+                PtraceData::None,
+            );
+            // The task may have died under us. PEEKUSER returns -1 then, which
+            // would look like every watchpoint (and singlestep) had fired.
+            if errno() == ESRCH {
+                return 0;
+            }
+            result as usize
         }
 
         /// @TODO should this be a GdbRegister type?
@@ -1068,7 +1105,26 @@ pub mod task_inner {
         }
 
         pub fn thread_areas(&self) -> Vec<user_desc> {
-            unimplemented!()
+            self.thread_areas_.clone()
+        }
+
+        /// The thread pointer of this task, i.e. the address of its TCB, or
+        /// null if it hasn't set one up yet. On x86-64 that's fs_base; on x86
+        /// it's the base of the thread area %gs selects.
+        pub fn tls_base(&self) -> RemotePtr<Void> {
+            let regs = self.regs_ref();
+            match self.arch() {
+                SupportedArch::X64 => RemotePtr::new_from_val(regs.fs_base() as usize),
+                SupportedArch::X86 => {
+                    let entry_number = (regs.gs() >> 3) as u32;
+                    self.thread_areas_
+                        .iter()
+                        .find(|desc| desc.entry_number == entry_number)
+                        .map_or(RemotePtr::null(), |desc| {
+                            RemotePtr::new_from_val(desc.base_addr as usize)
+                        })
+                }
+            }
         }
 
         pub fn set_status(&mut self, status: WaitStatus) {
@@ -1173,6 +1229,29 @@ pub mod task_inner {
             self.fds.as_ref().unwrap().borrow_mut()
         }
 
+        /// The fd remote syscalls should resolve paths relative to. Usually
+        /// RD_RESERVED_ROOT_DIR_FD, unless the tracee made rd move it.
+        pub fn reserved_root_dir_fd(&self) -> i32 {
+            match self.fds.as_ref() {
+                Some(fds) => fds
+                    .borrow()
+                    .reserved_fd(ReservedFd::RootDir)
+                    .unwrap_or(RD_RESERVED_ROOT_DIR_FD),
+                None => RD_RESERVED_ROOT_DIR_FD,
+            }
+        }
+
+        /// The tracee end of the socket tracees pass fds to rd through.
+        pub fn reserved_tracee_socket_fd(&self) -> i32 {
+            match self.fds.as_ref() {
+                Some(fds) => fds
+                    .borrow()
+                    .reserved_fd(ReservedFd::TraceeSocket)
+                    .unwrap_or_else(|| self.session().tracee_fd_number()),
+                None => self.session().tracee_fd_number(),
+            }
+        }
+
         /// Useful for tricky situations when we need to pass a reference to task to
         /// the FdTable methods for instance
         pub fn fd_table_shr_ptr(&self) -> FdTableSharedPtr {
@@ -1184,8 +1263,24 @@ pub mod task_inner {
             getuid().as_raw()
         }
 
+        /// The architecture of the syscall this task has just entered, worked
+        /// out from the instruction it entered it with.
         pub fn detect_syscall_arch(&self) -> SupportedArch {
-            unimplemented!()
+            let arch = self.arch();
+            let ip = self.ip().decrement_by_syscall_insn_length(arch);
+            // rd-page syscalls are always the task's arch.
+            if AddressSpace::rd_page_syscall_from_entry_point(ip).is_some() {
+                return arch;
+            }
+            let mut code = [0u8; 2];
+            let nread = self.read_bytes_ptrace(ip.to_data_ptr::<Void>(), &mut code);
+            match syscall_instruction_arch(arch, &code[0..nread]) {
+                Some(syscall_arch) => syscall_arch,
+                None => {
+                    ed_assert!(self, false, "No syscall instruction before {}", self.ip());
+                    unreachable!()
+                }
+            }
         }
 
         /// Call this when performing a clone syscall in this task. Returns
@@ -1290,6 +1385,12 @@ pub mod task_inner {
             rd_arch_function_selfless!(setup_preload_thread_locals_arch, self.arch(), self);
         }
 
+        /// Tell the preload library about the current `desched_fd_child` and
+        /// `cloned_file_data_fd_child`, after rd had to move them.
+        pub fn update_preload_thread_locals_fds(&mut self) {
+            rd_arch_function_selfless!(update_preload_thread_locals_fds_arch, self.arch(), self);
+        }
+
         pub fn setup_preload_thread_locals_from_clone(&mut self, origin: &mut TaskInner) {
             rd_arch_function_selfless!(
                 setup_preload_thread_locals_from_clone_arch,
@@ -1394,6 +1495,7 @@ pub mod task_inner {
                 did_set_breakpoint_after_cpuid: false,
                 is_stopped: false,
                 seccomp_bpf_enabled: false,
+                rseq_state: None,
                 detected_unexpected_exit: false,
                 registers_dirty: false,
                 extra_registers: None,
@@ -1623,7 +1725,7 @@ pub mod task_inner {
             let shr_ptr = self.session();
             let owning_handle =
                 OwningHandle::new_with_fn(shr_ptr, |s| match unsafe { (*s).as_record() } {
-                    Some(_rec_sess) => unimplemented!(),
+                    Some(rec_sess) => Ref::map(rec_sess.trace_writer(), |tw| tw.deref()),
                     None => match unsafe { (*s).as_replay() } {
                         Some(rep_sess) => Ref::map(rep_sess.trace_reader(), |tr| tr.deref()),
                         None => unreachable!(),
@@ -1743,6 +1845,14 @@ pub mod task_inner {
                         }
                     }
                 });
+            if maybe_cpu_index.is_none() && PerfCounters::core_type().is_some() {
+                // Unbound tracees still mustn't wander onto the other kind of
+                // core of a hybrid CPU: its PMU counts ticks differently.
+                let cpus = tracee_cpus();
+                if !set_cpus_affinity(&cpus) {
+                    fatal!("Can't restrict tracees to CPUs {:?}", cpus);
+                }
+            }
 
             let mut tid: pid_t;
             // After fork() in a multithreaded program, the child can safely call only
@@ -1756,6 +1866,17 @@ pub mod task_inner {
             let mut prog: sock_fprog = Default::default();
             prog.len = filter.filters.len() as u16;
             prog.filter = filter.filters.as_mut_ptr();
+            // Interception backends other than ptrace have the initial tracee
+            // install a filter of their own and send us its listener.
+            let mut maybe_notify_filter = session
+                .as_record()
+                .and_then(|rs| rs.interception().tracee_filter());
+            let maybe_notify_prog = maybe_notify_filter.as_mut().map(|f| {
+                let mut notify_prog: sock_fprog = Default::default();
+                notify_prog.len = f.filters.len() as u16;
+                notify_prog.filter = f.filters.as_mut_ptr();
+                notify_prog
+            });
             loop {
                 tid = unsafe { fork() };
                 // fork() can fail with EAGAIN due to temporary load issues. In such
@@ -1775,6 +1896,7 @@ pub mod task_inner {
                     &to_cstr_array(&argv_array),
                     &to_cstr_array(&envp_array),
                     &prog,
+                    maybe_notify_prog.as_ref(),
                 );
                 // run_initial_child never returns
             }
@@ -1792,8 +1914,13 @@ pub mod task_inner {
             if !Flags::get().disable_ptrace_exit_events {
                 options |= PTRACE_O_TRACEEXIT;
             }
+            // Replay turns vfork() into a clone() with CLONE_VM, so only a
+            // recording sees vfork events.
             if session.is_recording() {
-                options |= PTRACE_O_TRACEVFORK | PTRACE_O_TRACESECCOMP | PTRACE_O_TRACEEXEC;
+                options |= PTRACE_O_TRACEVFORK
+                    | PTRACE_O_TRACEVFORKDONE
+                    | PTRACE_O_TRACESECCOMP
+                    | PTRACE_O_TRACEEXEC;
             }
 
             let mut res = unsafe { ptrace(PTRACE_SEIZE, tid, 0, options | PTRACE_O_EXITKILL) };
@@ -1871,6 +1998,14 @@ pub mod task_inner {
                 t.clear_wait_status();
                 t.open_mem_fd();
             }
+            if maybe_notify_prog.is_some() {
+                // Sent before the SIGSTOP, so this doesn't block.
+                let listener = ScopedFd::from_raw(recvmsg_socket(&sock_fd_out.borrow()));
+                session
+                    .as_record()
+                    .unwrap()
+                    .set_interception_listener(SeccompNotifyListener::new(listener));
+            }
             wrapped_t
         }
 
@@ -1888,10 +2023,14 @@ pub mod task_inner {
         argv_array: &[&CStr],
         envp_array: &[&CStr],
         seccomp_prog: &sock_fprog,
+        maybe_notify_prog: Option<&sock_fprog>,
     ) {
         let pid = getpid();
 
         set_up_process(session, error_fd, sock_fd, sock_fd_number);
+        if let Some(notify_prog) = maybe_notify_prog {
+            set_up_notify_filter(notify_prog, sock_fd, error_fd);
+        }
         // The preceding code must run before sending SIGSTOP here,
         // since after SIGSTOP replay emulates almost all syscalls, but
         // we need the above syscalls to run "for real".
@@ -1947,6 +2086,16 @@ pub mod task_inner {
                 f.allow_syscalls_from_callsite(ip);
             }
         }
+        if RD_NATIVE_ARCH == SupportedArch::X64 {
+            f.route_syscalls_for_arch(
+                audit_arch(SupportedArch::X64),
+                &bufferable_syscalls::<X64Arch>(),
+            );
+        }
+        f.route_syscalls_for_arch(
+            audit_arch(SupportedArch::X86),
+            &bufferable_syscalls::<X86Arch>(),
+        );
         f.trace();
         f
     }
@@ -1963,16 +2112,8 @@ pub mod task_inner {
             RD_MAGIC_SAVE_DATA_FD,
             Box::new(MagicSaveDataMonitor::new()),
         );
-        fds.add_monitor(
-            t,
-            RD_RESERVED_ROOT_DIR_FD,
-            Box::new(PreserveFileMonitor::new()),
-        );
-        fds.add_monitor(
-            t,
-            tracee_socket_fd_number,
-            Box::new(PreserveFileMonitor::new()),
-        );
+        fds.reserve_fd(t, RD_RESERVED_ROOT_DIR_FD, ReservedFd::RootDir);
+        fds.reserve_fd(t, tracee_socket_fd_number, ReservedFd::TraceeSocket);
     }
 
     /// Prepare this process and its ancestors for recording/replay by
@@ -2099,10 +2240,81 @@ kernel is too old.",
         // anything that happens from this point on gets filtered!
     }
 
+    /// Install the filter of the interception backend and send its listener
+    /// to rd over `sock_fd`. Unlike rd's own filter this can go in before rd
+    /// has taken ptrace control, since it never asks for a ptracer. Notified
+    /// syscalls just wait until rd gets the listener after the SIGSTOP.
+    fn set_up_notify_filter(prog: &sock_fprog, sock_fd: &ScopedFd, err_fd: &ScopedFd) {
+        let listener = unsafe {
+            syscall(
+                SYS_seccomp,
+                SECCOMP_SET_MODE_FILTER,
+                SECCOMP_FILTER_FLAG_NEW_LISTENER,
+                prog as *const _,
+            )
+        } as c_int;
+        if 0 > listener {
+            spawned_child_fatal_error(
+                err_fd,
+                "seccomp(SECCOMP_FILTER_FLAG_NEW_LISTENER) failed: --experimental-unotify\n\
+needs Linux 5.5 or later.",
+            );
+        }
+
+        let mut data: u8 = 0;
+        let mut iov = iovec {
+            iov_base: &raw mut data as *mut c_void,
+            iov_len: 1,
+        };
+        // Room for one fd, aligned for cmsghdr.
+        let mut cmsgbuf = [0u64; 4];
+        let mut msg: libc::msghdr = unsafe { zeroed() };
+        msg.msg_iov = &raw mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = cmsgbuf.as_mut_ptr().cast();
+        msg.msg_controllen = unsafe { libc::CMSG_SPACE(size_of::<c_int>() as u32) } as usize;
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&raw const msg);
+            (*cmsg).cmsg_level = SOL_SOCKET;
+            (*cmsg).cmsg_type = SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(size_of::<c_int>() as u32) as usize;
+            *(libc::CMSG_DATA(cmsg) as *mut c_int) = listener;
+        }
+        if 0 > unsafe { libc::sendmsg(sock_fd.as_raw(), &raw const msg, 0) } {
+            spawned_child_fatal_error(err_fd, "error sending the seccomp listener to rd");
+        }
+        unsafe { libc::close(listener) };
+    }
+
     fn dr_user_word_offset(i: usize) -> usize {
         debug_assert!(i < NUM_X86_DEBUG_REGS);
         offset_of!(user, u_debugreg) + size_of::<usize>() * i
     }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[test]
+        fn debug_control() {
+            let mut dr7 = DebugControl::default();
+            dr7.enable(0, WatchBytesX86::Bytes1, WatchType::WatchExec);
+            assert_eq!(0x1, dr7.get());
+
+            dr7.enable(1, WatchBytesX86::Bytes4, WatchType::WatchWrite);
+            assert_eq!(0x1 | 0x4 | (0x1 << 20) | (0x3 << 22), dr7.get());
+
+            dr7.enable(3, WatchBytesX86::Bytes8, WatchType::WatchReadWrite);
+            assert_eq!(
+                0x1 | 0x4 | 0x40 | (0x1 << 20) | (0x3 << 22) | (0x3 << 28) | (0x2 << 30),
+                dr7.get()
+            );
+
+            // Reprogramming a slot replaces its type and length.
+            dr7.enable(1, WatchBytesX86::Bytes1, WatchType::WatchExec);
+            assert_eq!(0x1 | 0x4 | 0x40 | (0x3 << 28) | (0x2 << 30), dr7.get());
+        }
+    }
 }
 
 fn preload_thread_locals_local_addr(as_: &AddressSpace) -> Option<NonNull<c_void>> {
@@ -2146,6 +2358,34 @@ fn setup_preload_thread_locals_arch<Arch: Architecture>(t: &TaskInner) {
     }
 }
 
+fn update_preload_thread_locals_fds_arch<Arch: Architecture>(t: &mut TaskInner) {
+    let (desched_fd_offset, cloned_file_data_fd_offset) = match Arch::arch() {
+        SupportedArch::X86 => (
+            offset_of!(x86_preload_thread_locals, desched_counter_fd),
+            offset_of!(x86_preload_thread_locals, cloned_file_data_fd),
+        ),
+        SupportedArch::X64 => (
+            offset_of!(x64_preload_thread_locals, desched_counter_fd),
+            offset_of!(x64_preload_thread_locals, cloned_file_data_fd),
+        ),
+    };
+    // If the task's thread locals aren't the active ones, they'll be copied
+    // into place from `t.thread_locals` when they are.
+    let locals: *mut u8 = if t.tuid() == t.vm().thread_locals_tuid() {
+        match preload_thread_locals_local_addr(t.vm()) {
+            Some(local_addr) => local_addr.as_ptr().cast::<u8>(),
+            None => return,
+        }
+    } else {
+        t.thread_locals.as_mut_ptr()
+    };
+    unsafe {
+        (locals.add(desched_fd_offset) as *mut i32).write_unaligned(t.desched_fd_child);
+        (locals.add(cloned_file_data_fd_offset) as *mut i32)
+            .write_unaligned(t.cloned_file_data_fd_child);
+    }
+}
+
 fn setup_preload_thread_locals_from_clone_arch<Arch: Architecture>(
     t: &mut TaskInner,
     origin: &mut TaskInner,
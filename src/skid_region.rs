@@ -0,0 +1,113 @@
+//! Replay gets a task to a recorded tick count by programming a ticks
+//! interrupt some distance short of it, the skid region, and stepping the
+//! rest of the way. The interrupt isn't precise: the task keeps running for
+//! a while after the counter overflows, and if it runs past the target the
+//! replay has diverged. `PerfCounters::skid_size()` is how far that is known
+//! to go on the CPU, but some tasks skid further (e.g. when the hypervisor
+//! delivers PMU interrupts late), so each task widens its own region when
+//! it gets close to the edge.
+
+use crate::{perf_counters::PerfCounters, ticks::Ticks};
+use std::{
+    cmp::{max, min},
+    fmt::{self, Display, Formatter},
+};
+
+/// A task's region never grows past this; the interrupts it's used for are
+/// meant to save single-stepping through most of the ticks.
+const MAX_SKID_MARGIN: Ticks = 1_000_000;
+
+/// How far short of a ticks target a task's interrupts are programmed.
+#[derive(Copy, Clone, Debug)]
+pub struct SkidRegion {
+    margin: Ticks,
+}
+
+impl Default for SkidRegion {
+    fn default() -> Self {
+        Self::new(PerfCounters::skid_size())
+    }
+}
+
+impl SkidRegion {
+    pub fn new(margin: Ticks) -> SkidRegion {
+        debug_assert!(margin > 0);
+        SkidRegion { margin }
+    }
+
+    pub fn margin(&self) -> Ticks {
+        self.margin
+    }
+
+    /// The task stopped `skid` ticks after the tick count its interrupt was
+    /// programmed for. Widen the region to twice that if the skid used up
+    /// more than half of it, since the next one may be worse. Returns true
+    /// if the region grew.
+    pub fn observe(&mut self, skid: Ticks) -> bool {
+        if skid <= self.margin / 2 {
+            return false;
+        }
+        let margin = min(MAX_SKID_MARGIN, max(self.margin, 2 * skid));
+        let grew = margin > self.margin;
+        self.margin = margin;
+        grew
+    }
+}
+
+/// How ticks interrupts behaved over a replay session.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SkidStats {
+    /// Interrupts programmed to advance to a ticks target.
+    pub interrupts: u64,
+    /// Stops after the tick count the interrupt was programmed for.
+    pub late_stops: u64,
+    /// The furthest any task went past its interrupt.
+    pub max_skid: Ticks,
+    /// Times a task's skid region was widened.
+    pub widened: u64,
+    /// Ticks targets overshot despite the skid region.
+    pub overshoots: u64,
+}
+
+impl SkidStats {
+    pub fn note_interrupt(&mut self, skid: Ticks, widened: bool) {
+        self.interrupts += 1;
+        if skid > 0 {
+            self.late_stops += 1;
+            self.max_skid = max(self.max_skid, skid);
+        }
+        if widened {
+            self.widened += 1;
+        }
+    }
+}
+
+impl Display for SkidStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ticks interrupts, {} stopped late (by up to {} ticks), {} skid regions widened, \
+             {} targets overshot",
+            self.interrupts, self.late_stops, self.max_skid, self.widened, self.overshoots
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn widen_near_edge() {
+        let mut region = SkidRegion::new(100);
+        assert!(!region.observe(0));
+        assert!(!region.observe(50));
+        assert_eq!(region.margin(), 100);
+        assert!(region.observe(80));
+        assert_eq!(region.margin(), 160);
+        assert!(!region.observe(60));
+        assert_eq!(region.margin(), 160);
+        assert!(region.observe(MAX_SKID_MARGIN));
+        assert_eq!(region.margin(), MAX_SKID_MARGIN);
+    }
+}
@@ -0,0 +1,400 @@
+//! How the record loop finds out about tracee syscalls. By default rd's
+//! seccomp filter turns every syscall rd needs to see into a ptrace stop.
+//! With `--experimental-unotify`, a second filter in the tracee hands a few
+//! syscalls to a seccomp user notification listener instead, and rd answers
+//! them without stopping the tracee. The record loop drives whichever
+//! `InterceptionBackend` the session has.
+
+use crate::{
+    arch::Architecture,
+    kernel_abi::{SupportedArch, RD_NATIVE_ARCH},
+    log::LogLevel::{LogDebug, LogWarn},
+    remote_code_ptr::RemoteCodePtr,
+    seccomp_bpf::{audit_arch, SeccompFilter},
+    seccomp_unotify::{seccomp_notif, SeccompNotifyListener},
+    session::address_space::{address_space::AddressSpace, Traced},
+};
+use libc::{pid_t, pollfd, ENOENT, POLLIN};
+use std::{fs, os::unix::io::RawFd};
+
+/// A syscall a backend completes without a ptrace stop. The record loop
+/// records it as an intercepted syscall event so replay can supply the same
+/// result. The task is blocked at the syscall's entry until the backend
+/// answers, so its tick count is still the one the event needs.
+pub struct InterceptedSyscall {
+    /// The tid in rd's pid namespace, i.e. the task's rec tid.
+    pub tid: pid_t,
+    pub arch: SupportedArch,
+    pub syscallno: i32,
+    pub ip: RemoteCodePtr,
+    pub args: [u64; 6],
+    /// What the syscall returns, or -errno.
+    pub result: i64,
+}
+
+pub trait InterceptionBackend {
+    fn name(&self) -> &'static str;
+
+    /// A filter to install in the initial tracee, with a new listener, in
+    /// addition to rd's own filter. It's built before forking, so it can't
+    /// depend on the tracee.
+    fn tracee_filter(&self) -> Option<SeccompFilter> {
+        None
+    }
+
+    /// The listener of the filter from `tracee_filter()`.
+    fn set_listener(&mut self, _listener: SeccompNotifyListener) {}
+
+    /// An fd the record loop should poll while it waits for ptrace stops.
+    /// It's readable when `handle_pending()` has something to do.
+    fn poll_fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    /// Deal with the syscalls waiting on the backend, without blocking.
+    /// `record` is called for each one before its task can see the result.
+    fn handle_pending(&mut self, _record: &mut dyn FnMut(&InterceptedSyscall)) {}
+}
+
+/// Every syscall rd sees is a ptrace stop.
+#[derive(Default)]
+pub struct PtraceInterception;
+
+impl InterceptionBackend for PtraceInterception {
+    fn name(&self) -> &'static str {
+        "ptrace"
+    }
+}
+
+/// Syscalls whose results rd can supply without stopping the tracee, and
+/// that don't change any state replay has to reproduce.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum NotifiedSyscall {
+    Gettid,
+    Getpid,
+    Getppid,
+    Getuid,
+    Geteuid,
+    Getgid,
+    Getegid,
+    /// Allowed to run, since it always returns 0.
+    SchedYield,
+}
+
+const NOTIFIED_SYSCALLS: [NotifiedSyscall; 8] = [
+    NotifiedSyscall::Gettid,
+    NotifiedSyscall::Getpid,
+    NotifiedSyscall::Getppid,
+    NotifiedSyscall::Getuid,
+    NotifiedSyscall::Geteuid,
+    NotifiedSyscall::Getgid,
+    NotifiedSyscall::Getegid,
+    NotifiedSyscall::SchedYield,
+];
+
+impl NotifiedSyscall {
+    fn syscallno(self, arch: SupportedArch) -> i32 {
+        rd_arch_function_selfless!(notified_syscallno_arch, arch, self)
+    }
+
+    fn from_syscallno(syscallno: i32, arch: SupportedArch) -> Option<NotifiedSyscall> {
+        NOTIFIED_SYSCALLS
+            .iter()
+            .copied()
+            .find(|s| s.syscallno(arch) == syscallno)
+    }
+}
+
+fn notified_syscallno_arch<Arch: Architecture>(syscall: NotifiedSyscall) -> i32 {
+    // x86's 16-bit getuid() and friends mangle large ids; only the 32-bit
+    // versions are notified there.
+    let with_32 = |no32: i32, no: i32| if no32 >= 0 { no32 } else { no };
+    match syscall {
+        NotifiedSyscall::Gettid => Arch::GETTID,
+        NotifiedSyscall::Getpid => Arch::GETPID,
+        NotifiedSyscall::Getppid => Arch::GETPPID,
+        NotifiedSyscall::Getuid => with_32(Arch::GETUID32, Arch::GETUID),
+        NotifiedSyscall::Geteuid => with_32(Arch::GETEUID32, Arch::GETEUID),
+        NotifiedSyscall::Getgid => with_32(Arch::GETGID32, Arch::GETGID),
+        NotifiedSyscall::Getegid => with_32(Arch::GETEGID32, Arch::GETEGID),
+        NotifiedSyscall::SchedYield => Arch::SCHED_YIELD,
+    }
+}
+
+/// Answer `NOTIFIED_SYSCALLS` through a seccomp user notification listener.
+#[derive(Default)]
+pub struct UnotifyInterception {
+    listener: Option<SeccompNotifyListener>,
+}
+
+impl InterceptionBackend for UnotifyInterception {
+    fn name(&self) -> &'static str {
+        "unotify"
+    }
+
+    fn tracee_filter(&self) -> Option<SeccompFilter> {
+        let mut f = SeccompFilter::new();
+        // Syscalls from the syscallbuf are recorded by the syscallbuf.
+        for e in AddressSpace::rd_page_syscalls() {
+            if e.traced == Traced::Untraced {
+                let ip =
+                    AddressSpace::rd_page_syscall_exit_point(e.traced, e.privileged, e.enabled);
+                f.allow_syscalls_from_callsite(ip);
+            }
+        }
+        let mut archs = vec![SupportedArch::X86];
+        if RD_NATIVE_ARCH == SupportedArch::X64 {
+            archs.push(SupportedArch::X64);
+        }
+        for arch in archs {
+            let syscallnos: Vec<i32> = NOTIFIED_SYSCALLS
+                .iter()
+                .map(|s| s.syscallno(arch))
+                .collect();
+            f.notify_syscalls_for_arch(audit_arch(arch), &syscallnos);
+        }
+        f.allow();
+        Some(f)
+    }
+
+    fn set_listener(&mut self, listener: SeccompNotifyListener) {
+        self.listener = Some(listener);
+    }
+
+    fn poll_fd(&self) -> Option<RawFd> {
+        self.listener.as_ref().map(|l| l.as_raw())
+    }
+
+    fn handle_pending(&mut self, record: &mut dyn FnMut(&InterceptedSyscall)) {
+        let listener = match self.listener.as_ref() {
+            Some(listener) => listener,
+            None => return,
+        };
+        while has_pending(listener) {
+            let notif = match listener.recv() {
+                Ok(notif) => notif,
+                // The syscall went away before we got to it.
+                Err(ENOENT) => continue,
+                Err(e) => {
+                    fatal!("SECCOMP_IOCTL_NOTIF_RECV failed: errno {}", e);
+                    unreachable!()
+                }
+            };
+            handle_notification(listener, &notif, record);
+        }
+    }
+}
+
+fn has_pending(listener: &SeccompNotifyListener) -> bool {
+    let mut pfd = pollfd {
+        fd: listener.as_raw(),
+        events: POLLIN,
+        revents: 0,
+    };
+    let ret = unsafe { libc::poll(&mut pfd, 1, 0) };
+    ret > 0 && pfd.revents & POLLIN != 0
+}
+
+/// Answer, or let run, the syscall of `notif`, after handing it to `record`.
+/// Does nothing if its task died before it could be answered.
+fn handle_notification(
+    listener: &SeccompNotifyListener,
+    notif: &seccomp_notif,
+    record: &mut dyn FnMut(&InterceptedSyscall),
+) {
+    let tid = notif.pid as pid_t;
+    let arch = if notif.data.arch == audit_arch(SupportedArch::X86) {
+        SupportedArch::X86
+    } else {
+        SupportedArch::X64
+    };
+    let syscall = match NotifiedSyscall::from_syscallno(notif.data.nr, arch) {
+        Some(syscall) => syscall,
+        None => {
+            fatal!(
+                "Notified of syscall {} the filter shouldn't notify",
+                notif.data.nr
+            );
+            unreachable!()
+        }
+    };
+    let result = loop {
+        let maybe_result = match syscall {
+            NotifiedSyscall::SchedYield => Some(0),
+            _ => answer(syscall, tid),
+        };
+        // Make sure what we read from /proc was about this task. If we
+        // couldn't read it but the task is still waiting, something like
+        // its parent exited under us; try again.
+        if !listener.id_valid(notif.id) {
+            return;
+        }
+        if let Some(result) = maybe_result {
+            break result;
+        }
+    };
+
+    record(&InterceptedSyscall {
+        tid,
+        arch,
+        syscallno: notif.data.nr,
+        ip: RemoteCodePtr::from_val(notif.data.instruction_pointer as usize),
+        args: notif.data.args,
+        result,
+    });
+
+    let ret = match syscall {
+        NotifiedSyscall::SchedYield => listener.continue_syscall(notif.id),
+        _ => listener.respond(notif.id, result, 0),
+    };
+    match ret {
+        Ok(()) => log!(
+            LogDebug,
+            "{}: {:?} completed without a ptrace stop, result {}",
+            tid,
+            syscall,
+            result
+        ),
+        // The task was killed, or a signal interrupted the syscall, since
+        // id_valid(). The recorded result is only right for the first.
+        Err(ENOENT) => log!(
+            LogWarn,
+            "{}: {:?} went away after it was recorded",
+            tid,
+            syscall
+        ),
+        Err(e) => {
+            fatal!("Can't complete notified syscall of {}: errno {}", tid, e);
+            unreachable!()
+        }
+    }
+}
+
+/// What the id syscall `syscall` made by `tid` returns, as the tracee sees
+/// it: pids in its pid namespace and ids in its user namespace, neither of
+/// which need be rd's. `tid` is in rd's pid namespace.
+fn answer(syscall: NotifiedSyscall, tid: pid_t) -> Option<i64> {
+    let status = proc_status(tid)?;
+    match syscall {
+        // The last NSpid/NStgid field is the one in the task's innermost pid
+        // namespace.
+        NotifiedSyscall::Gettid => status_fields(&status, "NSpid:")?.last().copied(),
+        NotifiedSyscall::Getpid => status_fields(&status, "NStgid:")?.last().copied(),
+        NotifiedSyscall::Getppid => {
+            let ppid = status_field(&status, "PPid:", 0)?;
+            if ppid == 0 {
+                return Some(0);
+            }
+            let parent_status = proc_status(ppid as pid_t)?;
+            Some(ns_ppid(
+                &status_fields(&status, "NSpid:")?,
+                &status_fields(&parent_status, "NSpid:")?,
+            ))
+        }
+        NotifiedSyscall::Getuid
+        | NotifiedSyscall::Geteuid
+        | NotifiedSyscall::Getgid
+        | NotifiedSyscall::Getegid => {
+            let (key, index, map_file) = match syscall {
+                NotifiedSyscall::Getuid => ("Uid:", 0, "uid_map"),
+                NotifiedSyscall::Geteuid => ("Uid:", 1, "uid_map"),
+                NotifiedSyscall::Getgid => ("Gid:", 0, "gid_map"),
+                _ => ("Gid:", 1, "gid_map"),
+            };
+            // As seen from rd's user namespace.
+            let id = status_field(&status, key, index)?;
+            let own_ns = fs::read_link("/proc/self/ns/user").ok()?;
+            let tracee_ns = fs::read_link(format!("/proc/{}/ns/user", tid)).ok()?;
+            if own_ns == tracee_ns {
+                return Some(id);
+            }
+            let map = fs::read_to_string(format!("/proc/{}/{}", tid, map_file)).ok()?;
+            Some(map_id_into_ns(&map, id))
+        }
+        NotifiedSyscall::SchedYield => unreachable!(),
+    }
+}
+
+fn proc_status(tid: pid_t) -> Option<String> {
+    fs::read_to_string(format!("/proc/{}/status", tid)).ok()
+}
+
+/// The fields of the line starting with `key` in a /proc/<tid>/status.
+fn status_fields(status: &str, key: &str) -> Option<Vec<i64>> {
+    let line = status.lines().find(|l| l.starts_with(key))?;
+    line[key.len()..]
+        .split_whitespace()
+        .map(|f| f.parse().ok())
+        .collect()
+}
+
+/// Field `index` of the line starting with `key` in a /proc/<tid>/status.
+fn status_field(status: &str, key: &str, index: usize) -> Option<i64> {
+    status_fields(status, key)?.get(index).copied()
+}
+
+/// getppid() for a task whose NSpid line is `nspids`, with a parent whose
+/// NSpid line is `parent_nspids`. A parent outside the task's pid namespace
+/// shows up as 0.
+fn ns_ppid(nspids: &[i64], parent_nspids: &[i64]) -> i64 {
+    if nspids.len() == parent_nspids.len() {
+        *parent_nspids.last().unwrap()
+    } else {
+        0
+    }
+}
+
+/// The id the kernel shows for unmapped ids, /proc/sys/kernel/overflowuid's
+/// default.
+const OVERFLOW_ID: i64 = 65534;
+
+/// `id`, as seen from rd's user namespace, in the user namespace with the
+/// uid_map or gid_map `map` (as read by rd).
+fn map_id_into_ns(map: &str, id: i64) -> i64 {
+    for line in map.lines() {
+        let fields: Vec<i64> = line
+            .split_whitespace()
+            .filter_map(|f| f.parse().ok())
+            .collect();
+        if let &[inside, outside, count] = fields.as_slice() {
+            if outside <= id && id < outside + count {
+                return inside + id - outside;
+            }
+        }
+    }
+    OVERFLOW_ID
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn proc_status_fields() {
+        let status = "Name:\tcat\nTgid:\t1234\nPid:\t1235\nPPid:\t1\n\
+                      Uid:\t1000\t1001\t1000\t1000\nGid:\t100\t101\t100\t100\n";
+        assert_eq!(status_field(status, "Tgid:", 0), Some(1234));
+        assert_eq!(status_field(status, "PPid:", 0), Some(1));
+        assert_eq!(status_field(status, "Uid:", 1), Some(1001));
+        assert_eq!(status_field(status, "Gid:", 0), Some(100));
+        assert_eq!(status_field(status, "Gid:", 4), None);
+        assert_eq!(status_field(status, "TracerPid:", 0), None);
+    }
+
+    #[test]
+    fn namespace_ids() {
+        let status = "Tgid:\t1234\nPPid:\t1200\nNStgid:\t1234\t5\nNSpid:\t1235\t6\n";
+        assert_eq!(status_fields(status, "NSpid:"), Some(vec![1235, 6]));
+        assert_eq!(status_fields(status, "NStgid:").unwrap().last(), Some(&5));
+        // The parent is in the same pid namespace.
+        assert_eq!(ns_ppid(&[1235, 6], &[1200, 1]), 1);
+        // The parent is the process that created the namespace.
+        assert_eq!(ns_ppid(&[1235, 1], &[1200]), 0);
+
+        let map = "         0       1000          1\n         1     100000      65536\n";
+        assert_eq!(map_id_into_ns(map, 1000), 0);
+        assert_eq!(map_id_into_ns(map, 100005), 6);
+        assert_eq!(map_id_into_ns(map, 1001), OVERFLOW_ID);
+        assert_eq!(map_id_into_ns("0 0 4294967295\n", 1000), 1000);
+    }
+}
@@ -0,0 +1,287 @@
+//! Temporary files rd creates on disk: shared memory segments, emulated
+//! files on kernels without memfd_create() and scratch files handed to
+//! tracees. They all live under a directory named after the rd process,
+//! with a subdirectory per session, and are unlinked as soon as they're
+//! open. Whatever is left is removed when the session goes away, when rd
+//! exits or aborts, and, if rd was killed before it could clean up, by the
+//! next rd that starts.
+//!
+//! rd processes may share `tmp_dir()` without seeing each other's pids, e.g.
+//! parallel CI jobs in containers with their own pid namespaces. So process
+//! directory names have a random part besides the pid, and whether the rd
+//! process of a directory is still around is decided by a lock it holds on
+//! a file in there.
+
+use crate::{
+    log::LogLevel::{LogDebug, LogWarn},
+    scoped_fd::ScopedFd,
+    util::tmp_dir,
+};
+use nix::{
+    fcntl::{flock, FlockArg::LockExclusiveNonblock, OFlag},
+    sys::stat::Mode,
+    unistd::{getpid, getuid, unlink},
+};
+use rand::random;
+use std::{
+    cell::RefCell,
+    ffi::{OsStr, OsString},
+    fs,
+    fs::DirBuilder,
+    io,
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{DirBuilderExt, MetadataExt},
+    },
+    path::Path,
+    sync::{Mutex, Once},
+};
+
+/// The directories of rd processes are called `rd-tmp-<pid>-<random hex>`.
+/// Older versions of rd left out the random part.
+const PROCESS_DIR_PREFIX: &str = "rd-tmp-";
+
+/// The file in each process directory its rd process holds an exclusive
+/// flock() lock on until it exits.
+const LOCK_FILE_NAME: &str = "lock";
+
+lazy_static! {
+    /// The directory of this rd process, once it has been created.
+    static ref PROCESS_DIR: Mutex<Option<OsString>> = Mutex::new(None);
+}
+
+static INSTALL_ATEXIT: Once = Once::new();
+
+/// The temporary files of one session, in a directory that's created the
+/// first time it's needed. Dropping this removes the directory and anything
+/// left in it.
+pub struct TempResources {
+    session_id: u32,
+    dir: RefCell<Option<OsString>>,
+}
+
+impl TempResources {
+    pub fn new(session_id: u32) -> TempResources {
+        TempResources {
+            session_id,
+            dir: RefCell::new(None),
+        }
+    }
+
+    /// The session's directory, without a trailing '/'. Tracees open files
+    /// in it through `reserved_root_dir_fd()`, so it has to stay reachable
+    /// from their root directory.
+    pub fn dir(&self) -> OsString {
+        if let Some(dir) = self.dir.borrow().as_ref() {
+            return dir.clone();
+        }
+        let mut dir = process_dir();
+        dir.push(format!("/session-{}", self.session_id));
+        create_private_dir(&dir);
+        *self.dir.borrow_mut() = Some(dir.clone());
+        dir
+    }
+}
+
+impl Drop for TempResources {
+    fn drop(&mut self) {
+        if let Some(dir) = self.dir.borrow_mut().take() {
+            remove_dir(&dir);
+        }
+    }
+}
+
+/// Create a file nobody else can open: with O_TMPFILE if `dir`'s filesystem
+/// supports it, otherwise under `name` in `dir`, unlinked right after it's
+/// open. `name` is only used for the fallback. Returns an fd that isn't open
+/// on failure.
+pub fn create_unlinked_file(dir: &OsStr, name: &OsStr) -> ScopedFd {
+    let fd = ScopedFd::open_path_with_mode(
+        dir,
+        OFlag::O_TMPFILE | OFlag::O_RDWR | OFlag::O_CLOEXEC,
+        Mode::S_IRUSR | Mode::S_IWUSR,
+    );
+    if fd.is_open() {
+        return fd;
+    }
+    // Linux < 3.11, or a filesystem without O_TMPFILE support.
+    let mut path = dir.to_os_string();
+    path.push("/");
+    path.push(name);
+    let fd = ScopedFd::open_path_with_mode(
+        path.as_os_str(),
+        OFlag::O_CREAT | OFlag::O_EXCL | OFlag::O_RDWR | OFlag::O_CLOEXEC,
+        Mode::S_IRUSR | Mode::S_IWUSR,
+    );
+    if fd.is_open() {
+        unlink(path.as_os_str()).unwrap_or(());
+    }
+    fd
+}
+
+/// The directory of this rd process, created on first use. Sessions put
+/// their directories in it; files that don't belong to a session can go
+/// directly in it.
+pub fn process_dir() -> OsString {
+    let mut maybe_dir = PROCESS_DIR.lock().unwrap();
+    if let Some(dir) = maybe_dir.as_ref() {
+        return dir.clone();
+    }
+    let mut dir = tmp_dir();
+    dir.push(format!(
+        "/{}{}-{:016x}",
+        PROCESS_DIR_PREFIX,
+        getpid(),
+        random::<u64>()
+    ));
+    create_private_dir(&dir);
+    lock_process_dir(&dir);
+    INSTALL_ATEXIT.call_once(|| {
+        let ret = unsafe { libc::atexit(remove_process_dir_at_exit) };
+        assert_eq!(ret, 0);
+    });
+    *maybe_dir = Some(dir.clone());
+    dir
+}
+
+/// Remove everything this rd process has left on disk. Called on the way
+/// out, including from `notifying_abort()`, so it must not block: if the
+/// lock is held we're aborting from inside this module and give up.
+pub fn remove_process_dir() {
+    if let Ok(mut maybe_dir) = PROCESS_DIR.try_lock() {
+        if let Some(dir) = maybe_dir.take() {
+            remove_dir(&dir);
+        }
+    }
+}
+
+extern "C" fn remove_process_dir_at_exit() {
+    remove_process_dir();
+}
+
+/// Take the lock on our process directory `dir`. The fd is left open, so
+/// the lock is held until we exit; it's close-on-exec so tracees don't hold
+/// it for us.
+fn lock_process_dir(dir: &OsStr) {
+    let path = Path::new(dir).join(LOCK_FILE_NAME);
+    let mut fd = ScopedFd::open_path_with_mode(
+        &path,
+        OFlag::O_CREAT | OFlag::O_RDWR | OFlag::O_CLOEXEC,
+        Mode::S_IRUSR | Mode::S_IWUSR,
+    );
+    if !fd.is_open() || flock(fd.as_raw(), LockExclusiveNonblock).is_err() {
+        fatal!("Can't lock temporary directory {:?}", dir);
+    }
+    fd.extract();
+}
+
+/// Whether the rd process that `dir` belongs to may still be running.
+///
+/// Without a lock file, `dir` is either from an older rd or from one that
+/// hasn't taken its lock yet. Its pid can't tell us either way: that rd may
+/// be in another pid namespace, where the same number means a different
+/// process. So such directories are left alone.
+fn process_dir_in_use(dir: &Path) -> bool {
+    let fd = ScopedFd::open_path(
+        &dir.join(LOCK_FILE_NAME),
+        OFlag::O_RDONLY | OFlag::O_CLOEXEC,
+    );
+    if fd.is_open() {
+        return flock(fd.as_raw(), LockExclusiveNonblock).is_err();
+    }
+    true
+}
+
+/// Remove the directories of rd processes that are gone. Only directories
+/// owned by us are considered; `tmp_dir()` may be shared with other users.
+pub fn sweep_stale_process_dirs() {
+    let dir = tmp_dir();
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let uid = getuid().as_raw();
+    for entry in entries.filter_map(Result::ok) {
+        let pid = match stale_dir_pid(&entry.file_name()) {
+            Some(pid) => pid,
+            None => continue,
+        };
+        match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() && metadata.uid() == uid => (),
+            _ => continue,
+        }
+        if process_dir_in_use(&entry.path()) {
+            continue;
+        }
+        log!(
+            LogDebug,
+            "Removing {:?} left behind by rd process {}",
+            entry.path(),
+            pid
+        );
+        remove_dir(entry.path().as_os_str());
+    }
+}
+
+/// The pid in a process directory name, if `name` is one.
+fn stale_dir_pid(name: &OsStr) -> Option<libc::pid_t> {
+    let prefix = PROCESS_DIR_PREFIX.as_bytes();
+    if !name.as_bytes().starts_with(prefix) {
+        return None;
+    }
+    let rest = &name.as_bytes()[prefix.len()..];
+    let pid_part = rest.split(|&c| c == b'-').next()?;
+    let pid = std::str::from_utf8(pid_part).ok()?.parse().ok()?;
+    if pid > 0 {
+        Some(pid)
+    } else {
+        None
+    }
+}
+
+/// Create `dir`, accessible only to us. If it already exists it must be a
+/// directory (not a symlink to one) that we own, with mode 0700; otherwise
+/// someone else could read or replace the files we put there.
+fn create_private_dir(dir: &OsStr) {
+    match DirBuilder::new().mode(0o700).create(dir) {
+        Ok(()) => (),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => match fs::symlink_metadata(dir) {
+            Ok(metadata)
+                if metadata.file_type().is_dir()
+                    && metadata.uid() == getuid().as_raw()
+                    && metadata.mode() & 0o7777 == 0o700 => {}
+            Ok(_) => fatal!(
+                "Temporary directory {:?} exists but isn't a private directory of ours",
+                dir
+            ),
+            Err(e) => fatal!("Can't stat temporary directory {:?}: {}", dir, e),
+        },
+        Err(e) => fatal!("Can't create temporary directory {:?}: {}", dir, e),
+    }
+}
+
+fn remove_dir(dir: &OsStr) {
+    match fs::remove_dir_all(dir) {
+        Ok(()) => (),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => log!(LogWarn, "Can't remove temporary directory {:?}: {}", dir, e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn process_dir_names() {
+        assert_eq!(stale_dir_pid(OsStr::new("rd-tmp-1234")), Some(1234));
+        assert_eq!(
+            stale_dir_pid(OsStr::new("rd-tmp-1234-00c0ffee00c0ffee")),
+            Some(1234)
+        );
+        assert_eq!(stale_dir_pid(OsStr::new("rd-tmp-0")), None);
+        assert_eq!(stale_dir_pid(OsStr::new("rd-tmp-")), None);
+        assert_eq!(stale_dir_pid(OsStr::new("rd-tmp-12x")), None);
+        assert_eq!(stale_dir_pid(OsStr::new("rd-shared-syscallbuf")), None);
+    }
+}
@@ -1,6 +1,10 @@
 use crate::{
     log::LogLevel::LogDebug,
-    session::{task::Task, SessionSharedPtr, SessionSharedWeakPtr},
+    session::{
+        task::{Task, TaskSharedWeakPtr},
+        SessionSharedPtr,
+        SessionSharedWeakPtr,
+    },
     taskish_uid::ThreadGroupUid,
     wait_status::WaitStatus,
     weak_ptr_set::WeakPtrSet,
@@ -39,6 +43,11 @@ pub struct ThreadGroup {
     /// Whether this thread group has execed
     pub execed: bool,
 
+    /// The leader exited while other threads of the process carried on. The
+    /// kernel keeps it as a zombie, and doesn't report its exit to
+    /// waitpid(), until they have all exited too.
+    pub zombie_leader: bool,
+
     /// True when a task in the task-group received a SIGSEGV because we
     /// couldn't push a signal handler frame. Only used during recording.
     pub received_sigframe_sigsegv: bool,
@@ -111,6 +120,7 @@ impl ThreadGroup {
             real_tgid_own_namespace,
             dumpable: true,
             execed: false,
+            zombie_leader: false,
             received_sigframe_sigsegv: false,
             session_: session.clone(),
             parent_: maybe_parent,
@@ -213,6 +223,11 @@ impl ThreadGroup {
         }
     }
 
+    /// Whether `t` is the last task of this thread group.
+    pub fn is_only_task(&self, t: TaskSharedWeakPtr) -> bool {
+        self.tasks.iter_except(t).next().is_none()
+    }
+
     pub fn session(&self) -> SessionSharedPtr {
         self.session_.upgrade().unwrap()
     }
@@ -1,7 +1,11 @@
 pub mod compressed_reader;
 pub mod compressed_writer;
+pub mod file_hashes;
+pub mod trace_event_index;
+pub mod trace_exec_history;
 pub mod trace_frame;
 pub mod trace_reader;
 pub mod trace_stream;
 pub mod trace_task_event;
+pub mod trace_write_index;
 pub mod trace_writer;
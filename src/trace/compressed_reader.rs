@@ -1,6 +1,6 @@
 use crate::{
     scoped_fd::{ScopedFd, ScopedFdSharedPtr},
-    trace::compressed_writer::BlockHeader,
+    trace::compressed_writer::{BlockHeader, Compression},
     util::read_to_end,
 };
 use brotli_sys::{BrotliDecoderDecompress, BROTLI_DECODER_RESULT_SUCCESS};
@@ -33,6 +33,9 @@ pub struct CompressedReader {
     eof: bool,
     buffer: Vec<u8>,
     buffer_read_pos: usize,
+    /// Offset of the start of `buffer` in the uncompressed stream.
+    buffer_start_pos: u64,
+    compression: Compression,
     // Note that the struct members for saving state are not here as we have a separate struct
     // to handle that
 }
@@ -76,6 +79,7 @@ pub struct CompressedReaderState {
     saved_fd_offset: u64,
     saved_buffer: Vec<u8>,
     saved_buffer_read_pos: usize,
+    saved_buffer_start_pos: u64,
 }
 
 impl Default for CompressedReaderState {
@@ -84,6 +88,7 @@ impl Default for CompressedReaderState {
             saved_fd_offset: 0,
             saved_buffer: vec![],
             saved_buffer_read_pos: 0,
+            saved_buffer_start_pos: 0,
         }
     }
 }
@@ -126,9 +131,21 @@ impl CompressedReader {
             eof,
             buffer: Vec::new(),
             buffer_read_pos,
+            buffer_start_pos: 0,
+            compression: Default::default(),
         }
     }
 
+    /// The compression the trace header says the file was written with.
+    /// Must be called before the first read.
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
     pub fn at_end(&self) -> bool {
         self.eof && self.buffer_read_pos == self.buffer.len()
     }
@@ -157,9 +174,50 @@ impl CompressedReader {
     pub fn rewind(&mut self) {
         self.fd_offset = 0;
         self.buffer_read_pos = 0;
+        self.buffer_start_pos = 0;
         self.buffer.clear();
         self.eof = false;
     }
+
+    /// The current read position in the uncompressed stream.
+    pub fn uncompressed_pos(&self) -> u64 {
+        self.buffer_start_pos + self.buffer_read_pos as u64
+    }
+
+    /// Move the read position to `pos` in the uncompressed stream. Only the
+    /// block containing `pos` is decompressed; the blocks before it are
+    /// skipped using their headers.
+    pub fn seek(&mut self, pos: u64) -> io::Result<()> {
+        self.rewind();
+        let mut header_arr = [0u8; size_of::<BlockHeader>()];
+        loop {
+            let block_offset = self.fd_offset;
+            if !read_all(
+                &self.fd.as_ref().unwrap().borrow(),
+                &mut header_arr,
+                &mut self.fd_offset,
+            )? {
+                self.fd_offset = block_offset;
+                if pos == self.buffer_start_pos {
+                    self.eof = true;
+                    return Ok(());
+                }
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Tried to seek past the end of a CompressedReader",
+                ));
+            }
+            let header: BlockHeader = unsafe { transmute(header_arr) };
+            if pos < self.buffer_start_pos + header.uncompressed_length as u64 {
+                self.fd_offset = block_offset;
+                self.refill_buffer()?;
+                self.buffer_read_pos = (pos - self.buffer_start_pos) as usize;
+                return Ok(());
+            }
+            self.buffer_start_pos += header.uncompressed_length as u64;
+            self.fd_offset += header.compressed_length as u64;
+        }
+    }
     pub fn close(&mut self) {
         self.fd.take();
     }
@@ -172,6 +230,7 @@ impl CompressedReader {
             saved_fd_offset: self.fd_offset,
             saved_buffer: self.buffer.clone(),
             saved_buffer_read_pos: self.buffer_read_pos,
+            saved_buffer_start_pos: self.buffer_start_pos,
         }
     }
     /// Restore previously obtained state.
@@ -183,6 +242,7 @@ impl CompressedReader {
         self.fd_offset = state.saved_fd_offset;
         self.buffer = state.saved_buffer;
         self.buffer_read_pos = state.saved_buffer_read_pos;
+        self.buffer_start_pos = state.saved_buffer_start_pos;
     }
 
     /// Gathers stats on the file stream. These are independent of what's
@@ -267,9 +327,14 @@ impl CompressedReader {
             Err(e) => return Err(io::Error::new(ErrorKind::Other, e)),
         };
 
+        self.buffer_start_pos += self.buffer.len() as u64;
         self.buffer.resize(header.uncompressed_length as usize, 0);
         self.buffer_read_pos = 0;
-        if !do_decompress(compressed_buf.as_slice(), &mut self.buffer) {
+        let decompressed = match self.compression {
+            Compression::Brotli => do_decompress(compressed_buf.as_slice(), &mut self.buffer),
+            Compression::Zstd => do_decompress_zstd(compressed_buf.as_slice(), &mut self.buffer),
+        };
+        if !decompressed {
             return Err(io::Error::new(
                 io::ErrorKind::Other,
                 "Decompression Error. @TODO",
@@ -307,6 +372,16 @@ pub fn do_decompress(compressed: &[u8], uncompressed: &mut [u8]) -> bool {
     decompress_result == BROTLI_DECODER_RESULT_SUCCESS && out_size == uncompressed.len()
 }
 
+pub fn do_decompress_zstd(compressed: &[u8], uncompressed: &mut [u8]) -> bool {
+    match zstd::stream::decode_all(compressed) {
+        Ok(data) if data.len() == uncompressed.len() => {
+            uncompressed.copy_from_slice(&data);
+            true
+        }
+        _ => false,
+    }
+}
+
 impl BufRead for CompressedReader {
     fn fill_buf(&mut self) -> io::Result<&[u8]> {
         // If available to read bytes are "empty" and we have not yet reached EOF
@@ -0,0 +1,127 @@
+//! Content hashes of the files replay takes data from instead of the trace
+//! streams: files mmap records are backed by (clones, copies, hardlinks, or
+//! the original files rd assumed wouldn't change) and the cloned-data files
+//! of reads. A clone survives the original being rewritten, but a hardlink
+//! or the original itself doesn't, e.g. when a user rebuilds a binary between
+//! recording and replay. Replay then maps different bytes than the tracee
+//! saw and diverges somewhere later, with nothing pointing at the cause.
+//!
+//! So recording hashes every such file when the trace is closed, and replay
+//! checks a file's hash the first time it uses the file.
+//!
+//! The hashes are stored in the `file_hashes` file in the trace directory,
+//! as `<hash> <backing file>\0<original file>\0` for each file, with the hash
+//! in hex. Traces without the file aren't checked.
+
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    fs::File,
+    io,
+    io::Read,
+    os::unix::ffi::OsStrExt,
+    str,
+};
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x100_0000_01b3;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FileHash {
+    /// The file the tracee mapped or read, as the recording saw it.
+    pub original: OsString,
+    pub hash: u64,
+}
+
+/// The 64-bit FNV-1a hash of the contents of the file at `path`.
+pub fn hash_file(path: &OsStr) -> io::Result<u64> {
+    let mut f = File::open(path)?;
+    let mut hash = FNV_OFFSET_BASIS;
+    let mut buf = vec![0u8; 1 << 16];
+    loop {
+        let nread = f.read(&mut buf)?;
+        if nread == 0 {
+            return Ok(hash);
+        }
+        hash = fnv1a(hash, &buf[..nread]);
+    }
+}
+
+fn fnv1a(mut hash: u64, data: &[u8]) -> u64 {
+    for &b in data {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+/// The contents of a `file_hashes` file for `hashes`, by backing file name.
+pub fn format_file_hashes<'a>(
+    hashes: impl Iterator<Item = (&'a OsString, &'a FileHash)>,
+) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (backing, h) in hashes {
+        out.extend_from_slice(format!("{:016x} ", h.hash).as_bytes());
+        out.extend_from_slice(backing.as_bytes());
+        out.push(0);
+        out.extend_from_slice(h.original.as_bytes());
+        out.push(0);
+    }
+    out
+}
+
+/// Parse a `file_hashes` file. Malformed entries are skipped.
+pub fn parse_file_hashes(data: &[u8]) -> HashMap<OsString, FileHash> {
+    let mut hashes = HashMap::new();
+    let mut fields = data.split(|&b| b == 0);
+    while let (Some(entry), Some(original)) = (fields.next(), fields.next()) {
+        if entry.len() < 17 || entry[16] != b' ' {
+            continue;
+        }
+        let hash = match str::from_utf8(&entry[..16])
+            .ok()
+            .and_then(|s| u64::from_str_radix(s, 16).ok())
+        {
+            Some(hash) => hash,
+            None => continue,
+        };
+        hashes.insert(
+            OsStr::from_bytes(&entry[17..]).to_os_string(),
+            FileHash {
+                original: OsStr::from_bytes(original).to_os_string(),
+                hash,
+            },
+        );
+    }
+    hashes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn file_hashes_round_trip() {
+        // The FNV-1a test vectors.
+        assert_eq!(fnv1a(FNV_OFFSET_BASIS, b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(FNV_OFFSET_BASIS, b"a"), 0xaf63_dc4c_8601_ec8c);
+        let mut hashes = HashMap::new();
+        hashes.insert(
+            OsString::from("mmap_hardlink_3_libfoo.so"),
+            FileHash {
+                original: OsString::from("/usr/lib/libfoo.so"),
+                hash: 0x1234,
+            },
+        );
+        hashes.insert(
+            OsString::from("/opt/app/with space"),
+            FileHash {
+                original: OsString::from("/opt/app/with space"),
+                hash: u64::max_value(),
+            },
+        );
+        let data = format_file_hashes(hashes.iter());
+        assert_eq!(parse_file_hashes(&data), hashes);
+        assert!(parse_file_hashes(b"garbage\0/x\0").is_empty());
+    }
+}
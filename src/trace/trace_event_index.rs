@@ -0,0 +1,136 @@
+use crate::{
+    scoped_fd::ScopedFd,
+    trace::{trace_frame::FrameTime, trace_stream::SUBSTREAM_COUNT},
+    util::write_all,
+};
+use nix::{fcntl::OFlag, sys::stat::Mode};
+use std::{
+    convert::TryInto,
+    ffi::{OsStr, OsString},
+    fs,
+    io,
+    mem::size_of,
+};
+
+/// The trace writer adds an entry to the event index every this many events.
+/// To get to an event in between, the reader reads (but doesn't decode the
+/// raw data of) at most this many frames.
+pub const EVENT_INDEX_INTERVAL: FrameTime = 1024;
+
+/// Size in bytes of an entry in the event index file: the event number
+/// followed by one offset per substream, all little-endian u64s.
+const ENTRY_SIZE: usize = (1 + SUBSTREAM_COUNT) * size_of::<u64>();
+
+/// Where every substream of the trace is just before the frame for
+/// `next_event` is read. Offsets are in uncompressed bytes and are indexed by
+/// `Substream as usize`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct EventIndexEntry {
+    pub next_event: FrameTime,
+    pub offsets: [u64; SUBSTREAM_COUNT],
+}
+
+impl EventIndexEntry {
+    pub fn to_bytes(&self) -> [u8; ENTRY_SIZE] {
+        let mut bytes = [0u8; ENTRY_SIZE];
+        let values = Some(self.next_event)
+            .into_iter()
+            .chain(self.offsets.iter().copied());
+        for (chunk, v) in bytes.chunks_exact_mut(size_of::<u64>()).zip(values) {
+            chunk.copy_from_slice(&v.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> EventIndexEntry {
+        let mut values = bytes
+            .chunks_exact(size_of::<u64>())
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()));
+        let mut entry = EventIndexEntry {
+            next_event: values.next().unwrap(),
+            offsets: Default::default(),
+        };
+        for (o, v) in entry.offsets.iter_mut().zip(values) {
+            *o = v;
+        }
+        entry
+    }
+}
+
+/// Read the event index stored at `path`. Traces recorded before rd wrote
+/// event indexes don't have one; that (or any other problem reading it) just
+/// gives an empty index. A partial entry at the end, as left behind by a
+/// recording that was killed, is ignored.
+pub fn read_event_index(path: &OsStr) -> Vec<EventIndexEntry> {
+    match fs::read(path) {
+        Ok(bytes) => bytes
+            .chunks_exact(ENTRY_SIZE)
+            .map(EventIndexEntry::from_bytes)
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Replace the event index stored at `path` with `index`.
+pub fn rewrite_event_index(path: &OsStr, index: &[EventIndexEntry]) -> io::Result<()> {
+    let mut tmp_path = OsString::from(path);
+    tmp_path.push(".tmp");
+    let mut bytes = Vec::with_capacity(index.len() * ENTRY_SIZE);
+    for e in index {
+        bytes.extend_from_slice(&e.to_bytes());
+    }
+    fs::write(&tmp_path, bytes)?;
+    fs::rename(&tmp_path, path)
+}
+
+/// Open a new, empty event index at `path` for a trace being written.
+pub fn create_event_index(path: &OsStr) -> ScopedFd {
+    ScopedFd::open_path_with_mode(
+        path,
+        OFlag::O_CLOEXEC | OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_EXCL,
+        Mode::S_IRUSR | Mode::S_IWUSR,
+    )
+}
+
+pub fn append_event_index_entry(fd: &ScopedFd, entry: &EventIndexEntry) {
+    write_all(fd.as_raw(), &entry.to_bytes());
+}
+
+/// The entry to start from to get to the frame for `event`: the last one
+/// that's not past it.
+pub fn entry_for_event(index: &[EventIndexEntry], event: FrameTime) -> Option<&EventIndexEntry> {
+    match index.binary_search_by_key(&event, |e| e.next_event) {
+        Ok(i) => Some(&index[i]),
+        Err(0) => None,
+        Err(i) => Some(&index[i - 1]),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(next_event: FrameTime) -> EventIndexEntry {
+        EventIndexEntry {
+            next_event,
+            offsets: [next_event * 10, next_event * 20, 3, 4],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let e = entry(1024);
+        assert_eq!(EventIndexEntry::from_bytes(&e.to_bytes()), e);
+    }
+
+    #[test]
+    fn entry_lookup() {
+        let index = [entry(1024), entry(2048), entry(3072)];
+        assert_eq!(entry_for_event(&index, 1), None);
+        assert_eq!(entry_for_event(&index, 1023), None);
+        assert_eq!(entry_for_event(&index, 1024), Some(&index[0]));
+        assert_eq!(entry_for_event(&index, 2047), Some(&index[0]));
+        assert_eq!(entry_for_event(&index, 5000), Some(&index[2]));
+        assert_eq!(entry_for_event(&[], 5000), None);
+    }
+}
@@ -0,0 +1,269 @@
+use crate::{
+    remote_ptr::{RemotePtr, Void},
+    taskish_uid::AddressSpaceUid,
+    trace::{
+        trace_frame::FrameTime,
+        trace_reader::TraceReader,
+        trace_task_event::{TraceTaskEvent, TraceTaskEventVariant},
+    },
+};
+use libc::{pid_t, CLONE_THREAD};
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    os::unix::ffi::OsStrExt,
+};
+
+/// Linux truncates the task comm (i.e. prname) to this many bytes, not
+/// counting the terminating NUL.
+const TASK_COMM_LEN: usize = 15;
+
+/// One exec in the life of a process. The address space created by the exec
+/// is the one with `AddressSpaceUid::exec_count() == exec_count`.
+#[derive(Clone)]
+pub struct ExecGeneration {
+    pub pid: pid_t,
+    /// 1 for the first exec in a process, 2 for the second and so on.
+    /// Generation 0 is the address space inherited from the parent at fork
+    /// time and does not have an entry of its own.
+    pub exec_count: u32,
+    /// The global time of the exec.
+    pub time: FrameTime,
+    pub file_name: OsString,
+    pub cmd_line: Vec<OsString>,
+    pub exe_base: RemotePtr<Void>,
+}
+
+impl ExecGeneration {
+    /// The name the kernel would have given the task right after this exec.
+    /// The tracee may have changed it later with PR_SET_NAME.
+    pub fn prname(&self) -> &OsStr {
+        let bytes = self.file_name.as_bytes();
+        let start = bytes.iter().rposition(|&c| c == b'/').map_or(0, |p| p + 1);
+        let end = bytes.len().min(start + TASK_COMM_LEN);
+        OsStr::from_bytes(&bytes[start..end])
+    }
+}
+
+struct ProcessHistory {
+    pid: pid_t,
+    /// When this process was forked. 0 for the initial process.
+    birth: FrameTime,
+    /// Index into `ExecHistory::processes` of the process we were forked from.
+    parent: Option<usize>,
+    execs: Vec<ExecGeneration>,
+}
+
+/// Every exec that happened in a trace, grouped by process. Lets tools ask
+/// what image and command line a process had at any point in the trace,
+/// including for address spaces that were inherited across fork().
+///
+/// pids may be recycled during a recording, so every lookup is qualified by
+/// a time.
+pub struct ExecHistory {
+    processes: Vec<ProcessHistory>,
+}
+
+impl ExecHistory {
+    /// Build the history from all the task events in `trace`. This consumes
+    /// the task event stream of `trace`.
+    pub fn from_trace(trace: &mut TraceReader) -> ExecHistory {
+        let mut events = Vec::new();
+        let mut times = Vec::new();
+        let mut time: FrameTime = 0;
+        while let Some(e) = trace.read_task_event(Some(&mut time)) {
+            events.push(e);
+            times.push(time);
+        }
+        ExecHistory::new(&events, &times)
+    }
+
+    /// `times[i]` is the global time of `events[i]`.
+    pub fn new(events: &[TraceTaskEvent], times: &[FrameTime]) -> ExecHistory {
+        debug_assert_eq!(events.len(), times.len());
+        let mut processes: Vec<ProcessHistory> = Vec::new();
+        // Maps pid to the index in `processes` of the live process with that pid
+        let mut live: HashMap<pid_t, usize> = HashMap::new();
+        let mut tid_to_pid: HashMap<pid_t, pid_t> = HashMap::new();
+
+        for (e, &time) in events.iter().zip(times) {
+            match e.event_variant() {
+                TraceTaskEventVariant::Clone(c) if c.clone_flags() & CLONE_THREAD == 0 => {
+                    let parent_pid = *tid_to_pid
+                        .get(&c.parent_tid())
+                        .unwrap_or(&c.parent_tid());
+                    processes.push(ProcessHistory {
+                        pid: e.tid(),
+                        birth: time,
+                        parent: live.get(&parent_pid).copied(),
+                        execs: Vec::new(),
+                    });
+                    live.insert(e.tid(), processes.len() - 1);
+                    tid_to_pid.insert(e.tid(), e.tid());
+                }
+                TraceTaskEventVariant::Clone(c) => {
+                    let pid = *tid_to_pid
+                        .get(&c.parent_tid())
+                        .unwrap_or(&c.parent_tid());
+                    tid_to_pid.insert(e.tid(), pid);
+                }
+                TraceTaskEventVariant::Exec(ex) => {
+                    let pid = *tid_to_pid.get(&e.tid()).unwrap_or(&e.tid());
+                    let index = match live.get(&pid) {
+                        Some(&index) => index,
+                        None => {
+                            // The initial exec of the trace
+                            processes.push(ProcessHistory {
+                                pid,
+                                birth: 0,
+                                parent: None,
+                                execs: Vec::new(),
+                            });
+                            live.insert(pid, processes.len() - 1);
+                            tid_to_pid.insert(e.tid(), pid);
+                            processes.len() - 1
+                        }
+                    };
+                    let p = &mut processes[index];
+                    let exec_count = p.execs.len() as u32 + 1;
+                    p.execs.push(ExecGeneration {
+                        pid,
+                        exec_count,
+                        time,
+                        file_name: ex.file_name().to_owned(),
+                        cmd_line: ex.cmd_line().to_vec(),
+                        exe_base: ex.exe_base(),
+                    });
+                }
+                TraceTaskEventVariant::Exit(_) => {
+                    if let Some(pid) = tid_to_pid.remove(&e.tid()) {
+                        if !tid_to_pid.values().any(|&p| p == pid) {
+                            live.remove(&pid);
+                        }
+                    }
+                }
+            }
+        }
+
+        ExecHistory { processes }
+    }
+
+    /// All the execs done by the incarnation of `pid` alive at `time`.
+    pub fn generations(&self, pid: pid_t, time: FrameTime) -> &[ExecGeneration] {
+        match self.process_at(pid, time) {
+            Some(index) => &self.processes[index].execs,
+            None => &[],
+        }
+    }
+
+    /// The exec that produced the address space `pid` was using at `time`.
+    /// For a process that had not exec'd yet, this is whatever its parent
+    /// was running when it forked.
+    pub fn generation_at(&self, pid: pid_t, time: FrameTime) -> Option<&ExecGeneration> {
+        self.process_at(pid, time)
+            .and_then(|index| self.generation_in(index, time))
+    }
+
+    /// The exec that produced address space `vm`. `time` is any time at which
+    /// `vm` was in use; it's needed to tell recycled pids apart.
+    pub fn generation_for_vm(
+        &self,
+        vm: AddressSpaceUid,
+        time: FrameTime,
+    ) -> Option<&ExecGeneration> {
+        let index = self.process_at(vm.tid(), time)?;
+        let p = &self.processes[index];
+        match vm.exec_count() {
+            0 => p
+                .parent
+                .and_then(|parent| self.generation_in(parent, p.birth)),
+            n => p.execs.get(n as usize - 1),
+        }
+    }
+
+    fn process_at(&self, pid: pid_t, time: FrameTime) -> Option<usize> {
+        self.processes
+            .iter()
+            .rposition(|p| p.pid == pid && p.birth <= time)
+    }
+
+    fn generation_in(&self, index: usize, time: FrameTime) -> Option<&ExecGeneration> {
+        let p = &self.processes[index];
+        match p.execs.iter().rev().find(|g| g.time <= time) {
+            Some(g) => Some(g),
+            None => p
+                .parent
+                .and_then(|parent| self.generation_in(parent, p.birth)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use libc::{CLONE_SIGHAND, CLONE_VM, SIGCHLD};
+
+    fn exec(tid: pid_t, file_name: &str) -> TraceTaskEvent {
+        TraceTaskEvent::for_exec(
+            tid,
+            file_name.into(),
+            vec![file_name.into()],
+            RemotePtr::new_from_val(0x400000),
+        )
+    }
+
+    /// Shell 10 forks 11, whose thread 12 execs a program that execs again,
+    /// and forks a new 11 after the first one exits.
+    fn history() -> ExecHistory {
+        let thread_flags = CLONE_THREAD | CLONE_VM | CLONE_SIGHAND;
+        let events = vec![
+            exec(10, "/bin/sh"),
+            TraceTaskEvent::for_clone(11, 10, 11, SIGCHLD),
+            TraceTaskEvent::for_clone(12, 11, 12, thread_flags),
+            exec(12, "/usr/bin/a-program-with-a-long-name"),
+            exec(11, "/bin/true"),
+            TraceTaskEvent::for_exit(12, Default::default()),
+            TraceTaskEvent::for_exit(11, Default::default()),
+            TraceTaskEvent::for_clone(11, 10, 11, SIGCHLD),
+        ];
+        ExecHistory::new(&events, &[1, 5, 6, 8, 10, 12, 12, 15])
+    }
+
+    fn file_name(g: Option<&ExecGeneration>) -> Option<&str> {
+        g.and_then(|g| g.file_name.to_str())
+    }
+
+    #[test]
+    fn generations_by_time() {
+        let h = history();
+        assert_eq!(file_name(h.generation_at(10, 1)), Some("/bin/sh"));
+        // Before its first exec a process runs what its parent did.
+        assert_eq!(file_name(h.generation_at(11, 6)), Some("/bin/sh"));
+        let g = h.generation_at(11, 9).unwrap();
+        assert_eq!(g.pid, 11);
+        assert_eq!(g.exec_count, 1);
+        assert_eq!(g.prname(), OsStr::new("a-program-with-"));
+        let g = h.generation_at(11, 11).unwrap();
+        assert_eq!(g.exec_count, 2);
+        assert_eq!(g.prname(), OsStr::new("true"));
+        assert_eq!(h.generations(11, 11).len(), 2);
+        assert!(h.generation_at(99, 11).is_none());
+
+        // The second process 11 hasn't exec'd.
+        assert!(h.generations(11, 15).is_empty());
+        assert_eq!(file_name(h.generation_at(11, 15)), Some("/bin/sh"));
+    }
+
+    #[test]
+    fn generations_by_address_space() {
+        let h = history();
+        let vm = |exec_count| AddressSpaceUid::new_with(11, 0, exec_count);
+        assert_eq!(file_name(h.generation_for_vm(vm(0), 7)), Some("/bin/sh"));
+        assert_eq!(
+            file_name(h.generation_for_vm(vm(1), 9)),
+            Some("/usr/bin/a-program-with-a-long-name")
+        );
+        assert_eq!(file_name(h.generation_for_vm(vm(2), 11)), Some("/bin/true"));
+        assert!(h.generation_for_vm(vm(3), 11).is_none());
+    }
+}
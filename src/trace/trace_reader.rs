@@ -3,6 +3,7 @@ use crate::{
     event::{
         Event,
         EventType,
+        InterceptedSyscallEventData,
         OpenedFd,
         SignalDeterministic::{DeterministicSig, NondeterministicSig},
         SignalEventData,
@@ -14,17 +15,29 @@ use crate::{
     extra_registers::{ExtraRegisters, Format},
     kernel_abi::{common::preload_interface::mprotect_record, SupportedArch, RD_NATIVE_ARCH},
     log::LogLevel::{LogDebug, LogError},
-    perf_counters::TicksSemantics,
+    perf_counters::{CoreType, TicksSemantics},
+    record_environment::{CpuQuota, RecordEnvironment},
     registers::Registers,
     remote_ptr::{RemotePtr, Void},
     session::{address_space::kernel_mapping::KernelMapping, record_session::TraceUuid},
+    ticks::Ticks,
     trace::{
         compressed_reader::{CompressedReader, CompressedReaderState},
+        compressed_writer::{Compression, CompressedWriter, Sync},
+        file_hashes::{format_file_hashes, hash_file, parse_file_hashes, FileHash},
+        trace_event_index::{
+            entry_for_event,
+            read_event_index,
+            rewrite_event_index,
+            EventIndexEntry,
+        },
         trace_frame::{FrameTime, TraceFrame},
         trace_stream::{
             latest_trace_symlink,
+            substream,
             to_trace_arch,
             trace_save_dir,
+            ClonedData,
             MappedData,
             MappedDataSource::{SourceFile, SourceTrace, SourceZero},
             RawDataMetadata,
@@ -35,6 +48,7 @@ use crate::{
             TRACE_VERSION,
         },
         trace_task_event::{
+            AttachedFd,
             TraceTaskEvent,
             TraceTaskEventClone,
             TraceTaskEventExec,
@@ -46,9 +60,12 @@ use crate::{
         frame,
         header,
         m_map,
+        mem_write,
         signal,
         task_event,
         Arch as TraceArch,
+        Compression as TraceCompression,
+        CoreType as TraceCoreType,
         SignalDisposition as TraceSignalDisposition,
         SyscallState as TraceSyscallState,
         TicksSemantics as TraceTicksSemantics,
@@ -63,7 +80,12 @@ use crate::{
     },
     wait_status::WaitStatus,
 };
-use capnp::{message::ReaderOptions, serialize_packed::read_message};
+use capnp::{
+    message,
+    message::ReaderOptions,
+    serialize_packed::{read_message, write_message},
+    struct_list,
+};
 use libc::{ino_t, pid_t, time_t};
 use nix::{
     errno::errno,
@@ -74,14 +96,21 @@ use nix::{
     unistd::{access, AccessFlags},
 };
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
     convert::{TryFrom, TryInto},
     ffi::{OsStr, OsString},
-    fs::File,
+    fs,
+    fs::{remove_file, rename, File},
+    io,
     io::{stderr, BufRead, BufReader, Read, Write},
+    mem,
     mem::size_of,
     ops::{Deref, DerefMut},
-    os::unix::ffi::{OsStrExt, OsStringExt},
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        fs::FileExt,
+    },
+    path::Path,
     process::exit,
     ptr::copy_nonoverlapping,
 };
@@ -115,8 +144,10 @@ pub struct RawData {
 /// Create a copy of this stream that has exactly the same
 /// state as 'other', but for which mutations of this
 /// clone won't affect the state of 'other' (and vice versa).
-/// @TODO: Currently doing a derive Clone. In case the semantics are not exactly
-/// what we want, we will need to implement Clone manually.
+///
+/// A derived Clone gives us exactly that: clones share the underlying
+/// substream fds, but every CompressedReader tracks its own offset and only
+/// ever reads with pread(), so read positions are never shared.
 #[derive(Clone)]
 pub struct TraceReader {
     trace_stream: TraceStream,
@@ -130,6 +161,17 @@ pub struct TraceReader {
     uuid_: TraceUuid,
     trace_uses_cpuid_faulting: bool,
     preload_thread_locals_recorded_: bool,
+    page_size_: usize,
+    round_robin_quantum_: Option<Ticks>,
+    capture_reads_: Vec<OsString>,
+    core_type_: Option<CoreType>,
+    record_environment_: RecordEnvironment,
+    /// Empty for traces recorded without one.
+    event_index: Vec<EventIndexEntry>,
+    /// See `file_hashes`. Empty for traces recorded without them.
+    file_hashes: HashMap<OsString, FileHash>,
+    /// Files whose hash has been checked, see `verify_file_hash()`.
+    verified_files: HashSet<OsString>,
 }
 
 impl Deref for TraceReader {
@@ -159,16 +201,9 @@ impl TraceReader {
 
         self.tick_time();
 
-        let mem_writes = frame.get_mem_writes().unwrap();
-        self.raw_recs = Vec::new();
-        let mut it = mem_writes.iter();
-        while let Some(w) = it.next_back() {
-            self.raw_recs.push(RawDataMetadata {
-                addr: RemotePtr::new_from_val(w.get_addr().try_into().unwrap()),
-                size: w.get_size().try_into().unwrap(),
-                rec_tid: w.get_tid(),
-            });
-        }
+        // Last first, so they can be popped in order.
+        self.raw_recs = read_mem_writes(frame.get_mem_writes().unwrap());
+        self.raw_recs.reverse();
 
         let mut ret = TraceFrame::new();
         ret.global_time = self.time();
@@ -267,9 +302,19 @@ impl TraceReader {
                             syscall_ev.opened.push(opened_fd);
                         }
                     }
+                    frame::event::syscall::extra::ScmRightsBatch(batch) => {
+                        syscall_ev.scm_rights_batch = Some(batch as usize);
+                    }
                     _ => fatal!("Unknown syscall type or error encountered in decode"),
                 }
             }
+            frame::event::InterceptedSyscall(r) => {
+                ret.ev = Event::new_intercepted_syscall_event(InterceptedSyscallEventData {
+                    arch: from_trace_arch(r.get_arch().unwrap()),
+                    number: r.get_number(),
+                    result: r.get_result(),
+                });
+            }
             _ => fatal!("Event type not supported or error encountered in decode"),
         }
 
@@ -321,6 +366,7 @@ impl TraceReader {
                         fatal!("Invalid stat size");
                     }
                     data.file_size_bytes = map.get_stat_size() as usize;
+                    data.rd_mapping_slots_skipped = map.get_rd_mapping_slots_skipped();
                     if maybe_extra_fds.is_some() {
                         let extra_fds = maybe_extra_fds.unwrap();
                         if map.has_extra_fds() {
@@ -344,6 +390,9 @@ impl TraceReader {
                             let backing_file_name_int = f.get_backing_file_name().unwrap();
                             let is_clone = backing_file_name_int.starts_with(b"mmap_clone_");
                             let is_copy = backing_file_name_int.starts_with(b"mmap_copy_");
+                            // Copied in by `rd pack`, so no longer the file we recorded the
+                            // metadata of.
+                            let is_pack = backing_file_name_int.starts_with(b"mmap_pack_");
                             let mut backing_file_name_vec: Vec<u8> = Vec::new();
                             if backing_file_name_int[0] != b'/' {
                                 backing_file_name_vec.extend_from_slice(self.dir().as_bytes());
@@ -364,6 +413,7 @@ impl TraceReader {
                             let has_stat_buf = mode != 0 || uid != 0 || gid != 0 || mtime != 0;
                             if !is_clone
                                 && !is_copy
+                                && !is_pack
                                 && validate == ValidateSourceFile::Validate
                                 && has_stat_buf
                             {
@@ -404,6 +454,9 @@ impl TraceReader {
                                     );
                                 }
                             }
+                            if validate == ValidateSourceFile::Validate {
+                                self.verify_file_hash(OsStr::from_bytes(backing_file_name_int));
+                            }
                             data.filename = backing_file_name.to_os_string();
                             let file_offset_bytes = map.get_file_offset_bytes();
                             if file_offset_bytes < 0 {
@@ -480,11 +533,24 @@ impl TraceReader {
                     cmd_line_.push(OsStr::from_bytes(cmd.unwrap()).to_os_string());
                 }
                 let exe_base_ = r.get_exe_base().into();
+                let attached_fds_ = if r.get_attached() {
+                    let mut fds = Vec::new();
+                    for fd in r.get_attached_fds().unwrap().iter() {
+                        fds.push(AttachedFd {
+                            fd: fd.get_fd(),
+                            path: OsStr::from_bytes(fd.get_path().unwrap()).to_os_string(),
+                        });
+                    }
+                    Some(fds)
+                } else {
+                    None
+                };
                 te = TraceTaskEvent {
                     variant: TraceTaskEventVariant::Exec(TraceTaskEventExec {
                         file_name_: OsStr::from_bytes(file_name_).to_os_string(),
                         cmd_line_,
                         exe_base_,
+                        attached_fds_,
                     }),
                     tid_,
                 }
@@ -525,14 +591,30 @@ impl TraceReader {
             rec_tid: rec.rec_tid,
         };
         d.data.resize(rec.size, 0);
-        let nread = self
-            .reader_mut(Substream::RawData)
-            .read(&mut d.data)
-            .unwrap();
-        debug_assert_eq!(nread, d.data.len());
+        match rec.cloned {
+            Some(cloned) => self.read_cloned_data(&cloned, &mut d.data),
+            None => {
+                let nread = self
+                    .reader_mut(Substream::RawData)
+                    .read(&mut d.data)
+                    .unwrap();
+                debug_assert_eq!(nread, d.data.len());
+            }
+        }
         Some(d)
     }
 
+    /// Fill `buf` with raw data that was cloned into one of the trace's
+    /// cloned-data files.
+    fn read_cloned_data(&mut self, cloned: &ClonedData, buf: &mut [u8]) {
+        self.verify_file_hash(&cloned.file_name);
+        let path = Path::new(self.dir()).join(&cloned.file_name);
+        let result = File::open(&path).and_then(|f| f.read_exact_at(buf, cloned.offset));
+        if let Err(e) = result {
+            fatal!("Can't read cloned data from {:?}: {}", path, e);
+        }
+    }
+
     /// Like read_raw_data_for_frame, but doesn't actually read the data bytes.
     /// Simply return the raw metadata or `None` if there are no records left.
     pub fn read_raw_data_metadata_for_frame(&mut self) -> Option<RawDataMetadata> {
@@ -540,10 +622,101 @@ impl TraceReader {
             return None;
         }
         let d = self.raw_recs.pop().unwrap();
-        self.reader_mut(Substream::RawData).skip(d.size).unwrap();
+        if d.cloned.is_none() {
+            self.reader_mut(Substream::RawData).skip(d.size).unwrap();
+        }
         Some(d)
     }
 
+    /// Return the memory the frame for `event` restores during replay, in the
+    /// order replay applies it, without disturbing the read position of
+    /// `self`. Returns an empty Vec if there is no such event or it has no raw
+    /// data.
+    ///
+    /// Tools that want the data for many events should read the frames in
+    /// order instead.
+    pub fn raw_data_for_event(&self, event: FrameTime) -> Vec<(RemotePtr<Void>, Vec<u8>)> {
+        let mut trace = self.clone();
+        let mut result = Vec::new();
+        if trace.seek_to_event(event) {
+            trace.read_frame();
+            while let Some(raw) = trace.read_raw_data_for_frame() {
+                result.push((raw.addr, raw.data));
+            }
+        }
+        result
+    }
+
+    /// The number of events in the trace, or `None` if it has no event index.
+    /// Only the frames after the last entry of the index are read.
+    pub fn event_count(&self) -> Option<FrameTime> {
+        let last = self.event_index.last()?;
+        let mut trace = self.clone();
+        if trace.seek_to_event(last.next_event) {
+            while !trace.at_end() {
+                trace.read_frame();
+            }
+        }
+        Some(trace.time())
+    }
+
+    /// Position the trace so that the next `read_frame()` returns the frame
+    /// for `event`, with the mmap and task event records before it skipped.
+    /// The event index lets us jump close to `event` without reading the
+    /// frames before it; for traces without one we read from the start (or
+    /// from where we are, if that's not past `event`).
+    ///
+    /// Returns false if the trace ends before `event`.
+    pub fn seek_to_event(&mut self, event: FrameTime) -> bool {
+        let maybe_entry = entry_for_event(&self.event_index, event).copied();
+        match maybe_entry {
+            Some(entry) if self.time() >= event || self.time() + 1 < entry.next_event => {
+                for &s in SUBSTREAMS.iter() {
+                    if self.reader_mut(s).seek(entry.offsets[s as usize]).is_err() {
+                        fatal!("Event index of {:?} doesn't match the trace", self.dir());
+                    }
+                }
+                self.global_time = entry.next_event - 1;
+                self.raw_recs.clear();
+            }
+            None if self.time() >= event => {
+                self.rewind();
+                self.raw_recs.clear();
+            }
+            _ => (),
+        }
+
+        while self.time() + 1 < event {
+            if self.at_end() {
+                return false;
+            }
+            self.read_frame();
+            while self.read_raw_data_metadata_for_frame().is_some() {}
+            while self
+                .read_mapped_region(
+                    None,
+                    Some(ValidateSourceFile::DontValidate),
+                    None,
+                    None,
+                    None,
+                )
+                .is_some()
+            {}
+        }
+        loop {
+            let state = self.reader(Substream::Tasks).get_state();
+            let mut time: FrameTime = 0;
+            if self.read_task_event(Some(&mut time)).is_none() {
+                break;
+            }
+            if time >= event {
+                self.reader_mut(Substream::Tasks).restore_state(state);
+                break;
+            }
+        }
+        !self.at_end()
+    }
+
     /// Return true if we're at the end of the trace file.
     pub fn at_end(&self) -> bool {
         self.reader(Substream::Events).at_end()
@@ -580,6 +753,22 @@ impl TraceReader {
         self.global_time = 0;
     }
 
+    /// The tick count of task `tid` at each of its events, as (event, ticks)
+    /// pairs in trace order. Tick counts are per task and never decrease, so
+    /// this is sorted by both.
+    pub fn task_ticks(&self, tid: pid_t) -> Vec<(FrameTime, Ticks)> {
+        let mut trace = self.clone();
+        trace.rewind();
+        let mut result = Vec::new();
+        while !trace.at_end() {
+            let frame = trace.read_frame();
+            if frame.tid() == tid {
+                result.push((frame.time(), frame.ticks()));
+            }
+        }
+        result
+    }
+
     pub fn uncompressed_bytes(&self) -> u64 {
         let mut total: u64 = 0;
         for w in self.readers.values() {
@@ -595,6 +784,93 @@ impl TraceReader {
         total
     }
 
+    /// Rewrite every mapped region record in the trace, letting `f` modify
+    /// each one. The records are written to a new file that then replaces the
+    /// mmaps substream, so on error the trace is left as it was. Afterwards
+    /// the mmaps substream is read from the start again.
+    pub fn rewrite_mapped_regions(&mut self, mut f: impl FnMut(m_map::Builder)) -> io::Result<()> {
+        let path = self.path(Substream::Mmaps);
+        let mut tmp_path = path.clone();
+        tmp_path.push(".tmp");
+        let compression = self.reader(Substream::Mmaps).compression();
+        let mut mmaps_out = CompressedWriter::new(
+            &tmp_path,
+            substream(Substream::Mmaps).block_size,
+            substream(Substream::Mmaps).threads,
+            compression,
+        );
+
+        // Old offset of each record -> new offset, so we can fix up the event
+        // index.
+        let mut new_offsets: HashMap<u64, u64> = HashMap::new();
+        let mmaps = self.reader_mut(Substream::Mmaps);
+        mmaps.rewind();
+        while !mmaps.at_end() {
+            new_offsets.insert(mmaps.uncompressed_pos(), mmaps_out.uncompressed_pos());
+            let map_msg = read_message(&mut *mmaps, ReaderOptions::new()).unwrap();
+            let mut new_map_msg = message::Builder::new_default();
+            new_map_msg
+                .set_root(map_msg.get_root::<m_map::Reader>().unwrap())
+                .unwrap();
+            f(new_map_msg.get_root::<m_map::Builder>().unwrap());
+            if write_message(&mut mmaps_out, &new_map_msg).is_err() {
+                break;
+            }
+        }
+        new_offsets.insert(mmaps.uncompressed_pos(), mmaps_out.uncompressed_pos());
+        mmaps_out.close(Some(Sync::Sync));
+        if !mmaps_out.good() {
+            remove_file(&tmp_path).ok();
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("Unable to write {:?}", tmp_path),
+            ));
+        }
+        rename(&tmp_path, &path)?;
+
+        let mut mmaps = CompressedReader::new(&path);
+        mmaps.set_compression(compression);
+        self.readers.insert(Substream::Mmaps, mmaps);
+
+        if !self.event_index.is_empty() {
+            let mut event_index = mem::take(&mut self.event_index);
+            for entry in event_index.iter_mut() {
+                let offset = &mut entry.offsets[Substream::Mmaps as usize];
+                match new_offsets.get(offset) {
+                    Some(&new_offset) => *offset = new_offset,
+                    None => fatal!("Event index of {:?} doesn't match the trace", self.dir()),
+                }
+            }
+            self.event_index = event_index;
+            rewrite_event_index(&self.event_index_path(), &self.event_index)?;
+        }
+        Ok(())
+    }
+
+    /// Some backing files have been given new names, old name -> new name,
+    /// e.g. by `rd pack`. Keep their recorded hashes under the new names, so
+    /// replay still checks them.
+    pub fn rename_backing_files(
+        &mut self,
+        renamed: &HashMap<OsString, OsString>,
+    ) -> io::Result<()> {
+        let mut changed = false;
+        for (old_name, new_name) in renamed {
+            if let Some(h) = self.file_hashes.remove(old_name) {
+                self.file_hashes.insert(new_name.clone(), h);
+                changed = true;
+            }
+        }
+        if changed {
+            let hashes: BTreeMap<_, _> = self.file_hashes.iter().collect();
+            fs::write(
+                self.file_hashes_path(),
+                format_file_hashes(hashes.into_iter()),
+            )?;
+        }
+        Ok(())
+    }
+
     /// Open the trace in 'dir'. When 'dir' is the `None`, open the
     /// latest trace.
     ///
@@ -719,6 +995,28 @@ impl TraceReader {
         let xcr0_ = header.get_xcr0();
         let preload_thread_locals_recorded_ = header.get_preload_thread_locals_recorded();
         let ticks_semantics_ = from_trace_ticks_semantics(header.get_ticks_semantics().unwrap());
+        let page_size_ = header.get_page_size() as usize;
+        let round_robin_quantum_ = Some(header.get_round_robin_quantum()).filter(|&q| q > 0);
+        let mut capture_reads_: Vec<OsString> = Vec::new();
+        for pattern in header.get_capture_reads().unwrap().iter() {
+            capture_reads_.push(OsStr::from_bytes(pattern.unwrap()).to_os_string());
+        }
+        let core_type_ = from_trace_core_type(header.get_core_type().unwrap());
+        let cpu_quota = match (header.get_cpu_quota_us(), header.get_cpu_period_us()) {
+            (0, _) | (_, 0) => None,
+            (quota_us, period_us) => Some(CpuQuota {
+                quota_us,
+                period_us,
+            }),
+        };
+        let record_environment_ = RecordEnvironment {
+            cpu_quota,
+            nice: header.get_nice_level(),
+        };
+        let compression = from_trace_compression(header.get_compression().unwrap());
+        for r in readers.values_mut() {
+            r.set_compression(compression);
+        }
         let uuid_from_trace = header.get_uuid().unwrap();
         let mut uuid_ = TraceUuid::new();
         if uuid_from_trace.len() != uuid_.bytes.len() {
@@ -729,6 +1027,10 @@ impl TraceReader {
         // Set the global time at 0, so that when we tick it for the first
         // event, it matches the initial global time at recording, 1.
         trace_stream.global_time = 0;
+        let event_index_path = trace_stream.event_index_path();
+        let file_hashes = fs::read(trace_stream.file_hashes_path())
+            .map(|data| parse_file_hashes(&data))
+            .unwrap_or_default();
         TraceReader {
             trace_stream,
             xcr0_,
@@ -738,9 +1040,63 @@ impl TraceReader {
             uuid_,
             trace_uses_cpuid_faulting,
             preload_thread_locals_recorded_,
+            page_size_,
+            round_robin_quantum_,
+            capture_reads_,
+            core_type_,
+            record_environment_,
             // @TODO Is this what we want?
             monotonic_time_: 0.0,
             raw_recs: vec![],
+            event_index: read_event_index(&event_index_path),
+            file_hashes,
+            verified_files: HashSet::new(),
+        }
+    }
+
+    /// The first time replay uses `name`, a file the trace refers to, check
+    /// it still has the contents it had when recording ended. Dies naming the
+    /// file the tracee used if it doesn't: replay would quietly diverge.
+    fn verify_file_hash(&mut self, name: &OsStr) {
+        if !self.verified_files.insert(name.to_owned()) {
+            return;
+        }
+        let recorded = match self.file_hashes.get(name) {
+            Some(recorded) => recorded,
+            None => return,
+        };
+        let path = self.trace_file_path(name);
+        let hash = match hash_file(&path) {
+            Ok(hash) => hash,
+            Err(e) => {
+                fatal!("Failed to read {:?}: {}: replay is impossible", path, e);
+                unreachable!()
+            }
+        };
+        if hash == recorded.hash {
+            return;
+        }
+        if recorded.original.as_os_str() == name && !name.as_bytes().starts_with(b"/") {
+            // A file rd wrote into the trace, e.g. cloned data.
+            fatal!(
+                "{:?} changed since it was recorded: the trace is damaged and replay \
+                 would diverge. Record again",
+                path
+            );
+        } else if recorded.original.as_os_str() == name {
+            fatal!(
+                "{:?} changed since it was recorded: replay would diverge. Restore the \
+                 recorded version, or record again",
+                path
+            );
+        } else {
+            fatal!(
+                "{:?} changed since it was recorded, so the trace's {:?} doesn't match \
+                 what the tracee saw: replay would diverge. Restore the recorded \
+                 version, or record again",
+                recorded.original,
+                path
+            );
         }
     }
 
@@ -774,6 +1130,33 @@ impl TraceReader {
     pub fn preload_thread_locals_recorded(&self) -> bool {
         self.preload_thread_locals_recorded_
     }
+    /// The page size of the machine the trace was recorded on. Sizes the
+    /// recording kernel rounded up to whole pages (mremap lengths, say) must be
+    /// rounded with this rather than `page_size()`.
+    pub fn page_size(&self) -> usize {
+        self.page_size_
+    }
+    /// The timeslice length if the trace was recorded with the round-robin
+    /// scheduler. Replay follows the recorded schedule either way; this is
+    /// so tools can tell what the recording was after.
+    pub fn round_robin_quantum(&self) -> Option<Ticks> {
+        self.round_robin_quantum_
+    }
+    /// The `--capture-reads` patterns the trace was recorded with. Replay
+    /// disables syscall buffering for the same fds.
+    pub fn capture_reads(&self) -> &[OsString] {
+        &self.capture_reads_
+    }
+    /// The kind of core tracees ran on if the trace was recorded on a hybrid
+    /// CPU. Replay must count ticks with the same kind.
+    pub fn core_type(&self) -> Option<CoreType> {
+        self.core_type_
+    }
+    /// The cgroup CPU quota and nice level the trace was recorded under.
+    /// Traces that predate them look like neither was set.
+    pub fn record_environment(&self) -> RecordEnvironment {
+        self.record_environment_
+    }
     pub fn uuid(&self) -> &TraceUuid {
         &self.uuid_
     }
@@ -855,6 +1238,21 @@ fn from_trace_signal(event_type: EventType, signal: signal::Reader) -> Event {
     Event::new_signal_event(event_type, sig_event)
 }
 
+fn from_trace_compression(compression: TraceCompression) -> Compression {
+    match compression {
+        TraceCompression::Brotli => Compression::Brotli,
+        TraceCompression::Zstd => Compression::Zstd,
+    }
+}
+
+fn from_trace_core_type(core_type: TraceCoreType) -> Option<CoreType> {
+    match core_type {
+        TraceCoreType::Any => None,
+        TraceCoreType::Performance => Some(CoreType::Performance),
+        TraceCoreType::Efficiency => Some(CoreType::Efficiency),
+    }
+}
+
 fn from_trace_ticks_semantics(semantics: TraceTicksSemantics) -> TicksSemantics {
     match semantics {
         TraceTicksSemantics::RetiredConditionalBranches => {
@@ -894,3 +1292,81 @@ fn resolve_trace_name<T: AsRef<OsStr>>(maybe_trace_name: Option<&T>) -> OsString
 
     trace_name.to_os_string()
 }
+
+/// The first event in `task_ticks`, as returned by `TraceReader::task_ticks()`,
+/// by which the task has executed at least `ticks` ticks, or `None` if it
+/// never gets that far.
+pub fn first_event_with_ticks(
+    task_ticks: &[(FrameTime, Ticks)],
+    ticks: Ticks,
+) -> Option<FrameTime> {
+    let i = task_ticks.partition_point(|&(_, t)| t < ticks);
+    task_ticks.get(i).map(|&(time, _)| time)
+}
+
+/// The raw data records in a frame's `mem_writes`.
+fn read_mem_writes(mem_writes: struct_list::Reader<mem_write::Owned>) -> Vec<RawDataMetadata> {
+    mem_writes
+        .iter()
+        .map(|w| {
+            let cloned_file = w.get_cloned_file().unwrap();
+            let cloned = if cloned_file.is_empty() {
+                None
+            } else {
+                Some(ClonedData {
+                    file_name: OsStr::from_bytes(cloned_file).to_os_string(),
+                    offset: w.get_cloned_offset(),
+                })
+            };
+            RawDataMetadata {
+                addr: RemotePtr::new_from_val(w.get_addr().try_into().unwrap()),
+                size: w.get_size().try_into().unwrap(),
+                rec_tid: w.get_tid(),
+                cloned,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::trace::trace_writer::write_mem_writes;
+
+    #[test]
+    fn cloned_data_round_trip() {
+        let recs = vec![
+            RawDataMetadata {
+                addr: RemotePtr::new_from_val(0x1000),
+                size: 16,
+                rec_tid: 7,
+                cloned: None,
+            },
+            RawDataMetadata {
+                addr: RemotePtr::new_from_val(0x20000),
+                size: 8192,
+                rec_tid: 8,
+                cloned: Some(ClonedData {
+                    file_name: "cloned_data_8_0".into(),
+                    offset: 4096,
+                }),
+            },
+        ];
+        let mut msg = message::Builder::new_default();
+        let mut frame = msg.init_root::<frame::Builder>();
+        write_mem_writes(frame.reborrow().init_mem_writes(recs.len() as u32), &recs);
+        let mem_writes = frame.into_reader().get_mem_writes().unwrap();
+        assert_eq!(read_mem_writes(mem_writes), recs);
+    }
+
+    #[test]
+    fn ticks_lookup() {
+        let task_ticks = [(3, 0), (5, 100), (9, 100), (12, 250)];
+        assert_eq!(first_event_with_ticks(&task_ticks, 0), Some(3));
+        assert_eq!(first_event_with_ticks(&task_ticks, 1), Some(5));
+        assert_eq!(first_event_with_ticks(&task_ticks, 100), Some(5));
+        assert_eq!(first_event_with_ticks(&task_ticks, 250), Some(12));
+        assert_eq!(first_event_with_ticks(&task_ticks, 251), None);
+        assert_eq!(first_event_with_ticks(&[], 0), None);
+    }
+}
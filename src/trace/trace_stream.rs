@@ -11,6 +11,7 @@ use nix::{errno::errno, sys::stat::Mode, unistd::mkdir};
 use std::{
     env,
     ffi::{OsStr, OsString},
+    fs,
     io::Write,
     os::unix::ffi::{OsStrExt, OsStringExt},
     path::Path,
@@ -129,6 +130,40 @@ impl TraceStream {
         OsString::from_vec(ss)
     }
 
+    /// Where the memory checksums of the address space of `rec_tid` at event
+    /// `time` are stored, if any were taken.
+    pub fn checksum_path(&self, time: FrameTime, rec_tid: pid_t) -> OsString {
+        let mut ss: Vec<u8> = Vec::from(self.trace_dir.as_bytes());
+        write!(ss, "/checksum_{}_{}", time, rec_tid).unwrap();
+        OsString::from_vec(ss)
+    }
+
+    /// Whether any memory checksums were taken during recording, see
+    /// `checksum_path()`.
+    pub fn has_checksums(&self) -> bool {
+        match fs::read_dir(&self.trace_dir) {
+            Ok(entries) => entries
+                .filter_map(Result::ok)
+                .any(|entry| entry.file_name().as_bytes().starts_with(b"checksum_")),
+            Err(_) => false,
+        }
+    }
+
+    /// The directories the tracees listed with getdents/getdents64 during
+    /// recording. Their listings are in the trace, so replay doesn't need
+    /// them, but someone moving the trace elsewhere may want to know about
+    /// them. Empty for traces recorded before rd kept track.
+    pub fn directories_read(&self) -> Vec<OsString> {
+        match fs::read(self.directories_read_path()) {
+            Ok(data) => data
+                .split(|&b| b == 0)
+                .filter(|name| !name.is_empty())
+                .map(|name| OsStr::from_bytes(name).to_os_string())
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
     pub fn mmaps_block_size() -> usize {
         substream(Substream::Mmaps).block_size
     }
@@ -159,6 +194,41 @@ impl TraceStream {
         OsString::from_vec(version_path)
     }
 
+    /// Return the path of the event index. See `trace_event_index`.
+    pub(super) fn event_index_path(&self) -> OsString {
+        let mut path: Vec<u8> = self.trace_dir.clone().into_vec();
+        path.extend_from_slice(b"/event_index");
+        OsString::from_vec(path)
+    }
+
+    /// Return the path of the list of directories read during recording, as
+    /// NUL-terminated names.
+    pub(super) fn directories_read_path(&self) -> OsString {
+        let mut path: Vec<u8> = self.trace_dir.clone().into_vec();
+        path.extend_from_slice(b"/directories_read");
+        OsString::from_vec(path)
+    }
+
+    /// Return the path of the hashes of the files replay maps or reads data
+    /// from, see `file_hashes`.
+    pub(super) fn file_hashes_path(&self) -> OsString {
+        let mut path: Vec<u8> = self.trace_dir.clone().into_vec();
+        path.extend_from_slice(b"/file_hashes");
+        OsString::from_vec(path)
+    }
+
+    /// The path of `name`, a file the trace refers to: relative names are
+    /// relative to the trace directory.
+    pub fn trace_file_path(&self, name: &OsStr) -> OsString {
+        if name.as_bytes().starts_with(b"/") {
+            return name.to_owned();
+        }
+        let mut path: Vec<u8> = self.trace_dir.clone().into_vec();
+        path.push(b'/');
+        path.extend_from_slice(name.as_bytes());
+        OsString::from_vec(path)
+    }
+
     /// While the trace is being built, the version file is stored under this name.
     /// When the trace is closed we rename it to the correct name. This lets us
     /// detect incomplete traces.
@@ -189,11 +259,24 @@ pub struct TraceStream {
     pub(super) global_time: FrameTime,
 }
 
-#[derive(Clone, Default)]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub struct RawDataMetadata {
     pub addr: RemotePtr<Void>,
     pub size: usize,
     pub rec_tid: pid_t,
+    /// Where the data is if it was cloned rather than copied into the raw
+    /// data substream.
+    pub cloned: Option<ClonedData>,
+}
+
+/// Raw data cloned from the file a tracee read into one of the trace's
+/// cloned-data files, so the trace shares the file's blocks instead of
+/// holding a copy of them.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ClonedData {
+    /// Relative to the trace directory.
+    pub file_name: OsString,
+    pub offset: u64,
 }
 
 pub struct TraceRemoteFd {
@@ -213,6 +296,9 @@ pub struct MappedData {
     pub data_offset_bytes: usize,
     /// Original size of mapped file.
     pub file_size_bytes: usize,
+    /// For rd's own mappings, how many taken slots were skipped while
+    /// picking the address during recording.
+    pub rd_mapping_slots_skipped: u32,
 }
 
 pub(super) fn make_trace_dir(exe_path: &OsStr, output_trace_dir: &OsStr) -> OsString {
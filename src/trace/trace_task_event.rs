@@ -62,11 +62,20 @@ impl TraceTaskEventClone {
     }
 }
 
+/// An fd that was open in a process when rd attached to it.
+#[derive(Clone)]
+pub struct AttachedFd {
+    pub fd: i32,
+    /// What /proc/<pid>/fd/<fd> linked to.
+    pub path: OsString,
+}
+
 #[derive(Clone)]
 pub struct TraceTaskEventExec {
     pub(super) file_name_: OsString,
     pub(super) cmd_line_: Vec<OsString>,
     pub(super) exe_base_: RemotePtr<Void>,
+    pub(super) attached_fds_: Option<Vec<AttachedFd>>,
 }
 
 impl TraceTaskEventExec {
@@ -79,6 +88,12 @@ impl TraceTaskEventExec {
     pub fn exe_base(&self) -> RemotePtr<Void> {
         self.exe_base_
     }
+    /// `Some` (with the fds open at the time) if this event describes a
+    /// process that was already running when `rd record -p` attached to it
+    /// rather than an actual exec.
+    pub fn attached_fds(&self) -> Option<&[AttachedFd]> {
+        self.attached_fds_.as_ref().map(|fds| fds.as_slice())
+    }
 }
 
 #[derive(Clone)]
@@ -98,6 +113,64 @@ pub struct TraceTaskEvent {
 }
 
 impl TraceTaskEvent {
+    pub fn for_exec(
+        tid: pid_t,
+        file_name: OsString,
+        cmd_line: Vec<OsString>,
+        exe_base: RemotePtr<Void>,
+    ) -> TraceTaskEvent {
+        TraceTaskEvent {
+            variant: TraceTaskEventVariant::Exec(TraceTaskEventExec {
+                file_name_: file_name,
+                cmd_line_: cmd_line,
+                exe_base_: exe_base,
+                attached_fds_: None,
+            }),
+            tid_: tid,
+        }
+    }
+    /// The event standing in for the exec of a process that was already
+    /// running when rd attached to it.
+    pub fn for_attach(
+        tid: pid_t,
+        file_name: OsString,
+        cmd_line: Vec<OsString>,
+        exe_base: RemotePtr<Void>,
+        fds: Vec<AttachedFd>,
+    ) -> TraceTaskEvent {
+        TraceTaskEvent {
+            variant: TraceTaskEventVariant::Exec(TraceTaskEventExec {
+                file_name_: file_name,
+                cmd_line_: cmd_line,
+                exe_base_: exe_base,
+                attached_fds_: Some(fds),
+            }),
+            tid_: tid,
+        }
+    }
+    pub fn for_clone(
+        tid: pid_t,
+        parent_tid: pid_t,
+        own_ns_tid: pid_t,
+        clone_flags: i32,
+    ) -> TraceTaskEvent {
+        TraceTaskEvent {
+            variant: TraceTaskEventVariant::Clone(TraceTaskEventClone {
+                parent_tid_: parent_tid,
+                own_ns_tid_: own_ns_tid,
+                clone_flags_: clone_flags,
+            }),
+            tid_: tid,
+        }
+    }
+    pub fn for_exit(tid: pid_t, exit_status: WaitStatus) -> TraceTaskEvent {
+        TraceTaskEvent {
+            variant: TraceTaskEventVariant::Exit(TraceTaskEventExit {
+                exit_status_: exit_status,
+            }),
+            tid_: tid,
+        }
+    }
     pub fn tid(&self) -> pid_t {
         self.tid_
     }
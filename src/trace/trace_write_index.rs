@@ -0,0 +1,239 @@
+use crate::{
+    remote_ptr::{RemotePtr, Void},
+    trace::{
+        trace_frame::FrameTime,
+        trace_reader::TraceReader,
+        trace_task_event::{TraceTaskEvent, TraceTaskEventVariant},
+    },
+};
+use libc::{pid_t, CLONE_VM};
+use std::collections::HashMap;
+
+/// Writes are bucketed by page so a lookup only has to look at the writes
+/// that touched the page containing the address.
+const INDEX_PAGE_SIZE: usize = 4096;
+
+/// Identifies an address space for the lifetime of the trace: the tid that
+/// created it (by fork or exec) and the time it was created. Unlike a tid this
+/// is never recycled.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct VmKey {
+    pub tid: pid_t,
+    pub since: FrameTime,
+}
+
+/// A range of tracee memory written by an event, as recorded in the raw data
+/// of its trace frame. This covers syscall outparams as well as signal frames
+/// set up on delivery of a signal.
+#[derive(Clone, Debug)]
+pub struct MemoryWrite {
+    pub time: FrameTime,
+    pub rec_tid: pid_t,
+    pub vm: VmKey,
+    pub addr: RemotePtr<Void>,
+    pub size: usize,
+}
+
+impl MemoryWrite {
+    pub fn end(&self) -> RemotePtr<Void> {
+        self.addr + self.size
+    }
+
+    pub fn contains(&self, addr: RemotePtr<Void>) -> bool {
+        self.addr <= addr && addr < self.end()
+    }
+}
+
+/// Which address space each tid was using over time, replayed from the task
+/// events of a trace.
+struct VmHistory {
+    vms: HashMap<pid_t, Vec<(FrameTime, VmKey)>>,
+}
+
+impl VmHistory {
+    fn new(events: &[TraceTaskEvent], times: &[FrameTime]) -> VmHistory {
+        let mut history = VmHistory {
+            vms: HashMap::new(),
+        };
+        for (e, &time) in events.iter().zip(times) {
+            let vm = match e.event_variant() {
+                TraceTaskEventVariant::Clone(c) if c.clone_flags() & CLONE_VM != 0 => {
+                    history.vm_at(c.parent_tid(), time)
+                }
+                TraceTaskEventVariant::Clone(_) | TraceTaskEventVariant::Exec(_) => VmKey {
+                    tid: e.tid(),
+                    since: time,
+                },
+                TraceTaskEventVariant::Exit(_) => continue,
+            };
+            history.vms.entry(e.tid()).or_default().push((time, vm));
+        }
+        history
+    }
+
+    /// Tasks we saw no events for (i.e. the initial task before its first
+    /// exec) get an address space of their own starting at time 0.
+    fn vm_at(&self, tid: pid_t, time: FrameTime) -> VmKey {
+        self.vms
+            .get(&tid)
+            .and_then(|h| h.iter().rev().find(|(t, _)| *t <= time))
+            .map_or(VmKey { tid, since: 0 }, |(_, vm)| *vm)
+    }
+}
+
+/// An index of every memory write recorded in a trace, answering "which event
+/// last wrote this address?" without having to replay.
+///
+/// Only writes that rd records data for are known: syscall outparams, signal
+/// frames and the like. Writes done by the tracee's own instructions are not
+/// in the trace; if no event wrote an address, the value came from an
+/// instruction (or from a mapped file) and reverse execution is needed.
+///
+/// All of this is available from the raw data metadata of the trace, so the
+/// index can be built from any trace without decompressing the data itself.
+pub struct WriteIndex {
+    writes: Vec<MemoryWrite>,
+    /// Maps a page number to indices into `writes`, in increasing order.
+    pages: HashMap<usize, Vec<usize>>,
+    vms: VmHistory,
+}
+
+impl WriteIndex {
+    /// Build the index for `trace`. This consumes the task event and frame
+    /// streams of `trace`.
+    pub fn from_trace(trace: &mut TraceReader) -> WriteIndex {
+        let mut events = Vec::new();
+        let mut times = Vec::new();
+        let mut time: FrameTime = 0;
+        while let Some(e) = trace.read_task_event(Some(&mut time)) {
+            events.push(e);
+            times.push(time);
+        }
+
+        let mut index = WriteIndex {
+            writes: Vec::new(),
+            pages: HashMap::new(),
+            vms: VmHistory::new(&events, &times),
+        };
+        while !trace.at_end() {
+            let frame = trace.read_frame();
+            while let Some(raw) = trace.read_raw_data_metadata_for_frame() {
+                if raw.size == 0 {
+                    continue;
+                }
+                let vm = index.vms.vm_at(raw.rec_tid, frame.time());
+                index.add(MemoryWrite {
+                    time: frame.time(),
+                    rec_tid: raw.rec_tid,
+                    vm,
+                    addr: raw.addr,
+                    size: raw.size,
+                });
+            }
+        }
+        index
+    }
+
+    fn add(&mut self, w: MemoryWrite) {
+        let first_page = w.addr.as_usize() / INDEX_PAGE_SIZE;
+        let last_page = (w.end().as_usize() - 1) / INDEX_PAGE_SIZE;
+        let i = self.writes.len();
+        for page in first_page..=last_page {
+            self.pages.entry(page).or_default().push(i);
+        }
+        self.writes.push(w);
+    }
+
+    /// The address space `tid` was using at `time`.
+    pub fn vm_at(&self, tid: pid_t, time: FrameTime) -> VmKey {
+        self.vms.vm_at(tid, time)
+    }
+
+    /// The last recorded write to `addr` strictly before event `before`.
+    /// If `vm` is given, only writes to that address space are considered.
+    pub fn last_write_before(
+        &self,
+        addr: RemotePtr<Void>,
+        before: FrameTime,
+        vm: Option<VmKey>,
+    ) -> Option<&MemoryWrite> {
+        self.writes_to(addr, vm)
+            .rev()
+            .find(|w| w.time < before)
+    }
+
+    /// All recorded writes to `addr`, oldest first.
+    pub fn writes_to(
+        &self,
+        addr: RemotePtr<Void>,
+        vm: Option<VmKey>,
+    ) -> impl DoubleEndedIterator<Item = &MemoryWrite> {
+        let indices: &[usize] = match self.pages.get(&(addr.as_usize() / INDEX_PAGE_SIZE)) {
+            Some(indices) => indices,
+            None => &[],
+        };
+        indices
+            .iter()
+            .map(move |&i| &self.writes[i])
+            .filter(move |w| w.contains(addr) && vm.map_or(true, |vm| vm == w.vm))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use libc::{CLONE_THREAD, SIGCHLD};
+
+    #[test]
+    fn last_writes() {
+        // 10 forks 11 at event 5, and 11 creates the thread 12 at event 6.
+        let events = [
+            TraceTaskEvent::for_clone(11, 10, 11, SIGCHLD),
+            TraceTaskEvent::for_clone(12, 11, 12, CLONE_VM | CLONE_THREAD),
+        ];
+        let mut index = WriteIndex {
+            writes: Vec::new(),
+            pages: HashMap::new(),
+            vms: VmHistory::new(&events, &[5, 6]),
+        };
+        let initial_vm = VmKey { tid: 10, since: 0 };
+        let forked_vm = VmKey { tid: 11, since: 5 };
+        assert_eq!(index.vm_at(10, 7), initial_vm);
+        assert_eq!(index.vm_at(11, 4), VmKey { tid: 11, since: 0 });
+        assert_eq!(index.vm_at(12, 7), forked_vm);
+
+        let mut write = |time, rec_tid, addr, size| {
+            let vm = index.vm_at(rec_tid, time);
+            index.add(MemoryWrite {
+                time,
+                rec_tid,
+                vm,
+                addr: RemotePtr::new_from_val(addr),
+                size,
+            })
+        };
+        write(1, 10, 0x1ff8, 16);
+        write(7, 12, 0x2000, 8);
+        write(8, 10, 0x2004, 4);
+
+        let addr = RemotePtr::new_from_val(0x2004);
+        let time_of = |w: Option<&MemoryWrite>| w.map(|w| w.time);
+        assert_eq!(time_of(index.last_write_before(addr, 9, None)), Some(8));
+        assert_eq!(time_of(index.last_write_before(addr, 8, None)), Some(7));
+        assert_eq!(
+            time_of(index.last_write_before(addr, 8, Some(initial_vm))),
+            Some(1)
+        );
+        assert_eq!(
+            time_of(index.last_write_before(addr, 9, Some(forked_vm))),
+            Some(7)
+        );
+        assert_eq!(time_of(index.last_write_before(addr, 1, None)), None);
+        // The first write spans a page boundary.
+        let before_page = RemotePtr::new_from_val(0x1ffc);
+        assert_eq!(index.writes_to(before_page, None).count(), 1);
+        assert_eq!(index.writes_to(addr, None).count(), 3);
+        let past_end = RemotePtr::new_from_val(0x2008);
+        assert_eq!(index.writes_to(past_end, None).count(), 0);
+    }
+}
@@ -3,29 +3,40 @@ use crate::extra_registers::ExtraRegisters;
 use crate::{
     bindings::signal::siginfo_t,
     event::{Event, EventType, SignalDeterministic, SignalResolvedDisposition, SyscallState},
+    fuse_files::{is_fuse_file, FuseFilePolicy},
     kernel_abi::{
         common::preload_interface::{mprotect_record, SYSCALLBUF_PROTOCOL_VERSION},
         syscall_number_for_restart_syscall,
         RD_NATIVE_ARCH,
     },
     kernel_supplement::{btrfs_ioctl_clone_range_args, BTRFS_IOC_CLONE_, BTRFS_IOC_CLONE_RANGE_},
-    log::LogLevel::LogDebug,
-    perf_counters::{PerfCounters, TicksSemantics},
+    log::LogLevel::{LogDebug, LogWarn},
+    perf_counters::{CoreType, PerfCounters, TicksSemantics},
+    record_environment::RecordEnvironment,
     registers::Registers,
     remote_ptr::{RemotePtr, Void},
     scoped_fd::ScopedFd,
     session::{
-        address_space::kernel_mapping::KernelMapping,
+        address_space::kernel_mapping::{KernelMapping, SharedMemoryKind},
         record_session::{DisableCPUIDFeatures, TraceUuid},
         task::record_task::record_task::RecordTask,
     },
+    ticks::Ticks,
     trace::{
-        compressed_writer::CompressedWriter,
+        compressed_writer::{Compression, CompressedWriter},
+        file_hashes::{format_file_hashes, hash_file, FileHash},
+        trace_event_index::{
+            append_event_index_entry,
+            create_event_index,
+            EventIndexEntry,
+            EVENT_INDEX_INTERVAL,
+        },
         trace_stream::{
             latest_trace_symlink,
             make_trace_dir,
             substream,
             to_trace_arch,
+            ClonedData,
             MappedData,
             MappedDataSource,
             RawDataMetadata,
@@ -42,9 +53,12 @@ use crate::{
         header,
         m_map,
         m_map::source::Which::Trace,
+        mem_write,
         signal,
         task_event,
         SignalDisposition as TraceSignalDisposition,
+        Compression as TraceCompression,
+        CoreType as TraceCoreType,
         SyscallState as TraceSyscallState,
         TicksSemantics as TraceTicksSemantics,
     },
@@ -52,6 +66,7 @@ use crate::{
         all_cpuid_records,
         copy_file,
         monotonic_now_sec,
+        page_size,
         probably_not_interactive,
         should_copy_mmap_region,
         write_all,
@@ -64,8 +79,9 @@ use capnp::{
     primitive_list,
     private::layout::ListBuilder,
     serialize_packed::write_message,
+    struct_list,
 };
-use libc::{dev_t, ino_t, ioctl, pid_t, STDOUT_FILENO};
+use libc::{dev_t, ino_t, ioctl, pid_t, STDOUT_FILENO, S_IFMT, S_IFREG};
 use nix::{
     errno::errno,
     fcntl::{flock, readlink, FlockArg::LockExclusiveNonblock, OFlag},
@@ -76,10 +92,11 @@ use nix::{
     unistd::unlink,
 };
 use std::{
-    collections::HashMap,
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet, HashMap},
     convert::TryInto,
     ffi::{OsStr, OsString},
-    fs::{hard_link, rename, File},
+    fs::{self, hard_link, rename, File},
     io::Write,
     mem::size_of,
     ops::{Deref, DerefMut},
@@ -105,7 +122,13 @@ pub enum MappingOrigin {
     RemapMapping,
     ExecMapping,
     PatchMapping,
-    RdBufferMapping,
+    /// One of rd's own mappings, placed after skipping `slots_skipped` taken
+    /// slots, see `rd_mapping_placement`.
+    RdBufferMapping { slots_skipped: u32 },
+    /// A mapping that already existed when rd attached to a running process.
+    /// Even private file mappings may have been written to by then, so their
+    /// contents are recorded like those of an anonymous mapping.
+    AttachMapping,
 }
 #[derive(Copy, Clone, Eq, PartialEq)]
 pub enum CloseStatus {
@@ -156,15 +179,40 @@ pub struct TraceWriter {
     /// a file may be accessed through multiple names, only some of which
     /// are immutable.
     files_assumed_immutable: HashMap<(dev_t, ino_t), OsString>,
+    /// See `set_fuse_file_policy()`.
+    fuse_file_policy: FuseFilePolicy,
+    /// Files on FUSE filesystems tracees have accessed, with the name of
+    /// their copy in the trace if there is one. See `note_fuse_file()`.
+    fuse_files: HashMap<(dev_t, ino_t), Option<OsString>>,
     raw_recs: Vec<RawDataMetadata>,
     cpuid_records: Vec<CPUIDRecord>,
     ticks_semantics_: TicksSemantics,
     /// Keep the 'incomplete' (later renamed to 'version') file open until we
     /// rename it, so our flock() lock stays held on it.
     version_fd: ScopedFd,
+    /// See `trace_event_index`.
+    event_index_fd: ScopedFd,
     mmap_count: u32,
+    /// See `note_directory_read()`.
+    directories_read: RefCell<BTreeSet<OsString>>,
+    /// The files replay will take data from, named as in the trace, with
+    /// the file the tracee used. Hashed when the trace is closed, see
+    /// `file_hashes`.
+    backing_files: BTreeMap<OsString, OsString>,
     has_cpuid_faulting_: bool,
     supports_file_data_cloning_: bool,
+    compression: Compression,
+    /// See `set_round_robin_quantum()`.
+    round_robin_quantum: Option<Ticks>,
+    /// See `set_capture_reads()`.
+    capture_reads: Vec<OsString>,
+    /// See `PerfCounters::core_type()`.
+    core_type: Option<CoreType>,
+    /// See `record_environment`.
+    environment: RecordEnvironment,
+    /// Whether the trace directory was given with `rd record -o`, rather than
+    /// picked in the trace save directory.
+    explicit_trace_dir: bool,
 }
 
 impl Deref for TraceWriter {
@@ -201,18 +249,20 @@ impl TraceWriter {
         let mut frame = frame_msg.init_root::<frame::Builder>();
         frame.set_tid(t.tid);
         // DIFF NOTE: In rr ticks are signed. In rd they are not.
-        frame.set_ticks(t.tick_count() as i64);
+        let ticks = if ev.event_type() == EventType::EvInterceptedSyscall {
+            // The task is still running, blocked in the syscall, so what its
+            // counter has counted since it was resumed isn't in tick_count() yet.
+            t.tick_count() + t.hpc.read_ticks(t)
+        } else {
+            t.tick_count()
+        };
+        frame.set_ticks(ticks as i64);
         frame.set_monotonic_sec(monotonic_now_sec());
 
-        {
-            let mut mem_writes = frame.reborrow().init_mem_writes(self.raw_recs.len() as u32);
-            for (i, r) in self.raw_recs.iter().enumerate() {
-                let mut w = mem_writes.reborrow().get(i as u32);
-                w.set_tid(r.rec_tid);
-                w.set_addr(r.addr.as_usize() as u64);
-                w.set_size(r.size as u64);
-            }
-        }
+        write_mem_writes(
+            frame.reborrow().init_mem_writes(self.raw_recs.len() as u32),
+            &self.raw_recs,
+        );
         self.raw_recs.clear();
         frame.set_arch(to_trace_arch(t.arch()));
         {
@@ -313,8 +363,17 @@ impl TraceWriter {
                             o.set_device(opened.device);
                             o.set_inode(opened.inode.into());
                         }
+                    } else if let Some(batch) = e.scm_rights_batch {
+                        data.set_scm_rights_batch(batch as u32);
                     }
                 }
+                EventType::EvInterceptedSyscall => {
+                    let e = ev.intercepted_syscall_event();
+                    let mut syscall = event.init_intercepted_syscall();
+                    syscall.set_arch(to_trace_arch(e.arch));
+                    syscall.set_number(e.number);
+                    syscall.set_result(e.result);
+                }
                 _ => fatal!("Event type not recordable"),
             }
         }
@@ -324,7 +383,23 @@ impl TraceWriter {
             fatal!("Unable to write events");
         }
 
-        self.tick_time()
+        self.tick_time();
+        if self.global_time % EVENT_INDEX_INTERVAL == 0 {
+            self.write_event_index_entry();
+        }
+    }
+
+    /// Note where each substream is now, just before the frame for the
+    /// current global time gets written.
+    fn write_event_index_entry(&mut self) {
+        let mut entry = EventIndexEntry {
+            next_event: self.global_time,
+            offsets: Default::default(),
+        };
+        for &s in Substream::iter() {
+            entry.offsets[s as usize] = self.writer(s).uncompressed_pos();
+        }
+        append_event_index_entry(&self.event_index_fd, &entry);
     }
 
     /// Write mapped-region record to the trace.
@@ -370,18 +445,26 @@ impl TraceWriter {
                 e.set_fd(r.fd);
             }
             map.set_skip_monitoring_mapped_fd(skip_monitoring_mapped_fd);
+            if let MappingOrigin::RdBufferMapping { slots_skipped } = origin {
+                map.set_rd_mapping_slots_skipped(slots_skipped);
+            }
             let mut src = map.get_source();
             let mut backing_file_name = OsString::new();
 
             if origin == MappingOrigin::RemapMapping
                 || origin == MappingOrigin::PatchMapping
-                || origin == MappingOrigin::RdBufferMapping
+                || matches!(origin, MappingOrigin::RdBufferMapping { .. })
             {
                 src.reborrow().set_zero(());
-            } else if km.fsname().as_bytes().starts_with(b"/SYSV") {
+            } else if km.fsname().as_bytes().starts_with(b"/SYSV")
+                || km.shared_memory_kind() == Some(SharedMemoryKind::PosixShm)
+                || km.shared_memory_kind() == Some(SharedMemoryKind::Memfd)
+                || (origin == MappingOrigin::AttachMapping
+                    && !km.flags().contains(MapFlags::MAP_SHARED))
+            {
                 src.reborrow().set_trace(());
             } else if origin == MappingOrigin::SyscallMapping
-                && (km.inode() == 0 || km.fsname() == "/dev/zero (deleted)")
+                && (km.inode() == 0 || km.is_shared_anonymous())
             {
                 src.reborrow().set_zero(());
             } else if !km.fsname().as_bytes().starts_with(b"/") {
@@ -392,10 +475,26 @@ impl TraceWriter {
                     .files_assumed_immutable
                     .get(&(stat.st_dev, stat.st_ino));
 
+                let fuse_copy = if assumed_immutable.is_none() && is_fuse_file(&file_name) {
+                    self.note_fuse_file(km.fsname(), &file_name, stat)
+                } else {
+                    None
+                };
+
                 if assumed_immutable.is_some() {
                     src.reborrow()
                         .init_file()
                         .set_backing_file_name(assumed_immutable.unwrap().as_bytes());
+                } else if let Some(copy) = fuse_copy {
+                    // As for the executables copied below, don't create a shared mapping
+                    // of a file stored in the trace.
+                    if km.flags().contains(MapFlags::MAP_SHARED) {
+                        src.reborrow().set_trace(());
+                    } else {
+                        src.reborrow()
+                            .init_file()
+                            .set_backing_file_name(copy.as_bytes());
+                    }
                 } else if km.flags().contains(MapFlags::MAP_PRIVATE)
                     && self.try_clone_file(t, &file_name, &mut backing_file_name)
                 {
@@ -447,6 +546,13 @@ impl TraceWriter {
                 }
             }
 
+            if let m_map::source::File(f) = src.reborrow().which().unwrap() {
+                let backing = OsStr::from_bytes(&f.get_backing_file_name().unwrap()).to_owned();
+                self.backing_files
+                    .entry(backing)
+                    .or_insert_with(|| km.fsname().to_owned());
+            }
+
             record_in_trace = if let Trace(_) = src.which().unwrap() {
                 RecordInTrace::RecordInTrace
             } else {
@@ -517,6 +623,34 @@ impl TraceWriter {
             addr,
             rec_tid,
             size: d.len(),
+            cloned: None,
+        });
+    }
+
+    /// Like `write_raw()`, for `size` bytes that were cloned into the
+    /// cloned-data file `file_name`, at `offset`, rather than copied. The
+    /// data stays in that file; the trace only refers to it.
+    pub fn write_cloned_raw(
+        &mut self,
+        rec_tid: pid_t,
+        addr: RemotePtr<Void>,
+        size: usize,
+        file_name: &OsStr,
+        offset: u64,
+    ) {
+        // A cloned-data file holds data from many files, so it stands for
+        // itself.
+        self.backing_files
+            .entry(file_name.to_os_string())
+            .or_insert_with(|| file_name.to_os_string());
+        self.raw_recs.push(RawDataMetadata {
+            addr,
+            rec_tid,
+            size,
+            cloned: Some(ClonedData {
+                file_name: file_name.to_os_string(),
+                offset,
+            }),
         });
     }
 
@@ -544,6 +678,15 @@ impl TraceWriter {
                     cmd_line.set(i as u32, event_cmd_line[i].as_bytes());
                 }
                 exec.set_exe_base(e.exe_base().as_usize() as u64);
+                if let Some(event_fds) = e.attached_fds() {
+                    exec.set_attached(true);
+                    let mut fds = exec.init_attached_fds(event_fds.len() as u32);
+                    for (i, f) in event_fds.iter().enumerate() {
+                        let mut fd = fds.reborrow().get(i as u32);
+                        fd.set_fd(f.fd);
+                        fd.set_path(f.path.as_bytes());
+                    }
+                }
             }
             TraceTaskEventVariant::Exit(e) => {
                 task.init_exit().set_exit_status(e.exit_status().get());
@@ -581,13 +724,24 @@ impl TraceWriter {
             trace_stream: TraceStream::new(&make_trace_dir(file_name, output_trace_dir), 1),
             ticks_semantics_,
             mmap_count: 0,
+            directories_read: Default::default(),
+            backing_files: Default::default(),
             has_cpuid_faulting_: false,
             writers: Default::default(),
             files_assumed_immutable: Default::default(),
+            fuse_file_policy: Default::default(),
+            fuse_files: Default::default(),
             raw_recs: vec![],
             cpuid_records: vec![],
             version_fd: ScopedFd::new(),
+            event_index_fd: ScopedFd::new(),
             supports_file_data_cloning_: false,
+            compression: Compression::Zstd,
+            round_robin_quantum: None,
+            capture_reads: Vec::new(),
+            core_type: PerfCounters::core_type(),
+            environment: RecordEnvironment::capture(),
+            explicit_trace_dir: !output_trace_dir.is_empty(),
         };
 
         tw.bind_to_cpu = bind_to_cpu;
@@ -595,10 +749,21 @@ impl TraceWriter {
         for &s in Substream::iter() {
             tw.writers.insert(
                 s,
-                CompressedWriter::new(&tw.path(s), substream(s).block_size, substream(s).threads),
+                CompressedWriter::new(
+                    &tw.path(s),
+                    substream(s).block_size,
+                    substream(s).threads,
+                    tw.compression,
+                ),
             );
         }
 
+        let index_path = tw.event_index_path();
+        tw.event_index_fd = create_event_index(&index_path);
+        if !tw.event_index_fd.is_open() {
+            fatal!("Unable to create {:?}", index_path);
+        }
+
         let ver_path = tw.incomplete_version_path();
         tw.version_fd = ScopedFd::open_path_with_mode(
             ver_path.as_os_str(),
@@ -657,6 +822,76 @@ impl TraceWriter {
         tw
     }
 
+    /// Mark the trace as recorded with the round-robin scheduler using
+    /// timeslices of `quantum` ticks.
+    pub fn set_round_robin_quantum(&mut self, quantum: Option<Ticks>) {
+        self.round_robin_quantum = quantum;
+    }
+
+    /// Note the `--capture-reads` patterns in the trace header, so replay
+    /// can tell which files they applied to.
+    pub fn set_capture_reads(&mut self, patterns: Vec<OsString>) {
+        self.capture_reads = patterns;
+    }
+
+    /// What to do with files tracees open or map on FUSE filesystems, see
+    /// `fuse_files`.
+    pub fn fuse_file_policy(&self) -> FuseFilePolicy {
+        self.fuse_file_policy
+    }
+
+    pub fn set_fuse_file_policy(&mut self, policy: FuseFilePolicy) {
+        self.fuse_file_policy = policy;
+    }
+
+    /// A tracee accessed the file `name`, which is on a FUSE filesystem and
+    /// can be read at `path`. The first time for each file, warn about it or
+    /// copy it into the trace, depending on the policy. Returns the name of
+    /// the copy, relative to the trace directory, if there is one.
+    pub fn note_fuse_file(
+        &mut self,
+        name: &OsStr,
+        path: &OsStr,
+        stat: &libc::stat,
+    ) -> Option<OsString> {
+        let key = (stat.st_dev, stat.st_ino);
+        if let Some(copy) = self.fuse_files.get(&key) {
+            return copy.clone();
+        }
+        let copy = match self.fuse_file_policy {
+            FuseFilePolicy::Warn => {
+                log!(
+                    LogWarn,
+                    "{:?} is on a FUSE filesystem and may not be available at replay; \
+                     record with --copy-fuse-files to save it in the trace",
+                    name
+                );
+                None
+            }
+            FuseFilePolicy::Copy => {
+                let mut copy = OsString::new();
+                let mut trace_name = Vec::new();
+                write!(trace_name, "fuse_copy_{}_", self.fuse_files.len()).unwrap();
+                trace_name.extend_from_slice(file_name_bytes(name));
+                if stat.st_mode & S_IFMT == S_IFREG
+                    && self.copy_file_to(path, &trace_name, &mut copy)
+                {
+                    Some(copy)
+                } else {
+                    log!(
+                        LogWarn,
+                        "Couldn't copy {:?} from its FUSE filesystem into the trace; \
+                         it may not be available at replay",
+                        name
+                    );
+                    None
+                }
+            }
+        };
+        self.fuse_files.insert(key, copy.clone());
+        copy
+    }
+
     /// Called after the calling thread is actually bound to `bind_to_cpu`.
     pub fn setup_cpuid_records(
         &mut self,
@@ -687,6 +922,43 @@ impl TraceWriter {
             let mut w = self.writers.remove(s).unwrap();
             w.close(None);
         }
+        self.event_index_fd.close();
+
+        if !self.directories_read.borrow().is_empty() {
+            let mut names: Vec<u8> = Vec::new();
+            for name in self.directories_read.borrow().iter() {
+                names.extend_from_slice(name.as_bytes());
+                names.push(0);
+            }
+            if fs::write(self.directories_read_path(), names).is_err() {
+                fatal!("Unable to write {:?}", self.directories_read_path());
+            }
+        }
+
+        if !self.backing_files.is_empty() {
+            let mut hashes = BTreeMap::new();
+            for (backing, original) in &self.backing_files {
+                let path = self.trace_file_path(backing);
+                match hash_file(&path) {
+                    Ok(hash) => {
+                        let h = FileHash {
+                            original: original.clone(),
+                            hash,
+                        };
+                        hashes.insert(backing.clone(), h);
+                    }
+                    Err(e) => log!(
+                        LogWarn,
+                        "Can't hash {:?}, replay won't check it: {}",
+                        path,
+                        e
+                    ),
+                }
+            }
+            if fs::write(self.file_hashes_path(), format_file_hashes(hashes.iter())).is_err() {
+                fatal!("Unable to write {:?}", self.file_hashes_path());
+            }
+        }
 
         let mut header_msg = message::Builder::new_default();
         let mut header = header_msg.init_root::<header::Builder>();
@@ -701,11 +973,24 @@ impl TraceWriter {
         };
         header.set_cpuid_records(cpuid_data);
         header.set_xcr0(xcr0());
-        header.set_ticks_semantics(to_trace_ticks_semantics(
-            PerfCounters::default_ticks_semantics(),
-        ));
+        header.set_ticks_semantics(to_trace_ticks_semantics(self.ticks_semantics_));
         header.set_syscallbuf_protocol_version(SYSCALLBUF_PROTOCOL_VERSION);
         header.set_preload_thread_locals_recorded(true);
+        header.set_compression(to_trace_compression(self.compression));
+        header.set_page_size(page_size().try_into().unwrap());
+        header.set_round_robin_quantum(self.round_robin_quantum.unwrap_or(0));
+        header.set_core_type(to_trace_core_type(self.core_type));
+        if let Some(quota) = self.environment.cpu_quota {
+            header.set_cpu_quota_us(quota.quota_us);
+            header.set_cpu_period_us(quota.period_us);
+        }
+        header.set_nice_level(self.environment.nice);
+        let mut capture_reads = header
+            .reborrow()
+            .init_capture_reads(self.capture_reads.len() as u32);
+        for (i, pattern) in self.capture_reads.iter().enumerate() {
+            capture_reads.set(i as u32, pattern.as_bytes());
+        }
         // Add a random UUID to the trace metadata. This lets tools identify a trace
         // easily.
         match maybe_uuid {
@@ -730,9 +1015,21 @@ impl TraceWriter {
         self.version_fd.close();
     }
 
+    /// Note that a tracee listed the directory `path`. The list is written to
+    /// the trace when it's closed; see `TraceStream::directories_read()`.
+    pub fn note_directory_read(&self, path: &OsStr) {
+        self.directories_read.borrow_mut().insert(path.to_owned());
+    }
+
     /// We got far enough into recording that we should set this as the latest
     /// trace.
     pub fn make_latest_trace(&self) {
+        // A trace put somewhere explicitly isn't in the trace save directory,
+        // and recordings run in parallel, e.g. by CI, shouldn't fight over
+        // the link.
+        if self.explicit_trace_dir {
+            return;
+        }
         let link_name = latest_trace_symlink();
         // Try to update the symlink to `self`.  We only try attempt
         // to set the symlink once.  If the link is re-created after
@@ -740,8 +1037,9 @@ impl TraceWriter {
         // and it "won".  The link is then valid and points at some
         // very-recent trace, so that's good enough.
         //
-        // DIFF NOTE: rr swallows any error on unlink. We don't for now.
-        if unlink(link_name.as_os_str()).is_err() {
+        // DIFF NOTE: rr swallows any error on unlink. We only swallow the link
+        // not being there, which is also what losing that race looks like.
+        if unlink(link_name.as_os_str()).is_err() && errno() != libc::ENOENT {
             fatal!("Unable to unlink {:?}", link_name);
         }
 
@@ -763,6 +1061,10 @@ impl TraceWriter {
         self.ticks_semantics_
     }
 
+    pub fn set_ticks_semantics(&mut self, ticks_semantics: TicksSemantics) {
+        self.ticks_semantics_ = ticks_semantics;
+    }
+
     fn try_hardlink_file(&self, file_name: &OsStr, new_name: &mut OsString) -> bool {
         let base_file_name = Path::new(file_name).file_name().unwrap();
         let mut path: Vec<u8> = Vec::new();
@@ -825,11 +1127,14 @@ impl TraceWriter {
     }
 
     fn copy_file(&self, file_name: &OsStr, new_name: &mut OsString) -> bool {
-        let base_file_name = Path::new(file_name).file_name().unwrap();
         let mut path: Vec<u8> = Vec::new();
         write!(path, "mmap_clone_{}_", self.mmap_count).unwrap();
-        path.extend_from_slice(base_file_name.as_bytes());
+        path.extend_from_slice(file_name_bytes(file_name));
+        self.copy_file_to(file_name, &path, new_name)
+    }
 
+    /// Copy `file_name` to `path` in the trace directory.
+    fn copy_file_to(&self, file_name: &OsStr, path: &[u8], new_name: &mut OsString) -> bool {
         let src = ScopedFd::open_path(file_name, OFlag::O_RDONLY);
         if !src.is_open() {
             return false;
@@ -837,7 +1142,7 @@ impl TraceWriter {
         let mut dest_path = Vec::<u8>::new();
         dest_path.extend_from_slice(self.dir().as_bytes());
         dest_path.extend_from_slice(b"/");
-        dest_path.extend_from_slice(&path);
+        dest_path.extend_from_slice(path);
 
         let dest = ScopedFd::open_path_with_mode(
             dest_path.as_slice(),
@@ -849,7 +1154,7 @@ impl TraceWriter {
         }
 
         new_name.clear();
-        new_name.push(OsStr::from_bytes(&path));
+        new_name.push(OsStr::from_bytes(path));
         copy_file(dest.as_raw(), src.as_raw())
     }
 
@@ -896,6 +1201,13 @@ fn to_trace_syscall_state(state: SyscallState) -> TraceSyscallState {
     }
 }
 
+/// The last component of `file_name`, for naming copies in the trace after.
+fn file_name_bytes(file_name: &OsStr) -> &[u8] {
+    Path::new(file_name)
+        .file_name()
+        .map_or(&[][..], |n| n.as_bytes())
+}
+
 /// Given `file_name`, where `file_name` is relative to our root directory
 /// but is in the mount namespace of `t`, try to make it a file we can read.
 fn try_make_process_file_name(t: &RecordTask, file_name: &OsStr) -> OsString {
@@ -932,6 +1244,21 @@ fn try_make_process_file_name(t: &RecordTask, file_name: &OsStr) -> OsString {
     OsString::from_vec(process_file_name)
 }
 
+fn to_trace_compression(compression: Compression) -> TraceCompression {
+    match compression {
+        Compression::Brotli => TraceCompression::Brotli,
+        Compression::Zstd => TraceCompression::Zstd,
+    }
+}
+
+fn to_trace_core_type(core_type: Option<CoreType>) -> TraceCoreType {
+    match core_type {
+        None => TraceCoreType::Any,
+        Some(CoreType::Performance) => TraceCoreType::Performance,
+        Some(CoreType::Efficiency) => TraceCoreType::Efficiency,
+    }
+}
+
 fn to_trace_ticks_semantics(semantics: TicksSemantics) -> TraceTicksSemantics {
     match semantics {
         TicksSemantics::TicksRetiredConditionalBranches => {
@@ -940,3 +1267,20 @@ fn to_trace_ticks_semantics(semantics: TicksSemantics) -> TraceTicksSemantics {
         TicksSemantics::TicksTakenBranches => TraceTicksSemantics::TakenBranches,
     }
 }
+
+/// Fill in the frame's `mem_writes` with `recs`.
+pub(super) fn write_mem_writes(
+    mut mem_writes: struct_list::Builder<mem_write::Owned>,
+    recs: &[RawDataMetadata],
+) {
+    for (i, r) in recs.iter().enumerate() {
+        let mut w = mem_writes.reborrow().get(i as u32);
+        w.set_tid(r.rec_tid);
+        w.set_addr(r.addr.as_usize() as u64);
+        w.set_size(r.size as u64);
+        if let Some(cloned) = &r.cloned {
+            w.set_cloned_file(cloned.file_name.as_bytes());
+            w.set_cloned_offset(cloned.offset);
+        }
+    }
+}
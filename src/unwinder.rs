@@ -0,0 +1,164 @@
+use crate::{
+    elf_symbols::{file_offset, inlined_calls, nearest_symbol, ElfFiles, InlinedCall},
+    kernel_abi::SupportedArch,
+    remote_code_ptr::RemoteCodePtr,
+    remote_ptr::RemotePtr,
+    session::task::{task_common::read_val_mem, Task},
+};
+use gimli::{
+    BaseAddresses,
+    CfaRule,
+    EhFrame,
+    LittleEndian,
+    RegisterRule,
+    UninitializedUnwindContext,
+    UnwindSection,
+    X86_64,
+};
+
+/// Stop unwinding after this many frames; a corrupt stack can look like it
+/// goes on forever.
+const MAX_FRAMES: usize = 256;
+
+/// One frame of a backtrace.
+pub struct Frame {
+    pub ip: RemoteCodePtr,
+    /// `name+offset (file)`, see `nearest_symbol()`.
+    pub symbol: Option<String>,
+    /// The functions inlined at `ip`, innermost first.
+    pub inlined: Vec<InlinedCall>,
+}
+
+/// The registers that matter for unwinding.
+#[derive(Copy, Clone)]
+struct UnwindRegs {
+    ip: u64,
+    sp: u64,
+    bp: u64,
+}
+
+/// The stack of `t`, innermost frame first.
+///
+/// On x86-64 frames are unwound with the .eh_frame call frame information of
+/// the files mapped at each ip. Where there is none, and on x86, we follow
+/// the frame pointer chain, which only works for code compiled with frame
+/// pointers.
+pub fn backtrace(t: &mut dyn Task) -> Vec<Frame> {
+    let word_size = match t.arch() {
+        SupportedArch::X86 => 4,
+        SupportedArch::X64 => 8,
+    };
+    let mut regs = {
+        let r = t.regs_ref();
+        UnwindRegs {
+            ip: r.ip().register_value() as u64,
+            sp: r.sp().as_usize() as u64,
+            bp: r.bp() as u64,
+        }
+    };
+    let mut files = ElfFiles::default();
+    let mut frames = Vec::new();
+    while frames.len() < MAX_FRAMES {
+        let ip = RemoteCodePtr::from_val(regs.ip as usize);
+        // Return addresses point after the call instruction, which may be the
+        // start of a different function (or inlined call). Look up the call
+        // itself.
+        let lookup_ip = if frames.is_empty() {
+            ip
+        } else {
+            RemoteCodePtr::from_val(regs.ip as usize - 1)
+        };
+        frames.push(Frame {
+            ip,
+            symbol: nearest_symbol(&mut files, t, lookup_ip),
+            inlined: inlined_calls(&mut files, t, lookup_ip),
+        });
+
+        let maybe_next = match t.arch() {
+            SupportedArch::X64 => cfi_step(t, &mut files, regs, lookup_ip),
+            SupportedArch::X86 => None,
+        }
+        .or_else(|| frame_pointer_step(t, regs, word_size));
+        match maybe_next {
+            Some(next) if next.ip != 0 && next.sp > regs.sp => regs = next,
+            _ => break,
+        }
+    }
+    frames
+}
+
+/// Unwind one x86-64 frame with the .eh_frame CFI of the file mapped at
+/// `lookup_ip`. Only CFA rules based on rsp or rbp are supported, which is
+/// what compilers generate outside of hand-written assembly.
+fn cfi_step(
+    t: &mut dyn Task,
+    files: &mut ElfFiles,
+    regs: UnwindRegs,
+    lookup_ip: RemoteCodePtr,
+) -> Option<UnwindRegs> {
+    let (file_name, offset) = file_offset(t, lookup_ip)?;
+    let elf = files.get(&file_name)?;
+    let vaddr = elf.offset_to_vaddr(offset)?;
+    let (eh_frame_data, eh_frame_addr) = elf.section_data(".eh_frame")?;
+    let text_addr = elf.section_data(".text").map_or(0, |(_, addr)| addr);
+
+    let eh_frame = EhFrame::new(eh_frame_data, LittleEndian);
+    let bases = BaseAddresses::default()
+        .set_eh_frame(eh_frame_addr)
+        .set_text(text_addr);
+    let mut ctx = UninitializedUnwindContext::new();
+    let row = eh_frame
+        .unwind_info_for_address(&bases, &mut ctx, vaddr, EhFrame::cie_from_offset)
+        .ok()?;
+    let cfa = match row.cfa() {
+        CfaRule::RegisterAndOffset { register, offset } => {
+            let base = if *register == X86_64::RSP {
+                regs.sp
+            } else if *register == X86_64::RBP {
+                regs.bp
+            } else {
+                return None;
+            };
+            base.wrapping_add(*offset as u64)
+        }
+        CfaRule::Expression(_) => return None,
+    };
+    let ip = match row.register(X86_64::RA) {
+        RegisterRule::Offset(n) => read_word(t, cfa.wrapping_add(n as u64), 8)?,
+        _ => return None,
+    };
+    let bp = match row.register(X86_64::RBP) {
+        RegisterRule::Offset(n) => read_word(t, cfa.wrapping_add(n as u64), 8)?,
+        _ => regs.bp,
+    };
+    Some(UnwindRegs { ip, sp: cfa, bp })
+}
+
+/// Unwind one frame assuming the function saved the caller's frame pointer
+/// right below its return address and points the frame pointer there.
+fn frame_pointer_step(t: &mut dyn Task, regs: UnwindRegs, word_size: u64) -> Option<UnwindRegs> {
+    if regs.bp < regs.sp {
+        return None;
+    }
+    Some(UnwindRegs {
+        ip: read_word(t, regs.bp + word_size, word_size)?,
+        sp: regs.bp + 2 * word_size,
+        bp: read_word(t, regs.bp, word_size)?,
+    })
+}
+
+fn read_word(t: &mut dyn Task, addr: u64, word_size: u64) -> Option<u64> {
+    let mut ok = true;
+    let value = if word_size == 8 {
+        let ptr = RemotePtr::<u64>::new_from_val(addr as usize);
+        read_val_mem(t, ptr, Some(&mut ok))
+    } else {
+        let ptr = RemotePtr::<u32>::new_from_val(addr as usize);
+        read_val_mem(t, ptr, Some(&mut ok)) as u64
+    };
+    if ok {
+        Some(value)
+    } else {
+        None
+    }
+}
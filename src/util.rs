@@ -6,9 +6,13 @@ use crate::{
     },
     event::{Event, EventType},
     flags::{DumpOn, Flags},
-    kernel_abi::CloneParameterOrdering,
-    kernel_supplement::ARCH_SET_CPUID,
+    kernel_abi::{
+        common::{clone_args, open_how},
+        CloneParameterOrdering,
+    },
+    kernel_supplement::{ARCH_SET_CPUID, CLONE_ARGS_SIZE_VER0, OPEN_HOW_SIZE_VER0},
     log::LogLevel::{LogDebug, LogWarn},
+    perf_counters::PerfCounters,
     registers::Registers,
     remote_code_ptr::RemoteCodePtr,
     remote_ptr::{RemotePtr, Void},
@@ -24,7 +28,10 @@ use crate::{
     trace::trace_frame::FrameTime,
 };
 use libc::{
+    iovec,
     pid_t,
+    process_vm_readv,
+    process_vm_writev,
     pwrite64,
     siginfo_t,
     syscall,
@@ -38,7 +45,12 @@ use libc::{
     CLONE_SIGHAND,
     CLONE_THREAD,
     CLONE_VM,
+    E2BIG,
+    EFAULT,
     EINVAL,
+    ENOSYS,
+    EPERM,
+    ESRCH,
     STDERR_FILENO,
     S_IFDIR,
     S_IFREG,
@@ -46,7 +58,7 @@ use libc::{
 };
 use nix::{
     errno::errno,
-    sched::{sched_setaffinity, CpuSet},
+    sched::{sched_getaffinity, sched_setaffinity, CpuSet},
     sys::{
         mman::{MapFlags, ProtFlags},
         signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal},
@@ -87,7 +99,10 @@ use std::{
     path::Path,
     ptr::copy_nonoverlapping,
     slice,
-    sync::Mutex,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
 };
 
 #[cfg(target_arch = "x86")]
@@ -238,7 +253,7 @@ pub struct CPUIDRecord {
 }
 
 #[repr(C)]
-#[derive(Copy, Clone, Default)]
+#[derive(Copy, Clone, Default, Eq, PartialEq)]
 pub struct CPUIDData {
     pub eax: u32,
     pub ebx: u32,
@@ -258,6 +273,15 @@ pub fn cpuid(code: u32, subrequest: u32) -> CPUIDData {
     }
 }
 
+/// Read the time stamp counter of the CPU we're currently running on.
+pub fn rdtsc() -> u64 {
+    #[cfg(target_arch = "x86")]
+    let tsc = unsafe { std::arch::x86::_rdtsc() };
+    #[cfg(target_arch = "x86_64")]
+    let tsc = unsafe { std::arch::x86_64::_rdtsc() };
+    tsc
+}
+
 fn cpuid_record(eax: u32, ecx: u32) -> CPUIDRecord {
     CPUIDRecord {
         eax_in: eax,
@@ -457,7 +481,13 @@ pub fn page_size() -> usize {
 }
 
 pub fn ceil_page_size<T: Into<usize> + From<usize>>(size: T) -> T {
-    ((size.into() + page_size() - 1) & !(page_size() - 1)).into()
+    ceil_page_size_for(size, page_size())
+}
+
+/// Like `ceil_page_size()`, for a page size other than this machine's, e.g.
+/// the one a trace was recorded with.
+pub fn ceil_page_size_for<T: Into<usize> + From<usize>>(size: T, page_size: usize) -> T {
+    ((size.into() + page_size - 1) & !(page_size - 1)).into()
 }
 
 pub fn ceil_page_u64(size: u64) -> u64 {
@@ -469,6 +499,37 @@ pub fn floor_page_size<T: Into<usize> + From<usize>>(sz: T) -> T {
     (sz.into() & page_mask).into()
 }
 
+/// Read the `size` bytes of `t`'s memory at `start` at most `chunk_size`
+/// bytes at a time, so huge mappings don't need a buffer as big as they
+/// are. Each run of bytes that could be read is passed to `f` with its
+/// address; pages that can't be read (e.g. PROT_NONE guard pages, or past
+/// the end of a mapped file) are skipped.
+pub fn for_each_readable_chunk<F: FnMut(RemotePtr<Void>, &[u8])>(
+    t: &mut dyn Task,
+    start: RemotePtr<Void>,
+    size: usize,
+    chunk_size: usize,
+    mut f: F,
+) {
+    debug_assert!(chunk_size > 0 && chunk_size % page_size() == 0);
+    let end = start + size;
+    let mut buf = vec![0u8; min(size, chunk_size)];
+    let mut addr = start;
+    while addr < end {
+        let len = min(end - addr, chunk_size);
+        let valid = t.read_bytes_fallible(addr, &mut buf[0..len]).unwrap_or(0);
+        if valid > 0 {
+            f(addr, &buf[0..valid]);
+        }
+        addr = if valid < len {
+            // Skip the page the read stopped in.
+            RemotePtr::from(floor_page_size(addr.as_usize() + valid) + page_size())
+        } else {
+            addr + len
+        };
+    }
+}
+
 pub fn resize_shmem_segment(fd: &ScopedFd, num_bytes: usize) {
     if ftruncate(fd.as_raw(), num_bytes as libc::off_t).is_err() {
         // errno will be reported as part of fatal
@@ -605,6 +666,112 @@ pub fn pwrite_all_fallible(fd: i32, buf_initial: &[u8], offset: isize) -> Result
     Ok(written)
 }
 
+/// Cleared the first time process_vm_readv()/process_vm_writev() turn out to
+/// be unavailable (ENOSYS) or not allowed (EPERM, e.g. under some seccomp
+/// sandboxes), so we stop trying them.
+static PROCESS_VM_RW_USABLE: AtomicBool = AtomicBool::new(true);
+
+/// The most iovecs a single process_vm_readv() call accepts (UIO_MAXIOV).
+const PROCESS_VM_MAX_IOVECS: usize = 1024;
+
+/// Read the memory of `pid` at each of `reads` into the accompanying buffer
+/// with as few process_vm_readv() calls as possible.
+///
+/// Returns the number of bytes read, counting from the start of the first
+/// buffer: the kernel stops at the first page it can't read. That can be any
+/// page the tracee itself couldn't read, e.g. PROT_NONE ones, which
+/// /proc/<pid>/mem (but not this) can. Returns `None` if
+/// process_vm_readv() can't be used at all.
+pub fn process_vm_read_scattered(
+    pid: pid_t,
+    reads: &mut [(RemotePtr<Void>, &mut [u8])],
+) -> Option<usize> {
+    if !PROCESS_VM_RW_USABLE.load(Ordering::Relaxed) {
+        return None;
+    }
+    let mut total = 0;
+    for chunk in reads.chunks_mut(PROCESS_VM_MAX_IOVECS) {
+        let local: Vec<iovec> = chunk
+            .iter_mut()
+            .map(|(_, buf)| iovec {
+                iov_base: buf.as_mut_ptr().cast::<c_void>(),
+                iov_len: buf.len(),
+            })
+            .collect();
+        let remote: Vec<iovec> = chunk
+            .iter()
+            .map(|(addr, buf)| iovec {
+                iov_base: addr.as_usize() as *mut c_void,
+                iov_len: buf.len(),
+            })
+            .collect();
+        let wanted: usize = chunk.iter().map(|(_, buf)| buf.len()).sum();
+        let nread = unsafe {
+            process_vm_readv(
+                pid,
+                local.as_ptr(),
+                local.len() as _,
+                remote.as_ptr(),
+                remote.len() as _,
+                0,
+            )
+        };
+        if nread < 0 {
+            return process_vm_rw_failed(total);
+        }
+        total += nread as usize;
+        if nread as usize != wanted {
+            break;
+        }
+    }
+    Some(total)
+}
+
+/// Read `buf.len()` bytes of the memory of `pid` at `addr` with
+/// process_vm_readv(). See `process_vm_read_scattered()`.
+pub fn process_vm_read(pid: pid_t, addr: RemotePtr<Void>, buf: &mut [u8]) -> Option<usize> {
+    process_vm_read_scattered(pid, &mut [(addr, buf)])
+}
+
+/// Write `buf` to the memory of `pid` at `addr` with process_vm_writev().
+///
+/// Returns the number of bytes written. Unlike /proc/<pid>/mem this can't
+/// write to pages the tracee itself can't write, e.g. its code. Returns
+/// `None` if process_vm_writev() can't be used at all.
+pub fn process_vm_write(pid: pid_t, addr: RemotePtr<Void>, buf: &[u8]) -> Option<usize> {
+    if !PROCESS_VM_RW_USABLE.load(Ordering::Relaxed) {
+        return None;
+    }
+    let local = iovec {
+        iov_base: buf.as_ptr() as *mut c_void,
+        iov_len: buf.len(),
+    };
+    let remote = iovec {
+        iov_base: addr.as_usize() as *mut c_void,
+        iov_len: buf.len(),
+    };
+    let nwritten = unsafe { process_vm_writev(pid, &local, 1, &remote, 1, 0) };
+    if nwritten < 0 {
+        return process_vm_rw_failed(0);
+    }
+    Some(nwritten as usize)
+}
+
+fn process_vm_rw_failed(transferred: usize) -> Option<usize> {
+    match errno() {
+        ENOSYS | EPERM => {
+            log!(
+                LogDebug,
+                "process_vm_readv/writev unusable, falling back to /proc/<pid>/mem"
+            );
+            PROCESS_VM_RW_USABLE.store(false, Ordering::Relaxed);
+            None
+        }
+        // Typically EFAULT: the first page wasn't accessible.
+        _ => Some(transferred),
+    }
+}
+
 /// @TODO Hardcoded to false.
 pub fn check_for_pax_kernel() -> bool {
     false
@@ -1108,6 +1275,88 @@ pub fn extract_clone_parameters(t: &dyn Task) -> CloneParameters {
     rd_arch_function_selfless!(extract_clone_parameters_arch, t.arch(), t.regs_ref())
 }
 
+/// Read the argument struct of a clone3() that `t` is entering, `size`
+/// bytes at `addr`. Returns the errno the kernel fails the clone3() with if
+/// the struct is unacceptable.
+pub fn read_clone_args(
+    t: &mut dyn Task,
+    addr: RemotePtr<Void>,
+    size: usize,
+) -> Result<clone_args, i32> {
+    read_extensible_struct(t, addr, size, CLONE_ARGS_SIZE_VER0 as usize)
+}
+
+/// Read the `open_how` of an openat2() that `t` is entering, `size` bytes
+/// at `addr`. Returns the errno the kernel fails the openat2() with if the
+/// struct is unacceptable.
+pub fn read_open_how(
+    t: &mut dyn Task,
+    addr: RemotePtr<Void>,
+    size: usize,
+) -> Result<open_how, i32> {
+    read_extensible_struct(t, addr, size, OPEN_HOW_SIZE_VER0 as usize)
+}
+
+/// Read a syscall argument struct that's allowed to grow the way the kernel
+/// does: one shorter than ours (but at least `min_size`) is zero-extended,
+/// and one that's longer, however long, must be all zeroes past our fields.
+fn read_extensible_struct<T: Default>(
+    t: &mut dyn Task,
+    addr: RemotePtr<Void>,
+    size: usize,
+    min_size: usize,
+) -> Result<T, i32> {
+    if size < min_size {
+        return Err(EINVAL);
+    }
+    let addr = RemotePtr::<u8>::cast(addr);
+    let known = size_of::<T>();
+    let mut ok = true;
+    let bytes = read_mem(t, addr, min(size, known), Some(&mut ok));
+    if !ok {
+        return Err(EFAULT);
+    }
+    // A page at a time, so a huge size doesn't mean a huge buffer.
+    let mut offset = known;
+    while offset < size {
+        let len = min(size - offset, page_size());
+        let tail = read_mem(t, addr + offset, len, Some(&mut ok));
+        if !ok {
+            return Err(EFAULT);
+        }
+        if tail.iter().any(|&b| b != 0) {
+            return Err(E2BIG);
+        }
+        offset += len;
+    }
+    let mut result = T::default();
+    unsafe {
+        copy_nonoverlapping(bytes.as_ptr(), &raw mut result as *mut u8, bytes.len());
+    }
+    Ok(result)
+}
+
+/// The clone(2) parameters in the argument struct of a clone3().
+pub fn clone3_parameters(args: &clone_args) -> CloneParameters {
+    let flags = args.flags as i32;
+    let mut result = CloneParameters::default();
+    // The kernel starts the child with its stack pointer at the top of the
+    // stack it's given.
+    if args.stack != 0 {
+        result.stack = RemotePtr::new_from_val((args.stack + args.stack_size) as usize);
+    }
+    if flags & CLONE_PARENT_SETTID != 0 {
+        result.ptid = RemotePtr::new_from_val(args.parent_tid as usize);
+    }
+    if flags & (CLONE_CHILD_SETTID | CLONE_CHILD_CLEARTID) != 0 {
+        result.ctid = RemotePtr::new_from_val(args.child_tid as usize);
+    }
+    if flags & CLONE_SETTLS != 0 {
+        result.tls = RemotePtr::new_from_val(args.tls as usize);
+    }
+    result
+}
+
 /// Convert the flags passed to the clone() syscall, `flags_arg`, into
 /// the format understood by `clone_task_common()`.
 pub fn clone_flags_to_task_flags(flags_arg: i32) -> CloneFlags {
@@ -1146,6 +1395,14 @@ pub fn is_zombie_process(pid: pid_t) -> bool {
     return state.is_empty() || state[0].is_empty() || state[0].as_bytes()[0] == b'Z';
 }
 
+/// Whether `pid` is in uninterruptible sleep, e.g. waiting for an NFS server
+/// or a FUSE daemon. Signals, SIGKILL included, don't take effect until it
+/// wakes up.
+pub fn is_uninterruptible_sleep(pid: pid_t) -> bool {
+    let state = read_proc_status_fields(pid, &[b"State"]).unwrap_or(Vec::new());
+    !state.is_empty() && state[0].as_bytes().first() == Some(&b'D')
+}
+
 pub fn u8_raw_slice<D: Sized>(data: &D) -> *const [u8] {
     unsafe { slice::from_raw_parts(data as *const D as *const u8, size_of::<D>()) }
 }
@@ -1196,6 +1453,21 @@ pub fn trapped_instruction_at<T: Task>(t: &mut T, ip: RemoteCodePtr) -> TrappedI
     TrappedInstruction::None
 }
 
+/// The instruction rd emulates after a trap that ends right before the end of
+/// `code`, i.e. that `code` is the code up to the ip an instruction trap event
+/// was recorded with. Only RDTSC, RDTSCP and CPUID are emulated that way.
+pub fn emulated_instruction_ending_with(code: &[u8]) -> TrappedInstruction {
+    if code.ends_with(&RDTSCP_INSN) {
+        TrappedInstruction::Rdtscp
+    } else if code.ends_with(&RDTSC_INSN) {
+        TrappedInstruction::Rdtsc
+    } else if code.ends_with(&CPUID_INSN) {
+        TrappedInstruction::CpuId
+    } else {
+        TrappedInstruction::None
+    }
+}
+
 pub enum BindCPU {
     /// `RandomCPU` means binding to a randomly chosen CPU.
     RandomCPU,
@@ -1224,7 +1496,25 @@ pub fn choose_cpu(bind_cpu: BindCPU) -> Option<u32> {
         // performance win in certain circumstances,
         // presumably due to cheaper context switching and/or
         // better interaction with CPU frequency scaling.
-        BindCPU::BindToCPU(num) => Some(num),
+        BindCPU::BindToCPU(num) => {
+            if let (Some(core_type), Some(cpus)) =
+                (PerfCounters::core_type(), PerfCounters::core_type_cpus())
+            {
+                if !cpus.contains(&num) {
+                    clean_fatal!(
+                        "CPU {} is not a {}; use --core-type to pick the kind of core to run on.",
+                        num,
+                        core_type
+                    );
+                }
+            }
+            Some(num)
+        }
+        BindCPU::RandomCPU if PerfCounters::core_type().is_some() => {
+            // On a hybrid CPU only cores of the kind whose PMU we use will do.
+            let cpus = tracee_cpus();
+            Some(cpus[random::<usize>() % cpus.len()])
+        }
         BindCPU::RandomCPU => {
             let maybe_cpu = get_random_cpu_cgroup();
             match maybe_cpu {
@@ -1430,19 +1720,81 @@ pub fn read_proc_status_fields(tid: pid_t, matches_for: &[&[u8]]) -> io::Result<
 }
 
 // Returns true if we succeeded, false if we failed because the
-// requested CPU does not exist/is not available.
+// requested CPU does not exist/is not available (or doesn't fit in a cpu_set_t).
 pub fn set_cpu_affinity(cpu: u32) -> bool {
+    set_task_cpu_affinity(0, cpu)
+}
+
+/// Like `set_cpu_affinity()` but for task `tid`. Also returns false if the
+/// task has gone away.
+pub fn set_task_cpu_affinity(tid: pid_t, cpu: u32) -> bool {
+    let mut mask = CpuSet::new();
+    if mask.set(cpu as usize).is_err() {
+        return false;
+    }
+    if sched_setaffinity(Pid::from_raw(tid), &mask).is_err() {
+        if errno() == EINVAL || (tid != 0 && errno() == ESRCH) {
+            return false;
+        }
+        if tid == 0 {
+            fatal!("Couldn't bind to CPU `{}`", cpu);
+        }
+        fatal!("Couldn't bind task `{}` to CPU `{}`", tid, cpu);
+    }
+    true
+}
+
+/// Like `set_cpu_affinity()` but allows any of `cpus`.
+pub fn set_cpus_affinity(cpus: &[u32]) -> bool {
     let mut mask = CpuSet::new();
-    mask.set(cpu as usize).unwrap();
+    for &cpu in cpus {
+        if mask.set(cpu as usize).is_err() {
+            return false;
+        }
+    }
     if sched_setaffinity(Pid::from_raw(0), &mask).is_err() {
         if errno() == EINVAL {
             return false;
         }
-        fatal!("Couldn't bind to CPU `{}`", cpu);
+        fatal!("Couldn't bind to CPUs `{:?}`", cpus);
     }
     true
 }
 
+/// The CPUs tracees may run on: those rd is allowed to run on, and on a
+/// hybrid CPU only those with the kind of core whose PMU we use. Never empty;
+/// exits if rd isn't allowed to run on any core of that kind.
+pub fn tracee_cpus() -> Vec<u32> {
+    let cpus = allowed_cpus();
+    match (PerfCounters::core_type(), PerfCounters::core_type_cpus()) {
+        (Some(core_type), Some(core_type_cpus)) => {
+            let cpus: Vec<u32> = cpus
+                .into_iter()
+                .filter(|cpu| core_type_cpus.contains(cpu))
+                .collect();
+            if cpus.is_empty() {
+                clean_fatal!(
+                    "rd isn't allowed to run on any {}; use --core-type to pick the kind of core to run on.",
+                    core_type
+                );
+            }
+            cpus
+        }
+        _ => cpus,
+    }
+}
+
+/// The CPUs rd is allowed to run on, in ascending order.
+pub fn allowed_cpus() -> Vec<u32> {
+    match sched_getaffinity(Pid::from_raw(0)) {
+        Ok(mask) => (0..CpuSet::count())
+            .filter(|&cpu| mask.is_set(cpu).unwrap_or(false))
+            .map(|cpu| cpu as u32)
+            .collect(),
+        Err(_) => (0..get_num_cpus()).collect(),
+    }
+}
+
 pub fn to_cstring_array(ar: &[OsString]) -> Vec<CString> {
     let mut res = Vec::<CString>::new();
     for a in ar {
@@ -1534,6 +1886,26 @@ pub fn cpuid_compatible(trace_records: &[CPUIDRecord]) -> bool {
     }
 }
 
+/// Return the recorded CPUID leaves whose values differ from the ones this
+/// machine reports, paired with the values seen now. This is for diagnostics
+/// only: some leaves (e.g. the APIC ID in leaf 1) differ between CPUs of the
+/// very same machine.
+pub fn cpuid_mismatches(trace_records: &[CPUIDRecord]) -> Vec<(CPUIDRecord, CPUIDData)> {
+    let mut mismatches = Vec::new();
+    for rec in trace_records {
+        let ecx_in = if rec.ecx_in == std::u32::MAX {
+            0
+        } else {
+            rec.ecx_in
+        };
+        let current = cpuid(rec.eax_in, ecx_in);
+        if current != rec.out {
+            mismatches.push((*rec, current));
+        }
+    }
+    mismatches
+}
+
 pub fn has_effective_caps(_caps: u64) -> bool {
     unimplemented!()
 }
@@ -1560,3 +1932,68 @@ pub fn is_proc_fd_dir(filename_os: &OsStr) -> bool {
     let filename = filename_os.as_bytes();
     filename.starts_with(b"/proc/") && (filename.ends_with(b"/fd") || filename.ends_with(b"/fd/"))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nix::{
+        sys::mman::{mmap, mprotect, munmap},
+        unistd::getpid,
+    };
+    use std::ptr::null_mut;
+
+    #[test]
+    fn process_vm_read_write() {
+        let pid = getpid().as_raw();
+        let src = [1u8, 2, 3, 4, 5, 6, 7, 8];
+        let src_addr = RemotePtr::new_from_val(src.as_ptr() as usize);
+        let mut dst = [0u8; 8];
+        // A seccomp sandbox may not allow process_vm_readv(), and then there
+        // is nothing to test: rd uses /proc/<pid>/mem instead.
+        let nread = match process_vm_read(pid, src_addr, &mut dst) {
+            Some(nread) => nread,
+            None => return,
+        };
+        assert_eq!(nread, 8);
+        assert_eq!(dst, src);
+
+        let mut a = [0u8; 3];
+        let mut b = [0u8; 2];
+        let mut reads = [(src_addr + 5usize, &mut a[..]), (src_addr, &mut b[..])];
+        assert_eq!(process_vm_read_scattered(pid, &mut reads), Some(5));
+        assert_eq!(a, [6, 7, 8]);
+        assert_eq!(b, [1, 2]);
+
+        let dst_addr = RemotePtr::new_from_val(dst.as_mut_ptr() as usize);
+        assert_eq!(process_vm_write(pid, dst_addr, &[9; 4]), Some(4));
+        assert_eq!(dst, [9, 9, 9, 9, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn process_vm_read_stops_at_inaccessible_page() {
+        let pid = getpid().as_raw();
+        let size = page_size();
+        let p = unsafe {
+            mmap(
+                null_mut(),
+                2 * size,
+                ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+                MapFlags::MAP_PRIVATE | MapFlags::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        }
+        .unwrap();
+        let addr = RemotePtr::new_from_val(p as usize);
+        let second_page = (p as usize + size) as *mut c_void;
+        unsafe { mprotect(second_page, size, ProtFlags::PROT_NONE) }.unwrap();
+
+        let mut buf = vec![0u8; 2 * size];
+        if let Some(nread) = process_vm_read(pid, addr, &mut buf) {
+            assert_eq!(nread, size);
+            assert_eq!(process_vm_read(pid, addr + size, &mut buf[..1]), Some(0));
+            assert_eq!(process_vm_write(pid, addr + size, &buf[..1]), Some(0));
+        }
+        unsafe { munmap(p, 2 * size) }.unwrap();
+    }
+}
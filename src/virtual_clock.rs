@@ -0,0 +1,101 @@
+//! `rd record --virtual-clock`. Normally tracees read the host's clocks, and
+//! every recording of a program ends up with different times in it even when
+//! nothing else differs, which gets in the way of comparing traces.
+//!
+//! With the virtual clock, clock_gettime(), gettimeofday() and time() are
+//! answered by rd instead of the kernel. The vdso versions are patched into
+//! real syscalls at exec, so that covers the libc functions too. The time
+//! returned starts at a fixed point and advances a fixed step per trace event,
+//! so it only depends on how far the recording has got: two recordings of the
+//! same deterministic program see the same times.
+//! Every call is an event of its own, so the clocks never stand still between
+//! two calls.
+//!
+//! The values are recorded like any other syscall result. Replay doesn't
+//! need to know the virtual clock was used.
+
+use crate::trace::trace_frame::FrameTime;
+use libc::{
+    clockid_t,
+    CLOCK_BOOTTIME,
+    CLOCK_BOOTTIME_ALARM,
+    CLOCK_MONOTONIC,
+    CLOCK_MONOTONIC_COARSE,
+    CLOCK_MONOTONIC_RAW,
+    CLOCK_PROCESS_CPUTIME_ID,
+    CLOCK_REALTIME,
+    CLOCK_REALTIME_ALARM,
+    CLOCK_REALTIME_COARSE,
+    CLOCK_TAI,
+    CLOCK_THREAD_CPUTIME_ID,
+};
+
+const NS_PER_SEC: u64 = 1_000_000_000;
+
+/// Where the wall clocks start: 2020-01-01T00:00:00Z.
+const REALTIME_START_SECS: u64 = 1_577_836_800;
+
+/// Where the monotonic clocks start. Programs sometimes treat 0 as "never".
+const MONOTONIC_START_SECS: u64 = 1000;
+
+/// How far the clocks advance per trace event by default.
+pub const DEFAULT_STEP_NS: u64 = 1000;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct VirtualClock {
+    step_ns: u64,
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        VirtualClock::new(DEFAULT_STEP_NS)
+    }
+}
+
+impl VirtualClock {
+    pub fn new(step_ns: u64) -> VirtualClock {
+        debug_assert!(step_ns > 0);
+        VirtualClock { step_ns }
+    }
+
+    /// The time of `clock` at the event at `time`, in seconds and
+    /// nanoseconds. Returns `None` for clocks the virtual clock doesn't
+    /// cover, i.e. the dynamic clocks of other processes' CPU time and of
+    /// PTP devices; those are left to the kernel.
+    pub fn now(&self, clock: clockid_t, time: FrameTime) -> Option<(u64, u64)> {
+        let start_secs = match clock {
+            CLOCK_REALTIME | CLOCK_REALTIME_COARSE | CLOCK_REALTIME_ALARM | CLOCK_TAI => {
+                REALTIME_START_SECS
+            }
+            CLOCK_MONOTONIC
+            | CLOCK_MONOTONIC_RAW
+            | CLOCK_MONOTONIC_COARSE
+            | CLOCK_BOOTTIME
+            | CLOCK_BOOTTIME_ALARM => MONOTONIC_START_SECS,
+            CLOCK_PROCESS_CPUTIME_ID | CLOCK_THREAD_CPUTIME_ID => 0,
+            _ => return None,
+        };
+        let elapsed_ns = time.saturating_mul(self.step_ns);
+        Some((
+            start_secs + elapsed_ns / NS_PER_SEC,
+            elapsed_ns % NS_PER_SEC,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn advances_per_event() {
+        let clock = VirtualClock::new(250_000_000);
+        assert_eq!(clock.now(CLOCK_REALTIME, 0), Some((REALTIME_START_SECS, 0)));
+        assert_eq!(
+            clock.now(CLOCK_MONOTONIC, 5),
+            Some((MONOTONIC_START_SECS + 1, 250_000_000))
+        );
+        assert!(clock.now(CLOCK_MONOTONIC, 6) > clock.now(CLOCK_MONOTONIC, 5));
+        assert_eq!(clock.now(-2, 5), None);
+    }
+}
@@ -155,6 +155,16 @@ impl WaitStatus {
         }
     }
 
+    /// Could this be the PTRACE_EVENT_STOP injected by a PTRACE_INTERRUPT?
+    /// Those report SIGTRAP, but we sometimes see SIGSTOP at interrupts, though
+    /// the docs don't mention that. A SIGSTOP group-stop therefore can't be
+    /// told apart from an interrupt stop by the status alone; callers have to
+    /// know whether they sent a PTRACE_INTERRUPT.
+    pub fn is_ptrace_interrupt_stop(&self) -> bool {
+        let sig = self.maybe_group_stop_sig();
+        sig == SIGTRAP || sig == SIGSTOP
+    }
+
     pub fn is_syscall(&self) -> bool {
         unsafe {
             // Eliminate some obvious im-possibilities.
@@ -256,6 +266,9 @@ impl Display for WaitStatus {
                 " (STOP-{})",
                 signal_name(self.maybe_stop_sig().unwrap_sig())
             ),
+            WaitType::GroupStop if self.maybe_group_stop_sig() == SIGTRAP => {
+                write!(f, " (PTRACE-INTERRUPT)")
+            }
             WaitType::GroupStop => write!(
                 f,
                 " (GROUP-STOP-{})",
@@ -386,3 +399,28 @@ impl Display for MaybeStopSignal {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use libc::SIGTSTP;
+
+    fn event_stop(sig: i32) -> WaitStatus {
+        WaitStatus::new(((PTRACE_EVENT_STOP as i32) << 16) | (sig << 8) | 0x7f)
+    }
+
+    #[test]
+    fn group_stops() {
+        let tstp = event_stop(SIGTSTP);
+        assert_eq!(tstp.wait_type(), WaitType::GroupStop);
+        assert!(tstp.maybe_group_stop_sig() == SIGTSTP);
+        assert!(tstp.maybe_stop_sig().is_not_sig());
+        assert!(!tstp.maybe_ptrace_event().is_ptrace_event());
+        assert!(!tstp.is_ptrace_interrupt_stop());
+
+        let interrupt = event_stop(SIGTRAP);
+        assert_eq!(interrupt.wait_type(), WaitType::GroupStop);
+        assert!(interrupt.is_ptrace_interrupt_stop());
+        assert!(!WaitStatus::for_stop_sig(SIGTRAP).is_ptrace_interrupt_stop());
+    }
+}
@@ -1,6 +1,7 @@
 use crate::log::LogLevel::LogDebug;
 use std::{
     cell::RefCell,
+    cmp::Ordering,
     collections::{hash_set::Iter, HashSet},
     hash::{Hash, Hasher},
     ops::Deref,
@@ -26,6 +27,22 @@ impl<T> PartialEq for WeakPtrWrap<T> {
 
 impl<T> Eq for WeakPtrWrap<T> {}
 
+/// Ordering is by address of the RefCell. This is arbitrary but stable for
+/// the lifetime of the pointee, which is all that ordered containers need.
+impl<T> PartialOrd for WeakPtrWrap<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for WeakPtrWrap<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let addr = self.0.as_ptr().cast::<u8>() as usize;
+        let other_addr = other.0.as_ptr().cast::<u8>() as usize;
+        addr.cmp(&other_addr)
+    }
+}
+
 impl<T> Hash for WeakPtrWrap<T> {
     fn hash<H: Hasher>(&self, state: &mut H) {
         // We could upgrade the weak pointer and then take numeric address
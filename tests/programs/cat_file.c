@@ -0,0 +1,22 @@
+#include <fcntl.h>
+#include <stdio.h>
+#include <unistd.h>
+
+int main(int argc, char** argv) {
+  char buf[256];
+  ssize_t n;
+  int fd;
+  if (argc != 2) {
+    fprintf(stderr, "usage: %s FILE\n", argv[0]);
+    return 2;
+  }
+  fd = open(argv[1], O_RDONLY);
+  if (fd < 0) {
+    perror("open");
+    return 1;
+  }
+  while ((n = read(fd, buf, sizeof(buf))) > 0) {
+    fwrite(buf, 1, n, stdout);
+  }
+  return n < 0;
+}
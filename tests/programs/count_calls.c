@@ -0,0 +1,14 @@
+#include <stdio.h>
+
+/* Tests set breakpoints here. */
+void hit(int i) { (void)i; }
+
+int main(void) {
+  int i;
+  printf("%p\n", (void*)hit);
+  fflush(stdout);
+  for (i = 0; i < 5; ++i) {
+    hit(i);
+  }
+  return 0;
+}
@@ -0,0 +1,18 @@
+#include <stdio.h>
+#include <sys/ioctl.h>
+#include <unistd.h>
+
+int main(void) {
+  int fds[2];
+  int available = -1;
+  if (pipe(fds) < 0 || write(fds[1], "hello", 5) != 5) {
+    perror("filling a pipe");
+    return 1;
+  }
+  if (ioctl(fds[0], FIONREAD, &available) < 0) {
+    perror("FIONREAD");
+    return 1;
+  }
+  printf("%d bytes in the pipe\n", available);
+  return 0;
+}
@@ -0,0 +1,33 @@
+#include <signal.h>
+#include <stdio.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+int main(void) {
+  int fds[2];
+  int status;
+  char c;
+  if (pipe(fds) < 0) {
+    perror("pipe");
+    return 1;
+  }
+  pid_t child = fork();
+  if (child < 0) {
+    perror("fork");
+    return 1;
+  }
+  if (child == 0) {
+    /* Nothing is ever written, so this blocks until the SIGKILL. */
+    read(fds[0], &c, 1);
+    _exit(0);
+  }
+  /* Give the child time to block in read(). */
+  usleep(100000);
+  kill(child, SIGKILL);
+  if (waitpid(child, &status, 0) != child) {
+    perror("waitpid");
+    return 1;
+  }
+  printf("child killed by %d\n", WTERMSIG(status));
+  return 0;
+}
@@ -0,0 +1,24 @@
+#include <pthread.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <unistd.h>
+
+static pthread_t leader;
+
+static void* run(void* arg) {
+  (void)arg;
+  /* Wait until the leader is a zombie. */
+  pthread_join(leader, NULL);
+  printf("thread outlived its leader\n");
+  fflush(stdout);
+  exit(7);
+}
+
+int main(void) {
+  pthread_t thread;
+  leader = pthread_self();
+  if (pthread_create(&thread, NULL, run, NULL) != 0) {
+    return 1;
+  }
+  pthread_exit(NULL);
+}
@@ -0,0 +1,31 @@
+#include <fcntl.h>
+#include <stdio.h>
+#include <sys/random.h>
+#include <unistd.h>
+
+static void print_bytes(const char* name, const unsigned char* bytes,
+                        size_t len) {
+  size_t i;
+  printf("%s ", name);
+  for (i = 0; i < len; ++i) {
+    printf("%02x", bytes[i]);
+  }
+  printf("\n");
+}
+
+int main(void) {
+  unsigned char bytes[16];
+  int fd;
+  if (getrandom(bytes, sizeof(bytes), 0) != sizeof(bytes)) {
+    perror("getrandom");
+    return 1;
+  }
+  print_bytes("getrandom", bytes, sizeof(bytes));
+  fd = open("/dev/urandom", O_RDONLY);
+  if (fd < 0 || read(fd, bytes, sizeof(bytes)) != sizeof(bytes)) {
+    perror("reading /dev/urandom");
+    return 1;
+  }
+  print_bytes("urandom", bytes, sizeof(bytes));
+  return 0;
+}
@@ -0,0 +1,26 @@
+#include <signal.h>
+#include <stdio.h>
+#include <sys/signalfd.h>
+#include <unistd.h>
+
+int main(void) {
+  sigset_t mask;
+  sigemptyset(&mask);
+  sigaddset(&mask, SIGUSR1);
+  sigprocmask(SIG_BLOCK, &mask, NULL);
+
+  int fd = signalfd(-1, &mask, SFD_CLOEXEC);
+  if (fd < 0) {
+    perror("signalfd");
+    return 1;
+  }
+  kill(getpid(), SIGUSR1);
+
+  struct signalfd_siginfo si;
+  if (read(fd, &si, sizeof(si)) != sizeof(si)) {
+    perror("read");
+    return 1;
+  }
+  printf("signal %u from self %d\n", si.ssi_signo, si.ssi_pid == (unsigned)getpid());
+  return 0;
+}
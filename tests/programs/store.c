@@ -0,0 +1,11 @@
+#include <stdio.h>
+
+static volatile int counter;
+
+int main(void) {
+  printf("%p\n", (void*)&counter);
+  fflush(stdout);
+  /* No syscall writes this, so only replaying can tell what did. */
+  counter = 42;
+  return 0;
+}
@@ -0,0 +1,11 @@
+#include <link.h>
+#include <stdio.h>
+
+__thread int tls_var = 42;
+
+int main(void) {
+  /* The first link_map is the main program's. */
+  printf("%p %p\n", (void*)&tls_var, (void*)_r_debug.r_map);
+  fflush(stdout);
+  return 0;
+}
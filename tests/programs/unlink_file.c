@@ -0,0 +1,16 @@
+#include <stdio.h>
+#include <sys/stat.h>
+#include <unistd.h>
+
+int main(int argc, char** argv) {
+  struct stat st;
+  if (argc != 2) {
+    fprintf(stderr, "usage: %s FILE\n", argv[0]);
+    return 2;
+  }
+  if (unlink(argv[1]) < 0) {
+    perror("unlink");
+    return 1;
+  }
+  return stat(argv[1], &st) == 0;
+}
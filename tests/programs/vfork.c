@@ -0,0 +1,21 @@
+#include <stdio.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+int main(void) {
+  int status;
+  pid_t child = vfork();
+  if (child < 0) {
+    perror("vfork");
+    return 1;
+  }
+  if (child == 0) {
+    _exit(5);
+  }
+  if (waitpid(child, &status, 0) != child) {
+    perror("waitpid");
+    return 1;
+  }
+  printf("vfork child exited with %d\n", WEXITSTATUS(status));
+  return 0;
+}
@@ -0,0 +1,27 @@
+#include <stdio.h>
+#include <sys/time.h>
+#include <time.h>
+
+int main(void) {
+  struct timespec realtime, mono1, mono2;
+  struct timeval tv;
+  time_t now;
+  if (clock_gettime(CLOCK_REALTIME, &realtime) < 0 ||
+      clock_gettime(CLOCK_MONOTONIC, &mono1) < 0 ||
+      clock_gettime(CLOCK_MONOTONIC, &mono2) < 0 ||
+      gettimeofday(&tv, NULL) < 0) {
+    perror("reading the clocks");
+    return 1;
+  }
+  now = time(NULL);
+  printf("clock_gettime %ld\n", (long)realtime.tv_sec);
+  printf("gettimeofday %ld\n", (long)tv.tv_sec);
+  printf("time %ld\n", (long)now);
+  printf("monotonic %s\n",
+         mono2.tv_sec > mono1.tv_sec ||
+                 (mono2.tv_sec == mono1.tv_sec &&
+                  mono2.tv_nsec > mono1.tv_nsec)
+             ? "advances"
+             : "stands still");
+  return 0;
+}
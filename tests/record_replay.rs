@@ -0,0 +1,354 @@
+//! Record the programs in tests/programs, replay them and check the traces,
+//! and replay the checked-in traces in tests/traces.
+//!
+//! These need perf counters, and most need a C compiler; they're skipped on
+//! machines without them.
+
+#[macro_use]
+mod support;
+
+use std::fs;
+use support::{assert_trace_replays, checked_in_trace, Recording, ScratchDir, TestProgram};
+
+#[test]
+fn hello() {
+    require_recording!();
+    let recording = TestProgram::build("hello").record(&[]);
+    assert_eq!(recording.stdout(), "hello from the tracee\n");
+    recording.assert_has_syscall("write");
+    recording.assert_replays();
+}
+
+#[test]
+fn exit_code() {
+    require_recording!();
+    let recording = TestProgram::build("exit_code").record(&["3"]);
+    assert_eq!(recording.output.status.code(), Some(3));
+    recording.assert_has_syscall("exit_group");
+    recording.assert_replays();
+}
+
+#[test]
+fn signalfd() {
+    require_recording!();
+    let recording = TestProgram::build("signalfd").record(&[]);
+    assert_eq!(recording.stdout(), "signal 10 from self 1\n");
+    recording.assert_has_syscall("signalfd4");
+    recording.assert_replays();
+}
+
+#[test]
+fn leader_exit() {
+    require_recording!();
+    let recording = TestProgram::build("leader_exit").record(&[]);
+    assert_eq!(recording.stdout(), "thread outlived its leader\n");
+    assert_eq!(recording.output.status.code(), Some(7));
+    recording.assert_has_syscall("exit");
+    recording.assert_replays();
+}
+
+#[test]
+fn vfork() {
+    require_recording!();
+    let recording = TestProgram::build("vfork").record(&[]);
+    assert_eq!(recording.stdout(), "vfork child exited with 5\n");
+    recording.assert_has_syscall("vfork");
+    recording.assert_replays();
+}
+
+#[test]
+fn killed_in_syscall() {
+    require_recording!();
+    let recording = TestProgram::build("killed_in_syscall").record(&[]);
+    assert_eq!(recording.stdout(), "child killed by 9\n");
+    recording.assert_has_syscall("read");
+    recording.assert_replays();
+}
+
+#[test]
+fn ioctl() {
+    require_recording!();
+    let recording = TestProgram::build("ioctl").record(&[]);
+    // Replay gets the count from the recorded ioctl buffer.
+    assert_eq!(recording.stdout(), "5 bytes in the pipe\n");
+    recording.assert_replays();
+}
+
+#[test]
+fn capture_reads() {
+    require_recording!();
+    let dir = ScratchDir::new("capture_reads");
+    let input = dir.path().join("input");
+    fs::write(&input, "before\n").unwrap();
+    let pattern = format!("{}/*", dir.path().display());
+    let recording = TestProgram::build("cat_file")
+        .record_with_options(&["--capture-reads", &pattern], &[input.to_str().unwrap()]);
+    assert_eq!(recording.stdout(), "before\n");
+    // Replay doesn't look at the file.
+    fs::write(&input, "after\n").unwrap();
+    recording.assert_replays();
+}
+
+#[test]
+fn grep_path_args() {
+    require_recording!();
+    let dir = ScratchDir::new("grep_path_args");
+    let victim = dir.path().join("victim");
+    fs::write(&victim, "").unwrap();
+    let recording = TestProgram::build("unlink_file").record(&[victim.to_str().unwrap()]);
+    // Neither unlink() nor the stat() after it opens anything.
+    let grep = recording.grep(&["--path", "**/victim"]);
+    let called_with = format!("({:?}) = ", victim);
+    let hits: Vec<&str> = grep.lines().filter(|l| l.contains(&called_with)).collect();
+    assert!(
+        hits.iter()
+            .any(|l| l.contains("unlink") && l.ends_with(" = 0")),
+        "{}",
+        grep
+    );
+    assert!(
+        hits.iter()
+            .any(|l| l.contains("stat") && l.ends_with(" = -2")),
+        "{}",
+        grep
+    );
+}
+
+#[test]
+fn which_wrote_data() {
+    require_recording!();
+    let dir = ScratchDir::new("which_wrote_data");
+    let input = dir.path().join("input");
+    fs::write(&input, "before\n").unwrap();
+    let recording = TestProgram::build("cat_file").record(&[input.to_str().unwrap()]);
+    // The read of the file records the 7 bytes it wrote to the buffer.
+    let dump = recording.dump(&["--syscall", "read", "-m"]);
+    let line = dump
+        .lines()
+        .find(|l| l.contains("length:0x7 }"))
+        .unwrap_or_else(|| panic!("no 7 byte write in:\n{}", dump));
+    let addr = line.split("addr:").nth(1).unwrap();
+    let addr = &addr[..addr.find(',').unwrap()];
+    let out = recording.which_wrote(&["--data", addr]);
+    assert!(out.contains("(7 bytes)"), "{}", out);
+    assert!(out.contains(": 62 65 66 6f 72 65 0a\n"), "{}", out);
+}
+
+#[test]
+fn which_wrote_next_after() {
+    require_recording!();
+    let recording = TestProgram::build("store").record(&[]);
+    let addr = recording.stdout().trim().to_owned();
+    // The store comes after the write() that printed the address.
+    let dump = recording.dump(&["--syscall", "write"]);
+    let event = dump.split("global_time:").last().unwrap();
+    let event = &event[..event.find(',').unwrap()];
+    let out = recording.which_wrote(&["--next-after", event, &addr]);
+    assert!(out.contains(&format!(" wrote {} at ip ", addr)), "{}", out);
+    assert!(out.ends_with(": 2a\n"), "{}", out);
+}
+
+#[test]
+fn virtual_clock() {
+    require_recording!();
+    let recording =
+        TestProgram::build("virtual_clock").record_with_options(&["--virtual-clock"], &[]);
+    // The virtual wall clocks start at 2020-01-01T00:00:00Z, and a few
+    // hundred events move them on by less than a second.
+    assert_eq!(
+        recording.stdout(),
+        "clock_gettime 1577836800\n\
+         gettimeofday 1577836800\n\
+         time 1577836800\n\
+         monotonic advances\n"
+    );
+    recording.assert_has_syscall("clock_gettime");
+    recording.assert_replays();
+}
+
+#[test]
+fn random_seed() {
+    require_recording!();
+    let program = TestProgram::build("random_seed");
+    let first = program.record_with_options(&["--random-seed", "42"], &[]);
+    let second = program.record_with_options(&["--random-seed", "42"], &[]);
+    assert!(first.stdout().starts_with("getrandom "));
+    assert!(first.stdout().contains("\nurandom "));
+    assert_eq!(first.stdout(), second.stdout());
+    second.assert_has_syscall("getrandom");
+    second.assert_replays();
+}
+
+/// The event of the last `name` syscall of `recording`.
+fn last_syscall_event(recording: &Recording, name: &str) -> u64 {
+    let dump = recording.dump(&["--syscall", name]);
+    let event = dump.split("global_time:").last().unwrap();
+    event[..event.find(',').unwrap()].parse().unwrap()
+}
+
+/// The event right after the last `name` syscall of `recording`.
+fn event_after_syscall(recording: &Recording, name: &str) -> String {
+    (last_syscall_event(recording, name) + 1).to_string()
+}
+
+#[test]
+fn gdb_exec_file() {
+    require_recording!();
+    let recording = TestProgram::build("hello").record(&[]);
+    let mut gdb = recording.serve_to_gdb(&["-g", &event_after_syscall(&recording, "execve")]);
+    assert!(gdb
+        .request("qSupported:multiprocess+")
+        .contains("qXfer:exec-file:read+"));
+    let exec_file = gdb.request("qXfer:exec-file:read::0,1000");
+    assert!(exec_file.starts_with('l'), "{}", exec_file);
+    assert!(exec_file.ends_with("/hello"), "{}", exec_file);
+    assert_eq!(
+        gdb.request("qXfer:exec-file:read::0,2"),
+        format!("m{}", &exec_file[1..3])
+    );
+}
+
+#[test]
+fn gdb_dead_threads() {
+    require_recording!();
+    let recording = TestProgram::build("leader_exit").record(&[]);
+    // Stop where the leader exits, while its thread is still running.
+    let event = last_syscall_event(&recording, "exit").to_string();
+    let mut gdb = recording.serve_to_gdb(&["--dead-tasks", "memory", "-g", &event]);
+    let leader = gdb.request("qC");
+    let leader = leader.strip_prefix("QC").unwrap().to_owned();
+    let threads = gdb.request("qfThreadInfo");
+    let thread = threads
+        .strip_prefix('m')
+        .unwrap()
+        .split(',')
+        .find(|&tid| tid != leader)
+        .unwrap_or_else(|| panic!("no second thread in {}", threads))
+        .to_owned();
+
+    assert_eq!(gdb.request("c"), "W00");
+    for tid in &[&leader, &thread] {
+        assert_eq!(gdb.request(&format!("Hg{}", tid)), "OK");
+        let rip = gdb.request("p10");
+        assert_eq!(rip.len(), 16, "{}", rip);
+        assert!(gdb.request("g").starts_with(&gdb.request("p0")));
+    }
+    // The memory was kept when the last thread exited.
+    let rip = gdb.request("p10");
+    // Registers are in target (little endian) byte order.
+    let rip = u64::from_str_radix(&rip, 16).unwrap().swap_bytes();
+    let code = gdb.request(&format!("m{:x},2", rip));
+    assert_eq!(code.len(), 4, "{}", code);
+    assert_eq!(gdb.request("Hg7fffffff"), "E01");
+}
+
+#[test]
+fn gdb_break_condition() {
+    require_recording!();
+    let recording = TestProgram::build("count_calls").record(&[]);
+    let hit = recording.stdout().trim().to_owned();
+    let mut gdb = recording.serve_to_gdb(&["-g", &event_after_syscall(&recording, "execve")]);
+    let start = gdb.monitor("when-ticks");
+    let start = start
+        .strip_prefix("Current tick: ")
+        .unwrap()
+        .trim()
+        .to_owned();
+    assert_eq!(
+        gdb.request(&format!("Z0,{},1", hit.trim_start_matches("0x"))),
+        "OK"
+    );
+    assert_eq!(
+        gdb.monitor(&format!("break-condition {} hit 3", hit)),
+        format!("Set the condition of the breakpoint at {}\n", hit)
+    );
+    assert!(gdb.request("c").starts_with("T05"));
+    // hit(2) is the third call; rdi is register 5.
+    assert_eq!(gdb.request("p5"), "0200000000000000");
+
+    // Seeking back starts a new replay session. The breakpoint and its
+    // condition come along, and the hits are counted again from there.
+    assert_eq!(
+        gdb.monitor(&format!("seek-ticks {}", start)),
+        format!("Now at tick {}\n", start)
+    );
+    assert!(gdb.request("c").starts_with("T05"));
+    assert_eq!(gdb.request("p5"), "0200000000000000");
+    assert_eq!(gdb.request("c"), "W00");
+}
+
+#[test]
+fn gdb_seek_ticks() {
+    require_recording!();
+    let recording = TestProgram::build("count_calls").record(&[]);
+    let hit = recording.stdout().trim().to_owned();
+    let mut gdb = recording.serve_to_gdb(&["-g", &event_after_syscall(&recording, "execve")]);
+    assert_eq!(
+        gdb.request(&format!("Z0,{},1", hit.trim_start_matches("0x"))),
+        "OK"
+    );
+    assert!(gdb.request("c").starts_with("T05"));
+    let first_hit = gdb.monitor("when-ticks");
+    let ticks = first_hit.strip_prefix("Current tick: ").unwrap().trim();
+    assert!(gdb.request("c").starts_with("T05"));
+    assert_ne!(gdb.monitor("when-ticks"), first_hit);
+    // Back to the first call.
+    assert_eq!(
+        gdb.monitor(&format!("seek-ticks {}", ticks)),
+        format!("Now at tick {}\n", ticks)
+    );
+    assert_eq!(gdb.monitor("when-ticks"), first_hit);
+}
+
+#[test]
+fn gdb_tls_address() {
+    require_recording!();
+    let recording = TestProgram::build_dynamic("tls").record(&[]);
+    let stdout = recording.stdout();
+    let mut addrs = stdout
+        .split_whitespace()
+        .map(|addr| addr.trim_start_matches("0x").to_owned());
+    let (tls_var, lm) = (addrs.next().unwrap(), addrs.next().unwrap());
+    let mut gdb = recording.serve_to_gdb(&["-g", &event_after_syscall(&recording, "write")]);
+    let tid = gdb.request("qC");
+    let tid = tid.strip_prefix("QC").unwrap().to_owned();
+    // tls_var is the main program's only thread-local variable.
+    assert_eq!(
+        gdb.request(&format!("qGetTLSAddr:{},0,{}", tid, lm)),
+        tls_var
+    );
+    assert_eq!(
+        gdb.request(&format!("qGetTLSAddr:7fffffff,0,{}", lm)),
+        "E01"
+    );
+}
+
+#[test]
+fn gdb_watch_values() {
+    require_recording!();
+    let recording = TestProgram::build("store").record(&[]);
+    let addr = recording
+        .stdout()
+        .trim()
+        .trim_start_matches("0x")
+        .to_owned();
+    let mut gdb = recording.serve_to_gdb(&["-g", &event_after_syscall(&recording, "write")]);
+    assert_eq!(gdb.request(&format!("Z2,{},4", addr)), "OK");
+    let stop = gdb.request("c");
+    assert!(stop.starts_with("T05"), "{}", stop);
+    assert!(stop.contains(&format!(";watch:{};", addr)), "{}", stop);
+    assert!(
+        stop.ends_with(&format!(
+            ";rd-watch-old:{0},00000000;rd-watch-new:{0},2a000000;",
+            addr
+        )),
+        "{}",
+        stop
+    );
+}
+
+#[test]
+fn replay_checked_in_hello() {
+    require_replaying!();
+    assert_trace_replays(&checked_in_trace("hello"), "hello from the tracee\n");
+}
@@ -0,0 +1,369 @@
+//! Record and replay the small C programs in tests/programs with the rd
+//! binary being tested, and look at what ended up in the trace.
+//!
+//! Programs are compiled when the test runs, with `$CC` (or `cc`), and linked
+//! statically so what gets recorded doesn't depend on the machine's libc,
+//! unless they need the dynamic loader.
+//! Each program gets its own scratch directory for the executable and its
+//! traces, removed when the test is done.
+//!
+//! Recording needs a C compiler and perf counters rd can use, and replay
+//! needs the perf counters. Tests that need them start with
+//! `require_recording!()` or `require_replaying!()`, which skip the test,
+//! saying what's missing, on a machine that can't do it. Set
+//! `RD_TEST_REQUIRE_RECORDING` to make them fail there instead.
+//!
+//! Traces in tests/traces were recorded with scripts/record_test_traces.py
+//! and are checked in, so replay is tested on traces rd didn't just write.
+
+#![allow(dead_code)]
+
+use std::{
+    env,
+    fs,
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::{self, Child, Command, Output, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Skip the current test if `support::cannot_record()` says so.
+macro_rules! require_recording {
+    () => {
+        if let Some(why) = support::cannot_record() {
+            support::skip(&format!("can't record here: {}", why));
+            return;
+        }
+    };
+}
+
+/// Skip the current test if `support::cannot_replay()` says so.
+macro_rules! require_replaying {
+    () => {
+        if let Some(why) = support::cannot_replay() {
+            support::skip(&format!("can't replay here: {}", why));
+            return;
+        }
+    };
+}
+
+/// Say why a test is being skipped. Panics instead when
+/// `RD_TEST_REQUIRE_RECORDING` is set, so a machine that's meant to run
+/// these tests can't pass them by skipping them all.
+pub fn skip(why: &str) {
+    if env::var_os("RD_TEST_REQUIRE_RECORDING").is_some() {
+        panic!("{}", why);
+    }
+    eprintln!("skipped: {}", why);
+}
+
+/// Why tests can't record anything here, if they can't.
+pub fn cannot_record() -> Option<String> {
+    if Command::new(cc()).arg("--version").output().is_err() {
+        return Some(format!("no C compiler `{}`", cc()));
+    }
+    cannot_replay()
+}
+
+/// Why tests can't replay anything here, if they can't.
+pub fn cannot_replay() -> Option<String> {
+    let paranoid = fs::read_to_string("/proc/sys/kernel/perf_event_paranoid")
+        .ok()
+        .and_then(|s| s.trim().parse::<i32>().ok());
+    match paranoid {
+        Some(level) if level <= 1 => None,
+        Some(level) => Some(format!(
+            "kernel.perf_event_paranoid is {}, rd needs 1 or less",
+            level
+        )),
+        None => Some("no perf counters".into()),
+    }
+}
+
+fn cc() -> String {
+    env::var("CC").unwrap_or_else(|_| "cc".into())
+}
+
+fn rd() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_rd"))
+}
+
+/// A directory that is removed along with its contents when dropped.
+pub struct ScratchDir {
+    path: PathBuf,
+}
+
+impl ScratchDir {
+    pub fn new(name: &str) -> ScratchDir {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = env::temp_dir().join(format!(
+            "rd-test-{}-{}-{}",
+            name,
+            process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(&path).unwrap();
+        ScratchDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// One of the programs in tests/programs, compiled.
+pub struct TestProgram {
+    dir: ScratchDir,
+    exe: PathBuf,
+}
+
+impl TestProgram {
+    /// Compile tests/programs/`name`.c.
+    pub fn build(name: &str) -> TestProgram {
+        TestProgram::build_with(name, &["-static"])
+    }
+
+    /// Compile tests/programs/`name`.c and link it dynamically.
+    pub fn build_dynamic(name: &str) -> TestProgram {
+        TestProgram::build_with(name, &[])
+    }
+
+    fn build_with(name: &str, flags: &[&str]) -> TestProgram {
+        let dir = ScratchDir::new(name);
+        let src = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/programs")
+            .join(format!("{}.c", name));
+        let exe = dir.path().join(name);
+        let output = Command::new(cc())
+            .args(flags)
+            .args(&["-pthread", "-g", "-O0", "-o"])
+            .arg(&exe)
+            .arg(&src)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "compiling {} failed:\n{}",
+            src.display(),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        TestProgram { dir, exe }
+    }
+
+    /// Record the program run with `args`. Panics if rd didn't leave a
+    /// trace.
+    pub fn record(&self, args: &[&str]) -> Recording {
+        self.record_with_options(&[], args)
+    }
+
+    /// Like `record()`, passing `options` to `rd record`.
+    pub fn record_with_options(&self, options: &[&str], args: &[&str]) -> Recording {
+        let trace_root = self.dir.path().join("traces");
+        let output = rd()
+            .env("_RD_TRACE_DIR", &trace_root)
+            .arg("record")
+            .args(options)
+            .arg(&self.exe)
+            .args(args)
+            .output()
+            .unwrap();
+        let trace_dir = trace_root.join("latest-trace");
+        assert!(
+            trace_dir.exists(),
+            "rd record left no trace:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        Recording { trace_dir, output }
+    }
+}
+
+/// A trace of a `TestProgram`, and what rd printed while recording it.
+pub struct Recording {
+    trace_dir: PathBuf,
+    pub output: Output,
+}
+
+impl Recording {
+    /// What the tracee wrote to stdout while being recorded.
+    pub fn stdout(&self) -> String {
+        String::from_utf8_lossy(&self.output.stdout).into_owned()
+    }
+
+    /// Replay the whole trace, see `assert_trace_replays()`.
+    pub fn assert_replays(&self) {
+        assert_trace_replays(&self.trace_dir, &self.stdout());
+    }
+
+    /// The output of `rd dump` with `args` for the trace.
+    pub fn dump(&self, args: &[&str]) -> String {
+        self.run_rd("dump", args)
+    }
+
+    /// The output of `rd which-wrote` with `args` for the trace.
+    pub fn which_wrote(&self, args: &[&str]) -> String {
+        self.run_rd("which-wrote", args)
+    }
+
+    /// The output of `rd grep` with `args` for the trace.
+    pub fn grep(&self, args: &[&str]) -> String {
+        self.run_rd("grep", args)
+    }
+
+    /// Serve the trace to gdb with `rd replay -s` and `options`, and connect
+    /// to it.
+    pub fn serve_to_gdb(&self, options: &[&str]) -> GdbConnection {
+        GdbConnection::new(&self.trace_dir, options)
+    }
+
+    fn run_rd(&self, command: &str, args: &[&str]) -> String {
+        let output = rd()
+            .arg(command)
+            .args(args)
+            .arg(&self.trace_dir)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "rd {} failed:\n{}",
+            command,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    }
+
+    /// Panics unless the trace has an event for the syscall `name`.
+    pub fn assert_has_syscall(&self, name: &str) {
+        let dump = self.dump(&["--syscall", name]);
+        assert!(
+            dump.contains(&format!("SYSCALL: {}", name)),
+            "no {} syscall in the trace:\n{}",
+            name,
+            dump
+        );
+    }
+}
+
+/// The checked-in trace tests/traces/`name`.
+pub fn checked_in_trace(name: &str) -> PathBuf {
+    let trace_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/traces")
+        .join(name);
+    assert!(
+        trace_dir.exists(),
+        "no trace {}; record it with scripts/record_test_traces.py",
+        trace_dir.display()
+    );
+    trace_dir
+}
+
+/// Replay the whole trace in `trace_dir` without a debugger. Replay checks
+/// the registers at every event against the recording, so this panics if
+/// replay diverged, and also if the tracee's output isn't `stdout`.
+pub fn assert_trace_replays(trace_dir: &Path, stdout: &str) {
+    assert!(trace_dir.exists(), "no trace {}", trace_dir.display());
+    let output = rd()
+        .arg("replay")
+        .arg("--autopilot")
+        .arg(trace_dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "replay failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        stdout,
+        "replay wrote something else to stdout"
+    );
+}
+
+/// A connection to `rd replay -s`, speaking the gdb remote protocol. The
+/// replay is killed when this is dropped.
+pub struct GdbConnection {
+    rd: Child,
+    stream: TcpStream,
+}
+
+impl GdbConnection {
+    fn new(trace_dir: &Path, options: &[&str]) -> GdbConnection {
+        // Let the kernel pick a free port.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let rd = rd()
+            .arg("replay")
+            .args(options)
+            .arg("-s")
+            .arg(port.to_string())
+            .arg(trace_dir)
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        let deadline = Instant::now() + Duration::from_secs(30);
+        let stream = loop {
+            match TcpStream::connect(("127.0.0.1", port)) {
+                Ok(stream) => break stream,
+                Err(_) if Instant::now() < deadline => thread::sleep(Duration::from_millis(100)),
+                Err(e) => panic!("can't connect to rd replay -s {}: {}", port, e),
+            }
+        };
+        GdbConnection { rd, stream }
+    }
+
+    /// Send `packet` and return the reply.
+    pub fn request(&mut self, packet: &str) -> String {
+        let checksum = packet.bytes().fold(0u8, |sum, c| sum.wrapping_add(c));
+        write!(self.stream, "${}#{:02x}", packet, checksum).unwrap();
+        let mut reply = Vec::new();
+        let mut byte = [0u8];
+        // Skip the ack, read up to the '#' and then the checksum.
+        loop {
+            self.stream.read_exact(&mut byte).unwrap();
+            if byte[0] == b'$' {
+                break;
+            }
+        }
+        loop {
+            self.stream.read_exact(&mut byte).unwrap();
+            if byte[0] == b'#' {
+                break;
+            }
+            reply.push(byte[0]);
+        }
+        let mut checksum = [0u8; 2];
+        self.stream.read_exact(&mut checksum).unwrap();
+        self.stream.write_all(b"+").unwrap();
+        String::from_utf8_lossy(&reply).into_owned()
+    }
+
+    /// Run the `monitor` command `cmd` and return its output.
+    pub fn monitor(&mut self, cmd: &str) -> String {
+        let hex_cmd: String = cmd.bytes().map(|c| format!("{:02x}", c)).collect();
+        let reply = self.request(&format!("qRcmd,{}", hex_cmd));
+        let output: Vec<u8> = (0..reply.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&reply[i..i + 2], 16).unwrap())
+            .collect();
+        String::from_utf8(output).unwrap()
+    }
+}
+
+impl Drop for GdbConnection {
+    fn drop(&mut self) {
+        let _ = self.rd.kill();
+        let _ = self.rd.wait();
+    }
+}